@@ -2,7 +2,7 @@ ARCH ?= x86_64
 BUILD_TYPE ?= debug
 BUILD_TARGET := $(ARCH)-unknown-veos-gnu
 
-MODULES := kernel init test mkinitramfs
+MODULES := initramfs crypto kernel init test harness test-runner syscall-fuzz display display-demo mkinitramfs xtask
 
 TARGET_DIR := target
 
@@ -11,7 +11,22 @@ ISO := image.iso
 RUST_COMPILER_FLAGS := --target $(BUILD_TARGET)
 RUST_COMPILER := xargo
 
+# The cargo features the kernel is built with, e.g. `benchmark`.
+KERNEL_FEATURES ?=
+
+# The file with the secret key that the executables in the initramfs are
+# signed with. Nothing is signed if it is empty.
+SIGNING_KEY ?=
+
+# The host directory that the kernel mounts at /host/. Nothing is shared if it
+# is empty.
+SHARE ?=
+
 LINKER := ld
 LINKER_FLAGS := --gc-sections
 
-QEMU_FLAGS := --no-reboot -smp cores=4 -s -serial stdio
\ No newline at end of file
+QEMU_FLAGS := --no-reboot -smp cores=4 -s -serial stdio -device virtio-rng-pci -device AC97
+
+ifneq ($(SHARE),)
+QEMU_FLAGS += -virtfs local,path=$(SHARE),mount_tag=host,security_model=none,readonly=on
+endif
@@ -0,0 +1,4 @@
+BUILD_DIRS += crypto/target
+FMT_DIRS += crypto
+
+CRYPTO_CRATE_FILES := $(shell find crypto/src -name "*.rs") crypto/Cargo.toml
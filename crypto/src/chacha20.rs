@@ -0,0 +1,179 @@
+//! Implements the ChaCha20 stream cipher as specified in RFC 8439.
+//!
+//! ChaCha20 only provides confidentiality. It doesn't detect modifications of
+//! the encrypted data.
+
+use super::{read_u32_le, write_u32_le};
+
+/// The size of a key in bytes.
+pub const KEY_SIZE: usize = 32;
+
+/// The size of a nonce in bytes.
+pub const NONCE_SIZE: usize = 12;
+
+/// The size of a block of the key stream in bytes.
+pub const BLOCK_SIZE: usize = 64;
+
+/// The constant words at the start of the state ("expand 32-byte k").
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+/// Performs a quarter round on the given words of the state.
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// Encrypts or decrypts data with ChaCha20.
+///
+/// Both are the same operation, the data is combined with the key stream.
+#[derive(Clone)]
+pub struct ChaCha20 {
+    /// The key as words.
+    key: [u32; 8],
+    /// The nonce as words.
+    nonce: [u32; 3],
+    /// The counter of the next block of the key stream.
+    counter: u32,
+    /// The current block of the key stream.
+    keystream: [u8; BLOCK_SIZE],
+    /// The number of bytes of the current block that were used.
+    position: usize
+}
+
+impl ChaCha20 {
+    /// Creates a cipher that starts at the block with the given counter.
+    ///
+    /// The same nonce must never be used twice with the same key.
+    pub fn new(key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE], counter: u32) -> ChaCha20 {
+        let mut key_words = [0; 8];
+        for (i, word) in key_words.iter_mut().enumerate() {
+            *word = read_u32_le(&key[4 * i..]);
+        }
+
+        let mut nonce_words = [0; 3];
+        for (i, word) in nonce_words.iter_mut().enumerate() {
+            *word = read_u32_le(&nonce[4 * i..]);
+        }
+
+        ChaCha20 {
+            key: key_words,
+            nonce: nonce_words,
+            counter,
+            keystream: [0; BLOCK_SIZE],
+            position: BLOCK_SIZE
+        }
+    }
+
+    /// Returns the block of the key stream with the given counter.
+    pub fn block(&self, counter: u32) -> [u8; BLOCK_SIZE] {
+        let mut initial = [0; 16];
+        initial[..4].copy_from_slice(&CONSTANTS);
+        initial[4..12].copy_from_slice(&self.key);
+        initial[12] = counter;
+        initial[13..].copy_from_slice(&self.nonce);
+
+        let mut state = initial;
+        for _ in 0..10 {
+            quarter_round(&mut state, 0, 4, 8, 12);
+            quarter_round(&mut state, 1, 5, 9, 13);
+            quarter_round(&mut state, 2, 6, 10, 14);
+            quarter_round(&mut state, 3, 7, 11, 15);
+            quarter_round(&mut state, 0, 5, 10, 15);
+            quarter_round(&mut state, 1, 6, 11, 12);
+            quarter_round(&mut state, 2, 7, 8, 13);
+            quarter_round(&mut state, 3, 4, 9, 14);
+        }
+
+        let mut block = [0; BLOCK_SIZE];
+        for i in 0..16 {
+            write_u32_le(&mut block[4 * i..], state[i].wrapping_add(initial[i]));
+        }
+
+        block
+    }
+
+    /// Combines the data with the next bytes of the key stream.
+    ///
+    /// # Panics
+    /// Panics if the counter overflows, which happens after 256 GiB.
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.position == BLOCK_SIZE {
+                self.keystream = self.block(self.counter);
+                self.counter = self
+                    .counter
+                    .checked_add(1)
+                    .expect("The ChaCha20 block counter overflowed.");
+                self.position = 0;
+            }
+
+            *byte ^= self.keystream[self.position];
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The key used by the test vectors of RFC 8439.
+    const KEY: [u8; KEY_SIZE] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
+        0x1e, 0x1f
+    ];
+
+    /// Tests the block function with the test vector of RFC 8439 section 2.3.2.
+    #[test]
+    fn test_block() {
+        let nonce = [0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x4a, 0x00, 0x00, 0x00, 0x00];
+        let expected: [u8; BLOCK_SIZE] = [
+            0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20,
+            0x71, 0xc4, 0xc7, 0xd1, 0xf4, 0xc7, 0x33, 0xc0, 0x68, 0x03, 0x04, 0x22, 0xaa, 0x9a,
+            0xc3, 0xd4, 0x6c, 0x4e, 0xd2, 0x82, 0x64, 0x46, 0x07, 0x9f, 0xaa, 0x09, 0x14, 0xc2,
+            0xd7, 0x05, 0xd9, 0x8b, 0x02, 0xa2, 0xb5, 0x12, 0x9c, 0xd1, 0xde, 0x16, 0x4e, 0xb9,
+            0xcb, 0xd0, 0x83, 0xe8, 0xa2, 0x50, 0x3c, 0x4e
+        ];
+
+        assert_eq!(&ChaCha20::new(&KEY, &nonce, 0).block(1)[..], &expected[..]);
+    }
+
+    /// Tests the encryption with the test vector of RFC 8439 section 2.4.2.
+    #[test]
+    fn test_encryption() {
+        let nonce = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x4a, 0x00, 0x00, 0x00, 0x00];
+        let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+                                 only one tip for the future, sunscreen would be it.";
+        let expected: &[u8] = &[
+            0x6e, 0x2e, 0x35, 0x9a, 0x25, 0x68, 0xf9, 0x80, 0x41, 0xba, 0x07, 0x28, 0xdd, 0x0d,
+            0x69, 0x81, 0xe9, 0x7e, 0x7a, 0xec, 0x1d, 0x43, 0x60, 0xc2, 0x0a, 0x27, 0xaf, 0xcc,
+            0xfd, 0x9f, 0xae, 0x0b, 0xf9, 0x1b, 0x65, 0xc5, 0x52, 0x47, 0x33, 0xab, 0x8f, 0x59,
+            0x3d, 0xab, 0xcd, 0x62, 0xb3, 0x57, 0x16, 0x39, 0xd6, 0x24, 0xe6, 0x51, 0x52, 0xab,
+            0x8f, 0x53, 0x0c, 0x35, 0x9f, 0x08, 0x61, 0xd8, 0x07, 0xca, 0x0d, 0xbf, 0x50, 0x0d,
+            0x6a, 0x61, 0x56, 0xa3, 0x8e, 0x08, 0x8a, 0x22, 0xb6, 0x5e, 0x52, 0xbc, 0x51, 0x4d,
+            0x16, 0xcc, 0xf8, 0x06, 0x81, 0x8c, 0xe9, 0x1a, 0xb7, 0x79, 0x37, 0x36, 0x5a, 0xf9,
+            0x0b, 0xbf, 0x74, 0xa3, 0x5b, 0xe6, 0xb4, 0x0b, 0x8e, 0xed, 0xf2, 0x78, 0x5e, 0x42,
+            0x87, 0x4d
+        ];
+
+        let mut data = [0; 114];
+        data.copy_from_slice(plaintext);
+
+        // Splitting the data must not change the result.
+        let mut cipher = ChaCha20::new(&KEY, &nonce, 1);
+        let (first, second) = data.split_at_mut(50);
+        cipher.apply_keystream(first);
+        cipher.apply_keystream(second);
+        assert_eq!(&data[..], expected);
+
+        ChaCha20::new(&KEY, &nonce, 1).apply_keystream(&mut data);
+        assert_eq!(&data[..], plaintext);
+    }
+}
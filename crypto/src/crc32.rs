@@ -0,0 +1,100 @@
+//! Implements the CRC-32 checksum used by zlib, gzip and ethernet.
+//!
+//! This is the reflected variant with the polynomial `0x04c11db7`, an initial
+//! value of all ones and a final inversion. A CRC only detects accidental
+//! corruption, use `sha256` if the data could have been modified deliberately.
+
+/// The remainders of all four bit values for the reflected polynomial
+/// `0xedb88320`.
+///
+/// A table for four bits instead of eight is a lot smaller, while still being
+/// much faster than computing the checksum bit by bit.
+const TABLE: [u32; 16] = [
+    0x00000000, 0x1db71064, 0x3b6e20c8, 0x26d930ac, 0x76dc4190, 0x6b6b51f4, 0x4db26158, 0x5005713c,
+    0xedb88320, 0xf00f9344, 0xd6d6a3e8, 0xcb61b38c, 0x9b64c2b0, 0x86d3d2d4, 0xa00ae278, 0xbdbdf21c
+];
+
+/// Computes a CRC-32 checksum incrementally.
+#[derive(Clone)]
+pub struct Crc32 {
+    /// The current value of the checksum before the final inversion.
+    value: u32
+}
+
+impl Crc32 {
+    /// Starts a new checksum.
+    pub fn new() -> Crc32 {
+        Crc32 { value: !0 }
+    }
+
+    /// Adds the data to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.value ^= u32::from(byte);
+            self.value = (self.value >> 4) ^ TABLE[(self.value & 0xf) as usize];
+            self.value = (self.value >> 4) ^ TABLE[(self.value & 0xf) as usize];
+        }
+    }
+
+    /// Returns the checksum of the data added so far.
+    pub fn finish(&self) -> u32 {
+        !self.value
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+/// Returns the CRC-32 checksum of the data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The reflected polynomial.
+    const POLYNOMIAL: u32 = 0xedb88320;
+
+    /// Tests that the table matches the polynomial.
+    #[test]
+    fn test_table() {
+        for (value, &entry) in TABLE.iter().enumerate() {
+            let mut remainder = value as u32;
+            for _ in 0..4 {
+                remainder = if remainder & 1 == 1 {
+                    (remainder >> 1) ^ POLYNOMIAL
+                } else {
+                    remainder >> 1
+                };
+            }
+
+            assert_eq!(remainder, entry);
+        }
+    }
+
+    /// Tests the checksums of known vectors.
+    #[test]
+    fn test_known_vectors() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414fa339);
+    }
+
+    /// Tests that the checksum doesn't depend on how the data is split up.
+    #[test]
+    fn test_incremental() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"");
+        crc.update(b"56789");
+
+        assert_eq!(crc.finish(), 0xcbf43926);
+    }
+}
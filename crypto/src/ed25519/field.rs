@@ -0,0 +1,334 @@
+//! Implements arithmetic in the field of integers modulo `2^255 - 19`.
+
+use core::ops::{Add, Mul, Neg, Sub};
+
+/// The mask of the 51 bits of a limb.
+const LIMB_MASK: u64 = (1 << 51) - 1;
+
+/// Four times the modulus, which is added before subtracting.
+const FOUR_TIMES_MODULUS: [u64; 5] = [
+    4 * ((1 << 51) - 19),
+    4 * LIMB_MASK,
+    4 * LIMB_MASK,
+    4 * LIMB_MASK,
+    4 * LIMB_MASK
+];
+
+/// `p - 2` in little endian, used to compute inverses.
+const MODULUS_MINUS_TWO: [u8; 32] = [
+    0xeb, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f
+];
+
+/// `(p - 5) / 8` in little endian, used to compute square roots.
+const MODULUS_MINUS_FIVE_OVER_EIGHT: [u8; 32] = [
+    0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x0f
+];
+
+/// A square root of -1 in little endian.
+const SQRT_MINUS_ONE: [u8; 32] = [
+    0xb0, 0xa0, 0x0e, 0x4a, 0x27, 0x1b, 0xee, 0xc4, 0x78, 0xe4, 0x2f, 0xad, 0x06, 0x18, 0x43, 0x2f,
+    0xa7, 0xd7, 0xfb, 0x3d, 0x99, 0x00, 0x4d, 0x2b, 0x0b, 0xdf, 0xc1, 0x4f, 0x80, 0x24, 0x83, 0x2b
+];
+
+/// An element of the field.
+///
+/// The value is stored in five limbs of 51 bits each. After every operation
+/// the limbs are at most slightly larger than 51 bits, but the value isn't
+/// necessarily fully reduced.
+#[derive(Clone, Copy)]
+pub struct FieldElement([u64; 5]);
+
+impl FieldElement {
+    /// Returns the element with the given small value.
+    pub fn from_u64(value: u64) -> FieldElement {
+        FieldElement([value, 0, 0, 0, 0]).reduce()
+    }
+
+    /// Parses the little endian encoding of an element.
+    ///
+    /// The most significant bit is ignored.
+    pub fn from_bytes(bytes: &[u8; 32]) -> FieldElement {
+        let load = |offset: usize| {
+            bytes[offset..offset + 8]
+                .iter()
+                .enumerate()
+                .fold(0, |value, (i, &byte)| value | u64::from(byte) << (8 * i))
+        };
+
+        FieldElement([
+            load(0) & LIMB_MASK,
+            (load(6) >> 3) & LIMB_MASK,
+            (load(12) >> 6) & LIMB_MASK,
+            (load(19) >> 1) & LIMB_MASK,
+            (load(24) >> 12) & LIMB_MASK
+        ])
+    }
+
+    /// Returns the canonical little endian encoding of the element.
+    pub fn to_bytes(self) -> [u8; 32] {
+        let mut limbs = self.reduce().0;
+
+        // Add 19 to find out whether the value is at least p, then subtract p
+        // by adding 19 and dropping the bit 255.
+        let mut carry = (limbs[0] + 19) >> 51;
+        for limb in &limbs[1..] {
+            carry = (limb + carry) >> 51;
+        }
+
+        limbs[0] += 19 * carry;
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= LIMB_MASK;
+        }
+        limbs[4] &= LIMB_MASK;
+
+        let mut bytes = [0; 32];
+        let mut accumulator: u128 = 0;
+        let mut bits = 0;
+        let mut index = 0;
+        for &limb in &limbs {
+            accumulator |= u128::from(limb) << bits;
+            bits += 51;
+
+            while bits >= 8 {
+                bytes[index] = accumulator as u8;
+                accumulator >>= 8;
+                bits -= 8;
+                index += 1;
+            }
+        }
+        bytes[index] = accumulator as u8;
+
+        bytes
+    }
+
+    /// Returns true if the canonical encoding of the element is odd.
+    pub fn is_negative(&self) -> bool {
+        self.to_bytes()[0] & 1 == 1
+    }
+
+    /// Returns true if the element is zero.
+    pub fn is_zero(&self) -> bool {
+        self.to_bytes() == [0; 32]
+    }
+
+    /// Returns the square of the element.
+    pub fn square(&self) -> FieldElement {
+        *self * *self
+    }
+
+    /// Raises the element to the little endian exponent.
+    fn pow(&self, exponent: &[u8; 32]) -> FieldElement {
+        let mut result = FieldElement::from_u64(1);
+
+        for i in (0..256).rev() {
+            result = result.square();
+
+            if (exponent[i / 8] >> (i % 8)) & 1 == 1 {
+                result = result * *self;
+            }
+        }
+
+        result
+    }
+
+    /// Returns the multiplicative inverse of the element.
+    ///
+    /// The inverse of zero is zero.
+    pub fn invert(&self) -> FieldElement {
+        self.pow(&MODULUS_MINUS_TWO)
+    }
+
+    /// Returns a square root of `numerator / denominator` if there is one.
+    pub fn sqrt_ratio(
+        numerator: &FieldElement,
+        denominator: &FieldElement
+    ) -> Option<FieldElement> {
+        // The candidate root is `u * v^3 * (u * v^7)^((p - 5) / 8)`.
+        let denominator3 = denominator.square() * *denominator;
+        let denominator7 = denominator3.square() * *denominator;
+        let root = *numerator
+            * denominator3
+            * (*numerator * denominator7).pow(&MODULUS_MINUS_FIVE_OVER_EIGHT);
+
+        let check = *denominator * root.square();
+
+        if (check - *numerator).is_zero() {
+            Some(root)
+        } else if (check + *numerator).is_zero() {
+            Some(root * FieldElement::from_bytes(&SQRT_MINUS_ONE))
+        } else {
+            None
+        }
+    }
+
+    /// Selects `other` if `choice` is true and `self` otherwise, without
+    /// branching on `choice`.
+    pub fn select(&self, other: &FieldElement, choice: bool) -> FieldElement {
+        let mask = (choice as u64).wrapping_neg();
+        let mut limbs = self.0;
+
+        for (limb, &other_limb) in limbs.iter_mut().zip(other.0.iter()) {
+            *limb ^= mask & (*limb ^ other_limb);
+        }
+
+        FieldElement(limbs)
+    }
+
+    /// Carries the excess bits of every limb into the next one.
+    fn reduce(&self) -> FieldElement {
+        let mut limbs = self.0;
+
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= LIMB_MASK;
+        }
+        limbs[0] += 19 * (limbs[4] >> 51);
+        limbs[4] &= LIMB_MASK;
+
+        FieldElement(limbs)
+    }
+}
+
+impl Add for FieldElement {
+    type Output = FieldElement;
+
+    fn add(self, other: FieldElement) -> FieldElement {
+        let mut limbs = self.0;
+
+        for (limb, &other_limb) in limbs.iter_mut().zip(other.0.iter()) {
+            *limb += other_limb;
+        }
+
+        FieldElement(limbs).reduce()
+    }
+}
+
+impl Sub for FieldElement {
+    type Output = FieldElement;
+
+    fn sub(self, other: FieldElement) -> FieldElement {
+        let mut limbs = self.0;
+
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = *limb + FOUR_TIMES_MODULUS[i] - other.0[i];
+        }
+
+        FieldElement(limbs).reduce()
+    }
+}
+
+impl Neg for FieldElement {
+    type Output = FieldElement;
+
+    fn neg(self) -> FieldElement {
+        FieldElement::from_u64(0) - self
+    }
+}
+
+impl Mul for FieldElement {
+    type Output = FieldElement;
+
+    fn mul(self, other: FieldElement) -> FieldElement {
+        let a = self.0;
+        let b = other.0;
+        let m = |x: u64, y: u64| u128::from(x) * u128::from(y);
+
+        // Multiplying by 2^255 is the same as multiplying by 19.
+        let b1 = b[1] * 19;
+        let b2 = b[2] * 19;
+        let b3 = b[3] * 19;
+        let b4 = b[4] * 19;
+
+        let mut c = [
+            m(a[0], b[0]) + m(a[1], b4) + m(a[2], b3) + m(a[3], b2) + m(a[4], b1),
+            m(a[0], b[1]) + m(a[1], b[0]) + m(a[2], b4) + m(a[3], b3) + m(a[4], b2),
+            m(a[0], b[2]) + m(a[1], b[1]) + m(a[2], b[0]) + m(a[3], b4) + m(a[4], b3),
+            m(a[0], b[3]) + m(a[1], b[2]) + m(a[2], b[1]) + m(a[3], b[0]) + m(a[4], b4),
+            m(a[0], b[4]) + m(a[1], b[3]) + m(a[2], b[2]) + m(a[3], b[1]) + m(a[4], b[0])
+        ];
+
+        for i in 0..4 {
+            c[i + 1] += c[i] >> 51;
+            c[i] &= u128::from(LIMB_MASK);
+        }
+        c[0] += 19 * (c[4] >> 51);
+        c[4] &= u128::from(LIMB_MASK);
+        c[1] += c[0] >> 51;
+        c[0] &= u128::from(LIMB_MASK);
+
+        FieldElement([
+            c[0] as u64,
+            c[1] as u64,
+            c[2] as u64,
+            c[3] as u64,
+            c[4] as u64
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that encoding an element returns the canonical encoding.
+    #[test]
+    fn test_encoding() {
+        let mut bytes = [0x11; 32];
+        bytes[31] = 0x22;
+        assert_eq!(FieldElement::from_bytes(&bytes).to_bytes(), bytes);
+
+        // p + 1 is encoded as 1.
+        let mut bytes = [0xff; 32];
+        bytes[0] = 0xee;
+        bytes[31] = 0x7f;
+        assert_eq!(
+            FieldElement::from_bytes(&bytes).to_bytes(),
+            FieldElement::from_u64(1).to_bytes()
+        );
+    }
+
+    /// Tests the arithmetic operations.
+    #[test]
+    fn test_arithmetic() {
+        let two = FieldElement::from_u64(2);
+        let three = FieldElement::from_u64(3);
+
+        assert_eq!(
+            (two * three).to_bytes(),
+            FieldElement::from_u64(6).to_bytes()
+        );
+        assert_eq!((two - three + three).to_bytes(), two.to_bytes());
+        assert_eq!((-two + two).to_bytes(), [0; 32]);
+        assert_eq!(
+            (three.invert() * three).to_bytes(),
+            FieldElement::from_u64(1).to_bytes()
+        );
+
+        let minus_one = -FieldElement::from_u64(1);
+        assert_eq!(
+            FieldElement::from_bytes(&SQRT_MINUS_ONE)
+                .square()
+                .to_bytes(),
+            minus_one.to_bytes()
+        );
+    }
+
+    /// Tests that square roots are found if they exist.
+    #[test]
+    fn test_sqrt_ratio() {
+        let four = FieldElement::from_u64(4);
+        let nine = FieldElement::from_u64(9);
+        let root = FieldElement::sqrt_ratio(&four, &nine).unwrap();
+
+        assert_eq!((root.square() * nine).to_bytes(), four.to_bytes());
+
+        // 2 is not a square modulo p.
+        assert!(
+            FieldElement::sqrt_ratio(&FieldElement::from_u64(2), &FieldElement::from_u64(1))
+                .is_none()
+        );
+    }
+}
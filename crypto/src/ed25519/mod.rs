@@ -0,0 +1,195 @@
+//! Implements the Ed25519 signature scheme as specified in RFC 8032.
+//!
+//! A secret key is any 32 random bytes. The public key is derived from it
+//! and can be published, while signatures can only be created with the
+//! secret key.
+//!
+//! Verification rejects non-canonical encodings of points and scalars, but
+//! doesn't multiply by the cofactor, like most other implementations.
+
+mod field;
+mod point;
+mod scalar;
+
+use self::point::Point;
+use super::sha512::{sha512, Sha512};
+
+/// The size of a secret key in bytes.
+pub const SECRET_KEY_SIZE: usize = 32;
+
+/// The size of a public key in bytes.
+pub const PUBLIC_KEY_SIZE: usize = 32;
+
+/// The size of a signature in bytes.
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// An Ed25519 secret key.
+pub type SecretKey = [u8; SECRET_KEY_SIZE];
+
+/// An Ed25519 public key.
+pub type PublicKey = [u8; PUBLIC_KEY_SIZE];
+
+/// An Ed25519 signature.
+pub type Signature = [u8; SIGNATURE_SIZE];
+
+/// Returns the scalar and the prefix derived from the secret key.
+fn expand(secret_key: &SecretKey) -> ([u8; 32], [u8; 32]) {
+    let hash = sha512(secret_key);
+    let mut scalar = [0; 32];
+    let mut prefix = [0; 32];
+    scalar.copy_from_slice(&hash[..32]);
+    prefix.copy_from_slice(&hash[32..]);
+
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+
+    (scalar, prefix)
+}
+
+/// Returns the hash of the signature parts and the message reduced modulo the
+/// group order.
+fn challenge(encoded_r: &[u8], public_key: &PublicKey, message: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha512::new();
+    hasher.update(encoded_r);
+    hasher.update(public_key);
+    hasher.update(message);
+
+    scalar::reduce(&hasher.finish())
+}
+
+/// Returns the public key that belongs to the secret key.
+pub fn public_key(secret_key: &SecretKey) -> PublicKey {
+    let (scalar, _) = expand(secret_key);
+
+    Point::base().multiply(&scalar).to_bytes()
+}
+
+/// Signs the message with the secret key.
+pub fn sign(secret_key: &SecretKey, message: &[u8]) -> Signature {
+    let (secret_scalar, prefix) = expand(secret_key);
+    let public_key = Point::base().multiply(&secret_scalar).to_bytes();
+
+    let mut hasher = Sha512::new();
+    hasher.update(&prefix);
+    hasher.update(message);
+    let nonce = scalar::reduce(&hasher.finish());
+
+    let encoded_r = Point::base().multiply(&nonce).to_bytes();
+    let k = challenge(&encoded_r, &public_key, message);
+    let s = scalar::multiply_add(&k, &secret_scalar, &nonce);
+
+    let mut signature = [0; SIGNATURE_SIZE];
+    signature[..32].copy_from_slice(&encoded_r);
+    signature[32..].copy_from_slice(&s);
+
+    signature
+}
+
+/// Returns true if the signature of the message is valid for the public key.
+pub fn verify(public_key: &PublicKey, message: &[u8], signature: &Signature) -> bool {
+    let mut encoded_r = [0; 32];
+    let mut s = [0; 32];
+    encoded_r.copy_from_slice(&signature[..32]);
+    s.copy_from_slice(&signature[32..]);
+
+    if !scalar::is_canonical(&s) {
+        return false;
+    }
+
+    let (a, r) = match (Point::from_bytes(public_key), Point::from_bytes(&encoded_r)) {
+        (Some(a), Some(r)) => (a, r),
+        _ => return false
+    };
+
+    let k = challenge(&encoded_r, public_key, message);
+
+    // [s]B = R + [k]A
+    Point::base().multiply(&s).to_bytes() == r.add(&a.multiply(&k)).to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Converts a hexadecimal string to bytes.
+    fn bytes(hex: &str, buffer: &mut [u8]) {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+    }
+
+    /// Tests the first two test vectors of RFC 8032 section 7.1.
+    #[test]
+    fn test_known_vectors() {
+        let vectors: &[(&str, &str, &[u8], &str)] = &[
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                b"",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc\
+                 61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                &[0x72],
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e4\
+                 58f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"
+            )
+        ];
+
+        for &(secret_hex, public_hex, message, signature_hex) in vectors {
+            let mut secret_key = [0; SECRET_KEY_SIZE];
+            let mut expected_public_key = [0; PUBLIC_KEY_SIZE];
+            let mut expected_signature = [0; SIGNATURE_SIZE];
+            bytes(secret_hex, &mut secret_key);
+            bytes(public_hex, &mut expected_public_key);
+            bytes(signature_hex, &mut expected_signature);
+
+            assert_eq!(public_key(&secret_key), expected_public_key);
+
+            let signature = sign(&secret_key, message);
+            assert_eq!(&signature[..], &expected_signature[..]);
+            assert!(verify(&expected_public_key, message, &signature));
+        }
+    }
+
+    /// Tests that modified messages, signatures and keys are rejected.
+    #[test]
+    fn test_reject_modifications() {
+        let secret_key = [3; SECRET_KEY_SIZE];
+        let key = public_key(&secret_key);
+        let signature = sign(&secret_key, b"message");
+
+        assert!(verify(&key, b"message", &signature));
+        assert!(!verify(&key, b"massage", &signature));
+        assert!(!verify(
+            &public_key(&[4; SECRET_KEY_SIZE]),
+            b"message",
+            &signature
+        ));
+
+        for &index in &[0, 31, 32, 63] {
+            let mut modified = signature;
+            modified[index] ^= 1;
+            assert!(!verify(&key, b"message", &modified));
+        }
+
+        // Adding the order to s gives a non-canonical but otherwise valid
+        // signature.
+        let order: [u8; 32] = [
+            0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9,
+            0xde, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x10
+        ];
+        let mut malleable = signature;
+        let mut carry = 0;
+        for (byte, &order_byte) in malleable[32..].iter_mut().zip(order.iter()) {
+            let sum = u16::from(*byte) + u16::from(order_byte) + carry;
+            *byte = sum as u8;
+            carry = sum >> 8;
+        }
+        assert!(!verify(&key, b"message", &malleable));
+    }
+}
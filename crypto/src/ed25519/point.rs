@@ -0,0 +1,189 @@
+//! Implements the group of points on the twisted Edwards curve
+//! `-x^2 + y^2 = 1 + d * x^2 * y^2`.
+
+use super::field::FieldElement;
+
+/// The curve constant `d = -121665 / 121666` in little endian.
+const D: [u8; 32] = [
+    0xa3, 0x78, 0x59, 0x13, 0xca, 0x4d, 0xeb, 0x75, 0xab, 0xd8, 0x41, 0x41, 0x4d, 0x0a, 0x70, 0x00,
+    0x98, 0xe8, 0x79, 0x77, 0x79, 0x40, 0xc7, 0x8c, 0x73, 0xfe, 0x6f, 0x2b, 0xee, 0x6c, 0x03, 0x52
+];
+
+/// The encoding of the base point.
+const BASE_POINT: [u8; 32] = [
+    0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66
+];
+
+/// A point in extended coordinates.
+///
+/// The affine coordinates are `x = X / Z` and `y = Y / Z`, while `T = X * Y / Z`.
+#[derive(Clone, Copy)]
+pub struct Point {
+    /// The `X` coordinate.
+    x: FieldElement,
+    /// The `Y` coordinate.
+    y: FieldElement,
+    /// The `Z` coordinate.
+    z: FieldElement,
+    /// The `T` coordinate.
+    t: FieldElement
+}
+
+impl Point {
+    /// Returns the neutral element.
+    pub fn identity() -> Point {
+        Point {
+            x: FieldElement::from_u64(0),
+            y: FieldElement::from_u64(1),
+            z: FieldElement::from_u64(1),
+            t: FieldElement::from_u64(0)
+        }
+    }
+
+    /// Returns the base point of Ed25519.
+    pub fn base() -> Point {
+        Point::from_bytes(&BASE_POINT).expect("The base point is valid.")
+    }
+
+    /// Decodes a point.
+    ///
+    /// Returns `None` if the encoding isn't a point on the curve.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Option<Point> {
+        let y = FieldElement::from_bytes(bytes);
+        let x_is_negative = bytes[31] >> 7 == 1;
+
+        // Non-canonical encodings of y are rejected.
+        let mut canonical = *bytes;
+        canonical[31] &= 0x7f;
+        if y.to_bytes() != canonical {
+            return None;
+        }
+
+        // x^2 = (y^2 - 1) / (d * y^2 + 1)
+        let one = FieldElement::from_u64(1);
+        let y2 = y.square();
+        let mut x =
+            FieldElement::sqrt_ratio(&(y2 - one), &(FieldElement::from_bytes(&D) * y2 + one))?;
+
+        if x.is_zero() && x_is_negative {
+            return None;
+        }
+
+        if x.is_negative() != x_is_negative {
+            x = -x;
+        }
+
+        Some(Point {
+            x,
+            y,
+            z: one,
+            t: x * y
+        })
+    }
+
+    /// Returns the encoding of the point.
+    pub fn to_bytes(self) -> [u8; 32] {
+        let z_inverse = self.z.invert();
+        let x = self.x * z_inverse;
+        let y = self.y * z_inverse;
+
+        let mut bytes = y.to_bytes();
+        bytes[31] |= (x.is_negative() as u8) << 7;
+
+        bytes
+    }
+
+    /// Adds two points.
+    ///
+    /// The formula is complete, so it also works for doubling and the
+    /// neutral element.
+    pub fn add(&self, other: &Point) -> Point {
+        let d2 = FieldElement::from_bytes(&D) + FieldElement::from_bytes(&D);
+
+        let a = (self.y - self.x) * (other.y - other.x);
+        let b = (self.y + self.x) * (other.y + other.x);
+        let c = self.t * d2 * other.t;
+        let d = (self.z + self.z) * other.z;
+        let e = b - a;
+        let f = d - c;
+        let g = d + c;
+        let h = b + a;
+
+        Point {
+            x: e * f,
+            y: g * h,
+            z: f * g,
+            t: e * h
+        }
+    }
+
+    /// Multiplies the point by the little endian scalar.
+    ///
+    /// The same operations are performed regardless of the scalar.
+    pub fn multiply(&self, scalar: &[u8; 32]) -> Point {
+        let mut result = Point::identity();
+
+        for i in (0..256).rev() {
+            result = result.add(&result);
+
+            let sum = result.add(self);
+            let bit = (scalar[i / 8] >> (i % 8)) & 1 == 1;
+
+            result = Point {
+                x: result.x.select(&sum.x, bit),
+                y: result.y.select(&sum.y, bit),
+                z: result.z.select(&sum.z, bit),
+                t: result.t.select(&sum.t, bit)
+            };
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the curve constant has the right value.
+    #[test]
+    fn test_curve_constant() {
+        let d = FieldElement::from_bytes(&D);
+
+        assert_eq!(
+            (d * FieldElement::from_u64(121666)).to_bytes(),
+            (-FieldElement::from_u64(121665)).to_bytes()
+        );
+    }
+
+    /// Tests that points survive an encoding round trip.
+    #[test]
+    fn test_encoding() {
+        let base = Point::base();
+        assert_eq!(base.to_bytes(), BASE_POINT);
+
+        let point = base.multiply(&[7; 32]);
+        let decoded = Point::from_bytes(&point.to_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes(), point.to_bytes());
+    }
+
+    /// Tests that the multiplication agrees with repeated addition.
+    #[test]
+    fn test_multiply() {
+        let base = Point::base();
+        let mut scalar = [0; 32];
+        scalar[0] = 5;
+
+        let mut sum = Point::identity();
+        for _ in 0..5 {
+            sum = sum.add(&base);
+        }
+
+        assert_eq!(base.multiply(&scalar).to_bytes(), sum.to_bytes());
+        assert_eq!(
+            base.multiply(&[0; 32]).to_bytes(),
+            Point::identity().to_bytes()
+        );
+    }
+}
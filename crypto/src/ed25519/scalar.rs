@@ -0,0 +1,185 @@
+//! Implements arithmetic modulo the group order
+//! `L = 2^252 + 27742317777372353535851937790883648493`.
+//!
+//! Numbers are stored as little endian bytes or `u32` limbs. The operations
+//! are simple rather than fast, only a few of them are needed per signature.
+
+/// The group order in little endian limbs.
+const ORDER: [u32; 8] = [
+    0x5cf5d3ed, 0x5812631a, 0xa2f79cd6, 0x14def9de, 0x00000000, 0x00000000, 0x00000000, 0x10000000
+];
+
+/// Returns true if the little endian number is smaller than the group order.
+pub fn is_canonical(bytes: &[u8; 32]) -> bool {
+    let limbs = to_limbs(bytes);
+
+    for i in (0..8).rev() {
+        if limbs[i] != ORDER[i] {
+            return limbs[i] < ORDER[i];
+        }
+    }
+
+    false
+}
+
+/// Reduces the little endian number of at most 64 bytes modulo the group
+/// order.
+pub fn reduce(bytes: &[u8]) -> [u8; 32] {
+    let mut remainder = [0u32; 8];
+
+    // The number is reduced bit by bit, starting with the most significant
+    // one. The remainder is always smaller than the order, so doubling it
+    // never overflows.
+    for i in (0..bytes.len() * 8).rev() {
+        let bit = u32::from((bytes[i / 8] >> (i % 8)) & 1);
+
+        let mut carry = bit;
+        for limb in remainder.iter_mut() {
+            let next_carry = *limb >> 31;
+            *limb = *limb << 1 | carry;
+            carry = next_carry;
+        }
+
+        subtract_order_if_larger(&mut remainder);
+    }
+
+    from_limbs(&remainder)
+}
+
+/// Returns `(a * b + c) mod L`.
+pub fn multiply_add(a: &[u8; 32], b: &[u8; 32], c: &[u8; 32]) -> [u8; 32] {
+    let a = to_limbs(a);
+    let b = to_limbs(b);
+    let c = to_limbs(c);
+    let mut product = [0u32; 17];
+
+    for (i, &limb) in c.iter().enumerate() {
+        product[i] = limb;
+    }
+
+    for i in 0..8 {
+        let mut carry = 0u64;
+
+        for j in 0..8 {
+            let value = u64::from(a[i]) * u64::from(b[j]) + u64::from(product[i + j]) + carry;
+            product[i + j] = value as u32;
+            carry = value >> 32;
+        }
+
+        let mut k = i + 8;
+        while carry != 0 {
+            let value = u64::from(product[k]) + carry;
+            product[k] = value as u32;
+            carry = value >> 32;
+            k += 1;
+        }
+    }
+
+    let mut bytes = [0; 68];
+    for (i, &limb) in product.iter().enumerate() {
+        for j in 0..4 {
+            bytes[4 * i + j] = (limb >> (8 * j)) as u8;
+        }
+    }
+
+    reduce(&bytes)
+}
+
+/// Subtracts the order from the number if it isn't smaller, without
+/// branching on the value.
+fn subtract_order_if_larger(limbs: &mut [u32; 8]) {
+    let mut difference = [0u32; 8];
+    let mut borrow = 0u64;
+
+    for i in 0..8 {
+        let value = u64::from(limbs[i])
+            .wrapping_sub(u64::from(ORDER[i]))
+            .wrapping_sub(borrow);
+        difference[i] = value as u32;
+        borrow = value >> 63;
+    }
+
+    // Without a borrow the number was at least the order.
+    let mask = (borrow as u32).wrapping_sub(1);
+    for i in 0..8 {
+        limbs[i] = (difference[i] & mask) | (limbs[i] & !mask);
+    }
+}
+
+/// Converts little endian bytes to limbs.
+fn to_limbs(bytes: &[u8; 32]) -> [u32; 8] {
+    let mut limbs = [0; 8];
+
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        *limb = super::super::read_u32_le(&bytes[4 * i..]);
+    }
+
+    limbs
+}
+
+/// Converts limbs to little endian bytes.
+fn from_limbs(limbs: &[u32; 8]) -> [u8; 32] {
+    let mut bytes = [0; 32];
+
+    for (i, &limb) in limbs.iter().enumerate() {
+        super::super::write_u32_le(&mut bytes[4 * i..], limb);
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests the reduction of numbers around the order.
+    #[test]
+    fn test_reduce() {
+        let order = from_limbs(&ORDER);
+        assert!(!is_canonical(&order));
+        assert_eq!(reduce(&order), [0; 32]);
+
+        let mut order_plus_one = order;
+        order_plus_one[0] += 1;
+        let mut one = [0; 32];
+        one[0] = 1;
+        assert_eq!(reduce(&order_plus_one), one);
+
+        let mut order_minus_one = order;
+        order_minus_one[0] -= 1;
+        assert!(is_canonical(&order_minus_one));
+        assert_eq!(reduce(&order_minus_one), order_minus_one);
+
+        // 2^512 - 1 mod L
+        assert_eq!(
+            reduce(&[0xff; 64]),
+            [
+                0x00, 0x0f, 0x9c, 0x44, 0xe3, 0x11, 0x06, 0xa4, 0x47, 0x93, 0x85, 0x68, 0xa7, 0x1b,
+                0x0e, 0xd0, 0x65, 0xbe, 0xf5, 0x17, 0xd2, 0x73, 0xec, 0xce, 0x3d, 0x9a, 0x30, 0x7c,
+                0x1b, 0x41, 0x99, 0x03
+            ]
+        );
+    }
+
+    /// Tests the multiplication with known results.
+    #[test]
+    fn test_multiply_add() {
+        let mut two = [0; 32];
+        two[0] = 2;
+        let mut three = [0; 32];
+        three[0] = 3;
+        let mut seven = [0; 32];
+        seven[0] = 7;
+
+        assert_eq!(multiply_add(&two, &three, &[0; 32]), reduce(&[6]));
+        assert_eq!(multiply_add(&two, &three, &seven), reduce(&[13]));
+
+        // (L - 1) * (L - 1) = 1 mod L
+        let mut order_minus_one = from_limbs(&ORDER);
+        order_minus_one[0] -= 1;
+        assert_eq!(
+            multiply_add(&order_minus_one, &order_minus_one, &[0; 32]),
+            reduce(&[1])
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! This crate implements the cryptographic primitives of VeOS.
+//!
+//! The kernel uses it without `std`, while host tools such as `mkinitramfs`
+//! can use the same implementations, so both always compute the same hashes
+//! and checksums.
+//!
+//! The following primitives are implemented:
+//!
+//! - `sha256`: The SHA-256 hash function.
+//! - `sha512`: The SHA-512 hash function.
+//! - `crc32`: The CRC-32 checksum used by zlib and ethernet.
+//! - `chacha20`: The ChaCha20 stream cipher from RFC 8439.
+//! - `ed25519`: The Ed25519 signature scheme from RFC 8032.
+//! - `rng`: A cryptographically secure random number generator based on
+//!   ChaCha20.
+//!
+//! None of the implementations try to resist side channel attacks beyond
+//! avoiding data dependent branches and table lookups in ChaCha20 and in the
+//! scalar multiplication of Ed25519.
+
+#![no_std]
+
+pub mod chacha20;
+pub mod crc32;
+pub mod ed25519;
+pub mod rng;
+pub mod sha256;
+pub mod sha512;
+
+/// Reads a little endian `u32` from the first four bytes of the slice.
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0])
+        | u32::from(bytes[1]) << 8
+        | u32::from(bytes[2]) << 16
+        | u32::from(bytes[3]) << 24
+}
+
+/// Reads a big endian `u32` from the first four bytes of the slice.
+fn read_u32_be(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0]) << 24
+        | u32::from(bytes[1]) << 16
+        | u32::from(bytes[2]) << 8
+        | u32::from(bytes[3])
+}
+
+/// Reads a big endian `u64` from the first eight bytes of the slice.
+fn read_u64_be(bytes: &[u8]) -> u64 {
+    u64::from(read_u32_be(bytes)) << 32 | u64::from(read_u32_be(&bytes[4..]))
+}
+
+/// Writes the value as little endian to the first four bytes of the slice.
+fn write_u32_le(bytes: &mut [u8], value: u32) {
+    for (i, byte) in bytes[..4].iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+}
+
+/// Writes the value as big endian to the first four bytes of the slice.
+fn write_u32_be(bytes: &mut [u8], value: u32) {
+    for (i, byte) in bytes[..4].iter_mut().enumerate() {
+        *byte = (value >> (24 - 8 * i)) as u8;
+    }
+}
+
+/// Writes the value as big endian to the first eight bytes of the slice.
+fn write_u64_be(bytes: &mut [u8], value: u64) {
+    write_u32_be(bytes, (value >> 32) as u32);
+    write_u32_be(&mut bytes[4..], value as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that values survive a round trip in both byte orders.
+    #[test]
+    fn test_byte_order() {
+        let mut bytes = [0; 4];
+
+        write_u32_le(&mut bytes, 0x12345678);
+        assert_eq!(bytes, [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(read_u32_le(&bytes), 0x12345678);
+
+        write_u32_be(&mut bytes, 0x12345678);
+        assert_eq!(bytes, [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(read_u32_be(&bytes), 0x12345678);
+
+        let mut bytes = [0; 8];
+        write_u64_be(&mut bytes, 0x0123456789abcdef);
+        assert_eq!(bytes, [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]);
+        assert_eq!(read_u64_be(&bytes), 0x0123456789abcdef);
+    }
+}
@@ -0,0 +1,93 @@
+//! Implements a cryptographically secure random number generator.
+//!
+//! The generator produces the key stream of ChaCha20. After every request the
+//! key is replaced with fresh output of the generator, so that a later
+//! compromise of the state doesn't reveal earlier output.
+//!
+//! The generator is only as good as its seed. It doesn't gather entropy by
+//! itself, the user needs to seed it and should add more entropy with
+//! `reseed` when it becomes available.
+
+use super::chacha20::{ChaCha20, BLOCK_SIZE, KEY_SIZE, NONCE_SIZE};
+use super::sha256::Sha256;
+
+/// A random number generator based on ChaCha20.
+pub struct Rng {
+    /// The current key.
+    key: [u8; KEY_SIZE]
+}
+
+impl Rng {
+    /// Creates a generator from the given seed.
+    pub fn new(seed: &[u8; KEY_SIZE]) -> Rng {
+        Rng { key: *seed }
+    }
+
+    /// Mixes more entropy into the state of the generator.
+    pub fn reseed(&mut self, entropy: &[u8]) {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.key);
+        hasher.update(entropy);
+
+        self.key = hasher.finish();
+    }
+
+    /// Fills the buffer with random bytes.
+    pub fn fill(&mut self, buffer: &mut [u8]) {
+        let mut cipher = ChaCha20::new(&self.key, &[0; NONCE_SIZE], 0);
+
+        // The first block becomes the next key, so it is never output.
+        let mut next_key = [0; BLOCK_SIZE];
+        cipher.apply_keystream(&mut next_key);
+        self.key.copy_from_slice(&next_key[..KEY_SIZE]);
+
+        for byte in buffer.iter_mut() {
+            *byte = 0;
+        }
+        cipher.apply_keystream(buffer);
+    }
+
+    /// Returns a random `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill(&mut bytes);
+
+        bytes
+            .iter()
+            .enumerate()
+            .fold(0, |value, (i, &byte)| value | u64::from(byte) << (8 * i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the output only depends on the seed and the reseeds.
+    #[test]
+    fn test_deterministic() {
+        let mut first = Rng::new(&[1; KEY_SIZE]);
+        let mut second = Rng::new(&[1; KEY_SIZE]);
+        assert_eq!(first.next_u64(), second.next_u64());
+
+        first.reseed(b"entropy");
+        second.reseed(b"entropy");
+        assert_eq!(first.next_u64(), second.next_u64());
+
+        second.reseed(b"more entropy");
+        assert_ne!(first.next_u64(), second.next_u64());
+    }
+
+    /// Tests that the output changes with every request and the seed.
+    #[test]
+    fn test_output_changes() {
+        let mut rng = Rng::new(&[0; KEY_SIZE]);
+        let mut first = [0; 100];
+        let mut second = [0; 100];
+        rng.fill(&mut first);
+        rng.fill(&mut second);
+
+        assert!(first.iter().zip(second.iter()).any(|(a, b)| a != b));
+        assert_ne!(Rng::new(&[0; KEY_SIZE]).next_u64(), Rng::new(&[2; KEY_SIZE]).next_u64());
+    }
+}
@@ -0,0 +1,237 @@
+//! Implements the SHA-256 hash function as specified in FIPS 180-4.
+
+use super::{read_u32_be, write_u32_be};
+
+/// The size of a digest in bytes.
+pub const DIGEST_SIZE: usize = 32;
+
+/// The size of a block in bytes.
+const BLOCK_SIZE: usize = 64;
+
+/// A SHA-256 digest.
+pub type Digest = [u8; DIGEST_SIZE];
+
+/// The initial hash value.
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19
+];
+
+/// The round constants.
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+];
+
+/// Computes a SHA-256 digest incrementally.
+#[derive(Clone)]
+pub struct Sha256 {
+    /// The current hash value.
+    state: [u32; 8],
+    /// The bytes that don't fill a block yet.
+    buffer: [u8; BLOCK_SIZE],
+    /// The number of bytes in the buffer.
+    buffered: usize,
+    /// The total number of bytes hashed.
+    length: u64
+}
+
+impl Sha256 {
+    /// Starts a new digest.
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: INITIAL_STATE,
+            buffer: [0; BLOCK_SIZE],
+            buffered: 0,
+            length: 0
+        }
+    }
+
+    /// Adds the data to the digest.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+
+        if self.buffered > 0 {
+            let length = (BLOCK_SIZE - self.buffered).min(data.len());
+
+            self.buffer[self.buffered..self.buffered + length].copy_from_slice(&data[..length]);
+            self.buffered += length;
+            data = &data[length..];
+
+            if self.buffered < BLOCK_SIZE {
+                return;
+            }
+
+            let block = self.buffer;
+            self.compress(&block);
+            self.buffered = 0;
+        }
+
+        while data.len() >= BLOCK_SIZE {
+            self.compress(&data[..BLOCK_SIZE]);
+            data = &data[BLOCK_SIZE..];
+        }
+
+        self.buffer[..data.len()].copy_from_slice(data);
+        self.buffered = data.len();
+    }
+
+    /// Finishes the digest and returns it.
+    pub fn finish(mut self) -> Digest {
+        let bit_length = self.length.wrapping_mul(8);
+
+        // The message is padded with a one bit, zeros and the length in bits,
+        // so that it fills a whole number of blocks.
+        let mut padding = [0; BLOCK_SIZE + 8];
+        padding[0] = 0x80;
+        let zeros = (BLOCK_SIZE * 2 - 8 - self.buffered - 1) % BLOCK_SIZE;
+        for i in 0..8 {
+            padding[1 + zeros + i] = (bit_length >> (56 - 8 * i)) as u8;
+        }
+
+        let length = self.length;
+        self.update(&padding[..1 + zeros + 8]);
+        self.length = length;
+        debug_assert_eq!(self.buffered, 0);
+
+        let mut digest = [0; DIGEST_SIZE];
+        for (i, &word) in self.state.iter().enumerate() {
+            write_u32_be(&mut digest[4 * i..], word);
+        }
+
+        digest
+    }
+
+    /// Processes one block of data.
+    fn compress(&mut self, block: &[u8]) {
+        let mut schedule = [0u32; 64];
+
+        for i in 0..16 {
+            schedule[i] = read_u32_be(&block[4 * i..]);
+        }
+
+        for i in 16..64 {
+            let s0 = schedule[i - 15].rotate_right(7)
+                ^ schedule[i - 15].rotate_right(18)
+                ^ (schedule[i - 15] >> 3);
+            let s1 = schedule[i - 2].rotate_right(17)
+                ^ schedule[i - 2].rotate_right(19)
+                ^ (schedule[i - 2] >> 10);
+
+            schedule[i] = schedule[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut a = self.state[0];
+        let mut b = self.state[1];
+        let mut c = self.state[2];
+        let mut d = self.state[3];
+        let mut e = self.state[4];
+        let mut f = self.state[5];
+        let mut g = self.state[6];
+        let mut h = self.state[7];
+
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(schedule[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (word, added) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *word = word.wrapping_add(*added);
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256::new()
+    }
+}
+
+/// Returns the SHA-256 digest of the data.
+pub fn sha256(data: &[u8]) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Converts a hexadecimal string to a digest.
+    fn digest(hex: &str) -> Digest {
+        let mut digest = [0; DIGEST_SIZE];
+
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+
+        digest
+    }
+
+    /// Tests the digests of the test vectors from FIPS 180-4.
+    #[test]
+    fn test_known_vectors() {
+        assert_eq!(
+            sha256(b""),
+            digest("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            digest("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            digest("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1")
+        );
+    }
+
+    /// Tests that the digest doesn't depend on how the data is split up.
+    #[test]
+    fn test_incremental() {
+        let mut hasher = Sha256::new();
+        for _ in 0..1000 {
+            hasher.update(&[b'a'; 1000]);
+        }
+
+        assert_eq!(
+            hasher.finish(),
+            digest("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0")
+        );
+
+        let data = [0x5a; 200];
+        let expected = sha256(&data);
+        for split in 0..data.len() {
+            let mut hasher = Sha256::new();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+
+            assert_eq!(hasher.finish(), expected);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Implements the SHA-512 hash function as specified in FIPS 180-4.
+//!
+//! Ed25519 uses SHA-512, otherwise `sha256` should be preferred.
+
+use super::{read_u64_be, write_u64_be};
+
+/// The size of a digest in bytes.
+pub const DIGEST_SIZE: usize = 64;
+
+/// The size of a block in bytes.
+const BLOCK_SIZE: usize = 128;
+
+/// A SHA-512 digest.
+pub type Digest = [u8; DIGEST_SIZE];
+
+/// The initial hash value.
+const INITIAL_STATE: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179
+];
+
+/// The round constants.
+const ROUND_CONSTANTS: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817
+];
+
+/// Computes a SHA-512 digest incrementally.
+#[derive(Clone)]
+pub struct Sha512 {
+    /// The current hash value.
+    state: [u64; 8],
+    /// The bytes that don't fill a block yet.
+    buffer: [u8; BLOCK_SIZE],
+    /// The number of bytes in the buffer.
+    buffered: usize,
+    /// The total number of bytes hashed.
+    length: u64
+}
+
+impl Sha512 {
+    /// Starts a new digest.
+    pub fn new() -> Sha512 {
+        Sha512 {
+            state: INITIAL_STATE,
+            buffer: [0; BLOCK_SIZE],
+            buffered: 0,
+            length: 0
+        }
+    }
+
+    /// Adds the data to the digest.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+
+        if self.buffered > 0 {
+            let length = (BLOCK_SIZE - self.buffered).min(data.len());
+
+            self.buffer[self.buffered..self.buffered + length].copy_from_slice(&data[..length]);
+            self.buffered += length;
+            data = &data[length..];
+
+            if self.buffered < BLOCK_SIZE {
+                return;
+            }
+
+            let block = self.buffer;
+            self.compress(&block);
+            self.buffered = 0;
+        }
+
+        while data.len() >= BLOCK_SIZE {
+            self.compress(&data[..BLOCK_SIZE]);
+            data = &data[BLOCK_SIZE..];
+        }
+
+        self.buffer[..data.len()].copy_from_slice(data);
+        self.buffered = data.len();
+    }
+
+    /// Finishes the digest and returns it.
+    pub fn finish(mut self) -> Digest {
+        let bit_length = self.length.wrapping_mul(8);
+
+        // The message is padded with a one bit, zeros and the length in bits
+        // as a 128 bit number, so that it fills a whole number of blocks.
+        let mut padding = [0; BLOCK_SIZE + 16];
+        padding[0] = 0x80;
+        let zeros = (BLOCK_SIZE * 2 - 16 - self.buffered - 1) % BLOCK_SIZE;
+        write_u64_be(&mut padding[1 + zeros + 8..], bit_length);
+
+        let length = self.length;
+        self.update(&padding[..1 + zeros + 16]);
+        self.length = length;
+        debug_assert_eq!(self.buffered, 0);
+
+        let mut digest = [0; DIGEST_SIZE];
+        for (i, &word) in self.state.iter().enumerate() {
+            write_u64_be(&mut digest[8 * i..], word);
+        }
+
+        digest
+    }
+
+    /// Processes one block of data.
+    fn compress(&mut self, block: &[u8]) {
+        let mut schedule = [0u64; 80];
+
+        for i in 0..16 {
+            schedule[i] = read_u64_be(&block[8 * i..]);
+        }
+
+        for i in 16..80 {
+            let s0 = schedule[i - 15].rotate_right(1)
+                ^ schedule[i - 15].rotate_right(8)
+                ^ (schedule[i - 15] >> 7);
+            let s1 = schedule[i - 2].rotate_right(19)
+                ^ schedule[i - 2].rotate_right(61)
+                ^ (schedule[i - 2] >> 6);
+
+            schedule[i] = schedule[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut a = self.state[0];
+        let mut b = self.state[1];
+        let mut c = self.state[2];
+        let mut d = self.state[3];
+        let mut e = self.state[4];
+        let mut f = self.state[5];
+        let mut g = self.state[6];
+        let mut h = self.state[7];
+
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(schedule[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (word, added) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *word = word.wrapping_add(*added);
+        }
+    }
+}
+
+impl Default for Sha512 {
+    fn default() -> Sha512 {
+        Sha512::new()
+    }
+}
+
+/// Returns the SHA-512 digest of the data.
+pub fn sha512(data: &[u8]) -> Digest {
+    let mut hasher = Sha512::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Converts a hexadecimal string to a digest.
+    fn digest(hex: &str) -> Digest {
+        let mut digest = [0; DIGEST_SIZE];
+
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+
+        digest
+    }
+
+    /// Tests the digests of the test vectors from FIPS 180-4.
+    #[test]
+    fn test_known_vectors() {
+        assert_eq!(
+            &sha512(b"")[..],
+            &digest(
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+                 47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+            )[..]
+        );
+        assert_eq!(
+            &sha512(b"abc")[..],
+            &digest(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+            )[..]
+        );
+        assert_eq!(
+            &sha512(
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmno\
+                  ijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu"
+            )[..],
+            &digest(
+                "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018\
+                 501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909"
+            )[..]
+        );
+    }
+
+    /// Tests that the digest doesn't depend on how the data is split up.
+    #[test]
+    fn test_incremental() {
+        let data = [0x5a; 300];
+        let expected = sha512(&data);
+
+        for split in 0..data.len() {
+            let mut hasher = Sha512::new();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+
+            assert_eq!(&hasher.finish()[..], &expected[..]);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+TARGET_FILES += $(TARGET_DIR)/bin/display-demo
+BUILD_DIRS += display-demo/target
+INITRAMFS_FILES += /bin/display-demo
+FMT_DIRS += display-demo
+
+$(TARGET_DIR)/bin/display-demo: display-demo/target/$(BUILD_TARGET)/$(BUILD_TYPE)/display-demo
+	@mkdir -p $(shell dirname $@)
+	cp $< $@
+
+display-demo/target/$(BUILD_TARGET)/$(BUILD_TYPE)/display-demo: display-demo/target/$(BUILD_TARGET)/$(BUILD_TYPE)/libdisplay_demo.a
+	$(LINKER) $(LINKER_FLAGS) $< -o $@
+
+display-demo/target/$(BUILD_TARGET)/$(BUILD_TYPE)/libdisplay_demo.a: $(shell find display-demo/src -name "*.rs") display-demo/Cargo.toml $(STD_FILES)
+	cd display-demo && $(RUST_COMPILER) build $(RUST_COMPILER_FLAGS)
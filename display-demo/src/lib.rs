@@ -0,0 +1,174 @@
+//! Shows two overlapping surfaces of the display server.
+//!
+//! Each surface shows whether it has the focus and the last scancode it
+//! received. F1 passes the focus on to the other surface.
+
+#![no_std]
+
+#[macro_use]
+extern crate veos_std;
+#[allow(unused_extern_crates)]
+extern crate rlibc;
+
+use core::str;
+use core::time::Duration;
+use veos_std::display::{cell, color_code, Display, Event, Rectangle, Surface, SurfaceMemory};
+use veos_std::thread;
+use veos_std::Error;
+
+/// The number of times connecting to the display server is tried.
+const CONNECT_ATTEMPTS: usize = 20;
+
+/// The time between two attempts to connect to the display server.
+const CONNECT_INTERVAL_MS: u64 = 100;
+
+/// The memory of the first surface.
+static mut FIRST_MEMORY: SurfaceMemory = SurfaceMemory::new();
+
+/// The memory of the second surface.
+static mut SECOND_MEMORY: SurfaceMemory = SurfaceMemory::new();
+
+/// A surface together with what it shows.
+struct Window {
+    /// The surface of the window.
+    surface: Surface,
+    /// The title of the window.
+    title: &'static str,
+    /// Whether the window has the focus.
+    focused: bool,
+    /// The last scancode the window received.
+    last_scancode: Option<u8>,
+}
+
+impl Window {
+    /// Draws the window and tells the display server about it.
+    fn draw(&mut self, display: &Display) -> Result<(), Error> {
+        let bounds = self.surface.bounds();
+        let body = color_code(0, 7);
+        let title_bar = if self.focused {
+            color_code(15, 4)
+        } else {
+            color_code(0, 8)
+        };
+
+        self.surface.fill(bounds, cell(b' ', body));
+        self.surface.fill(
+            Rectangle {
+                height: 1,
+                ..bounds
+            },
+            cell(b' ', title_bar),
+        );
+        self.surface.write_str(1, 0, self.title, title_bar);
+
+        let hint = if self.focused {
+            "Press some keys or F1."
+        } else {
+            "Waiting for the focus."
+        };
+        self.surface.write_str(1, 2, hint, body);
+
+        if let Some(scancode) = self.last_scancode {
+            let mut text = *b"Last scancode: 0x00";
+            text[17] = hex_digit(scancode >> 4);
+            text[18] = hex_digit(scancode & 0xf);
+
+            self.surface
+                .write_str(1, 4, str::from_utf8(&text).unwrap(), body);
+        }
+
+        display.damage(&self.surface, bounds)
+    }
+}
+
+/// Returns the hexadecimal digit for the value below 16.
+fn hex_digit(value: u8) -> u8 {
+    if value < 10 {
+        b'0' + value
+    } else {
+        b'a' + value - 10
+    }
+}
+
+/// Connects to the display server, waiting for it to start.
+fn connect() -> Result<Display, Error> {
+    for _ in 1..CONNECT_ATTEMPTS {
+        if let Ok(display) = Display::connect() {
+            return Ok(display);
+        }
+
+        thread::sleep(Duration::from_millis(CONNECT_INTERVAL_MS));
+    }
+
+    Display::connect()
+}
+
+/// Creates the windows and redraws them on every event.
+fn run() -> Result<(), Error> {
+    let mut display = connect()?;
+
+    let mut windows = [
+        Window {
+            surface: display.create_surface(
+                unsafe { &mut FIRST_MEMORY },
+                Rectangle {
+                    x: 4,
+                    y: 3,
+                    width: 40,
+                    height: 10,
+                },
+            )?,
+            title: "First surface",
+            focused: false,
+            last_scancode: None,
+        },
+        Window {
+            surface: display.create_surface(
+                unsafe { &mut SECOND_MEMORY },
+                Rectangle {
+                    x: 30,
+                    y: 9,
+                    width: 40,
+                    height: 10,
+                },
+            )?,
+            title: "Second surface",
+            focused: false,
+            last_scancode: None,
+        },
+    ];
+
+    for window in windows.iter_mut() {
+        window.draw(&display)?;
+    }
+
+    loop {
+        let event = match display.next_event(None)? {
+            Some(event) => event,
+            None => continue,
+        };
+
+        let surface = match event {
+            Event::Key { surface, .. } | Event::Focus { surface, .. } => surface,
+        };
+
+        if let Some(window) = windows
+            .iter_mut()
+            .find(|window| window.surface.id() == surface)
+        {
+            match event {
+                Event::Key { scancode, .. } => window.last_scancode = Some(scancode),
+                Event::Focus { focused, .. } => window.focused = focused,
+            }
+
+            window.draw(&display)?;
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() {
+    if let Err(error) = run() {
+        println!("The display demo failed: {:?}", error);
+    }
+}
@@ -0,0 +1,18 @@
+TARGET_FILES += $(TARGET_DIR)/bin/display $(TARGET_DIR)/etc/servers/display.manifest
+BUILD_DIRS += display/target
+INITRAMFS_FILES += /bin/display /etc/servers/display.manifest
+FMT_DIRS += display
+
+$(TARGET_DIR)/bin/display: display/target/$(BUILD_TARGET)/$(BUILD_TYPE)/display
+	@mkdir -p $(shell dirname $@)
+	cp $< $@
+
+$(TARGET_DIR)/etc/servers/display.manifest: display/display.manifest
+	@mkdir -p $(shell dirname $@)
+	cp $< $@
+
+display/target/$(BUILD_TARGET)/$(BUILD_TYPE)/display: display/target/$(BUILD_TARGET)/$(BUILD_TYPE)/libdisplay.a
+	$(LINKER) $(LINKER_FLAGS) $< -o $@
+
+display/target/$(BUILD_TARGET)/$(BUILD_TYPE)/libdisplay.a: $(shell find display/src -name "*.rs") display/Cargo.toml $(STD_FILES)
+	cd display && $(RUST_COMPILER) build $(RUST_COMPILER_FLAGS)
\ No newline at end of file
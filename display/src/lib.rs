@@ -0,0 +1,457 @@
+//! The display server.
+//!
+//! The server composites the surfaces of its clients on the text screen and
+//! passes the keyboard input on to the client of the surface with the focus,
+//! which is the one on top. Pressing F1 moves the surface on top to the
+//! bottom, so that the focus cycles through the surfaces.
+//!
+//! The server assumes the 80x25 text mode that the boot loaders set up. There
+//! is no mouse driver yet, so the focus can only be changed with the
+//! keyboard.
+
+#![no_std]
+
+#[macro_use]
+extern crate veos_std;
+#[allow(unused_extern_crates)]
+extern crate rlibc;
+
+use core::ptr;
+use core::time::Duration;
+use veos_std::display::{
+    cell, color_code, Cell, Channel, Event, Rectangle, Request, SurfaceId, SERVICE_NAME,
+    SURFACE_CELLS,
+};
+use veos_std::io::{self, FileDescriptor, PollFd, O_NONBLOCK, POLL_IN};
+use veos_std::ipc::{grant, Ring};
+use veos_std::{service, Error};
+
+/// The number of columns of the screen.
+const SCREEN_WIDTH: u16 = 80;
+
+/// The number of rows of the screen.
+const SCREEN_HEIGHT: u16 = 25;
+
+/// The size of the screen device in bytes.
+const FRAME_SIZE: usize = SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize * 2;
+
+/// The maximum number of connected clients.
+const MAX_CLIENTS: usize = 8;
+
+/// The maximum number of surfaces of all clients.
+const MAX_SURFACES: usize = 16;
+
+/// The interval in which new clients are accepted.
+const ACCEPT_INTERVAL_MS: u64 = 50;
+
+/// The scancode of pressing F1, which passes the focus on.
+const FOCUS_SCANCODE: u8 = 0x3b;
+
+/// The bit that is set in the scancodes of released keys.
+const RELEASE_BIT: u8 = 0x80;
+
+/// A client of the server.
+struct Client {
+    /// The ID of the client process.
+    pid: u64,
+    /// Carries the requests from the client.
+    requests: Channel,
+    /// Carries the events to the client, once it was accepted.
+    events: Option<Channel>,
+}
+
+/// A surface on the screen.
+#[derive(Clone, Copy)]
+struct Surface {
+    /// The index of the client of the surface.
+    client: usize,
+    /// The ID the client gave the surface.
+    id: SurfaceId,
+    /// The area of the screen the surface covers.
+    area: Rectangle,
+    /// The cells of the surface, shared with the client.
+    cells: *const Cell,
+}
+
+impl Surface {
+    /// Returns the cell of the surface at the given position of the screen.
+    fn cell_at(&self, x: u16, y: u16) -> Cell {
+        let index = usize::from(y - self.area.y) * usize::from(self.area.width)
+            + usize::from(x - self.area.x);
+
+        // The client writes the cells concurrently.
+        unsafe { ptr::read_volatile(self.cells.add(index)) }
+    }
+}
+
+/// The state of the server.
+struct Server {
+    /// The connected clients.
+    clients: [Option<Client>; MAX_CLIENTS],
+    /// The surfaces from the bottom to the top.
+    surfaces: [Option<Surface>; MAX_SURFACES],
+    /// The number of surfaces.
+    surface_count: usize,
+    /// The screen device.
+    screen: FileDescriptor,
+    /// The keyboard device.
+    keyboard: FileDescriptor,
+    /// The contents of the screen.
+    frame: [u8; FRAME_SIZE],
+    /// The part of the screen that must be composited again.
+    damage: Rectangle,
+}
+
+impl Server {
+    /// Opens the devices of the server.
+    fn new() -> Result<Server, Error> {
+        Ok(Server {
+            clients: Default::default(),
+            surfaces: [None; MAX_SURFACES],
+            surface_count: 0,
+            screen: io::open("/dev/screen", 0)?,
+            keyboard: io::open("/dev/keyboard", O_NONBLOCK)?,
+            frame: [0; FRAME_SIZE],
+            damage: screen_area(),
+        })
+    }
+
+    /// Serves the clients forever.
+    fn run(&mut self) -> ! {
+        loop {
+            self.composite();
+            self.wait();
+            self.accept_rings();
+            self.handle_keyboard();
+            self.handle_requests();
+        }
+    }
+
+    /// Waits until there is input or the next clients should be accepted.
+    fn wait(&mut self) {
+        let mut descriptors = [PollFd {
+            descriptor: self.keyboard,
+            events: POLL_IN,
+            ready_events: 0,
+        }; MAX_CLIENTS + 1];
+        let mut count = 1;
+
+        for client in self.clients.iter().filter_map(Option::as_ref) {
+            descriptors[count].descriptor = client.requests.ring().descriptor();
+            count += 1;
+        }
+
+        // Errors only end the wait early.
+        let _ = io::poll(
+            &mut descriptors[..count],
+            Some(Duration::from_millis(ACCEPT_INTERVAL_MS)),
+        );
+    }
+
+    /// Accepts the rings that clients offered.
+    ///
+    /// The first ring of a client carries its requests, the second one its
+    /// events.
+    fn accept_rings(&mut self) {
+        while let Ok(Some(ring)) = Ring::accept(Some(Duration::from_millis(0))) {
+            let pid = ring.peer();
+
+            let waiting_client = self.clients.iter().position(|client| match *client {
+                Some(ref client) => client.pid == pid && client.events.is_none(),
+                None => false,
+            });
+
+            if let Some(index) = waiting_client {
+                if let Some(ref mut client) = self.clients[index] {
+                    client.events = Some(Channel::new(ring));
+                }
+                continue;
+            }
+
+            match self.clients.iter_mut().find(|client| client.is_none()) {
+                Some(slot) => {
+                    *slot = Some(Client {
+                        pid,
+                        requests: Channel::new(ring),
+                        events: None,
+                    })
+                }
+                None => println!("The display server has no room for process {}.", pid),
+            }
+        }
+    }
+
+    /// Handles the scancodes from the keyboard.
+    fn handle_keyboard(&mut self) {
+        let mut scancode = [0];
+
+        while let Ok(1) = io::read(self.keyboard, &mut scancode) {
+            match scancode[0] {
+                FOCUS_SCANCODE => self.cycle_focus(),
+                scancode if scancode == FOCUS_SCANCODE | RELEASE_BIT => (),
+                scancode => {
+                    if let Some(surface) = self.top() {
+                        self.send_event(
+                            surface.client,
+                            Event::Key {
+                                surface: surface.id,
+                                scancode,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles the requests of all clients.
+    fn handle_requests(&mut self) {
+        for index in 0..MAX_CLIENTS {
+            if let Some(ref client) = self.clients[index] {
+                // Resets the doorbell, the requests are all handled below.
+                let _ = client.requests.ring().wait(Some(Duration::from_millis(0)));
+            }
+
+            loop {
+                let message = match self.clients[index] {
+                    Some(ref mut client) => client.requests.receive(),
+                    None => None,
+                };
+
+                match message {
+                    Some(message) => {
+                        if let Some(request) = Request::decode(&message) {
+                            self.handle_request(index, request);
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Handles a request of the given client.
+    fn handle_request(&mut self, client: usize, request: Request) {
+        match request {
+            Request::CreateSurface {
+                surface,
+                grant,
+                area,
+            } => self.create_surface(client, surface, grant, area),
+            Request::Damage { surface, area } => {
+                if let Some(index) = self.find_surface(client, surface) {
+                    let surface = self.surfaces[index].unwrap();
+                    let area = area.intersection(&Rectangle {
+                        x: 0,
+                        y: 0,
+                        ..surface.area
+                    });
+
+                    self.add_damage(Rectangle {
+                        x: area.x.saturating_add(surface.area.x),
+                        y: area.y.saturating_add(surface.area.y),
+                        ..area
+                    });
+                }
+            }
+            Request::DestroySurface { surface } => {
+                if let Some(index) = self.find_surface(client, surface) {
+                    self.remove_surface(index);
+                }
+            }
+        }
+    }
+
+    /// Creates a surface of the client on top of the others.
+    fn create_surface(
+        &mut self,
+        client: usize,
+        id: SurfaceId,
+        grant: grant::GrantId,
+        area: Rectangle,
+    ) {
+        let cell_count = usize::from(area.width) * usize::from(area.height);
+        if area.is_empty()
+            || cell_count > SURFACE_CELLS
+            || self.surface_count == MAX_SURFACES
+            || self.find_surface(client, id).is_some()
+        {
+            return;
+        }
+
+        let address = match grant::accept(grant) {
+            Ok(address) => address,
+            Err(_) => return,
+        };
+
+        if let Some(top) = self.top() {
+            self.send_focus(&top, false);
+        }
+
+        let surface = Surface {
+            client,
+            id,
+            area,
+            cells: address as *const Cell,
+        };
+
+        self.surfaces[self.surface_count] = Some(surface);
+        self.surface_count += 1;
+
+        self.send_focus(&surface, true);
+        self.add_damage(area);
+    }
+
+    /// Removes the surface with the given index.
+    fn remove_surface(&mut self, index: usize) {
+        let surface = self.surfaces[index].unwrap();
+        let was_top = index + 1 == self.surface_count;
+
+        for index in index..self.surface_count - 1 {
+            self.surfaces[index] = self.surfaces[index + 1];
+        }
+
+        self.surface_count -= 1;
+        self.surfaces[self.surface_count] = None;
+
+        if was_top {
+            if let Some(top) = self.top() {
+                self.send_focus(&top, true);
+            }
+        }
+
+        self.add_damage(surface.area);
+    }
+
+    /// Moves the surface on top to the bottom, which gives the focus to the
+    /// surface below it.
+    fn cycle_focus(&mut self) {
+        if self.surface_count < 2 {
+            return;
+        }
+
+        let old_top = self.top().unwrap();
+
+        for index in (0..self.surface_count - 1).rev() {
+            self.surfaces[index + 1] = self.surfaces[index];
+        }
+        self.surfaces[0] = Some(old_top);
+
+        let new_top = self.top().unwrap();
+
+        self.send_focus(&old_top, false);
+        self.send_focus(&new_top, true);
+        self.add_damage(old_top.area.union(&new_top.area));
+    }
+
+    /// Returns the surface on top, which has the focus.
+    fn top(&self) -> Option<Surface> {
+        if self.surface_count > 0 {
+            self.surfaces[self.surface_count - 1]
+        } else {
+            None
+        }
+    }
+
+    /// Returns the index of the surface with the given ID of the client.
+    fn find_surface(&self, client: usize, id: SurfaceId) -> Option<usize> {
+        self.surfaces[..self.surface_count]
+            .iter()
+            .position(|surface| match *surface {
+                Some(ref surface) => surface.client == client && surface.id == id,
+                None => false,
+            })
+    }
+
+    /// Tells the client of the surface that it gained or lost the focus.
+    fn send_focus(&self, surface: &Surface, focused: bool) {
+        self.send_event(
+            surface.client,
+            Event::Focus {
+                surface: surface.id,
+                focused,
+            },
+        );
+    }
+
+    /// Sends the event to the client.
+    ///
+    /// The event is dropped if the client doesn't keep up with its events.
+    fn send_event(&self, client: usize, event: Event) {
+        if let Some(ref client) = self.clients[client] {
+            if let Some(ref events) = client.events {
+                let _ = events.try_send(&event.encode());
+            }
+        }
+    }
+
+    /// Marks the area of the screen to be composited again.
+    fn add_damage(&mut self, area: Rectangle) {
+        self.damage = self.damage.union(&area.intersection(&screen_area()));
+    }
+
+    /// Composites the damaged area and writes the screen.
+    fn composite(&mut self) {
+        if self.damage.is_empty() {
+            return;
+        }
+
+        let damage = self.damage;
+        self.damage = Rectangle::default();
+
+        for y in damage.y..damage.y + damage.height {
+            for x in damage.x..damage.x + damage.width {
+                let cell = self.surfaces[..self.surface_count]
+                    .iter()
+                    .rev()
+                    .filter_map(|surface| surface.as_ref())
+                    .find(|surface| surface.area.contains(x, y))
+                    .map_or_else(background, |surface| surface.cell_at(x, y));
+                let index = 2 * (usize::from(y) * usize::from(SCREEN_WIDTH) + usize::from(x));
+
+                self.frame[index] = cell as u8;
+                self.frame[index + 1] = (cell >> 8) as u8;
+            }
+        }
+
+        // The whole screen is written, so that the seek position of the
+        // device starts over. The kernel only updates the changed cells.
+        if let Err(error) = io::write(self.screen, &self.frame) {
+            println!("The display server could not write the screen: {}", error);
+        }
+    }
+}
+
+/// Returns the area of the whole screen.
+fn screen_area() -> Rectangle {
+    Rectangle {
+        x: 0,
+        y: 0,
+        width: SCREEN_WIDTH,
+        height: SCREEN_HEIGHT,
+    }
+}
+
+/// Returns the cell that is shown where there is no surface.
+fn background() -> Cell {
+    // Light gray on blue.
+    cell(b' ', color_code(7, 1))
+}
+
+#[no_mangle]
+pub fn main() {
+    let mut server = match Server::new() {
+        Ok(server) => server,
+        Err(error) => {
+            println!("The display server could not open its devices: {}", error);
+            return;
+        }
+    };
+
+    if service::register(SERVICE_NAME, 0).is_err() {
+        println!("The display server could not register its service.");
+        return;
+    }
+
+    server.run();
+}
@@ -0,0 +1,4 @@
+BUILD_DIRS += harness/target
+FMT_DIRS += harness
+
+HARNESS_FILES := $(shell find harness/src -name "*.rs") harness/Cargo.toml
@@ -0,0 +1,231 @@
+//! Runs the tests of VeOS user programs.
+//!
+//! Tests are declared with `veos_tests!` and checked with `check!`,
+//! `check_eq!` and `check_ne!`. `run` starts every test in a child process of
+//! its own, so a test that fails, panics or crashes doesn't take the others
+//! down with it, and reports the results on the standard output. The kernel
+//! mirrors that output to the serial port in test mode, where the
+//! `userspace-tests` scenario of `cargo xtask test` checks it.
+//!
+//! The children are created with `fork`, which makes the tests only reliable
+//! with a single CPU for now.
+
+#![no_std]
+
+#[macro_use]
+extern crate veos_std;
+
+use core::fmt;
+use core::time::Duration;
+use veos_std::process::{self, ExitStatus, PANIC_EXIT_CODE};
+use veos_std::{env, Error};
+
+/// The code a test exits with if one of its checks failed.
+pub const FAILURE_EXIT_CODE: i32 = 1;
+
+/// The environment variable that selects the tests to run.
+///
+/// Only the tests whose names contain its value run, unless it is `all`.
+pub const FILTER_VARIABLE: &str = "run_tests";
+
+/// The number of seconds after which a test counts as hanging.
+const TIMEOUT_SECONDS: u64 = 30;
+
+/// A test function together with its name.
+pub struct Test {
+    /// The path of the test function.
+    pub name: &'static str,
+    /// The test function.
+    pub function: fn()
+}
+
+/// How a test ended.
+enum Outcome {
+    /// The test returned.
+    Passed,
+    /// A check of the test failed.
+    Failed,
+    /// The test panicked.
+    Panicked,
+    /// The process of the test ended in another way, for example because it
+    /// crashed.
+    Ended(ExitStatus),
+    /// The test didn't end in time.
+    TimedOut,
+    /// The test couldn't be run.
+    NotRun(Error)
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Passed => write!(f, "ok"),
+            Outcome::Failed => write!(f, "FAILED"),
+            Outcome::Panicked => write!(f, "FAILED (panicked)"),
+            Outcome::Ended(status) => write!(f, "FAILED ({:?})", status),
+            Outcome::TimedOut => write!(f, "FAILED (timed out after {}s)", TIMEOUT_SECONDS),
+            Outcome::NotRun(error) => write!(f, "FAILED (not run: {})", error)
+        }
+    }
+}
+
+/// Declares tests.
+///
+/// Every test is a function without arguments. The macro defines the
+/// functions and a constant `TESTS` that lists them for `run`.
+#[macro_export]
+macro_rules! veos_tests {
+    ($($(#[$attribute:meta])* fn $name:ident() $body:block)*) => {
+        $(
+            $(#[$attribute])*
+            fn $name() $body
+        )*
+
+        /// The tests of this module.
+        pub const TESTS: &[$crate::Test] = &[$(
+            $crate::Test {
+                name: concat!(module_path!(), "::", stringify!($name)),
+                function: $name
+            }
+        ),*];
+    };
+}
+
+/// Fails the test if the condition doesn't hold.
+#[macro_export]
+macro_rules! check {
+    ($condition:expr) => {
+        if !$condition {
+            $crate::fail(file!(), line!(), format_args!("{}", stringify!($condition)));
+        }
+    };
+    ($condition:expr, $($argument:tt)+) => {
+        if !$condition {
+            $crate::fail(file!(), line!(), format_args!($($argument)+));
+        }
+    };
+}
+
+/// Fails the test if the values aren't equal.
+#[macro_export]
+macro_rules! check_eq {
+    ($left:expr, $right:expr) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if *left != *right {
+                    $crate::fail(
+                        file!(),
+                        line!(),
+                        format_args!(
+                            "{} == {}\n  left: {:?}\n right: {:?}",
+                            stringify!($left),
+                            stringify!($right),
+                            left,
+                            right
+                        )
+                    );
+                }
+            }
+        }
+    };
+}
+
+/// Fails the test if the values are equal.
+#[macro_export]
+macro_rules! check_ne {
+    ($left:expr, $right:expr) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if *left == *right {
+                    $crate::fail(
+                        file!(),
+                        line!(),
+                        format_args!(
+                            "{} != {}\n  both: {:?}",
+                            stringify!($left),
+                            stringify!($right),
+                            left
+                        )
+                    );
+                }
+            }
+        }
+    };
+}
+
+/// Reports the failed check and ends the test.
+///
+/// This is called by the check macros.
+pub fn fail(file: &str, line: u32, message: fmt::Arguments) -> ! {
+    println!("check failed at {}:{}: {}", file, line, message);
+    process::exit_with_code(FAILURE_EXIT_CODE);
+}
+
+/// Runs the selected tests of all the given lists and exits.
+///
+/// The process exits with `FAILURE_EXIT_CODE` if any of the tests failed.
+pub fn run(tests: &[&[Test]]) -> ! {
+    let filter = match env::var(FILTER_VARIABLE) {
+        Ok("all") | Err(_) => "",
+        Ok(filter) => filter
+    };
+    let is_selected = |test: &&Test| test.name.contains(filter);
+
+    let count = tests
+        .iter()
+        .flat_map(|tests| tests.iter())
+        .filter(&is_selected)
+        .count();
+    println!("Running {} tests.", count);
+
+    let mut failed = 0;
+
+    for test in tests
+        .iter()
+        .flat_map(|tests| tests.iter())
+        .filter(&is_selected)
+    {
+        let outcome = run_test(test);
+        println!("test {} ... {}", test.name, outcome);
+
+        match outcome {
+            Outcome::Passed => (),
+            _ => failed += 1
+        }
+    }
+
+    let passed = count - failed;
+
+    // This fails unless the kernel counts the calls of its functions.
+    let _ = process::dump_kernel_coverage();
+
+    if failed == 0 {
+        println!("test result: ok. {} passed; 0 failed", passed);
+        process::exit_with_code(0);
+    } else {
+        println!("test result: FAILED. {} passed; {} failed", passed, failed);
+        process::exit_with_code(FAILURE_EXIT_CODE);
+    }
+}
+
+/// Runs the test in a child process and waits until it ended.
+fn run_test(test: &Test) -> Outcome {
+    let pid = match process::fork() {
+        Ok(0) => {
+            (test.function)();
+            process::exit_with_code(0);
+        },
+        Ok(pid) => pid,
+        Err(error) => return Outcome::NotRun(error)
+    };
+
+    // A hanging test is left running, there is no way to kill it yet.
+    match process::wait(pid, Some(Duration::from_secs(TIMEOUT_SECONDS))) {
+        Ok(Some(ExitStatus::Exited(0))) => Outcome::Passed,
+        Ok(Some(ExitStatus::Exited(FAILURE_EXIT_CODE))) => Outcome::Failed,
+        Ok(Some(ExitStatus::Exited(PANIC_EXIT_CODE))) => Outcome::Panicked,
+        Ok(Some(status)) => Outcome::Ended(status),
+        Ok(None) => Outcome::TimedOut,
+        Err(error) => Outcome::NotRun(error)
+    }
+}
@@ -6,13 +6,83 @@ extern crate veos_std;
 extern crate rlibc;
 
 use core::time::Duration;
+use veos_std::ipc::message::send;
+use veos_std::process::wait;
 
 #[no_mangle]
 pub fn main() {
-    veos_std::process::exec("/bin/test").unwrap();
+    // The fuzzer is started if a seed was given on the kernel command line.
+    if veos_std::env::var("syscall_fuzz_seed").is_ok() {
+        if let Err(error) = veos_std::process::exec("/bin/syscall-fuzz") {
+            println!("Could not start the syscall fuzzer: {}", error);
+        }
+    }
+
+    // The userspace tests are run if they were requested on the kernel
+    // command line.
+    if veos_std::env::var("run_tests").is_ok() {
+        if let Err(error) = veos_std::process::exec("/bin/test-runner") {
+            println!("Could not start the test runner: {}", error);
+        }
+    }
+
+    let mut test_server = start_test_server();
+
+    // The display server takes over the screen, so it is only started on
+    // request.
+    if veos_std::env::var("display").is_ok() {
+        if let Err(error) = veos_std::process::spawn_server("/etc/servers/display.manifest") {
+            println!("Could not start the display server: {}", error);
+        } else if let Err(error) = veos_std::process::exec("/bin/display-demo") {
+            println!("Could not start the display demo: {}", error);
+        }
+    }
+
+    // The test server registers its service once it started, so the greeting
+    // is sent as soon as the service can be found.
+    let mut greeted = false;
 
     loop {
         veos_std::thread::sleep(Duration::from_millis(500));
         println!("Test");
+
+        // The test server is restarted whenever it ends.
+        if let Some(pid) = test_server {
+            match wait(pid, Some(Duration::from_millis(0))) {
+                Ok(Some(status)) => {
+                    println!("The test server ended ({:?}), restarting it.", status);
+                    test_server = start_test_server();
+                    greeted = false;
+                }
+                Ok(None) => (),
+                Err(error) => {
+                    println!("Could not wait for the test server: {}", error);
+                    test_server = None;
+                }
+            }
+        }
+
+        if !greeted {
+            if let Ok(service) = veos_std::service::lookup("test") {
+                greeted = true;
+
+                let message = b"Hello from init";
+                let timeout = Some(Duration::from_millis(500));
+                if let Err(error) = send(service.pid, service.endpoint, message, timeout) {
+                    println!("Could not greet the test server: {}", error);
+                }
+            }
+        }
+    }
+}
+
+/// Starts the test server and returns its process ID.
+fn start_test_server() -> Option<u64> {
+    match veos_std::process::spawn_server("/etc/servers/test.manifest") {
+        Ok(pid) => Some(pid),
+        Err(error) => {
+            println!("Could not start the test server: {}", error);
+            None
+        }
     }
 }
@@ -0,0 +1,4 @@
+BUILD_DIRS += initramfs/target
+FMT_DIRS += initramfs
+
+INITRAMFS_CRATE_FILES := $(shell find initramfs/src -name "*.rs") initramfs/Cargo.toml
@@ -0,0 +1,379 @@
+//! This crate describes the initramfs format of VeOS, parses and writes it.
+//!
+//! The kernel uses it to read the initramfs and `mkinitramfs` uses it to write
+//! it, so both always agree on the format. Parsing works without `std`, while
+//! writing needs the `std` feature, which is enabled by default.
+//!
+//! An initramfs starts with a header, which is followed by the metadata of
+//! all files and then by the file names and contents. All numbers are big
+//! endian `u64`s.
+//!
+//! Version 1 images start with `LEGACY_MAGIC` followed by the number of files.
+//! Later versions start with `MAGIC` followed by the format version and the
+//! number of files.
+//!
+//! The metadata of a file consists of the offset of its name, the length of
+//! its name, the offset of its content and the length of its content, where
+//! the offsets are relative to the start of the initramfs.
+
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "std")]
+mod writer;
+
+use core::mem::size_of;
+use core::str;
+
+#[cfg(feature = "std")]
+pub use writer::Writer;
+
+/// The magic number of version 1 images, which have no version field.
+pub const LEGACY_MAGIC: [u8; 8] = [b'V', b'e', b'O', b'S', b'i', b'r', b'f', b's'];
+
+/// The magic number of images that have a version field.
+pub const MAGIC: [u8; 8] = [b'V', b'e', b'O', b'S', b'i', b'r', b'f', b'v'];
+
+/// The version of the format without a version field.
+pub const LEGACY_VERSION: u64 = 1;
+
+/// The version of the format that is written by default.
+pub const CURRENT_VERSION: u64 = 2;
+
+/// The size of the metadata of a single file.
+pub const FILE_METADATA_SIZE: usize = size_of::<u64>() * 4;
+
+/// The suffix of the file that holds the detached signature of another file.
+///
+/// The signature of `/bin/init` is stored in `/bin/init.sig`.
+pub const SIGNATURE_SUFFIX: &str = ".sig";
+
+/// Returns whether the given format version can be read and written.
+pub fn is_supported(version: u64) -> bool {
+    if version < LEGACY_VERSION {
+        return false;
+    }
+
+    version <= CURRENT_VERSION
+}
+
+/// Returns the size of the header of the given format version.
+///
+/// The file metadata starts right after the header.
+///
+/// # Panics
+/// Panics if the version isn't supported.
+pub fn header_size(version: u64) -> usize {
+    assert!(is_supported(version), "Unsupported initramfs version.");
+
+    if version == LEGACY_VERSION {
+        size_of::<[u8; 8]>() + size_of::<u64>()
+    } else {
+        size_of::<[u8; 8]>() + size_of::<u64>() * 2
+    }
+}
+
+/// The errors that can occur when parsing an initramfs.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FormatError {
+    /// The magic number is missing.
+    InvalidMagic,
+    /// The format version isn't supported.
+    UnsupportedVersion(u64),
+    /// The initramfs is too short for its header or file metadata.
+    Truncated,
+    /// The file with the given index reaches past the end of the initramfs
+    /// or its name isn't valid UTF-8.
+    CorruptFile(usize)
+}
+
+/// A parsed initramfs.
+#[derive(Debug, Clone, Copy)]
+pub struct Initramfs<'a> {
+    /// The whole initramfs.
+    data: &'a [u8],
+    /// The format version.
+    version: u64,
+    /// The number of files.
+    file_count: usize
+}
+
+impl<'a> Initramfs<'a> {
+    /// Parses the header of the initramfs.
+    pub fn parse(data: &'a [u8]) -> Result<Initramfs<'a>, FormatError> {
+        if data.len() < size_of::<[u8; 8]>() {
+            return Err(FormatError::Truncated);
+        }
+
+        let version = if data[..size_of::<[u8; 8]>()] == LEGACY_MAGIC {
+            LEGACY_VERSION
+        } else if data[..size_of::<[u8; 8]>()] == MAGIC {
+            let version = read_u64(data, size_of::<[u8; 8]>()).ok_or(FormatError::Truncated)?;
+
+            // Version 1 is only valid with the legacy magic number.
+            if version == LEGACY_VERSION || !is_supported(version) {
+                return Err(FormatError::UnsupportedVersion(version));
+            }
+
+            version
+        } else {
+            return Err(FormatError::InvalidMagic);
+        };
+
+        let header_size = header_size(version);
+        let file_count = read_u64(data, header_size - size_of::<u64>())
+            .ok_or(FormatError::Truncated)?;
+        let metadata_end = (file_count as usize)
+            .checked_mul(FILE_METADATA_SIZE)
+            .and_then(|size| size.checked_add(header_size));
+
+        match metadata_end {
+            Some(end) if end <= data.len() => (),
+            _ => return Err(FormatError::Truncated)
+        }
+
+        Ok(Initramfs {
+            data,
+            version,
+            file_count: file_count as usize
+        })
+    }
+
+    /// Returns the format version of the initramfs.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns an iterator through the files in the initramfs.
+    ///
+    /// Files with names that aren't valid UTF-8 or that reach past the end
+    /// of the initramfs are skipped.
+    pub fn files(&self) -> Files<'a> {
+        Files {
+            data: self.data,
+            metadata_offset: header_size(self.version),
+            remaining: self.file_count
+        }
+    }
+
+    /// Returns the file with the given name.
+    pub fn find(&self, name: &str) -> Option<File<'a>> {
+        self.files().find(|file| file.name == name)
+    }
+
+    /// Checks that all files can be read.
+    ///
+    /// Returns the number of files or the first file that can't be read.
+    pub fn validate(&self) -> Result<usize, FormatError> {
+        let metadata_offset = header_size(self.version);
+
+        for index in 0..self.file_count {
+            let offset = metadata_offset + index * FILE_METADATA_SIZE;
+
+            if read_file(self.data, offset).is_none() {
+                return Err(FormatError::CorruptFile(index));
+            }
+        }
+
+        Ok(self.file_count)
+    }
+}
+
+/// A file in the initramfs.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct File<'a> {
+    /// The name of the file.
+    pub name: &'a str,
+    /// The content of the file.
+    pub content: &'a [u8]
+}
+
+/// An iterator through the files in an initramfs.
+#[derive(Debug, Clone)]
+pub struct Files<'a> {
+    /// The whole initramfs.
+    data: &'a [u8],
+    /// The offset of the metadata that is read next.
+    metadata_offset: usize,
+    /// The number of files that were not read yet.
+    remaining: usize
+}
+
+impl<'a> Iterator for Files<'a> {
+    type Item = File<'a>;
+
+    fn next(&mut self) -> Option<File<'a>> {
+        while self.remaining > 0 {
+            let offset = self.metadata_offset;
+            self.metadata_offset += FILE_METADATA_SIZE;
+            self.remaining -= 1;
+
+            if let Some(file) = read_file(self.data, offset) {
+                return Some(file);
+            }
+        }
+
+        None
+    }
+}
+
+/// Reads the file with the metadata at the given offset.
+///
+/// Returns `None` if the file reaches past the end of the data or its name
+/// isn't valid UTF-8.
+fn read_file<'a>(data: &'a [u8], offset: usize) -> Option<File<'a>> {
+    let read = |index: usize| read_u64(data, offset + index * size_of::<u64>());
+    let name = slice(data, read(0), read(1))?;
+    let content = slice(data, read(2), read(3))?;
+
+    Some(File {
+        name: str::from_utf8(name).ok()?,
+        content
+    })
+}
+
+/// Returns the slice of the data at the given offset with the given length.
+fn slice(data: &[u8], offset: Option<u64>, length: Option<u64>) -> Option<&[u8]> {
+    let start = offset? as usize;
+    let end = start.checked_add(length? as usize)?;
+
+    if end <= data.len() {
+        Some(&data[start..end])
+    } else {
+        None
+    }
+}
+
+/// Reads the big endian `u64` at the given offset.
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let end = offset.checked_add(size_of::<u64>())?;
+
+    if end > data.len() {
+        return None;
+    }
+
+    let mut result: u64 = 0;
+
+    for byte in &data[offset..end] {
+        result = (result << 8) | *byte as u64;
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the big endian `u64` at the given offset.
+    fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+        for i in 0..size_of::<u64>() {
+            data[offset + i] = (value >> ((size_of::<u64>() - i - 1) * 8)) as u8;
+        }
+    }
+
+    /// Writes an image of the given version that contains the file `/a` with
+    /// the content `bc` and returns its length.
+    fn write_image(data: &mut [u8], version: u64) -> usize {
+        let header_size = header_size(version);
+
+        if version == LEGACY_VERSION {
+            data[..8].copy_from_slice(&LEGACY_MAGIC);
+        } else {
+            data[..8].copy_from_slice(&MAGIC);
+            write_u64(data, 8, version);
+        }
+        write_u64(data, header_size - 8, 1);
+
+        let name_offset = header_size + FILE_METADATA_SIZE;
+        write_u64(data, header_size, name_offset as u64);
+        write_u64(data, header_size + 8, 2);
+        write_u64(data, header_size + 16, name_offset as u64 + 2);
+        write_u64(data, header_size + 24, 2);
+        data[name_offset..name_offset + 4].copy_from_slice(b"/abc");
+
+        name_offset + 4
+    }
+
+    /// Tests that all supported versions are parsed.
+    #[test]
+    fn test_parse_versions() {
+        for version in LEGACY_VERSION..CURRENT_VERSION + 1 {
+            let mut data = [0; 64];
+            let length = write_image(&mut data, version);
+            let initramfs = Initramfs::parse(&data[..length]).unwrap();
+
+            assert_eq!(initramfs.version(), version);
+            assert_eq!(initramfs.files().count(), 1);
+            assert_eq!(
+                initramfs.find("/a"),
+                Some(File {
+                    name: "/a",
+                    content: b"bc"
+                })
+            );
+            assert_eq!(initramfs.find("/b"), None);
+        }
+    }
+
+    /// Tests that invalid headers are rejected.
+    #[test]
+    fn test_invalid_header() {
+        let mut data = [0; 64];
+        let length = write_image(&mut data, CURRENT_VERSION);
+
+        assert_eq!(Initramfs::parse(&data[..4]).unwrap_err(), FormatError::Truncated);
+        assert_eq!(Initramfs::parse(&data[..30]).unwrap_err(), FormatError::Truncated);
+
+        write_u64(&mut data, 8, CURRENT_VERSION + 1);
+        assert_eq!(
+            Initramfs::parse(&data[..length]).unwrap_err(),
+            FormatError::UnsupportedVersion(CURRENT_VERSION + 1)
+        );
+
+        write_u64(&mut data, 8, LEGACY_VERSION);
+        assert_eq!(
+            Initramfs::parse(&data[..length]).unwrap_err(),
+            FormatError::UnsupportedVersion(LEGACY_VERSION)
+        );
+
+        data[0] = b'X';
+        assert_eq!(Initramfs::parse(&data[..length]).unwrap_err(), FormatError::InvalidMagic);
+    }
+
+    /// Tests that files reaching past the end are skipped.
+    #[test]
+    fn test_truncated_file() {
+        let mut data = [0; 64];
+        let length = write_image(&mut data, CURRENT_VERSION);
+        let initramfs = Initramfs::parse(&data[..length - 1]).unwrap();
+
+        assert_eq!(initramfs.files().count(), 0);
+        assert_eq!(initramfs.validate(), Err(FormatError::CorruptFile(0)));
+    }
+
+    /// Tests that intact images pass validation.
+    #[test]
+    fn test_validate() {
+        let mut data = [0; 64];
+        let length = write_image(&mut data, CURRENT_VERSION);
+        let initramfs = Initramfs::parse(&data[..length]).unwrap();
+
+        assert_eq!(initramfs.validate(), Ok(1));
+    }
+
+    /// Tests that names that aren't valid UTF-8 are reported.
+    #[test]
+    fn test_invalid_name() {
+        let mut data = [0; 64];
+        let length = write_image(&mut data, CURRENT_VERSION);
+        let name_offset = header_size(CURRENT_VERSION) + FILE_METADATA_SIZE;
+        data[name_offset + 1] = 0xff;
+        let initramfs = Initramfs::parse(&data[..length]).unwrap();
+
+        assert_eq!(initramfs.find("/a"), None);
+        assert_eq!(initramfs.validate(), Err(FormatError::CorruptFile(0)));
+    }
+}
@@ -0,0 +1,154 @@
+//! Writes initramfs images.
+
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use {header_size, is_supported, FILE_METADATA_SIZE, LEGACY_MAGIC, LEGACY_VERSION, MAGIC};
+
+/// Writes an initramfs image.
+///
+/// The number of files must be known in advance, because the metadata of all
+/// files precedes their names and contents.
+pub struct Writer<W: Write + Seek> {
+    /// Where the image is written to.
+    target: W,
+    /// The format version that is written.
+    version: u64,
+    /// The number of files in the image.
+    file_count: usize,
+    /// The number of files that were already added.
+    files_written: usize
+}
+
+impl<W: Write + Seek> Writer<W> {
+    /// Writes the header for the given number of files in the given format
+    /// version.
+    pub fn new(mut target: W, version: u64, file_count: usize) -> io::Result<Writer<W>> {
+        if !is_supported(version) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "unsupported format version"));
+        }
+
+        if version == LEGACY_VERSION {
+            target.write_all(&LEGACY_MAGIC)?;
+        } else {
+            target.write_all(&MAGIC)?;
+            write_u64(&mut target, version)?;
+        }
+        write_u64(&mut target, file_count as u64)?;
+
+        // Reserve the space for the file metadata, which is written with the
+        // files.
+        let metadata_size = (FILE_METADATA_SIZE * file_count) as u64;
+        io::copy(&mut io::repeat(0).take(metadata_size), &mut target)?;
+
+        Ok(Writer {
+            target,
+            version,
+            file_count,
+            files_written: 0
+        })
+    }
+
+    /// Adds a file with the given name and the content read from the source.
+    pub fn add_file<R: Read>(&mut self, name: &str, source: &mut R) -> io::Result<()> {
+        if self.files_written == self.file_count {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "too many files"));
+        }
+
+        let name_offset = self.target.seek(SeekFrom::End(0))?;
+        self.target.write_all(name.as_bytes())?;
+
+        let content_offset = self.target.seek(SeekFrom::End(0))?;
+        let content_length = io::copy(source, &mut self.target)?;
+
+        let metadata_offset = header_size(self.version) + self.files_written * FILE_METADATA_SIZE;
+        self.target.seek(SeekFrom::Start(metadata_offset as u64))?;
+        write_u64(&mut self.target, name_offset)?;
+        write_u64(&mut self.target, name.len() as u64)?;
+        write_u64(&mut self.target, content_offset)?;
+        write_u64(&mut self.target, content_length)?;
+
+        self.files_written += 1;
+
+        Ok(())
+    }
+
+    /// Checks that all files were added and returns the target.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.files_written != self.file_count {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "too few files"));
+        }
+
+        self.target.flush()?;
+
+        Ok(self.target)
+    }
+}
+
+/// Writes the `u64` in big endian.
+fn write_u64<W: Write>(target: &mut W, value: u64) -> io::Result<()> {
+    let mut bytes = [0; size_of::<u64>()];
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> ((size_of::<u64>() - i - 1) * 8)) as u8;
+    }
+
+    target.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::vec::Vec;
+    use {File, Initramfs, CURRENT_VERSION};
+
+    /// The files written in the tests.
+    const FILES: &[(&str, &[u8])] = &[
+        ("/bin/init", b"\x7fELF init"),
+        ("/etc/empty", b""),
+        ("/etc/large", &[0xab; 3000])
+    ];
+
+    /// Writes an initramfs with the test files in the given format version.
+    fn write_files(version: u64) -> Vec<u8> {
+        let mut writer = Writer::new(Cursor::new(Vec::new()), version, FILES.len()).unwrap();
+
+        for &(name, content) in FILES {
+            writer.add_file(name, &mut &content[..]).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Tests that the parser reads back what was written in every version.
+    #[test]
+    fn test_round_trip() {
+        for version in LEGACY_VERSION..CURRENT_VERSION + 1 {
+            let data = write_files(version);
+            let initramfs = Initramfs::parse(&data).unwrap();
+
+            assert_eq!(initramfs.version(), version);
+            assert_eq!(
+                initramfs.files().collect::<Vec<_>>(),
+                FILES
+                    .iter()
+                    .map(|&(name, content)| File { name, content })
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    /// Tests that the number of files must match and that unknown versions
+    /// are rejected.
+    #[test]
+    fn test_invalid_use() {
+        assert!(Writer::new(Cursor::new(Vec::new()), CURRENT_VERSION + 1, 0).is_err());
+
+        let mut writer = Writer::new(Cursor::new(Vec::new()), CURRENT_VERSION, 1).unwrap();
+        assert!(writer.add_file("/a", &mut &b"a"[..]).is_ok());
+        assert!(writer.add_file("/b", &mut &b"b"[..]).is_err());
+
+        let writer = Writer::new(Cursor::new(Vec::new()), CURRENT_VERSION, 1).unwrap();
+        assert!(writer.finish().is_err());
+    }
+}
@@ -0,0 +1,53 @@
+#!/bin/sh
+# Generates the assembly source for the symbol table of the given kernel
+# binary. Without a binary, an empty symbol table is generated.
+#
+# The table starts with the number of symbols, followed by an address sorted
+# array of entries of the form (address: u64, size: u32, name offset: u32).
+# The names follow the entries, each prefixed with its length as a u16.
+
+export LC_ALL=C
+
+echo "section .ksymbols progbits alloc noexec nowrite align=8"
+echo "global ksymbols"
+echo "ksymbols:"
+
+if [ -z "$1" ]; then
+    echo "    dq 0"
+    exit 0
+fi
+
+nm --defined-only --demangle --numeric-sort --print-size "$1" | awk '
+    BEGIN {
+        count = 0
+        offset = 0
+    }
+    NF >= 4 && $1 ~ /^[0-9a-f]+$/ && $2 ~ /^[0-9a-f]+$/ && $3 ~ /^[tTwW]$/ {
+        name = $0
+        sub(/^[^ ]+ [^ ]+ [^ ]+ /, "", name)
+        sub(/::h[0-9a-f]+$/, "", name)
+
+        # Names with quotes would need escaping and never occur in practice.
+        if (index(name, "\"") != 0) {
+            next
+        }
+
+        addresses[count] = $1
+        sizes[count] = $2
+        names[count] = name
+        offsets[count] = offset
+        offset += length(name) + 2
+        count++
+    }
+    END {
+        printf "    dq %d\n", count
+        for (i = 0; i < count; i++) {
+            printf "    dq 0x%s\n", addresses[i]
+            printf "    dd 0x%s, %d\n", sizes[i], offsets[i]
+        }
+        for (i = 0; i < count; i++) {
+            printf "    dw %d\n", length(names[i])
+            printf "    db \"%s\"\n", names[i]
+        }
+    }
+'
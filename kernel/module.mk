@@ -9,11 +9,19 @@ KERNEL_LINKER_FLAGS := -n -T $(LINKER_SCRIPT) --gc-sections
 
 KERNEL_LIB := kernel/target/$(KERNEL_BUILD_TARGET)/$(BUILD_TYPE)/libveos.a
 KERNEL_BINARY := kernel/target/$(KERNEL_BUILD_TARGET)/build/kernel-$(ARCH).bin
+KERNEL_BINARY_WITHOUT_SYMBOLS := kernel/target/$(KERNEL_BUILD_TARGET)/build/kernel-$(ARCH).nosym.bin
+
+SYMBOL_GENERATOR := kernel/mksymbols.sh
+EMPTY_SYMBOLS_SOURCE := kernel/target/$(KERNEL_BUILD_TARGET)/build/ksymbols-empty.asm
+SYMBOLS_SOURCE := kernel/target/$(KERNEL_BUILD_TARGET)/build/ksymbols.asm
 
 KERNEL_RUST_COMPILER_FLAGS := --target $(KERNEL_BUILD_TARGET)
 ifeq ($(BUILD_TYPE),release)
 	KERNEL_RUST_COMPILER_FLAGS += --release
 endif
+ifneq ($(KERNEL_FEATURES),)
+	KERNEL_RUST_COMPILER_FLAGS += --features "$(KERNEL_FEATURES)"
+endif
 
 ASM_FOLDERS := kernel/src/arch/$(ARCH)/init
 ASSEMBLY_SOURCE_FILES := $(foreach DIR, $(ASM_FOLDERS), $(wildcard $(DIR)/*.asm))
@@ -29,10 +37,28 @@ $(TARGET_DIR)/boot/kernel.bin: $(KERNEL_BINARY)
 	@mkdir -p $(shell dirname $@)
 	cp $< $@
 
-$(KERNEL_BINARY): $(ASSEMBLY_OBJECT_FILES) $(KERNEL_LINKER_SCRIPT) $(KERNEL_LIB)
-	$(LINKER) $(KERNEL_LINKER_FLAGS) -o $@ $(ASSEMBLY_OBJECT_FILES) $(KERNEL_LIB)
+# The kernel is linked twice. The first link uses an empty symbol table and
+# determines the addresses of all functions. The second link includes the
+# symbol table generated from the first one. The symbol table is placed after
+# the code, so the addresses of the functions don't change.
+$(KERNEL_BINARY): $(ASSEMBLY_OBJECT_FILES) $(SYMBOLS_SOURCE:.asm=.o) $(KERNEL_LINKER_SCRIPT) $(KERNEL_LIB)
+	$(LINKER) $(KERNEL_LINKER_FLAGS) -o $@ $(ASSEMBLY_OBJECT_FILES) $(SYMBOLS_SOURCE:.asm=.o) $(KERNEL_LIB)
+
+$(KERNEL_BINARY_WITHOUT_SYMBOLS): $(ASSEMBLY_OBJECT_FILES) $(EMPTY_SYMBOLS_SOURCE:.asm=.o) $(KERNEL_LINKER_SCRIPT) $(KERNEL_LIB)
+	$(LINKER) $(KERNEL_LINKER_FLAGS) -o $@ $(ASSEMBLY_OBJECT_FILES) $(EMPTY_SYMBOLS_SOURCE:.asm=.o) $(KERNEL_LIB)
+
+$(SYMBOLS_SOURCE): $(KERNEL_BINARY_WITHOUT_SYMBOLS) $(SYMBOL_GENERATOR)
+	@mkdir -p $(shell dirname $@)
+	$(SYMBOL_GENERATOR) $< > $@
+
+$(EMPTY_SYMBOLS_SOURCE): $(SYMBOL_GENERATOR)
+	@mkdir -p $(shell dirname $@)
+	$(SYMBOL_GENERATOR) > $@
+
+$(SYMBOLS_SOURCE:.asm=.o) $(EMPTY_SYMBOLS_SOURCE:.asm=.o): %.o : %.asm
+	$(ASSEMBLER) $(ASSEMBLER_FLAGS) $< -o $@
 
-$(KERNEL_LIB): $(shell find kernel/src -name "*.rs") kernel/Cargo.toml kernel/Xargo.toml
+$(KERNEL_LIB): $(shell find kernel/src -name "*.rs") kernel/Cargo.toml kernel/Xargo.toml $(INITRAMFS_CRATE_FILES) $(CRYPTO_CRATE_FILES)
 	cd kernel && $(RUST_COMPILER) build $(KERNEL_RUST_COMPILER_FLAGS)
 
 $(ASSEMBLY_OBJECT_FILES): kernel/target/$(KERNEL_BUILD_TARGET)/build/%.o : kernel/src/%.asm
@@ -3,8 +3,14 @@
 //! The job of this module is to have submodules for each architecture and to
 //! provide interfaces to them.
 
+use alloc::Vec;
 use core::time::Duration;
-use memory::address_space::AddressSpace;
+use cpufreq::PerformanceState;
+use kdebug::WatchpointSet;
+use keyboard::{Locks, Repeat};
+use memory::address_space::{AddressSpace, AddressSpaceHandle};
+use memory::huge_pages;
+use memory::numa::Topology;
 use memory::{MemoryArea, PageFlags, PhysicalAddress, VirtualAddress};
 use multitasking::stack::StackType;
 use sync::time::Timestamp;
@@ -41,8 +47,11 @@ pub trait Architecture {
     /// This initializes the IO on the target architecture.
     fn init_io();
 
-    /// This initializes the kernel logger.
-    fn init_logger();
+    /// Finds the devices and starts their drivers.
+    ///
+    /// This must be called once while the address space of the idle process
+    /// is active.
+    fn init_drivers();
 
     /// Returns the number of CPUs available.
     ///
@@ -52,6 +61,19 @@ pub trait Architecture {
     /// Returns the ID of the currently running CPU.
     fn get_cpu_id() -> usize;
 
+    /// Starts the CPU with the given ID, which must not be running.
+    ///
+    /// A CPU that was stopped with `park_cpu` continues there, any other CPU
+    /// enters its idle thread. Returns false if the CPU didn't start.
+    fn start_cpu(cpu_id: usize) -> bool;
+
+    /// Stops the current CPU until it is started again with `start_cpu`.
+    ///
+    /// # Safety
+    /// - Interrupts must be disabled and no locks may be held.
+    /// - The current CPU must not be the boot CPU.
+    unsafe fn park_cpu();
+
     /// Invokes the scheduler.
     ///
     /// This function changes the currently running thread on the current CPU
@@ -81,6 +103,16 @@ pub trait Architecture {
     /// interrupts are enabled when calling this function.
     unsafe fn cpu_halt();
 
+    /// Enables interrupts and halts the current CPU until the next interrupt
+    /// occurs.
+    ///
+    /// No interrupt can be handled between enabling interrupts and halting,
+    /// so an interrupt that arrives in between ends the halt.
+    ///
+    /// # Safety
+    /// - Make sure that no locks are held, as with `enable_interrupts`.
+    unsafe fn enable_interrupts_and_halt();
+
     /// Returns true if interrupts are enabled and false otherwise.
     fn get_interrupt_state() -> bool;
 
@@ -88,34 +120,48 @@ pub trait Architecture {
     ///
     /// # Safety
     /// - Make sure to re-enable them later. The best way to do so is by not
-    /// calling this function directly but rather
-    /// `sync::disable_preemption`.
+    /// calling this function directly but rather creating a
+    /// `sync::InterruptGuard`.
     unsafe fn disable_interrupts();
 
     /// Enables all interrupts.
     ///
     /// # Safety
     /// - Make sure that all critial sections have been accessed and that no
-    /// locks are held. It is better to just use `sync::PreemptionState`
+    /// locks are held. It is better to just drop a `sync::PreemptionGuard`
     /// instead of using this directly.
     unsafe fn enable_interrupts();
 
     /// Returns the current timestamp.
     fn get_current_timestamp() -> Timestamp;
 
+    /// Returns the number of seconds since the Unix epoch according to the
+    /// hardware clock or `None` if it can't be read.
+    fn read_hardware_clock() -> Option<u64>;
+
     /// Sets a timer to enable an interrupt in the given amount of time.
     fn interrupt_in(Duration);
 
     /// Switches the execution context and saves the current one.
     ///
     /// `old_context` is where the current context is saved to and
-    /// `new_context` is the next context to be loaded.
+    /// `new_context` is the next context to be loaded. The address space is
+    /// only switched if `new_address_space` differs from
+    /// `old_address_space`, which must be the active one.
     ///
     /// # Safety
     /// - To make sure that everything is properly cleaned up after switching
     /// the context, this should only be called by the scheduler.
     /// - Make sure preemption is disabled while calling this.
-    unsafe fn switch_context(old_context: &mut Self::Context, new_context: &Self::Context);
+    unsafe fn switch_context(
+        old_context: &mut Self::Context,
+        new_context: &Self::Context,
+        old_address_space: AddressSpaceHandle,
+        new_address_space: AddressSpaceHandle
+    );
+
+    /// Returns the handle of the currently active address space.
+    fn get_current_address_space() -> AddressSpaceHandle;
 
     /// Returns the size of usable free memory in bytes.
     fn get_free_memory_size() -> usize;
@@ -127,30 +173,169 @@ pub trait Architecture {
     /// Unmaps the page that contains the given address.
     unsafe fn unmap_page(page_address: VirtualAddress);
 
+    /// Maps `count` consecutive pages starting at the given page with the
+    /// given flags.
+    fn map_range(first_page_address: VirtualAddress, count: usize, flags: PageFlags);
+
+    /// Unmaps `count` consecutive pages starting at the given page.
+    unsafe fn unmap_range(first_page_address: VirtualAddress, count: usize);
+
+    /// Allocates a physical frame filled with zeros.
+    fn allocate_zeroed_frame() -> PhysicalAddress;
+
+    /// Zeroes a free physical frame, so that a later zeroed allocation is
+    /// faster.
+    ///
+    /// Returns false if enough frames are zeroed already or no frame is free.
+    fn zero_free_frame() -> bool;
+
+    /// Calls the function with the contents of the physical frame at the
+    /// given address as 64-bit words.
+    ///
+    /// # Safety
+    /// - Nothing else may use the frame while the function runs.
+    unsafe fn access_frame<R, F>(frame_address: PhysicalAddress, function: F) -> R
+    where
+        F: FnOnce(&mut [u64]) -> R;
+
+    /// Adds a reference to the physical frame at the given address.
+    ///
+    /// Every mapping of a frame and every other owner holds a reference.
+    fn add_frame_reference(frame_address: PhysicalAddress);
+
+    /// Returns true if the physical frame at the given address has more than
+    /// one reference.
+    fn is_frame_shared(frame_address: PhysicalAddress) -> bool;
+
+    /// Releases a reference to the physical frame at the given address.
+    ///
+    /// The frame is deallocated once its last reference is released.
+    ///
+    /// # Safety
+    /// - The released reference must not be used anymore.
+    unsafe fn release_frame(frame_address: PhysicalAddress);
+
+    /// Returns the statistics about huge pages.
+    fn get_huge_page_statistics() -> huge_pages::Statistics;
+
+    /// Returns the NUMA topology the firmware reports, if it reports one.
+    fn get_numa_topology() -> Option<Topology>;
+
+    /// Verifies the invariants of the current page table.
+    ///
+    /// This panics with the first entry that violates an invariant.
+    fn check_page_tables();
+
     /// Returns the physical memory area where the kernel is loaded.
     fn get_kernel_area() -> MemoryArea<PhysicalAddress>;
 
+    /// Returns the physical memory area reserved for allocations during boot.
+    fn get_early_heap_area() -> MemoryArea<PhysicalAddress>;
+
+    /// Returns the physical memory area reserved for waking up from sleep.
+    fn get_wakeup_area() -> MemoryArea<PhysicalAddress>;
+
+    /// Returns the physical memory area reserved for crash dumps.
+    ///
+    /// The area is mapped at its kernel address.
+    fn get_crash_dump_area() -> MemoryArea<PhysicalAddress>;
+
+    /// Returns the raw entries of the top level page table of the current
+    /// address space.
+    fn get_top_level_entries() -> [u64; 512];
+
     /// Returns the physical memory area where the initramfs is loaded.
     fn get_initramfs_area() -> MemoryArea<VirtualAddress>;
 
     /// Returns the page flags for the page containing the given address.
     fn get_page_flags(page_address: VirtualAddress) -> PageFlags;
 
+    /// Maps the physical area at its kernel address with the given flags.
+    ///
+    /// Pages that are mapped already are left as they are.
+    fn map_physical_area(area: MemoryArea<PhysicalAddress>, flags: PageFlags);
+
     /// Returns whether the given address is a userspace address.
     fn is_userspace_address(address: VirtualAddress) -> bool;
 
+    /// Loads the given watchpoints into the debug hardware of the current CPU.
+    fn load_watchpoints(watchpoints: &WatchpointSet);
+
+    /// Returns the frame pointer of the calling function.
+    fn get_frame_pointer() -> VirtualAddress;
+
     /// The size, in bytes, of a virtual page on the target architecture.
     const PAGE_SIZE: usize;
 
     /// The memory area where the heap is located.
     const HEAP_AREA: MemoryArea<VirtualAddress>;
 
+    /// The size of the physical memory, starting at address zero, that is
+    /// mapped at its kernel address while booting.
+    const BOOT_MAPPED_SIZE: usize;
+
+    /// The size of the physical memory that can be accessed at its kernel
+    /// address.
+    const PHYSICAL_WINDOW_SIZE: usize;
+
     /// Writes the formatted arguments.
     ///
     /// This takes arguments as dictated by `core::fmt` and prints them to the
     /// screen.
     fn write_fmt(args: fmt::Arguments);
 
+    /// Waits until all buffered output was sent to the output devices.
+    fn flush_output();
+
+    /// Returns the number of rows and columns of the console.
+    fn get_console_size() -> (usize, usize);
+
+    /// Returns the row and column the console writes at next.
+    fn get_console_cursor() -> (usize, usize);
+
+    /// Moves the position the console writes at next.
+    ///
+    /// Returns false if the position is outside of the console.
+    fn set_console_cursor(row: usize, column: usize) -> bool;
+
+    /// Lights the keyboard LEDs of the given lock keys and turns off the
+    /// others.
+    fn set_keyboard_leds(locks: Locks);
+
+    /// Sets how fast the keyboard repeats held keys.
+    ///
+    /// The keyboard may round the rate and the delay to values it supports.
+    fn set_keyboard_repeat(repeat: Repeat);
+
+    /// Suspends the machine to RAM and returns once it woke up again.
+    ///
+    /// Returns false if the machine can't be suspended.
+    fn suspend() -> bool;
+
+    /// Returns the performance states of the current CPU, the fastest one
+    /// first.
+    ///
+    /// Returns no states if the frequency of the CPU can't be changed.
+    fn get_performance_states() -> Vec<PerformanceState>;
+
+    /// Switches the current CPU to the given performance state.
+    fn set_performance_state(state: &PerformanceState);
+
+    /// Returns the temperature of the current CPU in degrees Celsius.
+    ///
+    /// Returns `None` if the temperature can't be read.
+    fn get_cpu_temperature() -> Option<u32>;
+
+    /// Exits the emulator that the kernel runs in with the given exit code.
+    ///
+    /// This only has an effect if the emulator provides an exit device.
+    fn exit_emulator(code: u8);
+
+    /// Restarts the machine.
+    ///
+    /// Buffered output is flushed first.
+    fn reboot() -> !;
+
     /// Sets the state of being interruptable to the given state.
     ///
     /// # Safety
@@ -179,8 +364,15 @@ pub trait Context {
         arg5: usize
     ) -> Self;
 
-    /// Creates a new context for an idle thread.
-    fn idle(stack_pointer: VirtualAddress) -> Self;
+    /// Creates a new context for a copy of the current thread.
+    ///
+    /// The new context returns to userspace where the current thread returns
+    /// from its syscall, with zero as the result.
+    fn fork(kernel_stack_pointer: VirtualAddress, address_space: &mut AddressSpace) -> Self;
+
+    /// Creates a new context for a kernel thread that runs the given
+    /// function.
+    fn kernel_thread(function: fn() -> !, stack_pointer: VirtualAddress) -> Self;
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -0,0 +1,353 @@
+//! A driver for the PCM output of AC'97 audio controllers.
+//!
+//! Samples are 16 bit little endian stereo samples at 48 kHz. They are
+//! written to `/dev/pcm` and go into a ring of buffers that the controller
+//! plays by DMA. A buffer is handed to the controller once it is full, or
+//! when the file is closed. Writes wait while all buffers are queued.
+//!
+//! The driver doesn't use interrupts. The state of the ring is read from the
+//! registers of the controller whenever samples are written. Writers that
+//! wait are woken after about the time it takes to play a buffer.
+
+use super::pci::{self, COMMAND_BUS_MASTER, COMMAND_IO_SPACE};
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use core::cmp::min;
+use core::ptr::write_volatile;
+use core::time::Duration;
+use devfs;
+use file_handle::{FileError, FileHandle, PollEvents, Result, SeekFrom, POLL_OUT};
+use multitasking::wait_queue::{self, WaitQueue};
+use sync::time::Timestamp;
+use sync::{cpu_relax, Mutex};
+use x86_64::instructions::port::{inb, inw, outb, outl, outw};
+
+/// The PCI vendor ID of the controller QEMU emulates.
+const VENDOR_ID: u16 = 0x8086;
+
+/// The PCI device ID of the ICH AC'97 controller QEMU emulates.
+const DEVICE_ID: u16 = 0x2415;
+
+/// The name of the PCM output device.
+const DEVICE_NAME: &str = "pcm";
+
+/// The offset of the reset register of the mixer.
+const MIXER_RESET: u16 = 0x00;
+
+/// The offset of the master volume of the mixer.
+const MIXER_MASTER_VOLUME: u16 = 0x02;
+
+/// The offset of the PCM output volume of the mixer.
+const MIXER_PCM_VOLUME: u16 = 0x18;
+
+/// The volume of both channels without attenuation.
+const FULL_VOLUME: u16 = 0x0000;
+
+/// The PCM output volume of both channels without gain.
+const NEUTRAL_GAIN: u16 = 0x0808;
+
+/// The offset of the PCM output registers of the bus master.
+const PCM_OUT: u16 = 0x10;
+
+/// The offset of the physical address of the buffer descriptor list.
+const BUFFER_LIST_ADDRESS: u16 = PCM_OUT;
+
+/// The offset of the index of the buffer that is currently played.
+const CURRENT_INDEX: u16 = PCM_OUT + 0x04;
+
+/// The offset of the index of the last buffer to play.
+const LAST_VALID_INDEX: u16 = PCM_OUT + 0x05;
+
+/// The offset of the status register of the PCM output.
+const STATUS: u16 = PCM_OUT + 0x06;
+
+/// The offset of the control register of the PCM output.
+const CONTROL: u16 = PCM_OUT + 0x0b;
+
+/// The offset of the global control register of the bus master.
+const GLOBAL_CONTROL: u16 = 0x2c;
+
+/// The global control bit that takes the codec out of its cold reset.
+const GLOBAL_COLD_RESET: u32 = 1 << 1;
+
+/// The status bit that tells that the DMA engine halted.
+const STATUS_HALTED: u16 = 1 << 0;
+
+/// The control bit that runs the DMA engine.
+const CONTROL_RUN: u8 = 1 << 0;
+
+/// The control bit that resets the PCM output registers.
+const CONTROL_RESET: u8 = 1 << 1;
+
+/// The number of entries in the buffer descriptor list.
+const BUFFER_COUNT: usize = 32;
+
+/// The size of each buffer in bytes, which lasts about 10 ms.
+const BUFFER_SIZE: usize = 2048;
+
+/// The time in milliseconds it takes to play a buffer, rounded down.
+const BUFFER_DURATION_MS: u64 = 10;
+
+/// The number of checks for the end of the reset before it is given up.
+const MAX_RESET_CHECKS: usize = 1 << 20;
+
+/// An entry of the buffer descriptor list.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BufferDescriptor {
+    /// The physical address of the buffer.
+    address: u32,
+    /// The number of 16 bit samples in the buffer.
+    samples: u16,
+    /// The flags of the buffer.
+    flags: u16
+}
+
+/// The buffer descriptor list.
+///
+/// It must be part of the kernel image, so that it is physically contiguous.
+/// Like the buffers, it is placed with the memory shared with devices.
+#[repr(C, align(8))]
+struct BufferList([BufferDescriptor; BUFFER_COUNT]);
+
+/// The buffer descriptor list of the PCM output.
+#[link_section = ".bss.dma"]
+static mut BUFFER_LIST: BufferList = BufferList(
+    [BufferDescriptor {
+        address: 0,
+        samples: 0,
+        flags: 0
+    }; BUFFER_COUNT]
+);
+
+/// The buffers with the samples.
+#[link_section = ".bss.dma"]
+static mut BUFFERS: [[u8; BUFFER_SIZE]; BUFFER_COUNT] = [[0; BUFFER_SIZE]; BUFFER_COUNT];
+
+/// The controller, if one was found.
+static CONTROLLER: Mutex<Option<Controller>> = Mutex::new(None);
+
+lazy_static! {
+    /// Woken when buffers may have been played.
+    static ref WRITERS: Arc<WaitQueue> = Arc::new(WaitQueue::new());
+}
+
+/// An AC'97 controller with its PCM output.
+struct Controller {
+    /// The first I/O port of the bus master registers.
+    bus_master: u16,
+    /// The index of the buffer that is being filled.
+    fill_index: usize,
+    /// The number of bytes in the buffer that is being filled.
+    fill_length: usize,
+    /// Whether the DMA engine was started.
+    started: bool
+}
+
+impl Controller {
+    /// Returns true if the controller doesn't play the buffer with the given
+    /// index.
+    fn is_free(&self, index: usize) -> bool {
+        let (halted, current, last_valid) = unsafe {
+            (
+                inw(self.bus_master + STATUS) & STATUS_HALTED != 0,
+                inb(self.bus_master + CURRENT_INDEX) as usize,
+                inb(self.bus_master + LAST_VALID_INDEX) as usize
+            )
+        };
+
+        // The controller plays the buffers from the current to the last
+        // valid one.
+        halted
+            || (index + BUFFER_COUNT - current) % BUFFER_COUNT
+                > (last_valid + BUFFER_COUNT - current) % BUFFER_COUNT
+    }
+
+    /// Copies as many bytes as fit into the buffer that is being filled and
+    /// returns their number.
+    ///
+    /// The buffer is queued once it is full.
+    fn fill(&mut self, samples: &[u8]) -> usize {
+        let length = min(samples.len(), BUFFER_SIZE - self.fill_length);
+
+        unsafe {
+            let buffer = &mut BUFFERS[self.fill_index];
+
+            for (target, &byte) in buffer[self.fill_length..].iter_mut().zip(samples) {
+                write_volatile(target, byte);
+            }
+        }
+
+        self.fill_length += length;
+
+        if self.fill_length == BUFFER_SIZE {
+            self.queue();
+        }
+
+        length
+    }
+
+    /// Hands the buffer that is being filled to the controller.
+    fn queue(&mut self) {
+        if self.fill_length == 0 {
+            return;
+        }
+
+        unsafe {
+            write_volatile(
+                &mut BUFFER_LIST.0[self.fill_index].samples,
+                (self.fill_length / 2) as u16
+            );
+
+            // Writing the last valid index restarts the halted engine, but
+            // setting the run bit again would skip a buffer.
+            outb(self.bus_master + LAST_VALID_INDEX, self.fill_index as u8);
+            if !self.started {
+                outb(self.bus_master + CONTROL, CONTROL_RUN);
+                self.started = true;
+            }
+        }
+
+        self.fill_index = (self.fill_index + 1) % BUFFER_COUNT;
+        self.fill_length = 0;
+    }
+}
+
+/// The PCM output as a file.
+struct PcmDevice;
+
+impl Drop for PcmDevice {
+    fn drop(&mut self) {
+        // The rest of the samples is played, too.
+        if let Some(ref mut controller) = *CONTROLLER.lock() {
+            controller.queue();
+        }
+    }
+}
+
+impl FileHandle for PcmDevice {
+    fn seek(&mut self, _position: SeekFrom) -> Result<u64> {
+        Err(FileError::NotSupported)
+    }
+
+    fn read(&mut self, _buffer: &mut [u8]) -> Result<()> {
+        Err(FileError::NotSupported)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        let mut controller = CONTROLLER.lock();
+        let controller = controller.as_mut().ok_or(FileError::DeviceRemoved)?;
+
+        // Either all samples are written or none, so the buffers that the
+        // samples need must be free.
+        let needed_buffers =
+            (controller.fill_length + buffer.len() + BUFFER_SIZE - 1) / BUFFER_SIZE;
+        if needed_buffers > BUFFER_COUNT - 1 {
+            return Err(FileError::NotSupported);
+        }
+
+        for index in 0..needed_buffers {
+            if !controller.is_free((controller.fill_index + index) % BUFFER_COUNT) {
+                return Err(FileError::WouldBlock);
+            }
+        }
+
+        let mut written = 0;
+        while written < buffer.len() {
+            written += controller.fill(&buffer[written..]);
+        }
+
+        Ok(())
+    }
+
+    fn len(&mut self) -> u64 {
+        0
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        match *CONTROLLER.lock() {
+            Some(ref controller) if controller.is_free(controller.fill_index) => POLL_OUT,
+            _ => PollEvents::empty()
+        }
+    }
+
+    fn wait_queue(&mut self) -> Option<&WaitQueue> {
+        // Without interrupts, the next buffer is only known to be played
+        // after it had the time to.
+        if let Some(time) =
+            Timestamp::get_current().offset(Duration::from_millis(BUFFER_DURATION_MS))
+        {
+            wait_queue::wake_at(time, Arc::downgrade(&WRITERS));
+        }
+
+        Some(&WRITERS)
+    }
+}
+
+/// Looks for an AC'97 controller and sets up its PCM output, if there is
+/// one.
+pub fn init() {
+    assert_has_not_been_called!("The AC'97 driver should only be started once.");
+
+    let function = match pci::find(VENDOR_ID, DEVICE_ID) {
+        Some(function) => function,
+        None => return
+    };
+
+    let (mixer, bus_master) = match (function.io_base(0), function.io_base(1)) {
+        (Some(mixer), Some(bus_master)) => (mixer, bus_master),
+        _ => {
+            warn!("The AC'97 controller has no I/O ports.");
+            return;
+        }
+    };
+
+    function.enable(COMMAND_IO_SPACE | COMMAND_BUS_MASTER);
+
+    unsafe {
+        outl(bus_master + GLOBAL_CONTROL, GLOBAL_COLD_RESET);
+        outw(mixer + MIXER_RESET, 0);
+
+        outb(bus_master + CONTROL, CONTROL_RESET);
+
+        let mut checks = 0;
+        while inb(bus_master + CONTROL) & CONTROL_RESET != 0 {
+            checks += 1;
+            if checks == MAX_RESET_CHECKS {
+                warn!("The AC'97 controller didn't finish its reset.");
+                return;
+            }
+
+            cpu_relax();
+        }
+
+        for (descriptor, buffer) in BUFFER_LIST.0.iter_mut().zip(BUFFERS.iter()) {
+            descriptor.address = to_physical!(buffer.as_ptr()) as u32;
+        }
+
+        outl(
+            bus_master + BUFFER_LIST_ADDRESS,
+            to_physical!(&BUFFER_LIST as *const BufferList) as u32
+        );
+
+        outw(mixer + MIXER_MASTER_VOLUME, FULL_VOLUME);
+        outw(mixer + MIXER_PCM_VOLUME, NEUTRAL_GAIN);
+    }
+
+    debug!(
+        "Found an AC'97 controller at {:?} (ports {:#x} and {:#x}).",
+        function, mixer, bus_master
+    );
+
+    *CONTROLLER.lock() = Some(Controller {
+        bus_master,
+        fill_index: 0,
+        fill_length: 0,
+        started: false
+    });
+
+    devfs::register(
+        DEVICE_NAME,
+        Box::new(|| Box::new(PcmDevice) as Box<FileHandle>)
+    );
+}
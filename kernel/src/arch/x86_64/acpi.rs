@@ -0,0 +1,656 @@
+//! Reads what is needed for sleeping and the NUMA topology from the ACPI
+//! tables.
+//!
+//! Only the fixed tables are read. The sleep type values for the S3 state
+//! come from the `_S3_` package in the DSDT, which is found by looking for
+//! its name in the AML code instead of interpreting it. The NUMA topology
+//! comes from the SRAT and the SLIT.
+
+use alloc::Vec;
+use memory::numa::{CpuAffinity, Distances, MemoryAffinity, Topology};
+use memory::physical::{PhysBox, PhysSlice};
+use memory::{Address, MemoryArea, PhysicalAddress, READABLE, WRITABLE};
+use sync::{cpu_relax, OnceCell};
+use x86_64::instructions::port::{inw, outb, outw};
+
+/// The signature of the root system description pointer.
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// The address of the segment of the extended BIOS data area.
+const EBDA_SEGMENT_POINTER: usize = 0x40e;
+
+/// The area of the BIOS that may contain the root system description
+/// pointer.
+const BIOS_AREA: (usize, usize) = (0xe0000, 0x100000);
+
+/// The size of the header of a system description table.
+const HEADER_SIZE: usize = 36;
+
+/// The offset of the physical address of the FACS in the FADT.
+const FIRMWARE_CONTROL: usize = 36;
+
+/// The offset of the physical address of the DSDT in the FADT.
+const DSDT: usize = 40;
+
+/// The offset of the SMI command port in the FADT.
+const SMI_COMMAND: usize = 48;
+
+/// The offset of the value that enables ACPI in the FADT.
+const ACPI_ENABLE: usize = 52;
+
+/// The offset of the first PM1 event block in the FADT.
+const PM1A_EVENT_BLOCK: usize = 56;
+
+/// The offset of the second PM1 event block in the FADT.
+const PM1B_EVENT_BLOCK: usize = 60;
+
+/// The offset of the first PM1 control block in the FADT.
+const PM1A_CONTROL_BLOCK: usize = 64;
+
+/// The offset of the second PM1 control block in the FADT.
+const PM1B_CONTROL_BLOCK: usize = 68;
+
+/// The offset of the 64 bit address of the FACS in the FADT.
+const X_FIRMWARE_CONTROL: usize = 132;
+
+/// The offset of the 64 bit address of the DSDT in the FADT.
+const X_DSDT: usize = 140;
+
+/// The offset of the waking vector in the FACS.
+const WAKING_VECTOR: usize = 12;
+
+/// The offset of the 64 bit waking vector in the FACS.
+const X_WAKING_VECTOR: usize = 24;
+
+/// The status bit that is set once the machine woke up.
+const WAKE_STATUS: u16 = 1 << 15;
+
+/// The control bit that tells that ACPI is enabled.
+const SCI_ENABLE: u16 = 1 << 0;
+
+/// The control bits of the sleep type.
+const SLEEP_TYPE: u16 = 0b111 << 10;
+
+/// The control bit that starts the sleep.
+const SLEEP_ENABLE: u16 = 1 << 13;
+
+/// The offset of the first structure in the SRAT.
+const SRAT_STRUCTURES: usize = 48;
+
+/// The SRAT structure type of a CPU with a local APIC.
+const SRAT_APIC_AFFINITY: u8 = 0;
+
+/// The SRAT structure type of a memory range.
+const SRAT_MEMORY_AFFINITY: u8 = 1;
+
+/// The SRAT structure type of a CPU with a local x2APIC.
+const SRAT_X2APIC_AFFINITY: u8 = 2;
+
+/// The flag of SRAT structures that tells that the CPU or memory is used.
+const SRAT_ENABLED: u32 = 1 << 0;
+
+/// The offset of the number of localities in the SLIT.
+const SLIT_LOCALITY_COUNT: usize = 36;
+
+/// The offset of the distance matrix in the SLIT.
+const SLIT_MATRIX: usize = 44;
+
+/// The number of checks for a state change before it is given up.
+const MAX_CHECKS: usize = 1 << 20;
+
+/// The AML opcode that names an object.
+const NAME_OP: u8 = 0x08;
+
+/// The AML opcode of a package.
+const PACKAGE_OP: u8 = 0x12;
+
+/// The AML prefix of a byte constant.
+const BYTE_PREFIX: u8 = 0x0a;
+
+/// The AML prefix of a word constant.
+const WORD_PREFIX: u8 = 0x0b;
+
+/// The AML opcode of the constant zero.
+const ZERO_OP: u8 = 0x00;
+
+/// The AML opcode of the constant one.
+const ONE_OP: u8 = 0x01;
+
+/// What is needed to put the machine into the S3 state.
+#[derive(Debug)]
+pub struct SleepInfo {
+    /// The port to enable ACPI with, if it isn't enabled yet.
+    smi_command: u16,
+    /// The value that enables ACPI.
+    acpi_enable: u8,
+    /// The ports of the PM1 status registers.
+    pm1_event: [u16; 2],
+    /// The ports of the PM1 control registers.
+    pm1_control: [u16; 2],
+    /// The sleep type values for the two control registers.
+    sleep_type: [u16; 2],
+    /// The physical address of the FACS.
+    facs: usize
+}
+
+impl SleepInfo {
+    /// Sets the physical address where the firmware starts the code after
+    /// waking up.
+    ///
+    /// The code is started in real mode.
+    pub fn set_waking_vector(&self, address: u32) {
+        let length = read_u32(self.facs, 4) as usize;
+
+        write_u32(self.facs, WAKING_VECTOR, address);
+
+        // The 64 bit vector is used instead, unless it is zero.
+        if length >= X_WAKING_VECTOR + 8 {
+            write_u32(self.facs, X_WAKING_VECTOR, 0);
+            write_u32(self.facs, X_WAKING_VECTOR + 4, 0);
+        }
+    }
+
+    /// Puts the machine to sleep.
+    ///
+    /// This only returns if the machine didn't go to sleep.
+    ///
+    /// # Safety
+    /// - Everything that is lost while sleeping must be saved.
+    pub unsafe fn enter(&self) {
+        if inw(self.pm1_control[0]) & SCI_ENABLE == 0 && self.smi_command != 0 {
+            outb(self.smi_command, self.acpi_enable);
+
+            wait_for(|| inw(self.pm1_control[0]) & SCI_ENABLE != 0);
+        }
+
+        for &port in self.pm1_event.iter().filter(|&&port| port != 0) {
+            // Writing the bit clears it.
+            outw(port, WAKE_STATUS);
+        }
+
+        for (&port, &sleep_type) in self.pm1_control.iter().zip(self.sleep_type.iter()) {
+            if port != 0 {
+                let value = inw(port) & !(SLEEP_TYPE | SLEEP_ENABLE);
+                outw(port, value | sleep_type << 10);
+            }
+        }
+
+        for &port in self.pm1_control.iter().filter(|&&port| port != 0) {
+            let value = inw(port);
+            outw(port, value | SLEEP_ENABLE);
+        }
+
+        // The machine sleeps while the bit is still clear.
+        wait_for(|| inw(self.pm1_event[0]) & WAKE_STATUS != 0);
+    }
+}
+
+/// The information needed for sleeping, if the machine supports it.
+static SLEEP_INFO: OnceCell<SleepInfo> = OnceCell::new();
+
+/// Reads the ACPI tables.
+pub fn init() {
+    assert_has_not_been_called!("The ACPI tables should only be read once.");
+
+    match find_sleep_info() {
+        Some(info) => {
+            debug!("The machine supports sleeping: {:?}", info);
+
+            assert!(SLEEP_INFO.set(info).is_ok());
+        },
+        None => debug!("The machine doesn't support sleeping.")
+    }
+}
+
+/// Returns what is needed to put the machine to sleep, if it can sleep.
+pub fn get_sleep_info() -> Option<&'static SleepInfo> {
+    SLEEP_INFO.get()
+}
+
+/// Reads the NUMA topology from the SRAT and the SLIT.
+///
+/// Returns `None` if there is no SRAT.
+pub fn get_numa_topology() -> Option<Topology> {
+    let srat = find_table(b"SRAT")?;
+    let (memory, cpus) = parse_srat(map_bytes(srat, read_u32(srat, 4) as usize).as_slice());
+
+    if memory.is_empty() && cpus.is_empty() {
+        return None;
+    }
+
+    let distances = find_table(b"SLIT")
+        .and_then(|slit| parse_slit(map_bytes(slit, read_u32(slit, 4) as usize).as_slice()));
+
+    Some(Topology::new(&memory, &cpus, distances.as_ref()))
+}
+
+/// Reads the information needed for sleeping from the ACPI tables.
+fn find_sleep_info() -> Option<SleepInfo> {
+    let fadt = find_table(b"FACP")?;
+    let fadt_length = read_u32(fadt, 4) as usize;
+
+    let facs = read_address(fadt, fadt_length, X_FIRMWARE_CONTROL, FIRMWARE_CONTROL)?;
+    let dsdt = read_address(fadt, fadt_length, X_DSDT, DSDT)?;
+
+    let dsdt_length = read_u32(dsdt, 4) as usize;
+    let dsdt_table = map_bytes(dsdt, dsdt_length);
+    let dsdt_bytes = dsdt_table.as_slice();
+    if !has_valid_checksum(dsdt_bytes) || dsdt_length < HEADER_SIZE {
+        return None;
+    }
+
+    let (sleep_type_a, sleep_type_b) = parse_sleep_type(&dsdt_bytes[HEADER_SIZE..], b"_S3_")?;
+
+    let info = SleepInfo {
+        smi_command: read_u32(fadt, SMI_COMMAND) as u16,
+        acpi_enable: map_bytes(fadt, fadt_length).read(ACPI_ENABLE),
+        pm1_event: [
+            read_u32(fadt, PM1A_EVENT_BLOCK) as u16,
+            read_u32(fadt, PM1B_EVENT_BLOCK) as u16
+        ],
+        pm1_control: [
+            read_u32(fadt, PM1A_CONTROL_BLOCK) as u16,
+            read_u32(fadt, PM1B_CONTROL_BLOCK) as u16
+        ],
+        sleep_type: [sleep_type_a, sleep_type_b],
+        facs
+    };
+
+    if info.pm1_event[0] == 0 || info.pm1_control[0] == 0 {
+        None
+    } else {
+        Some(info)
+    }
+}
+
+/// Returns the physical address of the table with the given signature.
+fn find_table(signature: &[u8; 4]) -> Option<usize> {
+    let rsdp = find_rsdp()?;
+    let revision = map_bytes(rsdp, 20).read(15);
+
+    // The XSDT holds 64 bit addresses, the RSDT holds 32 bit addresses.
+    let (root, entry_size) = if revision >= 2 {
+        (read_u64(rsdp, 24) as usize, 8)
+    } else {
+        (read_u32(rsdp, 16) as usize, 4)
+    };
+
+    let root_length = read_u32(root, 4) as usize;
+    if root_length < HEADER_SIZE || !has_valid_checksum(map_bytes(root, root_length).as_slice()) {
+        return None;
+    }
+
+    for index in 0..(root_length - HEADER_SIZE) / entry_size {
+        let offset = HEADER_SIZE + index * entry_size;
+        let table = if entry_size == 8 {
+            read_u64(root, offset) as usize
+        } else {
+            read_u32(root, offset) as usize
+        };
+
+        if map_bytes(table, HEADER_SIZE).as_slice()[..4] == signature[..] {
+            let length = read_u32(table, 4) as usize;
+
+            if has_valid_checksum(map_bytes(table, length).as_slice()) {
+                return Some(table);
+            }
+        }
+    }
+
+    None
+}
+
+/// Returns the physical address of the root system description pointer.
+fn find_rsdp() -> Option<usize> {
+    let ebda = (u32::from(read_u16(EBDA_SEGMENT_POINTER)) << 4) as usize;
+    let mut areas = [(ebda, ebda + 1024), BIOS_AREA];
+
+    // There is no extended BIOS data area.
+    if ebda == 0 {
+        areas[0] = (0, 0);
+    }
+
+    for &(start, end) in areas.iter() {
+        let bytes = map_bytes(start, end - start);
+        let area = bytes.as_slice();
+
+        for offset in (0..area.len().saturating_sub(20)).filter(|offset| offset % 16 == 0) {
+            let candidate = &area[offset..offset + 20];
+
+            if candidate[..8] == RSDP_SIGNATURE[..] && has_valid_checksum(candidate) {
+                return Some(start + offset);
+            }
+        }
+    }
+
+    None
+}
+
+/// Reads the address of a table from the FADT.
+///
+/// The 64 bit field is preferred, if the table contains it.
+fn read_address(fadt: usize, length: usize, x_offset: usize, offset: usize) -> Option<usize> {
+    let address = if length >= x_offset + 8 {
+        read_u64(fadt, x_offset)
+    } else {
+        0
+    };
+
+    let address = if address != 0 {
+        address as usize
+    } else {
+        read_u32(fadt, offset) as usize
+    };
+
+    if address == 0 {
+        None
+    } else {
+        Some(address)
+    }
+}
+
+/// Reads the sleep type values of the sleep state with the given name from
+/// the AML code.
+fn parse_sleep_type(aml: &[u8], name: &[u8; 4]) -> Option<(u16, u16)> {
+    let position = aml.windows(4).enumerate().position(|(index, window)| {
+        // The name must be defined by a name operation, which may use a path
+        // from the root.
+        window == &name[..]
+            && ((index >= 1 && aml[index - 1] == NAME_OP)
+                || (index >= 2 && aml[index - 1] == b'\\' && aml[index - 2] == NAME_OP))
+    })?;
+
+    let mut bytes = aml[position + 4..].iter().cloned();
+
+    if bytes.next()? != PACKAGE_OP {
+        return None;
+    }
+
+    // The upper two bits of the package length tell how many bytes follow.
+    let length_bytes = bytes.next()? >> 6;
+    for _ in 0..length_bytes {
+        bytes.next()?;
+    }
+
+    let element_count = bytes.next()?;
+    if element_count < 2 {
+        return None;
+    }
+
+    let mut next_integer = || match bytes.next()? {
+        ZERO_OP => Some(0),
+        ONE_OP => Some(1),
+        BYTE_PREFIX => bytes.next().map(u16::from),
+        WORD_PREFIX => {
+            let low = bytes.next()?;
+            let high = bytes.next()?;
+            Some(u16::from(low) | u16::from(high) << 8)
+        },
+        _ => None
+    };
+
+    let sleep_type_a = next_integer()?;
+    let sleep_type_b = next_integer()?;
+
+    Some((sleep_type_a & 0b111, sleep_type_b & 0b111))
+}
+
+/// Reads the enabled memory ranges and CPUs from the SRAT.
+fn parse_srat(table: &[u8]) -> (Vec<MemoryAffinity>, Vec<CpuAffinity>) {
+    let mut memory = Vec::new();
+    let mut cpus = Vec::new();
+    let mut offset = SRAT_STRUCTURES;
+
+    while offset + 2 <= table.len() {
+        let length = table[offset + 1] as usize;
+        if length < 2 || offset + length > table.len() {
+            break;
+        }
+
+        let entry = &table[offset..offset + length];
+
+        match entry[0] {
+            SRAT_APIC_AFFINITY if length >= 16 && u32_at(entry, 4) & SRAT_ENABLED != 0 => {
+                // The low byte of the domain is separate from the others.
+                let domain = u32::from(entry[2]) | (u32_at(entry, 8) & 0xffff_ff00);
+
+                cpus.push(CpuAffinity {
+                    domain,
+                    cpu_id: u32::from(entry[3])
+                });
+            },
+            SRAT_MEMORY_AFFINITY if length >= 40 && u32_at(entry, 28) & SRAT_ENABLED != 0 => {
+                let length = u64_at(entry, 16) as usize;
+
+                if length > 0 {
+                    memory.push(MemoryAffinity {
+                        domain: u32_at(entry, 2),
+                        area: MemoryArea::new(
+                            PhysicalAddress::from_usize(u64_at(entry, 8) as usize),
+                            length
+                        )
+                    });
+                }
+            },
+            SRAT_X2APIC_AFFINITY if length >= 24 && u32_at(entry, 12) & SRAT_ENABLED != 0 => {
+                cpus.push(CpuAffinity {
+                    domain: u32_at(entry, 4),
+                    cpu_id: u32_at(entry, 8)
+                });
+            },
+            _ => ()
+        }
+
+        offset += length;
+    }
+
+    (memory, cpus)
+}
+
+/// Reads the distances between the proximity domains from the SLIT.
+fn parse_slit(table: &[u8]) -> Option<Distances> {
+    if table.len() < SLIT_MATRIX {
+        return None;
+    }
+
+    let count = u64_at(table, SLIT_LOCALITY_COUNT) as usize;
+    let end = count
+        .checked_mul(count)
+        .and_then(|size| size.checked_add(SLIT_MATRIX))?;
+
+    if end > table.len() {
+        return None;
+    }
+
+    Some(Distances {
+        count,
+        matrix: table[SLIT_MATRIX..end].to_vec()
+    })
+}
+
+/// Reads the little endian 32 bit value at the offset in the bytes.
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    bytes[offset..offset + 4]
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | u32::from(byte))
+}
+
+/// Reads the little endian 64 bit value at the offset in the bytes.
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from(u32_at(bytes, offset)) | u64::from(u32_at(bytes, offset + 4)) << 32
+}
+
+/// Checks if the bytes add up to zero.
+fn has_valid_checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Maps the physical memory as bytes.
+fn map_bytes(address: usize, length: usize) -> PhysSlice<u8> {
+    // Everything is mapped writable, because the FACS may share a page with
+    // other tables.
+    let address = PhysicalAddress::from_usize(address);
+
+    unsafe { PhysSlice::map(address, length, READABLE | WRITABLE) }
+        .expect("An ACPI table lies outside of the physical memory window.")
+}
+
+/// Reads the 16 bit value at the physical address.
+fn read_u16(address: usize) -> u16 {
+    let bytes = map_bytes(address, 2);
+
+    u16::from(bytes.read(0)) | u16::from(bytes.read(1)) << 8
+}
+
+/// Reads the 32 bit value at the offset from the physical address.
+fn read_u32(address: usize, offset: usize) -> u32 {
+    let bytes = map_bytes(address + offset, 4);
+
+    u32::from(bytes.read(0))
+        | u32::from(bytes.read(1)) << 8
+        | u32::from(bytes.read(2)) << 16
+        | u32::from(bytes.read(3)) << 24
+}
+
+/// Reads the 64 bit value at the offset from the physical address.
+fn read_u64(address: usize, offset: usize) -> u64 {
+    u64::from(read_u32(address, offset)) | u64::from(read_u32(address, offset + 4)) << 32
+}
+
+/// Writes the 32 bit value at the offset from the physical address.
+fn write_u32(address: usize, offset: usize, value: u32) {
+    let address = PhysicalAddress::from_usize(address + offset);
+
+    unsafe { PhysBox::map(address, READABLE | WRITABLE) }
+        .expect("The ACPI value is misaligned or lies outside of the physical memory window.")
+        .write(value);
+}
+
+/// Waits until the condition holds, giving up after a while.
+fn wait_for<F: Fn() -> bool>(condition: F) {
+    for _ in 0..MAX_CHECKS {
+        if condition() {
+            return;
+        }
+
+        cpu_relax();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sleep_type_with_bytes() {
+        let aml = [
+            0x10, 0x08, b'\\', b'_', b'S', b'3', b'_', 0x12, 0x08, 0x04, 0x0a, 0x05, 0x0a, 0x05,
+            0x00, 0x00
+        ];
+
+        assert_eq!(parse_sleep_type(&aml, b"_S3_"), Some((5, 5)));
+    }
+
+    #[test]
+    fn parses_sleep_type_with_constants() {
+        let aml = [
+            0x08, b'_', b'S', b'3', b'_', 0x12, 0x06, 0x04, 0x01, 0x00, 0x00, 0x00
+        ];
+
+        assert_eq!(parse_sleep_type(&aml, b"_S3_"), Some((1, 0)));
+    }
+
+    #[test]
+    fn skips_long_package_lengths() {
+        let aml = [
+            0x08, b'_', b'S', b'3', b'_', 0x12, 0x40, 0x00, 0x02, 0x0a, 0x03, 0x0a, 0x04
+        ];
+
+        assert_eq!(parse_sleep_type(&aml, b"_S3_"), Some((3, 4)));
+    }
+
+    #[test]
+    fn ignores_names_that_are_not_defined() {
+        let aml = [0x70, b'_', b'S', b'3', b'_', 0x12, 0x06, 0x04, 0x01, 0x00];
+
+        assert_eq!(parse_sleep_type(&aml, b"_S3_"), None);
+    }
+
+    /// Returns an SRAT with the given structures.
+    fn srat(structures: &[&[u8]]) -> Vec<u8> {
+        let mut table = Vec::new();
+        table.resize(SRAT_STRUCTURES, 0);
+
+        for structure in structures {
+            table.extend_from_slice(structure);
+        }
+
+        table
+    }
+
+    #[test]
+    fn parses_srat() {
+        let apic = [0, 16, 1, 4, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let disabled_apic = [0, 16, 1, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let x2apic = [
+            2, 24, 0, 0, 3, 0, 0, 0, 0x10, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+        ];
+        let mut memory = [0; 40];
+        memory[..2].copy_from_slice(&[1, 40]);
+        memory[2] = 1;
+        memory[10] = 0x10; // The base is 1 MiB.
+        memory[18] = 0x20; // The length is 2 MiB.
+        memory[28] = 1;
+
+        let table = srat(&[&apic, &disabled_apic, &x2apic, &memory]);
+        let (memory, cpus) = parse_srat(&table);
+
+        assert_eq!(
+            cpus,
+            [
+                CpuAffinity {
+                    domain: 1,
+                    cpu_id: 4
+                },
+                CpuAffinity {
+                    domain: 3,
+                    cpu_id: 0x110
+                }
+            ]
+        );
+        assert_eq!(memory.len(), 1);
+        assert_eq!(memory[0].domain, 1);
+        assert_eq!(memory[0].area.start_address().as_usize(), 0x100000);
+        assert_eq!(memory[0].area.length(), 0x200000);
+    }
+
+    #[test]
+    fn stops_at_broken_srat_structures() {
+        let table = srat(&[&[1, 0, 0, 0]]);
+
+        assert_eq!(parse_srat(&table).0.len(), 0);
+        assert_eq!(parse_srat(&table).1.len(), 0);
+    }
+
+    #[test]
+    fn parses_slit() {
+        let mut table = Vec::new();
+        table.resize(SLIT_MATRIX, 0);
+        table[SLIT_LOCALITY_COUNT] = 2;
+        table.extend_from_slice(&[10, 21, 21, 10]);
+
+        let distances = parse_slit(&table).unwrap();
+        assert_eq!(distances.count, 2);
+        assert_eq!(distances.matrix[..], [10, 21, 21, 10]);
+
+        table.pop();
+        assert_eq!(parse_slit(&table), None);
+    }
+
+    #[test]
+    fn checks_checksums() {
+        assert!(has_valid_checksum(&[0x01, 0xff]));
+        assert!(!has_valid_checksum(&[0x01, 0xfe]));
+    }
+}
@@ -2,11 +2,12 @@
 
 use super::gdt::{TSS, USER_CODE_SEGMENT, USER_DATA_SEGMENT};
 use super::interrupts::lapic;
+use super::syscalls::{saved_user_registers, SavedUserRegisters};
 use arch;
 use core::mem::size_of;
-use memory::address_space::AddressSpace;
+use memory::address_space::{AddressSpace, AddressSpaceHandle};
 use memory::{Address, PhysicalAddress, VirtualAddress};
-use multitasking::scheduler::{after_context_switch, idle};
+use multitasking::scheduler::after_context_switch;
 use multitasking::Stack;
 use x86_64::registers::control_regs::cr3;
 use x86_64::structures::idt::ExceptionStackFrame;
@@ -16,8 +17,7 @@ use x86_64::structures::idt::ExceptionStackFrame;
 #[derive(Debug)]
 pub struct Context {
     pub kernel_stack_pointer: VirtualAddress,
-    base_pointer: VirtualAddress,
-    page_table_address: PhysicalAddress
+    base_pointer: VirtualAddress
 }
 
 impl arch::Context for Context {
@@ -58,25 +58,58 @@ impl arch::Context for Context {
 
         Context {
             kernel_stack_pointer,
-            base_pointer: kernel_stack_pointer,
-            page_table_address: unsafe { address_space.get_page_table_address() }
+            base_pointer: kernel_stack_pointer
         }
     }
 
-    /// Creates a context for an idle thread.
-    fn idle(mut stack_pointer: VirtualAddress) -> Context {
+    /// Creates a context that continues like the current thread returns from
+    /// its syscall.
+    fn fork(mut kernel_stack_pointer: VirtualAddress, address_space: &mut AddressSpace) -> Context {
+        let registers = saved_user_registers();
+
+        let stack_frame = ExceptionStackFrame {
+            instruction_pointer: ::x86_64::VirtualAddress(registers.instruction_pointer as usize),
+            code_segment: USER_CODE_SEGMENT.0 as u64,
+            cpu_flags: registers.flags,
+            stack_pointer: ::x86_64::VirtualAddress(registers.stack_pointer as usize),
+            stack_segment: USER_DATA_SEGMENT.0 as u64
+        };
+
         unsafe {
-            set_idle_stack(&mut stack_pointer);
+            set_forked_stack(
+                &mut kernel_stack_pointer,
+                stack_frame,
+                address_space,
+                &registers
+            );
+        }
+
+        Context {
+            kernel_stack_pointer,
+            base_pointer: kernel_stack_pointer
+        }
+    }
+
+    /// Creates a context for a kernel thread.
+    fn kernel_thread(function: fn() -> !, mut stack_pointer: VirtualAddress) -> Context {
+        unsafe {
+            set_kernel_thread_stack(&mut stack_pointer, function);
         }
 
         Context {
             kernel_stack_pointer: stack_pointer,
-            base_pointer: stack_pointer,
-            page_table_address: PhysicalAddress::from_usize(cr3().0 as usize)
+            base_pointer: stack_pointer
         }
     }
 }
 
+/// Returns the handle of the currently active address space.
+pub fn get_current_address_space() -> AddressSpaceHandle {
+    let page_table_address = PhysicalAddress::from_usize(cr3().0 as usize);
+
+    unsafe { AddressSpaceHandle::from_page_table_address(page_table_address) }
+}
+
 /// This is the first thing that's called by every new thread.
 #[naked]
 unsafe fn enter_thread() -> ! {
@@ -101,13 +134,41 @@ unsafe fn enter_thread() -> ! {
     unreachable!();
 }
 
-/// Sets the initial idle thread stack.
+/// This is the first thing that's called by a thread created by a fork.
+///
+/// It returns to userspace like the syscall of the forking thread, but with
+/// zero as the result.
+#[naked]
+unsafe fn enter_forked_thread() -> ! {
+    after_context_switch();
+    lapic::set_priority(0x0);
+    asm!("xor r12, r12
+          xor r11, r11
+          xor r10, r10
+          xor r9, r9
+          xor r8, r8
+          xor rdi, rdi
+          xor rsi, rsi
+          xor rdx, rdx
+          xor rcx, rcx
+          xor rax, rax
+          pop r15
+          pop r14
+          pop r13
+          pop rbx
+          pop rbp
+          iretq" : : : : "intel", "volatile");
+    unreachable!();
+}
+
+/// Sets the initial stack of a kernel thread, so that it starts in the given
+/// function.
 ///
 /// # Safety
 /// - Make sure that the stack pointer is valid.
-unsafe fn set_idle_stack(stack_pointer: &mut VirtualAddress) {
+unsafe fn set_kernel_thread_stack(stack_pointer: &mut VirtualAddress, function: fn() -> !) {
     *stack_pointer -= size_of::<u64>();
-    *((*stack_pointer).as_mut_ptr()) = idle as u64;
+    *((*stack_pointer).as_mut_ptr()) = function as u64;
 }
 
 /// Sets the initial kernel stack of a thread, so that it can properly start.
@@ -133,14 +194,62 @@ unsafe fn set_initial_stack(
     Stack::push_in(address_space, stack_pointer, enter_thread as u64);
 }
 
+/// Sets the initial kernel stack of a forked thread, so that it returns to
+/// userspace with the given registers.
+///
+/// # Safety
+/// - Make sure that the stack pointer is valid.
+unsafe fn set_forked_stack(
+    stack_pointer: &mut VirtualAddress,
+    stack_frame: ExceptionStackFrame,
+    address_space: &mut AddressSpace,
+    registers: &SavedUserRegisters
+) {
+    Stack::push_in(address_space, stack_pointer, stack_frame);
+    Stack::push_in(address_space, stack_pointer, registers.rbp);
+    Stack::push_in(address_space, stack_pointer, registers.rbx);
+    Stack::push_in(address_space, stack_pointer, registers.r13);
+    Stack::push_in(address_space, stack_pointer, registers.r14);
+    Stack::push_in(address_space, stack_pointer, registers.r15);
+    Stack::push_in(address_space, stack_pointer, enter_forked_thread as u64);
+}
+
 /// Switches the context from the old thread to the current thread.
 ///
+/// The page table is only reloaded if the threads run in different address
+/// spaces.
+///
 /// # Safety
 /// - To make sure that everything is properly cleaned up after switching the
 /// context, this should only be called by the scheduler.
 /// - Make sure preemption is disabled while calling this.
 #[naked]
-pub unsafe fn switch_context(old_context: &mut Context, new_context: &Context) {
+pub unsafe fn switch_context(
+    old_context: &mut Context,
+    new_context: &Context,
+    old_address_space: AddressSpaceHandle,
+    new_address_space: AddressSpaceHandle
+) {
+    #[naked]
+    #[inline(never)]
+    unsafe extern "C" fn switch_within_address_space(
+        old_sp: &mut VirtualAddress,
+        old_bp: &mut VirtualAddress,
+        new_sp: usize,
+        new_bp: usize
+    ) {
+        asm!("mov [rdi], rsp
+            mov [rsi], rbp
+            mov rsp, rdx
+            mov rbp, rcx"
+            : :
+            "{rdi}"(old_sp),
+            "{rsi}"(old_bp),
+            "{rdx}"(new_sp),
+            "{rcx}"(new_bp)
+            : : "intel", "volatile");
+    }
+
     #[naked]
     #[inline(never)]
     unsafe extern "C" fn switch(
@@ -172,11 +281,20 @@ pub unsafe fn switch_context(old_context: &mut Context, new_context: &Context) {
         .base_stack_pointer;
     TSS.as_mut().privilege_stack_table[0] = ::x86_64::VirtualAddress(base_sp.as_usize());
 
-    switch(
-        &mut old_context.kernel_stack_pointer,
-        &mut old_context.base_pointer,
-        new_sp.as_usize(),
-        new_bp.as_usize(),
-        new_context.page_table_address.as_usize()
-    );
+    if old_address_space == new_address_space {
+        switch_within_address_space(
+            &mut old_context.kernel_stack_pointer,
+            &mut old_context.base_pointer,
+            new_sp.as_usize(),
+            new_bp.as_usize()
+        );
+    } else {
+        switch(
+            &mut old_context.kernel_stack_pointer,
+            &mut old_context.base_pointer,
+            new_sp.as_usize(),
+            new_bp.as_usize(),
+            new_address_space.page_table_address().as_usize()
+        );
+    }
 }
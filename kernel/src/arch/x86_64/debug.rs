@@ -0,0 +1,76 @@
+//! Handles the debug registers on the x86_64 architecture.
+
+use kdebug::{WatchKind, WatchpointSet};
+use memory::Address;
+
+/// The bits in DR6 that show which watchpoints were hit.
+pub const DR6_HIT_MASK: u64 = 0b1111;
+
+/// The bit in DR6 that shows that a single step was completed.
+pub const DR6_SINGLE_STEP: u64 = 1 << 14;
+
+/// The value of DR6 without any recorded debug conditions.
+const DR6_CLEAR: u64 = 0xffff_0ff0;
+
+/// Returns the DR7 condition bits for the kind of watchpoint.
+fn condition_bits(kind: WatchKind) -> u64 {
+    match kind {
+        WatchKind::Execute => 0b00,
+        WatchKind::Write => 0b01,
+        WatchKind::ReadWrite => 0b11
+    }
+}
+
+/// Returns the DR7 length bits for the length of a watchpoint.
+fn length_bits(length: usize) -> u64 {
+    match length {
+        1 => 0b00,
+        2 => 0b01,
+        8 => 0b10,
+        4 => 0b11,
+        _ => unreachable!("Watchpoints are created with a valid length.")
+    }
+}
+
+/// Loads the watchpoints into the debug registers of the current CPU.
+pub fn load_watchpoints(watchpoints: &WatchpointSet) {
+    let mut control = 0;
+
+    for (slot, watchpoint) in watchpoints.iter().enumerate() {
+        if let Some(ref watchpoint) = *watchpoint {
+            let address = watchpoint.address.as_usize();
+
+            unsafe {
+                match slot {
+                    0 => asm!("mov dr0, $0" : : "r"(address) : : "intel", "volatile"),
+                    1 => asm!("mov dr1, $0" : : "r"(address) : : "intel", "volatile"),
+                    2 => asm!("mov dr2, $0" : : "r"(address) : : "intel", "volatile"),
+                    3 => asm!("mov dr3, $0" : : "r"(address) : : "intel", "volatile"),
+                    _ => unreachable!("There are only four debug address registers.")
+                }
+            }
+
+            // Enable the slot locally and set its condition and length.
+            control |= 1 << (slot * 2);
+            control |= condition_bits(watchpoint.kind) << (16 + slot * 4);
+            control |= length_bits(watchpoint.length) << (18 + slot * 4);
+        }
+    }
+
+    unsafe {
+        asm!("mov dr7, $0" : : "r"(control) : : "intel", "volatile");
+    }
+}
+
+/// Returns the debug conditions that caused the current debug exception and
+/// resets them.
+pub fn take_debug_status() -> u64 {
+    let status: u64;
+
+    unsafe {
+        asm!("mov $0, dr6" : "=r"(status) : : : "intel", "volatile");
+        asm!("mov dr6, $0" : : "r"(DR6_CLEAR) : : "intel", "volatile");
+    }
+
+    status
+}
@@ -5,6 +5,7 @@ use super::memory::{
     FINAL_STACK_TOP
 };
 use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
 use memory::Address;
 use multitasking::stack::AccessType;
 use multitasking::Stack;
@@ -46,6 +47,9 @@ pub const USER_CODE_SEGMENT: SegmentSelector = SegmentSelector::new(5, Privilege
 #[allow(dead_code)]
 pub const TSS_SELECTOR: SegmentSelector = SegmentSelector::new(6, PrivilegeLevel::Ring0);
 
+/// The bit of a TSS descriptor that marks the TSS as busy.
+const TSS_BUSY: u64 = 1 << (9 + 32);
+
 /// Represents the GDT.
 pub struct Gdt {
     /// The actual entries of the GDT.
@@ -120,6 +124,19 @@ impl Gdt {
         set_cs(KERNEL_CODE_SEGMENT);
         load_tss(TSS_SELECTOR);
     }
+
+    /// Loads this descriptor table again after the CPU lost its state.
+    ///
+    /// # Safety
+    /// - The table must have been loaded on this CPU before.
+    pub unsafe fn reload(&'static self) {
+        // Loading the TSS marks it as busy and a busy TSS can't be loaded,
+        // so the mark from the last load is removed first.
+        let tss_entry = &self.entries[(TSS_SELECTOR.0 >> 3) as usize] as *const u64 as *mut u64;
+        write_volatile(tss_entry, read_volatile(tss_entry) & !TSS_BUSY);
+
+        self.load();
+    }
 }
 
 bitflags! {
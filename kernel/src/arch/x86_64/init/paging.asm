@@ -92,9 +92,10 @@ enable_paging:
     or eax, 1 << 8
     wrmsr
 
-    ;finally enable paging
+    ;finally enable paging and write protection
+    ;write protection makes kernel writes to copy-on-write pages fault
     mov eax, cr0
-    or eax, 1 << 31
+    or eax, (1 << 31) | (1 << 16)
     mov cr0, eax
 
     ret
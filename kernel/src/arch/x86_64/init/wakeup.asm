@@ -0,0 +1,157 @@
+;The code that runs when the machine wakes up from sleep.
+;
+;The kernel copies the code between wakeup_start and wakeup_end to WAKEUP_BASE
+;below 1 MiB, where the firmware starts it in real mode. It switches to long
+;mode with the temporary page table in wakeup_page_table and continues in
+;restore_cpu_context with the context in wakeup_context.
+
+global wakeup_start
+global wakeup_end
+global wakeup_page_table
+global wakeup_context
+global save_cpu_context
+global restore_cpu_context
+
+;the physical address the wake-up code is copied to
+WAKEUP_BASE equ 0x8000
+
+;the offsets of the saved values in a context
+CONTEXT_RBX equ 0
+CONTEXT_RBP equ 8
+CONTEXT_R12 equ 16
+CONTEXT_R13 equ 24
+CONTEXT_R14 equ 32
+CONTEXT_R15 equ 40
+CONTEXT_RIP equ 48
+CONTEXT_RSP equ 56
+CONTEXT_CR3 equ 64
+
+;the address of a label in the copy of the wake-up code
+%define wakeup_address(label) ((label) - wakeup_start + WAKEUP_BASE)
+
+section .rodata
+bits 16
+wakeup_start:
+    cli
+    cld
+
+    ;the firmware may start the code with any segment, so use segment zero
+    jmp 0:wakeup_address(.real_mode)
+.real_mode:
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    lgdt [wakeup_address(wakeup_gdt.pointer)]
+
+    ;enable protected mode
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+
+    jmp dword wakeup_gdt.code32:wakeup_address(.protected_mode)
+
+bits 32
+.protected_mode:
+    mov ax, wakeup_gdt.data
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ;enable physical address extension
+    mov eax, cr4
+    or eax, 1 << 5
+    mov cr4, eax
+
+    mov eax, [wakeup_address(wakeup_page_table)]
+    mov cr3, eax
+
+    ;enable long mode and the no execute bit
+    mov ecx, 0xc0000080
+    rdmsr
+    or eax, (1 << 8) | (1 << 11)
+    wrmsr
+
+    ;enable paging and write protection
+    mov eax, cr0
+    or eax, (1 << 31) | (1 << 16)
+    mov cr0, eax
+
+    jmp wakeup_gdt.code64:wakeup_address(.long_mode)
+
+bits 64
+.long_mode:
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    mov rdi, [wakeup_address(wakeup_context)]
+    mov rax, restore_cpu_context
+    jmp rax
+
+align 8
+wakeup_gdt:
+    dq 0 ;required
+.code32: equ $ - wakeup_gdt
+    dq 0x00cf9a000000ffff ;executable, code, present, 32-bit, 4 GiB
+.data: equ $ - wakeup_gdt
+    dq 0x00cf92000000ffff ;writable, data, present, 32-bit, 4 GiB
+.code64: equ $ - wakeup_gdt
+    dq (1 << 43) | (1 << 44) | (1 << 47) | (1 << 53) ;executable, code, present, 64-bit
+.gdt_end:
+.pointer:
+    dw .gdt_end - wakeup_gdt - 1
+    dd wakeup_address(wakeup_gdt)
+
+align 8
+wakeup_page_table: ;the physical address of the temporary level 4 table
+    dq 0
+wakeup_context: ;the address of the context to restore
+    dq 0
+wakeup_end:
+
+section .text
+bits 64
+;Saves the callee saved registers, the return address, the stack pointer and
+;the page table to the context in rdi and returns 0.
+;
+;When the machine wakes up, restore_cpu_context returns from here again with 1.
+save_cpu_context:
+    mov [rdi + CONTEXT_RBX], rbx
+    mov [rdi + CONTEXT_RBP], rbp
+    mov [rdi + CONTEXT_R12], r12
+    mov [rdi + CONTEXT_R13], r13
+    mov [rdi + CONTEXT_R14], r14
+    mov [rdi + CONTEXT_R15], r15
+
+    mov rax, [rsp]
+    mov [rdi + CONTEXT_RIP], rax
+    lea rax, [rsp + 8]
+    mov [rdi + CONTEXT_RSP], rax
+
+    mov rax, cr3
+    mov [rdi + CONTEXT_CR3], rax
+
+    xor eax, eax
+    ret
+
+;Restores the context in rdi, which save_cpu_context saved.
+;
+;The temporary page table maps the kernel like the saved one, so the page
+;table can be switched here.
+restore_cpu_context:
+    mov rax, [rdi + CONTEXT_CR3]
+    mov cr3, rax
+
+    mov rbx, [rdi + CONTEXT_RBX]
+    mov rbp, [rdi + CONTEXT_RBP]
+    mov r12, [rdi + CONTEXT_R12]
+    mov r13, [rdi + CONTEXT_R13]
+    mov r14, [rdi + CONTEXT_R14]
+    mov r15, [rdi + CONTEXT_R15]
+    mov rsp, [rdi + CONTEXT_RSP]
+
+    mov eax, 1
+    jmp [rdi + CONTEXT_RIP]
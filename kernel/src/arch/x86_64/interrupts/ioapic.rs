@@ -19,11 +19,7 @@ pub fn init() {
         READABLE | WRITABLE | NO_CACHE
     );
 
-    // Disable the 8259 PIC.
-    unsafe {
-        outb(0x21, 0xff);
-        outb(0xa1, 0xff);
-    }
+    disable_pic();
 
     for i in 0..16 {
         let mut irq = IORedirectionEntry::new();
@@ -36,7 +32,47 @@ pub fn init() {
     irq2.set_inactive();
     set_irq(2, irq2);
 
-    // Reroute interrupts to the IOAPIC.
+    route_to_ioapic();
+}
+
+/// The number of redirection entries of the I/O APIC.
+const ENTRY_COUNT: usize = 24;
+
+/// The redirection entries of the I/O APIC.
+pub struct State([u64; ENTRY_COUNT]);
+
+/// Saves the redirection entries of the I/O APIC.
+pub fn save_state() -> State {
+    let mut entries = [0; ENTRY_COUNT];
+
+    for (number, entry) in entries.iter_mut().enumerate() {
+        *entry = get_irq(number as u8).0;
+    }
+
+    State(entries)
+}
+
+/// Restores the I/O APIC after the machine woke up.
+pub fn restore_state(state: &State) {
+    disable_pic();
+
+    for (number, &entry) in state.0.iter().enumerate() {
+        set_irq(number as u8, IORedirectionEntry(entry));
+    }
+
+    route_to_ioapic();
+}
+
+/// Disables the 8259 PIC.
+fn disable_pic() {
+    unsafe {
+        outb(0x21, 0xff);
+        outb(0xa1, 0xff);
+    }
+}
+
+/// Reroutes interrupts to the I/O APIC.
+fn route_to_ioapic() {
     unsafe {
         outb(0x22, 0x70);
         outb(0x23, 0x01);
@@ -51,6 +87,23 @@ fn set_register(reg: u8, value: u32) {
     }
 }
 
+/// Reads an I/O APIC register.
+fn get_register(reg: u8) -> u32 {
+    unsafe {
+        *get_ioapic_base().as_mut_ptr() = reg as u32;
+        *(get_ioapic_base() + 0x10).as_mut_ptr()
+    }
+}
+
+/// Returns the entry for the given IRQ number.
+fn get_irq(number: u8) -> IORedirectionEntry {
+    assert!(number < 24);
+
+    let reg = 0x10 + number * 2;
+
+    IORedirectionEntry(u64::from(get_register(reg + 1)) << 32 | u64::from(get_register(reg)))
+}
+
 /// Sets the given IRQ number to the specified value.
 fn set_irq(number: u8, value: IORedirectionEntry) {
     assert!(number < 24);
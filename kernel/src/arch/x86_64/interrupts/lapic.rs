@@ -1,17 +1,24 @@
 //! Handles configuration of the Local Advanced Programmable Interrupt
 //! Controller (LAPIC).
 
-use super::super::memory::map_page_at;
+use super::super::sync::{set_tsc_frequency, time_since_tsc, tsc_after};
 use super::{IRQ8_INTERRUPT_TICKS, SPURIOUS_INTERRUPT_HANDLER_NUM, TIMER_INTERRUPT_HANDLER_NUM};
-use memory::{PhysicalAddress, VirtualAddress, NO_CACHE, READABLE, WRITABLE};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use memory::physical::PhysSlice;
+use memory::{PhysicalAddress, NO_CACHE, READABLE, WRITABLE};
 use raw_cpuid::CpuId;
-use sync::{disable_preemption, restore_preemption_state};
-use x86_64::instructions::interrupts;
+use sync::{InterruptGuard, OnceCell};
+use x86_64::instructions::{interrupts, rdtsc};
 use x86_64::instructions::port::{inb, outb};
 
 /// The physical base address of the memory mapped LAPIC.
 const LAPIC_BASE: PhysicalAddress = PhysicalAddress::from_const(0xfee00000);
 
+/// The size of the memory mapped registers of the LAPIC.
+const LAPIC_SIZE: usize = 0x1000;
+
 /// The offset for the CMCI interrupt LVT register.
 const CMCI_INTERRUPT: usize = 0x2f0;
 
@@ -51,6 +58,9 @@ const INTERRUPT_COMMAND_REGISTER_LOW: usize = 0x300;
 /// The offset for the interrupt command register (bits 32-63).
 const INTERRUPT_COMMAND_REGISTER_HIGH: usize = 0x310;
 
+/// The bit of the interrupt command register that asserts the level.
+const LEVEL_ASSERT: u64 = 1 << 14;
+
 /// The offset for the end of interrupt register.
 const END_OF_INTERRUPT: usize = 0xb0;
 
@@ -62,16 +72,46 @@ const DESTINATION_FORMAT_REGISTER: usize = 0xe0;
 
 // TODO: This assumes the LAPICS on all CPUs have the same frequency.
 /// The amount of LAPIC timer ticks per milliseconds. Measured at runtime.
+static TICKS_PER_MS: OnceCell<u32> = OnceCell::new();
+
+/// The amount of LAPIC timer ticks per milliseconds before the timer is
+/// calibrated.
+///
+/// This is the value that qemu uses.
+const DEFAULT_TICKS_PER_MS: u32 = 1000000;
+
+/// The memory mapped registers of the LAPIC.
 ///
-/// This value is initialized to the value that qemu uses.
-static mut TICKS_PER_MS: u32 = 1000000;
+/// Every CPU accesses its own LAPIC at the same address.
+static REGISTERS: OnceCell<PhysSlice<u32>> = OnceCell::new();
+
+cpu_local! {
+    /// The time stamp counter value at which the timer should fire or zero if
+    /// it isn't known.
+    static ref TIMER_DEADLINE: AtomicUsize = |_| AtomicUsize::new(0);
+}
 
 /// Initializes the LAPIC.
 pub fn init() {
     assert_has_not_been_called!("The LAPIC should only be initialized once.");
 
-    map_page_at(get_lapic_base(), LAPIC_BASE, READABLE | WRITABLE | NO_CACHE);
+    let flags = READABLE | WRITABLE | NO_CACHE;
+    let registers = unsafe { PhysSlice::map(LAPIC_BASE, LAPIC_SIZE / size_of::<u32>(), flags) }
+        .expect("The LAPIC registers could not be mapped.");
+    assert!(REGISTERS.set(registers).is_ok());
+
+    configure(true);
+}
+
+/// Sets up the LAPIC of a CPU that starts after the boot CPU.
+///
+/// Only the boot CPU receives external interrupts.
+pub fn init_cpu() {
+    configure(false);
+}
 
+/// Sets up the LAPIC of the current CPU.
+fn configure(external_interrupts: bool) {
     let cpu_id = CpuId::new()
         .get_feature_info()
         .unwrap()
@@ -84,6 +124,9 @@ pub fn init() {
     let mut lint0_register = LVTRegister::new();
     lint0_register.set_delivery_mode(EXTINT_DELIVERY_MODE);
     lint0_register.set_trigger_mode(LEVEL_SENSITIVE);
+    if !external_interrupts {
+        lint0_register.set_inactive();
+    }
 
     let mut lint1_register = LVTRegister::new();
     lint1_register.set_delivery_mode(NMI_DELIVERY_MODE);
@@ -159,8 +202,9 @@ pub fn calibrate_timer() {
         // Enable interrupts.
         interrupts::enable();
 
-        // Start LAPIC timer for comparison.
+        // Start LAPIC timer and read the time stamp counter for comparison.
         set_register(TIMER_INITIAL_COUNT, <u32>::max_value());
+        let start_tsc = rdtsc();
 
         // Wait until the specified amount of time has passed.
         while *IRQ8_INTERRUPT_TICKS.lock() < end_tick {
@@ -169,16 +213,38 @@ pub fn calibrate_timer() {
 
         // Measure LAPIC timer ticks.
         let timer_ticks_passed = <u32>::max_value() - get_register(TIMER_CURRENT_COUNT);
+        let tsc_cycles_passed = rdtsc() - start_tsc;
 
         // Disable interrupts again.
         interrupts::disable();
 
-        TICKS_PER_MS = timer_ticks_passed / measure_accuracy_in_ms as u32;
+        let ticks_per_ms = timer_ticks_passed / measure_accuracy_in_ms as u32;
+
+        assert!(
+            TICKS_PER_MS.set(ticks_per_ms).is_ok(),
+            "The LAPIC timer should only be calibrated once."
+        );
 
         // Restore the NMI state.
         outb(0x70, nmi_bit);
 
-        debug!("Timer calibrated to have {} ticks per ms.", TICKS_PER_MS);
+        debug!("Timer calibrated to have {} ticks per ms.", ticks_per_ms);
+
+        // Only a time stamp counter with a constant rate can measure time.
+        let invariant_tsc = CpuId::new()
+            .get_extended_function_info()
+            .map_or(false, |info| info.has_invariant_tsc());
+
+        if invariant_tsc {
+            let cycles_per_microsecond = tsc_cycles_passed / (measure_accuracy_in_ms * 1000);
+
+            set_tsc_frequency(cycles_per_microsecond as usize);
+
+            debug!(
+                "The time stamp counter runs at {} cycles per microsecond.",
+                cycles_per_microsecond
+            );
+        }
     }
 }
 
@@ -192,7 +258,28 @@ pub fn signal_eoi() {
 /// Sets the periodic lapic timer to the specified delay in milliseconds.
 pub fn set_timer(delay: u32) {
     unsafe {
-        set_register(TIMER_INITIAL_COUNT, delay * TICKS_PER_MS);
+        let ticks_per_ms = *TICKS_PER_MS.get().unwrap_or(&DEFAULT_TICKS_PER_MS);
+
+        set_register(TIMER_INITIAL_COUNT, delay * ticks_per_ms);
+    }
+
+    let deadline = if delay > 0 {
+        tsc_after(Duration::from_millis(u64::from(delay))).unwrap_or(0)
+    } else {
+        0
+    };
+
+    TIMER_DEADLINE.store(deadline, Ordering::Relaxed);
+}
+
+/// Returns how late the timer interrupt arrived after its deadline.
+///
+/// The deadline is forgotten, so every interrupt is only measured once.
+/// Returns `None` if the deadline isn't known.
+pub fn take_timer_latency() -> Option<Duration> {
+    match TIMER_DEADLINE.swap(0, Ordering::Relaxed) {
+        0 => None,
+        deadline => time_since_tsc(deadline)
     }
 }
 
@@ -205,7 +292,109 @@ pub fn set_priority(value: u8) {
 
 /// Gets the current task priority for the local APIC.
 pub fn get_priority() -> u8 {
-    unsafe { get_register(TASK_PRIORITY_REGISTER) as u8 }
+    get_register(TASK_PRIORITY_REGISTER) as u8
+}
+
+/// The registers of the LAPIC that are lost while the machine sleeps.
+pub struct State {
+    /// The destination format register.
+    destination_format: u32,
+    /// The logical destination register.
+    logical_destination: u32,
+    /// The task priority register.
+    task_priority: u32,
+    /// The spurious interrupt register.
+    spurious_interrupt: u32,
+    /// The LVT registers in the order of `LVT_REGISTERS`.
+    lvt_registers: [u32; 7],
+    /// The remaining count of the timer.
+    timer_count: u32
+}
+
+/// The LVT registers that are saved before sleeping.
+const LVT_REGISTERS: [usize; 7] = [
+    CMCI_INTERRUPT,
+    TIMER_INTERRUPT,
+    THERMAL_SENSOR_INTERRUPT,
+    PERFORMANCE_COUNTER_INTERRUPT,
+    LINT0_INTERRUPT,
+    LINT1_INTERRUPT,
+    ERROR_INTERRUPT
+];
+
+/// Saves the state of the LAPIC of the current CPU.
+pub fn save_state() -> State {
+    let mut lvt_registers = [0; 7];
+    for (value, &offset) in lvt_registers.iter_mut().zip(LVT_REGISTERS.iter()) {
+        *value = get_register(offset);
+    }
+
+    State {
+        destination_format: get_register(DESTINATION_FORMAT_REGISTER),
+        logical_destination: get_register(LOGICAL_DESTINATION_REGISTER),
+        task_priority: get_register(TASK_PRIORITY_REGISTER),
+        spurious_interrupt: get_register(SPURIOUS_INTERRUPT),
+        lvt_registers,
+        timer_count: get_register(TIMER_CURRENT_COUNT)
+    }
+}
+
+/// Restores the state of the LAPIC of the current CPU.
+///
+/// # Safety
+/// - The state must have been saved on this CPU.
+pub unsafe fn restore_state(state: &State) {
+    set_register(DESTINATION_FORMAT_REGISTER, state.destination_format);
+    set_register(LOGICAL_DESTINATION_REGISTER, state.logical_destination);
+    set_register(TASK_PRIORITY_REGISTER, state.task_priority);
+
+    // The LVT registers can only be unmasked once the LAPIC is enabled.
+    set_register(SPURIOUS_INTERRUPT, state.spurious_interrupt);
+
+    for (&value, &offset) in state.lvt_registers.iter().zip(LVT_REGISTERS.iter()) {
+        set_register(offset, value);
+    }
+
+    set_register(TIMER_INITIAL_COUNT, state.timer_count);
+
+    // The time stamp counter doesn't keep its value across the sleep.
+    TIMER_DEADLINE.store(0, Ordering::Relaxed);
+}
+
+/// Puts all other CPUs into their wait-for-SIPI state.
+///
+/// This makes sure that they don't run while the machine goes to sleep.
+pub fn park_other_cpus() {
+    let icr = ALL_EXCLUDING_SELF.bits() | u64::from(INIT_DELIVERY_MODE.bits()) | LEVEL_ASSERT;
+
+    set_icr(icr);
+    wait_for_delivery();
+}
+
+/// Puts the CPU with the given LAPIC ID into its wait-for-SIPI state.
+pub fn send_init(apic_id: u8) {
+    let icr = u64::from(apic_id) << 56 | u64::from(INIT_DELIVERY_MODE.bits()) | LEVEL_ASSERT;
+
+    set_icr(icr);
+    wait_for_delivery();
+}
+
+/// Starts the CPU with the given LAPIC ID in real mode at the start of the
+/// given page, if it waits for a SIPI.
+pub fn send_startup(apic_id: u8, page: u8) {
+    let icr = u64::from(apic_id) << 56 | u64::from(STARTUP_DELIVERY_MODE.bits()) | u64::from(page);
+
+    set_icr(icr);
+    wait_for_delivery();
+}
+
+/// Waits until the LAPIC sent the last interrupt.
+fn wait_for_delivery() {
+    unsafe {
+        while get_register(INTERRUPT_COMMAND_REGISTER_LOW) & DELIVERY_STATUS.bits() != 0 {
+            asm!("pause" : : : : "intel", "volatile");
+        }
+    }
 }
 
 /// Sets the ICR to the specified value.
@@ -213,40 +402,32 @@ fn set_icr(value: u64) {
     let value_low = value as u32;
     let value_high = (value >> 32) as u32;
 
-    unsafe {
-        let preemption_state = disable_preemption();
+    // Writing the low half sends the interrupt, so no other interrupt may be
+    // sent in between.
+    let _interrupts = InterruptGuard::new();
 
+    unsafe {
         set_register(INTERRUPT_COMMAND_REGISTER_HIGH, value_high);
         set_register(INTERRUPT_COMMAND_REGISTER_LOW, value_low);
-
-        restore_preemption_state(&preemption_state);
     }
 }
 
-/// Returns the base address for the LAPIC of this CPU.
-fn get_lapic_base() -> VirtualAddress {
-    LAPIC_BASE.to_virtual()
+/// Returns the registers of the LAPIC.
+fn registers() -> &'static PhysSlice<u32> {
+    REGISTERS.expect("The LAPIC is used before it was initialized.")
 }
 
 /// Sets a LAPIC register.
 ///
 /// # Safety
-/// - Ensure the LAPIC is mapped.
 /// - Setting registers incorrectly can cause interrupts to behave unexpected.
 unsafe fn set_register(offset: usize, value: u32) {
-    assert!(offset < 0x1000);
-
-    *(get_lapic_base() + offset).as_mut_ptr() = value;
+    registers().write(offset / size_of::<u32>(), value);
 }
 
 /// Gets a LAPIC register.
-///
-/// # Safety
-/// - Ensure the LAPIC is mapped.
-unsafe fn get_register(offset: usize) -> u32 {
-    assert!(offset < 0x1000);
-
-    *(get_lapic_base() + offset).as_mut_ptr()
+fn get_register(offset: usize) -> u32 {
+    registers().read(offset / size_of::<u32>())
 }
 
 /// Sets an LVT register.
@@ -311,6 +492,10 @@ bitflags! {
         const EXTINT_DELIVERY_MODE = 0b111 << 8,
         /// Delivers an INIT request.
         const INIT_DELIVERY_MODE = 0b101 << 8,
+        /// Delivers a startup request.
+        ///
+        /// Only valid for the interrupt command register.
+        const STARTUP_DELIVERY_MODE = 0b110 << 8,
         /// The delivery status of the interrupt.
         ///
         /// Read only.
@@ -4,10 +4,17 @@ mod ioapic;
 pub mod lapic;
 
 pub use self::lapic::issue_self_interrupt;
-use super::sync::CLOCK;
-use core::time::Duration;
+use super::debug::{self, DR6_HIT_MASK, DR6_SINGLE_STEP};
+use super::memory::{is_userspace_address, resolve_copy_on_write};
+use super::ps2_keyboard;
+use super::serial;
+use super::sync;
+use super::vga_buffer;
+use config;
+use kdebug::{self, WATCHPOINT_SLOTS};
 use memory::{Address, VirtualAddress};
 use multitasking::scheduler::schedule_next_thread;
+use multitasking::trace::{Registers, ResumeMode, StopReason};
 use sync::Mutex;
 use x86_64::instructions::interrupts;
 use x86_64::instructions::port::{inb, outb};
@@ -28,9 +35,28 @@ const TIMER_INTERRUPT_HANDLER_NUM: u8 = 0x30;
 /// The handler number for the spurious interrupt.
 const SPURIOUS_INTERRUPT_HANDLER_NUM: u8 = 0x2f;
 
+/// The flags a tracer may change in a stopped thread.
+///
+/// These are the carry, parity, adjust, zero, sign, direction and overflow
+/// flags.
+const TRACER_CHANGEABLE_FLAGS: u64 = 0xcd5;
+
+/// The trap flag, which raises a debug exception after the next instruction.
+const TRAP_FLAG: u64 = 1 << 8;
+
+/// The resume flag, which suppresses execution watchpoints for the next
+/// instruction.
+const RESUME_FLAG: u64 = 1 << 16;
+
 /// The number of IRQ8 interrupt ticks that have passed since it was enabled.
 static IRQ8_INTERRUPT_TICKS: Mutex<u64> = Mutex::new(0);
 
+/// The number of RTC ticks between two deferred screen flushes.
+///
+/// At 1024 ticks per second this flushes the screen about 60 times per
+/// second.
+const SCREEN_FLUSH_INTERVAL_TICKS: u64 = 16;
+
 lazy_static! {
     /// The interrupt descriptor table used by the kernel.
     static ref IDT: Idt = {
@@ -38,6 +64,7 @@ lazy_static! {
 
         // Exception handlers.
         idt.divide_by_zero.set_handler_fn(divide_by_zero_handler);
+        idt.debug.set_handler_fn(debug_handler);
         idt.breakpoint.set_handler_fn(breakpoint_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
         unsafe {
@@ -52,6 +79,8 @@ lazy_static! {
 
         // IRQ interrupts that are explicitly handled.
         idt[IRQ_INTERRUPT_NUMS[1] as usize].set_handler_fn(irq1_handler);
+        idt[IRQ_INTERRUPT_NUMS[3] as usize].set_handler_fn(irq3_handler);
+        idt[IRQ_INTERRUPT_NUMS[4] as usize].set_handler_fn(irq4_handler);
         idt[IRQ_INTERRUPT_NUMS[8] as usize].set_handler_fn(irq8_handler);
 
         // The schedule interrupt is invoked for every reschedule.
@@ -79,6 +108,59 @@ pub fn init() {
     lapic::calibrate_timer();
 }
 
+/// Initializes interrupts on a CPU that starts after the boot CPU.
+///
+/// The interrupts of devices are only delivered to the boot CPU.
+pub fn init_cpu() {
+    IDT.load();
+
+    lapic::init_cpu();
+}
+
+/// The interrupt state that is lost while the machine sleeps.
+pub struct State {
+    /// The state of the LAPIC.
+    lapic: lapic::State,
+    /// The state of the I/O APIC.
+    ioapic: ioapic::State,
+    /// Status register b of the RTC, which enables its interrupts.
+    rtc_status_b: u8
+}
+
+/// Saves the interrupt state before the machine goes to sleep.
+pub fn save_state() -> State {
+    let rtc_status_b = unsafe {
+        let nmi_bit = inb(0x70) & 0x80;
+        outb(0x70, nmi_bit | 0x0b);
+        inb(0x71)
+    };
+
+    State {
+        lapic: lapic::save_state(),
+        ioapic: ioapic::save_state(),
+        rtc_status_b
+    }
+}
+
+/// Restores the interrupt state after the machine woke up.
+///
+/// # Safety
+/// - The state must have been saved on this CPU.
+pub unsafe fn restore_state(state: &State) {
+    IDT.load();
+
+    lapic::restore_state(&state.lapic);
+    ioapic::restore_state(&state.ioapic);
+
+    let nmi_bit = inb(0x70) & 0x80;
+    outb(0x70, nmi_bit | 0x0b);
+    outb(0x71, state.rtc_status_b);
+
+    // Read status register c, so that the RTC raises interrupts again.
+    outb(0x70, nmi_bit | 0x0c);
+    inb(0x71);
+}
+
 macro_rules! irq_interrupt {
     ($(#[$attr: meta])* fn $name: ident $content: tt) => {
         $(#[$attr])*
@@ -100,15 +182,115 @@ macro_rules! irq_interrupt {
     };
 }
 
+/// Returns true if the exception happened in userspace.
+fn is_from_userspace(stack_frame: &ExceptionStackFrame) -> bool {
+    stack_frame.code_segment & 0b11 == 3
+}
+
+/// Returns the frame pointer of the interrupted code.
+///
+/// # Safety
+/// - This must only be called directly from an interrupt handler, that was
+/// compiled with frame pointers.
+#[inline(always)]
+unsafe fn interrupted_frame_pointer() -> VirtualAddress {
+    let frame_pointer: usize;
+    asm!("mov $0, [rbp]" : "=r"(frame_pointer) : : : "intel", "volatile");
+    VirtualAddress::from_usize(frame_pointer)
+}
+
+/// Returns the registers of the interrupted code.
+fn interrupted_registers(
+    stack_frame: &ExceptionStackFrame,
+    frame_pointer: VirtualAddress
+) -> Registers {
+    Registers {
+        instruction_pointer: stack_frame.instruction_pointer.0,
+        stack_pointer: stack_frame.stack_pointer.0,
+        frame_pointer: frame_pointer.as_usize(),
+        flags: stack_frame.cpu_flags as usize,
+        ..Default::default()
+    }
+}
+
+/// Resumes the interrupted code with the registers set by a tracer.
+fn resume_interrupted(stack_frame: &mut ExceptionStackFrame, registers: &Registers, mode: ResumeMode) {
+    stack_frame.instruction_pointer = ::x86_64::VirtualAddress(registers.instruction_pointer);
+    stack_frame.stack_pointer = ::x86_64::VirtualAddress(registers.stack_pointer);
+
+    let mut flags = (stack_frame.cpu_flags & !TRACER_CHANGEABLE_FLAGS)
+        | (registers.flags as u64 & TRACER_CHANGEABLE_FLAGS);
+
+    if mode == ResumeMode::SingleStep {
+        flags |= TRAP_FLAG;
+    } else {
+        flags &= !TRAP_FLAG;
+    }
+
+    stack_frame.cpu_flags = flags;
+}
+
 /// The divide by zero exception handler of the kernel.
 extern "x86-interrupt" fn divide_by_zero_handler(stack_frame: &mut ExceptionStackFrame) {
+    let frame_pointer = unsafe { interrupted_frame_pointer() };
+
     error!("Divide by zero exception.");
     error!("{:?}", stack_frame);
-    loop {}
+
+    let mut registers = interrupted_registers(stack_frame, frame_pointer);
+
+    if is_from_userspace(stack_frame) {
+        let mode = ::interrupts::user_fault(&mut registers);
+        resume_interrupted(stack_frame, &registers, mode);
+    } else {
+        ::interrupts::kernel_fault(&registers);
+    }
+}
+
+/// The debug exception handler of the kernel.
+///
+/// Debug exceptions are raised by watchpoint hits and after single steps of
+/// traced threads.
+extern "x86-interrupt" fn debug_handler(stack_frame: &mut ExceptionStackFrame) {
+    let frame_pointer = unsafe { interrupted_frame_pointer() };
+    let status = debug::take_debug_status();
+    let mut registers = interrupted_registers(stack_frame, frame_pointer);
+    let mut mode = ResumeMode::Continue;
+
+    for slot in 0..WATCHPOINT_SLOTS {
+        if status & DR6_HIT_MASK & (1 << slot) != 0 {
+            mode = kdebug::watchpoint_hit(slot, &mut registers);
+        }
+    }
+
+    if status & DR6_SINGLE_STEP != 0 {
+        if !is_from_userspace(stack_frame) {
+            error!("Single step exception in the kernel.");
+            error!("{:?}", stack_frame);
+            loop {}
+        }
+
+        mode = ::interrupts::user_debug_exception(StopReason::SingleStep, &mut registers);
+    }
+
+    resume_interrupted(stack_frame, &registers, mode);
+
+    // Execution watchpoints would otherwise trigger again for the same
+    // instruction.
+    stack_frame.cpu_flags |= RESUME_FLAG;
 }
 
 /// The breakpoint exception handler of the kernel.
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut ExceptionStackFrame) {
+    let frame_pointer = unsafe { interrupted_frame_pointer() };
+
+    if is_from_userspace(stack_frame) {
+        let mut registers = interrupted_registers(stack_frame, frame_pointer);
+        let mode = ::interrupts::user_debug_exception(StopReason::Breakpoint, &mut registers);
+        resume_interrupted(stack_frame, &registers, mode);
+        return;
+    }
+
     error!("Breakpoint exception.");
     error!("{:?}", stack_frame);
     loop {}
@@ -131,12 +313,33 @@ extern "x86-interrupt" fn double_fault_handler(
 /// The page fault handler of the kernel.
 extern "x86-interrupt" fn page_fault_handler(
     stack_frame: &mut ExceptionStackFrame,
-    _error_code: PageFaultErrorCode
+    error_code: PageFaultErrorCode
 ) {
-    ::interrupts::page_fault_handler(
-        VirtualAddress::from_usize(control_regs::cr2().0),
-        VirtualAddress::from_usize(stack_frame.instruction_pointer.0)
+    let address = VirtualAddress::from_usize(control_regs::cr2().0);
+
+    let write_to_present_page =
+        PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+
+    // Writes to copy-on-write pages continue once the page has its own frame.
+    // With write protection enabled this includes writes of the kernel to
+    // user memory.
+    if error_code.contains(write_to_present_page)
+        && is_userspace_address(address)
+        && resolve_copy_on_write(address)
+    {
+        return;
+    }
+
+    let frame_pointer = unsafe { interrupted_frame_pointer() };
+    let mut registers = interrupted_registers(stack_frame, frame_pointer);
+
+    let mode = ::interrupts::page_fault_handler(
+        address,
+        &mut registers,
+        is_from_userspace(stack_frame)
     );
+
+    resume_interrupted(stack_frame, &registers, mode);
 }
 
 /// The software interrupt handler that invokes schedule operations.
@@ -156,6 +359,10 @@ extern "x86-interrupt" fn empty_handler(_: &mut ExceptionStackFrame) {}
 irq_interrupt!(
 /// The handler for the lapic timer interrupt.
 fn timer_handler {
+    if let Some(latency) = lapic::take_timer_latency() {
+        ::interrupts::latency::record(TIMER_INTERRUPT_HANDLER_NUM, latency);
+    }
+
     ::interrupts::timer_interrupt();
 });
 
@@ -163,9 +370,16 @@ irq_interrupt!(
 /// The handler for IRQ8.
 fn irq8_handler {
     unsafe {
-        *IRQ8_INTERRUPT_TICKS.lock() += 1;
-        // TODO: Find a better time source, that isn't relying on interrupts.
-        CLOCK += Duration::new(0, 1_000_000_000 / 1024);
+        let ticks = {
+            let mut ticks = IRQ8_INTERRUPT_TICKS.lock();
+            *ticks += 1;
+            *ticks
+        };
+        sync::tick();
+
+        if config::DEFERRED_SCREEN_FLUSH && ticks % SCREEN_FLUSH_INTERVAL_TICKS == 0 {
+            vga_buffer::flush_deferred();
+        }
 
         // Read status register c of the RTC to signal the end of an interrupt.
         let nmi_bit = inb(0x70) & 0x80;
@@ -177,7 +391,22 @@ fn irq8_handler {
 irq_interrupt!(
 /// The handler for IRQ1.
 fn irq1_handler {
-    let scancode = unsafe { ::x86_64::instructions::port::inb(0x60) };
+    let byte = unsafe { ::x86_64::instructions::port::inb(0x60) };
+
+    // Responses to keyboard commands arrive here as well.
+    if let Some(scancode) = ps2_keyboard::receive(byte) {
+        ::interrupts::keyboard_interrupt(scancode);
+    }
+});
 
-    ::interrupts::keyboard_interrupt(scancode);
+irq_interrupt!(
+/// The handler for IRQ3, which is shared by COM2 and COM4.
+fn irq3_handler {
+    serial::interrupt(3);
+});
+
+irq_interrupt!(
+/// The handler for IRQ4, which is shared by COM1 and COM3.
+fn irq4_handler {
+    serial::interrupt(4);
 });
@@ -3,32 +3,54 @@
 use super::paging::inactive_page_table::InactivePageTable;
 use super::paging::page_table_entry::*;
 use super::paging::page_table_manager::PageTableManager;
-use super::paging::{convert_flags, Page, PageFrame, CURRENT_PAGE_TABLE};
+use super::paging::{add_frame_reference, check_kernel_half, convert_flags, Page, PageFrame,
+                    CURRENT_PAGE_TABLE};
 use super::PAGE_SIZE;
+use core::cmp::min;
 use core::ptr;
-use memory::{address_space_manager, Address, AddressSpace, PageFlags, PhysicalAddress, VirtualAddress};
-use super::{KERNEL_STACK_AREA_BASE, KERNEL_STACK_MAX_SIZE, KERNEL_STACK_OFFSET, USER_STACK_AREA_BASE,
-    USER_STACK_MAX_SIZE, USER_STACK_OFFSET};
+use memory::{address_space_manager, Address, AddressSpace, MappingError, MemoryArea, PageFlags, PhysicalAddress, VirtualAddress};
+use super::{KERNEL_STACK_AREA_BASE, KERNEL_STACK_MAX_SIZE, KERNEL_STACK_OFFSET,
+    SHARED_MEMORY_AREA_BASE, SHARED_MEMORY_AREA_SIZE, USER_STACK_AREA_BASE, USER_STACK_AREA_SIZE,
+    USER_STACK_DEFAULT_SIZE, USER_STACK_MAX_SIZE};
 use multitasking::{Stack, ThreadID};
 use multitasking::stack::AccessType;
+use x86_64::instructions::tlb;
 
 pub struct AddressSpaceManager {
-    table: InactivePageTable
+    table: InactivePageTable,
+    /// Whether large ranges may be mapped with huge pages.
+    huge_pages: bool
 }
 
 impl address_space_manager::AddressSpaceManager for AddressSpaceManager {
+    const USER_STACK_SIZE: usize = USER_STACK_DEFAULT_SIZE;
+
+    const USER_STACK_MAX_SIZE: usize = USER_STACK_MAX_SIZE;
+
+    const USER_STACK_AREA: MemoryArea<VirtualAddress> =
+        MemoryArea::new(USER_STACK_AREA_BASE, USER_STACK_AREA_SIZE);
+
+    const SHARED_MEMORY_AREA: MemoryArea<VirtualAddress> =
+        MemoryArea::new(SHARED_MEMORY_AREA_BASE, SHARED_MEMORY_AREA_SIZE);
+
     fn new() -> AddressSpaceManager {
         AddressSpaceManager {
-            table: InactivePageTable::copy_from_current()
+            table: InactivePageTable::copy_from_current(),
+            huge_pages: false
         }
     }
 
     fn idle() -> AddressSpaceManager {
         AddressSpaceManager {
-            table: InactivePageTable::from_current_table()
+            table: InactivePageTable::from_current_table(),
+            huge_pages: false
         }
     }
 
+    fn set_huge_pages(&mut self, enabled: bool) {
+        self.huge_pages = enabled;
+    }
+
     fn write_to(&mut self, buffer: &[u8], address: VirtualAddress, flags: PageFlags) {
         let flags = convert_flags(flags);
 
@@ -42,65 +64,180 @@ impl address_space_manager::AddressSpaceManager for AddressSpaceManager {
         for page_num in start_page_num..end_page_num {
             let page_address = VirtualAddress::from_page_num(page_num);
 
-            // First map with write permissions.
-            self.table
-                .change_permissions_or_map(Page::from_address(page_address), WRITABLE);
+            // Huge pages are written to as they are, so they aren't split.
+            let is_huge_page = self.table.get_huge_page_entry(page_address).is_some();
+
+            let mut entry = None;
+            let physical_address = if is_huge_page {
+                self.table.translate_address(page_address).unwrap()
+            } else {
+                // First map with write permissions.
+                self.table
+                    .change_permissions_or_map(Page::from_address(page_address), WRITABLE)
+                    .expect("Writing to a page that can't be mapped.");
+
+                // Get the physical address.
+                let new_entry = self.table.get_entry_and_map(page_address);
+                let physical_address = new_entry
+                    .points_to()
+                    .expect("The just mapped page isn't mapped.");
 
-            // Get the physical address.
-            let mut entry = self.table.get_entry_and_map(page_address);
-            let physical_address = entry
-                .points_to()
-                .expect("The just mapped page isn't mapped.");
+                entry = Some(new_entry);
+                physical_address
+            };
+
+            let write_length = min(
+                PAGE_SIZE - current_offset,
+                buffer.len() - current_buffer_position
+            );
 
             // Write to the physical address.
-            let (new_current_buffer_position, new_current_offset) = CURRENT_PAGE_TABLE
-                .lock()
-                .with_temporary_page(&PageFrame::from_address(physical_address), |page| {
-                    let start_address = page.get_address() + current_offset;
-
-                    let write_length =
-                        if (PAGE_SIZE - current_offset) >= buffer.len() - current_buffer_position {
-                            // If the rest fits within the page.
-                            buffer.len() - current_buffer_position
-                        } else {
-                            // There is still more to fill.
-                            PAGE_SIZE - current_offset
-                        };
-
-                    unsafe {
-                        ptr::copy_nonoverlapping(
-                            buffer.as_ptr(),
-                            start_address.as_mut_ptr(),
-                            write_length
-                        );
-                    }
-
-                    (
-                        current_buffer_position + write_length,
-                        (current_offset + write_length) % PAGE_SIZE
-                    )
-                });
-
-            current_offset = new_current_offset;
-            current_buffer_position = new_current_buffer_position;
+            {
+                let mut current_page_table = CURRENT_PAGE_TABLE.lock();
+                let mapping =
+                    current_page_table.map_temporarily(&PageFrame::from_address(physical_address));
+                let start_address = mapping.get_address() + current_offset;
+
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        buffer[current_buffer_position..].as_ptr(),
+                        start_address.as_mut_ptr(),
+                        write_length
+                    );
+                }
+            }
+
+            current_buffer_position += write_length;
+            current_offset = (current_offset + write_length) % PAGE_SIZE;
 
             // Change to the desired flags.
-            entry.set_flags(flags);
+            if let Some(ref mut entry) = entry {
+                entry.set_flags(flags);
+            }
         }
 
         self.table.unmap();
     }
 
+    fn read_from(&mut self, buffer: &mut [u8], address: VirtualAddress) -> bool {
+        if buffer.is_empty() {
+            return true;
+        }
+
+        let start_page_num = address.page_num();
+        let end_page_num = (address + buffer.len() - 1).page_num() + 1;
+
+        let mut current_offset = address.offset_in_page();
+        let mut current_buffer_position = 0;
+        let mut success = true;
+
+        // For all pages.
+        for page_num in start_page_num..end_page_num {
+            let page_address = VirtualAddress::from_page_num(page_num);
+
+            // Get the physical address, without mapping anything new or
+            // splitting huge pages.
+            let physical_address = match self.table.translate_address(page_address) {
+                Some(address) => address,
+                None => {
+                    success = false;
+                    break;
+                }
+            };
+
+            let read_length = min(
+                PAGE_SIZE - current_offset,
+                buffer.len() - current_buffer_position
+            );
+
+            // Read from the physical address.
+            {
+                let mut current_page_table = CURRENT_PAGE_TABLE.lock();
+                let mapping =
+                    current_page_table.map_temporarily(&PageFrame::from_address(physical_address));
+                let start_address = mapping.get_address() + current_offset;
+
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        start_address.as_ptr(),
+                        buffer[current_buffer_position..].as_mut_ptr(),
+                        read_length
+                    );
+                }
+            }
+
+            current_buffer_position += read_length;
+            current_offset = 0;
+        }
+
+        self.table.unmap();
+
+        success
+    }
+
+    fn translate_address(&mut self, address: VirtualAddress) -> Option<PhysicalAddress> {
+        let physical_address = self.table.translate_address(address);
+
+        self.table.unmap();
+
+        physical_address
+    }
+
     unsafe fn get_page_table_address(&self) -> PhysicalAddress {
         self.table.get_frame().get_address()
     }
 
-    fn map_page(&mut self, page_address: VirtualAddress, flags: PageFlags) {
+    fn map_page(
+        &mut self,
+        page_address: VirtualAddress,
+        flags: PageFlags
+    ) -> Result<(), MappingError> {
+        let flags = convert_flags(flags);
+
+        let result = self.table.map_page(Page::from_address(page_address), flags);
+
+        self.table.unmap();
+
+        result
+    }
+
+    fn map_range(
+        &mut self,
+        first_page_address: VirtualAddress,
+        count: usize,
+        flags: PageFlags
+    ) -> Result<(), MappingError> {
         let flags = convert_flags(flags);
 
-        self.table.map_page(Page::from_address(page_address), flags);
+        let result = self.table.map_range(
+            Page::from_address(first_page_address),
+            count,
+            flags,
+            self.huge_pages
+        );
 
         self.table.unmap();
+
+        result
+    }
+
+    fn map_page_at(
+        &mut self,
+        page_address: VirtualAddress,
+        frame_address: PhysicalAddress,
+        flags: PageFlags
+    ) -> Result<(), MappingError> {
+        let flags = convert_flags(flags);
+
+        let result = self.table.map_page_at(
+            Page::from_address(page_address),
+            PageFrame::from_address(frame_address),
+            flags
+        );
+
+        self.table.unmap();
+
+        result
     }
 
     unsafe fn unmap_page(&mut self, start_address: VirtualAddress) {
@@ -116,6 +253,75 @@ impl address_space_manager::AddressSpaceManager for AddressSpaceManager {
         self.table.unmap();
     }
 
+    unsafe fn unmap_range(&mut self, first_page_address: VirtualAddress, count: usize) {
+        self.table
+            .unmap_range(Page::from_address(first_page_address), count);
+
+        self.table.unmap();
+    }
+
+    unsafe fn unmap_page_without_freeing(&mut self, start_address: VirtualAddress) {
+        self.table
+            .unmap_page_without_freeing(Page::from_address(start_address));
+
+        self.table.unmap();
+    }
+
+    fn share_copy_on_write(&mut self, area: MemoryArea<VirtualAddress>, flags: PageFlags) {
+        let flags = convert_flags(flags);
+        let child_flags = if flags.contains(WRITABLE) {
+            (flags - WRITABLE) | COPY_ON_WRITE
+        } else {
+            flags
+        };
+
+        let first_page_num = area.start_address().page_num();
+        let last_page_num = (area.start_address() + area.length() - 1).page_num();
+
+        for page_num in first_page_num..last_page_num + 1 {
+            let page_address = VirtualAddress::from_page_num(page_num);
+            let frame = {
+                let mut table = CURRENT_PAGE_TABLE.lock();
+                let mut entry = match table.get_entry(page_address) {
+                    Some(entry) => entry,
+                    None => continue
+                };
+
+                let frame = match entry.points_to() {
+                    Some(address) if entry.flags().contains(PRESENT) => address,
+                    _ => continue
+                };
+
+                if flags.contains(WRITABLE) {
+                    // TODO: Consider multiple CPUs.
+                    let parent_flags = (entry.flags() - WRITABLE) | COPY_ON_WRITE;
+                    entry.set_flags(parent_flags);
+                    tlb::flush(::x86_64::VirtualAddress(page_address.as_usize()));
+                }
+
+                frame
+            };
+
+            add_frame_reference(frame);
+
+            self.table
+                .map_page_at(
+                    Page::from_address(page_address),
+                    PageFrame::from_address(frame),
+                    child_flags
+                )
+                .expect("The page of a segment couldn't be shared.");
+        }
+
+        self.table.unmap();
+    }
+
+    fn check_kernel_half(&mut self) {
+        check_kernel_half(self.table.get_l4());
+
+        self.table.unmap();
+    }
+
     fn create_kernel_stack(id: ThreadID, address_space: &mut AddressSpace) -> Stack {
         let tid: usize = id.into();
         Stack::new(
@@ -127,22 +333,25 @@ impl address_space_manager::AddressSpaceManager for AddressSpaceManager {
         )
     }
 
-    fn create_user_stack(id: ThreadID, address_space: &mut AddressSpace) -> Stack {
-        let tid: usize = id.into();
+    fn create_user_stack(
+        area: MemoryArea<VirtualAddress>,
+        address_space: &mut AddressSpace
+    ) -> Stack {
         Stack::new(
-            0x2000,
-            USER_STACK_MAX_SIZE,
-            USER_STACK_AREA_BASE + USER_STACK_OFFSET * tid,
+            min(0x2000, area.length()),
+            area.length(),
+            area.start_address(),
             AccessType::UserAccessible,
             Some(address_space)
         )
     }
 
-    fn create_idle_stack(cpu_id: usize) -> Stack {
+    fn create_kernel_thread_stack(id: ThreadID) -> Stack {
+        let tid: usize = id.into();
         Stack::new(
             0x3000,
             KERNEL_STACK_MAX_SIZE,
-            KERNEL_STACK_AREA_BASE + KERNEL_STACK_OFFSET * cpu_id,
+            KERNEL_STACK_AREA_BASE + KERNEL_STACK_OFFSET * tid,
             AccessType::KernelOnly,
             None
         )
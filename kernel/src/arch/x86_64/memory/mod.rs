@@ -1,6 +1,8 @@
 //! Handles all x86_64 memory related issues.
 
+use memory::huge_pages::Statistics;
 use memory::{Address, MemoryArea, PageFlags, PhysicalAddress, VirtualAddress};
+use sync::OnceCell;
 
 pub mod address_space_manager;
 mod paging;
@@ -38,11 +40,20 @@ pub const KERNEL_STACK_MAX_SIZE: usize = 0x200000;
 /// The base address of the process stack area.
 pub const USER_STACK_AREA_BASE: VirtualAddress = VirtualAddress::from_const(0x00007f8000000000);
 
-/// The offset of the start addresses of thread stacks.
-pub const USER_STACK_OFFSET: usize = 0x400000;
+/// The size of the area where the user stacks are located.
+pub const USER_STACK_AREA_SIZE: usize = 0x8000000000;
+
+/// The size of a thread stack if no other size is requested.
+pub const USER_STACK_DEFAULT_SIZE: usize = 0x200000;
 
 /// The maximum size of a thread stack.
-pub const USER_STACK_MAX_SIZE: usize = 0x200000;
+pub const USER_STACK_MAX_SIZE: usize = 0x4000000;
+
+/// The base address of the area where shared memory is mapped.
+pub const SHARED_MEMORY_AREA_BASE: VirtualAddress = VirtualAddress::from_const(0x00007f0000000000);
+
+/// The size of the area where shared memory is mapped.
+pub const SHARED_MEMORY_AREA_SIZE: usize = 0x8000000000;
 
 /// The start address of the heap.
 pub const HEAP_START: VirtualAddress = VirtualAddress::from_const(0xfffffd8000000000);
@@ -55,12 +66,37 @@ pub const HEAP_MAX_SIZE: usize = PAGE_SIZE * 512 * 512 * 512;
 /// The size of a single page.
 pub const PAGE_SIZE: usize = 0x1000;
 
+/// The size of the physical memory that the boot page tables map.
+///
+/// They map the first GiB with huge pages, both at its physical and at its
+/// kernel address.
+pub const BOOT_MAPPED_SIZE: usize = 512 * 0x200000;
+
+/// The size of the physical memory that can be accessed at its kernel
+/// address.
+///
+/// The kernel addresses of higher physical addresses hold the stacks and the
+/// heap.
+pub const PHYSICAL_WINDOW_SIZE: usize = 0xfffffd0000000000 - 0xffff800000000000;
+
+/// The physical memory below 1 MiB that is reserved for waking up from sleep.
+///
+/// It holds the wake-up code and the temporary page table it uses.
+pub const WAKEUP_AREA: MemoryArea<PhysicalAddress> =
+    MemoryArea::new(PhysicalAddress::from_const(0x8000), 4 * PAGE_SIZE);
+
+/// The physical memory below 1 MiB that is reserved for crash dumps.
+///
+/// It isn't used otherwise, so a dump is kept until the next boot.
+pub const CRASH_DUMP_AREA: MemoryArea<PhysicalAddress> =
+    MemoryArea::new(PhysicalAddress::from_const(0x10000), 16 * PAGE_SIZE);
+
 /// The area where the initramfs will be mapped.
 const INITRAMFS_MAP_AREA_START: VirtualAddress =
     VirtualAddress::from_const(0xffff800000000000 + 512 * 512 * 512);
 
 /// The run-time memory area of the initramfs.
-static mut INITRAMFS_AREA: MemoryArea<VirtualAddress> = MemoryArea::const_default();
+static INITRAMFS_AREA: OnceCell<MemoryArea<VirtualAddress>> = OnceCell::new();
 
 extern "C" {
     /// The end of the kernel in its initial mapping.
@@ -73,6 +109,9 @@ extern "C" {
     static DATA_START: PhysicalAddress;
     /// The start of the .bss segment.
     static BSS_START: PhysicalAddress;
+    /// The end of the memory shared with devices at the start of the .bss
+    /// segment.
+    static DMA_END: PhysicalAddress;
     /// The end of the .bss segment.
     static BSS_END: PhysicalAddress;
     /// The address of the temporary map table.
@@ -91,6 +130,10 @@ extern "C" {
     static STACK_BOTTOM: PhysicalAddress;
     /// The top of the initial kernel stack.
     static STACK_TOP: PhysicalAddress;
+    /// The start of the area reserved for allocations during boot.
+    static EARLY_HEAP_START: PhysicalAddress;
+    /// The end of the area reserved for allocations during boot.
+    static EARLY_HEAP_END: PhysicalAddress;
 }
 
 /// The physical address at which the kernel starts.
@@ -100,6 +143,23 @@ pub fn get_kernel_area() -> MemoryArea<PhysicalAddress> {
     MemoryArea::from_start_and_end(start, end)
 }
 
+/// Returns the physical memory area reserved for allocations during boot.
+pub fn get_early_heap_area() -> MemoryArea<PhysicalAddress> {
+    let start = unsafe { EARLY_HEAP_START };
+    let end = unsafe { EARLY_HEAP_END };
+    MemoryArea::from_start_and_end(start, end)
+}
+
+/// Returns the physical memory area reserved for waking up from sleep.
+pub fn get_wakeup_area() -> MemoryArea<PhysicalAddress> {
+    WAKEUP_AREA
+}
+
+/// Returns the physical memory area reserved for crash dumps.
+pub fn get_crash_dump_area() -> MemoryArea<PhysicalAddress> {
+    CRASH_DUMP_AREA
+}
+
 /// Initializes the memory manager.
 pub fn init() {
     assert_has_not_been_called!("The x86_64 memory initialization should only be called once.");
@@ -108,15 +168,22 @@ pub fn init() {
 
     paging::init(physical_initramfs_area);
 
+    // The crash dump is written while panicking, when mapping pages may not
+    // work anymore.
+    map_physical_area(CRASH_DUMP_AREA, ::memory::READABLE | ::memory::WRITABLE);
+
     let start = INITRAMFS_MAP_AREA_START + physical_initramfs_area.start_address().offset_in_page();
-    unsafe {
-        INITRAMFS_AREA = MemoryArea::new(start, physical_initramfs_area.length());
-    }
+    assert!(
+        INITRAMFS_AREA
+            .set(MemoryArea::new(start, physical_initramfs_area.length()))
+            .is_ok(),
+        "The initramfs should only be mapped once."
+    );
 }
 
 /// Returns the start address of the initramfs.
 pub fn get_initramfs_area() -> MemoryArea<VirtualAddress> {
-    unsafe { INITRAMFS_AREA }
+    *INITRAMFS_AREA.expect("The initramfs is only available after memory initialization.")
 }
 
 /// Maps the given page using the given flags.
@@ -124,11 +191,88 @@ pub fn map_page(page_address: VirtualAddress, flags: PageFlags) {
     paging::map_page(page_address, flags);
 }
 
+/// Maps `count` consecutive pages starting at the given page using the given
+/// flags.
+pub fn map_range(first_page_address: VirtualAddress, count: usize, flags: PageFlags) {
+    paging::map_range(first_page_address, count, flags);
+}
+
 /// Maps the given page to the given frame using the given flags.
 pub fn map_page_at(page_address: VirtualAddress, frame_address: PhysicalAddress, flags: PageFlags) {
     paging::map_page_at(page_address, frame_address, flags);
 }
 
+/// Maps the physical area at its kernel address using the given flags.
+///
+/// Pages that are mapped already are left as they are.
+pub fn map_physical_area(area: MemoryArea<PhysicalAddress>, flags: PageFlags) {
+    let mut frame_address = area.start_address().page_align_down();
+
+    while frame_address < area.end_address() {
+        let page_address = frame_address.to_virtual();
+
+        if !get_page_flags(page_address).contains(::memory::PRESENT) {
+            map_page_at(page_address, frame_address, flags);
+        }
+
+        frame_address += PAGE_SIZE;
+    }
+}
+
+/// Returns the raw entries of the current level 4 page table.
+pub fn get_l4_entries() -> [u64; 512] {
+    paging::get_l4_entries()
+}
+
+/// Allocates a zeroed frame and returns its address.
+pub fn allocate_zeroed_frame() -> PhysicalAddress {
+    paging::allocate_zeroed_frame()
+}
+
+/// Zeroes a free frame ahead of time.
+pub fn zero_free_frame() -> bool {
+    paging::zero_free_frame()
+}
+
+/// Calls the function with the words of the frame at the given address.
+pub unsafe fn access_frame<R, F>(frame_address: PhysicalAddress, function: F) -> R
+where
+    F: FnOnce(&mut [u64]) -> R
+{
+    paging::access_frame(frame_address, function)
+}
+
+/// Adds a reference to the frame at the given address.
+pub fn add_frame_reference(frame_address: PhysicalAddress) {
+    paging::add_frame_reference(frame_address);
+}
+
+/// Returns true if the frame at the given address has more than one
+/// reference.
+pub fn is_frame_shared(frame_address: PhysicalAddress) -> bool {
+    paging::is_frame_shared(frame_address)
+}
+
+/// Releases a reference to the frame at the given address.
+///
+/// # Safety
+/// - Make sure that the released reference isn't used anymore.
+pub unsafe fn release_frame(frame_address: PhysicalAddress) {
+    paging::release_frame(frame_address);
+}
+
+/// Gives the copy-on-write page at the given address its own frame.
+///
+/// Returns false if the page isn't a copy-on-write page.
+pub fn resolve_copy_on_write(address: VirtualAddress) -> bool {
+    paging::resolve_copy_on_write(address)
+}
+
+/// Returns the statistics about huge pages.
+pub fn get_huge_page_statistics() -> Statistics {
+    paging::get_huge_page_statistics()
+}
+
 /// Returns the flags of the given page.
 pub fn get_page_flags(page_address: VirtualAddress) -> PageFlags {
     paging::get_page_flags(page_address)
@@ -142,7 +286,25 @@ pub unsafe fn unmap_page(start_address: VirtualAddress) {
     paging::unmap_page(start_address);
 }
 
+/// Unmaps `count` consecutive pages starting at the given page.
+///
+/// # Safety
+/// - Make sure that nothing references those pages anymore.
+pub unsafe fn unmap_range(first_page_address: VirtualAddress, count: usize) {
+    paging::unmap_range(first_page_address, count);
+}
+
+/// Verifies the invariants of the current page table.
+pub fn check_page_tables() {
+    paging::check_current_page_table();
+}
+
 /// Checks if the address is a kernel or a userspace address.
 pub fn is_userspace_address(address: VirtualAddress) -> bool {
     address <= VIRTUAL_LOW_MAX_ADDRESS
 }
+
+/// Checks if the address is canonical, which is required to map it.
+pub fn is_canonical(address: VirtualAddress) -> bool {
+    address <= VIRTUAL_LOW_MAX_ADDRESS || address >= VIRTUAL_HIGH_MIN_ADDRESS
+}
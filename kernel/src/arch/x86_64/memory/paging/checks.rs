@@ -0,0 +1,211 @@
+//! Verifies the invariants of the current page table.
+//!
+//! The checks are meant for debugging, so the first violating entry causes a
+//! panic. Huge pages are only checked at their first address.
+
+use super::super::{get_initramfs_area, is_userspace_address, DOUBLE_FAULT_STACK_AREA_BASE,
+                   DOUBLE_FAULT_STACK_MAX_SIZE, DOUBLE_FAULT_STACK_OFFSET, FINAL_STACK_TOP,
+                   KERNEL_STACK_AREA_BASE, KERNEL_STACK_MAX_SIZE, KERNEL_STACK_OFFSET};
+use super::current_page_table::CURRENT_PAGE_TABLE;
+use super::inactive_page_table::{KERNEL_L4_ENTRIES, KERNEL_L4_INDICES};
+use super::page_table::{Level1, Level2, Level3, Level4, PageTable, ENTRY_NUMBER};
+use super::page_table_entry::*;
+use super::page_table_manager::PageTableManager;
+use arch::{self, Architecture};
+use memory::{Address, VirtualAddress};
+
+/// The index of the level 4 entry that maps inactive page tables.
+const INACTIVE_TABLE_INDEX: usize = 509;
+
+/// The index of the level 4 entry that maps the page tables themselves.
+const RECURSIVE_INDEX: usize = 511;
+
+/// Checks all mapped pages of the current page table.
+pub fn check_current_page_table() {
+    let mut current_page_table = CURRENT_PAGE_TABLE.lock();
+    let l4 = current_page_table.get_l4();
+
+    check_kernel_half(l4);
+
+    for index in 0..ENTRY_NUMBER {
+        if index == INACTIVE_TABLE_INDEX || index == RECURSIVE_INDEX {
+            continue;
+        }
+
+        let flags = l4[index].flags();
+
+        if flags.contains(PRESENT) {
+            let address = address_of(index, 0, 0, 0);
+            let l3 = l4.get_next_level(address).unwrap();
+
+            check_l3(l3, index, effective_flags(permission_flags(), flags));
+        }
+    }
+}
+
+/// Checks that the kernel half of the given level 4 table points to the level 3
+/// tables that all address spaces share.
+pub fn check_kernel_half(l4: &PageTable<Level4>) {
+    for (&index, shared_entry) in KERNEL_L4_INDICES.iter().zip(KERNEL_L4_ENTRIES.iter()) {
+        // The processor may set the accessed flag in each table separately.
+        let ignored_flags = ACCESSED | DIRTY;
+        let entry = &l4[index];
+
+        if entry.points_to() != shared_entry.points_to()
+            || entry.flags() - ignored_flags != shared_entry.flags() - ignored_flags
+        {
+            panic!(
+                "Page table check failed: Level 4 entry {} ({:?}) is not the shared {:?}.",
+                index, entry, shared_entry
+            );
+        }
+    }
+}
+
+/// Checks all mapped pages below the given level 3 table.
+fn check_l3(l3: &PageTable<Level3>, l4_index: usize, parent_flags: PageTableEntryFlags) {
+    for index in 0..ENTRY_NUMBER {
+        let flags = l3[index].flags();
+        let address = address_of(l4_index, index, 0, 0);
+
+        if !flags.contains(PRESENT) {
+            continue;
+        }
+
+        if flags.contains(HUGE_PAGE) {
+            check_page(address, effective_flags(parent_flags, flags));
+        } else {
+            let l2 = l3.get_next_level(address).unwrap();
+
+            check_l2(l2, l4_index, index, effective_flags(parent_flags, flags));
+        }
+    }
+}
+
+/// Checks all mapped pages below the given level 2 table.
+fn check_l2(
+    l2: &PageTable<Level2>,
+    l4_index: usize,
+    l3_index: usize,
+    parent_flags: PageTableEntryFlags
+) {
+    for index in 0..ENTRY_NUMBER {
+        let flags = l2[index].flags();
+        let address = address_of(l4_index, l3_index, index, 0);
+
+        if !flags.contains(PRESENT) {
+            continue;
+        }
+
+        if flags.contains(HUGE_PAGE) {
+            check_page(address, effective_flags(parent_flags, flags));
+        } else {
+            let l1 = l2.get_next_level(address).unwrap();
+
+            check_l1(
+                l1,
+                l4_index,
+                l3_index,
+                index,
+                effective_flags(parent_flags, flags)
+            );
+        }
+    }
+}
+
+/// Checks all mapped pages in the given level 1 table.
+fn check_l1(
+    l1: &PageTable<Level1>,
+    l4_index: usize,
+    l3_index: usize,
+    l2_index: usize,
+    parent_flags: PageTableEntryFlags
+) {
+    for index in 0..ENTRY_NUMBER {
+        let flags = l1[index].flags();
+
+        if flags.contains(PRESENT) {
+            check_page(
+                address_of(l4_index, l3_index, l2_index, index),
+                effective_flags(parent_flags, flags)
+            );
+        }
+    }
+}
+
+/// Checks a single mapped page with the flags it effectively has.
+fn check_page(address: VirtualAddress, flags: PageTableEntryFlags) {
+    let violation = if !is_userspace_address(address) && flags.contains(USER_ACCESSIBLE) {
+        Some("a user accessible kernel page")
+    } else if flags.contains(WRITABLE) && !flags.contains(NO_EXECUTE) {
+        Some("writable and executable")
+    } else if get_initramfs_area().contains(address) && flags.contains(WRITABLE) {
+        Some("a writable initramfs page")
+    } else if is_guard_page(address) {
+        Some("a mapped guard page")
+    } else {
+        None
+    };
+
+    if let Some(violation) = violation {
+        panic!(
+            "Page table check failed: {:?} ({:?}) is {}.",
+            address, flags, violation
+        );
+    }
+}
+
+/// Returns the flags a page has, considering the flags of the entries that
+/// lead to it.
+///
+/// A page is only writable or user accessible if all entries allow it and it
+/// is not executable if any entry forbids it.
+fn effective_flags(
+    parent_flags: PageTableEntryFlags,
+    flags: PageTableEntryFlags
+) -> PageTableEntryFlags {
+    (flags - permission_flags()) | (flags & parent_flags & permission_flags())
+        | (parent_flags & NO_EXECUTE)
+}
+
+/// Returns the flags that an entry must allow for the pages below it.
+fn permission_flags() -> PageTableEntryFlags {
+    WRITABLE | USER_ACCESSIBLE
+}
+
+/// Returns true if the address lies in a guard page below a stack.
+///
+/// User stacks are placed dynamically, so their guard pages are not checked
+/// here.
+fn is_guard_page(address: VirtualAddress) -> bool {
+    // The last kernel stack slot contains the initial kernel stack.
+    let in_guard = |base: VirtualAddress, end: VirtualAddress, offset: usize, max_size: usize| {
+        address >= base && address < end && (address - base) % offset >= max_size
+    };
+
+    in_guard(
+        KERNEL_STACK_AREA_BASE,
+        FINAL_STACK_TOP - KERNEL_STACK_OFFSET,
+        KERNEL_STACK_OFFSET,
+        KERNEL_STACK_MAX_SIZE
+    )
+        || in_guard(
+            DOUBLE_FAULT_STACK_AREA_BASE,
+            DOUBLE_FAULT_STACK_AREA_BASE
+                + DOUBLE_FAULT_STACK_OFFSET * arch::Current::get_cpu_num(),
+            DOUBLE_FAULT_STACK_OFFSET,
+            DOUBLE_FAULT_STACK_MAX_SIZE
+        )
+}
+
+/// Returns the virtual address that the given table indices map.
+fn address_of(l4_index: usize, l3_index: usize, l2_index: usize, l1_index: usize) -> VirtualAddress {
+    let address = l4_index << 39 | l3_index << 30 | l2_index << 21 | l1_index << 12;
+
+    // Addresses in the upper half are sign extended.
+    if l4_index >= ENTRY_NUMBER / 2 {
+        VirtualAddress::from_usize(address | 0xffff000000000000)
+    } else {
+        VirtualAddress::from_usize(address)
+    }
+}
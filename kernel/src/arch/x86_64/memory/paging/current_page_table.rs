@@ -1,16 +1,20 @@
 //! Handles interactions with the current page table.
 
 use super::inactive_page_table::InactivePageTable;
-use super::page_table::{Level1, Level4, PageTable};
+use super::page_table::{Level1, Level4, PageTable, ENTRY_NUMBER};
 use super::page_table_entry::*;
 use super::page_table_manager::PageTableManager;
-use super::{Page, PageFrame};
+use super::PageFrame;
 use core::cell::UnsafeCell;
+use core::cmp::max;
 use core::ops::{Deref, DerefMut};
 use core::ptr;
 use core::ptr::Unique;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use memory::{Address, PhysicalAddress, VirtualAddress};
-use sync::{Mutex, PreemptionState};
+use multitasking::{get_cpu_id, get_cpu_num};
+use sync::time::Timestamp;
+use sync::{LockStats, PreemptionGuard};
 use x86_64::instructions::tlb;
 use x86_64::registers::control_regs;
 
@@ -23,19 +27,26 @@ const L4_TABLE: *mut PageTable<Level4> = 0xfffffffffffff000 as *mut PageTable<Le
 /// The base address for all temporary addresses.
 const TEMPORARY_ADDRESS_BASE: VirtualAddress = VirtualAddress::from_const(0xffffffffffc00000);
 
+/// The statistics of the references to the current page table.
+///
+/// An acquisition counts as contended if another reference existed already.
+static CURRENT_PAGE_TABLE_STATS: LockStats = LockStats::new("CURRENT_PAGE_TABLE");
+
 /// The method to access the current page table.
 pub static CURRENT_PAGE_TABLE: CurrentPageTableLock =
     unsafe { CurrentPageTableLock::new(CurrentPageTable::new()) };
 
-// TODO: Revisit page table locking.
 /// Protects the current page table from being accessed directly.
 ///
 /// This serves to stop the page table from being switched while being accessed.
+/// It does not serialize changes to the mappings: the mappings of an address
+/// space are protected by the lock of that address space and the kernel half
+/// is protected by its own lock.
 pub struct CurrentPageTableLock {
     /// The page table being locked.
     current_page_table: UnsafeCell<CurrentPageTable>,
-    /// The reference count to the table.
-    reference_count: Mutex<usize>
+    /// The number of references to the table.
+    reference_count: AtomicUsize
 }
 
 // This is safe because the page table will manage it's own exclusion
@@ -50,17 +61,18 @@ impl CurrentPageTableLock {
     const unsafe fn new(table: CurrentPageTable) -> CurrentPageTableLock {
         CurrentPageTableLock {
             current_page_table: UnsafeCell::new(table),
-            reference_count: Mutex::new(0)
+            reference_count: AtomicUsize::new(0)
         }
     }
 
     /// Locks the current page table.
     pub fn lock(&self) -> CurrentPageTableReference {
-        let rc: &mut usize = &mut self.reference_count.lock();
-        *rc += 1;
+        let previous_count = self.reference_count.fetch_add(1, Ordering::Acquire);
+        let acquired_at = CURRENT_PAGE_TABLE_STATS.record_acquisition(previous_count > 0);
         CurrentPageTableReference {
             current_page_table: unsafe { &mut *self.current_page_table.get() },
-            reference_count: &self.reference_count
+            reference_count: &self.reference_count,
+            acquired_at
         }
     }
 }
@@ -68,13 +80,17 @@ impl CurrentPageTableLock {
 /// Serves as a reference to a locked current page table.
 pub struct CurrentPageTableReference<'a> {
     current_page_table: &'a mut CurrentPageTable,
-    reference_count: &'a Mutex<usize>
+    reference_count: &'a AtomicUsize,
+    /// The time the reference was created, if lock statistics are collected.
+    acquired_at: Option<Timestamp>
 }
 
 impl<'a> Drop for CurrentPageTableReference<'a> {
     fn drop(&mut self) {
-        let rc: &mut usize = &mut self.reference_count.lock();
-        *rc -= 1;
+        self.reference_count.fetch_sub(1, Ordering::Release);
+        if let Some(acquired_at) = self.acquired_at {
+            CURRENT_PAGE_TABLE_STATS.record_release(acquired_at);
+        }
     }
 }
 
@@ -121,26 +137,29 @@ impl CurrentPageTable {
     ///
     /// # Safety
     /// - Should not be called while another inactive table is mapped.
-    pub unsafe fn map_inactive(&mut self, frame: &PageFrame) -> PreemptionState {
+    pub unsafe fn map_inactive(&mut self, frame: &PageFrame) -> PreemptionGuard {
         let l4 = self.get_l4();
         let entry = &mut l4[509];
-        let preemption_state = entry.lock();
+        let preemption = entry.lock();
         if !entry.flags().contains(PRESENT) {
             entry
                 .set_flags(PRESENT | WRITABLE | NO_EXECUTE)
                 .set_address(frame.get_address());
         }
 
-        preemption_state
+        preemption
     }
 
     /// Unmaps the currently mapped inactive page table.
-    pub fn unmap_inactive(&mut self, preemption_state: &PreemptionState) {
+    ///
+    /// This consumes the guard that `map_inactive` returned.
+    pub fn unmap_inactive(&mut self, preemption: PreemptionGuard) {
         let l4 = self.get_l4();
         let entry = &mut l4[509];
         debug_assert!(entry.flags().contains(PRESENT));
         entry.remove_flags(PRESENT);
-        entry.unlock(&preemption_state);
+        entry.unlock();
+        drop(preemption);
     }
 
     /// Returns a mutable reference to the temporary mapping page table.
@@ -153,32 +172,27 @@ impl CurrentPageTable {
             .expect("Temporary page table not mapped.")
     }
 
-    /// Performs the given action with the mapped page.
-    pub fn with_temporary_page<F, T>(&mut self, frame: &PageFrame, action: F) -> T
-    where
-        F: Fn(&mut Page) -> T
-    {
-        // Map the page.
-        let index = page_frame_hash(frame);
-        let temporary_map_table = self.get_temporary_map_table();
-        let entry = &mut temporary_map_table[index];
-        let preemption_state = entry.lock();
-
-        let virtual_address = TEMPORARY_ADDRESS_BASE + (index << 12);
-
-        if entry.points_to() != Some(frame.get_address()) {
-            tlb::flush(::x86_64::VirtualAddress(virtual_address.as_usize()));
-            entry.set_address(frame.get_address());
-            entry.set_flags(PRESENT | WRITABLE | DISABLE_CACHE | NO_EXECUTE);
+    /// Maps the frame to a temporary address until the returned mapping is
+    /// dropped.
+    ///
+    /// The mapping borrows the current page table, so only one temporary
+    /// mapping exists per reference to it.
+    pub fn map_temporarily(&mut self, frame: &PageFrame) -> TemporaryMapping {
+        // If the thread moves to another CPU before the entry is locked, it
+        // only shares the entries of that CPU for this mapping.
+        let index = temporary_entry_index(frame, get_cpu_id(), get_cpu_num());
+        let address = TEMPORARY_ADDRESS_BASE + (index << 12);
+        let entry = &mut self.get_temporary_map_table()[index];
+        let preemption = entry.lock();
+
+        entry.set_address(frame.get_address());
+        entry.set_flags(PRESENT | WRITABLE | DISABLE_CACHE | WRITE_TROUGH_CACHING | NO_EXECUTE);
+
+        TemporaryMapping {
+            entry,
+            address,
+            _preemption: preemption
         }
-
-        // Perform the action.
-        let result: T = action(&mut Page::from_address(virtual_address));
-
-        // Unlock this entry.
-        entry.unlock(&preemption_state);
-
-        result
     }
 
     /// Writes the given value to the given physical address.
@@ -187,24 +201,20 @@ impl CurrentPageTable {
         physical_address: PhysicalAddress,
         data: T
     ) {
-        self.with_temporary_page(&PageFrame::from_address(physical_address), |page| {
-            let virtual_address =
-                page.get_address().as_usize() | (physical_address.offset_in_page());
+        let mapping = self.map_temporarily(&PageFrame::from_address(physical_address));
+        let virtual_address = mapping.get_address() + physical_address.offset_in_page();
 
-            unsafe {
-                ptr::write(virtual_address as *mut T, data);
-            }
-        });
+        unsafe {
+            ptr::write(virtual_address.as_mut_ptr(), data);
+        }
     }
 
     /// Reads from the given physical address.
     pub fn read_from_physical<T: Sized + Copy>(&mut self, physical_address: PhysicalAddress) -> T {
-        self.with_temporary_page(&PageFrame::from_address(physical_address), |page| {
-            let virtual_address =
-                page.get_address().as_usize() | (physical_address.offset_in_page());
+        let mapping = self.map_temporarily(&PageFrame::from_address(physical_address));
+        let virtual_address = mapping.get_address() + physical_address.offset_in_page();
 
-            unsafe { ptr::read(virtual_address as *mut T) }
-        })
+        unsafe { ptr::read(virtual_address.as_ptr()) }
     }
 
     /// Switches to the new page table returning the current one.
@@ -214,8 +224,6 @@ impl CurrentPageTable {
     pub unsafe fn switch(&mut self, new_table: InactivePageTable) -> InactivePageTable {
         let old_frame =
             PageFrame::from_address(PhysicalAddress::from_usize(control_regs::cr3().0 as usize));
-        let old_table = InactivePageTable::from_frame(old_frame.copy(), &new_table);
-
         let new_frame = new_table.get_frame();
 
         drop(new_table);
@@ -226,19 +234,84 @@ impl CurrentPageTable {
         ));
 
         // Map the now inactive old table.
-        self.map_inactive(&old_frame);
+        let preemption = self.map_inactive(&old_frame);
+
+        InactivePageTable::from_frame(old_frame, preemption)
+    }
+}
+
+/// A page frame that is mapped to a temporary address.
+///
+/// The entry of the temporary map table stays locked while the mapping
+/// exists, which keeps other CPUs from mapping another frame there and
+/// disables preemption. Dropping the mapping unmaps the frame and flushes it
+/// from the TLB, so the temporary address can't be used after the mapping is
+/// gone.
+pub struct TemporaryMapping<'a> {
+    /// The entry of the temporary map table that maps the frame.
+    entry: &'a mut PageTableEntry,
+    /// The temporary address of the frame.
+    address: VirtualAddress,
+    /// Keeps preemption disabled while the entry is locked.
+    ///
+    /// It is dropped after `drop` unlocked the entry.
+    _preemption: PreemptionGuard
+}
 
-        old_table
+impl<'a> TemporaryMapping<'a> {
+    /// Returns the temporary address of the frame.
+    pub fn get_address(&self) -> VirtualAddress {
+        self.address
+    }
+}
+
+impl<'a> Drop for TemporaryMapping<'a> {
+    fn drop(&mut self) {
+        self.entry.remove_flags(PRESENT);
+        tlb::flush(::x86_64::VirtualAddress(self.address.as_usize()));
+        self.entry.unlock();
     }
 }
 
+/// Returns the index of the temporary map table entry that maps the frame on
+/// the given CPU.
+///
+/// Every CPU uses its own range of entries, so CPUs rarely wait for each
+/// other's mappings. Within that range, the frames are spread by their hash.
+fn temporary_entry_index(frame: &PageFrame, cpu_id: usize, cpu_num: usize) -> usize {
+    let entries_per_cpu = max(ENTRY_NUMBER / max(cpu_num, 1), 1);
+    let first_entry = (cpu_id * entries_per_cpu) % ENTRY_NUMBER;
+
+    first_entry + page_frame_hash(frame) % entries_per_cpu
+}
+
 /// Hashes page frames to values from 0 to 511.
 ///
-/// This serves to speed up temporary mapping of page frames,
-/// by better utilizing the available space.
+/// This serves to spread temporary mappings over the available entries.
 fn page_frame_hash(frame: &PageFrame) -> usize {
     // UNOPTIMIZED: Possibly use a better hash algorithm here?
     let mut address = frame.get_address().as_usize() >> 12;
     address *= 101489;
     address % 512
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that every CPU gets its own range of entries.
+    #[test]
+    fn test_temporary_entry_index() {
+        let frame = PageFrame::from_address(PhysicalAddress::from_usize(0x1234000));
+
+        assert_eq!(temporary_entry_index(&frame, 0, 1), page_frame_hash(&frame));
+
+        for cpu_id in 0..4 {
+            let index = temporary_entry_index(&frame, cpu_id, 4);
+
+            assert!(index >= cpu_id * 128 && index < (cpu_id + 1) * 128);
+        }
+
+        assert!(temporary_entry_index(&frame, 1000, 1024) < ENTRY_NUMBER);
+    }
+}
@@ -1,13 +1,77 @@
 //! Handles the allocation of physical page frames.
 
-use super::free_list::{FreeListIterator, FREE_LIST};
-use super::{PageFrame, PAGE_SIZE};
+use super::free_list::{FreeList, FreeListIterator, FREE_LIST};
+use super::page_table::ENTRY_NUMBER;
+use super::{zero_frame, PageFrame, HUGE_PAGE_SIZE, PAGE_SIZE};
+use alloc::btree_map::BTreeMap;
 use core::cell::Cell;
-use memory::{oom, MemoryArea};
+use memory::numa;
+use memory::tracking::{self, Kind};
+use memory::{oom, Address, MemoryArea, PhysicalAddress};
+use sync::Mutex;
+
+/// The number of frames that the idle threads zero ahead of time.
+const ZEROED_POOL_SIZE: usize = 256;
+
+/// Free frames that were already filled with zeros.
+struct ZeroedPool {
+    /// The addresses of the zeroed frames.
+    frames: [PhysicalAddress; ZEROED_POOL_SIZE],
+    /// The number of zeroed frames.
+    len: usize
+}
+
+impl ZeroedPool {
+    /// Creates an empty pool.
+    fn new() -> ZeroedPool {
+        ZeroedPool {
+            frames: [PhysicalAddress::from_usize(0); ZEROED_POOL_SIZE],
+            len: 0
+        }
+    }
+
+    /// Returns true if no more frames fit into the pool.
+    fn is_full(&self) -> bool {
+        self.len == ZEROED_POOL_SIZE
+    }
+
+    /// Adds the zeroed frame to the pool.
+    ///
+    /// Returns the frame again if the pool is full.
+    fn push(&mut self, frame: PageFrame) -> Result<(), PageFrame> {
+        if self.is_full() {
+            Err(frame)
+        } else {
+            self.frames[self.len] = frame.get_address();
+            self.len += 1;
+
+            Ok(())
+        }
+    }
+
+    /// Takes a zeroed frame out of the pool.
+    fn pop(&mut self) -> Option<PageFrame> {
+        if self.len == 0 {
+            None
+        } else {
+            self.len -= 1;
+
+            Some(PageFrame::from_address(self.frames[self.len]))
+        }
+    }
+}
 
 /// Used to allocate page frames.
 pub struct FrameAllocator {
-    free_frames: Cell<usize>
+    free_frames: Cell<usize>,
+    /// The frames that were zeroed ahead of time.
+    ///
+    /// They are counted as allocated by the free list.
+    zeroed_frames: Mutex<ZeroedPool>,
+    /// The number of additional references to frames mapped more than once.
+    ///
+    /// Frames with a single reference don't have an entry.
+    extra_references: Mutex<BTreeMap<PhysicalAddress, usize>>
 }
 
 // It is save to implement sync, because access is restricted by the lock on
@@ -26,39 +90,215 @@ lazy_static! {
             }
 
             Cell::new(number)
-        }
+        },
+        zeroed_frames: Mutex::new(ZeroedPool::new()),
+        extra_references: Mutex::new(BTreeMap::new())
     };
 }
 
 impl FrameAllocator {
     /// Allocates a page frame.
+    ///
+    /// Frames on the NUMA node of the current CPU are preferred. The frames
+    /// reserved for reclaiming memory and the frames that were zeroed ahead of
+    /// time are only used once all others are gone.
     pub fn allocate(&self) -> PageFrame {
+        loop {
+            if let Some(frame) = self.allocate_local() {
+                return frame;
+            }
+
+            let free_frames = match self.allocate_free() {
+                Ok(frame) => return frame,
+                Err(free_frames) => free_frames
+            };
+
+            if let Some(frame) = self.zeroed_frames.lock().pop() {
+                return frame;
+            }
+
+            oom::out_of_frames(free_frames);
+        }
+    }
+
+    /// Allocates a page frame that is filled with zeros.
+    ///
+    /// Frames that were zeroed ahead of time are preferred.
+    pub fn allocate_zeroed(&self) -> PageFrame {
+        if let Some(frame) = self.zeroed_frames.lock().pop() {
+            return frame;
+        }
+
+        let frame = self.allocate();
+        zero_frame(&frame);
+
+        frame
+    }
+
+    /// Zeroes a free frame ahead of time, so that `allocate_zeroed` doesn't
+    /// have to.
+    ///
+    /// Returns false if enough frames are zeroed already or only the frames
+    /// reserved for reclaiming memory are left.
+    pub fn zero_free_frame(&self) -> bool {
+        if self.zeroed_frames.lock().is_full() {
+            return false;
+        }
+
+        let frame = match self.allocate_free() {
+            Ok(frame) => frame,
+            Err(_) => return false
+        };
+
+        zero_frame(&frame);
+
+        // Another CPU may have filled the pool in the meantime.
+        let result = self.zeroed_frames.lock().push(frame);
+
+        match result {
+            Ok(()) => true,
+            Err(frame) => {
+                unsafe {
+                    self.deallocate(frame);
+                }
+
+                false
+            }
+        }
+    }
+
+    /// Allocates the first frame of the free list.
+    ///
+    /// Returns the number of free frames instead if only the frames reserved
+    /// for reclaiming memory are left.
+    fn allocate_free(&self) -> Result<PageFrame, usize> {
         // NOTE: The lock on the list also locks the allocator, should the inner
         // workings of the allocator be changed, then there will also need to be a
         // locking mechanism.
         let list = FREE_LIST.lock();
+        let free_frames = self.free_frames.get();
+
+        if free_frames <= oom::reserved_frames() {
+            return Err(free_frames);
+        }
+
+        let mut iterator = FreeListIterator::from_guard(list);
+
+        let free_area = iterator
+            .next()
+            .expect("The free list is empty, but frames are free.");
+        let mut list = iterator.finish();
+
+        let page_frame = PageFrame::from_address(free_area.start_address());
+
+        let new_free_area = free_area.without_first_frame();
+
+        list.remove(free_area);
+        unsafe {
+            if new_free_area.length() > 0 {
+                list.insert(new_free_area);
+            }
+        }
+        self.free_frames.set(free_frames - 1);
+
+        tracking::record_allocation(Kind::Frame, page_frame.get_address().as_usize(), PAGE_SIZE);
+
+        Ok(page_frame)
+    }
+
+    /// Allocates the consecutive page frames of a huge page.
+    ///
+    /// Returns `None` if no free area contains enough aligned frames or only
+    /// the frames reserved for reclaiming memory are left. The frames are
+    /// deallocated one by one, like any other frame.
+    pub fn allocate_huge(&self) -> Option<PageFrame> {
+        let list = FREE_LIST.lock();
+        if self.free_frames.get() < ENTRY_NUMBER + oom::reserved_frames() {
+            return None;
+        }
+
+        let mut iterator = FreeListIterator::from_guard(list);
+
+        let mut found = None;
+        for free_area in &mut iterator {
+            if let Some(start) = free_area.start_address().align_up(HUGE_PAGE_SIZE) {
+                if start + HUGE_PAGE_SIZE <= free_area.end_address() {
+                    found = Some((free_area, start));
+                    break;
+                }
+            }
+        }
+
+        let mut list = iterator.finish();
+        let (free_area, start) = found?;
+
+        self.take_frames(&mut list, free_area, start, ENTRY_NUMBER);
+
+        Some(PageFrame::from_address(start))
+    }
+
+    /// Allocates a page frame on the NUMA node of the current CPU.
+    ///
+    /// Returns `None` if the node has no free frames, the machine has a single
+    /// node or only the frames reserved for reclaiming memory are left.
+    fn allocate_local(&self) -> Option<PageFrame> {
+        let topology = numa::get_topology()?;
+        if topology.nodes().len() < 2 {
+            return None;
+        }
+
+        let node = &topology.nodes()[numa::current_node()];
+
+        let list = FREE_LIST.lock();
+        if self.free_frames.get() <= oom::reserved_frames() {
+            return None;
+        }
+
         let mut iterator = FreeListIterator::from_guard(list);
 
-        let free_area = iterator.next();
+        let mut found = None;
+        for free_area in &mut iterator {
+            if let Some(start) = node.first_frame_in(free_area) {
+                found = Some((free_area, start));
+                break;
+            }
+        }
+
         let mut list = iterator.finish();
+        let (free_area, start) = found?;
 
-        if !free_area.is_none() {
-            let free_area = free_area.unwrap();
-            let page_frame = PageFrame::from_address(free_area.start_address());
+        self.take_frames(&mut list, free_area, start, 1);
 
-            let new_free_area = free_area.without_first_frame();
+        Some(PageFrame::from_address(start))
+    }
+
+    /// Takes `count` frames starting at `start` out of the free area.
+    ///
+    /// The rest of the area stays in the free list.
+    fn take_frames(
+        &self,
+        list: &mut FreeList,
+        free_area: MemoryArea<PhysicalAddress>,
+        start: PhysicalAddress,
+        count: usize
+    ) {
+        let end = start + count * PAGE_SIZE;
+
+        list.remove(free_area);
+        unsafe {
+            let before = MemoryArea::from_start_and_end(free_area.start_address(), start);
+            let after = MemoryArea::from_start_and_end(end, free_area.end_address());
 
-            list.remove(free_area);
-            unsafe {
-                if new_free_area.length() > 0 {
-                    list.insert(new_free_area);
+            for remaining_area in [before, after].iter() {
+                if remaining_area.length() > 0 {
+                    list.insert(*remaining_area);
                 }
             }
-            self.free_frames.set(self.free_frames.get() - 1);
+        }
+        self.free_frames.set(self.free_frames.get() - count);
 
-            page_frame
-        } else {
-            oom();
+        for i in 0..count {
+            tracking::record_allocation(Kind::Frame, start.as_usize() + i * PAGE_SIZE, PAGE_SIZE);
         }
     }
 
@@ -70,13 +310,62 @@ impl FrameAllocator {
         // NOTE: The lock on the list also locks the allocator, should the inner
         // workings of the allocator be changed, then there will also need to be a
         // locking mechanism.
+        tracking::record_deallocation(Kind::Frame, frame.get_address().as_usize());
+
         let mut list = FREE_LIST.lock();
         self.free_frames.set(self.free_frames.get() + 1);
         list.insert(MemoryArea::new(frame.get_address(), PAGE_SIZE));
     }
 
-    /// Returns the current number of free frames.
+    /// Adds a reference to the page frame.
+    ///
+    /// The frame is then only deallocated after it was released once more.
+    pub fn add_reference(&self, frame: &PageFrame) {
+        *self
+            .extra_references
+            .lock()
+            .entry(frame.get_address())
+            .or_insert(0) += 1;
+    }
+
+    /// Releases a reference to the page frame and deallocates it, if it was
+    /// the last one.
+    ///
+    /// # Safety
+    /// - The released reference must not be used anymore.
+    pub unsafe fn release(&self, frame: PageFrame) {
+        let is_last_reference = {
+            let mut extra_references = self.extra_references.lock();
+
+            let remaining = extra_references.get_mut(&frame.get_address()).map(|count| {
+                *count -= 1;
+                *count
+            });
+
+            match remaining {
+                Some(0) => {
+                    extra_references.remove(&frame.get_address());
+                    false
+                },
+                Some(_) => false,
+                None => true
+            }
+        };
+
+        if is_last_reference {
+            self.deallocate(frame);
+        }
+    }
+
+    /// Returns true if the page frame has more than one reference.
+    pub fn is_shared(&self, frame: &PageFrame) -> bool {
+        self.extra_references
+            .lock()
+            .contains_key(&frame.get_address())
+    }
+
+    /// Returns the current number of free frames, including the zeroed ones.
     pub fn get_free_frame_num(&self) -> usize {
-        self.free_frames.get()
+        self.free_frames.get() + self.zeroed_frames.lock().len
     }
 }
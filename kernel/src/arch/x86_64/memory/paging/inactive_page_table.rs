@@ -8,22 +8,62 @@ use super::page_table_entry::*;
 use super::page_table_manager::PageTableManager;
 use super::PageFrame;
 use core::ptr::Unique;
-use memory::{Address, PhysicalAddress};
-use sync::PreemptionState;
+use memory::{Address, PhysicalAddress, VirtualAddress};
+use sync::PreemptionGuard;
 use x86_64::registers::control_regs::cr3;
 
 /// The reference to the place where the level 4 table will be mapped.
 const L4_TABLE: *mut PageTable<Level4> = 0xffffffffffffd000 as *mut PageTable<Level4>;
 
+/// The indices of the level 4 entries that map the kernel.
+pub const KERNEL_L4_INDICES: [usize; 4] = [256, 257, 506, 507];
+
+lazy_static! {
+    /// The level 4 entries that are the same in every address space.
+    ///
+    /// They point to level 3 tables shared by all address spaces, so they
+    /// are only read from the current table once. Missing level 3 tables are
+    /// created first, because a table created later would only be part of
+    /// one address space.
+    pub static ref KERNEL_L4_ENTRIES: [PageTableEntry; 4] = {
+        let mut current_page_table = CURRENT_PAGE_TABLE.lock();
+        let l4 = current_page_table.get_l4();
+        let mut entries = [
+            PageTableEntry::new(),
+            PageTableEntry::new(),
+            PageTableEntry::new(),
+            PageTableEntry::new()
+        ];
+
+        for (entry, &index) in entries.iter_mut().zip(KERNEL_L4_INDICES.iter()) {
+            let address = VirtualAddress::from_usize(0xffff000000000000 | index << 39);
+
+            l4.next_level_and_map(address);
+            *entry = l4[index].clone();
+        }
+
+        entries
+    };
+}
+
+/// Makes the kernel half of the current page table the one that all address
+/// spaces share.
+///
+/// This should be called once the kernel is remapped, before the first
+/// address space is created.
+pub fn share_kernel_half() {
+    let _kernel_entries = &*KERNEL_L4_ENTRIES;
+}
+
 /// Represents a currently inactive page table that needs to be modified.
 pub struct InactivePageTable {
     /// A reference to the level 4 table.
     l4_table: Unique<PageTable<Level4>>,
     /// The page frame of the level 4 table.
     l4_frame: PageFrame,
-    /// Optionally contains the preemption state of the mapped entry in the
-    /// current page table.
-    preemption_state: Option<PreemptionState>
+    /// The guard of the entry in the current page table that maps this table,
+    /// while it is mapped.
+    preemption: Option<PreemptionGuard>
 }
 
 impl PageTableManager for InactivePageTable {
@@ -37,10 +77,7 @@ impl PageTableManager for InactivePageTable {
 
 impl Drop for InactivePageTable {
     fn drop(&mut self) {
-        match self.preemption_state {
-            Some(_) => self.unmap(),
-            None => ()
-        }
+        self.unmap();
     }
 }
 
@@ -51,7 +88,7 @@ impl InactivePageTable {
     /// - Should only be called during kernel setup.
     pub unsafe fn new() -> InactivePageTable {
         let frame = FRAME_ALLOCATOR.allocate();
-        let preemption_state = CURRENT_PAGE_TABLE.lock().map_inactive(&frame);
+        let preemption = CURRENT_PAGE_TABLE.lock().map_inactive(&frame);
 
         // Zero the page.
         let table = &mut *L4_TABLE;
@@ -68,23 +105,24 @@ impl InactivePageTable {
         InactivePageTable {
             l4_table: Unique::new_unchecked(L4_TABLE),
             l4_frame: frame,
-            preemption_state: Some(preemption_state)
+            preemption: Some(preemption)
         }
     }
 
     /// Creates a copy of the current page table kernel part as an inactive
     /// page table.
     pub fn copy_from_current() -> InactivePageTable {
+        let kernel_entries = &*KERNEL_L4_ENTRIES;
+
         let frame = FRAME_ALLOCATOR.allocate();
-        let preemption_state = unsafe { CURRENT_PAGE_TABLE.lock().map_inactive(&frame) };
+        let preemption = unsafe { CURRENT_PAGE_TABLE.lock().map_inactive(&frame) };
 
         let table = unsafe { &mut *L4_TABLE };
         table.zero();
 
-        table[256] = CURRENT_PAGE_TABLE.lock().get_l4()[256].clone();
-        table[257] = CURRENT_PAGE_TABLE.lock().get_l4()[257].clone();
-        table[506] = CURRENT_PAGE_TABLE.lock().get_l4()[506].clone();
-        table[507] = CURRENT_PAGE_TABLE.lock().get_l4()[507].clone();
+        for (&index, entry) in KERNEL_L4_INDICES.iter().zip(kernel_entries.iter()) {
+            table[index] = entry.clone();
+        }
 
         unsafe {
             table[510]
@@ -95,30 +133,24 @@ impl InactivePageTable {
             .set_address(frame.get_address())
             .set_flags(PRESENT | WRITABLE | NO_EXECUTE);
 
-        CURRENT_PAGE_TABLE.lock().unmap_inactive(&preemption_state);
+        CURRENT_PAGE_TABLE.lock().unmap_inactive(preemption);
 
         InactivePageTable {
             l4_table: unsafe { Unique::new_unchecked(L4_TABLE) },
             l4_frame: frame,
-            preemption_state: None
+            preemption: None
         }
     }
 
     /// Creates an inactive page table at the given address.
     ///
-    /// The old_table parameter points to a table containing the preemption
-    /// state.
-    pub fn from_frame(frame: PageFrame, old_table: &InactivePageTable) -> InactivePageTable {
+    /// The table must already be mapped and the preemption parameter is the
+    /// guard that was returned when mapping it.
+    pub fn from_frame(frame: PageFrame, preemption: PreemptionGuard) -> InactivePageTable {
         InactivePageTable {
             l4_table: unsafe { Unique::new_unchecked(L4_TABLE) },
             l4_frame: frame,
-            preemption_state: Some(unsafe {
-                old_table
-                    .preemption_state
-                    .as_ref()
-                    .expect("The old table was not mapped.")
-                    .copy()
-            })
+            preemption: Some(preemption)
         }
     }
 
@@ -127,7 +159,7 @@ impl InactivePageTable {
         InactivePageTable {
             l4_table: unsafe { Unique::new_unchecked(L4_TABLE) },
             l4_frame: PageFrame::from_address(PhysicalAddress::from_usize(cr3().0 as usize)),
-            preemption_state: None
+            preemption: None
         }
     }
 
@@ -136,9 +168,9 @@ impl InactivePageTable {
     /// # Safety
     /// - Ensure that it is properly unmapped every time it's mapped.
     unsafe fn map(&mut self) {
-        if self.preemption_state.is_none() {
-            let preemption_state = CURRENT_PAGE_TABLE.lock().map_inactive(&self.l4_frame);
-            self.preemption_state = Some(preemption_state);
+        if self.preemption.is_none() {
+            let preemption = CURRENT_PAGE_TABLE.lock().map_inactive(&self.l4_frame);
+            self.preemption = Some(preemption);
         }
     }
 
@@ -150,11 +182,8 @@ impl InactivePageTable {
     /// Unmaps the currently loaded inactive page table.
     pub fn unmap(&mut self) {
         // TODO: Find something better than unmapping manually after every use.
-        if !self.preemption_state.is_none() {
-            CURRENT_PAGE_TABLE
-                .lock()
-                .unmap_inactive(self.preemption_state.as_ref().unwrap());
-            self.preemption_state = None;
+        if let Some(preemption) = self.preemption.take() {
+            CURRENT_PAGE_TABLE.lock().unmap_inactive(preemption);
         }
     }
 }
@@ -1,4 +1,5 @@
 //! Deals with the page tables.
+mod checks;
 mod current_page_table;
 mod frame_allocator;
 mod free_list;
@@ -7,14 +8,48 @@ mod page_table;
 pub mod page_table_entry;
 pub mod page_table_manager;
 
+pub use self::checks::{check_current_page_table, check_kernel_half};
 pub use self::current_page_table::CURRENT_PAGE_TABLE;
 use self::frame_allocator::FRAME_ALLOCATOR;
+use self::page_table::ENTRY_NUMBER;
 use self::page_table_entry::*;
 use self::page_table_manager::PageTableManager;
 use super::*;
 use core::fmt;
+use core::mem::size_of;
+use core::ptr;
+use core::slice;
+use x86_64::instructions::tlb;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use memory;
+use memory::early_heap;
+use memory::huge_pages::Statistics;
 use memory::{Address, PageFlags, PhysicalAddress, VirtualAddress};
+use sync::{LockStats, Mutex};
+
+/// The size of a huge page, which a level 2 entry maps directly.
+pub const HUGE_PAGE_SIZE: usize = PAGE_SIZE * ENTRY_NUMBER;
+
+/// The number of huge pages that are currently mapped.
+static MAPPED_HUGE_PAGES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The number of huge pages that were split into normal pages.
+static SPLIT_HUGE_PAGES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The number of huge pages that couldn't be allocated.
+static FAILED_HUGE_PAGES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The contention statistics of the kernel page table lock.
+static KERNEL_PAGE_TABLE_STATS: LockStats = LockStats::new("KERNEL_PAGE_TABLE");
+
+lazy_static! {
+    /// Serializes changes to the mappings of the kernel half.
+    ///
+    /// The kernel half is shared by all address spaces, so it can't be
+    /// protected by the lock of a single address space.
+    static ref KERNEL_PAGE_TABLE_LOCK: Mutex<()> =
+        Mutex::with_stats((), &KERNEL_PAGE_TABLE_STATS);
+}
 
 /// Initializes the paging.
 pub fn init(initramfs_area: MemoryArea<PhysicalAddress>) {
@@ -26,6 +61,9 @@ pub fn init(initramfs_area: MemoryArea<PhysicalAddress>) {
     debug!("Remapping the kernel...");
     unsafe { remap_kernel() };
 
+    debug!("Sharing the kernel half of the page table...");
+    inactive_page_table::share_kernel_half();
+
     debug!("Mapping the initramfs...");
     unsafe { map_initramfs(initramfs_area) };
 }
@@ -42,8 +80,13 @@ pub fn convert_flags(flags: PageFlags) -> PageTableEntryFlags {
         entry_flags |= NO_EXECUTE;
     }
 
+    // The memory types are selected through the PAT, see the `pat` module.
     if flags.contains(memory::NO_CACHE) {
+        entry_flags |= DISABLE_CACHE | WRITE_TROUGH_CACHING;
+    } else if flags.contains(memory::WRITE_COMBINING) {
         entry_flags |= DISABLE_CACHE;
+    } else if flags.contains(memory::WRITE_THROUGH) {
+        entry_flags |= WRITE_TROUGH_CACHING;
     }
 
     if flags.contains(memory::USER_ACCESSIBLE) {
@@ -57,10 +100,16 @@ pub fn convert_flags(flags: PageFlags) -> PageTableEntryFlags {
 pub fn get_page_flags(page_address: VirtualAddress) -> PageFlags {
     let mut flags = PageFlags::empty();
     let mut table = CURRENT_PAGE_TABLE.lock();
+    let page_address = Page::from_address(page_address).get_address();
 
-    if let Some(entry) = table.get_entry(Page::from_address(page_address).get_address()) {
-        let entry_flags = entry.flags();
+    // Looking at a huge page must not split it.
+    let huge_page_flags = table
+        .get_huge_page_entry(page_address)
+        .map(|entry| entry.flags());
+    let entry_flags =
+        huge_page_flags.or_else(|| table.get_entry(page_address).map(|entry| entry.flags()));
 
+    if let Some(entry_flags) = entry_flags {
         if entry_flags.contains(PRESENT) {
             flags |= ::memory::PRESENT;
         }
@@ -73,8 +122,12 @@ pub fn get_page_flags(page_address: VirtualAddress) -> PageFlags {
             flags |= memory::EXECUTABLE;
         }
 
-        if entry_flags.contains(DISABLE_CACHE) {
+        if entry_flags.contains(DISABLE_CACHE | WRITE_TROUGH_CACHING) {
             flags |= memory::NO_CACHE;
+        } else if entry_flags.contains(DISABLE_CACHE) {
+            flags |= memory::WRITE_COMBINING;
+        } else if entry_flags.contains(WRITE_TROUGH_CACHING) {
+            flags |= memory::WRITE_THROUGH;
         }
 
         if entry_flags.contains(USER_ACCESSIBLE) {
@@ -85,6 +138,15 @@ pub fn get_page_flags(page_address: VirtualAddress) -> PageFlags {
     flags
 }
 
+/// Returns the statistics about huge pages.
+pub fn get_huge_page_statistics() -> Statistics {
+    Statistics {
+        mapped: MAPPED_HUGE_PAGES.load(Ordering::Relaxed),
+        split: SPLIT_HUGE_PAGES.load(Ordering::Relaxed),
+        failed: FAILED_HUGE_PAGES.load(Ordering::Relaxed)
+    }
+}
+
 /// Returns the size of unused physical memory.
 pub fn get_free_memory_size() -> usize {
     FRAME_ALLOCATOR.get_free_frame_num() * PAGE_SIZE
@@ -92,18 +154,158 @@ pub fn get_free_memory_size() -> usize {
 
 /// Maps the given page to the given frame using the given flags.
 pub fn map_page_at(page_address: VirtualAddress, frame_address: PhysicalAddress, flags: PageFlags) {
-    CURRENT_PAGE_TABLE.lock().map_page_at(
-        Page::from_address(page_address),
-        PageFrame::from_address(frame_address),
-        convert_flags(flags)
+    let _kernel_page_table = KERNEL_PAGE_TABLE_LOCK.lock();
+
+    CURRENT_PAGE_TABLE
+        .lock()
+        .map_page_at(
+            Page::from_address(page_address),
+            PageFrame::from_address(frame_address),
+            convert_flags(flags)
+        )
+        .expect("Invalid page mapping in the current page table.");
+}
+
+/// Returns the raw entries of the current level 4 table.
+pub fn get_l4_entries() -> [u64; ENTRY_NUMBER] {
+    let mut entries = [0; ENTRY_NUMBER];
+    let mut table = CURRENT_PAGE_TABLE.lock();
+    let l4 = table.get_l4();
+
+    for (index, entry) in entries.iter_mut().enumerate() {
+        *entry = l4[index].raw();
+    }
+
+    entries
+}
+
+/// Allocates a frame and fills it with zeros.
+pub fn allocate_zeroed_frame() -> PhysicalAddress {
+    FRAME_ALLOCATOR.allocate_zeroed().get_address()
+}
+
+/// Zeroes a free frame ahead of time for later zeroed allocations.
+///
+/// Returns false if there is nothing left to zero.
+pub fn zero_free_frame() -> bool {
+    FRAME_ALLOCATOR.zero_free_frame()
+}
+
+/// Calls the function with the words of the frame at the given address.
+///
+/// The frame is mapped temporarily while the function runs.
+pub unsafe fn access_frame<R, F>(frame_address: PhysicalAddress, function: F) -> R
+where
+    F: FnOnce(&mut [u64]) -> R
+{
+    let mut current_page_table = CURRENT_PAGE_TABLE.lock();
+    let mapping = current_page_table.map_temporarily(&PageFrame::from_address(frame_address));
+    let words = slice::from_raw_parts_mut(
+        mapping.get_address().as_mut_ptr(),
+        PAGE_SIZE / size_of::<u64>()
     );
+
+    function(words)
+}
+
+/// Fills the frame with zeros.
+fn zero_frame(frame: &PageFrame) {
+    let mut current_page_table = CURRENT_PAGE_TABLE.lock();
+    let mapping = current_page_table.map_temporarily(frame);
+
+    unsafe {
+        ptr::write_bytes(mapping.get_address().as_mut_ptr::<u8>(), 0, PAGE_SIZE);
+    }
+}
+
+/// Adds a reference to the given frame.
+pub fn add_frame_reference(frame_address: PhysicalAddress) {
+    FRAME_ALLOCATOR.add_reference(&PageFrame::from_address(frame_address));
+}
+
+/// Returns true if the given frame has more than one reference.
+pub fn is_frame_shared(frame_address: PhysicalAddress) -> bool {
+    FRAME_ALLOCATOR.is_shared(&PageFrame::from_address(frame_address))
+}
+
+/// Releases a reference to the given frame.
+///
+/// # Safety
+/// - The released reference must not be used anymore.
+pub unsafe fn release_frame(frame_address: PhysicalAddress) {
+    FRAME_ALLOCATOR.release(PageFrame::from_address(frame_address));
+}
+
+/// Gives the page that contains the given address its own writable frame,
+/// if it is a copy-on-write page of the current address space.
+///
+/// The frame is only copied if other address spaces still use it. Returns
+/// false if the page isn't a copy-on-write page.
+pub fn resolve_copy_on_write(address: VirtualAddress) -> bool {
+    let page_address = Page::from_address(address).get_address();
+    let mut table = CURRENT_PAGE_TABLE.lock();
+
+    // Huge pages are split before they are shared.
+    if table.get_huge_page_entry(page_address).is_some() {
+        return false;
+    }
+
+    let (flags, old_frame) = {
+        let entry = match table.get_entry(page_address) {
+            Some(entry) => entry,
+            None => return false
+        };
+        let flags = entry.flags();
+
+        if !flags.contains(PRESENT) || !flags.contains(COPY_ON_WRITE) {
+            // Another thread may have resolved the fault already.
+            return flags.contains(PRESENT) && flags.contains(WRITABLE);
+        }
+
+        (flags, PageFrame::from_address(entry.points_to().unwrap()))
+    };
+
+    let new_frame = if FRAME_ALLOCATOR.is_shared(&old_frame) {
+        let new_frame = FRAME_ALLOCATOR.allocate();
+        let mapping = table.map_temporarily(&new_frame);
+
+        unsafe {
+            ptr::copy_nonoverlapping(
+                page_address.as_ptr::<u8>(),
+                mapping.get_address().as_mut_ptr::<u8>(),
+                PAGE_SIZE
+            );
+        }
+
+        Some(new_frame)
+    } else {
+        None
+    };
+
+    let mut entry = table
+        .get_entry(page_address)
+        .expect("The copy-on-write page was unmapped.");
+
+    if let Some(new_frame) = new_frame {
+        entry.set_address(new_frame.get_address());
+        unsafe { FRAME_ALLOCATOR.release(old_frame) };
+    }
+
+    // TODO: Consider multiple CPUs.
+    entry.set_flags((flags - COPY_ON_WRITE) | WRITABLE);
+    tlb::flush(::x86_64::VirtualAddress(page_address.as_usize()));
+
+    true
 }
 
 /// Maps the given page using the given flags.
 pub fn map_page(page_address: VirtualAddress, flags: PageFlags) {
+    let _kernel_page_table = KERNEL_PAGE_TABLE_LOCK.lock();
+
     CURRENT_PAGE_TABLE
         .lock()
-        .map_page(Page::from_address(page_address), convert_flags(flags));
+        .map_page(Page::from_address(page_address), convert_flags(flags))
+        .expect("Invalid page mapping in the current page table.");
 }
 
 /// Unmaps the given page.
@@ -111,11 +313,41 @@ pub fn map_page(page_address: VirtualAddress, flags: PageFlags) {
 /// # Safety
 /// - Make sure this page isn't referenced anymore when unmapping it.
 pub unsafe fn unmap_page(start_address: VirtualAddress) {
+    let _kernel_page_table = KERNEL_PAGE_TABLE_LOCK.lock();
+
     CURRENT_PAGE_TABLE
         .lock()
         .unmap_page(Page::from_address(start_address));
 }
 
+/// Maps `count` consecutive pages starting at the given page using the given
+/// flags.
+pub fn map_range(first_page_address: VirtualAddress, count: usize, flags: PageFlags) {
+    let _kernel_page_table = KERNEL_PAGE_TABLE_LOCK.lock();
+
+    CURRENT_PAGE_TABLE
+        .lock()
+        .map_range(
+            Page::from_address(first_page_address),
+            count,
+            convert_flags(flags),
+            false
+        )
+        .expect("Invalid page mapping in the current page table.");
+}
+
+/// Unmaps `count` consecutive pages starting at the given page.
+///
+/// # Safety
+/// - Make sure the pages aren't referenced anymore when unmapping them.
+pub unsafe fn unmap_range(first_page_address: VirtualAddress, count: usize) {
+    let _kernel_page_table = KERNEL_PAGE_TABLE_LOCK.lock();
+
+    CURRENT_PAGE_TABLE
+        .lock()
+        .unmap_range(Page::from_address(first_page_address), count);
+}
+
 /// Maps the initramfs into the kernel.
 ///
 /// # Safety
@@ -149,11 +381,13 @@ unsafe fn remap_kernel() {
         let mut map_section = |size: usize, start: PhysicalAddress, flags: PageTableEntryFlags| {
             for i in 0..size / PAGE_SIZE {
                 let address = start + i * PAGE_SIZE;
-                new_page_table.map_page_at(
-                    Page::from_address(address.to_virtual()),
-                    PageFrame::from_address(address),
-                    flags
-                );
+                new_page_table
+                    .map_page_at(
+                        Page::from_address(address.to_virtual()),
+                        PageFrame::from_address(address),
+                        flags
+                    )
+                    .expect("The kernel sections must be mappable.");
             }
         };
 
@@ -170,32 +404,52 @@ unsafe fn remap_kernel() {
             WRITABLE | GLOBAL | NO_EXECUTE
         );
 
-        // Map the bss section
+        // Map the memory shared with devices, which starts the bss section.
         map_section(
-            BSS_END - BSS_START,
+            DMA_END - BSS_START,
             BSS_START,
+            WRITABLE | GLOBAL | NO_EXECUTE | WRITE_TROUGH_CACHING
+        );
+
+        // Map the rest of the bss section
+        map_section(
+            BSS_END - DMA_END,
+            DMA_END,
+            WRITABLE | GLOBAL | NO_EXECUTE
+        );
+
+        // Map the used part of the early heap.
+        let early_heap_area = early_heap::get_used_area();
+        map_section(
+            early_heap_area.length(),
+            early_heap_area.start_address(),
             WRITABLE | GLOBAL | NO_EXECUTE
         );
     }
 
-    // Map the VGA buffer.
+    // Map the VGA buffer write-combining. It is never read, the writer keeps
+    // a copy of the screen.
     // TODO: Allow for a different address to be used here.
-    new_page_table.map_page_at(
-        Page::from_address(VirtualAddress::from_usize(to_virtual!(0xb8000))),
-        PageFrame::from_address(PhysicalAddress::from_usize(0xb8000)),
-        WRITABLE | GLOBAL | NO_EXECUTE
-    );
+    new_page_table
+        .map_page_at(
+            Page::from_address(VirtualAddress::from_usize(to_virtual!(0xb8000))),
+            PageFrame::from_address(PhysicalAddress::from_usize(0xb8000)),
+            WRITABLE | GLOBAL | NO_EXECUTE | DISABLE_CACHE
+        )
+        .expect("The VGA buffer must be mappable.");
 
     // Map the stack pages.
     let stack_size = STACK_TOP - STACK_BOTTOM;
     for i in 0..stack_size / PAGE_SIZE {
         let physical_address = STACK_BOTTOM + i * PAGE_SIZE;
         let virtual_address = FINAL_STACK_TOP - stack_size + i * PAGE_SIZE;
-        new_page_table.map_page_at(
-            Page::from_address(virtual_address),
-            PageFrame::from_address(physical_address),
-            WRITABLE | GLOBAL | NO_EXECUTE
-        );
+        new_page_table
+            .map_page_at(
+                Page::from_address(virtual_address),
+                PageFrame::from_address(physical_address),
+                WRITABLE | GLOBAL | NO_EXECUTE
+            )
+            .expect("The kernel stack must be mappable.");
     }
 
     CURRENT_PAGE_TABLE.lock().switch(new_page_table).unmap();
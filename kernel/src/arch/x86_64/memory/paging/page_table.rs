@@ -2,10 +2,12 @@
 
 use super::frame_allocator::FRAME_ALLOCATOR;
 use super::page_table_entry::*;
+use super::PAGE_SIZE;
 use core::marker::PhantomData;
 use core::ops::Index;
 use core::ops::IndexMut;
 use memory::{Address, VirtualAddress};
+use x86_64::instructions::tlb;
 
 /// The number of entries in a page table.
 pub const ENTRY_NUMBER: usize = 512;
@@ -131,6 +133,48 @@ impl<T: ReducablePageTableLevel> PageTable<T> {
     }
 }
 
+impl PageTable<Level2> {
+    /// Replaces the huge page that contains the given address with a level 1
+    /// table that maps the same frames with the same flags.
+    ///
+    /// Returns false if the address isn't mapped by a huge page.
+    pub fn split_huge_page(&mut self, address: VirtualAddress) -> bool {
+        let index = PageTable::<Level2>::table_index(address);
+        let flags = self[index].flags();
+
+        if !flags.contains(PRESENT) || !flags.contains(HUGE_PAGE) {
+            return false;
+        }
+
+        let first_frame = self[index].points_to().unwrap();
+        let mut page_flags = flags;
+        page_flags.remove(HUGE_PAGE | ENTRY_LOCK);
+
+        let frame = FRAME_ALLOCATOR.allocate();
+        self[index]
+            .set_address(frame.get_address())
+            .set_flags(PAGE_TABLE_FLAGS);
+
+        let table_address = (self as *const _ as usize | index << 3) << 9;
+
+        // The table is accessed through the address the huge page was
+        // translated with before.
+        tlb::flush(::x86_64::VirtualAddress(table_address));
+
+        let table = unsafe { &mut *(table_address as *mut PageTable<Level1>) };
+        for (i, entry) in table.entries.iter_mut().enumerate() {
+            *entry = PageTableEntry::new();
+            entry
+                .set_address(first_frame + i * PAGE_SIZE)
+                .set_flags(page_flags);
+        }
+
+        tlb::flush(::x86_64::VirtualAddress(address.as_usize()));
+
+        true
+    }
+}
+
 impl<T: PageTableLevel> PageTable<T> {
     /// Returns the index of the given page table level in the given address.
     pub fn table_index(address: VirtualAddress) -> usize {
@@ -5,7 +5,7 @@ use super::PageFrame;
 use core::fmt;
 use core::sync::atomic::{AtomicU64, Ordering};
 use memory::{Address, PhysicalAddress};
-use sync::{cpu_relax, disable_preemption, restore_preemption_state, PreemptionState};
+use sync::{cpu_relax, PreemptionGuard};
 
 /// Serves as a mask for the physical address in a page table entry.
 const PHYSICAL_ADDRESS_MASK: usize = 0xffffffffff << 12;
@@ -40,6 +40,10 @@ bitflags! {
         const GLOBAL = 1 << 8,
         /// Ensures mutual exclusion for pages this entry points to.
         const ENTRY_LOCK = 1 << 9,
+        /// The page shares its frame with other address spaces.
+        ///
+        /// It is mapped read-only and gets its own frame on the first write.
+        const COPY_ON_WRITE = 1 << 10,
         /// No code on this page can be executed.
         const NO_EXECUTE = 1 << 63,
 
@@ -59,6 +63,11 @@ impl PageTableEntry {
         PageTableEntryFlags::from_bits_truncate(self.0)
     }
 
+    /// Returns the raw value of the entry.
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+
     /// Returns the address this entry points to.
     pub fn points_to(&self) -> Option<PhysicalAddress> {
         if self.flags().contains(PRESENT) {
@@ -95,10 +104,17 @@ impl PageTableEntry {
         self.set_flags(current_flags)
     }
 
-    /// Unmaps and deallocates the frame this entry points to.
+    /// Unmaps and releases the frame this entry points to.
+    ///
+    /// The frame is deallocated, if this was its last reference.
     pub fn unmap(&mut self) {
         let address = self.points_to().expect("Trying to unmap an unmapped page.");
-        unsafe { FRAME_ALLOCATOR.deallocate(PageFrame::from_address(address)) };
+        unsafe { FRAME_ALLOCATOR.release(PageFrame::from_address(address)) };
+        self.0 = 0;
+    }
+
+    /// Unmaps this entry without deallocating the frame it points to.
+    pub fn unmap_without_freeing(&mut self) {
         self.0 = 0;
     }
 
@@ -106,22 +122,18 @@ impl PageTableEntry {
     ///
     /// They can't be accessed by other processors/threads after being locked.
     /// # Note
-    /// The preemtion state must be restored when unlocking.
-    pub fn lock(&mut self) -> PreemptionState {
-        let mut preemption_state;
+    /// The returned guard keeps preemption disabled and must only be dropped
+    /// after unlocking.
+    pub fn lock(&mut self) -> PreemptionGuard {
         let atomic_lock: &AtomicU64 = unsafe { &*((&mut self.0) as *mut u64 as *mut AtomicU64) };
         loop {
-            unsafe {
-                preemption_state = disable_preemption();
-            }
+            let preemption = PreemptionGuard::new();
             let lock_switch =
                 atomic_lock.fetch_or(ENTRY_LOCK.bits(), Ordering::Acquire) & ENTRY_LOCK.bits() == 0;
             if lock_switch {
-                break;
+                return preemption;
             } else {
-                unsafe {
-                    restore_preemption_state(&preemption_state);
-                }
+                drop(preemption);
             }
 
             // Wait until the lock looks unlocked before retrying
@@ -129,17 +141,13 @@ impl PageTableEntry {
                 cpu_relax();
             }
         }
-
-        preemption_state
     }
 
-    /// Unlocks the pages this entry points to and restores the preemption
-    /// state.
-    pub fn unlock(&mut self, preemption_state: &PreemptionState) {
+    /// Unlocks the pages this entry points to.
+    ///
+    /// The guard returned when locking should be dropped afterwards.
+    pub fn unlock(&mut self) {
         self.0 = self.0 & !ENTRY_LOCK.bits();
-        unsafe {
-            restore_preemption_state(preemption_state);
-        }
     }
 
     /// Checks if this entry is locked.
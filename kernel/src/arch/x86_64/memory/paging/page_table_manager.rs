@@ -1,22 +1,108 @@
 //! Uses a trait that has general page table managing functions.
 
+use super::super::{is_canonical, is_userspace_address};
 use super::frame_allocator::FRAME_ALLOCATOR;
-use super::page_table::{Level1, Level2, Level4, PageTable};
-use super::page_table_entry::{PageTableEntry, PageTableEntryFlags, PRESENT};
-use super::{Page, PageFrame};
+use super::page_table::{Level1, Level2, Level4, PageTable, ENTRY_NUMBER};
+use super::page_table_entry::{PageTableEntry, PageTableEntryFlags, GLOBAL, HUGE_PAGE, PRESENT,
+                              USER_ACCESSIBLE};
+use super::{zero_frame, Page, PageFrame, FAILED_HUGE_PAGES, HUGE_PAGE_SIZE, MAPPED_HUGE_PAGES,
+            PAGE_SIZE, SPLIT_HUGE_PAGES};
 use core::ops::{Deref, DerefMut};
-use memory::{Address, PhysicalAddress, VirtualAddress};
-use sync::PreemptionState;
+use core::sync::atomic::Ordering;
+use memory::{Address, MappingError, PhysicalAddress, VirtualAddress};
+use sync::PreemptionGuard;
 use x86_64::instructions::tlb;
 
+/// Unmapping more pages than this at once flushes the whole TLB instead of
+/// the single pages.
+const FULL_TLB_FLUSH_THRESHOLD: usize = 32;
+
+/// Checks that `count` pages starting at `start` can be mapped with the given
+/// flags.
+///
+/// Invalid mappings are bugs in the caller, so debug builds panic on them.
+fn check_mapping(
+    start: VirtualAddress,
+    count: usize,
+    flags: PageTableEntryFlags
+) -> Result<(), MappingError> {
+    let result = match find_mapping_error(start, count, flags) {
+        Some(error) => Err(error),
+        None => Ok(())
+    };
+
+    debug_assert!(
+        result.is_ok(),
+        "Invalid mapping of {} pages at {:?} with {:?}: {:?}",
+        count,
+        start,
+        flags,
+        result
+    );
+
+    result
+}
+
+/// Returns the reason why the pages can't be mapped with the given flags, if
+/// there is one.
+fn find_mapping_error(
+    start: VirtualAddress,
+    count: usize,
+    flags: PageTableEntryFlags
+) -> Option<MappingError> {
+    if count == 0 {
+        return None;
+    }
+
+    let last_page = (count - 1)
+        .checked_mul(PAGE_SIZE)
+        .and_then(|offset| start.checked_add(offset));
+
+    match last_page {
+        // The range must not span the non-canonical hole.
+        Some(last_page)
+            if is_canonical(start)
+                && is_canonical(last_page)
+                && is_userspace_address(start) == is_userspace_address(last_page) =>
+        {
+            if flags.contains(USER_ACCESSIBLE) && !is_userspace_address(last_page) {
+                Some(MappingError::UserAccessibleKernelPage)
+            } else {
+                None
+            }
+        },
+        _ => Some(MappingError::NonCanonicalAddress)
+    }
+}
+
+/// Allocates a frame for a page with the given flags.
+///
+/// The frames of user pages are zeroed, so that a process never sees what
+/// another one left in them.
+fn allocate_frame(flags: PageTableEntryFlags) -> PageFrame {
+    if flags.contains(USER_ACCESSIBLE) {
+        FRAME_ALLOCATOR.allocate_zeroed()
+    } else {
+        FRAME_ALLOCATOR.allocate()
+    }
+}
+
+/// Counts a huge page that was split into normal pages.
+fn count_split_huge_page() {
+    MAPPED_HUGE_PAGES.fetch_sub(1, Ordering::Relaxed);
+    SPLIT_HUGE_PAGES.fetch_add(1, Ordering::Relaxed);
+}
+
 /// A reference to a locked level 1 page table.
 pub struct Level1TableReference<'a> {
     /// The reference to the level 2 table that contains the level 1 table.
     table: &'a mut PageTable<Level2>,
     /// The address of the level 1 table.
     address: VirtualAddress,
-    /// The preemption state of the lock.
-    preemption_state: PreemptionState
+    /// Keeps preemption disabled while the table is locked.
+    ///
+    /// It is dropped after `drop` unlocked the table.
+    _preemption: PreemptionGuard
 }
 
 impl<'a> Deref for Level1TableReference<'a> {
@@ -38,7 +124,7 @@ impl<'a> Drop for Level1TableReference<'a> {
         let table_index = PageTable::<Level2>::table_index(self.address);
         let l2_entry = &mut self.table[table_index];
 
-        l2_entry.unlock(&self.preemption_state);
+        l2_entry.unlock();
     }
 }
 
@@ -71,18 +157,46 @@ pub trait PageTableManager {
 
     /// Returns the corresponding physical address to a virtual address.
     fn translate_address(&mut self, address: VirtualAddress) -> Option<PhysicalAddress> {
+        let huge_page = self
+            .get_huge_page_entry(address)
+            .and_then(|entry| entry.points_to());
+
+        if let Some(huge_page) = huge_page {
+            return Some(huge_page + (address.as_usize() & (HUGE_PAGE_SIZE - 1)));
+        }
+
         self.get_l1(address)
             .and_then(|l1| l1[PageTable::<Level1>::table_index(address)].points_to())
             .map(|page_address| page_address + (address.as_usize() & 0xfff))
     }
 
+    /// Returns the level 2 entry of the huge page that contains the given
+    /// address, if the address is mapped by one.
+    fn get_huge_page_entry(&mut self, address: VirtualAddress) -> Option<&mut PageTableEntry> {
+        assert!(valid_address!(address));
+
+        let l2 = self
+            .get_l4()
+            .get_next_level_mut(address)
+            .and_then(|l3| l3.get_next_level_mut(address))?;
+        let entry = &mut l2[PageTable::<Level2>::table_index(address)];
+
+        if entry.flags().contains(PRESENT) && entry.flags().contains(HUGE_PAGE) {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
     /// Returns a mutable reference to the level 1 table corresponding to the
     /// given address.
+    ///
+    /// A huge page at the address is split into normal pages first.
     fn get_l1(&mut self, address: VirtualAddress) -> Option<Level1TableReference> {
         assert!(valid_address!(address));
 
         let table_index = PageTable::<Level2>::table_index(address);
-        let preemption_state = {
+        let preemption = {
             let l4 = self.get_l4();
 
             let l2 = l4
@@ -91,6 +205,10 @@ pub trait PageTableManager {
 
             match l2 {
                 Some(table) => {
+                    if table.split_huge_page(address) {
+                        count_split_huge_page();
+                    }
+
                     let l2_entry = &mut table[table_index];
                     if l2_entry.points_to().is_some() {
                         Some(l2_entry.lock())
@@ -102,15 +220,15 @@ pub trait PageTableManager {
             }
         };
 
-        match preemption_state {
-            Some(preemption_state) => Some(Level1TableReference {
+        match preemption {
+            Some(preemption) => Some(Level1TableReference {
                 table: self
                     .get_l4()
                     .get_next_level_mut(address)
                     .and_then(|l3| l3.get_next_level_mut(address))
                     .unwrap(),
                 address,
-                preemption_state
+                _preemption: preemption
             }),
             None => None
         }
@@ -120,16 +238,22 @@ pub trait PageTableManager {
     /// creating it.
     ///
     /// This creates new page tables if the parent tables for the wanted table
-    /// are not already mapped.
+    /// are not already mapped. A huge page at the address is split into normal
+    /// pages first.
     fn get_l1_and_map(&mut self, address: VirtualAddress) -> Level1TableReference {
         assert!(valid_address!(address));
 
         let table_index = PageTable::<Level2>::table_index(address);
-        let preemption_state = {
+        let preemption = {
             let l2 = self
                 .get_l4()
                 .next_level_and_map(address)
                 .next_level_and_map(address);
+
+            if l2.split_huge_page(address) {
+                count_split_huge_page();
+            }
+
             let l2_entry = &mut l2[table_index];
             l2_entry.lock()
         };
@@ -146,7 +270,7 @@ pub trait PageTableManager {
                 .next_level_and_map(address)
                 .next_level_and_map(address),
             address,
-            preemption_state
+            _preemption: preemption
         }
     }
 
@@ -172,7 +296,16 @@ pub trait PageTableManager {
     }
 
     /// Maps the given page to the given frame with the given flags.
-    fn map_page_at(&mut self, page: Page, frame: PageFrame, flags: PageTableEntryFlags) {
+    ///
+    /// The page is always mapped as present.
+    fn map_page_at(
+        &mut self,
+        page: Page,
+        frame: PageFrame,
+        flags: PageTableEntryFlags
+    ) -> Result<(), MappingError> {
+        check_mapping(page.get_address(), 1, flags)?;
+
         if let Some(entry) = self.get_entry(page.get_address()) {
             debug_assert!(
                 !entry.flags().contains(PRESENT),
@@ -187,10 +320,16 @@ pub trait PageTableManager {
         entry
             .set_address(frame.get_address())
             .set_flags(flags | PRESENT);
+
+        Ok(())
     }
 
     /// Maps the given page to an allocated frame with the given flags.
-    fn map_page(&mut self, page: Page, flags: PageTableEntryFlags) {
+    ///
+    /// The page is always mapped as present.
+    fn map_page(&mut self, page: Page, flags: PageTableEntryFlags) -> Result<(), MappingError> {
+        check_mapping(page.get_address(), 1, flags)?;
+
         if let Some(entry) = self.get_entry(page.get_address()) {
             debug_assert!(
                 !entry.flags().contains(PRESENT),
@@ -199,13 +338,205 @@ pub trait PageTableManager {
             );
         }
 
-        let frame = FRAME_ALLOCATOR.allocate();
+        let frame = allocate_frame(flags);
 
-        self.map_page_at(page, frame, flags);
+        self.map_page_at(page, frame, flags)
+    }
+
+    /// Maps `count` consecutive pages starting at `start` to allocated frames
+    /// with the given flags.
+    ///
+    /// The pages are always mapped as present. The level 1 tables are only
+    /// looked up once for all the pages in them. If `allow_huge_pages` is set,
+    /// user accessible parts of the range that cover a whole huge page are
+    /// mapped as huge pages where possible.
+    fn map_range(
+        &mut self,
+        start: Page,
+        count: usize,
+        flags: PageTableEntryFlags,
+        allow_huge_pages: bool
+    ) -> Result<(), MappingError> {
+        check_mapping(start.get_address(), count, flags)?;
+
+        let mut address = start.get_address();
+        let end_address = address + count * PAGE_SIZE;
+        let allow_huge_pages = allow_huge_pages && flags.contains(USER_ACCESSIBLE);
+
+        while address < end_address {
+            if allow_huge_pages
+                && address.is_aligned_to(HUGE_PAGE_SIZE)
+                && end_address - address >= HUGE_PAGE_SIZE
+                && self.map_huge_page(address, flags)
+            {
+                address += HUGE_PAGE_SIZE;
+                continue;
+            }
+
+            let mut l1 = self.get_l1_and_map(address);
+
+            loop {
+                let index = PageTable::<Level1>::table_index(address);
+
+                debug_assert!(
+                    !l1[index].flags().contains(PRESENT),
+                    "Trying to double map page {:?}",
+                    address
+                );
+
+                let frame = allocate_frame(flags);
+                l1[index]
+                    .set_address(frame.get_address())
+                    .set_flags(flags | PRESENT);
+
+                address += PAGE_SIZE;
+
+                // Stop at the end of the range or the level 1 table.
+                if address >= end_address || PageTable::<Level1>::table_index(address) == 0 {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Maps a huge page at the given address to allocated frames with the
+    /// given flags.
+    ///
+    /// Returns false without mapping anything if a level 1 table covers the
+    /// address already or there are not enough consecutive free frames.
+    fn map_huge_page(&mut self, address: VirtualAddress, flags: PageTableEntryFlags) -> bool {
+        debug_assert!(address.is_aligned_to(HUGE_PAGE_SIZE));
+
+        let l2 = self
+            .get_l4()
+            .next_level_and_map(address)
+            .next_level_and_map(address);
+        let entry = &mut l2[PageTable::<Level2>::table_index(address)];
+
+        if entry.flags().contains(PRESENT) {
+            return false;
+        }
+
+        match FRAME_ALLOCATOR.allocate_huge() {
+            Some(frame) => {
+                if flags.contains(USER_ACCESSIBLE) {
+                    for index in 0..ENTRY_NUMBER {
+                        let address = frame.get_address() + index * PAGE_SIZE;
+
+                        zero_frame(&PageFrame::from_address(address));
+                    }
+                }
+
+                entry
+                    .set_address(frame.get_address())
+                    .set_flags(flags | PRESENT | HUGE_PAGE);
+                MAPPED_HUGE_PAGES.fetch_add(1, Ordering::Relaxed);
+
+                true
+            },
+            None => {
+                FAILED_HUGE_PAGES.fetch_add(1, Ordering::Relaxed);
+
+                false
+            }
+        }
+    }
+
+    /// Unmaps the huge page at the given address and releases its frames.
+    ///
+    /// # Safety
+    /// - Make sure the page isn't referenced anywhere anymore.
+    unsafe fn unmap_huge_page(&mut self, address: VirtualAddress) {
+        let first_frame = {
+            let entry = self
+                .get_huge_page_entry(address)
+                .expect("Trying to unmap a huge page that isn't mapped.");
+            let first_frame = entry.points_to().unwrap();
+
+            entry.unmap_without_freeing();
+
+            first_frame
+        };
+
+        // The frames of a split huge page are released one by one, so they
+        // are always released that way.
+        for i in 0..ENTRY_NUMBER {
+            FRAME_ALLOCATOR.release(PageFrame::from_address(first_frame + i * PAGE_SIZE));
+        }
+
+        MAPPED_HUGE_PAGES.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Unmaps `count` consecutive pages starting at `start`.
+    ///
+    /// Pages in the range that aren't mapped are skipped. Huge pages that lie
+    /// completely in the range are unmapped as a whole, the others are split.
+    /// The TLB is flushed once after all pages were unmapped.
+    ///
+    /// # Safety
+    /// - Make sure the pages aren't referenced anywhere anymore.
+    unsafe fn unmap_range(&mut self, start: Page, count: usize) {
+        // TODO: Consider multiple CPUs.
+        let start_address = start.get_address();
+        let end_address = start_address + count * PAGE_SIZE;
+        let mut address = start_address;
+        let mut unmapped_global_page = false;
+
+        while address < end_address {
+            let is_whole_huge_page = address.is_aligned_to(HUGE_PAGE_SIZE)
+                && end_address - address >= HUGE_PAGE_SIZE
+                && self.get_huge_page_entry(address).is_some();
+
+            if is_whole_huge_page {
+                self.unmap_huge_page(address);
+                address += HUGE_PAGE_SIZE;
+                continue;
+            }
+
+            if let Some(mut l1) = self.get_l1(address) {
+                loop {
+                    let index = PageTable::<Level1>::table_index(address);
+
+                    if l1[index].points_to().is_some() {
+                        unmapped_global_page |= l1[index].flags().contains(GLOBAL);
+                        l1[index].unmap();
+                    }
+
+                    address += PAGE_SIZE;
+
+                    if address >= end_address || PageTable::<Level1>::table_index(address) == 0 {
+                        break;
+                    }
+                }
+            } else {
+                // Skip to the next level 1 table.
+                address =
+                    VirtualAddress::from_usize((address.as_usize() | (HUGE_PAGE_SIZE - 1)) + 1);
+            }
+        }
+
+        // Global pages survive a full flush.
+        if count > FULL_TLB_FLUSH_THRESHOLD && !unmapped_global_page {
+            tlb::flush_all();
+        } else {
+            for i in 0..count {
+                tlb::flush(::x86_64::VirtualAddress((start_address + i * PAGE_SIZE).as_usize()));
+            }
+        }
     }
 
     /// Changes the permissions of the page or map it, if it wasn't mapped.
-    fn change_permissions_or_map(&mut self, page: Page, flags: PageTableEntryFlags) {
+    ///
+    /// The page is always present afterwards.
+    fn change_permissions_or_map(
+        &mut self,
+        page: Page,
+        flags: PageTableEntryFlags
+    ) -> Result<(), MappingError> {
+        check_mapping(page.get_address(), 1, flags)?;
+
         let is_mapped = {
             if let Some(entry) = self.get_entry(page.get_address()) {
                 entry.flags().contains(PRESENT)
@@ -218,8 +549,10 @@ pub trait PageTableManager {
             self.get_entry(page.get_address())
                 .unwrap()
                 .set_flags(PRESENT | flags);
+
+            Ok(())
         } else {
-            self.map_page(page, flags);
+            self.map_page(page, flags)
         }
     }
 
@@ -239,6 +572,20 @@ pub trait PageTableManager {
         tlb::flush(::x86_64::VirtualAddress(page.get_address().as_usize()));
     }
 
+    /// Unmaps the given page without deallocating its frame.
+    ///
+    /// # Safety
+    /// - Make sure the page isn't referenced anywhere anymore.
+    unsafe fn unmap_page_without_freeing(&mut self, page: Page) {
+        // TODO: Consider multiple CPUs.
+        let entry = self.get_entry(page.get_address());
+
+        entry
+            .expect("Trying to unmap a page that isn't mapped.")
+            .unmap_without_freeing();
+        tlb::flush(::x86_64::VirtualAddress(page.get_address().as_usize()));
+    }
+
     /// Unmaps the given page, not checking if it was mapped.
     ///
     /// # Safety
@@ -257,3 +604,45 @@ pub trait PageTableManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::page_table_entry::WRITABLE;
+    use super::*;
+
+    /// Tests that non-canonical ranges are rejected.
+    #[test]
+    fn test_non_canonical_mappings() {
+        let low_max = VirtualAddress::from_usize(0x00007fffffffffff).page_align_down();
+        let high_min = VirtualAddress::from_usize(0xffff800000000000);
+
+        assert_eq!(find_mapping_error(low_max, 1, WRITABLE), None);
+        assert_eq!(find_mapping_error(high_min, 1, WRITABLE), None);
+        assert_eq!(
+            find_mapping_error(low_max + PAGE_SIZE, 1, WRITABLE),
+            Some(MappingError::NonCanonicalAddress)
+        );
+        assert_eq!(
+            find_mapping_error(low_max, 2, WRITABLE),
+            Some(MappingError::NonCanonicalAddress)
+        );
+        assert_eq!(
+            find_mapping_error(high_min, usize::max_value(), WRITABLE),
+            Some(MappingError::NonCanonicalAddress)
+        );
+    }
+
+    /// Tests that user accessible pages must be in the lower half.
+    #[test]
+    fn test_user_accessible_mappings() {
+        let user_page = VirtualAddress::from_usize(0x400000);
+        let kernel_page = VirtualAddress::from_usize(0xffff800000000000);
+
+        assert_eq!(find_mapping_error(user_page, 4, USER_ACCESSIBLE), None);
+        assert_eq!(find_mapping_error(kernel_page, 4, WRITABLE), None);
+        assert_eq!(
+            find_mapping_error(kernel_page, 1, USER_ACCESSIBLE | WRITABLE),
+            Some(MappingError::UserAccessibleKernelPage)
+        );
+    }
+}
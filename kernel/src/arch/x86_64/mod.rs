@@ -2,36 +2,77 @@
 //!
 //! This module does all the architecture specific things for x86_64.
 
+mod ac97;
+mod acpi;
 pub mod context;
+mod debug;
 mod gdt;
 mod interrupts;
 pub mod memory;
+mod pat;
+mod pc_speaker;
+mod pci;
+mod ps2_keyboard;
+mod pstate;
+mod rtc;
+#[macro_use]
+pub mod serial;
+mod sleep;
+mod smp;
 pub mod sync;
 mod syscalls;
+mod thermal;
 pub mod vga_buffer;
-#[macro_use]
-mod serial;
+mod virtio;
+mod virtio_9p;
+mod virtio_rng;
 
 pub use self::context::Context;
 use self::gdt::{GDT, TSS};
 use self::interrupts::issue_self_interrupt;
 use self::interrupts::SCHEDULE_INTERRUPT_NUM;
-use self::serial::SerialPort;
 use super::Architecture;
+use alloc::Vec;
+use config;
+use cpufreq::PerformanceState;
 use core::fmt;
 use core::fmt::Write;
 use core::time::Duration;
-use log::{set_logger, Level, Log, Metadata, Record};
+use kdebug::WatchpointSet;
+use keyboard::{Locks, Repeat};
+use memory::address_space::AddressSpaceHandle;
+use memory::huge_pages;
+use memory::numa::Topology;
 use memory::{Address, MemoryArea, PageFlags, PhysicalAddress, VirtualAddress};
 use multitasking::{StackType, CURRENT_THREAD};
 use raw_cpuid::CpuId;
-use sync::mutex::Mutex;
 use sync::time::Timestamp;
+use x86_64::instructions::port::outb;
+use x86_64::instructions::tables::{lidt, DescriptorTablePointer};
 use x86_64::instructions::{rdmsr, wrmsr};
 use x86_64::registers::*;
 
 pub struct X86_64;
 
+/// Enables the features of the current CPU that the kernel relies on.
+///
+/// # Safety
+/// - The CPU must support the features.
+unsafe fn enable_cpu_features() {
+    // Enable syscall/sysret instructions and the NXE bit in the page table.
+    wrmsr(msr::IA32_EFER, rdmsr(msr::IA32_EFER) | 1 << 11 | 1);
+
+    // Enable global pages.
+    let cr4_flags = control_regs::cr4() | control_regs::Cr4::ENABLE_GLOBAL_PAGES;
+    control_regs::cr4_write(cr4_flags);
+
+    // Enable read only pages.
+    let cr0_flags = control_regs::cr0() | control_regs::Cr0::WRITE_PROTECT;
+    control_regs::cr0_write(cr0_flags);
+
+    pat::init();
+}
+
 impl Architecture for X86_64 {
     type AddressSpaceManager = memory::address_space_manager::AddressSpaceManager;
 
@@ -49,6 +90,7 @@ impl Architecture for X86_64 {
 
         if let Some(features) = cpuid.get_feature_info() {
             supported &= features.has_apic();
+            supported &= features.has_pat();
         } else {
             supported = false;
         }
@@ -65,16 +107,7 @@ impl Architecture for X86_64 {
         }
 
         unsafe {
-            // Enable syscall/sysret instructions and the NXE bit in the page table.
-            wrmsr(msr::IA32_EFER, rdmsr(msr::IA32_EFER) | 1 << 11 | 1);
-
-            // Enable global pages.
-            let cr4_flags = control_regs::cr4() | control_regs::Cr4::ENABLE_GLOBAL_PAGES;
-            control_regs::cr4_write(cr4_flags);
-
-            // Enable read only pages.
-            let cr0_flags = control_regs::cr0() | control_regs::Cr0::WRITE_PROTECT;
-            control_regs::cr0_write(cr0_flags);
+            enable_cpu_features();
         }
     }
 
@@ -97,18 +130,22 @@ impl Architecture for X86_64 {
 
         debug!("Initializing interrupts...");
         interrupts::init();
+
+        serial::late_init();
+        vga_buffer::late_init();
     }
 
-    fn init_io() {
-        vga_buffer::init();
-        COM1.lock().init();
+    fn init_drivers() {
+        acpi::init();
+        pc_speaker::init();
+        ac97::init();
+        virtio_rng::init();
+        virtio_9p::init();
     }
 
-    fn init_logger() {
-        // Ignore the result. If the logger fails to be initialized, logging won't work.
-        match set_logger(&KERNEL_LOGGER) {
-            _ => ()
-        }
+    fn init_io() {
+        vga_buffer::init();
+        serial::init();
     }
 
     fn get_cpu_num() -> usize {
@@ -125,6 +162,14 @@ impl Architecture for X86_64 {
             .initial_local_apic_id() as usize
     }
 
+    fn start_cpu(cpu_id: usize) -> bool {
+        smp::start_cpu(cpu_id)
+    }
+
+    unsafe fn park_cpu() {
+        smp::park_cpu()
+    }
+
     fn invoke_scheduler() {
         issue_self_interrupt(SCHEDULE_INTERRUPT_NUM);
     }
@@ -151,6 +196,11 @@ impl Architecture for X86_64 {
         sync::cpu_halt()
     }
 
+    #[inline(always)]
+    unsafe fn enable_interrupts_and_halt() {
+        sync::enable_interrupts_and_halt()
+    }
+
     #[inline(always)]
     fn get_interrupt_state() -> bool {
         sync::interrupts_enabled()
@@ -170,6 +220,10 @@ impl Architecture for X86_64 {
         sync::get_current_timestamp()
     }
 
+    fn read_hardware_clock() -> Option<u64> {
+        rtc::read_unix_time()
+    }
+
     fn interrupt_in(duration: Duration) {
         // TODO: allow more fine grained sleeps than milliseconds
         let mut sleep_duration = duration.subsec_millis();
@@ -183,8 +237,22 @@ impl Architecture for X86_64 {
     }
 
     #[inline(always)]
-    unsafe fn switch_context(old_context: &mut Context, new_context: &Context) {
-        context::switch_context(old_context, new_context)
+    unsafe fn switch_context(
+        old_context: &mut Context,
+        new_context: &Context,
+        old_address_space: AddressSpaceHandle,
+        new_address_space: AddressSpaceHandle
+    ) {
+        context::switch_context(
+            old_context,
+            new_context,
+            old_address_space,
+            new_address_space
+        )
+    }
+
+    fn get_current_address_space() -> AddressSpaceHandle {
+        context::get_current_address_space()
     }
 
     fn get_free_memory_size() -> usize {
@@ -199,10 +267,73 @@ impl Architecture for X86_64 {
         memory::unmap_page(page_address)
     }
 
+    fn map_range(first_page_address: VirtualAddress, count: usize, flags: PageFlags) {
+        memory::map_range(first_page_address, count, flags)
+    }
+
+    unsafe fn unmap_range(first_page_address: VirtualAddress, count: usize) {
+        memory::unmap_range(first_page_address, count)
+    }
+
+    fn allocate_zeroed_frame() -> PhysicalAddress {
+        memory::allocate_zeroed_frame()
+    }
+
+    fn zero_free_frame() -> bool {
+        memory::zero_free_frame()
+    }
+
+    unsafe fn access_frame<R, F>(frame_address: PhysicalAddress, function: F) -> R
+    where
+        F: FnOnce(&mut [u64]) -> R
+    {
+        memory::access_frame(frame_address, function)
+    }
+
+    fn add_frame_reference(frame_address: PhysicalAddress) {
+        memory::add_frame_reference(frame_address)
+    }
+
+    fn is_frame_shared(frame_address: PhysicalAddress) -> bool {
+        memory::is_frame_shared(frame_address)
+    }
+
+    unsafe fn release_frame(frame_address: PhysicalAddress) {
+        memory::release_frame(frame_address)
+    }
+
+    fn get_huge_page_statistics() -> huge_pages::Statistics {
+        memory::get_huge_page_statistics()
+    }
+
+    fn get_numa_topology() -> Option<Topology> {
+        acpi::get_numa_topology()
+    }
+
+    fn check_page_tables() {
+        memory::check_page_tables()
+    }
+
     fn get_kernel_area() -> MemoryArea<PhysicalAddress> {
         memory::get_kernel_area()
     }
 
+    fn get_early_heap_area() -> MemoryArea<PhysicalAddress> {
+        memory::get_early_heap_area()
+    }
+
+    fn get_wakeup_area() -> MemoryArea<PhysicalAddress> {
+        memory::get_wakeup_area()
+    }
+
+    fn get_crash_dump_area() -> MemoryArea<PhysicalAddress> {
+        memory::get_crash_dump_area()
+    }
+
+    fn get_top_level_entries() -> [u64; 512] {
+        memory::get_l4_entries()
+    }
+
     fn get_initramfs_area() -> MemoryArea<VirtualAddress> {
         memory::get_initramfs_area()
     }
@@ -211,83 +342,117 @@ impl Architecture for X86_64 {
         memory::get_page_flags(page_address)
     }
 
+    fn map_physical_area(area: MemoryArea<PhysicalAddress>, flags: PageFlags) {
+        memory::map_physical_area(area, flags)
+    }
+
     fn is_userspace_address(address: VirtualAddress) -> bool {
         memory::is_userspace_address(address)
     }
 
+    fn load_watchpoints(watchpoints: &WatchpointSet) {
+        debug::load_watchpoints(watchpoints)
+    }
+
+    #[inline(always)]
+    fn get_frame_pointer() -> VirtualAddress {
+        let frame_pointer: usize;
+        unsafe {
+            asm!("mov $0, rbp" : "=r"(frame_pointer) : : : "intel", "volatile");
+        }
+        VirtualAddress::from_usize(frame_pointer)
+    }
+
     const PAGE_SIZE: usize = memory::PAGE_SIZE;
 
     const HEAP_AREA: MemoryArea<VirtualAddress> =
         MemoryArea::new(memory::HEAP_START, memory::HEAP_MAX_SIZE);
 
+    const BOOT_MAPPED_SIZE: usize = memory::BOOT_MAPPED_SIZE;
+
+    const PHYSICAL_WINDOW_SIZE: usize = memory::PHYSICAL_WINDOW_SIZE;
+
     fn write_fmt(args: fmt::Arguments) {
-        vga_buffer::WRITER.lock().write_fmt(args).unwrap();
-    }
-}
+        let mut writer = vga_buffer::WRITER.lock();
+
+        writer.write_fmt(args).unwrap();
 
-/// The COM1 serial port.
-pub static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::new(0x3f8));
-
-/// The type of the logger for the kernel.
-pub struct KernelLogger;
-
-/// The kernel logger.
-pub static KERNEL_LOGGER: KernelLogger = KernelLogger;
-
-/// Determines whether all logging should be to the screen.
-const LOG_TO_SCREEN: bool = false;
-
-impl Log for KernelLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
-    }
-
-    fn log(&self, record: &Record) {
-        let reset = "\x1b[0m";
-        let red = "\x1b[31m";
-        let yellow = "\x1b[33m";
-        let time = Timestamp::get_current();
-        match record.metadata().level() {
-            Level::Error => {
-                println!("{}: {}", record.level(), record.args());
-                serial_println!(
-                    "{} {}{}{}: {}",
-                    time,
-                    red,
-                    record.level(),
-                    reset,
-                    record.args()
-                );
-            },
-            Level::Warn => {
-                println!("{}: {}", record.level(), record.args());
-                serial_println!(
-                    "{} {}{}{}: {}",
-                    time,
-                    yellow,
-                    record.level(),
-                    reset,
-                    record.args()
-                );
-            },
-            Level::Info => {
-                println!("{}", record.args());
-                serial_println!("{} {}", time, record.args());
-            },
-            Level::Debug => {
-                if LOG_TO_SCREEN {
-                    println!("{}: {}", record.level(), record.args());
-                }
-                serial_println!("{} {}: {}", time, record.level(), record.args());
-            },
-            Level::Trace => {
-                if LOG_TO_SCREEN {
-                    println!("{}: {}", record.level(), record.args());
-                }
-                serial_println!("{} {}: {}", time, record.level(), record.args());
-            }
+        // Otherwise the RTC interrupt flushes the screen.
+        if !config::DEFERRED_SCREEN_FLUSH {
+            writer.flush();
         }
     }
 
-    fn flush(&self) {}
+    fn flush_output() {
+        vga_buffer::flush();
+        serial::flush();
+    }
+
+    fn get_console_size() -> (usize, usize) {
+        vga_buffer::size()
+    }
+
+    fn get_console_cursor() -> (usize, usize) {
+        vga_buffer::cursor()
+    }
+
+    fn set_console_cursor(row: usize, column: usize) -> bool {
+        vga_buffer::set_cursor(row, column)
+    }
+
+    fn set_keyboard_leds(locks: Locks) {
+        ps2_keyboard::set_leds(locks);
+    }
+
+    fn set_keyboard_repeat(repeat: Repeat) {
+        ps2_keyboard::set_repeat(repeat);
+    }
+
+    fn suspend() -> bool {
+        sleep::suspend()
+    }
+
+    fn get_performance_states() -> Vec<PerformanceState> {
+        pstate::get_states()
+    }
+
+    fn set_performance_state(state: &PerformanceState) {
+        pstate::set_state(state)
+    }
+
+    fn get_cpu_temperature() -> Option<u32> {
+        thermal::get_temperature()
+    }
+
+    fn exit_emulator(code: u8) {
+        // The buffered serial output would be lost otherwise.
+        Self::flush_output();
+
+        unsafe { outb(EXIT_PORT, code) }
+    }
+
+    fn reboot() -> ! {
+        Self::flush_output();
+
+        unsafe {
+            // Pulsing the reset line of the keyboard controller resets the
+            // CPU on most machines.
+            outb(KEYBOARD_CONTROLLER_COMMAND_PORT, RESET_COMMAND);
+
+            // Otherwise an interrupt without an IDT causes a triple fault.
+            lidt(&DescriptorTablePointer { limit: 0, base: 0 });
+            asm!("int3" : : : : "intel", "volatile");
+        }
+
+        loop {}
+    }
 }
+
+/// The I/O port of the exit device that QEMU provides in tests.
+const EXIT_PORT: u16 = 0xf4;
+
+/// The command port of the PS/2 keyboard controller.
+const KEYBOARD_CONTROLLER_COMMAND_PORT: u16 = 0x64;
+
+/// The keyboard controller command that pulses the reset line of the CPU.
+const RESET_COMMAND: u8 = 0xfe;
@@ -0,0 +1,77 @@
+//! Programs the page attribute table.
+//!
+//! The memory type of a page is selected by the PAT, PCD and PWT bits of its
+//! page table entry, which together index the eight entries of the PAT. The
+//! kernel never sets the PAT bit, so only the first four entries are used and
+//! the upper four repeat them:
+//!
+//! | PCD | PWT | Memory type     |
+//! |-----|-----|-----------------|
+//! | 0   | 0   | write-back      |
+//! | 0   | 1   | write-through   |
+//! | 1   | 0   | write-combining |
+//! | 1   | 1   | uncacheable     |
+//!
+//! Only the third entry differs from the default, which is uncacheable unless
+//! overridden by the MTRRs there.
+
+use x86_64::instructions::wrmsr;
+
+/// The MSR that holds the page attribute table.
+pub const IA32_PAT: u32 = 0x277;
+
+/// The memory types a PAT entry can select.
+#[derive(Debug, Clone, Copy)]
+enum MemoryType {
+    /// Accesses are not cached.
+    Uncacheable = 0x00,
+    /// Writes are collected in a buffer and written in bursts.
+    WriteCombining = 0x01,
+    /// Reads are cached, writes go to memory right away.
+    WriteThrough = 0x04,
+    /// Reads and writes are cached.
+    WriteBack = 0x06
+}
+
+/// The memory types of the entries of the PAT.
+const LAYOUT: [MemoryType; 8] = [
+    MemoryType::WriteBack,
+    MemoryType::WriteThrough,
+    MemoryType::WriteCombining,
+    MemoryType::Uncacheable,
+    MemoryType::WriteBack,
+    MemoryType::WriteThrough,
+    MemoryType::WriteCombining,
+    MemoryType::Uncacheable
+];
+
+/// Returns the value of the PAT MSR for the given memory types.
+fn pat_value(layout: &[MemoryType; 8]) -> u64 {
+    layout
+        .iter()
+        .enumerate()
+        .fold(0, |value, (index, &memory_type)| {
+            value | (memory_type as u64) << (index * 8)
+        })
+}
+
+/// Programs the page attribute table of the current CPU.
+///
+/// This must happen before any page is mapped write-combining or
+/// write-through.
+pub fn init() {
+    unsafe {
+        wrmsr(IA32_PAT, pat_value(&LAYOUT));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the entries are placed at the right bytes.
+    #[test]
+    fn test_pat_value() {
+        assert_eq!(pat_value(&LAYOUT), 0x0001_0406_0001_0406);
+    }
+}
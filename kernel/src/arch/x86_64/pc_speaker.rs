@@ -0,0 +1,124 @@
+//! Plays tones on the PC speaker.
+//!
+//! Channel 2 of the programmable interval timer drives the speaker with a
+//! square wave. Writing a frequency in hertz as a little endian `u32` to
+//! `/dev/speaker` starts a tone with that frequency, writing zero stops it.
+//! The tone also stops once the file is closed.
+
+use alloc::boxed::Box;
+use devfs;
+use file_handle::{FileError, FileHandle, PollEvents, Result, SeekFrom, POLL_OUT};
+use sync::Mutex;
+use x86_64::instructions::port::{inb, outb};
+
+/// The name of the speaker device.
+const DEVICE_NAME: &str = "speaker";
+
+/// The frequency the programmable interval timer counts with.
+const TIMER_FREQUENCY: u32 = 1_193_182;
+
+/// The data port of channel 2 of the timer.
+const CHANNEL_2_PORT: u16 = 0x42;
+
+/// The command port of the timer.
+const COMMAND_PORT: u16 = 0x43;
+
+/// The command that makes channel 2 output a square wave with a 16 bit
+/// divisor.
+const SQUARE_WAVE_COMMAND: u8 = 0b1011_0110;
+
+/// The port that connects channel 2 to the speaker.
+const SPEAKER_PORT: u16 = 0x61;
+
+/// The bits of the speaker port that enable the gate of channel 2 and the
+/// speaker.
+const SPEAKER_ENABLE: u8 = 0b11;
+
+/// Serializes the accesses to the timer channel and the speaker port.
+static SPEAKER_LOCK: Mutex<()> = Mutex::new(());
+
+/// Plays a tone with the given frequency in hertz until it is stopped.
+///
+/// Frequencies that the timer can't produce are clamped.
+pub fn play(frequency: u32) {
+    if frequency == 0 {
+        stop();
+        return;
+    }
+
+    let divisor = (TIMER_FREQUENCY / frequency).max(1).min(0xffff);
+    let _lock = SPEAKER_LOCK.lock();
+
+    unsafe {
+        outb(COMMAND_PORT, SQUARE_WAVE_COMMAND);
+        outb(CHANNEL_2_PORT, divisor as u8);
+        outb(CHANNEL_2_PORT, (divisor >> 8) as u8);
+
+        let state = inb(SPEAKER_PORT);
+        outb(SPEAKER_PORT, state | SPEAKER_ENABLE);
+    }
+}
+
+/// Stops the current tone.
+pub fn stop() {
+    let _lock = SPEAKER_LOCK.lock();
+
+    unsafe {
+        let state = inb(SPEAKER_PORT);
+        outb(SPEAKER_PORT, state & !SPEAKER_ENABLE);
+    }
+}
+
+/// The speaker as a file.
+struct SpeakerDevice;
+
+impl Drop for SpeakerDevice {
+    fn drop(&mut self) {
+        stop();
+    }
+}
+
+impl FileHandle for SpeakerDevice {
+    fn seek(&mut self, _position: SeekFrom) -> Result<u64> {
+        Err(FileError::NotSupported)
+    }
+
+    fn read(&mut self, _buffer: &mut [u8]) -> Result<()> {
+        Err(FileError::NotSupported)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        if buffer.len() % 4 != 0 {
+            return Err(FileError::NotSupported);
+        }
+
+        for frequency in buffer.chunks(4) {
+            play(
+                u32::from(frequency[0])
+                    | u32::from(frequency[1]) << 8
+                    | u32::from(frequency[2]) << 16
+                    | u32::from(frequency[3]) << 24
+            );
+        }
+
+        Ok(())
+    }
+
+    fn len(&mut self) -> u64 {
+        0
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        POLL_OUT
+    }
+}
+
+/// Makes the speaker available as a device file.
+pub fn init() {
+    assert_has_not_been_called!("The PC speaker should only be initialized once.");
+
+    devfs::register(
+        DEVICE_NAME,
+        Box::new(|| Box::new(SpeakerDevice) as Box<FileHandle>)
+    );
+}
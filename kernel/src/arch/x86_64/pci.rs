@@ -0,0 +1,216 @@
+//! Accesses PCI devices through the configuration ports.
+//!
+//! Only the configuration space is handled here, the drivers access their
+//! devices themselves.
+
+use alloc::Vec;
+use sync::Mutex;
+use x86_64::instructions::port::{inl, outl};
+
+/// The port that selects the configuration register to access.
+const CONFIG_ADDRESS: u16 = 0xcf8;
+
+/// The port that accesses the selected configuration register.
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// The vendor ID read from functions that don't exist.
+const NO_VENDOR: u16 = 0xffff;
+
+/// The offset of the command register.
+const COMMAND: u8 = 0x04;
+
+/// The offset of the header type.
+const HEADER_TYPE: u8 = 0x0e;
+
+/// The offset of the first base address register.
+const FIRST_BAR: u8 = 0x10;
+
+/// The number of registers in the configuration header.
+const HEADER_REGISTERS: usize = 16;
+
+/// The command bit that enables the I/O space.
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+
+/// The command bit that enables the memory space.
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+
+/// The command bit that lets the device access memory by itself.
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+/// Serializes the accesses to the two configuration ports.
+static CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+/// A function of a PCI device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Function {
+    /// The bus of the device.
+    pub bus: u8,
+    /// The device number on the bus.
+    pub device: u8,
+    /// The number of the function of the device.
+    pub function: u8
+}
+
+impl Function {
+    /// Returns the value for the address port to access the register.
+    fn config_address(&self, offset: u8) -> u32 {
+        1 << 31
+            | u32::from(self.bus) << 16
+            | u32::from(self.device) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset & !0b11)
+    }
+
+    /// Reads the configuration register that contains the given offset.
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        let _lock = CONFIG_LOCK.lock();
+
+        unsafe {
+            outl(CONFIG_ADDRESS, self.config_address(offset));
+            inl(CONFIG_DATA)
+        }
+    }
+
+    /// Writes the configuration register that contains the given offset.
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        let _lock = CONFIG_LOCK.lock();
+
+        unsafe {
+            outl(CONFIG_ADDRESS, self.config_address(offset));
+            outl(CONFIG_DATA, value);
+        }
+    }
+
+    /// Reads the 16 bit value at the given offset.
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> (8 * (offset & 0b10))) as u16
+    }
+
+    /// Returns the vendor ID of the function.
+    pub fn vendor_id(&self) -> u16 {
+        self.read_u16(0x00)
+    }
+
+    /// Returns the device ID of the function.
+    pub fn device_id(&self) -> u16 {
+        self.read_u16(0x02)
+    }
+
+    /// Returns the value of the base address register with the given index.
+    pub fn bar(&self, index: u8) -> u32 {
+        self.read_u32(FIRST_BAR + 4 * index)
+    }
+
+    /// Returns the base of the I/O ports of the given base address
+    /// register, if it describes I/O ports.
+    pub fn io_base(&self, index: u8) -> Option<u16> {
+        let bar = self.bar(index);
+
+        if bar & 1 == 1 {
+            Some((bar & !0b11) as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Sets the given bits in the command register.
+    pub fn enable(&self, command: u16) {
+        let value = self.read_u32(COMMAND);
+
+        // The upper half is the status register, where writing ones clears
+        // bits.
+        self.write_u32(COMMAND, (value & 0xffff) | u32::from(command));
+    }
+
+    /// Returns true if the device of the function has multiple functions.
+    fn is_multi_function(&self) -> bool {
+        (self.read_u32(HEADER_TYPE) >> 16) & 0x80 != 0
+    }
+}
+
+/// Returns all functions that exist.
+pub fn all() -> Vec<Function> {
+    let mut functions = Vec::new();
+
+    for bus in 0..256 {
+        for device in 0..32 {
+            let first = Function {
+                bus: bus as u8,
+                device,
+                function: 0
+            };
+
+            if first.vendor_id() == NO_VENDOR {
+                continue;
+            }
+
+            let function_count = if first.is_multi_function() { 8 } else { 1 };
+
+            for function in 0..function_count {
+                let function = Function { function, ..first };
+
+                if function.vendor_id() != NO_VENDOR {
+                    functions.push(function);
+                }
+            }
+        }
+    }
+
+    functions
+}
+
+/// Returns the first function with the given vendor and device ID.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<Function> {
+    all()
+        .into_iter()
+        .find(|function| function.vendor_id() == vendor_id && function.device_id() == device_id)
+}
+
+/// The saved configuration header of a function.
+pub struct SavedHeader {
+    /// The function the header belongs to.
+    function: Function,
+    /// The first 16 registers of the header.
+    registers: [u32; HEADER_REGISTERS]
+}
+
+/// Saves the configuration headers of all functions.
+///
+/// The firmware may reset them while the machine sleeps.
+pub fn save_headers() -> Vec<SavedHeader> {
+    all()
+        .into_iter()
+        .map(|function| {
+            let mut registers = [0; HEADER_REGISTERS];
+
+            for (index, register) in registers.iter_mut().enumerate() {
+                *register = function.read_u32(4 * index as u8);
+            }
+
+            SavedHeader {
+                function,
+                registers
+            }
+        })
+        .collect()
+}
+
+/// Restores the configuration headers that were saved before.
+pub fn restore_headers(headers: &[SavedHeader]) {
+    for header in headers {
+        let function = header.function;
+
+        // The cache line size and latency timer are in the lower half of the
+        // fourth register, the rest of it is read only.
+        let value = function.read_u32(0x0c);
+        function.write_u32(0x0c, (value & !0xffff) | (header.registers[3] & 0xffff));
+
+        for index in 4..HEADER_REGISTERS {
+            function.write_u32(4 * index as u8, header.registers[index]);
+        }
+
+        // The command register is restored last, once the base addresses are
+        // valid again.
+        function.write_u32(COMMAND, header.registers[1] & 0xffff);
+    }
+}
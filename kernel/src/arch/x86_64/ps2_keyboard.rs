@@ -0,0 +1,362 @@
+//! Sends commands to the PS/2 keyboard.
+//!
+//! A command consists of a command byte followed by a data byte. The keyboard
+//! acknowledges every byte with `ACK` or asks for it again with `RESEND`.
+//! These responses arrive through the keyboard interrupt just like scancodes,
+//! so the interrupt handler passes every received byte here first and only
+//! treats the bytes that aren't responses as scancodes.
+//!
+//! Only one command is sent at a time. Of every kind of command only the
+//! latest one waits to be sent, so toggling a lock key quickly doesn't queue
+//! up outdated LED states. A command that isn't acknowledged in time is given
+//! up, because the acknowledgement may have been lost while the interrupt was
+//! masked or there may be no keyboard at all.
+
+use core::time::Duration;
+use keyboard::{Locks, Repeat, CAPS_LOCK, NUM_LOCK, SCROLL_LOCK};
+use sync::time::Timestamp;
+use sync::{cpu_relax, Mutex};
+use x86_64::instructions::port::{inb, outb};
+
+/// The port that bytes are sent to the keyboard through.
+const DATA_PORT: u16 = 0x60;
+
+/// The port that holds the status of the controller.
+const STATUS_PORT: u16 = 0x64;
+
+/// The status bit that is set while the controller didn't pass on the last
+/// sent byte yet.
+const INPUT_FULL: u8 = 1 << 1;
+
+/// The number of times the status is checked before a byte is dropped.
+const WRITE_ATTEMPTS: usize = 10000;
+
+/// The command that sets the LEDs.
+const SET_LEDS: u8 = 0xed;
+
+/// The command that sets the repeat rate and delay.
+const SET_TYPEMATIC: u8 = 0xf3;
+
+/// The response to a byte that was received.
+const ACK: u8 = 0xfa;
+
+/// The response to a byte that has to be sent again.
+const RESEND: u8 = 0xfe;
+
+/// The number of times a byte is sent before the command is given up.
+const MAX_ATTEMPTS: usize = 3;
+
+/// The time after which a command that wasn't acknowledged is given up.
+const COMMAND_TIMEOUT_MS: u64 = 100;
+
+/// The repeat delays that the keyboard supports in milliseconds.
+const DELAYS: [u32; 4] = [250, 500, 750, 1000];
+
+/// The commands sent to the keyboard.
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new());
+
+/// A command that is being sent.
+struct Transfer {
+    /// The command byte and the data byte.
+    bytes: [u8; 2],
+    /// The number of bytes that were acknowledged.
+    acknowledged: usize,
+    /// The number of times the current byte was sent.
+    attempts: usize,
+    /// The time the command was started.
+    started_at: Timestamp
+}
+
+/// The state of the commands sent to the keyboard.
+struct Keyboard {
+    /// The command that is being sent.
+    transfer: Option<Transfer>,
+    /// The data byte of the LED command that waits to be sent.
+    pending_leds: Option<u8>,
+    /// The data byte of the typematic command that waits to be sent.
+    pending_typematic: Option<u8>
+}
+
+impl Keyboard {
+    /// Creates the state of a keyboard without commands.
+    const fn new() -> Keyboard {
+        Keyboard {
+            transfer: None,
+            pending_leds: None,
+            pending_typematic: None
+        }
+    }
+
+    /// Queues a command that sets the LEDs.
+    fn set_leds<F: FnMut(u8)>(&mut self, data: u8, now: Timestamp, send: &mut F) {
+        self.pending_leds = Some(data);
+        self.start_next(now, send);
+    }
+
+    /// Queues a command that sets the repeat rate and delay.
+    fn set_typematic<F: FnMut(u8)>(&mut self, data: u8, now: Timestamp, send: &mut F) {
+        self.pending_typematic = Some(data);
+        self.start_next(now, send);
+    }
+
+    /// Starts the next waiting command, unless another one is being sent.
+    ///
+    /// A command that was sent too long ago is given up first.
+    fn start_next<F: FnMut(u8)>(&mut self, now: Timestamp, send: &mut F) {
+        let timed_out = match self.transfer {
+            Some(ref transfer) => now.checked_sub(transfer.started_at).map_or(false, |time| {
+                time >= Duration::from_millis(COMMAND_TIMEOUT_MS)
+            }),
+            None => false
+        };
+
+        if timed_out {
+            self.transfer = None;
+        }
+
+        if self.transfer.is_some() {
+            return;
+        }
+
+        let bytes = if let Some(data) = self.pending_leds.take() {
+            [SET_LEDS, data]
+        } else if let Some(data) = self.pending_typematic.take() {
+            [SET_TYPEMATIC, data]
+        } else {
+            return;
+        };
+
+        send(bytes[0]);
+
+        self.transfer = Some(Transfer {
+            bytes,
+            acknowledged: 0,
+            attempts: 1,
+            started_at: now
+        });
+    }
+
+    /// Handles a byte received from the keyboard.
+    ///
+    /// Returns the byte if it isn't a response to a command.
+    fn receive<F: FnMut(u8)>(&mut self, byte: u8, now: Timestamp, send: &mut F) -> Option<u8> {
+        let finished = match self.transfer {
+            Some(ref mut transfer) => match byte {
+                ACK => {
+                    transfer.acknowledged += 1;
+                    transfer.attempts = 1;
+
+                    if transfer.acknowledged < transfer.bytes.len() {
+                        send(transfer.bytes[transfer.acknowledged]);
+                        false
+                    } else {
+                        true
+                    }
+                },
+                RESEND if transfer.attempts < MAX_ATTEMPTS => {
+                    transfer.attempts += 1;
+                    send(transfer.bytes[transfer.acknowledged]);
+                    false
+                },
+                RESEND => {
+                    warn!(
+                        "The keyboard rejected the command {:#x}.",
+                        transfer.bytes[0]
+                    );
+                    true
+                },
+                _ => return Some(byte)
+            },
+            None => return Some(byte)
+        };
+
+        if finished {
+            self.transfer = None;
+            self.start_next(now, send);
+        }
+
+        None
+    }
+}
+
+/// Handles a byte received from the keyboard.
+///
+/// Returns the byte if it is a scancode rather than a response to a command.
+pub fn receive(byte: u8) -> Option<u8> {
+    KEYBOARD
+        .lock()
+        .receive(byte, Timestamp::get_current(), &mut write_data)
+}
+
+/// Lights the LEDs of the given lock keys.
+pub fn set_leds(locks: Locks) {
+    KEYBOARD
+        .lock()
+        .set_leds(leds_byte(locks), Timestamp::get_current(), &mut write_data);
+}
+
+/// Sets the repeat rate and delay to the supported values closest to the
+/// given ones.
+pub fn set_repeat(repeat: Repeat) {
+    KEYBOARD.lock().set_typematic(
+        typematic_byte(repeat),
+        Timestamp::get_current(),
+        &mut write_data
+    );
+}
+
+/// Sends the byte to the keyboard.
+///
+/// The byte is dropped if the controller doesn't accept it in time. The
+/// command it belongs to is given up later then.
+fn write_data(byte: u8) {
+    for _ in 0..WRITE_ATTEMPTS {
+        unsafe {
+            if inb(STATUS_PORT) & INPUT_FULL == 0 {
+                outb(DATA_PORT, byte);
+                return;
+            }
+        }
+
+        cpu_relax();
+    }
+}
+
+/// Returns the data byte of the LED command for the lock keys.
+fn leds_byte(locks: Locks) -> u8 {
+    let mut byte = 0;
+
+    if locks.contains(SCROLL_LOCK) {
+        byte |= 1 << 0;
+    }
+    if locks.contains(NUM_LOCK) {
+        byte |= 1 << 1;
+    }
+    if locks.contains(CAPS_LOCK) {
+        byte |= 1 << 2;
+    }
+
+    byte
+}
+
+/// Returns the repeat rate in millihertz that the rate bits of the typematic
+/// command select.
+///
+/// The period is `(8 + A) * 2^B * 4.17 ms`, where `A` are the lower three bits
+/// and `B` the upper two.
+fn rate_millihertz(rate_bits: u8) -> u64 {
+    let period_micros = ((8 + u64::from(rate_bits & 0b111)) << (rate_bits >> 3)) * 4170;
+
+    1_000_000_000 / period_micros
+}
+
+/// Returns the data byte of the typematic command that comes closest to the
+/// repeat rate and delay.
+fn typematic_byte(repeat: Repeat) -> u8 {
+    let wanted_millihertz = i64::from(repeat.rate) * 1000;
+
+    let rate_bits = (0..32)
+        .min_by_key(|&bits| (rate_millihertz(bits) as i64 - wanted_millihertz).abs())
+        .unwrap();
+    let delay_bits = (0..DELAYS.len() as u8)
+        .min_by_key(|&bits| (i64::from(DELAYS[bits as usize]) - i64::from(repeat.delay)).abs())
+        .unwrap();
+
+    delay_bits << 5 | rate_bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::Vec;
+
+    /// Returns the time the given number of milliseconds after boot.
+    fn at(millis: u64) -> Timestamp {
+        Timestamp::from_duration(Duration::from_millis(millis))
+    }
+
+    /// Returns the repeat settings with the rate and the delay.
+    fn repeat(rate: u32, delay: u32) -> Repeat {
+        Repeat { rate, delay }
+    }
+
+    /// Tests that the bytes of a command are sent once the previous one was
+    /// acknowledged.
+    #[test]
+    fn test_acknowledged_command() {
+        let mut keyboard = Keyboard::new();
+        let mut sent = Vec::new();
+
+        {
+            let mut send = |byte| sent.push(byte);
+
+            keyboard.set_leds(0b100, at(0), &mut send);
+            assert_eq!(keyboard.receive(0x1e, at(1), &mut send), Some(0x1e));
+            assert_eq!(keyboard.receive(ACK, at(1), &mut send), None);
+            assert_eq!(keyboard.receive(ACK, at(2), &mut send), None);
+            assert_eq!(keyboard.receive(ACK, at(3), &mut send), Some(ACK));
+        }
+
+        assert_eq!(sent, [SET_LEDS, 0b100]);
+    }
+
+    /// Tests that bytes are sent again and the command is given up after too
+    /// many attempts.
+    #[test]
+    fn test_resend() {
+        let mut keyboard = Keyboard::new();
+        let mut sent = Vec::new();
+
+        {
+            let mut send = |byte| sent.push(byte);
+
+            keyboard.set_typematic(0x2b, at(0), &mut send);
+            keyboard.receive(ACK, at(1), &mut send);
+            keyboard.receive(RESEND, at(1), &mut send);
+            keyboard.receive(RESEND, at(1), &mut send);
+            keyboard.receive(RESEND, at(1), &mut send);
+        }
+
+        assert_eq!(sent, [SET_TYPEMATIC, 0x2b, 0x2b, 0x2b]);
+        assert!(keyboard.transfer.is_none());
+    }
+
+    /// Tests that only the latest waiting command of a kind is sent and that
+    /// a command without acknowledgement is given up.
+    #[test]
+    fn test_waiting_commands() {
+        let mut keyboard = Keyboard::new();
+        let mut sent = Vec::new();
+
+        {
+            let mut send = |byte| sent.push(byte);
+
+            keyboard.set_leds(1, at(0), &mut send);
+            keyboard.set_leds(2, at(10), &mut send);
+            keyboard.set_typematic(0x20, at(20), &mut send);
+            keyboard.set_leds(3, at(30), &mut send);
+            keyboard.set_leds(4, at(COMMAND_TIMEOUT_MS), &mut send);
+            keyboard.receive(ACK, at(101), &mut send);
+            keyboard.receive(ACK, at(102), &mut send);
+        }
+
+        assert_eq!(sent, [SET_LEDS, SET_LEDS, 4, SET_TYPEMATIC]);
+    }
+
+    /// Tests that the lock keys are mapped to their LEDs.
+    #[test]
+    fn test_leds_byte() {
+        assert_eq!(leds_byte(Locks::empty()), 0);
+        assert_eq!(leds_byte(SCROLL_LOCK), 0b001);
+        assert_eq!(leds_byte(NUM_LOCK | CAPS_LOCK), 0b110);
+    }
+
+    /// Tests that the closest supported rate and delay are chosen.
+    #[test]
+    fn test_typematic_byte() {
+        assert_eq!(typematic_byte(repeat(30, 250)), 0x00);
+        assert_eq!(typematic_byte(repeat(2, 1000)), 0x7f);
+        assert_eq!(typematic_byte(repeat(11, 500)), 0x2b);
+        assert_eq!(typematic_byte(repeat(20, 600)), 0x24);
+    }
+}
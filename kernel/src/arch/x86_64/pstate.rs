@@ -0,0 +1,80 @@
+//! Reads and selects the performance states of Intel CPUs.
+//!
+//! With Enhanced SpeedStep, the ratio of the core clock to the 100 MHz bus
+//! clock is requested by writing it to `IA32_PERF_CTL`. The range of
+//! supported ratios comes from `MSR_PLATFORM_INFO`.
+//!
+//! The `_PSS` objects of the ACPI tables aren't used, because they are
+//! usually defined in SSDTs that are only loaded by running AML code.
+
+use alloc::Vec;
+use cpufreq::PerformanceState;
+use raw_cpuid::CpuId;
+use x86_64::instructions::{rdmsr, wrmsr};
+
+/// The MSR that holds the range of supported ratios.
+const MSR_PLATFORM_INFO: u32 = 0xce;
+
+/// The MSR that requests a performance state.
+const IA32_PERF_CTL: u32 = 0x199;
+
+/// The MSR that enables Enhanced SpeedStep.
+const IA32_MISC_ENABLE: u32 = 0x1a0;
+
+/// The bit of `IA32_MISC_ENABLE` that enables Enhanced SpeedStep.
+const MISC_ENABLE_EIST: u64 = 1 << 16;
+
+/// The frequency of the bus clock the ratios refer to.
+const BUS_CLOCK_MHZ: u32 = 100;
+
+/// The family of the Intel CPUs that have `MSR_PLATFORM_INFO`.
+const INTEL_FAMILY: u8 = 6;
+
+/// Returns the performance states of the CPU, the fastest one first.
+///
+/// Returns no states if the CPU can't change its frequency this way.
+pub fn get_states() -> Vec<PerformanceState> {
+    let cpuid = CpuId::new();
+    let is_intel = cpuid
+        .get_vendor_info()
+        .map_or(false, |vendor| vendor.as_string() == "GenuineIntel");
+    let has_eist = cpuid.get_feature_info().map_or(false, |features| {
+        features.has_eist() && features.family_id() == INTEL_FAMILY
+    });
+
+    // Reading the MSRs on other CPUs would fault.
+    if !is_intel || !has_eist {
+        return Vec::new();
+    }
+
+    let platform_info = unsafe {
+        let misc_enable = rdmsr(IA32_MISC_ENABLE);
+        if misc_enable & MISC_ENABLE_EIST == 0 {
+            wrmsr(IA32_MISC_ENABLE, misc_enable | MISC_ENABLE_EIST);
+        }
+
+        rdmsr(MSR_PLATFORM_INFO)
+    };
+
+    let max_ratio = ((platform_info >> 8) & 0xff) as u32;
+    let min_ratio = ((platform_info >> 40) & 0xff) as u32;
+
+    if min_ratio == 0 || min_ratio > max_ratio {
+        return Vec::new();
+    }
+
+    (min_ratio..max_ratio + 1)
+        .rev()
+        .map(|ratio| PerformanceState {
+            frequency_mhz: ratio * BUS_CLOCK_MHZ,
+            control: u64::from(ratio) << 8
+        })
+        .collect()
+}
+
+/// Requests the given performance state for the current CPU.
+pub fn set_state(state: &PerformanceState) {
+    unsafe {
+        wrmsr(IA32_PERF_CTL, state.control);
+    }
+}
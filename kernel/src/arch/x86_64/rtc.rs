@@ -0,0 +1,252 @@
+//! Reads the date and time from the real time clock in the CMOS.
+//!
+//! The RTC keeps the wall clock time while the machine is off. It is only
+//! read once during boot, afterwards the time is advanced by the kernel
+//! clock.
+
+use sync::InterruptGuard;
+use x86_64::instructions::port::{inb, outb};
+
+/// The register that holds the seconds.
+const SECONDS_REGISTER: u8 = 0x00;
+
+/// The register that holds the minutes.
+const MINUTES_REGISTER: u8 = 0x02;
+
+/// The register that holds the hours.
+const HOURS_REGISTER: u8 = 0x04;
+
+/// The register that holds the day of the month.
+const DAY_REGISTER: u8 = 0x07;
+
+/// The register that holds the month.
+const MONTH_REGISTER: u8 = 0x08;
+
+/// The register that holds the last two digits of the year.
+const YEAR_REGISTER: u8 = 0x09;
+
+/// Status register a, which reports running updates.
+const STATUS_A_REGISTER: u8 = 0x0a;
+
+/// Status register b, which holds the format of the values.
+const STATUS_B_REGISTER: u8 = 0x0b;
+
+/// Set in status register a while the RTC updates its values.
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+
+/// Set in status register b if the hours use the 24 hour format.
+const FORMAT_24_HOURS: u8 = 0x02;
+
+/// Set in status register b if the values are binary instead of BCD.
+const FORMAT_BINARY: u8 = 0x04;
+
+/// Set in the hours in the 12 hour format for times after noon.
+const HOUR_PM: u8 = 0x80;
+
+/// The maximum number of attempts to read consistent values.
+const MAX_READ_ATTEMPTS: usize = 16;
+
+/// The values of the RTC registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    /// The seconds.
+    seconds: u8,
+    /// The minutes.
+    minutes: u8,
+    /// The hours.
+    hours: u8,
+    /// The day of the month.
+    day: u8,
+    /// The month.
+    month: u8,
+    /// The last two digits of the year.
+    year: u8
+}
+
+/// Returns the number of seconds since the Unix epoch.
+///
+/// Returns `None` if the RTC didn't report a consistent, valid time.
+pub fn read_unix_time() -> Option<u64> {
+    let interrupts = InterruptGuard::new();
+
+    let (time, status_b) = unsafe {
+        let nmi_bit = inb(0x70) & 0x80;
+        let mut result = None;
+
+        // The values may change between reading two of them, so they are read
+        // until two reads agree.
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let first = read_raw_time(nmi_bit);
+            let second = read_raw_time(nmi_bit);
+
+            if first.is_some() && first == second {
+                result = first;
+                break;
+            }
+        }
+
+        let status_b = read_register(nmi_bit, STATUS_B_REGISTER);
+
+        (result, status_b)
+    };
+
+    drop(interrupts);
+
+    time.and_then(|time| to_unix_time(time, status_b))
+}
+
+/// Reads the given CMOS register, keeping the NMI bit.
+unsafe fn read_register(nmi_bit: u8, register: u8) -> u8 {
+    outb(0x70, nmi_bit | register);
+    inb(0x71)
+}
+
+/// Reads the time registers, unless an update is in progress.
+unsafe fn read_raw_time(nmi_bit: u8) -> Option<RawTime> {
+    if read_register(nmi_bit, STATUS_A_REGISTER) & UPDATE_IN_PROGRESS != 0 {
+        return None;
+    }
+
+    Some(RawTime {
+        seconds: read_register(nmi_bit, SECONDS_REGISTER),
+        minutes: read_register(nmi_bit, MINUTES_REGISTER),
+        hours: read_register(nmi_bit, HOURS_REGISTER),
+        day: read_register(nmi_bit, DAY_REGISTER),
+        month: read_register(nmi_bit, MONTH_REGISTER),
+        year: read_register(nmi_bit, YEAR_REGISTER)
+    })
+}
+
+/// Converts a BCD encoded value to binary.
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Converts the register values in the format of status register b to the
+/// number of seconds since the Unix epoch.
+///
+/// The year is assumed to lie in the 21st century.
+fn to_unix_time(time: RawTime, status_b: u8) -> Option<u64> {
+    let pm = time.hours & HOUR_PM != 0;
+    let hours = time.hours & !HOUR_PM;
+
+    let convert = |value| {
+        if status_b & FORMAT_BINARY != 0 {
+            value
+        } else {
+            from_bcd(value)
+        }
+    };
+
+    let mut hours = convert(hours);
+
+    if status_b & FORMAT_24_HOURS == 0 {
+        // 12 am is midnight and 12 pm is noon.
+        hours %= 12;
+
+        if pm {
+            hours += 12;
+        }
+    }
+
+    let seconds = convert(time.seconds);
+    let minutes = convert(time.minutes);
+    let day = convert(time.day);
+    let month = convert(time.month);
+    let year = 2000 + convert(time.year) as u64;
+
+    if seconds > 59 || minutes > 59 || hours > 23 || day < 1 || day > 31 || month < 1 || month > 12
+    {
+        return None;
+    }
+
+    let days = days_since_epoch(year, month as u64, day as u64);
+
+    Some(((days * 24 + hours as u64) * 60 + minutes as u64) * 60 + seconds as u64)
+}
+
+/// Returns the number of days between the Unix epoch and the given date.
+///
+/// The date must not lie before the epoch.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    // Counting the years from March on puts the leap day at the end.
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let days = year * 365 + year / 4 - year / 100 + year / 400 + day_of_year;
+
+    // The number of days from the 1st of March of the year 0 to the epoch.
+    days - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns BCD register values with the given hours and date.
+    fn raw_time(hours: u8, day: u8, month: u8, year: u8) -> RawTime {
+        RawTime {
+            seconds: 0x30,
+            minutes: 0x15,
+            hours,
+            day,
+            month,
+            year
+        }
+    }
+
+    /// Tests that dates are converted to the right number of days.
+    #[test]
+    fn days() {
+        assert_eq!(days_since_epoch(1970, 1, 1), 0);
+        assert_eq!(days_since_epoch(2000, 3, 1), 11_017);
+        assert_eq!(days_since_epoch(2024, 2, 29), 19_782);
+        assert_eq!(days_since_epoch(2024, 12, 31), 20_088);
+    }
+
+    /// Tests that BCD values in the 24 hour format are converted.
+    #[test]
+    fn bcd_24_hours() {
+        let time = raw_time(0x13, 0x29, 0x02, 0x24);
+
+        assert_eq!(to_unix_time(time, FORMAT_24_HOURS), Some(1_709_212_530));
+    }
+
+    /// Tests that binary values in the 12 hour format are converted.
+    #[test]
+    fn binary_12_hours() {
+        let afternoon = RawTime {
+            seconds: 30,
+            minutes: 15,
+            hours: HOUR_PM | 1,
+            day: 29,
+            month: 2,
+            year: 24
+        };
+        let midnight = RawTime {
+            hours: 12,
+            ..afternoon
+        };
+
+        assert_eq!(to_unix_time(afternoon, FORMAT_BINARY), Some(1_709_212_530));
+        assert_eq!(to_unix_time(midnight, FORMAT_BINARY), Some(1_709_165_730));
+    }
+
+    /// Tests that invalid values are rejected.
+    #[test]
+    fn invalid_time() {
+        let invalid_times = [
+            raw_time(0x25, 0x01, 0x01, 0x24),
+            raw_time(0x12, 0x00, 0x01, 0x24),
+            raw_time(0x12, 0x01, 0x13, 0x24)
+        ];
+
+        for &time in invalid_times.iter() {
+            assert_eq!(to_unix_time(time, FORMAT_24_HOURS), None);
+        }
+    }
+}
@@ -1,60 +1,578 @@
 //! This module handles communication over serial ports.
+//!
+//! The ports are found in the BIOS data area and configured from the kernel
+//! command line. `serial=<settings>` applies to all ports and
+//! `serial.ttyS<n>=<settings>` to a single port. The settings consist of the
+//! baud rate, optionally followed by the number of data bits, the parity (`n`,
+//! `o` or `e`) and the number of stop bits, for example `115200,8n1`.
+//!
+//! Once interrupts are set up, output is queued in a ring buffer per port and
+//! sent by the transmit interrupt, so writers don't wait for the UART. Output
+//! written with interrupts disabled is sent directly, because the interrupt
+//! couldn't arrive.
+//!
+//! The receive interrupt of COM1 passes the received bytes to the kernel's
+//! debug shell. The other ports only transmit.
+//!
+//! The ports are available as `/dev/ttyS0` to `/dev/ttyS3`.
 
+use super::X86_64;
+use alloc::boxed::Box;
+use arch::Architecture;
+use boot;
 use core::fmt;
+use devfs;
+use device::DeviceHandle;
+use file_handle::{FileError, FileHandle, PollEvents, Result, SeekFrom, POLL_OUT};
+use keyboard::Queue;
+use sync::Mutex;
 use x86_64::instructions::port::{inb, outb};
 
-/// Represents a serial port that can be read from and written to.
+/// The maximum number of serial ports.
+pub const PORT_COUNT: usize = 4;
+
+/// The physical address of the port addresses in the BIOS data area.
+const BIOS_DATA_AREA_PORTS: usize = 0x400;
+
+/// The usual addresses of the ports, used if the BIOS data area names none.
+const STANDARD_PORTS: [u16; PORT_COUNT] = [0x3f8, 0x2f8, 0x3e8, 0x2e8];
+
+/// The IRQs of the ports.
+///
+/// By convention COM1 and COM3 share IRQ4 and COM2 and COM4 share IRQ3.
+pub const PORT_IRQS: [u8; PORT_COUNT] = [4, 3, 4, 3];
+
+/// The names of the device files of the ports.
+const DEVICE_NAMES: [&str; PORT_COUNT] = ["ttyS0", "ttyS1", "ttyS2", "ttyS3"];
+
+/// The command line keys for the settings of the individual ports.
+const OPTION_KEYS: [&str; PORT_COUNT] = [
+    "serial.ttyS0",
+    "serial.ttyS1",
+    "serial.ttyS2",
+    "serial.ttyS3"
+];
+
+/// The frequency that the baud rate divisor divides.
+const UART_CLOCK_RATE: u32 = 115200;
+
+/// The size of the transmit buffer of a port.
+const TRANSMIT_BUFFER_SIZE: usize = 4096;
+
+/// The number of bytes that fit into the transmit FIFO of the UART.
+const FIFO_SIZE: usize = 16;
+
+/// The offset of the data register.
+const DATA: u16 = 0;
+
+/// The offset of the interrupt enable register.
+const INTERRUPT_ENABLE: u16 = 1;
+
+/// The offset of the FIFO control register, which reads as the interrupt
+/// identification register.
+const FIFO_CONTROL: u16 = 2;
+
+/// The offset of the line control register.
+const LINE_CONTROL: u16 = 3;
+
+/// The offset of the modem control register.
+const MODEM_CONTROL: u16 = 4;
+
+/// The offset of the line status register.
+const LINE_STATUS: u16 = 5;
+
+/// The offset of the scratch register.
+const SCRATCH: u16 = 7;
+
+/// The interrupt enable bit for received data.
+const RECEIVED_DATA_INTERRUPT: u8 = 1 << 0;
+
+/// The interrupt enable bit for an empty transmit buffer.
+const TRANSMIT_EMPTY_INTERRUPT: u8 = 1 << 1;
+
+/// The line control bit that maps the divisor to the first two registers.
+const DIVISOR_LATCH: u8 = 1 << 7;
+
+/// The line status bit that is set while received data can be read.
+const DATA_READY: u8 = 1 << 0;
+
+/// The line status bit that is set while the transmit buffer is empty.
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// The index of the port whose input goes to the debug shell.
+const INPUT_PORT: usize = 0;
+
+/// The serial ports.
+///
+/// COM1 is assumed to exist until the ports are detected, so early messages
+/// aren't lost.
+static PORTS: Mutex<[Option<SerialPort>; PORT_COUNT]> = Mutex::new([
+    Some(SerialPort::new(0x3f8, 4, Config::DEFAULT)),
+    None,
+    None,
+    None
+]);
+
+/// The parity of the characters sent over a serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity bit is sent.
+    None,
+    /// The parity bit makes the number of set bits odd.
+    Odd,
+    /// The parity bit makes the number of set bits even.
+    Even
+}
+
+/// The settings of a serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// The number of bits per second.
+    pub baud_rate: u32,
+    /// The number of data bits per character, between five and eight.
+    pub data_bits: u8,
+    /// The parity of the characters.
+    pub parity: Parity,
+    /// The number of stop bits, one or two.
+    pub stop_bits: u8
+}
+
+impl Config {
+    /// The settings used if the command line names none.
+    pub const DEFAULT: Config = Config {
+        baud_rate: 38400,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1
+    };
+
+    /// Parses settings of the form `<baud rate>[,<data bits><parity><stop bits>]`.
+    ///
+    /// Returns `None` if the UART can't use the settings.
+    pub fn parse(string: &str) -> Option<Config> {
+        let mut parts = string.splitn(2, ',');
+        let baud_rate: u32 = parts.next()?.parse().ok()?;
+
+        if baud_rate == 0 || UART_CLOCK_RATE % baud_rate != 0 {
+            return None;
+        }
+
+        let mut config = Config {
+            baud_rate,
+            ..Config::DEFAULT
+        };
+
+        if let Some(format) = parts.next() {
+            let format = format.as_bytes();
+
+            if format.len() != 3 {
+                return None;
+            }
+
+            config.data_bits = match format[0] {
+                digit if digit >= b'5' && digit <= b'8' => digit - b'0',
+                _ => return None
+            };
+            config.parity = match format[1] {
+                b'n' => Parity::None,
+                b'o' => Parity::Odd,
+                b'e' => Parity::Even,
+                _ => return None
+            };
+            config.stop_bits = match format[2] {
+                b'1' | b'2' => format[2] - b'0',
+                _ => return None
+            };
+        }
+
+        Some(config)
+    }
+
+    /// Returns the baud rate divisor.
+    fn divisor(&self) -> u16 {
+        (UART_CLOCK_RATE / self.baud_rate) as u16
+    }
+
+    /// Returns the value of the line control register.
+    fn line_control(&self) -> u8 {
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Odd => 0x08,
+            Parity::Even => 0x18
+        };
+
+        (self.data_bits - 5) | (self.stop_bits - 1) << 2 | parity
+    }
+}
+
+/// Represents a serial port that can be written to.
 pub struct SerialPort {
     /// The IO-Port that the serial port is located at.
-    port: u16
+    port: u16,
+    /// The IRQ of the serial port.
+    irq: u8,
+    /// The settings of the serial port.
+    config: Config,
+    /// Whether the transmit interrupt sends the buffered output.
+    interrupt_driven: bool,
+    /// Whether the receive interrupt is enabled.
+    receiving: bool,
+    /// The output that wasn't sent yet.
+    buffer: [u8; TRANSMIT_BUFFER_SIZE],
+    /// The index of the oldest byte in the buffer.
+    start: usize,
+    /// The number of bytes in the buffer.
+    length: usize
 }
 
 impl SerialPort {
     /// Creates a new serial port.
-    pub const fn new(port: u16) -> SerialPort {
-        SerialPort { port }
+    const fn new(port: u16, irq: u8, config: Config) -> SerialPort {
+        SerialPort {
+            port,
+            irq,
+            config,
+            interrupt_driven: false,
+            receiving: false,
+            buffer: [0; TRANSMIT_BUFFER_SIZE],
+            start: 0,
+            length: 0
+        }
     }
 
-    /// Initializes the serial port.
+    /// Checks whether a UART exists at the given IO-Port.
+    fn exists(port: u16) -> bool {
+        unsafe {
+            outb(port + SCRATCH, 0x5a);
+            inb(port + SCRATCH) == 0x5a
+        }
+    }
+
+    /// Initializes the serial port with its settings.
     ///
     /// According to the [OS-dev wiki](https://wiki.osdev.org/Serial_ports).
-    pub fn init(&mut self) {
+    fn init(&mut self) {
+        let divisor = self.config.divisor();
+
         unsafe {
-            outb(self.port + 1, 0x00); // Disable all interrupts
-            outb(self.port + 3, 0x80); // Enable DLAB (set baud rate divisor)
-            outb(self.port + 0, 0x03); // Set divisor to 3 (lo byte) 38400 baud
-            outb(self.port + 1, 0x00); //                  (hi byte)
-            outb(self.port + 3, 0x03); // 8 bits, no parity, one stop bit
-            outb(self.port + 2, 0xC7); // Enable FIFO, clear them, with 14-byte threshold
-            outb(self.port + 4, 0x0B); // IRQs enabled, RTS/DSR set
+            outb(self.port + INTERRUPT_ENABLE, 0x00); // Disable all interrupts
+            outb(self.port + LINE_CONTROL, DIVISOR_LATCH); // Set the baud rate divisor
+            outb(self.port + DATA, divisor as u8); // (lo byte)
+            outb(self.port + INTERRUPT_ENABLE, (divisor >> 8) as u8); // (hi byte)
+            outb(self.port + LINE_CONTROL, self.config.line_control());
+            outb(self.port + FIFO_CONTROL, 0xC7); // Enable and clear FIFOs, 14-byte threshold
+            outb(self.port + MODEM_CONTROL, 0x0B); // IRQs enabled, RTS/DSR set
         }
     }
 
     /// Checks if the last trasmission is fully finished.
     fn transmission_ready(&self) -> bool {
-        unsafe { inb(self.port + 5) & 0x20 != 1 }
+        unsafe { inb(self.port + LINE_STATUS) & TRANSMIT_EMPTY != 0 }
     }
 
-    /// Transmits a character on the serial port.
-    pub fn transmit(&mut self, data: u8) {
+    /// Transmits a character on the serial port, waiting until it can be
+    /// sent.
+    fn transmit(&mut self, data: u8) {
         while !self.transmission_ready() {}
 
         unsafe {
-            outb(self.port, data);
+            outb(self.port + DATA, data);
         }
     }
-}
 
-impl fmt::Write for SerialPort {
-    fn write_str(&mut self, string: &str) -> fmt::Result {
-        for byte in string.bytes() {
+    /// Writes the bytes to the serial port.
+    ///
+    /// The bytes are only buffered if the transmit interrupt can send them.
+    fn write(&mut self, bytes: &[u8], buffered: bool) {
+        if !self.interrupt_driven || !buffered {
+            // The buffered output must be sent first to keep the order.
+            self.flush();
+
+            for &byte in bytes {
+                self.transmit(byte);
+            }
+
+            return;
+        }
+
+        for &byte in bytes {
+            if self.length == TRANSMIT_BUFFER_SIZE {
+                // Make room by sending the oldest byte directly.
+                let oldest = self.pop().unwrap();
+                self.transmit(oldest);
+            }
+
+            let end = (self.start + self.length) % TRANSMIT_BUFFER_SIZE;
+            self.buffer[end] = byte;
+            self.length += 1;
+        }
+
+        self.fill_fifo();
+    }
+
+    /// Sends all buffered output, waiting until it was sent.
+    fn flush(&mut self) {
+        while let Some(byte) = self.pop() {
             self.transmit(byte);
         }
+    }
+
+    /// Handles the interrupt of the serial port.
+    ///
+    /// The received bytes are passed to `receive`.
+    fn handle_interrupt<F: FnMut(u8)>(&mut self, mut receive: F) {
+        // Reading the interrupt identification acknowledges the interrupt.
+        unsafe {
+            inb(self.port + FIFO_CONTROL);
+        }
+
+        // Reading all received bytes clears the receive interrupt.
+        while self.receiving && unsafe { inb(self.port + LINE_STATUS) } & DATA_READY != 0 {
+            receive(unsafe { inb(self.port + DATA) });
+        }
+
+        self.fill_fifo();
+    }
+
+    /// Moves buffered output into the transmit FIFO if it is empty and
+    /// enables the transmit interrupt while output remains.
+    ///
+    /// The receive interrupt stays enabled if the port receives.
+    fn fill_fifo(&mut self) {
+        if self.transmission_ready() {
+            for _ in 0..FIFO_SIZE {
+                match self.pop() {
+                    Some(byte) => unsafe { outb(self.port + DATA, byte) },
+                    None => break
+                }
+            }
+        }
+
+        let mut interrupts = if self.length > 0 {
+            TRANSMIT_EMPTY_INTERRUPT
+        } else {
+            0
+        };
+
+        if self.receiving {
+            interrupts |= RECEIVED_DATA_INTERRUPT;
+        }
+
+        unsafe {
+            outb(self.port + INTERRUPT_ENABLE, interrupts);
+        }
+    }
+
+    /// Removes the oldest byte from the buffer.
+    fn pop(&mut self) -> Option<u8> {
+        if self.length == 0 {
+            return None;
+        }
+
+        let byte = self.buffer[self.start];
+        self.start = (self.start + 1) % TRANSMIT_BUFFER_SIZE;
+        self.length -= 1;
+
+        Some(byte)
+    }
+}
+
+/// Writes formatted output to a serial port.
+struct Output<'a> {
+    /// The serial port.
+    port: &'a mut SerialPort,
+    /// Whether the output may be buffered.
+    buffered: bool
+}
+
+impl<'a> fmt::Write for Output<'a> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        self.port.write(string.as_bytes(), self.buffered);
 
         Ok(())
     }
 }
 
+/// A serial port as a file.
+struct SerialDevice {
+    /// The device with the index of the serial port.
+    device: DeviceHandle<usize>
+}
+
+impl FileHandle for SerialDevice {
+    fn seek(&mut self, _position: SeekFrom) -> Result<u64> {
+        Err(FileError::NotSupported)
+    }
+
+    fn read(&mut self, _buffer: &mut [u8]) -> Result<()> {
+        Err(FileError::NotSupported)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        self.device.operate(|&index| write(index, buffer))
+    }
+
+    fn len(&mut self) -> u64 {
+        0
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        POLL_OUT
+    }
+}
+
+/// Detects and initializes the serial ports.
+pub fn init() {
+    assert_has_not_been_called!("The serial ports should only be initialized once.");
+
+    let bios_ports = unsafe { *(to_virtual!(BIOS_DATA_AREA_PORTS) as *const [u16; PORT_COUNT]) };
+    let addresses = if bios_ports.iter().any(|&port| port != 0) {
+        bios_ports
+    } else {
+        STANDARD_PORTS
+    };
+
+    // Logging while the ports are locked would deadlock, so the settings are
+    // read first.
+    let default_config = config_from_command_line("serial").unwrap_or(Config::DEFAULT);
+    let mut configs = [default_config; PORT_COUNT];
+    for (config, key) in configs.iter_mut().zip(OPTION_KEYS.iter()) {
+        if let Some(port_config) = config_from_command_line(key) {
+            *config = port_config;
+        }
+    }
+
+    let mut found = [false; PORT_COUNT];
+
+    {
+        let mut ports = PORTS.lock();
+
+        for index in 0..PORT_COUNT {
+            let address = addresses[index];
+
+            ports[index] = if address != 0 && SerialPort::exists(address) {
+                let mut port = SerialPort::new(address, PORT_IRQS[index], configs[index]);
+                port.init();
+                found[index] = true;
+
+                Some(port)
+            } else {
+                None
+            };
+        }
+    }
+
+    for index in (0..PORT_COUNT).filter(|&index| found[index]) {
+        debug!(
+            "Found {} at {:#x} (IRQ {}, {:?}).",
+            DEVICE_NAMES[index], addresses[index], PORT_IRQS[index], configs[index]
+        );
+    }
+}
+
+/// Lets the transmit interrupts send the output, enables the input of COM1
+/// and makes the ports available as device files.
+///
+/// This must be called after the interrupts are set up.
+pub fn late_init() {
+    assert_has_not_been_called!("The serial ports should only be initialized once.");
+
+    for (index, port) in PORTS.lock().iter_mut().enumerate() {
+        if let Some(ref mut port) = *port {
+            port.interrupt_driven = true;
+            port.receiving = index == INPUT_PORT;
+            port.fill_fifo();
+
+            let device = DeviceHandle::new(index);
+            devfs::register(
+                DEVICE_NAMES[index],
+                Box::new(move || {
+                    Box::new(SerialDevice {
+                        device: device.clone()
+                    }) as Box<FileHandle>
+                })
+            );
+        }
+    }
+}
+
+/// Handles an interrupt on the given IRQ.
+///
+/// All ports that share the IRQ are checked.
+pub fn interrupt(irq: u8) {
+    let mut input = Queue::new();
+
+    for port in PORTS.lock().iter_mut() {
+        if let Some(ref mut port) = *port {
+            if port.irq == irq {
+                port.handle_interrupt(|byte| {
+                    input.push(byte);
+                });
+            }
+        }
+    }
+
+    // The input is passed on once the ports are unlocked, so that its
+    // handler may write to them.
+    while let Some(byte) = input.pop() {
+        ::interrupts::serial_input(byte);
+    }
+}
+
+/// Writes the bytes to the serial port with the given index.
+pub fn write(index: usize, bytes: &[u8]) {
+    let buffered = X86_64::get_interrupt_state();
+
+    if let Some(ref mut port) = PORTS.lock()[index] {
+        port.write(bytes, buffered);
+    }
+}
+
+/// Writes the formatted output to the first serial port.
+pub fn write_fmt(args: fmt::Arguments) {
+    let buffered = X86_64::get_interrupt_state();
+
+    let mut ports = PORTS.lock();
+
+    if let Some(port) = ports.iter_mut().filter_map(|port| port.as_mut()).next() {
+        fmt::Write::write_fmt(&mut Output { port, buffered }, args).unwrap();
+    }
+}
+
+/// Sets the ports up again after the machine woke up.
+pub fn resume() {
+    for port in PORTS.lock().iter_mut() {
+        if let Some(ref mut port) = *port {
+            port.init();
+
+            if port.interrupt_driven {
+                port.fill_fifo();
+            }
+        }
+    }
+}
+
+/// Sends all buffered output, waiting until it was sent.
+pub fn flush() {
+    for port in PORTS.lock().iter_mut() {
+        if let Some(ref mut port) = *port {
+            port.flush();
+        }
+    }
+}
+
+/// Reads the settings with the given key from the command line.
+fn config_from_command_line(key: &str) -> Option<Config> {
+    let value = boot::get_option_value(key)?;
+    let config = Config::parse(value);
+
+    if config.is_none() {
+        warn!("Invalid serial settings {}={}.", key, value);
+    }
+
+    config
+}
+
 /// Prints the given line to the serial port.
 ///
 /// It uses the arguments passed to it and prints the string with the
@@ -73,6 +591,47 @@ macro_rules! serial_println {
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => ({
-        $crate::arch::x86_64::COM1.lock().write_fmt(format_args!($($arg)*)).unwrap();
+        $crate::arch::x86_64::serial::write_fmt(format_args!($($arg)*));
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests parsing valid and invalid serial settings.
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(
+            Config::parse("115200"),
+            Some(Config {
+                baud_rate: 115200,
+                ..Config::DEFAULT
+            })
+        );
+        assert_eq!(
+            Config::parse("9600,7e2"),
+            Some(Config {
+                baud_rate: 9600,
+                data_bits: 7,
+                parity: Parity::Even,
+                stop_bits: 2
+            })
+        );
+        assert_eq!(Config::parse("0"), None);
+        assert_eq!(Config::parse("100000"), None);
+        assert_eq!(Config::parse("9600,"), None);
+        assert_eq!(Config::parse("9600,9n1"), None);
+        assert_eq!(Config::parse("9600,8x1"), None);
+        assert_eq!(Config::parse("9600,8n3"), None);
+        assert_eq!(Config::parse("fast"), None);
+    }
+
+    /// Tests the line control register values.
+    #[test]
+    fn test_line_control() {
+        assert_eq!(Config::DEFAULT.line_control(), 0x03);
+        assert_eq!(Config::parse("9600,7e2").unwrap().line_control(), 0x1e);
+        assert_eq!(Config::parse("9600,5o1").unwrap().line_control(), 0x08);
+    }
+}
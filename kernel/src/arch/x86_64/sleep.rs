@@ -0,0 +1,329 @@
+//! Suspends the machine to RAM using the ACPI S3 state.
+//!
+//! The firmware only keeps the memory while the machine sleeps. Before going
+//! to sleep, the state of the CPU and the devices the kernel set up is saved
+//! and the wake-up code from `init/wakeup.asm` is copied below 1 MiB. After
+//! waking up, the firmware starts that code in real mode, which switches to
+//! long mode and returns from `save_cpu_context` a second time.
+//!
+//! Only the state of the CPU, the interrupt controllers, the serial ports, the
+//! PS/2 controller, the screen and the PCI configuration headers is restored.
+//! The state of the virtio and AC'97 devices is lost and the VGA hardware
+//! isn't set up again, in case the firmware doesn't do it.
+
+use super::acpi::{self, SleepInfo};
+use super::gdt::GDT;
+use super::memory::{get_l4_entries, map_physical_area, WAKEUP_AREA};
+use super::pat::IA32_PAT;
+use super::{interrupts, pci, serial, vga_buffer};
+use core::ptr;
+use memory::{Address, READABLE, WRITABLE};
+use multitasking::hotplug;
+use sync::{cpu_relax, InterruptGuard};
+use x86_64::instructions::port::{inb, outb};
+use x86_64::instructions::{rdmsr, wrmsr};
+use x86_64::registers::control_regs;
+use x86_64::registers::msr;
+
+/// The number of entries in a page table.
+const ENTRY_COUNT: usize = 512;
+
+/// The flags of a present and writable page table entry.
+const TABLE_FLAGS: u64 = 0x3;
+
+/// The flags of a present and writable 2 MiB page.
+const HUGE_PAGE_FLAGS: u64 = 0x83;
+
+/// The status and command port of the PS/2 controller.
+const PS2_COMMAND: u16 = 0x64;
+
+/// The data port of the PS/2 controller.
+const PS2_DATA: u16 = 0x60;
+
+/// The PS/2 controller command that reads the configuration byte.
+const PS2_READ_CONFIG: u8 = 0x20;
+
+/// The PS/2 controller command that writes the configuration byte.
+const PS2_WRITE_CONFIG: u8 = 0x60;
+
+/// The number of checks of the PS/2 controller before it is given up.
+const PS2_MAX_CHECKS: usize = 1 << 16;
+
+/// The model specific registers that are lost while sleeping.
+const SAVED_MSRS: [u32; 7] = [
+    msr::IA32_FS_BASE,
+    msr::IA32_GS_BASE,
+    msr::IA32_KERNEL_GS_BASE,
+    msr::IA32_STAR,
+    msr::IA32_LSTAR,
+    msr::IA32_FMASK,
+    IA32_PAT
+];
+
+/// The registers that `save_cpu_context` saves.
+///
+/// The layout must match the offsets in `init/wakeup.asm`.
+#[repr(C)]
+pub struct CpuContext {
+    /// The callee saved registers, the instruction and stack pointer and the
+    /// page table.
+    registers: [u64; 9]
+}
+
+impl CpuContext {
+    /// Creates a context that jumps to the given address with the given
+    /// stack and page table.
+    pub fn new(instruction_pointer: u64, stack_pointer: u64, page_table: u64) -> CpuContext {
+        let mut registers = [0; 9];
+
+        // The callee saved registers start out as zero.
+        registers[6] = instruction_pointer;
+        registers[7] = stack_pointer;
+        registers[8] = page_table;
+
+        CpuContext { registers }
+    }
+}
+
+/// The context that is restored after waking up.
+static mut CPU_CONTEXT: CpuContext = CpuContext { registers: [0; 9] };
+
+extern "C" {
+    /// The start of the wake-up code.
+    static wakeup_start: u8;
+    /// The end of the wake-up code.
+    static wakeup_end: u8;
+    /// The slot for the temporary page table in the wake-up code.
+    static wakeup_page_table: u8;
+    /// The slot for the address of the context in the wake-up code.
+    static wakeup_context: u8;
+
+    /// Saves the context and returns 0, or returns 1 once it is restored.
+    pub fn save_cpu_context(context: *mut CpuContext) -> u64;
+}
+
+/// The control registers and MSRs of the CPU.
+pub struct CpuState {
+    /// The value of CR0.
+    cr0: control_regs::Cr0,
+    /// The value of CR4.
+    cr4: control_regs::Cr4,
+    /// The value of the EFER MSR.
+    efer: u64,
+    /// The values of the MSRs in `SAVED_MSRS`.
+    msrs: [u64; 7]
+}
+
+impl CpuState {
+    /// Saves the state of the current CPU.
+    pub fn save() -> CpuState {
+        let mut msrs = [0; 7];
+        for (value, &register) in msrs.iter_mut().zip(SAVED_MSRS.iter()) {
+            *value = unsafe { rdmsr(register) };
+        }
+
+        CpuState {
+            cr0: control_regs::cr0(),
+            cr4: control_regs::cr4(),
+            efer: unsafe { rdmsr(msr::IA32_EFER) },
+            msrs
+        }
+    }
+
+    /// Restores the state of the current CPU.
+    ///
+    /// # Safety
+    /// - The state must have been saved on this CPU.
+    pub unsafe fn restore(&self) {
+        control_regs::cr4_write(self.cr4);
+        control_regs::cr0_write(self.cr0);
+        wrmsr(msr::IA32_EFER, self.efer);
+
+        for (&value, &register) in self.msrs.iter().zip(SAVED_MSRS.iter()) {
+            wrmsr(register, value);
+        }
+    }
+}
+
+/// Suspends the machine to RAM and returns after it woke up.
+///
+/// Returns false if the machine doesn't support sleeping, other CPUs are
+/// online or the machine didn't go to sleep.
+pub fn suspend() -> bool {
+    let info = match acpi::get_sleep_info() {
+        Some(info) => info,
+        None => return false
+    };
+
+    // The wake-up code only restores the state of this CPU.
+    if hotplug::online_count() > 1 {
+        warn!("The machine can't sleep while other CPUs are online.");
+        return false;
+    }
+
+    let _interrupts = InterruptGuard::new();
+
+    info!("Suspending to RAM...");
+    vga_buffer::flush();
+    serial::flush();
+
+    // The other CPUs are offline, but the firmware may run them.
+    interrupts::lapic::park_other_cpus();
+
+    let cpu_state = CpuState::save();
+    let interrupt_state = interrupts::save_state();
+    let pci_headers = pci::save_headers();
+    let ps2_config = read_ps2_config();
+
+    prepare_wakeup(info);
+
+    let slept = unsafe { sleep(info) };
+
+    unsafe {
+        cpu_state.restore();
+        GDT.reload();
+        interrupts::restore_state(&interrupt_state);
+    }
+
+    pci::restore_headers(&pci_headers);
+    if let Some(config) = ps2_config {
+        write_ps2_config(config);
+    }
+    serial::resume();
+    vga_buffer::redraw();
+
+    if slept {
+        info!("Woke up again.");
+    } else {
+        warn!("The machine didn't go to sleep.");
+    }
+
+    slept
+}
+
+/// Copies the wake-up code below 1 MiB and lets the firmware start it.
+fn prepare_wakeup(info: &SleepInfo) {
+    unsafe {
+        prepare_trampoline(&CPU_CONTEXT);
+    }
+
+    info.set_waking_vector(WAKEUP_AREA.start_address().as_usize() as u32);
+}
+
+/// Copies the wake-up code below 1 MiB and sets up its page table, so that
+/// running it restores the given context.
+///
+/// # Safety
+/// - The context must stay valid until the code ran.
+/// - Nothing else may run the wake-up code in the meantime.
+pub unsafe fn prepare_trampoline(context: *const CpuContext) {
+    map_physical_area(WAKEUP_AREA, READABLE | WRITABLE);
+
+    let base = WAKEUP_AREA.start_address();
+    let l4_table = base + 0x1000;
+    let l3_table = base + 0x2000;
+    let l2_table = base + 0x3000;
+
+    let start = &wakeup_start as *const u8;
+    let length = &wakeup_end as *const u8 as usize - start as usize;
+    assert!(
+        length <= 0x1000,
+        "The wake-up code doesn't fit into a page."
+    );
+
+    ptr::copy_nonoverlapping(start, base.to_virtual().as_mut_ptr(), length);
+
+    // The temporary table maps the kernel half like the current one and
+    // the first 2 MiB to themselves for the wake-up code.
+    let l4 = l4_table.to_virtual().as_mut_ptr::<[u64; ENTRY_COUNT]>();
+    let l3 = l3_table.to_virtual().as_mut_ptr::<[u64; ENTRY_COUNT]>();
+    let l2 = l2_table.to_virtual().as_mut_ptr::<[u64; ENTRY_COUNT]>();
+
+    *l4 = get_l4_entries();
+    for entry in (*l4)[..ENTRY_COUNT / 2].iter_mut() {
+        *entry = 0;
+    }
+    (*l4)[0] = l3_table.as_usize() as u64 | TABLE_FLAGS;
+
+    *l3 = [0; ENTRY_COUNT];
+    (*l3)[0] = l2_table.as_usize() as u64 | TABLE_FLAGS;
+
+    *l2 = [0; ENTRY_COUNT];
+    (*l2)[0] = HUGE_PAGE_FLAGS;
+
+    write_slot(&wakeup_page_table, l4_table.as_usize() as u64);
+    write_slot(&wakeup_context, context as u64);
+}
+
+/// Writes the value into the given slot of the copied wake-up code.
+///
+/// # Safety
+/// - The slot must be a label of the wake-up code.
+unsafe fn write_slot(slot: &u8, value: u64) {
+    let offset = slot as *const u8 as usize - &wakeup_start as *const u8 as usize;
+    let target = (WAKEUP_AREA.start_address() + offset).to_virtual();
+
+    ptr::write_unaligned(target.as_mut_ptr::<u64>(), value);
+}
+
+/// Saves the context and puts the machine to sleep.
+///
+/// Returns true once the machine woke up again and false if it didn't go to
+/// sleep.
+///
+/// # Safety
+/// - Everything else that is lost while sleeping must be saved.
+#[inline(never)]
+unsafe fn sleep(info: &SleepInfo) -> bool {
+    if save_cpu_context(&mut CPU_CONTEXT) != 0 {
+        return true;
+    }
+
+    // The caches are lost while sleeping.
+    asm!("wbinvd" : : : "memory" : "intel", "volatile");
+
+    info.enter();
+
+    false
+}
+
+/// Reads the configuration byte of the PS/2 controller, if there is one.
+fn read_ps2_config() -> Option<u8> {
+    unsafe {
+        outb(PS2_COMMAND, PS2_READ_CONFIG);
+
+        // The output buffer is full once the byte can be read.
+        if wait_for_ps2(|status| status & 0b01 != 0) {
+            Some(inb(PS2_DATA))
+        } else {
+            None
+        }
+    }
+}
+
+/// Writes the configuration byte of the PS/2 controller.
+fn write_ps2_config(config: u8) {
+    unsafe {
+        outb(PS2_COMMAND, PS2_WRITE_CONFIG);
+
+        // The input buffer must be empty before the byte is written.
+        if wait_for_ps2(|status| status & 0b10 == 0) {
+            outb(PS2_DATA, config);
+        }
+    }
+}
+
+/// Waits until the status of the PS/2 controller fulfills the condition.
+///
+/// Returns false if it didn't in time.
+fn wait_for_ps2<F: Fn(u8) -> bool>(condition: F) -> bool {
+    for _ in 0..PS2_MAX_CHECKS {
+        if condition(unsafe { inb(PS2_COMMAND) }) {
+            return true;
+        }
+
+        cpu_relax();
+    }
+
+    false
+}
@@ -0,0 +1,226 @@
+//! Starts and stops the CPUs other than the boot CPU.
+//!
+//! The kernel doesn't start the other CPUs during boot. A CPU is started with
+//! an INIT interrupt followed by startup interrupts, which run the wake-up
+//! code from `init/wakeup.asm` in real mode. Like after sleeping, that code
+//! switches to long mode and restores a saved context. A CPU that starts for
+//! the first time enters its idle thread that way, a parked CPU returns from
+//! `park_cpu` again.
+
+use super::gdt::GDT;
+use super::interrupts::{self, lapic};
+use super::memory::WAKEUP_AREA;
+use super::sleep::{prepare_trampoline, save_cpu_context, CpuContext, CpuState};
+use super::sync::{cpu_halt, cpu_relax, tsc_after};
+use super::{enable_cpu_features, syscalls, X86_64};
+use alloc::Vec;
+use arch::Architecture;
+use core::cell::UnsafeCell;
+use core::mem::forget;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT};
+use core::time::Duration;
+use memory::Address;
+use multitasking::CURRENT_THREAD;
+use x86_64::instructions::rdtsc;
+
+/// The size of the stack a CPU uses until it enters its idle thread.
+const BOOT_STACK_SIZE: usize = 0x4000;
+
+/// The time to wait after the INIT interrupt in milliseconds.
+const INIT_DELAY_MS: u64 = 10;
+
+/// The time to wait after a startup interrupt in milliseconds.
+const STARTUP_DELAY_MS: u64 = 1;
+
+/// The time a CPU may take to start or to stop in milliseconds.
+const TIMEOUT_MS: u64 = 100;
+
+/// The number of checks before giving up if the time can't be measured.
+const MAX_CHECKS: usize = 1 << 24;
+
+/// The CPU was never started.
+const NEVER_STARTED: usize = 0;
+
+/// The CPU runs kernel code.
+const RUNNING: usize = 1;
+
+/// The CPU halts until it is started again.
+const PARKED: usize = 2;
+
+/// Whether a CPU is being started, which uses the wake-up code.
+static STARTING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// What a CPU needs to be started again.
+struct Parking {
+    /// Whether the CPU never started, runs or is parked.
+    state: AtomicUsize,
+    /// The context the CPU continues with when it is started.
+    context: UnsafeCell<CpuContext>
+}
+
+// The context is only used by its CPU while it runs and by the CPU that
+// starts it while it doesn't.
+unsafe impl Sync for Parking {}
+
+cpu_local! {
+    /// What the CPU needs to be started again.
+    static ref PARKING: Parking = |_| Parking {
+        state: AtomicUsize::new(NEVER_STARTED),
+        context: UnsafeCell::new(CpuContext::new(0, 0, 0))
+    };
+}
+
+/// Starts the CPU with the given ID.
+///
+/// Returns false if the CPU didn't start in time or another CPU is being
+/// started right now.
+pub fn start_cpu(cpu_id: usize) -> bool {
+    if STARTING.compare_and_swap(false, true, Ordering::Acquire) {
+        return false;
+    }
+
+    let started = start(cpu_id, PARKING.get_specific(cpu_id));
+
+    STARTING.store(false, Ordering::Release);
+
+    started
+}
+
+/// Starts the CPU while no other CPU is being started.
+fn start(cpu_id: usize, parking: &Parking) -> bool {
+    let timeout = Duration::from_millis(TIMEOUT_MS);
+
+    // A CPU that went offline stops shortly afterwards.
+    if !wait_for(|| parking.state.load(Ordering::Acquire) != RUNNING, timeout) {
+        return false;
+    }
+
+    if parking.state.load(Ordering::Acquire) == NEVER_STARTED {
+        let mut stack = Vec::new();
+        stack.resize(BOOT_STACK_SIZE, 0u8);
+        let stack_top = (stack.as_ptr() as usize + BOOT_STACK_SIZE) & !0xf;
+        // The CPU only leaves the stack when it enters its idle thread.
+        forget(stack);
+
+        let page_table = CURRENT_THREAD
+            .get_specific(cpu_id)
+            .lock()
+            .address_space
+            .page_table_address();
+
+        // The entry function is entered like after a call.
+        unsafe {
+            *parking.context.get() = CpuContext::new(
+                enter_idle_thread as usize as u64,
+                (stack_top - 8) as u64,
+                page_table.as_usize() as u64
+            );
+        }
+    }
+
+    unsafe {
+        prepare_trampoline(parking.context.get());
+    }
+
+    let apic_id = cpu_id as u8;
+    let page = (WAKEUP_AREA.start_address().as_usize() / 0x1000) as u8;
+
+    lapic::send_init(apic_id);
+    delay(Duration::from_millis(INIT_DELAY_MS));
+
+    // The second startup interrupt is only needed if the first one is lost.
+    for _ in 0..2 {
+        if parking.state.load(Ordering::Acquire) == RUNNING {
+            break;
+        }
+
+        lapic::send_startup(apic_id, page);
+        delay(Duration::from_millis(STARTUP_DELAY_MS));
+    }
+
+    if wait_for(|| parking.state.load(Ordering::Acquire) == RUNNING, timeout) {
+        true
+    } else {
+        // Stop the CPU, so that it doesn't start once it's given up on.
+        lapic::send_init(apic_id);
+        false
+    }
+}
+
+/// Sets up a CPU that starts for the first time and enters its idle thread.
+extern "C" fn enter_idle_thread() -> ! {
+    unsafe {
+        enable_cpu_features();
+        GDT.load();
+    }
+
+    syscalls::init();
+    interrupts::init_cpu();
+
+    PARKING.state.store(RUNNING, Ordering::Release);
+
+    unsafe { X86_64::enter_first_thread() }
+}
+
+/// Stops the current CPU until `start_cpu` starts it again.
+///
+/// # Safety
+/// - Interrupts must be disabled and no locks may be held.
+pub unsafe fn park_cpu() {
+    let cpu_state = CpuState::save();
+
+    halt_until_started(&PARKING);
+
+    cpu_state.restore();
+    GDT.reload();
+    interrupts::init_cpu();
+
+    PARKING.state.store(RUNNING, Ordering::Release);
+}
+
+/// Saves the context and halts the CPU.
+///
+/// Returns once the CPU was started again.
+///
+/// # Safety
+/// - Everything else that is lost when the CPU is started again must be
+/// saved.
+#[inline(never)]
+unsafe fn halt_until_started(parking: &Parking) {
+    if save_cpu_context(parking.context.get()) != 0 {
+        return;
+    }
+
+    parking.state.store(PARKED, Ordering::Release);
+
+    // Only the INIT interrupt that starts the CPU again ends this.
+    loop {
+        cpu_halt();
+    }
+}
+
+/// Waits until the condition holds.
+///
+/// Returns false if it doesn't hold within the timeout.
+fn wait_for<F: Fn() -> bool>(condition: F, timeout: Duration) -> bool {
+    let deadline = tsc_after(timeout);
+
+    for _ in 0..MAX_CHECKS {
+        if condition() {
+            return true;
+        }
+
+        if deadline.map_or(false, |deadline| rdtsc() as usize >= deadline) {
+            return false;
+        }
+
+        cpu_relax();
+    }
+
+    condition()
+}
+
+/// Waits for the given time.
+fn delay(duration: Duration) {
+    wait_for(|| false, duration);
+}
@@ -1,12 +1,33 @@
 //! Handles architecture specific synchronization.
 
+use core::cmp::min;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use core::time::Duration;
 use sync::time::Timestamp;
-use x86_64::instructions::interrupts;
+use x86_64::instructions::{interrupts, rdtsc};
 use x86_64::registers::flags::*;
 
-/// The number of milliseconds since boot.
-pub static mut CLOCK: Duration = Duration::from_secs(0);
+/// The number of nanoseconds between two RTC ticks.
+pub const TICK_NANOS: usize = 1_000_000_000 / 1024;
+
+/// The number of nanoseconds since boot at the last RTC tick.
+static CLOCK: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The time stamp counter at the last RTC tick.
+static TICK_TSC: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Incremented before and after each tick, so it is odd during a tick.
+static CLOCK_SEQUENCE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The number of time stamp counter cycles per microsecond or zero if the
+/// time stamp counter can't be used.
+static TSC_CYCLES_PER_MICROSECOND: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The latest time in nanoseconds that was handed out.
+///
+/// No timestamp is older than this, even if the time stamp counters of the
+/// CPUs differ slightly.
+static LATEST_TIMESTAMP: AtomicUsize = ATOMIC_USIZE_INIT;
 
 /// Called while spinning (name borrowed from Linux). Can be implemented to call
 /// a platform-specific method of lightening CPU load in spinlocks.
@@ -29,6 +50,20 @@ pub unsafe fn cpu_halt() {
     asm!("hlt" :::: "volatile");
 }
 
+/// Enables interrupts and halts the cpu, until it is woken again.
+///
+/// Interrupts are only recognized after the instruction following `sti`, so
+/// none can arrive before the cpu halts.
+///
+/// # Safety
+/// - Don't use this function directly, rather use the interface through the
+/// sync module.
+#[inline(always)]
+pub unsafe fn enable_interrupts_and_halt() {
+    asm!("sti
+          hlt" :::: "volatile");
+}
+
 /// Disables interrupts.
 ///
 /// # Safety
@@ -55,7 +90,108 @@ pub fn interrupts_enabled() -> bool {
     flags().contains(Flags::IF)
 }
 
+/// Advances the clock by one RTC tick.
+///
+/// This must only be called by the RTC interrupt handler.
+pub fn tick() {
+    CLOCK_SEQUENCE.fetch_add(1, Ordering::AcqRel);
+    CLOCK.fetch_add(TICK_NANOS, Ordering::Relaxed);
+    TICK_TSC.store(rdtsc() as usize, Ordering::Relaxed);
+    CLOCK_SEQUENCE.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Sets the frequency of the time stamp counter.
+///
+/// From then on the time between two RTC ticks is measured with the time
+/// stamp counter, which must run at a constant rate.
+pub fn set_tsc_frequency(cycles_per_microsecond: usize) {
+    TSC_CYCLES_PER_MICROSECOND.store(cycles_per_microsecond, Ordering::Release);
+}
+
 /// Returns the current timestamp.
+///
+/// Between the RTC ticks the time is interpolated with the time stamp
+/// counter, which gives it microsecond resolution.
 pub fn get_current_timestamp() -> Timestamp {
-    Timestamp::from_duration(unsafe { CLOCK })
+    let (clock, tick_tsc) = loop {
+        let sequence = CLOCK_SEQUENCE.load(Ordering::Acquire);
+        let clock = CLOCK.load(Ordering::Relaxed);
+        let tick_tsc = TICK_TSC.load(Ordering::Relaxed);
+
+        if sequence % 2 == 0 && CLOCK_SEQUENCE.load(Ordering::Acquire) == sequence {
+            break (clock, tick_tsc);
+        }
+
+        cpu_relax();
+    };
+
+    let cycles_per_microsecond = TSC_CYCLES_PER_MICROSECOND.load(Ordering::Acquire);
+    let since_tick = if cycles_per_microsecond == 0 {
+        0
+    } else {
+        let microseconds = (rdtsc() as usize).saturating_sub(tick_tsc) / cycles_per_microsecond;
+
+        // The time must not reach the next tick before it happened.
+        min(microseconds * 1000, TICK_NANOS - 1)
+    };
+
+    let mut now = clock + since_tick;
+    let mut latest = LATEST_TIMESTAMP.load(Ordering::Relaxed);
+
+    while latest < now {
+        let previous = LATEST_TIMESTAMP.compare_and_swap(latest, now, Ordering::Relaxed);
+
+        if previous == latest {
+            break;
+        }
+
+        latest = previous;
+    }
+
+    if latest > now {
+        now = latest;
+    }
+
+    Timestamp::from_duration(Duration::new(
+        (now / 1_000_000_000) as u64,
+        (now % 1_000_000_000) as u32
+    ))
+}
+
+/// Returns the value the time stamp counter will have after `delay`.
+///
+/// Returns `None` if the time stamp counter can't measure time.
+pub fn tsc_after(delay: Duration) -> Option<usize> {
+    let cycles_per_microsecond = TSC_CYCLES_PER_MICROSECOND.load(Ordering::Acquire);
+
+    if cycles_per_microsecond == 0 {
+        return None;
+    }
+
+    let microseconds = (delay.as_secs() as usize)
+        .saturating_mul(1_000_000)
+        .saturating_add(delay.subsec_micros() as usize);
+
+    Some((rdtsc() as usize).saturating_add(microseconds.saturating_mul(cycles_per_microsecond)))
+}
+
+/// Returns the time that passed since the time stamp counter had the given
+/// value.
+///
+/// Returns `None` if the time stamp counter can't measure time. A value that
+/// wasn't reached yet counts as no time.
+pub fn time_since_tsc(tsc: usize) -> Option<Duration> {
+    let cycles_per_microsecond = TSC_CYCLES_PER_MICROSECOND.load(Ordering::Acquire);
+
+    if cycles_per_microsecond == 0 {
+        return None;
+    }
+
+    let cycles = (rdtsc() as usize).saturating_sub(tsc);
+    let nanoseconds = cycles.saturating_mul(1000) / cycles_per_microsecond;
+
+    Some(Duration::new(
+        (nanoseconds / 1_000_000_000) as u64,
+        (nanoseconds % 1_000_000_000) as u32
+    ))
 }
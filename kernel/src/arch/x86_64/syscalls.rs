@@ -1,10 +1,41 @@
 //! Serves to accept syscalls.
 
 use super::gdt::{USER_32BIT_CODE_SEGMENT, KERNEL_CODE_SEGMENT, TSS};
+use core::mem::size_of;
 use syscalls::syscall_handler;
 use x86_64::registers::flags::Flags;
 use x86_64::registers::msr::{wrmsr, IA32_FMASK, IA32_KERNEL_GS_BASE, IA32_LSTAR, IA32_STAR};
 
+/// The user registers that the syscall entry saves on the kernel stack.
+///
+/// The fields are ordered like they lie on the stack, directly below the base
+/// of the kernel stack.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SavedUserRegisters {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub rbx: u64,
+    pub rbp: u64,
+    /// The address the syscall returns to.
+    pub instruction_pointer: u64,
+    /// The flags register of the thread.
+    pub flags: u64,
+    /// The user stack pointer of the thread.
+    pub stack_pointer: u64
+}
+
+/// Returns the user registers of the current thread, which is inside a
+/// syscall.
+pub fn saved_user_registers() -> SavedUserRegisters {
+    unsafe {
+        let stack_base = TSS.privilege_stack_table[0].0 as usize;
+
+        *((stack_base - size_of::<SavedUserRegisters>()) as *const SavedUserRegisters)
+    }
+}
+
 /// Initializes the system to be able to accept syscalls.
 pub fn init() {
     let sysret_cs = USER_32BIT_CODE_SEGMENT.0 as u64;
@@ -69,10 +100,23 @@ extern "C" fn syscall_entry() {
               push r11 //The flags register
               push rcx //The program counter
 
+              // Save the callee-saved registers, so a forked thread can start
+              // with them.
+              push rbp
+              push rbx
+              push r13
+              push r14
+              push r15
+
               // Call the actual handler.
               call $0
 
               // Restore the context.
+              pop r15
+              pop r14
+              pop r13
+              pop rbx
+              pop rbp
               pop rcx
               pop r11
               pop r12
@@ -0,0 +1,65 @@
+//! Reads the digital thermal sensor of Intel CPUs.
+//!
+//! The sensor doesn't report the temperature directly, but how far it is
+//! below the temperature at which the CPU throttles itself (TjMax).
+
+use raw_cpuid::CpuId;
+use x86_64::instructions::rdmsr;
+
+/// The MSR that holds the reading of the thermal sensor.
+const IA32_THERM_STATUS: u32 = 0x19c;
+
+/// The MSR that holds TjMax.
+const MSR_TEMPERATURE_TARGET: u32 = 0x1a2;
+
+/// The bit of `IA32_THERM_STATUS` that tells that the reading is valid.
+const THERM_STATUS_VALID: u64 = 1 << 31;
+
+/// The TjMax of CPUs that don't report it.
+const DEFAULT_TJ_MAX: u32 = 100;
+
+/// The family of the Intel CPUs that have `MSR_TEMPERATURE_TARGET`.
+const INTEL_FAMILY: u8 = 6;
+
+/// Returns the temperature of the current CPU in degrees Celsius.
+///
+/// Returns `None` if the CPU has no sensor or the reading isn't valid.
+pub fn get_temperature() -> Option<u32> {
+    let cpuid = CpuId::new();
+    let is_intel = cpuid
+        .get_vendor_info()
+        .map_or(false, |vendor| vendor.as_string() == "GenuineIntel");
+    let has_sensor = cpuid
+        .get_thermal_power_info()
+        .map_or(false, |info| info.has_dts());
+
+    // Reading the MSRs on other CPUs would fault.
+    if !is_intel || !has_sensor {
+        return None;
+    }
+
+    let status = unsafe { rdmsr(IA32_THERM_STATUS) };
+    if status & THERM_STATUS_VALID == 0 {
+        return None;
+    }
+
+    let below_tj_max = ((status >> 16) & 0x7f) as u32;
+
+    Some(get_tj_max().saturating_sub(below_tj_max))
+}
+
+/// Returns the temperature at which the CPU throttles itself.
+fn get_tj_max() -> u32 {
+    let family = CpuId::new()
+        .get_feature_info()
+        .map_or(0, |features| features.family_id());
+
+    if family != INTEL_FAMILY {
+        return DEFAULT_TJ_MAX;
+    }
+
+    match ((unsafe { rdmsr(MSR_TEMPERATURE_TARGET) } >> 16) & 0xff) as u32 {
+        0 => DEFAULT_TJ_MAX,
+        tj_max => tj_max
+    }
+}
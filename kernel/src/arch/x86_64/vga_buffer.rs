@@ -2,10 +2,30 @@
 //!
 //! This module is used to handle IO with the basic VGA interface usually
 //! located at 0xb8000;
+//!
+//! Reading and writing the VGA memory is slow, so all changes go to a copy of
+//! the screen in kernel memory first. Only the rectangle that changed since
+//! the last flush is written to the VGA memory.
+//!
+//! Written text may contain ANSI escape sequences. Select graphic rendition
+//! sequences, like `\x1b[31m`, set the colors of the following characters,
+//! so text colored for a terminal looks the same on the screen. Other escape
+//! sequences are left out.
+//!
+//! The screen is also available as the device `/dev/screen`, which holds two
+//! bytes per character, the character and its color code, row by row. After
+//! the last byte the seek position starts over at the first one, so that whole
+//! screens can be written one after another. While the device is open,
+//! console output is not shown on the screen.
 
+use alloc::boxed::Box;
 use boot;
+use config;
+use core::cmp::{max, min};
 use core::fmt;
 use core::ptr::Unique;
+use devfs;
+use file_handle::{FileError, FileHandle, PollEvents, Result, SeekFrom, POLL_IN, POLL_OUT};
 use memory::VirtualAddress;
 use sync::Mutex;
 use volatile::Volatile;
@@ -33,6 +53,21 @@ pub enum Color {
     White = 15
 }
 
+/// The VGA colors of the eight ANSI colors.
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray
+];
+
+/// The bit that turns a color into its bright variant.
+const BRIGHT: u8 = 0x8;
+
 /// Represents a color code in the buffer.
 ///
 /// A color code includes both information about the foreground and the
@@ -47,6 +82,189 @@ impl ColorCode {
     }
 }
 
+/// The maximum number of characters on the screen.
+///
+/// This fits the largest VGA text mode with 132 columns and 60 rows.
+const MAX_CHARACTERS: usize = 132 * 60;
+
+/// The character that removes the character before the cursor.
+const BACKSPACE: u8 = 0x08;
+
+/// The name of the screen device.
+const DEVICE_NAME: &str = "screen";
+
+/// The character that starts an escape sequence.
+const ESCAPE: u8 = 0x1b;
+
+/// The number of parameters of an escape sequence that are kept.
+const MAX_PARAMETERS: usize = 8;
+
+/// The colors that select graphic rendition sequences set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Style {
+    /// The foreground color.
+    foreground: u8,
+    /// The background color.
+    background: u8,
+    /// Whether the text is bold, which shows the foreground brighter.
+    bold: bool
+}
+
+impl Style {
+    /// The style that text starts with.
+    const DEFAULT: Style = Style {
+        foreground: Color::LightGray as u8,
+        background: Color::Black as u8,
+        bold: false
+    };
+
+    /// Applies the parameters of a select graphic rendition sequence.
+    ///
+    /// Unsupported parameters are ignored.
+    fn apply(&mut self, parameters: &[u16]) {
+        // A sequence without parameters resets the style.
+        if parameters.is_empty() {
+            *self = Style::DEFAULT;
+        }
+
+        for &parameter in parameters {
+            let color = |base: u16| ANSI_COLORS[(parameter - base) as usize] as u8;
+
+            match parameter {
+                0 => *self = Style::DEFAULT,
+                1 => self.bold = true,
+                22 => self.bold = false,
+                39 => self.foreground = Style::DEFAULT.foreground,
+                49 => self.background = Style::DEFAULT.background,
+                _ if parameter >= 30 && parameter <= 37 => self.foreground = color(30),
+                _ if parameter >= 40 && parameter <= 47 => self.background = color(40),
+                _ if parameter >= 90 && parameter <= 97 => self.foreground = color(90) | BRIGHT,
+                _ => ()
+            }
+        }
+    }
+
+    /// Returns the color code of text in this style.
+    fn color_code(&self) -> ColorCode {
+        let foreground = if self.bold {
+            self.foreground | BRIGHT
+        } else {
+            self.foreground
+        };
+
+        ColorCode(self.background << 4 | foreground)
+    }
+}
+
+/// The progress of reading an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EscapeState {
+    /// No escape sequence is being read.
+    Text,
+    /// The escape character was read.
+    Escape,
+    /// The start of a control sequence was read, its parameters follow.
+    ControlSequence
+}
+
+/// What a written byte results in.
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// The byte is shown.
+    Print(u8),
+    /// A select graphic rendition sequence with the given number of
+    /// parameters ended.
+    SetGraphics([u16; MAX_PARAMETERS], usize),
+    /// The byte is part of an escape sequence.
+    Nothing
+}
+
+/// Separates escape sequences from the text around them.
+#[derive(Debug)]
+struct EscapeParser {
+    /// The progress of the current escape sequence.
+    state: EscapeState,
+    /// The parameters of the current control sequence.
+    parameters: [u16; MAX_PARAMETERS],
+    /// The number of parameters read so far.
+    parameter_count: usize
+}
+
+impl EscapeParser {
+    /// Creates a parser that starts outside of an escape sequence.
+    const fn new() -> EscapeParser {
+        EscapeParser {
+            state: EscapeState::Text,
+            parameters: [0; MAX_PARAMETERS],
+            parameter_count: 0
+        }
+    }
+
+    /// Reads the next byte.
+    fn feed(&mut self, byte: u8) -> Action {
+        match self.state {
+            EscapeState::Text => {
+                if byte == ESCAPE {
+                    self.state = EscapeState::Escape;
+                    Action::Nothing
+                } else {
+                    Action::Print(byte)
+                }
+            },
+            EscapeState::Escape => {
+                if byte == b'[' {
+                    self.state = EscapeState::ControlSequence;
+                    self.parameters = [0; MAX_PARAMETERS];
+                    self.parameter_count = 0;
+                } else {
+                    // Other escape sequences consist of a single byte.
+                    self.state = EscapeState::Text;
+                }
+
+                Action::Nothing
+            },
+            EscapeState::ControlSequence => self.feed_control_sequence(byte)
+        }
+    }
+
+    /// Reads the next byte of a control sequence.
+    fn feed_control_sequence(&mut self, byte: u8) -> Action {
+        match byte {
+            b'0'...b'9' => {
+                if self.parameter_count == 0 {
+                    self.parameter_count = 1;
+                }
+
+                if let Some(parameter) = self.parameters.get_mut(self.parameter_count - 1) {
+                    *parameter = parameter
+                        .saturating_mul(10)
+                        .saturating_add(u16::from(byte - b'0'));
+                }
+
+                Action::Nothing
+            },
+            b';' => {
+                // An empty parameter counts as zero.
+                self.parameter_count = max(self.parameter_count, 1).saturating_add(1);
+
+                Action::Nothing
+            },
+            b'm' => {
+                self.state = EscapeState::Text;
+
+                Action::SetGraphics(self.parameters, min(self.parameter_count, MAX_PARAMETERS))
+            },
+            // Any other final byte ends an unsupported sequence.
+            0x40...0x7e => {
+                self.state = EscapeState::Text;
+
+                Action::Nothing
+            },
+            _ => Action::Nothing
+        }
+    }
+}
+
 /// Represents a character in the buffer.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -83,13 +301,29 @@ impl Buffer {
             (&mut *position_ptr).write(character);
         }
     }
+}
 
-    /// Reads a character from this buffer.
-    fn read_char(&self, row_position: usize, column_position: usize) -> ScreenChar {
-        let start = unsafe { self.address.as_ref() as *const Volatile<ScreenChar> };
-        unsafe {
-            let position_ptr = start.offset((row_position * self.width + column_position) as isize);
-            (&*position_ptr).read()
+/// A rectangle of the screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rectangle {
+    /// The first row.
+    top: usize,
+    /// The row after the last one.
+    bottom: usize,
+    /// The first column.
+    left: usize,
+    /// The column after the last one.
+    right: usize
+}
+
+impl Rectangle {
+    /// Returns the smallest rectangle that contains both rectangles.
+    fn union(self, other: Rectangle) -> Rectangle {
+        Rectangle {
+            top: min(self.top, other.top),
+            bottom: max(self.bottom, other.bottom),
+            left: min(self.left, other.left),
+            right: max(self.right, other.right)
         }
     }
 }
@@ -102,15 +336,41 @@ pub struct Writer {
     row_position: usize,
     /// The color code used throughout the buffer.
     color_code: ColorCode,
+    /// The colors set by escape sequences.
+    style: Style,
+    /// Separates escape sequences from the written text.
+    escape_parser: EscapeParser,
     /// Access to the buffer itself.
-    buffer: Buffer
+    buffer: Buffer,
+    /// The characters on the screen, row by row.
+    characters: [ScreenChar; MAX_CHARACTERS],
+    /// The part of the screen that changed since the last flush.
+    dirty: Option<Rectangle>,
+    /// The number of open screen devices.
+    device_users: usize
 }
 
 impl Writer {
     /// Writes the given character to the buffer.
     pub fn write_char(&mut self, byte: u8) {
+        let byte = match self.escape_parser.feed(byte) {
+            Action::Print(byte) => byte,
+            Action::SetGraphics(parameters, count) => {
+                self.style.apply(&parameters[..count]);
+                self.color_code = self.style.color_code();
+                return;
+            },
+            Action::Nothing => return
+        };
+
+        // The screen belongs to the process that opened the device.
+        if self.device_users > 0 {
+            return;
+        }
+
         match byte {
             b'\n' => self.new_line(),
+            BACKSPACE => self.backspace(),
             byte => {
                 if self.column_position >= self.buffer.width {
                     self.new_line();
@@ -120,7 +380,7 @@ impl Writer {
                 let row_position = self.row_position;
                 let color_code = self.color_code;
 
-                self.buffer.write_char(
+                self.set_char(
                     row_position,
                     column_position,
                     ScreenChar {
@@ -141,13 +401,115 @@ impl Writer {
         }
     }
 
+    /// Writes all changes since the last flush to the buffer.
+    pub fn flush(&mut self) {
+        if let Some(dirty) = self.dirty.take() {
+            for row in dirty.top..dirty.bottom {
+                for column in dirty.left..dirty.right {
+                    let character = self.characters[row * self.buffer.width + column];
+
+                    self.buffer.write_char(row, column, character);
+                }
+            }
+        }
+    }
+
+    /// Moves the cursor to the given position.
+    ///
+    /// Returns false if the position is outside of the screen.
+    fn set_cursor(&mut self, row: usize, column: usize) -> bool {
+        if row >= self.buffer.height || column >= self.buffer.width {
+            return false;
+        }
+
+        self.row_position = row;
+        self.column_position = column;
+
+        true
+    }
+
+    /// Returns the size of the screen device in bytes.
+    fn device_size(&self) -> usize {
+        self.buffer.width * self.buffer.height * 2
+    }
+
+    /// Returns the byte at the given offset of the screen device.
+    fn read_device_byte(&self, offset: usize) -> u8 {
+        let character = self.characters[offset / 2];
+
+        if offset % 2 == 0 {
+            character.character
+        } else {
+            character.color_code.0
+        }
+    }
+
+    /// Writes the byte at the given offset of the screen device.
+    fn write_device_byte(&mut self, offset: usize, byte: u8) {
+        if self.read_device_byte(offset) == byte {
+            return;
+        }
+
+        let index = offset / 2;
+        let mut character = self.characters[index];
+
+        if offset % 2 == 0 {
+            character.character = byte;
+        } else {
+            character.color_code = ColorCode(byte);
+        }
+
+        let width = self.buffer.width;
+        self.set_char(index / width, index % width, character);
+    }
+
+    /// Sets the character at the given position.
+    fn set_char(&mut self, row_position: usize, column_position: usize, character: ScreenChar) {
+        self.characters[row_position * self.buffer.width + column_position] = character;
+
+        self.mark_dirty(Rectangle {
+            top: row_position,
+            bottom: row_position + 1,
+            left: column_position,
+            right: column_position + 1
+        });
+    }
+
+    /// Marks the rectangle as changed.
+    fn mark_dirty(&mut self, rectangle: Rectangle) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(rectangle),
+            None => rectangle
+        });
+    }
+
+    /// Removes the character before the cursor on the current line.
+    fn backspace(&mut self) {
+        if self.column_position == 0 {
+            return;
+        }
+
+        self.column_position -= 1;
+
+        let column_position = self.column_position;
+        let row_position = self.row_position;
+        let color_code = self.color_code;
+
+        self.set_char(
+            row_position,
+            column_position,
+            ScreenChar {
+                character: b' ',
+                color_code
+            }
+        );
+    }
+
     /// Inserts a new line character.
     fn new_line(&mut self) {
         let height = self.buffer.height;
         if self.row_position >= self.buffer.height - 1 {
-            for i in 1..height {
-                self.shift_line(i);
-            }
+            self.scroll();
             self.row_position = height - 2;
             self.clear_line(height - 1);
         }
@@ -156,13 +518,21 @@ impl Writer {
         self.column_position = 0;
     }
 
-    /// Shifts the given line upwards.
-    fn shift_line(&mut self, line: usize) {
-        for i in 0..self.buffer.width {
-            let char_below = self.buffer.read_char(line, i);
+    /// Moves all lines one line upwards.
+    fn scroll(&mut self) {
+        let width = self.buffer.width;
+        let height = self.buffer.height;
 
-            self.buffer.write_char(line - 1, i, char_below);
+        for i in width..width * height {
+            self.characters[i - width] = self.characters[i];
         }
+
+        self.mark_dirty(Rectangle {
+            top: 0,
+            bottom: height,
+            left: 0,
+            right: width
+        });
     }
 
     /// Clears the given line.
@@ -175,7 +545,7 @@ impl Writer {
         };
 
         for i in 0..width {
-            self.buffer.write_char(line, i, space);
+            self.set_char(line, i, space);
         }
     }
 
@@ -193,7 +563,8 @@ impl Writer {
     fn init(&mut self, info: Info) {
         assert_has_not_been_called!("The VGA buffer should only be initialized once.");
 
-        self.buffer.height = info.height;
+        // Rows that don't fit into the copy of the screen stay unused.
+        self.buffer.height = min(info.height, MAX_CHARACTERS / info.width);
         self.buffer.width = info.width;
         self.buffer.address = unsafe { Unique::new_unchecked(info.address.as_mut_ptr()) };
     }
@@ -212,13 +583,22 @@ pub static WRITER: Mutex<Writer> = Mutex::new(Writer {
     column_position: 0,
     row_position: 0,
     color_code: ColorCode::new(Color::LightGray, Color::Black),
-    buffer: Buffer::new(to_virtual!(0xb8000), 25, 80)
+    style: Style::DEFAULT,
+    escape_parser: EscapeParser::new(),
+    buffer: Buffer::new(to_virtual!(0xb8000), 25, 80),
+    characters: [ScreenChar {
+        character: 0,
+        color_code: ColorCode(0)
+    }; MAX_CHARACTERS],
+    dirty: None,
+    device_users: 0
 });
 
 /// Contains basic buffer information.
 ///
 /// This is what is used to convey information about the buffer from the
 /// outside to this module.
+#[derive(Clone, Copy)]
 pub struct Info {
     pub height: usize,
     pub width: usize,
@@ -232,7 +612,249 @@ pub fn init() {
     clear_screen();
 }
 
+/// Makes the screen available as a device file.
+pub fn late_init() {
+    assert_has_not_been_called!("The screen device should only be registered once.");
+
+    devfs::register(
+        DEVICE_NAME,
+        Box::new(|| Box::new(ScreenDevice::open()) as Box<FileHandle>)
+    );
+}
+
 /// Clears the screen.
 pub fn clear_screen() {
-    WRITER.lock().clear_screen();
+    let mut writer = WRITER.lock();
+
+    writer.clear_screen();
+    writer.flush();
+}
+
+/// Returns the number of rows and columns of the screen.
+pub fn size() -> (usize, usize) {
+    let writer = WRITER.lock();
+
+    (writer.buffer.height, writer.buffer.width)
+}
+
+/// Returns the row and column the next character is written at.
+pub fn cursor() -> (usize, usize) {
+    let writer = WRITER.lock();
+
+    (writer.row_position, writer.column_position)
+}
+
+/// Moves the position the next character is written at.
+///
+/// Returns false if the position is outside of the screen.
+pub fn set_cursor(row: usize, column: usize) -> bool {
+    WRITER.lock().set_cursor(row, column)
+}
+
+/// Writes the changes to the screen to the buffer.
+pub fn flush() {
+    WRITER.lock().flush();
+}
+
+/// Writes the whole screen to the buffer again.
+///
+/// This is needed when the buffer lost its content, like after sleeping.
+pub fn redraw() {
+    let mut writer = WRITER.lock();
+    let (width, height) = (writer.buffer.width, writer.buffer.height);
+
+    writer.mark_dirty(Rectangle {
+        top: 0,
+        bottom: height,
+        left: 0,
+        right: width
+    });
+    writer.flush();
+}
+
+/// Writes the changes to the screen to the buffer, unless the screen is
+/// being written to.
+///
+/// This is called periodically from the RTC interrupt with the
+/// `deferred_screen_flush` feature, where waiting for the lock could
+/// deadlock.
+pub fn flush_deferred() {
+    if let Some(mut writer) = WRITER.try_lock() {
+        writer.flush();
+    }
+}
+
+/// The screen as a file.
+struct ScreenDevice {
+    /// The current seek position.
+    position: usize
+}
+
+impl ScreenDevice {
+    /// Opens the screen device, which hides the console output.
+    fn open() -> ScreenDevice {
+        WRITER.lock().device_users += 1;
+
+        ScreenDevice { position: 0 }
+    }
+
+    /// Checks that `length` bytes from the seek position are on the screen.
+    fn check_length(&self, writer: &Writer, length: usize) -> Result<()> {
+        if self.position + length > writer.device_size() {
+            Err(FileError::SeekPastEnd)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Moves the seek position past `length` bytes.
+    fn advance(&mut self, writer: &Writer, length: usize) {
+        self.position += length;
+
+        if self.position == writer.device_size() {
+            self.position = 0;
+        }
+    }
+}
+
+impl Drop for ScreenDevice {
+    fn drop(&mut self) {
+        let mut writer = WRITER.lock();
+
+        writer.device_users -= 1;
+
+        // The console starts over on an empty screen.
+        if writer.device_users == 0 {
+            writer.clear_screen();
+            writer.flush();
+        }
+    }
+}
+
+impl FileHandle for ScreenDevice {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64> {
+        let size = WRITER.lock().device_size() as i64;
+        let new_position = match position {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => size + offset,
+            SeekFrom::Current(offset) => self.position as i64 + offset
+        };
+
+        if new_position < 0 {
+            Err(FileError::SeekBeforeStart)
+        } else if new_position > size {
+            Err(FileError::SeekPastEnd)
+        } else {
+            self.position = new_position as usize;
+
+            Ok(new_position as u64)
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let writer = WRITER.lock();
+        self.check_length(&writer, buffer.len())?;
+
+        for (offset, byte) in buffer.iter_mut().enumerate() {
+            *byte = writer.read_device_byte(self.position + offset);
+        }
+
+        self.advance(&writer, buffer.len());
+
+        Ok(())
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        let mut writer = WRITER.lock();
+        self.check_length(&writer, buffer.len())?;
+
+        for (offset, &byte) in buffer.iter().enumerate() {
+            writer.write_device_byte(self.position + offset, byte);
+        }
+
+        self.advance(&writer, buffer.len());
+
+        // Otherwise the RTC interrupt flushes the screen.
+        if !config::DEFERRED_SCREEN_FLUSH {
+            writer.flush();
+        }
+
+        Ok(())
+    }
+
+    fn len(&mut self) -> u64 {
+        WRITER.lock().device_size() as u64
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        POLL_IN | POLL_OUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::Vec;
+
+    /// Feeds the string to a new parser and returns the resulting actions,
+    /// without the bytes that were part of escape sequences.
+    fn parse(string: &str) -> Vec<Action> {
+        let mut parser = EscapeParser::new();
+
+        string
+            .bytes()
+            .map(|byte| parser.feed(byte))
+            .filter(|action| *action != Action::Nothing)
+            .collect()
+    }
+
+    /// Returns a select graphic rendition action with the given parameters.
+    fn graphics(parameters: &[u16]) -> Action {
+        let mut all_parameters = [0; MAX_PARAMETERS];
+        all_parameters[..parameters.len()].copy_from_slice(parameters);
+
+        Action::SetGraphics(all_parameters, parameters.len())
+    }
+
+    /// Tests that escape sequences are separated from the text.
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("ab"), [Action::Print(b'a'), Action::Print(b'b')]);
+        assert_eq!(
+            parse("\x1b[31mE\x1b[0m"),
+            [graphics(&[31]), Action::Print(b'E'), graphics(&[0])]
+        );
+        assert_eq!(parse("\x1b[1;;33m"), [graphics(&[1, 0, 33])]);
+        assert_eq!(parse("\x1b[m"), [graphics(&[])]);
+        assert_eq!(
+            parse("\x1b[2Jx\x1bcy"),
+            [Action::Print(b'x'), Action::Print(b'y')]
+        );
+        assert_eq!(
+            parse("\x1b[1;2;3;4;5;6;7;8;9m"),
+            [graphics(&[1, 2, 3, 4, 5, 6, 7, 8])]
+        );
+    }
+
+    /// Tests that the parameters set the expected colors.
+    #[test]
+    fn test_apply() {
+        let mut style = Style::DEFAULT;
+
+        style.apply(&[31]);
+        assert_eq!(style.color_code().0, 0x04);
+
+        style.apply(&[1, 44]);
+        assert_eq!(style.color_code().0, 0x1c);
+
+        style.apply(&[22, 93, 49]);
+        assert_eq!(style.color_code().0, 0x0e);
+
+        style.apply(&[5, 39]);
+        assert_eq!(style.color_code().0, 0x07);
+
+        style.apply(&[33]);
+        style.apply(&[]);
+        assert_eq!(style, Style::DEFAULT);
+    }
 }
@@ -0,0 +1,296 @@
+//! Provides the legacy PCI interface of virtio devices.
+//!
+//! Every driver uses only the first queue of its device. Only a single
+//! request is ever pending per device, so the drivers poll for its completion
+//! instead of using interrupts.
+
+use super::pci::{self, COMMAND_BUS_MASTER, COMMAND_IO_SPACE};
+use arch::schedule;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use core::time::Duration;
+use multitasking::{ThreadState, CURRENT_THREAD};
+use sync::time::Timestamp;
+use x86_64::instructions::port::{inb, inl, inw, outb, outl, outw};
+
+/// The PCI vendor ID of virtio devices.
+pub const VENDOR_ID: u16 = 0x1af4;
+
+/// The offset of the features the device offers.
+const HOST_FEATURES: u16 = 0x00;
+
+/// The offset of the features the driver accepted.
+const GUEST_FEATURES: u16 = 0x04;
+
+/// The offset of the page number of the selected queue.
+const QUEUE_ADDRESS: u16 = 0x08;
+
+/// The offset of the size of the selected queue.
+const QUEUE_SIZE: u16 = 0x0c;
+
+/// The offset of the selector of the queue.
+const QUEUE_SELECT: u16 = 0x0e;
+
+/// The offset of the register that notifies the device about a queue.
+const QUEUE_NOTIFY: u16 = 0x10;
+
+/// The offset of the device status.
+const DEVICE_STATUS: u16 = 0x12;
+
+/// The offset of the device specific configuration.
+///
+/// It starts here, because MSI-X is never enabled.
+const DEVICE_CONFIG: u16 = 0x14;
+
+/// The status bit that acknowledges that the device was found.
+const STATUS_ACKNOWLEDGE: u8 = 1;
+
+/// The status bit that tells that a driver for the device exists.
+const STATUS_DRIVER: u8 = 2;
+
+/// The status bit that tells that the driver is ready.
+const STATUS_DRIVER_OK: u8 = 4;
+
+/// The status bit that tells that the driver gave up on the device.
+const STATUS_FAILED: u8 = 128;
+
+/// The descriptor flag that chains the next descriptor.
+const DESCRIPTOR_NEXT: u16 = 1;
+
+/// The descriptor flag that lets the device write to the buffer.
+const DESCRIPTOR_WRITE: u16 = 2;
+
+/// The alignment of the used ring in the legacy interface.
+const QUEUE_ALIGNMENT: usize = 4096;
+
+/// The size of the memory reserved for a queue.
+const QUEUE_MEMORY_SIZE: usize = 4 * QUEUE_ALIGNMENT;
+
+/// The memory of a queue.
+///
+/// It must be part of the kernel image, so that it is physically contiguous,
+/// and be placed in the `.bss.dma` section, which is mapped write-through.
+#[repr(C, align(4096))]
+pub struct QueueMemory([u8; QUEUE_MEMORY_SIZE]);
+
+impl QueueMemory {
+    /// Creates zeroed queue memory.
+    pub const fn new() -> QueueMemory {
+        QueueMemory([0; QUEUE_MEMORY_SIZE])
+    }
+}
+
+/// A buffer that is handed to the device.
+///
+/// The buffer must be part of the kernel image, so that it is physically
+/// contiguous. Like the queue memory, it belongs in the `.bss.dma` section.
+pub struct Buffer {
+    /// The start of the buffer.
+    pub address: *const u8,
+    /// The length of the buffer in bytes.
+    pub length: usize,
+    /// Whether the device writes to the buffer instead of reading it.
+    pub writable: bool
+}
+
+/// A virtio device that was set up with its first queue.
+pub struct Device {
+    /// The first I/O port of the device.
+    io_base: u16,
+    /// The features that were accepted.
+    features: u32,
+    /// The memory of the queue.
+    queue: &'static mut QueueMemory,
+    /// The number of entries in the queue.
+    queue_size: usize,
+    /// The offset of the available ring in the queue memory.
+    available_offset: usize,
+    /// The offset of the used ring in the queue memory.
+    used_offset: usize,
+    /// The index of the next entry in the available ring.
+    next_available: u16,
+    /// The index of the next entry in the used ring.
+    next_used: u16
+}
+
+impl Device {
+    /// Initializes the device of the function with its queue in the given
+    /// memory.
+    ///
+    /// Of the wanted features those that the device offers are accepted. The
+    /// name of the device is only used for messages. Returns `None` if the
+    /// device can't be used.
+    pub fn new(
+        function: pci::Function,
+        name: &str,
+        queue: &'static mut QueueMemory,
+        wanted_features: u32
+    ) -> Option<Device> {
+        let io_base = match function.io_base(0) {
+            Some(io_base) => io_base,
+            None => {
+                warn!("The virtio {} device has no I/O ports.", name);
+                return None;
+            }
+        };
+
+        function.enable(COMMAND_IO_SPACE | COMMAND_BUS_MASTER);
+
+        let features = unsafe {
+            // Writing zero resets the device.
+            outb(io_base + DEVICE_STATUS, 0);
+            outb(io_base + DEVICE_STATUS, STATUS_ACKNOWLEDGE);
+            outb(io_base + DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+            let features = inl(io_base + HOST_FEATURES) & wanted_features;
+            outl(io_base + GUEST_FEATURES, features);
+
+            outw(io_base + QUEUE_SELECT, 0);
+
+            features
+        };
+
+        let queue_size = unsafe { inw(io_base + QUEUE_SIZE) } as usize;
+        let available_offset = 16 * queue_size;
+        let used_offset = align_up(available_offset + 6 + 2 * queue_size);
+        let queue_memory_size = used_offset + align_up(6 + 8 * queue_size);
+
+        if queue_size == 0 || queue_memory_size > QUEUE_MEMORY_SIZE {
+            unsafe {
+                outb(io_base + DEVICE_STATUS, STATUS_FAILED);
+            }
+            warn!(
+                "The virtio {} device has an unsupported queue size of {}.",
+                name, queue_size
+            );
+            return None;
+        }
+
+        unsafe {
+            let queue_address = to_physical!(&*queue as *const QueueMemory);
+
+            outl(
+                io_base + QUEUE_ADDRESS,
+                (queue_address / QUEUE_ALIGNMENT) as u32
+            );
+            outb(
+                io_base + DEVICE_STATUS,
+                STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK
+            );
+        }
+
+        debug!(
+            "Found a virtio {} device at {:?} (ports {:#x}).",
+            name, function, io_base
+        );
+
+        Some(Device {
+            io_base,
+            features,
+            queue,
+            queue_size,
+            available_offset,
+            used_offset,
+            next_available: 0,
+            next_used: 0
+        })
+    }
+
+    /// Returns the features that were accepted.
+    pub fn features(&self) -> u32 {
+        self.features
+    }
+
+    /// Reads the byte at the given offset of the device configuration.
+    pub fn read_config(&self, offset: u16) -> u8 {
+        unsafe { inb(self.io_base + DEVICE_CONFIG + offset) }
+    }
+
+    /// Hands the buffers to the device as a single request.
+    ///
+    /// The previous request must have been completed.
+    ///
+    /// # Panics
+    /// Panics if there are more buffers than entries in the queue.
+    pub fn submit(&mut self, buffers: &[Buffer]) {
+        assert!(
+            buffers.len() <= self.queue_size,
+            "A virtio request has too many buffers."
+        );
+
+        unsafe {
+            let queue = self.queue.0.as_mut_ptr();
+
+            // The request always starts at the first descriptor.
+            for (index, buffer) in buffers.iter().enumerate() {
+                let descriptor = queue.offset(16 * index as isize);
+
+                let mut flags = if buffer.writable { DESCRIPTOR_WRITE } else { 0 };
+                if index + 1 < buffers.len() {
+                    flags |= DESCRIPTOR_NEXT;
+                }
+
+                write_volatile(descriptor as *mut u64, to_physical!(buffer.address) as u64);
+                write_volatile(descriptor.offset(8) as *mut u32, buffer.length as u32);
+                write_volatile(descriptor.offset(12) as *mut u16, flags);
+                write_volatile(descriptor.offset(14) as *mut u16, index as u16 + 1);
+            }
+
+            let available = queue.offset(self.available_offset as isize);
+            let slot = self.next_available as usize % self.queue_size;
+            write_volatile(available.offset(4 + 2 * slot as isize) as *mut u16, 0);
+
+            self.next_available = self.next_available.wrapping_add(1);
+
+            // The device must see the entry before the new index.
+            fence(Ordering::SeqCst);
+            write_volatile(available.offset(2) as *mut u16, self.next_available);
+            fence(Ordering::SeqCst);
+
+            outw(self.io_base + QUEUE_NOTIFY, 0);
+        }
+    }
+
+    /// Returns the number of bytes the device wrote, if it completed the
+    /// request.
+    pub fn poll(&mut self) -> Option<usize> {
+        unsafe {
+            let used = self.queue.0.as_ptr().offset(self.used_offset as isize);
+
+            if read_volatile(used.offset(2) as *const u16) == self.next_used {
+                return None;
+            }
+
+            fence(Ordering::SeqCst);
+
+            let slot = self.next_used as usize % self.queue_size;
+            let length = read_volatile(used.offset(4 + 8 * slot as isize + 4) as *const u32);
+
+            self.next_used = self.next_used.wrapping_add(1);
+
+            Some(length as usize)
+        }
+    }
+}
+
+/// Aligns the offset to the queue alignment.
+fn align_up(offset: usize) -> usize {
+    (offset + QUEUE_ALIGNMENT - 1) / QUEUE_ALIGNMENT * QUEUE_ALIGNMENT
+}
+
+/// Returns the first virtio device function with the given device ID.
+pub fn find(device_id: u16) -> Option<pci::Function> {
+    pci::find(VENDOR_ID, device_id)
+}
+
+/// Sleeps for the given duration.
+///
+/// This must only be called by the threads of the drivers.
+pub fn sleep(duration: Duration) {
+    let wake_time = Timestamp::get_current()
+        .offset(duration)
+        .expect("The time since boot overflowed.");
+
+    CURRENT_THREAD.lock().state = ThreadState::Sleeping(wake_time);
+    schedule();
+}
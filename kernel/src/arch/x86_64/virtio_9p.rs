@@ -0,0 +1,135 @@
+//! A driver for the legacy PCI interface of virtio 9P transports.
+//!
+//! QEMU shares a host directory through this device (`-virtfs`). The driver
+//! only moves the messages, the 9P client itself is in the `ninep` module.
+//!
+//! Files on the host are read while the reading process is locked, so the
+//! driver can't sleep. It spins until the host answered instead.
+
+use super::virtio::{self, Buffer, Device, QueueMemory};
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::cmp::min;
+use core::ptr::{read_volatile, write_volatile};
+use file_handle::{FileError, Result};
+use ninep::{self, MAX_MESSAGE_SIZE};
+use sync::cpu_relax;
+
+/// The PCI device ID of transitional virtio 9P transports.
+const DEVICE_ID: u16 = 0x1009;
+
+/// The feature bit that tells that the device configuration has a tag.
+const FEATURE_MOUNT_TAG: u32 = 1;
+
+/// The number of checks for the response before the device is given up.
+const MAX_RESPONSE_CHECKS: usize = 1 << 28;
+
+/// The memory of the queue of the device.
+#[link_section = ".bss.dma"]
+static mut QUEUE: QueueMemory = QueueMemory::new();
+
+/// The buffer with the request for the device.
+#[link_section = ".bss.dma"]
+static mut REQUEST: [u8; MAX_MESSAGE_SIZE] = [0; MAX_MESSAGE_SIZE];
+
+/// The buffer that receives the response of the device.
+#[link_section = ".bss.dma"]
+static mut RESPONSE: [u8; MAX_MESSAGE_SIZE] = [0; MAX_MESSAGE_SIZE];
+
+/// The transport through the device.
+///
+/// Only a single instance exists, which owns the request and response
+/// buffers.
+struct Transport {
+    /// The device.
+    device: Device,
+    /// Whether the device stopped answering.
+    failed: bool
+}
+
+impl ninep::Transport for Transport {
+    fn transact(&mut self, request: &[u8], response: &mut [u8]) -> Result<usize> {
+        if self.failed {
+            return Err(FileError::DeviceRemoved);
+        }
+
+        let request_length = min(request.len(), MAX_MESSAGE_SIZE);
+        let response_length = min(response.len(), MAX_MESSAGE_SIZE);
+
+        unsafe {
+            for (shared, &byte) in REQUEST.iter_mut().zip(request[..request_length].iter()) {
+                write_volatile(shared, byte);
+            }
+
+            self.device.submit(&[
+                Buffer {
+                    address: REQUEST.as_ptr(),
+                    length: request_length,
+                    writable: false
+                },
+                Buffer {
+                    address: RESPONSE.as_ptr(),
+                    length: response_length,
+                    writable: true
+                }
+            ]);
+        }
+
+        let mut checks = 0;
+        let length = loop {
+            if let Some(length) = self.device.poll() {
+                break min(length, response_length);
+            }
+
+            checks += 1;
+            if checks == MAX_RESPONSE_CHECKS {
+                warn!("The virtio 9P device didn't answer, giving it up.");
+                self.failed = true;
+                return Err(FileError::DeviceRemoved);
+            }
+
+            cpu_relax();
+        };
+
+        unsafe {
+            for (byte, shared) in response[..length].iter_mut().zip(RESPONSE.iter()) {
+                *byte = read_volatile(shared);
+            }
+        }
+
+        Ok(length)
+    }
+}
+
+/// Looks for a 9P transport and mounts the host directory, if there is one.
+pub fn init() {
+    assert_has_not_been_called!("The virtio 9P driver should only be started once.");
+
+    let function = match virtio::find(DEVICE_ID) {
+        Some(function) => function,
+        None => return
+    };
+
+    // The queue memory is only ever used by this device.
+    let device = match Device::new(function, "9P", unsafe { &mut QUEUE }, FEATURE_MOUNT_TAG) {
+        Some(device) => device,
+        None => return
+    };
+
+    let mut tag = String::new();
+    if device.features() & FEATURE_MOUNT_TAG != 0 {
+        let length = u16::from(device.read_config(0)) | u16::from(device.read_config(1)) << 8;
+
+        for offset in 0..length {
+            tag.push(char::from(device.read_config(2 + offset)));
+        }
+    }
+
+    ninep::mount(
+        &tag,
+        Box::new(Transport {
+            device,
+            failed: false
+        })
+    );
+}
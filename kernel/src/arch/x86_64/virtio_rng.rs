@@ -0,0 +1,122 @@
+//! A driver for the legacy PCI interface of virtio entropy devices.
+//!
+//! Virtual machines without `RDRAND` get their randomness from the host
+//! through this device. A kernel thread periodically requests random bytes
+//! and adds them to the entropy pool of the kernel.
+
+use super::virtio::{self, Buffer, Device, QueueMemory};
+use arch::schedule;
+use core::ptr::read_volatile;
+use core::time::Duration;
+use multitasking::scheduler::after_context_switch;
+use multitasking::{spawn_kernel_thread, Name, CURRENT_THREAD};
+use random;
+use sync::time::Timestamp;
+use sync::{enable_preemption, Mutex};
+
+/// The PCI device ID of transitional virtio entropy devices.
+const DEVICE_ID: u16 = 0x1005;
+
+/// The number of random bytes requested at once.
+const REQUEST_SIZE: usize = 64;
+
+/// The time between two requests.
+const REQUEST_INTERVAL_SECS: u64 = 10;
+
+/// The time after which a request is given up.
+const REQUEST_TIMEOUT_MS: u64 = 1000;
+
+/// The time between two checks whether a request was completed.
+const POLL_INTERVAL_MS: u64 = 1;
+
+/// The entropy credited per random byte from the host.
+const ENTROPY_BITS_PER_BYTE: usize = 8;
+
+/// The memory of the queue of the device.
+#[link_section = ".bss.dma"]
+static mut QUEUE: QueueMemory = QueueMemory::new();
+
+/// The buffer that receives the random bytes.
+#[link_section = ".bss.dma"]
+static mut BUFFER: [u8; REQUEST_SIZE] = [0; REQUEST_SIZE];
+
+/// The entropy device, if one was found.
+static DEVICE: Mutex<Option<Device>> = Mutex::new(None);
+
+/// Hands the buffer to the device to fill it with random bytes.
+fn request(device: &mut Device) {
+    device.submit(&[Buffer {
+        address: unsafe { BUFFER.as_ptr() },
+        length: REQUEST_SIZE,
+        writable: true
+    }]);
+}
+
+/// Looks for an entropy device and starts its thread, if there is one.
+///
+/// This must be called once while the address space of the idle process is
+/// active.
+pub fn init() {
+    assert_has_not_been_called!("The virtio entropy driver should only be started once.");
+
+    let function = match virtio::find(DEVICE_ID) {
+        Some(function) => function,
+        None => return
+    };
+
+    // The queue memory is only ever used by this device.
+    if let Some(device) = Device::new(function, "entropy", unsafe { &mut QUEUE }, 0) {
+        *DEVICE.lock() = Some(device);
+        spawn_kernel_thread(Name::new("virtio-rng"), requester);
+    }
+}
+
+/// Requests random bytes from the device and adds them to the entropy pool.
+fn requester() -> ! {
+    // The thread starts right after a context switch away from another
+    // thread.
+    after_context_switch();
+    unsafe {
+        enable_preemption();
+    }
+
+    loop {
+        request(
+            DEVICE
+                .lock()
+                .as_mut()
+                .expect("The entropy thread runs without a device.")
+        );
+
+        let deadline = Timestamp::get_current()
+            .offset(Duration::from_millis(REQUEST_TIMEOUT_MS))
+            .expect("The time since boot overflowed.");
+
+        loop {
+            if let Some(length) = DEVICE.lock().as_mut().and_then(|device| device.poll()) {
+                let length = length.min(REQUEST_SIZE);
+                let mut bytes = [0; REQUEST_SIZE];
+                unsafe {
+                    for (byte, shared) in bytes.iter_mut().zip(BUFFER.iter()) {
+                        *byte = read_volatile(shared);
+                    }
+                }
+
+                random::add_entropy(&bytes[..length], length * ENTROPY_BITS_PER_BYTE);
+                break;
+            }
+
+            if Timestamp::get_current() > deadline {
+                warn!("The virtio entropy device didn't answer, stopping its thread.");
+
+                CURRENT_THREAD.lock().kill();
+                schedule();
+                unreachable!("The stopped virtio entropy thread was scheduled.");
+            }
+
+            virtio::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        }
+
+        virtio::sleep(Duration::from_secs(REQUEST_INTERVAL_SECS));
+    }
+}
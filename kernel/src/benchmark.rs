@@ -0,0 +1,203 @@
+//! This module contains microbenchmarks of the kernel.
+//!
+//! The benchmarks only run with the `benchmark` feature. They run once
+//! during boot, before the init process is started, and log their results.
+
+use alloc::boxed::Box;
+use alloc::Vec;
+use arch::{self, Architecture, Context};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use core::time::Duration;
+use elf;
+use log;
+use memory::address_space::{AddressSpace, AddressSpaceHandle};
+use memory::VirtualAddress;
+use multitasking::resource_group::ROOT_GROUP;
+use multitasking::scheduler::READY_LIST;
+use multitasking::Inheritance;
+use sync::time::Timestamp;
+
+/// The program that is spawned by the process creation benchmark.
+const SPAWN_PROGRAM: &'static str = "/bin/test";
+
+/// The number of processes that are spawned by the process creation
+/// benchmark.
+const SPAWN_ITERATIONS: u32 = 100;
+
+/// The number of round trips between two contexts that are measured by the
+/// context switch benchmark.
+const SWITCH_ITERATIONS: u32 = 10000;
+
+/// The size of the stacks used by the context switch benchmark.
+const SWITCH_STACK_SIZE: usize = 0x4000;
+
+/// The architecture specific context type.
+type ArchContext = <arch::Current as Architecture>::Context;
+
+/// The contexts that the context switch benchmark switches between.
+struct SwitchPartners {
+    /// The context of the benchmark itself.
+    main: ArchContext,
+    /// The context that switches straight back.
+    partner: ArchContext,
+    /// The address space the benchmark runs in.
+    main_address_space: AddressSpaceHandle,
+    /// The address space the partner runs in.
+    partner_address_space: AddressSpaceHandle
+}
+
+/// The address of the `SwitchPartners` of the running context switch
+/// benchmark.
+static SWITCH_PARTNERS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Runs all benchmarks.
+pub fn run() {
+    spawn_exit();
+    context_switch();
+}
+
+/// Measures the latency of spawning a process and letting it exit.
+///
+/// The spawned processes never run, so this measures the work the kernel
+/// does to create and destroy a process.
+fn spawn_exit() {
+    let mut total = Duration::new(0, 0);
+    let mut fastest = None;
+    let mut slowest = Duration::new(0, 0);
+
+    // The log messages of every spawned process would dominate the results.
+    let log_level = log::max_level();
+    log::set_max_level(log::LevelFilter::Warn);
+
+    for _ in 0..SPAWN_ITERATIONS {
+        let start = Timestamp::get_current();
+
+        let pid = elf::process_from_initramfs_file(
+            SPAWN_PROGRAM,
+            &[SPAWN_PROGRAM],
+            &[],
+            true,
+            Inheritance::from_current()
+        ).expect("The benchmark program could not be loaded.");
+
+        let thread = READY_LIST.lock().pop();
+        assert!(
+            thread.as_ref().map_or(false, |thread| thread.pid == pid),
+            "The spawned thread is not the next ready thread."
+        );
+
+        // Dropping the only thread of the process also drops the process.
+        drop(thread);
+
+        let duration = Timestamp::get_current() - start;
+
+        total += duration;
+        slowest = if duration > slowest { duration } else { slowest };
+        fastest = match fastest {
+            Some(fastest) if fastest < duration => Some(fastest),
+            _ => Some(duration)
+        };
+    }
+
+    log::set_max_level(log_level);
+
+    info!(
+        "Spawn and exit of {}: average {}µs, fastest {}µs, slowest {}µs.",
+        SPAWN_PROGRAM,
+        as_micros(total) / SPAWN_ITERATIONS as u64,
+        as_micros(fastest.unwrap_or_default()),
+        as_micros(slowest)
+    );
+}
+
+/// Measures the cost of context switches within an address space and between
+/// address spaces.
+fn context_switch() {
+    let within = average_round_trip(arch::Current::get_current_address_space());
+
+    let other_address_space = AddressSpace::new(usize::max_value(), ROOT_GROUP);
+    let between = average_round_trip(other_address_space.handle());
+
+    info!(
+        "Context switch round trip: {}ns within an address space, {}ns between address spaces.",
+        as_nanos(within),
+        as_nanos(between)
+    );
+}
+
+/// Returns the average time of switching to a partner context in the given
+/// address space and back.
+///
+/// The partner only runs kernel code on a heap allocated stack, which are
+/// mapped in every address space.
+fn average_round_trip(partner_address_space: AddressSpaceHandle) -> Duration {
+    let mut main_stack = Vec::new();
+    main_stack.resize(SWITCH_STACK_SIZE, 0u8);
+    let mut partner_stack = Vec::new();
+    partner_stack.resize(SWITCH_STACK_SIZE, 0u8);
+
+    // The main context is overwritten by the first switch, so it never runs
+    // the partner function.
+    let mut partners = Box::new(SwitchPartners {
+        main: ArchContext::kernel_thread(switch_partner, stack_top(&mut main_stack)),
+        partner: ArchContext::kernel_thread(switch_partner, stack_top(&mut partner_stack)),
+        main_address_space: arch::Current::get_current_address_space(),
+        partner_address_space
+    });
+
+    let partners_address = &mut *partners as *mut SwitchPartners as usize;
+    SWITCH_PARTNERS.store(partners_address, Ordering::Release);
+
+    let start = Timestamp::get_current();
+
+    for _ in 0..SWITCH_ITERATIONS {
+        unsafe {
+            arch::Current::switch_context(
+                &mut partners.main,
+                &partners.partner,
+                partners.main_address_space,
+                partners.partner_address_space
+            );
+        }
+    }
+
+    let duration = Timestamp::get_current() - start;
+
+    // The partner stays suspended, so its stack can be freed.
+    SWITCH_PARTNERS.store(0, Ordering::Release);
+
+    duration / SWITCH_ITERATIONS
+}
+
+/// Switches straight back to the context switch benchmark whenever it runs.
+fn switch_partner() -> ! {
+    loop {
+        unsafe {
+            let partners = &mut *(SWITCH_PARTNERS.load(Ordering::Acquire) as *mut SwitchPartners);
+
+            arch::Current::switch_context(
+                &mut partners.partner,
+                &partners.main,
+                partners.partner_address_space,
+                partners.main_address_space
+            );
+        }
+    }
+}
+
+/// Returns the 16 byte aligned top of the given stack.
+fn stack_top(stack: &mut Vec<u8>) -> VirtualAddress {
+    let end = stack.as_mut_ptr() as usize + stack.len();
+
+    VirtualAddress::from_usize(end & !0xf)
+}
+
+/// Returns the number of whole nanoseconds in the duration.
+fn as_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+/// Returns the number of whole microseconds in the duration.
+fn as_micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + duration.subsec_micros() as u64
+}
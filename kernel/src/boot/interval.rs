@@ -0,0 +1,197 @@
+//! Removes excluded areas from a sequence of memory areas.
+
+use memory::{Address, MemoryArea, PhysicalAddress};
+
+/// Iterates over the parts of a sequence of memory areas that aren't covered
+/// by any of the excluded areas.
+///
+/// The excluded areas may be given in any order and may overlap with each
+/// other, with multiple of the memory areas or with none at all. Empty
+/// excluded areas are ignored.
+pub struct Subtraction<I, E> {
+    /// The memory areas to subtract from.
+    areas: I,
+    /// The areas to remove from the memory areas.
+    excluded: E,
+    /// The part of the current memory area that wasn't returned yet.
+    current: Option<MemoryArea<PhysicalAddress>>
+}
+
+/// Returns an iterator over the parts of `areas` not covered by `excluded`.
+pub fn subtract<I, E>(areas: I, excluded: E) -> Subtraction<I, E>
+where
+    I: Iterator<Item = MemoryArea<PhysicalAddress>>,
+    E: AsRef<[MemoryArea<PhysicalAddress>]>
+{
+    Subtraction {
+        areas,
+        excluded,
+        current: None
+    }
+}
+
+impl<I, E> Iterator for Subtraction<I, E>
+where
+    I: Iterator<Item = MemoryArea<PhysicalAddress>>,
+    E: AsRef<[MemoryArea<PhysicalAddress>]>
+{
+    type Item = MemoryArea<PhysicalAddress>;
+
+    fn next(&mut self) -> Option<MemoryArea<PhysicalAddress>> {
+        loop {
+            let area = match self.current.take() {
+                Some(area) => area,
+                None => self.areas.next()?
+            };
+
+            let excluded = self.excluded.as_ref();
+            let end = area.end_address();
+            let mut start = area.start_address();
+
+            // Skip all excluded areas covering the start.
+            loop {
+                let excluded_end = excluded
+                    .iter()
+                    .find(|excluded| excluded.length() > 0 && excluded.contains(start))
+                    .map(|excluded| excluded.end_address());
+
+                match excluded_end {
+                    Some(excluded_end) if excluded_end < end => start = excluded_end,
+                    Some(_) => {
+                        start = end;
+                        break;
+                    },
+                    None => break
+                }
+            }
+
+            if start >= end {
+                continue;
+            }
+
+            // The returned part ends where the next excluded area starts.
+            let part_end = excluded
+                .iter()
+                .filter(|excluded| excluded.length() > 0)
+                .map(|excluded| excluded.start_address())
+                .filter(|&excluded_start| start < excluded_start && excluded_start < end)
+                .min()
+                .unwrap_or(end);
+
+            if part_end < end {
+                self.current = Some(MemoryArea::from_start_and_end(part_end, end));
+            }
+
+            return Some(MemoryArea::from_start_and_end(start, part_end));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::Vec;
+
+    /// Creates a memory area from its start and end address.
+    fn area(start: usize, end: usize) -> MemoryArea<PhysicalAddress> {
+        MemoryArea::from_start_and_end(
+            PhysicalAddress::from_usize(start),
+            PhysicalAddress::from_usize(end)
+        )
+    }
+
+    /// Returns the start and end addresses of the subtraction result.
+    fn run(
+        areas: &[MemoryArea<PhysicalAddress>],
+        excluded: &[MemoryArea<PhysicalAddress>]
+    ) -> Vec<(usize, usize)> {
+        subtract(areas.iter().cloned(), excluded)
+            .map(|area| (area.start_address().as_usize(), area.end_address().as_usize()))
+            .collect()
+    }
+
+    /// Tests that areas are returned unchanged without exclusions.
+    #[test]
+    fn test_nothing_excluded() {
+        assert_eq!(
+            run(&[area(0x1000, 0x5000), area(0x8000, 0x9000)], &[]),
+            [(0x1000, 0x5000), (0x8000, 0x9000)]
+        );
+        assert_eq!(
+            run(&[area(0x1000, 0x5000)], &[area(0x6000, 0x7000), area(0, 0x1000)]),
+            [(0x1000, 0x5000)]
+        );
+    }
+
+    /// Tests excluding areas in the middle, at the start and at the end.
+    #[test]
+    fn test_contained_exclusions() {
+        assert_eq!(
+            run(&[area(0x1000, 0x5000)], &[area(0x2000, 0x3000)]),
+            [(0x1000, 0x2000), (0x3000, 0x5000)]
+        );
+        assert_eq!(
+            run(&[area(0x1000, 0x5000)], &[area(0x1000, 0x2000)]),
+            [(0x2000, 0x5000)]
+        );
+        assert_eq!(
+            run(&[area(0x1000, 0x5000)], &[area(0x4000, 0x5000)]),
+            [(0x1000, 0x4000)]
+        );
+        assert!(run(&[area(0x1000, 0x5000)], &[area(0x1000, 0x5000)]).is_empty());
+    }
+
+    /// Tests exclusions that only partially overlap with an area.
+    #[test]
+    fn test_partial_overlaps() {
+        assert_eq!(
+            run(&[area(0x1000, 0x5000)], &[area(0, 0x2000)]),
+            [(0x2000, 0x5000)]
+        );
+        assert_eq!(
+            run(&[area(0x1000, 0x5000)], &[area(0x4000, 0x9000)]),
+            [(0x1000, 0x4000)]
+        );
+        assert!(run(&[area(0x1000, 0x5000)], &[area(0, 0x9000)]).is_empty());
+    }
+
+    /// Tests unsorted exclusions that overlap with each other.
+    #[test]
+    fn test_unsorted_overlapping_exclusions() {
+        assert_eq!(
+            run(
+                &[area(0x1000, 0x9000)],
+                &[area(0x6000, 0x7000), area(0x2000, 0x4000), area(0x3000, 0x5000)]
+            ),
+            [(0x1000, 0x2000), (0x5000, 0x6000), (0x7000, 0x9000)]
+        );
+        assert_eq!(
+            run(&[area(0x1000, 0x9000)], &[area(0x2000, 0x4000), area(0x2000, 0x4000)]),
+            [(0x1000, 0x2000), (0x4000, 0x9000)]
+        );
+    }
+
+    /// Tests a single exclusion spanning multiple areas.
+    #[test]
+    fn test_exclusion_spanning_areas() {
+        assert_eq!(
+            run(
+                &[area(0x1000, 0x3000), area(0x4000, 0x6000), area(0x7000, 0x9000)],
+                &[area(0x2000, 0x8000)]
+            ),
+            [(0x1000, 0x2000), (0x8000, 0x9000)]
+        );
+    }
+
+    /// Tests that empty areas and exclusions have no effect.
+    #[test]
+    fn test_empty_areas() {
+        assert_eq!(
+            run(
+                &[area(0x1000, 0x1000), area(0x2000, 0x3000)],
+                &[area(0x2800, 0x2800)]
+            ),
+            [(0x2000, 0x3000)]
+        );
+    }
+}
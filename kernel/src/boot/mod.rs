@@ -1,11 +1,20 @@
 //! Provides information about the initial status of the system.
 mod freestanding;
+mod interval;
 mod multiboot;
 mod multiboot2;
+mod regions;
+
+pub use self::regions::{get_region_type, get_regions, is_ram, is_reserved, overlaps_available,
+                        Region, RegionType};
 
 #[cfg(target_arch = "x86_64")]
+use alloc::Vec;
 use arch::{self, vga_buffer, Architecture};
+use core::{iter, str};
+use memory::{early_heap, memtest};
 use memory::{Address, MemoryArea, PhysicalAddress, PAGE_SIZE};
+use sync::OnceCell;
 
 /// Lists possiblities for boot sources.
 enum BootMethod {
@@ -17,53 +26,105 @@ enum BootMethod {
     Multiboot2
 }
 
-/// The memory area containing the initramfs.
-fn initramfs() -> MemoryArea<PhysicalAddress> {
-    let area = get_initramfs_area();
-    // Align to the previous page.
-    let initramfs_start = area.start_address().page_align_down();
-
-    // Round up the the next page boundary.
-    let initramfs_length = area.length();
-    let initramfs_length = if initramfs_length > 0 {
-        (initramfs_length - 1) / PAGE_SIZE * PAGE_SIZE + PAGE_SIZE
-    } else {
-        0
-    };
-    MemoryArea::new(initramfs_start, initramfs_length)
+/// A module loaded by the boot loader.
+#[derive(Debug, Clone, Copy)]
+pub struct Module {
+    /// The physical memory containing the module.
+    pub area: MemoryArea<PhysicalAddress>,
+    /// The name of the module.
+    pub name: &'static str
 }
 
-/// Provides an iterator for a memory map.
-pub struct MemoryMapIterator {
-    multiboot_iterator: multiboot::MemoryMapIterator,
-    to_exclude: [MemoryArea<PhysicalAddress>; 2],
-    current_entry: Option<MemoryArea<PhysicalAddress>>,
-    exclude_index: usize
+/// The information passed by the boot loader.
+///
+/// It is copied into memory owned by the kernel, so that the memory
+/// containing the original can be reused.
+struct BootInformation {
+    /// The name of the boot loader.
+    bootloader_name: &'static str,
+    /// The command line passed to the kernel.
+    command_line: &'static str,
+    /// The modules loaded by the boot loader.
+    modules: &'static [Module]
 }
 
-impl MemoryMapIterator {
-    /// Creates a new memory map iterator.
-    fn new() -> MemoryMapIterator {
-        let kernel_area = arch::Current::get_kernel_area();
-        let initramfs_area = initramfs();
+/// The maximum number of areas that are excluded from the memory map.
+///
+/// This includes the defective areas found by the memory test.
+const MAX_EXCLUDED_AREAS: usize = 32 + memtest::MAX_BAD_AREAS;
+
+/// Returns the smallest page aligned area containing the given area.
+fn page_align_outward(area: MemoryArea<PhysicalAddress>) -> MemoryArea<PhysicalAddress> {
+    if area.length() == 0 {
+        return area;
+    }
+
+    let start = area.start_address().page_align_down();
+    let end = area
+        .end_address()
+        .align_up(PAGE_SIZE)
+        .expect("The area reaches the end of the address space.");
+
+    MemoryArea::from_start_and_end(start, end)
+}
 
-        let to_exclude = if kernel_area.start_address() <= initramfs_area.start_address() {
-            [kernel_area, initramfs_area]
-        } else {
-            [initramfs_area, kernel_area]
+/// Returns the areas within available memory that are in use since boot.
+///
+/// These are the kernel, the used part of the early heap, the modules loaded
+/// by the boot loader and the frames that failed the memory test.
+///
+/// The areas are page aligned. Unused entries are empty.
+fn get_excluded_areas() -> [MemoryArea<PhysicalAddress>; MAX_EXCLUDED_AREAS] {
+    let mut excluded = [MemoryArea::new(PhysicalAddress::from_const(0), 0); MAX_EXCLUDED_AREAS];
+
+    {
+        let mut count = 0;
+        let mut exclude = |area: MemoryArea<PhysicalAddress>| {
+            assert!(
+                count < MAX_EXCLUDED_AREAS,
+                "Too many areas to exclude from the memory map."
+            );
+
+            excluded[count] = page_align_outward(area);
+            count += 1;
         };
 
-        let mut multiboot_iterator = multiboot::get_memory_map();
+        exclude(arch::Current::get_kernel_area());
+        exclude(early_heap::finish());
+        exclude(arch::Current::get_wakeup_area());
+        exclude(arch::Current::get_crash_dump_area());
 
-        let current_entry = multiboot_iterator.next();
+        for module in get_modules() {
+            exclude(module.area);
+        }
+
+        for &area in memtest::get_bad_areas().iter() {
+            if area.length() > 0 {
+                exclude(area);
+            }
+        }
+    }
+
+    excluded
+}
 
-        let exclude_index = 0;
+/// Provides an iterator for a memory map.
+pub struct MemoryMapIterator {
+    /// The available regions without the excluded areas.
+    free_areas: interval::Subtraction<
+        regions::AvailableRegionIterator,
+        [MemoryArea<PhysicalAddress>; MAX_EXCLUDED_AREAS]
+    >
+}
 
+impl MemoryMapIterator {
+    /// Creates a new memory map iterator.
+    fn new() -> MemoryMapIterator {
         MemoryMapIterator {
-            multiboot_iterator,
-            to_exclude,
-            current_entry,
-            exclude_index
+            free_areas: interval::subtract(
+                regions::AvailableRegionIterator::new(),
+                get_excluded_areas()
+            )
         }
     }
 }
@@ -72,75 +133,19 @@ impl Iterator for MemoryMapIterator {
     type Item = MemoryArea<PhysicalAddress>;
 
     fn next(&mut self) -> Option<MemoryArea<PhysicalAddress>> {
-        // NOTE: This assumes function makes a few assumptions to work properly:
-        // - The to_exclude list must be ordered by the start addresses.
-        // - The to_exclude entries must not overlap.
-        // - The memory areas must not overlap.
-        // - A to_exclude entry must lie completely within a memory area.
-
-        let get_next_entry = |iterator: &mut MemoryMapIterator| match *get_boot_method() {
-            BootMethod::Multiboot => iterator.multiboot_iterator.next(),
-            _ => unimplemented!()
-        };
-
-        loop {
-            return if let Some(current_entry) = self.current_entry {
-                if self.exclude_index >= self.to_exclude.len() {
-                    // If all the exclude areas were handled.
-
-                    self.current_entry = get_next_entry(self);
-
-                    Some(current_entry)
-                } else {
-                    // Handle the exclude areas.
-
-                    if self.to_exclude[self.exclude_index].is_contained_in(current_entry) {
-                        // The area to exclude is contained in the current free entry.
-                        let (entry_before, entry_after) = {
-                            let exclude_area = &self.to_exclude[self.exclude_index];
-
-                            (
-                                MemoryArea::new(
-                                    current_entry.start_address(),
-                                    exclude_area.start_address() - current_entry.start_address()
-                                ),
-                                MemoryArea::new(
-                                    exclude_area.end_address(),
-                                    current_entry.end_address() - exclude_area.end_address()
-                                )
-                            )
-                        };
-
-                        self.exclude_index += 1;
-
-                        if entry_after.end_address() == entry_after.start_address() {
-                            self.current_entry = get_next_entry(self);
-                        } else {
-                            self.current_entry = Some(entry_after);
-                        }
-
-                        if entry_before.end_address() == entry_before.start_address() {
-                            continue;
-                        } else {
-                            Some(entry_before)
-                        }
-                    } else {
-                        self.current_entry = get_next_entry(self);
-
-                        Some(current_entry)
-                    }
-                }
-            } else {
-                None
-            };
-        }
+        self.free_areas.next()
     }
 }
 
 /// The method that the system was booted with.
-// This will only be set once very early. After that it can be assumed to be
-// static.
-static mut BOOT_METHOD: BootMethod = BootMethod::Unknown;
+static BOOT_METHOD: OnceCell<BootMethod> = OnceCell::new();
+
+/// The information passed by the boot loader.
+static BOOT_INFORMATION: OnceCell<BootInformation> = OnceCell::new();
+
+/// Information about the VGA buffer.
+#[cfg(target_arch = "x86_64")]
+static VGA_INFO: OnceCell<vga_buffer::Info> = OnceCell::new();
 
 /// Initializes the boot module and all the data it provides.
 pub fn init(magic_number: u32, information_structure_address: usize) {
@@ -153,51 +158,195 @@ pub fn init(magic_number: u32, information_structure_address: usize) {
         BootMethod::Multiboot => multiboot::init(information_structure_address),
         _ => freestanding::init()
     };
+
+    match *get_boot_method() {
+        BootMethod::Multiboot2 => regions::init(multiboot2::get_memory_map()),
+        BootMethod::Multiboot => regions::init(multiboot::get_memory_map()),
+        _ => regions::init(iter::empty())
+    }
+
+    copy_boot_information();
+
+    #[cfg(target_arch = "x86_64")]
+    copy_vga_info();
+
+    match *get_boot_method() {
+        BootMethod::Multiboot2 => multiboot2::release(),
+        BootMethod::Multiboot => multiboot::release(),
+        _ => ()
+    }
+}
+
+/// Copies the information passed by the boot loader onto the early heap.
+fn copy_boot_information() {
+    let bootloader_name = match *get_boot_method() {
+        BootMethod::Multiboot2 => multiboot2::get_bootloader_name(),
+        BootMethod::Multiboot => multiboot::get_bootloader_name(),
+        _ => "no boot loader"
+    };
+
+    let command_line = match *get_boot_method() {
+        BootMethod::Multiboot2 => multiboot2::get_command_line(),
+        BootMethod::Multiboot => multiboot::get_command_line(),
+        _ => ""
+    };
+
+    let mut count = 0;
+    for_each_module(|_, _| count += 1);
+
+    let empty_module = Module {
+        area: MemoryArea::new(PhysicalAddress::from_const(0), 0),
+        name: ""
+    };
+    let modules = early_heap::allocate_slice(count, empty_module);
+
+    let mut index = 0;
+    for_each_module(|area, name| {
+        modules[index] = Module {
+            area,
+            name: copy_str(name)
+        };
+        index += 1;
+    });
+
+    let modules: &'static [Module] = modules;
+
+    let boot_information = BootInformation {
+        bootloader_name: copy_str(bootloader_name),
+        command_line: copy_str(command_line),
+        modules
+    };
+
+    assert!(
+        BOOT_INFORMATION.set(boot_information).is_ok(),
+        "The boot information should only be copied once."
+    );
+}
+
+/// Calls `f` with the memory area and the name of every module loaded by the
+/// boot loader.
+fn for_each_module<F: FnMut(MemoryArea<PhysicalAddress>, &str)>(f: F) {
+    match *get_boot_method() {
+        BootMethod::Multiboot2 => multiboot2::for_each_module(f),
+        BootMethod::Multiboot => multiboot::for_each_module(f),
+        _ => ()
+    }
+}
+
+/// Copies the information about the VGA buffer.
+#[cfg(target_arch = "x86_64")]
+fn copy_vga_info() {
+    let vga_info = match *get_boot_method() {
+        BootMethod::Multiboot2 => multiboot2::get_vga_info(),
+        _ => freestanding::get_vga_info()
+    };
+
+    assert!(
+        VGA_INFO.set(vga_info).is_ok(),
+        "The VGA information should only be copied once."
+    );
+}
+
+/// Copies the string onto the early heap.
+fn copy_str(string: &str) -> &'static str {
+    let copy = early_heap::allocate_slice(string.len(), 0u8);
+    copy.copy_from_slice(string.as_bytes());
+
+    unsafe { str::from_utf8_unchecked(copy) }
 }
 
 /// Identifies the boot method.
 fn set_boot_method(magic_number: u32) {
-    unsafe {
-        BOOT_METHOD = match magic_number {
-            0x36d76289 => BootMethod::Multiboot2,
-            0x2badb002 => BootMethod::Multiboot,
-            _ => BootMethod::Unknown
-        }
-    }
+    let boot_method = match magic_number {
+        0x36d76289 => BootMethod::Multiboot2,
+        0x2badb002 => BootMethod::Multiboot,
+        _ => BootMethod::Unknown
+    };
+
+    assert!(
+        BOOT_METHOD.set(boot_method).is_ok(),
+        "The boot method should only be set once."
+    );
 }
 
 /// Returns the method the system was booted with.
 fn get_boot_method() -> &'static BootMethod {
-    unsafe { &BOOT_METHOD }
+    BOOT_METHOD.get().unwrap_or(&BootMethod::Unknown)
 }
 
 /// Returns information about the VGA buffer.
 #[cfg(target_arch = "x86_64")]
 pub fn get_vga_info() -> vga_buffer::Info {
-    match *get_boot_method() {
-        BootMethod::Multiboot2 => multiboot2::get_vga_info(),
-        _ => freestanding::get_vga_info()
-    }
+    *VGA_INFO.expect("The VGA information is only available after booting.")
 }
 
 /// Returns the name of the boot loader.
 pub fn get_bootloader_name() -> &'static str {
-    match *get_boot_method() {
-        BootMethod::Multiboot2 => multiboot2::get_bootloader_name(),
-        BootMethod::Multiboot => multiboot::get_bootloader_name(),
-        _ => "no boot loader"
-    }
+    get_boot_information().bootloader_name
+}
+
+/// Returns the command line passed to the kernel.
+pub fn get_command_line() -> &'static str {
+    get_boot_information().command_line
+}
+
+/// Returns true if the given option is on the kernel command line.
+///
+/// Options are separated by whitespace.
+pub fn has_option(option: &str) -> bool {
+    get_command_line()
+        .split_whitespace()
+        .any(|word| word == option)
+}
+
+/// Returns the value of the last `key=value` option with the given key on the
+/// kernel command line.
+pub fn get_option_value(key: &str) -> Option<&'static str> {
+    get_command_line()
+        .split_whitespace()
+        .filter_map(|word| {
+            let mut parts = word.splitn(2, '=');
+
+            match (parts.next(), parts.next()) {
+                (Some(word_key), Some(value)) if word_key == key => Some(value),
+                _ => None
+            }
+        })
+        .last()
+}
+
+/// Returns the environment variables for the init process.
+///
+/// These are all `key=value` words on the kernel command line.
+pub fn get_init_environment() -> Vec<&'static str> {
+    get_command_line()
+        .split_whitespace()
+        .filter(|word| word.find('=').map_or(false, |index| index > 0))
+        .collect()
+}
+
+/// Returns the modules loaded by the boot loader.
+pub fn get_modules() -> &'static [Module] {
+    get_boot_information().modules
+}
+
+/// Returns the information passed by the boot loader.
+fn get_boot_information() -> &'static BootInformation {
+    BOOT_INFORMATION.expect("The boot information is only available after booting.")
 }
 
 /// Returns the memory area of the initramfs.
 pub fn get_initramfs_area() -> MemoryArea<PhysicalAddress> {
-    match *get_boot_method() {
-        BootMethod::Multiboot => multiboot::get_initramfs_area(),
-        _ => unimplemented!()
-    }
+    get_modules()
+        .iter()
+        .find(|module| module.name == "initramfs")
+        .expect("No initramfs found.")
+        .area
 }
 
 /// Returns an iterator for the map of usable memory.
+///
+/// Parts of the usable memory that overlap with reserved regions are left out.
 pub fn get_memory_map() -> MemoryMapIterator {
     MemoryMapIterator::new()
 }
@@ -1,10 +1,14 @@
 //! Handles the multiboot information structure.
 
+use super::regions::{Region, RegionType};
 use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use memory::physical::{PhysBox, PhysSlice};
 use memory::{Address, MemoryArea, PhysicalAddress, VirtualAddress};
 
 /// Represents the multiboot information structure.
 #[repr(C)]
+#[derive(Clone, Copy)]
 struct MultibootInformation {
     // DOCME
     flags: u32,
@@ -65,6 +69,7 @@ bitflags! {
 
 /// Represents an entry in the given memory map.
 #[repr(C, packed)]
+#[derive(Clone, Copy)]
 struct MmapEntry {
     /// The size of the entry.
     size: u32,
@@ -73,13 +78,12 @@ struct MmapEntry {
     /// The length of the memory area.
     length: usize,
     /// The type of memory contained in the area.
-    ///
-    /// 1 means usable memory.
     mem_type: u32
 }
 
 /// Represents a module loaded by the boot loader.
 #[repr(C, packed)]
+#[derive(Clone, Copy)]
 struct ModuleEntry {
     /// The start address of the module.
     mod_start: u32,
@@ -91,50 +95,71 @@ struct ModuleEntry {
     reserved: u32
 }
 
-/// The base address for the information strucuture.
-// This is only valid after init was called.
-static mut STRUCT_BASE_ADDRESS: *const MultibootInformation = 0 as *const MultibootInformation;
+/// The physical address of the information strucuture.
+// This is only valid between the calls to init and release.
+static STRUCT_BASE_ADDRESS: AtomicUsize = ATOMIC_USIZE_INIT;
 
 /// Initializes the multiboot module.
 pub fn init(information_structure_address: usize) {
     assert_has_not_been_called!("The multiboot module should only be initialized once.");
 
-    unsafe {
-        STRUCT_BASE_ADDRESS =
-            to_virtual!(information_structure_address) as *const MultibootInformation
-    };
+    STRUCT_BASE_ADDRESS.store(information_structure_address, Ordering::Release);
 
     assert!(!get_flags().contains(A_OUT | ELF));
 }
 
-/// Returns the memory area of the initramfs.
-pub fn get_initramfs_area() -> MemoryArea<PhysicalAddress> {
-    let module_entry = get_initramfs_module_entry();
-
-    MemoryArea::from_start_and_end(
-        PhysicalAddress::from_usize(module_entry.mod_start as usize),
-        PhysicalAddress::from_usize(module_entry.mod_end as usize)
-    )
+/// Drops the reference to the information structure.
+///
+/// The information can't be accessed afterwards, so that the memory containing
+/// it can be reused.
+pub fn release() {
+    STRUCT_BASE_ADDRESS.store(0, Ordering::Release);
 }
 
-/// Returns the module entry for the initramfs.
-fn get_initramfs_module_entry() -> &'static ModuleEntry {
+/// Returns the entries of all the modules loaded by the boot loader.
+fn get_module_entries() -> Option<PhysSlice<ModuleEntry>> {
     let info = get_info();
-    let mod_count = info.mods_count as usize;
-    let mod_addr = to_virtual!(info.mods_addr) as usize;
 
-    for i in 0..mod_count {
-        let mod_entry =
-            unsafe { &*((mod_addr + i * size_of::<ModuleEntry>()) as *const ModuleEntry) };
-        let mod_string = from_c_str!(VirtualAddress::from_usize(to_virtual!(
+    if get_flags().contains(MODULES) {
+        let address = PhysicalAddress::from_usize(info.mods_addr as usize);
+        let entries = unsafe { PhysSlice::early(address, info.mods_count as usize) };
+
+        Some(entries.expect("The module entries lie outside of the boot mapping."))
+    } else {
+        None
+    }
+}
+
+/// Calls `f` with the memory area and the name of every module loaded by the
+/// boot loader.
+pub fn for_each_module<F: FnMut(MemoryArea<PhysicalAddress>, &str)>(mut f: F) {
+    let entries = match get_module_entries() {
+        Some(entries) => entries,
+        None => return
+    };
+
+    for mod_entry in entries.as_slice() {
+        let name = from_c_str!(VirtualAddress::from_usize(to_virtual!(
             mod_entry.string as usize
         ))).unwrap();
-        if mod_string == "initramfs" {
-            return mod_entry;
-        }
+
+        f(
+            MemoryArea::from_start_and_end(
+                PhysicalAddress::from_usize(mod_entry.mod_start as usize),
+                PhysicalAddress::from_usize(mod_entry.mod_end as usize)
+            ),
+            name
+        );
     }
+}
 
-    panic!("No initramfs found.");
+/// Returns the command line passed to the kernel.
+pub fn get_command_line() -> &'static str {
+    if get_flags().contains(CMDLINE) {
+        from_c_str!(VirtualAddress::from_usize(to_virtual!(get_info().cmdline))).unwrap()
+    } else {
+        ""
+    }
 }
 
 /// Returns the name of the boot loader.
@@ -155,15 +180,22 @@ fn get_flags() -> MultibootFlags {
 }
 
 /// Returns the multiboot structure.
-fn get_info() -> &'static MultibootInformation {
-    unsafe { &*STRUCT_BASE_ADDRESS }
+fn get_info() -> MultibootInformation {
+    let address = STRUCT_BASE_ADDRESS.load(Ordering::Acquire);
+
+    assert!(address != 0, "The multiboot information was already released.");
+
+    unsafe { PhysBox::early(PhysicalAddress::from_usize(address)) }
+        .expect("The multiboot information lies outside of the boot mapping.")
+        .read()
 }
 
 /// Provides an iterator for the memory map.
+#[derive(Clone)]
 pub struct MemoryMapIterator {
-    /// The address of the current entry in the memory map.
+    /// The physical address of the current entry in the memory map.
     address: usize,
-    /// The address after the last entry in the memory map.
+    /// The physical address after the last entry in the memory map.
     max_address: usize
 }
 
@@ -172,8 +204,8 @@ impl MemoryMapIterator {
     fn new() -> MemoryMapIterator {
         if get_flags().contains(MMAP) {
             MemoryMapIterator {
-                address: to_virtual!(get_info().mmap_addr),
-                max_address: to_virtual!(get_info().mmap_addr + get_info().mmap_length)
+                address: get_info().mmap_addr as usize,
+                max_address: (get_info().mmap_addr + get_info().mmap_length) as usize
             }
         } else {
             MemoryMapIterator {
@@ -185,23 +217,24 @@ impl MemoryMapIterator {
 }
 
 impl Iterator for MemoryMapIterator {
-    type Item = MemoryArea<PhysicalAddress>;
+    type Item = Region;
 
-    fn next(&mut self) -> Option<MemoryArea<PhysicalAddress>> {
-        while self.address < self.max_address {
-            let current_entry = unsafe { &*(self.address as *const MmapEntry) };
+    fn next(&mut self) -> Option<Region> {
+        if self.address < self.max_address {
+            let address = PhysicalAddress::from_usize(self.address);
+            let current_entry = unsafe { PhysBox::<MmapEntry>::early(address) }
+                .expect("The memory map lies outside of the boot mapping.")
+                .read();
 
             self.address += size_of::<u32>() + current_entry.size as usize;
 
-            if current_entry.mem_type == 1 {
-                // only a type of 1 is usable memory
-                return Some(MemoryArea::new(
-                    current_entry.base_addr,
-                    current_entry.length
-                ));
-            }
+            Some(Region {
+                area: MemoryArea::new(current_entry.base_addr, current_entry.length),
+                region_type: RegionType::from_number(current_entry.mem_type)
+            })
+        } else {
+            None
         }
-        None
     }
 }
 
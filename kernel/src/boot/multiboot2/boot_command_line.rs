@@ -1,9 +1,28 @@
-//!Handles the boot command line multiboot2 tag.
+//! Handles the boot command line tag.
 
-///Represents the boot command line tag.
+use super::get_tag;
+use memory::{Address, VirtualAddress};
+
+/// Represents the boot command line tag.
+///
+/// The null terminated command line follows the tag.
 #[repr(C)]
-struct BootCommandLine { //type = 1
+struct BootCommandLine {
+    // type = 1
     tag_type: u32,
-    size: u32,
-    string: [u8]
+    size: u32
+}
+
+/// Returns the command line passed to the kernel.
+pub fn get_command_line() -> &'static str {
+    match get_tag(1) {
+        Some(tag_address) => {
+            let tag = unsafe { &*(tag_address as *const BootCommandLine) };
+            let string_address = VirtualAddress::from_usize(to_virtual!(tag_address as usize + 8));
+
+            from_c_str!(string_address, tag.size as usize - 9)
+                .expect("Command line illegally formatted")
+        },
+        None => ""
+    }
 }
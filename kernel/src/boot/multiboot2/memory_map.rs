@@ -1,53 +1,93 @@
-//!Handles the memory map multiboot2 tag.
+//! Handles the memory map tag.
+
 use super::get_tag;
+use boot::regions::{Region, RegionType};
+use core::mem::size_of;
+use memory::{Address, MemoryArea, PhysicalAddress};
 
-///Represents the memory map tag.
+/// Represents the memory map tag.
 #[repr(C)]
-struct MemoryMap { //type = 6
+struct MemoryMap {
+    // type = 6
     tag_type: u32,
     size: u32,
+    /// The size of every entry, which may grow in later versions.
     entry_size: u32,
-    entry_version: u32,
-    entries: usize
+    entry_version: u32
 }
 
-///Represents an entry of version 0 in the memory map.
+/// Represents an entry in the memory map.
 #[repr(C)]
-struct MemoryMapEntryVersion0 {
+struct MemoryMapEntry {
+    /// The start address of the memory area.
     base_addr: u64,
+    /// The length of the memory area.
     length: u64,
+    /// The type of memory contained in the area.
     memory_type: u32,
     reserved: u32
 }
 
-///Represents an iterator for the memory map tags.
-struct MemoryMapEntryVersion0Iterator {
-    memory_map: &'static MemoryMap,
-    current_address: usize
+/// Provides an iterator for the memory map.
+#[derive(Clone)]
+pub struct MemoryMapIterator {
+    /// The address of the current entry.
+    address: usize,
+    /// The address after the last entry.
+    end_address: usize,
+    /// The size of every entry.
+    entry_size: usize
 }
 
-impl MemoryMapEntryVersion0Iterator {
-    ///Creates a new iterator for the memory map tags.
-    fn new(address: usize) -> MemoryMapEntryVersion0Iterator {
-        let memory_map = unsafe { &*(address as *const MemoryMap) };
-        MemoryMapEntryVersion0Iterator {
-            memory_map: memory_map,
-            current_address: address + 16
+impl MemoryMapIterator {
+    /// Creates a new iterator through the memory map.
+    ///
+    /// The iterator is empty if there is no memory map.
+    fn new() -> MemoryMapIterator {
+        match get_tag(6) {
+            Some(tag_address) => {
+                let tag = unsafe { &*(tag_address as *const MemoryMap) };
+
+                MemoryMapIterator {
+                    address: tag_address as usize + size_of::<MemoryMap>(),
+                    end_address: tag_address as usize + tag.size as usize,
+                    entry_size: tag.entry_size as usize
+                }
+            },
+            None => MemoryMapIterator {
+                address: 0,
+                end_address: 0,
+                entry_size: 0
+            }
         }
     }
 }
 
-impl Iterator for MemoryMapEntryVersion0Iterator {
-    type Item = &'static MemoryMapEntryVersion0;
+impl Iterator for MemoryMapIterator {
+    type Item = Region;
 
-    fn next(&mut self) -> Option<&'static MemoryMapEntryVersion0> {
-        if self.current_address < (self.memory_map as *const MemoryMap as usize) + self.memory_map.size as usize {
-            let entry = unsafe { &*(self.current_address as *const MemoryMapEntryVersion0) };
-            self.current_address += self.memory_map.entry_size as usize;
-            Some(entry)
-        }
-        else {
-            None
+    fn next(&mut self) -> Option<Region> {
+        // Entries smaller than the known fields would be misread.
+        if self.entry_size < size_of::<MemoryMapEntry>()
+            || self.address + self.entry_size > self.end_address
+        {
+            return None;
         }
+
+        let entry = unsafe { &*(self.address as *const MemoryMapEntry) };
+        self.address += self.entry_size;
+
+        Some(Region {
+            area: MemoryArea::new(
+                PhysicalAddress::from_usize(entry.base_addr as usize),
+                entry.length as usize
+            ),
+            region_type: RegionType::from_number(entry.memory_type)
+        })
     }
 }
+
+/// Returns the memory map given by the boot loader.
+pub fn get_memory_map() -> MemoryMapIterator {
+    MemoryMapIterator::new()
+}
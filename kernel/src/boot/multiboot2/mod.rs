@@ -1,9 +1,17 @@
 //! Handles the multiboot2 information structure.
+mod boot_command_line;
 mod boot_loader_name;
 mod framebuffer_info;
+mod memory_map;
+mod module;
 
+pub use self::boot_command_line::get_command_line;
 pub use self::boot_loader_name::get_bootloader_name;
 pub use self::framebuffer_info::get_vga_info;
+pub use self::memory_map::get_memory_map;
+pub use self::module::for_each_module;
+
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 
 /// Represents a tag in the information structure.
 #[repr(C)]
@@ -20,10 +28,8 @@ struct BasicTagIterator {
 impl BasicTagIterator {
     /// Returns a new iterator for the tags.
     fn new() -> BasicTagIterator {
-        unsafe {
-            BasicTagIterator {
-                current_address: STRUCT_BASE_ADDRESS + 8
-            }
+        BasicTagIterator {
+            current_address: STRUCT_BASE_ADDRESS.load(Ordering::Acquire) + 8
         }
     }
 }
@@ -31,7 +37,7 @@ impl BasicTagIterator {
 /// The base address for the information structure.
 // this will only be valid after init was called and will never be changed
 // afterwards
-static mut STRUCT_BASE_ADDRESS: usize = 0;
+static STRUCT_BASE_ADDRESS: AtomicUsize = ATOMIC_USIZE_INIT;
 
 impl Iterator for BasicTagIterator {
     type Item = *const BasicTag;
@@ -58,10 +64,15 @@ pub fn init(information_structure_address: usize) {
     assert_has_not_been_called!("The multiboot2 module should only be initialized once.");
 
     assert!(check_validity(information_structure_address));
-    unsafe { STRUCT_BASE_ADDRESS = information_structure_address };
+    STRUCT_BASE_ADDRESS.store(information_structure_address, Ordering::Release);
 }
 
 /// Checks if the passed information structure is valid.
+/// Drops the reference to the information structure.
+pub fn release() {
+    STRUCT_BASE_ADDRESS.store(0, Ordering::Release);
+}
+
 fn check_validity(information_structure_address: usize) -> bool {
     let total_size: u32 = unsafe { *(information_structure_address as *const u32) };
     let end_tag_type: u32 =
@@ -1,12 +1,37 @@
-//!Handles the module multiboot2 tag.
+//! Handles the module tags.
 
-///Represents the module tag.
+use super::BasicTagIterator;
+use memory::{Address, MemoryArea, PhysicalAddress, VirtualAddress};
+
+/// Represents a module tag.
+///
+/// The null terminated name of the module follows the tag.
 #[repr(C)]
-struct Module { //type = 3
+struct Module {
+    // type = 3
     tag_type: u32,
     size: u32,
-    mod_start: usize, //verify this is really 64 bit
-    mod_end: usize,
-    string: [u8]
+    /// The start address of the module.
+    mod_start: u32,
+    /// The end address of the module.
+    mod_end: u32
 }
 
+/// Calls `f` with the memory area and the name of every module loaded by the
+/// boot loader.
+pub fn for_each_module<F: FnMut(MemoryArea<PhysicalAddress>, &str)>(mut f: F) {
+    for tag_address in BasicTagIterator::new().filter(|&tag| unsafe { (*tag).tag_type == 3 }) {
+        let tag = unsafe { &*(tag_address as *const Module) };
+        let string_address = VirtualAddress::from_usize(to_virtual!(tag_address as usize + 16));
+        let name = from_c_str!(string_address, tag.size as usize - 17)
+            .expect("Module name illegally formatted");
+
+        f(
+            MemoryArea::from_start_and_end(
+                PhysicalAddress::from_usize(tag.mod_start as usize),
+                PhysicalAddress::from_usize(tag.mod_end as usize)
+            ),
+            name
+        );
+    }
+}
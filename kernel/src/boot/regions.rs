@@ -0,0 +1,244 @@
+//! Keeps a table of all the regions in the memory map of the boot loader.
+//!
+//! The table is filled once during boot, before the heap exists, so it is
+//! stored on the early heap. It records reserved regions as well, so that
+//! drivers can check whether a physical range is RAM and so that no frames are
+//! handed out from reserved regions, even if the memory map marks them as
+//! available, too.
+
+use core::cmp::{max, min};
+use core::fmt;
+use memory::early_heap;
+use memory::{Address, MemoryArea, PhysicalAddress, PAGE_SIZE};
+use sync::OnceCell;
+
+/// The type of a region in the memory map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionType {
+    /// The region is usable RAM.
+    Available,
+    /// The region contains ACPI tables and can be used once they were read.
+    AcpiReclaimable,
+    /// The region must be preserved across sleep states for ACPI.
+    AcpiNvs,
+    /// The region contains defective RAM.
+    Defective,
+    /// The region is reserved, for example for memory mapped devices.
+    Reserved
+}
+
+impl RegionType {
+    /// Returns the region type for the given type number of a memory map
+    /// entry.
+    ///
+    /// Unknown types are treated as reserved.
+    pub fn from_number(number: u32) -> RegionType {
+        match number {
+            1 => RegionType::Available,
+            3 => RegionType::AcpiReclaimable,
+            4 => RegionType::AcpiNvs,
+            5 => RegionType::Defective,
+            _ => RegionType::Reserved
+        }
+    }
+}
+
+impl fmt::Display for RegionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            RegionType::Available => "available",
+            RegionType::AcpiReclaimable => "ACPI reclaimable",
+            RegionType::AcpiNvs => "ACPI NVS",
+            RegionType::Defective => "defective",
+            RegionType::Reserved => "reserved"
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// A region in the memory map.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    /// The physical memory covered by the region.
+    pub area: MemoryArea<PhysicalAddress>,
+    /// The type of the region.
+    pub region_type: RegionType
+}
+
+/// The value the region table is filled with before it is initialized.
+const EMPTY_REGION: Region = Region {
+    area: MemoryArea::new(PhysicalAddress::from_const(0), 0),
+    region_type: RegionType::Reserved
+};
+
+/// The regions of the memory map, sorted by their start address.
+static REGIONS: OnceCell<&'static [Region]> = OnceCell::new();
+
+/// Fills the region table with the regions of the given memory map.
+pub fn init<I: Iterator<Item = Region> + Clone>(memory_map: I) {
+    assert_has_not_been_called!("The region table should only be initialized once.");
+
+    let is_not_empty = |region: &Region| region.area.length() > 0;
+
+    let count = memory_map.clone().filter(&is_not_empty).count();
+    let regions = early_heap::allocate_slice(count, EMPTY_REGION);
+
+    for (slot, region) in regions.iter_mut().zip(memory_map.filter(&is_not_empty)) {
+        *slot = region;
+    }
+
+    regions.sort_unstable_by_key(|region| region.area.start_address());
+
+    let regions: &'static [Region] = regions;
+
+    assert!(
+        REGIONS.set(regions).is_ok(),
+        "The region table should only be initialized once."
+    );
+
+    info!("Memory map:");
+    for region in get_regions() {
+        info!(
+            "  {:?} - {:?}: {}",
+            region.area.start_address(),
+            region.area.end_address(),
+            region.region_type
+        );
+    }
+}
+
+/// Returns all the regions of the memory map, sorted by their start address.
+pub fn get_regions() -> &'static [Region] {
+    REGIONS.get().map_or(&[], |regions| *regions)
+}
+
+/// Returns true if the area overlaps with a region that is not available.
+pub fn is_reserved(area: MemoryArea<PhysicalAddress>) -> bool {
+    get_regions().iter().any(|region| {
+        region.region_type != RegionType::Available && region.area.overlaps_with(area)
+    })
+}
+
+/// Returns true if the whole area is RAM that is available to the kernel.
+pub fn is_ram(area: MemoryArea<PhysicalAddress>) -> bool {
+    !is_reserved(area)
+        && get_regions().iter().any(|region| {
+            region.region_type == RegionType::Available && area.is_contained_in(region.area)
+        })
+}
+
+/// Returns true if any part of the area may be handed out as free memory.
+pub fn overlaps_available(area: MemoryArea<PhysicalAddress>) -> bool {
+    AvailableRegionIterator::new().any(|available| available.overlaps_with(area))
+}
+
+/// Returns the type of the region containing the given address.
+///
+/// If multiple regions contain the address, the most restrictive type is
+/// returned.
+pub fn get_region_type(address: PhysicalAddress) -> Option<RegionType> {
+    let mut region_type = None;
+
+    for region in get_regions().iter().filter(|region| region.area.contains(address)) {
+        if region_type.is_none() || region.region_type != RegionType::Available {
+            region_type = Some(region.region_type);
+        }
+    }
+
+    region_type
+}
+
+/// Iterates over the page aligned parts of the available regions that don't
+/// overlap with any other region.
+pub struct AvailableRegionIterator {
+    /// The index of the current region in the region table.
+    index: usize,
+    /// The address at which the rest of the current region starts.
+    cursor: Option<PhysicalAddress>
+}
+
+impl AvailableRegionIterator {
+    /// Creates a new iterator over the available regions.
+    pub fn new() -> AvailableRegionIterator {
+        AvailableRegionIterator {
+            index: 0,
+            cursor: None
+        }
+    }
+
+    /// Moves on to the next region in the table.
+    fn advance(&mut self) {
+        self.index += 1;
+        self.cursor = None;
+    }
+}
+
+impl Iterator for AvailableRegionIterator {
+    type Item = MemoryArea<PhysicalAddress>;
+
+    fn next(&mut self) -> Option<MemoryArea<PhysicalAddress>> {
+        let regions = get_regions();
+
+        while self.index < regions.len() {
+            let region = regions[self.index];
+
+            if region.region_type != RegionType::Available {
+                self.advance();
+                continue;
+            }
+
+            let end = region.area.end_address().page_align_down();
+            let mut start = max(
+                self.cursor.unwrap_or(region.area.start_address()),
+                region.area.start_address()
+            );
+            start = start.align_up(PAGE_SIZE).unwrap_or(end);
+
+            let not_available = |region: &&Region| region.region_type != RegionType::Available;
+
+            // Skip the reserved regions overlapping the first frame.
+            loop {
+                let reserved_end = regions
+                    .iter()
+                    .filter(&not_available)
+                    .find(|reserved| {
+                        start < end
+                            && reserved.area.overlaps_with(MemoryArea::new(start, PAGE_SIZE))
+                    })
+                    .map(|reserved| reserved.area.end_address());
+
+                match reserved_end {
+                    Some(reserved_end) => start = reserved_end.align_up(PAGE_SIZE).unwrap_or(end),
+                    None => break
+                }
+            }
+
+            if start >= end {
+                self.advance();
+                continue;
+            }
+
+            // Stop at the next reserved region.
+            let piece_end = regions
+                .iter()
+                .filter(&not_available)
+                .filter(|reserved| {
+                    reserved.area.start_address() > start && reserved.area.start_address() < end
+                })
+                .fold(end, |piece_end, reserved| {
+                    min(piece_end, reserved.area.start_address().page_align_down())
+                });
+
+            if piece_end == end {
+                self.advance();
+            } else {
+                self.cursor = Some(piece_end);
+            }
+
+            return Some(MemoryArea::from_start_and_end(start, piece_end));
+        }
+
+        None
+    }
+}
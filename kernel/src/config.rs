@@ -0,0 +1,50 @@
+//! Exposes the cargo features the kernel was built with as constants.
+//!
+//! Code should test these constants instead of using `#[cfg(feature = ...)]`
+//! where possible. The code of disabled features is then still type checked,
+//! while the compiler removes it all the same. `cargo xtask` checks the
+//! dependencies between the features before building the kernel.
+
+/// Whether the call sites of live heap and page frame allocations are
+/// recorded.
+pub const ALLOC_TRACKING: bool = cfg!(feature = "alloc_tracking");
+
+/// Whether the kernel microbenchmarks run during boot.
+pub const BENCHMARK: bool = cfg!(feature = "benchmark");
+
+/// Whether the calls of the instrumented kernel functions are counted.
+pub const COVERAGE: bool = cfg!(feature = "coverage");
+
+/// Whether the screen is flushed periodically instead of after every print.
+pub const DEFERRED_SCREEN_FLUSH: bool = cfg!(feature = "deferred_screen_flush");
+
+/// Whether the acquisitions, contention and hold times of the named kernel
+/// locks are counted.
+pub const LOCK_STATS: bool = cfg!(feature = "lock_stats");
+
+/// Whether the invariants of the page tables are verified at run time.
+pub const PAGE_TABLE_CHECKS: bool = cfg!(feature = "page_table_checks");
+
+/// Whether regions in which preemption is disabled for too long are
+/// reported.
+pub const PREEMPTION_AUDIT: bool = cfg!(feature = "preemption_audit");
+
+/// The names of all features together with whether they are enabled.
+pub const FEATURES: &[(&str, bool)] = &[
+    ("alloc_tracking", ALLOC_TRACKING),
+    ("benchmark", BENCHMARK),
+    ("coverage", COVERAGE),
+    ("deferred_screen_flush", DEFERRED_SCREEN_FLUSH),
+    ("lock_stats", LOCK_STATS),
+    ("page_table_checks", PAGE_TABLE_CHECKS),
+    ("preemption_audit", PREEMPTION_AUDIT)
+];
+
+/// Logs the enabled features.
+pub fn log_features() {
+    for &(name, enabled) in FEATURES {
+        if enabled {
+            info!("The feature {} is enabled.", name);
+        }
+    }
+}
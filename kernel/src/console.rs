@@ -0,0 +1,537 @@
+//! This module makes the console available as a file.
+//!
+//! Reading from the console returns the characters typed on the keyboard.
+//! Every process has its own queue of input. The typed characters go to the
+//! queue of the process that read from the console last, the foreground
+//! process. Characters typed while there is no foreground process are kept
+//! for the next process that reads.
+//!
+//! The typed characters pass through a line discipline first. In canonical
+//! mode, which is the default, the kernel echoes them and collects them into
+//! a line that backspace edits, and the line is only passed on once it ends.
+//! In raw mode every character is passed on right away without an echo.
+//! When the foreground process closes its input, the console returns to
+//! canonical mode.
+//!
+//! Both the input and the output of the console accept the requests of
+//! `FileHandle::control` to query its size, to move the cursor and to switch
+//! the mode. They also control the lock keys and the key repeat of the
+//! keyboard.
+
+use alloc::btree_map::BTreeMap;
+use arch::{self, Architecture};
+use core::cmp::min;
+use core::mem::size_of;
+use core::ptr;
+use file_handle::{FileError, FileHandle, PollEvents, Result, SeekFrom, POLL_IN, POLL_OUT};
+use keyboard::{self, Locks, Queue, Repeat};
+use multitasking::wait_queue::WaitQueue;
+use multitasking::{ProcessID, CURRENT_THREAD};
+use sync::Mutex;
+
+/// The request that returns the number of rows and columns as a `Position`.
+const GET_SIZE: u32 = 0;
+
+/// The request that returns the position of the cursor as a `Position`.
+const GET_CURSOR: u32 = 1;
+
+/// The request that moves the cursor to the given `Position`.
+const SET_CURSOR: u32 = 2;
+
+/// The request that returns the mode as a `u32`.
+const GET_MODE: u32 = 3;
+
+/// The request that sets the mode to the given `u32`.
+const SET_MODE: u32 = 4;
+
+/// The request that returns the lock keys that are on as a `u32`.
+const GET_LOCKS: u32 = 5;
+
+/// The request that switches the lock keys in the given `u32` on and the
+/// others off.
+const SET_LOCKS: u32 = 6;
+
+/// The request that returns how held keys repeat as a `Repeat`.
+const GET_REPEAT: u32 = 7;
+
+/// The request that sets how held keys repeat to the given `Repeat`.
+const SET_REPEAT: u32 = 8;
+
+/// The maximum length of a line in canonical mode.
+const MAX_LINE_LENGTH: usize = 128;
+
+/// The character that removes the last character of the line.
+const BACKSPACE: u8 = 0x08;
+
+/// The character that some terminals send for backspace.
+const DELETE: u8 = 0x7f;
+
+lazy_static! {
+    /// The input typed on the keyboard.
+    static ref INPUT: Mutex<Input> = Mutex::new(Input {
+        foreground: None,
+        queues: BTreeMap::new(),
+        unclaimed: Queue::new(),
+        discipline: LineDiscipline::new()
+    });
+
+    /// Woken when characters are passed on to the queues of the readers.
+    static ref READERS: WaitQueue = WaitQueue::new();
+}
+
+/// A position on the console or its size as passed to userspace.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Position {
+    /// The row or the number of rows.
+    row: u32,
+    /// The column or the number of columns.
+    column: u32
+}
+
+/// How the typed characters are passed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Lines are edited and echoed by the kernel.
+    Canonical = 0,
+    /// Characters are passed on unchanged.
+    Raw = 1
+}
+
+impl Mode {
+    /// Converts the raw request argument to a mode.
+    fn from_raw(mode: u32) -> Option<Mode> {
+        match mode {
+            0 => Some(Mode::Canonical),
+            1 => Some(Mode::Raw),
+            _ => None
+        }
+    }
+}
+
+/// Turns the typed characters into input for the foreground process.
+struct LineDiscipline {
+    /// The current mode.
+    mode: Mode,
+    /// The line that is being edited in canonical mode.
+    line: [u8; MAX_LINE_LENGTH],
+    /// The length of the line.
+    length: usize
+}
+
+impl LineDiscipline {
+    /// Creates a line discipline in canonical mode.
+    const fn new() -> LineDiscipline {
+        LineDiscipline {
+            mode: Mode::Canonical,
+            line: [0; MAX_LINE_LENGTH],
+            length: 0
+        }
+    }
+
+    /// Handles a typed character.
+    ///
+    /// The characters that are ready to be read are passed to `pass_on`.
+    /// Returns the character to echo, if any.
+    fn receive<F: FnMut(u8)>(&mut self, character: u8, mut pass_on: F) -> Option<u8> {
+        if self.mode == Mode::Raw {
+            pass_on(character);
+            return None;
+        }
+
+        match character {
+            b'\n' => {
+                self.pass_on_line(&mut pass_on);
+                pass_on(b'\n');
+                Some(b'\n')
+            },
+            BACKSPACE | DELETE if self.length > 0 => {
+                self.length -= 1;
+                Some(BACKSPACE)
+            },
+            character
+                if character.is_ascii()
+                    && !character.is_ascii_control()
+                    && self.length < MAX_LINE_LENGTH =>
+            {
+                self.line[self.length] = character;
+                self.length += 1;
+                Some(character)
+            },
+            _ => None
+        }
+    }
+
+    /// Switches to the mode.
+    ///
+    /// The unfinished line is passed on when switching to raw mode.
+    fn set_mode<F: FnMut(u8)>(&mut self, mode: Mode, mut pass_on: F) {
+        if mode == Mode::Raw {
+            self.pass_on_line(&mut pass_on);
+        }
+
+        self.mode = mode;
+    }
+
+    /// Passes on the line and starts a new one.
+    fn pass_on_line<F: FnMut(u8)>(&mut self, pass_on: &mut F) {
+        for &character in self.line[..self.length].iter() {
+            pass_on(character);
+        }
+
+        self.length = 0;
+    }
+}
+
+/// The input of the console.
+struct Input {
+    /// The process that receives the typed characters.
+    foreground: Option<ProcessID>,
+    /// The characters that weren't read yet by each process.
+    queues: BTreeMap<ProcessID, Queue>,
+    /// The characters typed while there was no foreground process.
+    unclaimed: Queue,
+    /// Decides when typed characters are passed on.
+    discipline: LineDiscipline
+}
+
+impl Input {
+    /// Makes the process the foreground process and returns its queue.
+    fn claim(&mut self, pid: ProcessID) -> &mut Queue {
+        self.foreground = Some(pid);
+
+        if !self.queues.contains_key(&pid) {
+            self.queues.insert(pid, Queue::new());
+        }
+
+        let queue = self.queues.get_mut(&pid).unwrap();
+        while let Some(character) = self.unclaimed.pop() {
+            queue.push(character);
+        }
+
+        queue
+    }
+
+    /// Passes the typed character through the line discipline.
+    ///
+    /// Returns the character to echo, if any.
+    fn receive(&mut self, character: u8) -> Option<u8> {
+        let mut ready = Queue::new();
+        let echo = self.discipline.receive(character, |character| {
+            ready.push(character);
+        });
+
+        self.pass_on(ready);
+
+        echo
+    }
+
+    /// Switches the line discipline to the mode.
+    fn set_mode(&mut self, mode: Mode) {
+        let mut ready = Queue::new();
+        self.discipline.set_mode(mode, |character| {
+            ready.push(character);
+        });
+
+        self.pass_on(ready);
+    }
+
+    /// Adds the characters to the queue of the foreground process.
+    fn pass_on(&mut self, mut characters: Queue) {
+        let queue = match self.foreground {
+            Some(pid) => self.queues.get_mut(&pid),
+            None => None
+        };
+
+        let queue = match queue {
+            Some(queue) => queue,
+            None => &mut self.unclaimed
+        };
+
+        while let Some(character) = characters.pop() {
+            queue.push(character);
+        }
+    }
+}
+
+/// A handle to the console.
+pub struct Console;
+
+impl FileHandle for Console {
+    fn seek(&mut self, _position: SeekFrom) -> Result<u64> {
+        Err(FileError::NotSupported)
+    }
+
+    fn read(&mut self, _buffer: &mut [u8]) -> Result<()> {
+        Err(FileError::NotSupported)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        for &byte in buffer {
+            print!("{}", byte as char);
+        }
+        Ok(())
+    }
+
+    fn len(&mut self) -> u64 {
+        0
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        POLL_OUT
+    }
+
+    fn control(&mut self, request: u32, argument: &mut [u8]) -> Result<()> {
+        control(request, argument)
+    }
+}
+
+/// A handle to the input of the console.
+pub struct ConsoleInput {
+    /// The process that read through this handle.
+    reader: Option<ProcessID>
+}
+
+impl ConsoleInput {
+    /// Creates a new handle to the input of the console.
+    pub fn new() -> ConsoleInput {
+        ConsoleInput { reader: None }
+    }
+}
+
+impl Drop for ConsoleInput {
+    fn drop(&mut self) {
+        if let Some(pid) = self.reader {
+            let mut input = INPUT.lock();
+
+            input.queues.remove(&pid);
+
+            if input.foreground == Some(pid) {
+                input.set_mode(Mode::Canonical);
+            }
+        }
+    }
+}
+
+impl FileHandle for ConsoleInput {
+    fn seek(&mut self, _position: SeekFrom) -> Result<u64> {
+        Err(FileError::NotSupported)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let pid = CURRENT_THREAD.lock().pid;
+        self.reader = Some(pid);
+
+        let mut input = INPUT.lock();
+        let queue = input.claim(pid);
+
+        if queue.len() < buffer.len() {
+            return Err(FileError::WouldBlock);
+        }
+
+        for byte in buffer.iter_mut() {
+            *byte = queue.pop().unwrap();
+        }
+
+        Ok(())
+    }
+
+    fn read_available(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let pid = CURRENT_THREAD.lock().pid;
+        self.reader = Some(pid);
+
+        let mut input = INPUT.lock();
+        let queue = input.claim(pid);
+
+        if queue.len() == 0 && buffer.len() > 0 {
+            return Err(FileError::WouldBlock);
+        }
+
+        let length = min(queue.len(), buffer.len());
+        for byte in buffer[..length].iter_mut() {
+            *byte = queue.pop().unwrap();
+        }
+
+        Ok(length)
+    }
+
+    fn len(&mut self) -> u64 {
+        0
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        let has_input = match self.reader {
+            Some(pid) => INPUT
+                .lock()
+                .queues
+                .get(&pid)
+                .map_or(false, |queue| queue.len() > 0),
+            None => false
+        };
+
+        if has_input {
+            POLL_IN
+        } else {
+            PollEvents::empty()
+        }
+    }
+
+    fn wait_queue(&mut self) -> Option<&WaitQueue> {
+        Some(&READERS)
+    }
+
+    fn control(&mut self, request: u32, argument: &mut [u8]) -> Result<()> {
+        control(request, argument)
+    }
+}
+
+/// Adds a character typed on the keyboard.
+///
+/// Characters that arrive while the queue of the foreground process is full
+/// are dropped.
+pub fn add_input(character: u8) {
+    let echo = INPUT.lock().receive(character);
+    READERS.wake_all();
+
+    // The echo is printed once the input is unlocked again.
+    if let Some(character) = echo {
+        print!("{}", character as char);
+    }
+}
+
+/// Performs a request on the console.
+fn control(request: u32, argument: &mut [u8]) -> Result<()> {
+    match request {
+        GET_SIZE => {
+            let (rows, columns) = arch::Current::get_console_size();
+
+            let size = Position {
+                row: rows as u32,
+                column: columns as u32
+            };
+
+            write_argument(argument, size)
+        },
+        GET_CURSOR => {
+            let (row, column) = arch::Current::get_console_cursor();
+
+            let cursor = Position {
+                row: row as u32,
+                column: column as u32
+            };
+
+            write_argument(argument, cursor)
+        },
+        SET_CURSOR => {
+            let position: Position = read_argument(argument)?;
+
+            if arch::Current::set_console_cursor(position.row as usize, position.column as usize) {
+                Ok(())
+            } else {
+                Err(FileError::InvalidArgument)
+            }
+        },
+        GET_MODE => write_argument(argument, INPUT.lock().discipline.mode as u32),
+        SET_MODE => {
+            let mode: u32 = read_argument(argument)?;
+            let mode = Mode::from_raw(mode).ok_or(FileError::InvalidArgument)?;
+
+            INPUT.lock().set_mode(mode);
+            READERS.wake_all();
+
+            Ok(())
+        },
+        GET_LOCKS => write_argument(argument, keyboard::locks().bits()),
+        SET_LOCKS => {
+            let locks: u32 = read_argument(argument)?;
+            let locks = Locks::from_bits(locks).ok_or(FileError::InvalidArgument)?;
+
+            keyboard::set_locks(locks);
+
+            Ok(())
+        },
+        GET_REPEAT => write_argument(argument, keyboard::repeat()),
+        SET_REPEAT => {
+            let repeat: Repeat = read_argument(argument)?;
+
+            if keyboard::set_repeat(repeat) {
+                Ok(())
+            } else {
+                Err(FileError::InvalidArgument)
+            }
+        },
+        _ => Err(FileError::InvalidArgument)
+    }
+}
+
+/// Reads the argument of a request, which must have the size of `T`.
+fn read_argument<T: Copy>(argument: &[u8]) -> Result<T> {
+    if argument.len() != size_of::<T>() {
+        return Err(FileError::InvalidArgument);
+    }
+
+    Ok(unsafe { ptr::read_unaligned(argument.as_ptr() as *const T) })
+}
+
+/// Writes the result of a request to the argument, which must have the size
+/// of `T`.
+fn write_argument<T: Copy>(argument: &mut [u8], value: T) -> Result<()> {
+    if argument.len() != size_of::<T>() {
+        return Err(FileError::InvalidArgument);
+    }
+
+    unsafe { ptr::write_unaligned(argument.as_mut_ptr() as *mut T, value) };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::Vec;
+
+    /// Feeds the characters to the line discipline and returns what was
+    /// passed on and what was echoed.
+    fn feed(discipline: &mut LineDiscipline, characters: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut passed_on = Vec::new();
+        let mut echoed = Vec::new();
+
+        for &character in characters {
+            let echo = discipline.receive(character, |character| passed_on.push(character));
+
+            echoed.extend(echo);
+        }
+
+        (passed_on, echoed)
+    }
+
+    /// Tests that canonical mode passes on edited lines.
+    #[test]
+    fn test_canonical_mode() {
+        let mut discipline = LineDiscipline::new();
+
+        let (passed_on, echoed) = feed(&mut discipline, b"lx\x08s");
+        assert!(passed_on.is_empty());
+        assert_eq!(echoed, b"lx\x08s");
+
+        let (passed_on, echoed) = feed(&mut discipline, b"\x1b\n\x08");
+        assert_eq!(passed_on, b"ls\n");
+        assert_eq!(echoed, b"\n");
+    }
+
+    /// Tests that raw mode passes on every character and that switching to it
+    /// passes on the unfinished line.
+    #[test]
+    fn test_raw_mode() {
+        let mut discipline = LineDiscipline::new();
+        let mut passed_on = Vec::new();
+
+        feed(&mut discipline, b"ab");
+        discipline.set_mode(Mode::Raw, |character| passed_on.push(character));
+        assert_eq!(passed_on, b"ab");
+
+        let (passed_on, echoed) = feed(&mut discipline, b"\x1b[A\x08");
+        assert_eq!(passed_on, b"\x1b[A\x08");
+        assert!(echoed.is_empty());
+    }
+}
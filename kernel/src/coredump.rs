@@ -0,0 +1,473 @@
+//! Writes core dumps of processes that crash.
+//!
+//! When a process is killed because of a fault, an ELF core file is written
+//! with the user accessible segments of its address space and the registers
+//! of the faulting thread. The registers are stored in an `NT_PRSTATUS` note
+//! in the layout used by x86_64 Linux, so that common debuggers can open the
+//! dump together with the executable.
+//!
+//! Dumps are only written for processes whose core size limit isn't zero,
+//! which it is by default. Segments that don't fit within the limit are left
+//! out. There is no writable filesystem, so the dumps are kept in memory and
+//! read from `/core/<pid>`. Only the latest few dumps are kept.
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use alloc::Vec;
+use core::cmp::{max, min};
+use file_handle::{offset_by, FileError, FileHandle, PollEvents, Result, SeekFrom, POLL_IN};
+use memory::{Address, MemoryArea, PageFlags, VirtualAddress, EXECUTABLE, PAGE_SIZE, READABLE,
+             WRITABLE};
+use multitasking::limits::Resource;
+use multitasking::trace::Registers;
+use multitasking::{get_current_process, ProcessID, CURRENT_THREAD};
+use sync::Mutex;
+use vfs::FileSystem;
+
+/// The directory that contains the dumps.
+pub const CORE_DIRECTORY: &str = "/core/";
+
+/// The number of dumps that are kept.
+const MAX_DUMPS: usize = 4;
+
+/// The size of the ELF header.
+const HEADER_SIZE: usize = 64;
+
+/// The size of a program header.
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// The size of a section header.
+const SECTION_HEADER_SIZE: u16 = 64;
+
+/// The type of core files.
+const TYPE_CORE: u16 = 4;
+
+/// The machine value of x86_64.
+const MACHINE_X86_64: u16 = 0x3e;
+
+/// The type of loadable segments.
+const SEGMENT_TYPE_LOAD: u32 = 1;
+
+/// The type of note segments.
+const SEGMENT_TYPE_NOTE: u32 = 4;
+
+/// The type of the note that contains the registers.
+const NOTE_TYPE_PRSTATUS: u32 = 1;
+
+/// The name of the notes, padded to four bytes.
+const NOTE_NAME: &[u8] = b"CORE\0\0\0\0";
+
+/// The length of the note name, including the terminating zero.
+const NOTE_NAME_LENGTH: u32 = 5;
+
+/// The size of the process status in the note.
+const PRSTATUS_SIZE: usize = 336;
+
+/// The offset of the process ID in the process status.
+const PRSTATUS_PID_OFFSET: usize = 32;
+
+/// The offset of the registers in the process status.
+const PRSTATUS_REGISTERS_OFFSET: usize = 112;
+
+/// The index of the frame pointer within the registers.
+const REGISTER_FRAME_POINTER: usize = 4;
+
+/// The index of the instruction pointer within the registers.
+const REGISTER_INSTRUCTION_POINTER: usize = 16;
+
+/// The index of the flags within the registers.
+const REGISTER_FLAGS: usize = 18;
+
+/// The index of the stack pointer within the registers.
+const REGISTER_STACK_POINTER: usize = 19;
+
+/// The signal that faults are reported as.
+const SIGNAL_SEGMENTATION_FAULT: u16 = 11;
+
+/// The size of the note segment.
+const NOTE_SIZE: usize = 12 + 8 + PRSTATUS_SIZE;
+
+lazy_static! {
+    /// The latest dumps with the processes they belong to, oldest first.
+    static ref DUMPS: Mutex<Vec<(ProcessID, Arc<Vec<u8>>)>> = Mutex::new(Vec::new());
+}
+
+/// Writes a core dump of the current process, if its limit allows it.
+///
+/// The registers are those of the faulting thread.
+pub fn dump_current_process(registers: &Registers) {
+    let pid = CURRENT_THREAD.lock().pid;
+    let pcb = get_current_process();
+
+    let limit = pcb.get_limit(Resource::CoreSize).soft;
+    if limit == 0 {
+        return;
+    }
+
+    let segments = pcb.address_space.user_segments();
+    let core = build(
+        &segments,
+        registers,
+        usize::from(pid) as u32,
+        limit,
+        |buffer, address| pcb.address_space.read_from(buffer, address)
+    );
+
+    let core = match core {
+        Some(core) => core,
+        None => {
+            warn!("The core size limit of {:?} is too small for a dump.", pid);
+            return;
+        }
+    };
+
+    info!(
+        "Wrote a core dump of {} bytes to {}{}.",
+        core.len(),
+        CORE_DIRECTORY,
+        usize::from(pid)
+    );
+
+    let mut dumps = DUMPS.lock();
+    dumps.retain(|&(dumped_pid, _)| dumped_pid != pid);
+    if dumps.len() == MAX_DUMPS {
+        dumps.remove(0);
+    }
+    dumps.push((pid, Arc::new(core)));
+}
+
+/// The filesystem that contains the kept dumps, named by process ID.
+pub struct CoreFileSystem;
+
+impl FileSystem for CoreFileSystem {
+    fn open(&self, name: &str) -> Result<Box<FileHandle>> {
+        let pid: usize = name.parse().map_err(|_| FileError::FileNotFound)?;
+
+        let content = DUMPS
+            .lock()
+            .iter()
+            .find(|&&(dumped_pid, _)| usize::from(dumped_pid) == pid)
+            .map(|&(_, ref content)| content.clone())
+            .ok_or(FileError::FileNotFound)?;
+
+        Ok(Box::new(CoreFile { content, offset: 0 }))
+    }
+}
+
+/// An opened dump.
+struct CoreFile {
+    /// The content of the dump.
+    content: Arc<Vec<u8>>,
+    /// The current seek position.
+    offset: u64
+}
+
+impl FileHandle for CoreFile {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64> {
+        let size = self.content.len() as u64;
+        let offset = match position {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(distance) => offset_by(self.offset, distance)?,
+            SeekFrom::End(distance) => offset_by(size, distance)?
+        };
+
+        if offset > size {
+            Err(FileError::SeekPastEnd)
+        } else {
+            self.offset = offset;
+            Ok(offset)
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let start = min(self.offset, self.content.len() as u64) as usize;
+
+        if start + buffer.len() > self.content.len() {
+            return Err(FileError::SeekPastEnd);
+        }
+
+        buffer.copy_from_slice(&self.content[start..start + buffer.len()]);
+        self.offset += buffer.len() as u64;
+
+        Ok(())
+    }
+
+    fn len(&mut self) -> u64 {
+        self.content.len() as u64
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        POLL_IN
+    }
+}
+
+/// Builds a core file of at most `limit` bytes.
+///
+/// `read` fills the buffer from the given address and returns false if the
+/// memory isn't mapped, in which case it is dumped as zeros. Returns `None`
+/// if not even the registers fit within the limit.
+fn build<F>(
+    segments: &[(MemoryArea<VirtualAddress>, PageFlags)],
+    registers: &Registers,
+    pid: u32,
+    limit: usize,
+    mut read: F
+) -> Option<Vec<u8>>
+where
+    F: FnMut(&mut [u8], VirtualAddress) -> bool
+{
+    // The segment data starts after the room for the headers of all
+    // segments, since it is only known afterwards which of them fit.
+    let headers_size = HEADER_SIZE + (segments.len() + 1) * PROGRAM_HEADER_SIZE + NOTE_SIZE;
+    let mut included = Vec::new();
+    let mut data_end = 0;
+
+    for (index, &(area, _)) in segments.iter().enumerate() {
+        let start = file_offset(area.start_address(), max(headers_size, data_end));
+
+        match start.checked_add(area.length()) {
+            Some(end) if end <= limit => {
+                included.push((index, start));
+                data_end = end;
+            },
+            _ => break
+        }
+    }
+
+    let note_offset = HEADER_SIZE + (included.len() + 1) * PROGRAM_HEADER_SIZE;
+    let size = max(note_offset + NOTE_SIZE, data_end);
+
+    if size > limit {
+        return None;
+    }
+
+    let mut core = Vec::with_capacity(size);
+    core.resize(size, 0);
+
+    write_header(&mut core, included.len() as u16 + 1);
+    write_program_header(
+        &mut core[HEADER_SIZE..],
+        SEGMENT_TYPE_NOTE,
+        0,
+        note_offset,
+        MemoryArea::new(VirtualAddress::from_usize(0), 0),
+        NOTE_SIZE,
+        4
+    );
+    write_note(&mut core[note_offset..], registers, pid);
+
+    for (header_index, &(index, start)) in included.iter().enumerate() {
+        let (area, flags) = segments[index];
+        let header_offset = HEADER_SIZE + (header_index + 1) * PROGRAM_HEADER_SIZE;
+
+        write_program_header(
+            &mut core[header_offset..],
+            SEGMENT_TYPE_LOAD,
+            segment_flags(flags),
+            start,
+            area,
+            area.length(),
+            PAGE_SIZE
+        );
+
+        let mut copied = 0;
+        while copied < area.length() {
+            let address = area.start_address() + copied;
+            let chunk_size = min(PAGE_SIZE - address.offset_in_page(), area.length() - copied);
+            let chunk = &mut core[start + copied..start + copied + chunk_size];
+
+            if !read(chunk, address) {
+                for byte in chunk.iter_mut() {
+                    *byte = 0;
+                }
+            }
+
+            copied += chunk_size;
+        }
+    }
+
+    Some(core)
+}
+
+/// Returns the first offset at or after `minimum` that lies at the same
+/// offset within a page as the address.
+fn file_offset(address: VirtualAddress, minimum: usize) -> usize {
+    let page_start = (minimum + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+
+    page_start + address.offset_in_page()
+}
+
+/// Writes the ELF header.
+fn write_header(core: &mut [u8], program_header_count: u16) {
+    core[0..4].copy_from_slice(b"\x7fELF");
+    // 64-bit, little endian, version 1.
+    core[4] = 2;
+    core[5] = 1;
+    core[6] = 1;
+
+    write_le(&mut core[16..], u64::from(TYPE_CORE), 2);
+    write_le(&mut core[18..], u64::from(MACHINE_X86_64), 2);
+    write_le(&mut core[20..], 1, 4);
+    write_le(&mut core[32..], HEADER_SIZE as u64, 8);
+    write_le(&mut core[52..], HEADER_SIZE as u64, 2);
+    write_le(&mut core[54..], PROGRAM_HEADER_SIZE as u64, 2);
+    write_le(&mut core[56..], u64::from(program_header_count), 2);
+    write_le(&mut core[58..], u64::from(SECTION_HEADER_SIZE), 2);
+}
+
+/// Writes a program header.
+fn write_program_header(
+    header: &mut [u8],
+    segment_type: u32,
+    flags: u32,
+    offset: usize,
+    area: MemoryArea<VirtualAddress>,
+    file_size: usize,
+    alignment: usize
+) {
+    write_le(&mut header[0..], u64::from(segment_type), 4);
+    write_le(&mut header[4..], u64::from(flags), 4);
+    write_le(&mut header[8..], offset as u64, 8);
+    write_le(&mut header[16..], area.start_address().as_usize() as u64, 8);
+    write_le(&mut header[32..], file_size as u64, 8);
+    write_le(&mut header[40..], area.length() as u64, 8);
+    write_le(&mut header[48..], alignment as u64, 8);
+}
+
+/// Writes the note with the registers of the faulting thread.
+fn write_note(note: &mut [u8], registers: &Registers, pid: u32) {
+    write_le(&mut note[0..], u64::from(NOTE_NAME_LENGTH), 4);
+    write_le(&mut note[4..], PRSTATUS_SIZE as u64, 4);
+    write_le(&mut note[8..], u64::from(NOTE_TYPE_PRSTATUS), 4);
+    note[12..20].copy_from_slice(NOTE_NAME);
+
+    let status = &mut note[20..20 + PRSTATUS_SIZE];
+    write_le(&mut status[0..], u64::from(SIGNAL_SEGMENTATION_FAULT), 4);
+    write_le(&mut status[12..], u64::from(SIGNAL_SEGMENTATION_FAULT), 2);
+    write_le(&mut status[PRSTATUS_PID_OFFSET..], u64::from(pid), 4);
+
+    let values = [
+        (REGISTER_FRAME_POINTER, registers.frame_pointer),
+        (REGISTER_INSTRUCTION_POINTER, registers.instruction_pointer),
+        (REGISTER_FLAGS, registers.flags),
+        (REGISTER_STACK_POINTER, registers.stack_pointer)
+    ];
+
+    for &(index, value) in &values {
+        write_le(
+            &mut status[PRSTATUS_REGISTERS_OFFSET + index * 8..],
+            value as u64,
+            8
+        );
+    }
+}
+
+/// Returns the ELF segment flags for the page flags.
+fn segment_flags(flags: PageFlags) -> u32 {
+    let mut segment_flags = 0;
+
+    if flags.contains(EXECUTABLE) {
+        segment_flags |= 0x1;
+    }
+    if flags.contains(WRITABLE) {
+        segment_flags |= 0x2;
+    }
+    if flags.contains(READABLE) {
+        segment_flags |= 0x4;
+    }
+
+    segment_flags
+}
+
+/// Writes the lowest `size` bytes of the value in little endian.
+fn write_le(bytes: &mut [u8], value: u64, size: usize) {
+    for (index, byte) in bytes[..size].iter_mut().enumerate() {
+        *byte = (value >> (8 * index)) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::USER_ACCESSIBLE;
+
+    /// Reads the little endian value of `size` bytes at the offset.
+    fn read_le(bytes: &[u8], offset: usize, size: usize) -> u64 {
+        bytes[offset..offset + size]
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | u64::from(byte))
+    }
+
+    /// Returns a segment at the address with the length.
+    fn segment(address: usize, length: usize) -> (MemoryArea<VirtualAddress>, PageFlags) {
+        (
+            MemoryArea::new(VirtualAddress::from_usize(address), length),
+            READABLE | WRITABLE | USER_ACCESSIBLE
+        )
+    }
+
+    /// Fills the buffer with the lowest byte of the address, except for the
+    /// page at 0x5000, which isn't mapped.
+    fn read(buffer: &mut [u8], address: VirtualAddress) -> bool {
+        if address.page_num() == 5 {
+            return false;
+        }
+
+        for byte in buffer.iter_mut() {
+            *byte = address.as_usize() as u8 | 1;
+        }
+
+        true
+    }
+
+    #[test]
+    fn core_contains_headers_and_registers() {
+        let registers = Registers {
+            instruction_pointer: 0x1234,
+            stack_pointer: 0x5678,
+            ..Default::default()
+        };
+        let core = build(&[segment(0x4000, 0x2000)], &registers, 7, 1 << 20, read).unwrap();
+
+        assert_eq!(&core[0..4], b"\x7fELF");
+        assert_eq!(read_le(&core, 16, 2), u64::from(TYPE_CORE));
+        assert_eq!(read_le(&core, 56, 2), 2);
+
+        let note_offset = read_le(&core, HEADER_SIZE + 8, 8) as usize;
+        let status = note_offset + 20;
+        assert_eq!(read_le(&core, status + PRSTATUS_PID_OFFSET, 4), 7);
+        assert_eq!(
+            read_le(&core, status + PRSTATUS_REGISTERS_OFFSET + 16 * 8, 8),
+            0x1234
+        );
+        assert_eq!(
+            read_le(&core, status + PRSTATUS_REGISTERS_OFFSET + 19 * 8, 8),
+            0x5678
+        );
+    }
+
+    #[test]
+    fn unmapped_pages_are_zero() {
+        let registers = Registers::default();
+        let core = build(&[segment(0x4000, 0x2000)], &registers, 1, 1 << 20, read).unwrap();
+
+        let header = HEADER_SIZE + PROGRAM_HEADER_SIZE;
+        let offset = read_le(&core, header + 8, 8) as usize;
+        assert_eq!(offset % PAGE_SIZE, 0);
+        assert_eq!(read_le(&core, header + 16, 8), 0x4000);
+        assert_eq!(core[offset], 1);
+        assert_eq!(core[offset + PAGE_SIZE], 0);
+    }
+
+    #[test]
+    fn segments_beyond_the_limit_are_left_out() {
+        let segments = [segment(0x4000, 0x1000), segment(0x10000, 0x1000)];
+        let core = build(&segments, &Registers::default(), 1, 0x2000, read).unwrap();
+
+        assert_eq!(read_le(&core, 56, 2), 2);
+        assert!(core.len() <= 0x2000);
+
+        assert!(build(&segments, &Registers::default(), 1, 0x100, read).is_none());
+    }
+}
@@ -0,0 +1,117 @@
+//! This module counts how often instrumented kernel functions are called.
+//!
+//! The counts are only collected with the `coverage` feature. A function is
+//! instrumented by starting it with `cover!(name)`, which defines a counter
+//! for it in the `.coverage` section. The linker places all counters next to
+//! each other, so `dump` also reports the functions that were never called,
+//! which are the code paths that the test scenarios miss.
+//!
+//! The counters are dumped over the serial port when the kernel panics and
+//! when a process requests it, which the userspace test runner does after
+//! all tests ran.
+
+use config;
+use core::mem::size_of;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The call counter of an instrumented function.
+pub struct Counter {
+    /// The path of the function.
+    name: &'static str,
+    /// The number of calls.
+    calls: AtomicUsize
+}
+
+extern "C" {
+    /// The start of the `.coverage` section.
+    #[link_name = "coverage_start"]
+    static COVERAGE_START: u8;
+
+    /// The end of the `.coverage` section.
+    #[link_name = "coverage_end"]
+    static COVERAGE_END: u8;
+}
+
+impl Counter {
+    /// Creates a counter for the function with the given path.
+    pub const fn new(name: &'static str) -> Counter {
+        Counter {
+            name,
+            calls: AtomicUsize::new(0)
+        }
+    }
+
+    /// Records a call of the function.
+    pub fn record_call(&self) {
+        if config::COVERAGE {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the number of recorded calls.
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the counters of all instrumented functions.
+fn counters() -> &'static [Counter] {
+    unsafe {
+        let start = &COVERAGE_START as *const u8 as usize;
+        let end = &COVERAGE_END as *const u8 as usize;
+        let count = (end - start) / size_of::<Counter>();
+
+        slice::from_raw_parts(start as *const Counter, count)
+    }
+}
+
+/// Returns the number of counters of functions that were called.
+fn called_count(counters: &[Counter]) -> usize {
+    counters
+        .iter()
+        .filter(|counter| counter.calls() > 0)
+        .count()
+}
+
+/// Writes the counters of all instrumented functions to the serial port.
+///
+/// Every counter is written on its own line as `coverage: <calls> <name>`.
+/// Returns false if the kernel doesn't count calls.
+pub fn dump() -> bool {
+    if !config::COVERAGE {
+        return false;
+    }
+
+    let counters = counters();
+
+    serial_println!(
+        "coverage: {} of {} instrumented functions were called",
+        called_count(counters),
+        counters.len()
+    );
+
+    for counter in counters {
+        serial_println!("coverage: {} {}", counter.calls(), counter.name);
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that only called functions are counted.
+    #[test]
+    fn counts_called_functions() {
+        let counters = [Counter::new("a"), Counter::new("b"), Counter::new("c")];
+
+        assert_eq!(called_count(&counters), 0);
+
+        counters[0].calls.fetch_add(1, Ordering::Relaxed);
+        counters[2].calls.fetch_add(3, Ordering::Relaxed);
+
+        assert_eq!(called_count(&counters), 2);
+    }
+}
@@ -0,0 +1,336 @@
+//! Scales the frequency of the CPU with its load.
+//!
+//! The architecture provides the performance states (P-states) of the CPU.
+//! A governor decides which of them is used:
+//!
+//! - `performance`: The fastest state.
+//! - `powersave`: The slowest state.
+//! - `ondemand`: The fastest state while the CPU is busy, otherwise a state
+//!   that is just fast enough for the load. The load is sampled periodically
+//!   from the idle time the scheduler accounts.
+//!
+//! The governor is `ondemand`, unless `cpufreq=<governor>` on the kernel
+//! command line selects another one. It can be changed with the
+//! `set_governor` syscall and `/proc/cpufreq` shows the current state.
+//!
+//! While the CPU is too hot, it is throttled to the slowest state, regardless
+//! of the governor.
+//!
+//! The kernel only runs on one CPU so far, so only that CPU is scaled.
+
+use alloc::string::String;
+use alloc::Vec;
+use arch::{self, schedule, Architecture};
+use boot;
+use core::fmt::Write;
+use core::time::Duration;
+use multitasking::scheduler::{after_context_switch, get_idle_time};
+use multitasking::{get_cpu_id, spawn_kernel_thread, Name, ThreadState, CURRENT_THREAD};
+use procfs;
+use sync::enable_preemption;
+use sync::time::Timestamp;
+use sync::Mutex;
+
+/// The interval in which the load is sampled.
+const SAMPLE_INTERVAL_MS: u64 = 100;
+
+/// The load in percent above which `ondemand` selects the fastest state.
+const UP_THRESHOLD_PERCENT: u32 = 80;
+
+/// A performance state of the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerformanceState {
+    /// The frequency of the CPU in this state in MHz.
+    pub frequency_mhz: u32,
+    /// The architecture specific value that selects the state.
+    pub control: u64
+}
+
+/// Decides which performance state is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Governor {
+    /// Always uses the fastest state.
+    Performance,
+    /// Always uses the slowest state.
+    Powersave,
+    /// Follows the load of the CPU.
+    OnDemand
+}
+
+impl Governor {
+    /// Returns the governor with the given number, as used by the syscall.
+    pub fn from_number(number: usize) -> Option<Governor> {
+        match number {
+            0 => Some(Governor::Performance),
+            1 => Some(Governor::Powersave),
+            2 => Some(Governor::OnDemand),
+            _ => None
+        }
+    }
+
+    /// Returns the governor with the given name.
+    fn from_name(name: &str) -> Option<Governor> {
+        match name {
+            "performance" => Some(Governor::Performance),
+            "powersave" => Some(Governor::Powersave),
+            "ondemand" => Some(Governor::OnDemand),
+            _ => None
+        }
+    }
+
+    /// Returns the name of the governor.
+    fn name(self) -> &'static str {
+        match self {
+            Governor::Performance => "performance",
+            Governor::Powersave => "powersave",
+            Governor::OnDemand => "ondemand"
+        }
+    }
+}
+
+/// The state of the frequency scaling.
+struct CpuFreq {
+    /// The performance states, the fastest one first.
+    states: Vec<PerformanceState>,
+    /// The current governor.
+    governor: Governor,
+    /// The index of the current state.
+    current: usize,
+    /// The load in percent at the last sample.
+    load_percent: u32,
+    /// Whether the slowest state is forced.
+    throttled: bool,
+    /// The time of the last sample.
+    last_sample: Timestamp,
+    /// The idle time of the CPU at the last sample.
+    last_idle_time: Duration
+}
+
+impl CpuFreq {
+    /// Measures the load since the last sample and selects the state the
+    /// governor wants.
+    fn sample(&mut self) {
+        let now = Timestamp::get_current();
+        let idle_time = get_idle_time(get_cpu_id());
+
+        let elapsed = as_micros(now.checked_sub(self.last_sample).unwrap_or_default());
+        let idle = as_micros(idle_time - self.last_idle_time);
+
+        if elapsed > 0 {
+            self.load_percent = 100 - (idle.min(elapsed) * 100 / elapsed) as u32;
+        }
+
+        self.last_sample = now;
+        self.last_idle_time = idle_time;
+
+        self.apply();
+    }
+
+    /// Switches to the state the governor wants, if it isn't used already.
+    fn apply(&mut self) {
+        let index = if self.throttled {
+            self.states.len() - 1
+        } else {
+            choose_state(&self.states, self.governor, self.load_percent)
+        };
+
+        if index != self.current {
+            arch::Current::set_performance_state(&self.states[index]);
+            self.current = index;
+        }
+    }
+}
+
+/// The frequency scaling, if the CPU supports it.
+static CPUFREQ: Mutex<Option<CpuFreq>> = Mutex::new(None);
+
+/// Reads the performance states and starts the governor.
+///
+/// This must be called once while the address space of the idle process is
+/// active.
+pub fn init() {
+    assert_has_not_been_called!("The frequency scaling should only be started once.");
+
+    procfs::register("cpufreq", describe);
+
+    let states = arch::Current::get_performance_states();
+    if states.is_empty() {
+        debug!("The CPU doesn't support frequency scaling.");
+        return;
+    }
+
+    let governor = match boot::get_option_value("cpufreq") {
+        Some(name) => Governor::from_name(name).unwrap_or_else(|| {
+            warn!("Unknown cpufreq governor \"{}\", using ondemand.", name);
+            Governor::OnDemand
+        }),
+        None => Governor::OnDemand
+    };
+
+    info!(
+        "Scaling the CPU between {} and {} MHz ({}).",
+        states[states.len() - 1].frequency_mhz,
+        states[0].frequency_mhz,
+        governor.name()
+    );
+
+    let mut cpufreq = CpuFreq {
+        states,
+        governor,
+        current: 0,
+        load_percent: 100,
+        throttled: false,
+        last_sample: Timestamp::get_current(),
+        last_idle_time: get_idle_time(get_cpu_id())
+    };
+
+    // The CPU starts out in an unknown state.
+    arch::Current::set_performance_state(&cpufreq.states[0]);
+    cpufreq.apply();
+
+    *CPUFREQ.lock() = Some(cpufreq);
+
+    spawn_kernel_thread(Name::new("cpufreq"), governor_thread);
+}
+
+/// Selects the governor.
+///
+/// Returns false if the CPU doesn't support frequency scaling.
+pub fn set_governor(governor: Governor) -> bool {
+    match *CPUFREQ.lock() {
+        Some(ref mut cpufreq) => {
+            cpufreq.governor = governor;
+            cpufreq.apply();
+
+            true
+        },
+        None => false
+    }
+}
+
+/// Forces the slowest state while `throttled` is true.
+///
+/// Returns false if the CPU doesn't support frequency scaling.
+pub fn set_throttled(throttled: bool) -> bool {
+    match *CPUFREQ.lock() {
+        Some(ref mut cpufreq) => {
+            cpufreq.throttled = throttled;
+            cpufreq.apply();
+
+            true
+        },
+        None => false
+    }
+}
+
+/// Returns the index of the state the governor selects for the load.
+///
+/// The states are sorted with the fastest one first.
+fn choose_state(states: &[PerformanceState], governor: Governor, load_percent: u32) -> usize {
+    let slowest = states.len() - 1;
+
+    match governor {
+        Governor::Performance => 0,
+        Governor::Powersave => slowest,
+        Governor::OnDemand => {
+            if load_percent >= UP_THRESHOLD_PERCENT {
+                return 0;
+            }
+
+            // The frequency grows with the load from the slowest to the
+            // fastest state.
+            let min = states[slowest].frequency_mhz;
+            let max = states[0].frequency_mhz;
+            let target = min + (max - min) * load_percent / 100;
+
+            states
+                .iter()
+                .rposition(|state| state.frequency_mhz >= target)
+                .unwrap_or(0)
+        }
+    }
+}
+
+/// Generates the content of `/proc/cpufreq`.
+fn describe() -> String {
+    let mut text = String::new();
+
+    match *CPUFREQ.lock() {
+        Some(ref cpufreq) => {
+            let frequency = cpufreq.states[cpufreq.current].frequency_mhz;
+
+            writeln!(text, "governor: {}", cpufreq.governor.name()).unwrap();
+            writeln!(text, "frequency: {} MHz", frequency).unwrap();
+            writeln!(text, "load: {}%", cpufreq.load_percent).unwrap();
+            writeln!(text, "throttled: {}", cpufreq.throttled).unwrap();
+            write!(text, "states:").unwrap();
+            for state in cpufreq.states.iter() {
+                write!(text, " {}", state.frequency_mhz).unwrap();
+            }
+            writeln!(text).unwrap();
+        },
+        None => writeln!(text, "unsupported").unwrap()
+    }
+
+    text
+}
+
+/// Returns the duration in microseconds.
+fn as_micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + u64::from(duration.subsec_nanos() / 1000)
+}
+
+/// The function the governor thread runs.
+fn governor_thread() -> ! {
+    // The thread starts right after a context switch away from another
+    // thread.
+    after_context_switch();
+    unsafe {
+        enable_preemption();
+    }
+
+    loop {
+        if let Some(ref mut cpufreq) = *CPUFREQ.lock() {
+            if cpufreq.governor == Governor::OnDemand {
+                cpufreq.sample();
+            }
+        }
+
+        let wake_time = Timestamp::get_current()
+            .offset(Duration::from_millis(SAMPLE_INTERVAL_MS))
+            .expect("The time since boot overflowed.");
+
+        CURRENT_THREAD.lock().state = ThreadState::Sleeping(wake_time);
+        schedule();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns states from 3000 MHz down to 1000 MHz in steps of 500 MHz.
+    fn states() -> Vec<PerformanceState> {
+        (2..7)
+            .rev()
+            .map(|step| PerformanceState {
+                frequency_mhz: step * 500,
+                control: u64::from(step)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fixed_governors_ignore_the_load() {
+        assert_eq!(choose_state(&states(), Governor::Performance, 0), 0);
+        assert_eq!(choose_state(&states(), Governor::Powersave, 100), 4);
+    }
+
+    #[test]
+    fn ondemand_follows_the_load() {
+        assert_eq!(choose_state(&states(), Governor::OnDemand, 0), 4);
+        assert_eq!(choose_state(&states(), Governor::OnDemand, 30), 2);
+        assert_eq!(choose_state(&states(), Governor::OnDemand, 60), 1);
+        assert_eq!(choose_state(&states(), Governor::OnDemand, 80), 0);
+    }
+}
@@ -0,0 +1,404 @@
+//! Writes a dump of the kernel state when the kernel crashes.
+//!
+//! The dump is written to a memory area that is reserved for it, so it isn't
+//! lost when the kernel halts. The host can read it from the memory of the
+//! machine, `cargo xtask crashdump` finds it in a memory image and prints it.
+//! The next boot reports the crash of the previous one if the memory wasn't
+//! cleared in between.
+//!
+//! A dump starts with a header that contains a magic number, the version of
+//! the format, the number and total length of the sections and a checksum of
+//! them. Each section starts with its kind and length and is padded to eight
+//! bytes. All numbers are little endian. The reason of the crash, the
+//! backtrace, the running threads and the recent log records are stored as
+//! text, the page table section contains the raw entries of the top level
+//! page table. Sections that don't fit into the area are cut off.
+
+use arch::{self, Architecture};
+use core::cmp::min;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use core::{slice, str};
+use ksymbol;
+use logger;
+use memory::{Address, VirtualAddress};
+use multitasking::backtrace::collect_kernel_backtrace;
+use multitasking::{get_cpu_id, get_cpu_num, CURRENT_THREAD};
+
+/// The magic number at the start of a dump.
+const MAGIC: &[u8; 8] = b"VEOSDUMP";
+
+/// The version of the format.
+const VERSION: u32 = 1;
+
+/// The size of the header of a dump.
+const HEADER_SIZE: usize = 32;
+
+/// The size of the header of a section.
+const SECTION_HEADER_SIZE: usize = 8;
+
+/// The alignment of the sections.
+const SECTION_ALIGNMENT: usize = 8;
+
+/// The section that describes the reason of the crash.
+const REASON_SECTION: u32 = 1;
+
+/// The section that contains the backtrace of the crash.
+const BACKTRACE_SECTION: u32 = 2;
+
+/// The section that lists the threads running on each CPU.
+const THREADS_SECTION: u32 = 3;
+
+/// The section that contains the recent log records.
+const LOG_SECTION: u32 = 4;
+
+/// The section that contains the top level page table.
+const PAGE_TABLE_SECTION: u32 = 5;
+
+/// The maximum number of frames in the backtrace.
+const MAX_FRAMES: usize = 32;
+
+/// Whether the area is ready for a dump.
+static READY: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Whether a dump was written already.
+static WRITTEN: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Writes a dump into a buffer.
+struct Writer<'a> {
+    /// The buffer that the dump is written to.
+    buffer: &'a mut [u8],
+    /// The offset where the next byte is written.
+    position: usize,
+    /// The offset of the section being written, if it fits.
+    section_start: Option<usize>,
+    /// The number of sections written.
+    sections: u32
+}
+
+impl<'a> Writer<'a> {
+    /// Starts a dump in the given buffer.
+    fn new(buffer: &'a mut [u8]) -> Writer<'a> {
+        assert!(buffer.len() >= HEADER_SIZE && buffer.len() % SECTION_ALIGNMENT == 0);
+
+        Writer {
+            buffer,
+            position: HEADER_SIZE,
+            section_start: None,
+            sections: 0
+        }
+    }
+
+    /// Starts a section of the given kind.
+    ///
+    /// If there is no space left for the section, its content is dropped.
+    fn begin(&mut self, kind: u32) {
+        if self.buffer.len() - self.position < SECTION_HEADER_SIZE {
+            return;
+        }
+
+        write_le(&mut self.buffer[self.position..], u64::from(kind), 4);
+        write_le(&mut self.buffer[self.position + 4..], 0, 4);
+        self.section_start = Some(self.position);
+        self.position += SECTION_HEADER_SIZE;
+    }
+
+    /// Appends the bytes to the current section, as far as they fit.
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.section_start.is_none() {
+            return;
+        }
+
+        let length = min(bytes.len(), self.buffer.len() - self.position);
+        self.buffer[self.position..self.position + length].copy_from_slice(&bytes[..length]);
+        self.position += length;
+    }
+
+    /// Finishes the current section.
+    fn end(&mut self) {
+        if let Some(start) = self.section_start.take() {
+            let length = self.position - start - SECTION_HEADER_SIZE;
+            write_le(&mut self.buffer[start + 4..], length as u64, 4);
+
+            // The buffer length is a multiple of the alignment, so the
+            // padding always fits.
+            while self.position % SECTION_ALIGNMENT != 0 {
+                self.buffer[self.position] = 0;
+                self.position += 1;
+            }
+
+            self.sections += 1;
+        }
+    }
+
+    /// Writes the header and returns the length of the dump.
+    fn finish(self) -> usize {
+        let length = self.position - HEADER_SIZE;
+        let checksum = checksum(&self.buffer[HEADER_SIZE..self.position]);
+
+        write_le(&mut self.buffer[8..], u64::from(VERSION), 4);
+        write_le(&mut self.buffer[12..], u64::from(self.sections), 4);
+        write_le(&mut self.buffer[16..], length as u64, 8);
+        write_le(&mut self.buffer[24..], checksum, 8);
+        // The magic number comes last, so that a partial dump isn't valid.
+        self.buffer[..MAGIC.len()].copy_from_slice(MAGIC);
+
+        self.position
+    }
+}
+
+impl<'a> Write for Writer<'a> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        self.write_bytes(string.as_bytes());
+        Ok(())
+    }
+}
+
+/// Reports the dump of the previous boot and prepares the area for the next
+/// one.
+///
+/// The area must be mapped when this is called.
+pub fn init() {
+    assert_has_not_been_called!("The crash dump area should only be prepared once.");
+
+    let area = get_area();
+
+    if let Some(sections) = validate(area) {
+        let reason = find_section(sections, REASON_SECTION)
+            .and_then(|reason| str::from_utf8(reason).ok())
+            .unwrap_or("unknown");
+        warn!("The previous boot crashed: {}", reason);
+    }
+
+    // Clearing the magic number is enough to invalidate the old dump.
+    for byte in area[..MAGIC.len()].iter_mut() {
+        *byte = 0;
+    }
+
+    READY.store(true, Ordering::Release);
+}
+
+/// Writes a dump of the kernel state.
+///
+/// The backtrace starts at the given program counter and follows the chain
+/// of frame pointers starting at `frame_pointer`. Only the first crash is
+/// dumped, because later ones are likely caused by the first.
+pub fn write(
+    reason: &fmt::Display,
+    program_counter: VirtualAddress,
+    frame_pointer: VirtualAddress
+) {
+    if !READY.load(Ordering::Acquire) || WRITTEN.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let mut writer = Writer::new(get_area());
+
+    writer.begin(REASON_SECTION);
+    let _ = write!(writer, "{} (on cpu{})", reason, get_cpu_id());
+    writer.end();
+
+    writer.begin(BACKTRACE_SECTION);
+    write_backtrace(&mut writer, program_counter, frame_pointer);
+    writer.end();
+
+    writer.begin(THREADS_SECTION);
+    for cpu_id in 0..get_cpu_num() {
+        // The crash may have happened while a thread was locked.
+        let _ = match CURRENT_THREAD.get_specific(cpu_id).try_lock() {
+            Some(thread) => writeln!(writer, "cpu{}: {:?}", cpu_id, *thread),
+            None => writeln!(writer, "cpu{}: <locked>", cpu_id)
+        };
+    }
+    writer.end();
+
+    writer.begin(LOG_SECTION);
+    logger::read_history(|older, newer| {
+        writer.write_bytes(older);
+        writer.write_bytes(newer);
+    });
+    writer.end();
+
+    writer.begin(PAGE_TABLE_SECTION);
+    for &entry in arch::Current::get_top_level_entries().iter() {
+        let mut bytes = [0; 8];
+        write_le(&mut bytes, entry, 8);
+        writer.write_bytes(&bytes);
+    }
+    writer.end();
+
+    let length = writer.finish();
+
+    error!(
+        "Wrote a crash dump of {} bytes at {:?}.",
+        length,
+        arch::Current::get_crash_dump_area().start_address()
+    );
+}
+
+/// Writes the backtrace of the crash, with the function of each frame.
+fn write_backtrace(
+    writer: &mut Writer,
+    program_counter: VirtualAddress,
+    frame_pointer: VirtualAddress
+) {
+    let mut frames = [VirtualAddress::from_usize(0); MAX_FRAMES];
+    let count = collect_kernel_backtrace(program_counter, frame_pointer, &mut frames);
+
+    // A panic doesn't know its program counter, so the first frame is empty.
+    let frames = frames[..count]
+        .iter()
+        .filter(|address| address.as_usize() != 0);
+
+    for (index, &address) in frames.enumerate() {
+        let _ = match ksymbol::resolve(address) {
+            Some(symbol) => writeln!(writer, "#{:<2} {:?} ({})", index, address, symbol),
+            None => writeln!(writer, "#{:<2} {:?} (unknown function)", index, address)
+        };
+    }
+}
+
+/// Returns the reserved area.
+fn get_area() -> &'static mut [u8] {
+    let area = arch::Current::get_crash_dump_area();
+
+    // The area is reserved for the dump and mapped at its kernel address.
+    unsafe {
+        slice::from_raw_parts_mut(
+            area.start_address().to_virtual().as_mut_ptr(),
+            area.length()
+        )
+    }
+}
+
+/// Returns the sections of the dump in the buffer, if it contains a valid
+/// dump.
+fn validate(buffer: &[u8]) -> Option<&[u8]> {
+    if buffer.len() < HEADER_SIZE
+        || &buffer[..MAGIC.len()] != &MAGIC[..]
+        || read_le(buffer, 8, 4) != u64::from(VERSION)
+    {
+        return None;
+    }
+
+    let length = read_le(buffer, 16, 8) as usize;
+
+    if length > buffer.len() - HEADER_SIZE {
+        return None;
+    }
+
+    let sections = &buffer[HEADER_SIZE..HEADER_SIZE + length];
+
+    if checksum(sections) == read_le(buffer, 24, 8) {
+        Some(sections)
+    } else {
+        None
+    }
+}
+
+/// Returns the content of the first section of the given kind.
+fn find_section(sections: &[u8], kind: u32) -> Option<&[u8]> {
+    let mut offset = 0;
+
+    while sections.len() - offset >= SECTION_HEADER_SIZE {
+        let length = read_le(sections, offset + 4, 4) as usize;
+        let start = offset + SECTION_HEADER_SIZE;
+
+        if length > sections.len() - start {
+            return None;
+        }
+
+        if read_le(sections, offset, 4) == u64::from(kind) {
+            return Some(&sections[start..start + length]);
+        }
+
+        offset = start + length;
+        offset += (SECTION_ALIGNMENT - offset % SECTION_ALIGNMENT) % SECTION_ALIGNMENT;
+    }
+
+    None
+}
+
+/// Returns the FNV-1a hash of the bytes.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Writes the lowest `size` bytes of `value` in little endian.
+fn write_le(bytes: &mut [u8], value: u64, size: usize) {
+    for (index, byte) in bytes[..size].iter_mut().enumerate() {
+        *byte = (value >> (8 * index)) as u8;
+    }
+}
+
+/// Reads a little endian number of `size` bytes at `offset`.
+fn read_le(bytes: &[u8], offset: usize, size: usize) -> u64 {
+    bytes[offset..offset + size]
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | u64::from(byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a dump with the given sections into the buffer.
+    fn dump(buffer: &mut [u8], sections: &[(u32, &[u8])]) -> usize {
+        let mut writer = Writer::new(buffer);
+
+        for &(kind, content) in sections {
+            writer.begin(kind);
+            writer.write_bytes(content);
+            writer.end();
+        }
+
+        writer.finish()
+    }
+
+    #[test]
+    fn sections_can_be_found() {
+        let mut buffer = [0xff; 128];
+        let length = dump(
+            &mut buffer,
+            &[(REASON_SECTION, b"oops"), (LOG_SECTION, b"the log")]
+        );
+        let sections = validate(&buffer).unwrap();
+
+        assert_eq!(length, HEADER_SIZE + 16 + 16);
+        assert_eq!(read_le(&buffer, 12, 4), 2);
+        assert_eq!(find_section(sections, REASON_SECTION), Some(&b"oops"[..]));
+        assert_eq!(find_section(sections, LOG_SECTION), Some(&b"the log"[..]));
+        assert_eq!(find_section(sections, THREADS_SECTION), None);
+    }
+
+    #[test]
+    fn corrupted_dumps_are_invalid() {
+        let mut buffer = [0; 64];
+        dump(&mut buffer, &[(REASON_SECTION, b"oops")]);
+        assert!(validate(&buffer).is_some());
+
+        buffer[HEADER_SIZE + SECTION_HEADER_SIZE] = b'O';
+        assert!(validate(&buffer).is_none());
+        assert!(validate(&[0; 64]).is_none());
+    }
+
+    #[test]
+    fn sections_are_cut_off() {
+        let mut buffer = [0; 64];
+        let length = dump(
+            &mut buffer,
+            &[(LOG_SECTION, &[b'x'; 40]), (REASON_SECTION, b"oops")]
+        );
+        let sections = validate(&buffer).unwrap();
+
+        assert_eq!(length, 64);
+        assert_eq!(
+            find_section(sections, LOG_SECTION).map(|log| log.len()),
+            Some(64 - HEADER_SIZE - SECTION_HEADER_SIZE)
+        );
+        assert_eq!(find_section(sections, REASON_SECTION), None);
+    }
+}
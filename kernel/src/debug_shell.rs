@@ -0,0 +1,295 @@
+//! A small shell on the serial console for inspecting the kernel.
+//!
+//! The bytes received on COM1 are queued by the interrupt handler and read by
+//! the shell thread, which echoes them and runs a command for every line. The
+//! shell doesn't depend on userspace, so a headless system can be inspected
+//! without attaching a debugger, even if init is stuck.
+//!
+//! The prompt is only printed once the first byte was received, so the
+//! serial output stays the same if nobody uses the shell.
+
+use arch::{self, schedule, Architecture};
+use core::str;
+use core::time::Duration;
+use keyboard::Queue;
+use log::{self, LevelFilter};
+use multitasking::scheduler::{self, after_context_switch};
+use multitasking::{self, spawn_kernel_thread, Name, ThreadState, CURRENT_THREAD};
+use sync::time::Timestamp;
+use sync::{enable_preemption, Mutex};
+
+/// The interval in which the shell checks for input.
+const POLL_INTERVAL_MS: u64 = 20;
+
+/// The maximum length of a command line.
+const MAX_LINE_LENGTH: usize = 80;
+
+/// The byte sent by the backspace key of most terminals.
+const DELETE: u8 = 0x7f;
+
+/// The byte sent by the backspace key of the remaining terminals.
+const BACKSPACE: u8 = 0x08;
+
+/// The prompt printed before every command line.
+const PROMPT: &str = "> ";
+
+/// The text printed by `help`.
+const HELP: &str = "Commands:
+  ps            list the processes
+  threads       list the threads of every CPU
+  mem           show the free memory
+  log           show the log level
+  log <level>   set the log level (off, error, warn, info, debug, trace)
+  reboot        restart the machine
+  help          show this text
+";
+
+/// The received bytes that the shell didn't read yet.
+static INPUT: Mutex<Queue> = Mutex::new(Queue::new());
+
+/// A command of the shell.
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    /// Lists the processes.
+    Processes,
+    /// Lists the threads.
+    Threads,
+    /// Shows the free memory.
+    Memory,
+    /// Shows the log level.
+    ShowLogLevel,
+    /// Sets the log level.
+    SetLogLevel(LevelFilter),
+    /// Restarts the machine.
+    Reboot,
+    /// Lists the commands.
+    Help,
+    /// Does nothing for an empty line.
+    Empty,
+    /// A line that isn't a valid command.
+    Unknown(&'a str)
+}
+
+/// What the terminal has to show after a byte was added to the line.
+#[derive(Debug, PartialEq)]
+enum Edit {
+    /// The byte was appended to the line.
+    Echo(u8),
+    /// The last byte of the line was removed.
+    Erase,
+    /// The line is complete.
+    Complete,
+    /// Nothing changed.
+    Ignore
+}
+
+/// Collects the received bytes into a command line.
+struct LineEditor {
+    /// The bytes of the line.
+    line: [u8; MAX_LINE_LENGTH],
+    /// The number of bytes in the line.
+    length: usize,
+    /// Whether the last byte completed a line with a carriage return.
+    after_return: bool
+}
+
+impl LineEditor {
+    /// Creates an editor with an empty line.
+    fn new() -> LineEditor {
+        LineEditor {
+            line: [0; MAX_LINE_LENGTH],
+            length: 0,
+            after_return: false
+        }
+    }
+
+    /// Adds the received byte to the line.
+    ///
+    /// Only printable ASCII characters are kept. Bytes beyond the maximum
+    /// line length are dropped.
+    fn feed(&mut self, byte: u8) -> Edit {
+        let after_return = self.after_return;
+        self.after_return = byte == b'\r';
+
+        match byte {
+            // Terminals that send both only complete the line once.
+            b'\n' if after_return => Edit::Ignore,
+            b'\r' | b'\n' => Edit::Complete,
+            BACKSPACE | DELETE if self.length > 0 => {
+                self.length -= 1;
+                Edit::Erase
+            },
+            b' '...b'~' if self.length < MAX_LINE_LENGTH => {
+                self.line[self.length] = byte;
+                self.length += 1;
+                Edit::Echo(byte)
+            },
+            _ => Edit::Ignore
+        }
+    }
+
+    /// Returns the line collected so far.
+    fn line(&self) -> &str {
+        str::from_utf8(&self.line[..self.length]).expect("The line contains only ASCII.")
+    }
+
+    /// Starts a new line.
+    fn clear(&mut self) {
+        self.length = 0;
+    }
+}
+
+/// Parses the command line.
+fn parse(line: &str) -> Command {
+    let mut words = line.split_whitespace();
+
+    match (words.next(), words.next(), words.next()) {
+        (None, _, _) => Command::Empty,
+        (Some("ps"), None, _) => Command::Processes,
+        (Some("threads"), None, _) => Command::Threads,
+        (Some("mem"), None, _) => Command::Memory,
+        (Some("log"), None, _) => Command::ShowLogLevel,
+        (Some("log"), Some(level), None) => match level.parse() {
+            Ok(level) => Command::SetLogLevel(level),
+            Err(_) => Command::Unknown(line.trim())
+        },
+        (Some("reboot"), None, _) => Command::Reboot,
+        (Some("help"), None, _) => Command::Help,
+        _ => Command::Unknown(line.trim())
+    }
+}
+
+/// Starts the shell thread.
+///
+/// This must be called once while the address space of the idle process is
+/// active.
+pub fn init() {
+    assert_has_not_been_called!("There should only be one debug shell.");
+
+    spawn_kernel_thread(Name::new("debug shell"), shell);
+}
+
+/// Queues a byte received on the serial console.
+///
+/// Bytes are dropped if the shell doesn't keep up.
+pub fn add_input(byte: u8) {
+    INPUT.lock().push(byte);
+}
+
+/// Returns the oldest byte that the shell didn't read yet.
+fn next_input() -> Option<u8> {
+    INPUT.lock().pop()
+}
+
+/// Runs the command.
+fn run(command: Command) {
+    match command {
+        Command::Processes => serial_print!("{}", multitasking::describe_processes()),
+        Command::Threads => serial_print!("{}", scheduler::describe_threads()),
+        Command::Memory => {
+            serial_println!("{} KiB free", arch::Current::get_free_memory_size() / 1024)
+        },
+        Command::ShowLogLevel => serial_println!("The log level is {}.", log::max_level()),
+        Command::SetLogLevel(level) => {
+            log::set_max_level(level);
+            serial_println!("The log level is now {}.", level);
+        },
+        Command::Reboot => {
+            serial_println!("Rebooting...");
+            arch::Current::reboot();
+        },
+        Command::Help => serial_print!("{}", HELP),
+        Command::Empty => (),
+        Command::Unknown(line) => {
+            serial_println!("Unknown command \"{}\", try \"help\".", line)
+        }
+    }
+}
+
+/// The function the shell thread runs.
+fn shell() -> ! {
+    // The shell starts right after a context switch away from another
+    // thread.
+    after_context_switch();
+    unsafe {
+        enable_preemption();
+    }
+
+    let mut editor = LineEditor::new();
+    let mut started = false;
+
+    loop {
+        while let Some(byte) = next_input() {
+            if !started {
+                serial_print!("\n{}", PROMPT);
+                started = true;
+            }
+
+            match editor.feed(byte) {
+                Edit::Echo(byte) => serial_print!("{}", byte as char),
+                Edit::Erase => serial_print!("\x08 \x08"),
+                Edit::Complete => {
+                    serial_print!("\n");
+                    run(parse(editor.line()));
+                    editor.clear();
+                    serial_print!("{}", PROMPT);
+                },
+                Edit::Ignore => ()
+            }
+        }
+
+        let wake_time = Timestamp::get_current()
+            .offset(Duration::from_millis(POLL_INTERVAL_MS))
+            .expect("The time since boot overflowed.");
+
+        CURRENT_THREAD.lock().state = ThreadState::Sleeping(wake_time);
+        schedule();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that lines are edited and completed.
+    #[test]
+    fn test_line_editor() {
+        let mut editor = LineEditor::new();
+
+        assert_eq!(editor.feed(b'p'), Edit::Echo(b'p'));
+        assert_eq!(editor.feed(b'x'), Edit::Echo(b'x'));
+        assert_eq!(editor.feed(DELETE), Edit::Erase);
+        assert_eq!(editor.feed(0x1b), Edit::Ignore);
+        assert_eq!(editor.feed(b's'), Edit::Echo(b's'));
+        assert_eq!(editor.feed(b'\r'), Edit::Complete);
+        assert_eq!(editor.feed(b'\n'), Edit::Ignore);
+        assert_eq!(editor.line(), "ps");
+
+        editor.clear();
+
+        assert_eq!(editor.feed(BACKSPACE), Edit::Ignore);
+        assert_eq!(editor.feed(b'\n'), Edit::Complete);
+        assert_eq!(editor.line(), "");
+
+        for _ in 0..MAX_LINE_LENGTH {
+            assert_eq!(editor.feed(b'a'), Edit::Echo(b'a'));
+        }
+
+        assert_eq!(editor.feed(b'a'), Edit::Ignore);
+    }
+
+    /// Tests that commands are parsed.
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(" ps "), Command::Processes);
+        assert_eq!(parse("threads"), Command::Threads);
+        assert_eq!(parse("mem"), Command::Memory);
+        assert_eq!(parse(""), Command::Empty);
+        assert_eq!(parse("log"), Command::ShowLogLevel);
+        assert_eq!(parse("log debug"), Command::SetLogLevel(LevelFilter::Debug));
+        assert_eq!(parse("log loud"), Command::Unknown("log loud"));
+        assert_eq!(parse("ps aux "), Command::Unknown("ps aux"));
+        assert_eq!(parse("reboot"), Command::Reboot);
+        assert_eq!(parse("help"), Command::Help);
+    }
+}
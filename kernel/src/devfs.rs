@@ -0,0 +1,79 @@
+//! Makes devices available as files below `/dev/`.
+//!
+//! Drivers register their devices by name together with a function that
+//! opens them. The devices form a filesystem that is mounted at `/dev/`.
+//!
+//! Files of devices that can go away should access the device through a
+//! `DeviceHandle`, so that they fail cleanly once the device is unregistered.
+
+use alloc::boxed::Box;
+use alloc::Vec;
+use file_handle::{FileError, FileHandle, Result};
+use sync::Mutex;
+use vfs::FileSystem;
+
+/// The directory that contains the devices.
+pub const DEVICE_DIRECTORY: &str = "/dev/";
+
+/// Opens a device.
+pub type Opener = Box<Fn() -> Box<FileHandle> + Send>;
+
+/// A registered device.
+struct Device {
+    /// The name of the device within the device directory.
+    name: &'static str,
+    /// Opens the device.
+    open: Opener
+}
+
+lazy_static! {
+    /// All registered devices.
+    static ref DEVICES: Mutex<Vec<Device>> = Mutex::new(Vec::new());
+}
+
+/// Registers a device with the given name.
+///
+/// # Panics
+/// Panics if a device with that name was already registered.
+pub fn register(name: &'static str, open: Opener) {
+    let mut devices = DEVICES.lock();
+
+    assert!(
+        devices.iter().all(|device| device.name != name),
+        "The device {} was registered twice.",
+        name
+    );
+
+    devices.push(Device { name, open });
+}
+
+/// Removes the device with the given name, so that it can't be opened
+/// anymore.
+///
+/// Files that are already open stay open. Returns false if there was no
+/// device with that name.
+pub fn unregister(name: &str) -> bool {
+    let mut devices = DEVICES.lock();
+
+    match devices.iter().position(|device| device.name == name) {
+        Some(index) => {
+            devices.remove(index);
+            true
+        },
+        None => false
+    }
+}
+
+/// The filesystem that contains the registered devices.
+pub struct DeviceFileSystem;
+
+impl FileSystem for DeviceFileSystem {
+    fn open(&self, name: &str) -> Result<Box<FileHandle>> {
+        DEVICES
+            .lock()
+            .iter()
+            .find(|device| device.name == name)
+            .map(|device| (device.open)())
+            .ok_or(FileError::FileNotFound)
+    }
+}
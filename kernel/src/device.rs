@@ -0,0 +1,140 @@
+//! Provides reference counted handles to devices that can be detached.
+//!
+//! A driver creates a `DeviceHandle` for every device it drives and hands
+//! clones of it to everything that uses the device, such as the open files of
+//! its device node. When the device goes away, the driver detaches it. From
+//! then on all operations through any handle fail with `DeviceRemoved`, while
+//! the device data is only dropped once the last handle is gone.
+
+use alloc::arc::Arc;
+use arch::{self, Architecture};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use file_handle::{FileError, Result};
+
+/// The state shared by all handles to a device.
+struct Device<T> {
+    /// Whether the device is still attached to its driver.
+    attached: AtomicBool,
+    /// The number of operations that are currently running on the device.
+    operations: AtomicUsize,
+    /// The data of the driver for the device.
+    data: T
+}
+
+/// A reference counted handle to a device.
+pub struct DeviceHandle<T> {
+    /// The device.
+    device: Arc<Device<T>>
+}
+
+impl<T> Clone for DeviceHandle<T> {
+    fn clone(&self) -> DeviceHandle<T> {
+        DeviceHandle {
+            device: self.device.clone()
+        }
+    }
+}
+
+impl<T> DeviceHandle<T> {
+    /// Creates the first handle to an attached device with the given data.
+    pub fn new(data: T) -> DeviceHandle<T> {
+        DeviceHandle {
+            device: Arc::new(Device {
+                attached: AtomicBool::new(true),
+                operations: AtomicUsize::new(0),
+                data
+            })
+        }
+    }
+
+    /// Returns true if the device is still attached to its driver.
+    pub fn is_attached(&self) -> bool {
+        self.device.attached.load(Ordering::SeqCst)
+    }
+
+    /// Runs the operation on the device data, if the device is still
+    /// attached.
+    ///
+    /// The driver can't finish detaching the device while the operation runs.
+    pub fn operate<F, R>(&self, operation: F) -> Result<R>
+    where
+        F: FnOnce(&T) -> R
+    {
+        let device = &self.device;
+
+        // The operation is announced before the check, so that a concurrent
+        // `detach` either sees it or this sees the detached device.
+        device.operations.fetch_add(1, Ordering::SeqCst);
+
+        let result = if device.attached.load(Ordering::SeqCst) {
+            Ok(operation(&device.data))
+        } else {
+            Err(FileError::DeviceRemoved)
+        };
+
+        device.operations.fetch_sub(1, Ordering::SeqCst);
+
+        result
+    }
+
+    /// Detaches the device from its driver.
+    ///
+    /// This waits for the running operations to finish. Afterwards no
+    /// operation will access the device data again, so the driver can release
+    /// the hardware. The data itself is dropped with the last handle.
+    pub fn detach(&self) {
+        let device = &self.device;
+
+        device.attached.store(false, Ordering::SeqCst);
+
+        while device.operations.load(Ordering::SeqCst) != 0 {
+            arch::Current::cpu_relax();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts how often it was dropped.
+    struct DropCounter<'a>(&'a AtomicUsize);
+
+    impl<'a> Drop for DropCounter<'a> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Tests that operations fail after the device was detached.
+    #[test]
+    fn test_detach() {
+        let handle = DeviceHandle::new(5);
+        let clone = handle.clone();
+
+        assert_eq!(clone.operate(|&value| value + 1).unwrap(), 6);
+
+        handle.detach();
+
+        assert!(!clone.is_attached());
+        match clone.operate(|&value| value + 1) {
+            Err(FileError::DeviceRemoved) => (),
+            _ => panic!("An operation on a detached device succeeded.")
+        }
+    }
+
+    /// Tests that the data is dropped with the last handle.
+    #[test]
+    fn test_drop_with_last_handle() {
+        let drops = AtomicUsize::new(0);
+        let handle = DeviceHandle::new(DropCounter(&drops));
+        let clone = handle.clone();
+
+        handle.detach();
+        drop(handle);
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+
+        drop(clone);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+}
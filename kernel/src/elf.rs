@@ -1,15 +1,44 @@
 //! Handles ELF files.
 
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::Vec;
+use arch::{self, Architecture};
+use core::cmp::min;
 use core::fmt;
-use core::mem;
-use core::mem::size_of;
-use file_handle::FileHandle;
-use initramfs;
+use core::str;
+use exec_policy;
+use file_handle::{self, FileHandle, MemoryFile};
 use memory::address_space;
 use memory::address_space::{AddressSpace, Segment};
-use memory::{Address, MemoryArea, PhysicalAddress, VirtualAddress, PAGE_SIZE};
-use multitasking::{create_process, ProcessID};
+use memory::{page_cache, Address, MemoryArea, VirtualAddress, PAGE_SIZE};
+use multitasking::limits::Resource;
+use multitasking::{create_process, get_process, Inheritance, Name, ProcessID};
+use vfs;
+
+/// The maximum length of the first line of a script.
+const MAX_INTERPRETER_LINE_LENGTH: usize = 128;
+
+/// The size of the ELF header of a 64 bit file.
+const HEADER_SIZE: usize = 64;
+
+/// The size of a program header entry of a 64 bit file.
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// The size of a section header entry of a 64 bit file.
+const SECTION_HEADER_SIZE: usize = 64;
+
+/// The section type of sections that contain notes.
+const SECTION_TYPE_NOTE: u32 = 7;
+
+/// The note type of a GNU build-id note.
+const NOTE_TYPE_GNU_BUILD_ID: u32 = 3;
+
+/// The largest note section that is searched for a build-id.
+const MAX_NOTE_SECTION_SIZE: usize = 256;
+
+/// The amount of segment data that is copied into the address space at once.
+const LOAD_CHUNK_SIZE: usize = 16 * PAGE_SIZE;
 
 /// Represents an ELF file.
 struct ElfFile {
@@ -20,46 +49,94 @@ struct ElfFile {
 }
 
 impl ElfFile {
-    /// Reads an ELF file from the initramfs.
-    fn from_initramfs(name: &str) -> Result<ElfFile, ElfError> {
-        if let Ok(mut file_handle) = initramfs::open(name) {
-            Header::from_file_handle(&mut *file_handle).and_then(|header| {
-                let file_size = file_handle.len();
-
-                // Check if the program header is fully contained in the file.
-                if file_size
-                    < (header.program_header_offset as u64).saturating_add(
-                        (header.program_header_entry_num as u64)
-                            .saturating_mul(header.program_header_entry_size as u64)
-                    ) {
-                    return Err(ElfError::InvalidFile);
-                }
+    /// Reads an ELF file from the given file handle.
+    fn from_file_handle(mut file_handle: Box<FileHandle>) -> Result<ElfFile, ElfError> {
+        Header::from_file_handle(&mut *file_handle).and_then(|header| {
+            let file_size = file_handle.len();
 
-                // Check that all the program header segments are fully contained in the file.
-                {
-                    let program_header_iterator = ProgramHeaderIterator {
-                        current_header_index: 0,
-                        header_num: header.program_header_entry_num as usize,
-                        header_size: header.program_header_entry_size as usize,
-                        header_offset: header.program_header_offset as u64,
-                        file_handle: &mut *file_handle
-                    };
-
-                    for program_header in program_header_iterator {
-                        if !program_header.is_fully_contained(file_size) {
-                            return Err(ElfError::InvalidFile);
-                        }
+            // Check if the program header is fully contained in the file.
+            if file_size
+                < (header.program_header_offset as u64).saturating_add(
+                    (header.program_header_entry_num as u64)
+                        .saturating_mul(header.program_header_entry_size as u64)
+                )
+            {
+                return Err(ElfError::Truncated {
+                    offset: header.program_header_offset as u64,
+                    length: header.program_header_entry_num as usize
+                        * header.program_header_entry_size as usize
+                });
+            }
+
+            // Check that all the program header segments are fully contained in the file.
+            {
+                let program_header_iterator = ProgramHeaderIterator {
+                    current_header_index: 0,
+                    header_num: header.program_header_entry_num as usize,
+                    header_size: header.program_header_entry_size as usize,
+                    header_offset: header.program_header_offset as u64,
+                    endianness: header.endianness,
+                    file_handle: &mut *file_handle
+                };
+
+                for program_header in program_header_iterator {
+                    let program_header = program_header?;
+
+                    if !program_header.is_fully_contained(file_size) {
+                        return Err(ElfError::Truncated {
+                            offset: program_header.offset as u64,
+                            length: program_header.size_in_file
+                        });
                     }
                 }
+            }
 
-                Ok(ElfFile {
-                    file_handle,
-                    header
-                })
+            Ok(ElfFile {
+                file_handle,
+                header
             })
-        } else {
-            Err(ElfError::FileNotExistant)
+        })
+    }
+
+    /// Searches the note sections of the file for a build-id.
+    ///
+    /// Files without section headers or with malformed ones have no build-id.
+    fn build_id(&mut self) -> Option<BuildId> {
+        let entry_size = self.header.section_header_entry_size as u64;
+
+        if self.header.section_header_offset == 0 || entry_size < SECTION_HEADER_SIZE as u64 {
+            return None;
         }
+
+        for index in 0..self.header.section_header_entry_num as u64 {
+            let offset = self.header
+                .section_header_offset
+                .checked_add(index * entry_size)?;
+            let mut buffer = [0u8; SECTION_HEADER_SIZE];
+
+            self.file_handle.read_at(&mut buffer, offset).ok()?;
+
+            let section = SectionHeader::parse(&buffer, offset, self.header.endianness).ok()?;
+
+            if section.section_type != SECTION_TYPE_NOTE
+                || section.size > MAX_NOTE_SECTION_SIZE as u64
+            {
+                continue;
+            }
+
+            let mut buffer = [0u8; MAX_NOTE_SECTION_SIZE];
+            let notes = &mut buffer[..section.size as usize];
+
+            if self.file_handle.read_at(notes, section.offset).is_err() {
+                continue;
+            }
+
+            if let Some(build_id) = find_build_id(notes, self.header.endianness) {
+                return Some(build_id);
+            }
+        }
+
+        None
     }
 
     /// Returns an iterator for the program header table.
@@ -69,45 +146,82 @@ impl ElfFile {
             header_num: self.header.program_header_entry_num as usize,
             header_size: self.header.program_header_entry_size as usize,
             header_offset: self.header.program_header_offset as u64,
+            endianness: self.header.endianness,
             file_handle: &mut *self.file_handle
         }
     }
 }
 
 /// The possible types of errors that can occur while handling ELF files.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum ElfError {
     /// The file to load doesn't exist.
     FileNotExistant,
-    /// The file is too short or doesn't contain a valid header.
+    /// The file doesn't start with the ELF magic number.
     NotAnElfFile,
+    /// A structure extends past the end of the file.
+    Truncated {
+        /// The offset of the structure within the file.
+        offset: u64,
+        /// The length of the structure.
+        length: usize
+    },
     /// The file is using an unknown ELF version.
-    UnknownVersion,
-    /// The file is of the wrong type.
-    ///
-    /// This could mean any of the following:
-    /// - The file is for a different architecture.
-    /// - The file has the wrong endianness.
-    /// - The file is of the wrong ELF class for this architecture.
-    /// - The file is not executable.
-    WrongType,
+    UnknownVersion(u8),
+    /// The file is of an ELF class other than the one of this architecture.
+    WrongClass(u8),
+    /// The file has a different endianness than this architecture.
+    WrongEndianness(u8),
+    /// The file uses an ABI other than the System V ABI.
+    WrongAbi(u8),
+    /// The file is for a different instruction set.
+    WrongInstructionSet(u16),
+    /// The file is not an executable.
+    NotExecutable(u16),
+    /// The file doesn't have a program header table.
+    MissingProgramHeaders,
+    /// The size of a program header entry is too small.
+    InvalidProgramHeaderSize(u16),
     /// The file is not a valid ELF file.
     InvalidFile,
     /// The segments within the ELF file overlapped.
-    OverlappingSegments
+    OverlappingSegments,
+    /// The segments don't fit within the address space size limit or the
+    /// memory limit of the resource group.
+    AddressSpaceLimitExceeded,
+    /// The first line of a script doesn't name a valid interpreter.
+    InvalidInterpreter,
+    /// A segment is not completely within the userspace area or overlaps the
+    /// user stack area.
+    InvalidSegmentAddress,
+    /// The alignment of a segment is not a power of two or the segment
+    /// address doesn't match its file offset modulo the alignment.
+    InvalidSegmentAlignment,
+    /// The entry point is not within the userspace area.
+    InvalidEntryPoint,
+    /// The file isn't signed correctly and the exec policy is enforced.
+    InvalidSignature
 }
 
 /// Differentiates the endianness (byte order).
-#[repr(u8)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum Endianness {
     /// Least significant byte first.
-    Little = 1,
+    Little,
     /// Most significant byte first.
-    Big = 2
+    Big
 }
 
 impl Endianness {
+    /// Converts the value in the ELF identification to an endianness.
+    fn from_raw(value: u8) -> Option<Endianness> {
+        match value {
+            1 => Some(Endianness::Little),
+            2 => Some(Endianness::Big),
+            _ => None
+        }
+    }
+
     /// Returns true if the endianness is the same as the one of the kernel.
     fn is_native(&self) -> bool {
         if cfg!(target_endian = "little") {
@@ -121,16 +235,24 @@ impl Endianness {
 }
 
 /// Differentiates between 32 and 64 bit executables.
-#[repr(u8)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum ELFClass {
     /// A 32 bit exectuable.
-    Bit32 = 1,
+    Bit32,
     /// A 64 bit executable.
-    Bit64 = 2
+    Bit64
 }
 
 impl ELFClass {
+    /// Converts the value in the ELF identification to a class.
+    fn from_raw(value: u8) -> Option<ELFClass> {
+        match value {
+            1 => Some(ELFClass::Bit32),
+            2 => Some(ELFClass::Bit64),
+            _ => None
+        }
+    }
+
     /// Checks if the bus width for the binary is the same as in the kernel.
     fn is_native(&self) -> bool {
         if cfg!(target_pointer_width = "64") {
@@ -144,40 +266,63 @@ impl ELFClass {
 }
 
 /// The different types of ELF files.
-#[repr(u16)]
 #[derive(Debug, PartialEq, Clone, Copy)]
 #[allow(dead_code)]
 enum ElfType {
     /// No file type is specified.
-    NoFileType = 0,
+    NoFileType,
     /// The ELF file is suitable for further linking.
-    Relocatable = 1,
+    Relocatable,
     /// The ELF file may be executed.
-    Executable = 2,
+    Executable,
     /// The ELF file is a shared object.
-    Shared = 3,
+    Shared,
     /// File contents are unspecified (reserved).
-    Core = 4
+    Core
+}
+
+impl ElfType {
+    /// Converts the raw file type to an ELF type.
+    fn from_raw(value: u16) -> Option<ElfType> {
+        match value {
+            0 => Some(ElfType::NoFileType),
+            1 => Some(ElfType::Relocatable),
+            2 => Some(ElfType::Executable),
+            3 => Some(ElfType::Shared),
+            4 => Some(ElfType::Core),
+            _ => None
+        }
+    }
 }
 
 /// The instruction set of the executable file.
-#[repr(u16)]
 #[derive(Debug, PartialEq, Clone, Copy)]
 #[allow(dead_code)]
 enum InstructionSet {
     /// No instruction set was given.
-    NoSpecific = 0,
+    NoSpecific,
     /// The ELF file uses the x86 instruction set.
     #[allow(non_camel_case_types)]
-    x86 = 3,
+    x86,
     /// The ELF file uses the ARM instruction set.
-    ARM = 0x28,
+    ARM,
     /// The ELF file uses the x86_64 instruction set.
     #[allow(non_camel_case_types)]
-    x86_64 = 0x3e
+    x86_64
 }
 
 impl InstructionSet {
+    /// Converts the raw machine value to an instruction set.
+    fn from_raw(value: u16) -> Option<InstructionSet> {
+        match value {
+            0 => Some(InstructionSet::NoSpecific),
+            3 => Some(InstructionSet::x86),
+            0x28 => Some(InstructionSet::ARM),
+            0x3e => Some(InstructionSet::x86_64),
+            _ => None
+        }
+    }
+
     /// Returns true if the instruction set corresponds to the instruction set
     /// of the machine.
     fn is_native(&self) -> bool {
@@ -189,23 +334,72 @@ impl InstructionSet {
     }
 }
 
+/// Decodes integers at fixed offsets of a byte slice.
+struct ByteReader<'a> {
+    /// The bytes to decode.
+    bytes: &'a [u8],
+    /// The offset of the bytes within the file.
+    file_offset: u64,
+    /// The byte order of the integers.
+    endianness: Endianness
+}
+
+impl<'a> ByteReader<'a> {
+    /// Reads the unsigned integer of `length` bytes at `offset`.
+    fn read(&self, offset: usize, length: usize) -> Result<u64, ElfError> {
+        let file_offset = self.file_offset + offset as u64;
+
+        let bytes = match self.bytes.get(offset..offset + length) {
+            Some(bytes) => bytes,
+            None => return Err(ElfError::Truncated {
+                offset: file_offset,
+                length
+            })
+        };
+
+        let value = match self.endianness {
+            Endianness::Little => bytes
+                .iter()
+                .rev()
+                .fold(0, |value, &byte| (value << 8) | byte as u64),
+            Endianness::Big => bytes
+                .iter()
+                .fold(0, |value, &byte| (value << 8) | byte as u64)
+        };
+
+        Ok(value)
+    }
+
+    /// Reads the byte at `offset`.
+    fn u8(&self, offset: usize) -> Result<u8, ElfError> {
+        self.read(offset, 1).map(|value| value as u8)
+    }
+
+    /// Reads the 16 bit integer at `offset`.
+    fn u16(&self, offset: usize) -> Result<u16, ElfError> {
+        self.read(offset, 2).map(|value| value as u16)
+    }
+
+    /// Reads the 32 bit integer at `offset`.
+    fn u32(&self, offset: usize) -> Result<u32, ElfError> {
+        self.read(offset, 4).map(|value| value as u32)
+    }
+
+    /// Reads the 64 bit integer at `offset`.
+    fn u64(&self, offset: usize) -> Result<u64, ElfError> {
+        self.read(offset, 8)
+    }
+}
+
 /// Represents the header at the beginning of an ELF file.
-#[repr(C, packed)]
+#[derive(Debug)]
 struct Header {
-    /// The magic number: [0x7f, 'E', 'L', 'F'].
-    magic: [u8; 4],
     /// The class of ELF file.
     elf_class: ELFClass,
     /// The endianness of the ELF file.
     endianness: Endianness,
-    /// The ELF version.
-    version: u8,
     /// The ABI of the file.
     abi: u8,
-    /// The ABI version of the file.
-    abi_version: u8,
-    /// Unused padding bytes.
-    padding: [u8; 7],
     /// The type of ELF file.
     elf_type: ElfType,
     /// The instruction set used by the ELF file.
@@ -216,118 +410,143 @@ struct Header {
     program_entry: VirtualAddress,
     /// The offset from file start to the program header.
     program_header_offset: usize,
-    /// The offset from file start to the section header.
-    section_header_offset: usize,
-    /// Architecture specific flags.
-    flags: u32,
-    /// The size of the ELF header.
-    header_size: u16,
     /// The size of a program header entry.
     program_header_entry_size: u16,
     /// The amount of program header entries.
     program_header_entry_num: u16,
+    /// The offset from file start to the section header.
+    section_header_offset: u64,
     /// The size of a section header entry.
     section_header_entry_size: u16,
     /// The amount of section header entries.
-    section_header_entry_num: u16,
-    /// The index of the section header to the name strings.
-    name_string_table_index: u16
-}
-
-impl fmt::Debug for Header {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if !self.is_valid() {
-            write!(f, "Invalid ELF header.")
-        } else {
-            write!(
-                f,
-                "Magic: ['\\x{:x}', '{}', '{}', '{}'],
-{}: {:?}, {}: {:?}, {}: {}, {}: {:?}, {}: {}, {}: {:?}",
-                self.magic[0],
-                self.magic[1] as char,
-                self.magic[2] as char,
-                self.magic[3] as char,
-                "ELFClass",
-                self.elf_class,
-                "Endianness",
-                self.endianness,
-                "ABI",
-                self.abi,
-                "Type",
-                { self.elf_type },
-                "Version",
-                { self.elf_version },
-                "Entry address",
-                { self.program_entry }
-            )
-        }
-    }
+    section_header_entry_num: u16
 }
 
 impl Header {
     /// Creates a ELF header from the file handle.
     fn from_file_handle(file_handle: &mut FileHandle) -> Result<Header, ElfError> {
-        let file_size = file_handle.len();
+        let mut buffer = [0u8; HEADER_SIZE];
+        let length = min(file_handle.len(), HEADER_SIZE as u64) as usize;
 
-        if file_size < size_of::<Header>() as u64 {
+        if file_handle.read_at(&mut buffer[..length], 0).is_err() {
+            return Err(ElfError::InvalidFile);
+        }
+
+        Header::parse(&buffer[..length])
+    }
+
+    /// Parses the ELF header at the start of the given bytes.
+    fn parse(bytes: &[u8]) -> Result<Header, ElfError> {
+        if !bytes.starts_with(&[0x7f, b'E', b'L', b'F']) {
             return Err(ElfError::NotAnElfFile);
         }
 
-        let header: Header = unsafe {
-            let mut header_buffer: [u8; size_of::<Header>()] = mem::uninitialized();
+        // The identification bytes are independent of the endianness.
+        let identification = ByteReader {
+            bytes,
+            file_offset: 0,
+            endianness: Endianness::Little
+        };
 
-            file_handle.read(&mut header_buffer).unwrap();
+        let raw_class = identification.u8(4)?;
+        let elf_class = match ELFClass::from_raw(raw_class) {
+            Some(ref class) if class.is_native() => *class,
+            _ => return Err(ElfError::WrongClass(raw_class))
+        };
 
-            mem::transmute(header_buffer)
+        let raw_endianness = identification.u8(5)?;
+        let endianness = match Endianness::from_raw(raw_endianness) {
+            Some(ref endianness) if endianness.is_native() => *endianness,
+            _ => return Err(ElfError::WrongEndianness(raw_endianness))
         };
 
-        if !header.is_valid() {
-            return Err(ElfError::NotAnElfFile);
+        let version = identification.u8(6)?;
+        if version != 1 {
+            return Err(ElfError::UnknownVersion(version));
         }
 
-        if header.version != 1 {
-            return Err(ElfError::UnknownVersion);
+        let abi = identification.u8(7)?;
+        if abi != 0 || identification.u8(8)? != 0 {
+            return Err(ElfError::WrongAbi(abi));
         }
 
-        if !header.is_executable() {
-            return Err(ElfError::WrongType);
-        }
+        let reader = ByteReader {
+            bytes,
+            file_offset: 0,
+            endianness
+        };
 
-        Ok(header)
-    }
+        let raw_type = reader.u16(16)?;
+        let elf_type = match ElfType::from_raw(raw_type) {
+            Some(ElfType::Executable) => ElfType::Executable,
+            _ => return Err(ElfError::NotExecutable(raw_type))
+        };
 
-    /// Returns true if the header is valid and can be understood by the kernel.
-    fn is_valid(&self) -> bool {
-        self.magic == [0x7f, 'E' as u8, 'L' as u8, 'F' as u8]
-    }
+        let raw_instruction_set = reader.u16(18)?;
+        let instruction_set = match InstructionSet::from_raw(raw_instruction_set) {
+            Some(ref instruction_set) if instruction_set.is_native() => *instruction_set,
+            _ => return Err(ElfError::WrongInstructionSet(raw_instruction_set))
+        };
+
+        let program_header_offset = reader.u64(32)? as usize;
+        if program_header_offset == 0 {
+            return Err(ElfError::MissingProgramHeaders);
+        }
 
-    /// Returns true if the file can be executed.
-    fn is_executable(&self) -> bool {
-        self.endianness.is_native()
-            && { self.instruction_set }.is_native()
-            && self.abi == 0
-            && self.abi_version == 0
-            && { self.elf_type } == ElfType::Executable
-            && self.program_header_offset != 0
-            && self.elf_class.is_native()
+        let program_header_entry_size = reader.u16(54)?;
+        if (program_header_entry_size as usize) < PROGRAM_HEADER_SIZE {
+            return Err(ElfError::InvalidProgramHeaderSize(
+                program_header_entry_size
+            ));
+        }
+
+        Ok(Header {
+            elf_class,
+            endianness,
+            abi,
+            elf_type,
+            instruction_set,
+            elf_version: reader.u32(20)?,
+            program_entry: VirtualAddress::from_usize(reader.u64(24)? as usize),
+            program_header_offset,
+            program_header_entry_size,
+            program_header_entry_num: reader.u16(56)?,
+            section_header_offset: reader.u64(40)?,
+            section_header_entry_size: reader.u16(58)?,
+            section_header_entry_num: reader.u16(60)?
+        })
     }
 }
 
 /// Represents the different segment types in the program header.
-#[repr(u32)]
 #[derive(Debug, PartialEq)]
-#[allow(dead_code)]
 enum SegmentType {
     /// An unused entry.
-    Null = 0,
+    Null,
     /// A loadable segment.
-    Load = 1,
+    Load,
     /// Dynamic linking tables.
-    Dynamic = 2,
+    Dynamic,
     /// The path to a program interpreter.
-    Interpreter = 3,
+    Interpreter,
     /// Note sections.
-    Note = 4
+    Note,
+    /// A segment type that the kernel doesn't handle.
+    Other(u32)
+}
+
+impl SegmentType {
+    /// Converts the raw segment type to a segment type.
+    fn from_raw(value: u32) -> SegmentType {
+        match value {
+            0 => SegmentType::Null,
+            1 => SegmentType::Load,
+            2 => SegmentType::Dynamic,
+            3 => SegmentType::Interpreter,
+            4 => SegmentType::Note,
+            other => SegmentType::Other(other)
+        }
+    }
 }
 
 bitflags! {
@@ -343,7 +562,7 @@ bitflags! {
 }
 
 /// Represents the program header of an ELF file.
-#[repr(C, packed)]
+#[derive(Debug)]
 struct ProgramHeader {
     /// The type of the segment.
     segment_type: SegmentType,
@@ -353,11 +572,6 @@ struct ProgramHeader {
     offset: usize,
     /// The virtual address at which the segment should be mapped.
     virtual_address: VirtualAddress,
-    /// The physical address at which the segment should be mapped.
-    ///
-    /// # Note
-    /// Reserved for systems with physical addressing.
-    physical_address: PhysicalAddress,
     /// The size of the segment within the file.
     size_in_file: usize,
     /// The size of the segment within memory.
@@ -367,10 +581,209 @@ struct ProgramHeader {
 }
 
 impl ProgramHeader {
+    /// Returns the range of the segment that consists of whole pages which
+    /// are completely backed by the file.
+    ///
+    /// The range is given as offsets from the start of the segment. Only
+    /// these pages can be shared with other processes, because all their
+    /// content is part of this segment.
+    fn cacheable_pages(&self) -> (usize, usize) {
+        let start_address = self.virtual_address.as_usize();
+
+        // The pages must start at the same offsets in the file and in memory.
+        if start_address % PAGE_SIZE != self.offset % PAGE_SIZE {
+            return (0, 0);
+        }
+
+        let first_page = (start_address + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let end = (start_address + self.size_in_file) / PAGE_SIZE * PAGE_SIZE;
+
+        if end <= first_page {
+            (0, 0)
+        } else {
+            (first_page - start_address, end - start_address)
+        }
+    }
+
+    /// Parses the program header at the start of the given bytes.
+    ///
+    /// `file_offset` is the offset of the bytes within the file.
+    fn parse(
+        bytes: &[u8],
+        file_offset: u64,
+        endianness: Endianness
+    ) -> Result<ProgramHeader, ElfError> {
+        let reader = ByteReader {
+            bytes,
+            file_offset,
+            endianness
+        };
+
+        // The physical address at offset 24 is only relevant for systems with
+        // physical addressing.
+        Ok(ProgramHeader {
+            segment_type: SegmentType::from_raw(reader.u32(0)?),
+            flags: SegmentFlags::from_bits_truncate(reader.u32(4)?),
+            offset: reader.u64(8)? as usize,
+            virtual_address: VirtualAddress::from_usize(reader.u64(16)? as usize),
+            size_in_file: reader.u64(32)? as usize,
+            size_in_memory: reader.u64(40)? as usize,
+            align: reader.u64(48)? as usize
+        })
+    }
+
     fn is_fully_contained(&self, file_size: u64) -> bool {
         file_size >= (self.offset as u64).saturating_add(self.size_in_file as u64)
             || self.size_in_file == 0
     }
+
+    /// Checks that the segment can safely be loaded into a user address space.
+    ///
+    /// `is_userspace` decides whether an address belongs to userspace and
+    /// `stack_area` is the area reserved for user stacks.
+    fn validate<F>(&self, is_userspace: F, stack_area: MemoryArea<VirtualAddress>) -> Result<(), ElfError>
+    where
+        F: Fn(VirtualAddress) -> bool
+    {
+        let virtual_address = self.virtual_address;
+        let size_in_memory = self.size_in_memory;
+        let offset = self.offset;
+        let align = self.align;
+
+        if self.size_in_file > size_in_memory {
+            return Err(ElfError::InvalidFile);
+        }
+
+        if align > 1
+            && (!align.is_power_of_two() || virtual_address.as_usize() % align != offset % align)
+        {
+            return Err(ElfError::InvalidSegmentAlignment);
+        }
+
+        if size_in_memory == 0 {
+            return Ok(());
+        }
+
+        let last_address = match virtual_address.checked_add(size_in_memory - 1) {
+            Some(address) => address,
+            None => return Err(ElfError::InvalidSegmentAddress)
+        };
+
+        if !is_userspace(virtual_address)
+            || !is_userspace(last_address)
+            || MemoryArea::new(virtual_address, size_in_memory).overlaps_with(stack_area)
+        {
+            return Err(ElfError::InvalidSegmentAddress);
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the entry point lies in userspace outside of the stack area.
+fn validate_entry_point<F>(
+    entry_point: VirtualAddress,
+    is_userspace: F,
+    stack_area: MemoryArea<VirtualAddress>
+) -> Result<(), ElfError>
+where
+    F: Fn(VirtualAddress) -> bool
+{
+    if is_userspace(entry_point) && !MemoryArea::new(entry_point, 1).overlaps_with(stack_area) {
+        Ok(())
+    } else {
+        Err(ElfError::InvalidEntryPoint)
+    }
+}
+
+/// The parts of a section header that the kernel uses.
+#[derive(Debug)]
+struct SectionHeader {
+    /// The type of the section.
+    section_type: u32,
+    /// The offset of the section contents from the beginning of the file.
+    offset: u64,
+    /// The size of the section contents in the file.
+    size: u64
+}
+
+impl SectionHeader {
+    /// Parses the section header at the start of the given bytes.
+    ///
+    /// `file_offset` is the offset of the bytes within the file.
+    fn parse(
+        bytes: &[u8],
+        file_offset: u64,
+        endianness: Endianness
+    ) -> Result<SectionHeader, ElfError> {
+        let reader = ByteReader {
+            bytes,
+            file_offset,
+            endianness
+        };
+
+        Ok(SectionHeader {
+            section_type: reader.u32(4)?,
+            offset: reader.u64(24)?,
+            size: reader.u64(32)?
+        })
+    }
+}
+
+/// The build-id that identifies the exact build of an executable.
+#[derive(Clone, PartialEq)]
+pub struct BuildId(Vec<u8>);
+
+impl fmt::Display for BuildId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BuildId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BuildId({})", self)
+    }
+}
+
+/// Returns the build-id in the given note section contents.
+fn find_build_id(notes: &[u8], endianness: Endianness) -> Option<BuildId> {
+    /// Rounds the size up to the alignment of note entries.
+    fn align(size: usize) -> usize {
+        (size + 3) & !3
+    }
+
+    let reader = ByteReader {
+        bytes: notes,
+        file_offset: 0,
+        endianness
+    };
+    let mut offset = 0;
+
+    while offset < notes.len() {
+        let name_size = reader.u32(offset).ok()? as usize;
+        let description_size = reader.u32(offset + 4).ok()? as usize;
+        let note_type = reader.u32(offset + 8).ok()?;
+
+        let name_start = offset + 12;
+        let description_start = name_start + align(name_size);
+
+        let name = notes.get(name_start..name_start + name_size)?;
+        let description =
+            notes.get(description_start..description_start + description_size)?;
+
+        if note_type == NOTE_TYPE_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(BuildId(description.to_vec()));
+        }
+
+        offset = description_start + align(description_size);
+    }
+
+    None
 }
 
 /// Provides an iterator for the program headers.
@@ -383,54 +796,200 @@ struct ProgramHeaderIterator<'a> {
     header_size: usize,
     /// The offset of the program header table in the file.
     header_offset: u64,
+    /// The endianness of the ELF file.
+    endianness: Endianness,
     /// The handle to the ELF file.
     file_handle: &'a mut FileHandle
 }
 
 impl<'a> Iterator for ProgramHeaderIterator<'a> {
-    type Item = ProgramHeader;
+    type Item = Result<ProgramHeader, ElfError>;
 
-    fn next(&mut self) -> Option<ProgramHeader> {
+    fn next(&mut self) -> Option<Result<ProgramHeader, ElfError>> {
         if self.current_header_index >= self.header_num {
             None
         } else {
-            let program_header: ProgramHeader = unsafe {
-                let mut program_header_buffer: [u8; size_of::<ProgramHeader>()] =
-                    mem::uninitialized();
-
-                self.file_handle
-                    .read_at(
-                        &mut program_header_buffer,
-                        self.header_offset
-                            + self.header_size as u64 * self.current_header_index as u64
-                    )
-                    .unwrap();
-
-                mem::transmute(program_header_buffer)
-            };
+            let offset =
+                self.header_offset + self.header_size as u64 * self.current_header_index as u64;
+            let mut buffer = [0u8; PROGRAM_HEADER_SIZE];
 
             self.current_header_index += 1;
 
-            Some(program_header)
+            if self.file_handle.read_at(&mut buffer, offset).is_err() {
+                return Some(Err(ElfError::Truncated {
+                    offset,
+                    length: PROGRAM_HEADER_SIZE
+                }));
+            }
+
+            Some(ProgramHeader::parse(&buffer, offset, self.endianness))
         }
     }
 }
 
-/// Creates a new process from the given file on the initramfs.
-pub fn process_from_initramfs_file(name: &str) -> Result<ProcessID, ElfError> {
-    ElfFile::from_initramfs(name).and_then(|file| process_from_elf_file(file))
+/// The interpreter named in the first line of a script.
+#[derive(Debug)]
+pub struct Interpreter {
+    /// The path of the interpreter.
+    pub path: String,
+    /// The optional argument that is passed to the interpreter.
+    pub argument: Option<String>
+}
+
+/// Returns the interpreter if the given executable file is a script.
+///
+/// Scripts start with `#!`, followed by the path of the interpreter and an
+/// optional argument on the first line.
+pub fn script_interpreter(name: &str) -> Result<Option<Interpreter>, ElfError> {
+    let mut file_handle = open_executable(name).map_err(|_| ElfError::FileNotExistant)?;
+
+    let mut buffer = [0u8; MAX_INTERPRETER_LINE_LENGTH];
+    let length = min(file_handle.len(), buffer.len() as u64) as usize;
+    let line = &mut buffer[..length];
+
+    if file_handle.read(line).is_err() {
+        return Err(ElfError::InvalidFile);
+    }
+
+    if !line.starts_with(b"#!") {
+        return Ok(None);
+    }
+
+    let line = match line.iter().position(|&byte| byte == b'\n') {
+        Some(end) => &line[2..end],
+        None => return Err(ElfError::InvalidInterpreter)
+    };
+
+    let line = str::from_utf8(line).map_err(|_| ElfError::InvalidInterpreter)?;
+    let line = line.trim();
+
+    let (path, argument) = match line.find(|c: char| c.is_whitespace()) {
+        Some(index) => (&line[..index], Some(line[index..].trim())),
+        None => (line, None)
+    };
+
+    if path.is_empty() {
+        return Err(ElfError::InvalidInterpreter);
+    }
+
+    Ok(Some(Interpreter {
+        path: String::from(path),
+        argument: argument.map(String::from)
+    }))
+}
+
+/// Opens the executable file with the given name.
+fn open_executable(name: &str) -> file_handle::Result<Box<FileHandle>> {
+    vfs::open(name)
 }
 
-/// Creates a new process from the given ELF file handle.
-fn process_from_elf_file(mut file: ElfFile) -> Result<ProcessID, ElfError> {
-    let mut address_space = AddressSpace::new();
+/// Reads the whole executable file with the given name.
+fn read_executable(name: &str) -> Result<Vec<u8>, ElfError> {
+    let mut file_handle = open_executable(name).map_err(|_| ElfError::FileNotExistant)?;
+    let mut content = Vec::new();
+    content.resize(file_handle.len() as usize, 0);
+
+    file_handle
+        .read_at(&mut content, 0)
+        .map_err(|_| ElfError::InvalidFile)?;
+
+    Ok(content)
+}
+
+/// Creates a new process from the executable file at the given path.
+///
+/// The arguments and environment variables are passed to the new process,
+/// which starts with the given inheritance. Unless `allow_huge_pages` is set,
+/// the process never uses huge pages.
+pub fn process_from_initramfs_file(
+    name: &str,
+    arguments: &[&str],
+    environment: &[&str],
+    allow_huge_pages: bool,
+    inheritance: Inheritance
+) -> Result<ProcessID, ElfError> {
+    // The file is read once, so that the verified content is the one that
+    // is loaded.
+    let path = vfs::normalize(name).ok_or(ElfError::FileNotExistant)?;
+    let content = read_executable(&path)?;
+    exec_policy::check(&path, &content)?;
+
+    let mut file = ElfFile::from_file_handle(Box::new(MemoryFile::new(content)))?;
+    let build_id = file.build_id();
+
+    // Only files that can't change are cached.
+    let cache_key = if vfs::is_immutable(&path) {
+        Some(&path[..])
+    } else {
+        None
+    };
+
+    let process_id = process_from_elf_file(
+        file,
+        cache_key,
+        arguments,
+        environment,
+        Name::from_path(name),
+        allow_huge_pages,
+        inheritance
+    )?;
+
+    match build_id {
+        Some(ref build_id) => info!("Started {} as {:?} (build-id {}).", name, process_id, build_id),
+        None => info!("Started {} as {:?} (no build-id).", name, process_id)
+    }
+
+    if let Some(mut pcb) = get_process(process_id) {
+        pcb.build_id = build_id;
+    }
+
+    Ok(process_id)
+}
+
+/// Creates a new process with the given name from the given ELF file handle.
+///
+/// If a cache key is given, the read-only pages of the file are shared
+/// through the page cache under that key.
+fn process_from_elf_file(
+    mut file: ElfFile,
+    cache_key: Option<&str>,
+    arguments: &[&str],
+    environment: &[&str],
+    name: Name,
+    allow_huge_pages: bool,
+    inheritance: Inheritance
+) -> Result<ProcessID, ElfError> {
+    let stack_area = AddressSpace::user_stack_area();
+
+    validate_entry_point(
+        file.header.program_entry,
+        arch::Current::is_userspace_address,
+        stack_area
+    )?;
+
+    let size_limit = inheritance.limits.get(Resource::AddressSpaceSize).soft;
+    let mut address_space = AddressSpace::new(size_limit, inheritance.resource_group);
+    if !allow_huge_pages {
+        address_space.set_huge_pages(false);
+    }
+
+    let mut load_buffer = Vec::new();
+    load_buffer.resize(LOAD_CHUNK_SIZE, 0u8);
 
     {
         let mut iterator = file.program_headers();
 
         // For each segment.
         while let Some(program_header) = iterator.next() {
-            if { program_header.segment_type } != SegmentType::Load {
+            let program_header = program_header?;
+
+            if program_header.segment_type != SegmentType::Load {
+                continue;
+            }
+
+            program_header.validate(arch::Current::is_userspace_address, stack_area)?;
+
+            if program_header.size_in_memory == 0 {
                 continue;
             }
 
@@ -459,48 +1018,84 @@ fn process_from_elf_file(mut file: ElfFile) -> Result<ProcessID, ElfError> {
                 address_space::SegmentType::FromFile
             );
 
+            if !address_space.has_room_for(program_header.size_in_memory) {
+                return Err(ElfError::AddressSpaceLimitExceeded);
+            }
+
             if !address_space.add_segment(segment) {
                 return Err(ElfError::OverlappingSegments);
             }
 
-            // Map all the segments (page by page).
-            let pages_in_file = if program_header.size_in_file != 0 {
-                (program_header.size_in_file - 1) / PAGE_SIZE + 1
-            } else {
-                0
+            let (cached_start, cached_end) = match cache_key {
+                Some(_) if !flags.contains(::memory::WRITABLE) => program_header.cacheable_pages(),
+                _ => (0, 0)
             };
-            for i in 0..pages_in_file {
-                let mut segment_data_buffer: [u8; ::memory::PAGE_SIZE] =
-                    unsafe { mem::uninitialized() };
 
-                let segment_data = if program_header.size_in_file < (i + 1) * PAGE_SIZE {
-                    &mut segment_data_buffer[0..program_header.size_in_file % PAGE_SIZE]
+            // Copy the file contents in chunks of multiple pages, so the page
+            // table only has to be prepared once per chunk.
+            let mut loaded = 0;
+            while loaded < program_header.size_in_file {
+                let is_cached = loaded >= cached_start && loaded < cached_end;
+                let file_offset = (program_header.offset + loaded) as u64;
+                let page_address = program_header.virtual_address + loaded;
+
+                if is_cached {
+                    let cache_key = cache_key.unwrap();
+
+                    if let Some(frame) = page_cache::get(cache_key, file_offset) {
+                        address_space
+                            .map_page_to(page_address, frame)
+                            .map_err(|_| ElfError::InvalidSegmentAddress)?;
+
+                        loaded += PAGE_SIZE;
+                        continue;
+                    }
+                }
+
+                let length = if is_cached {
+                    PAGE_SIZE
+                } else if loaded < cached_start {
+                    min(LOAD_CHUNK_SIZE, cached_start - loaded)
                 } else {
-                    &mut segment_data_buffer[..]
+                    min(LOAD_CHUNK_SIZE, program_header.size_in_file - loaded)
                 };
+                let segment_data = &mut load_buffer[..length];
 
-                let read_result = iterator
-                    .file_handle
-                    .read_at(segment_data, (program_header.offset + i * PAGE_SIZE) as u64);
+                let read_result = iterator.file_handle.read_at(segment_data, file_offset);
 
                 if read_result.is_err() {
                     return Err(ElfError::InvalidFile);
                 }
 
-                address_space
-                    .write_to(segment_data, program_header.virtual_address + i * PAGE_SIZE);
+                address_space.write_to(segment_data, page_address);
+
+                if is_cached {
+                    let frames = address_space
+                        .take_frame_references(MemoryArea::new(page_address, PAGE_SIZE), false)
+                        .expect("The just loaded page isn't mapped.");
+
+                    page_cache::insert(cache_key.unwrap(), file_offset, frames[0]);
+                }
+
+                loaded += length;
             }
 
-            let last_mapped_page = (program_header.virtual_address + program_header.size_in_file
-                - 1)
-                .as_usize() / PAGE_SIZE + 1;
-            let last_page_to_map = (program_header.virtual_address + program_header.size_in_memory
-                - 1)
-                .as_usize() / PAGE_SIZE + 1;
-            let page_aligned_start_address = program_header.virtual_address.page_align_down();
+            // Map the pages that are not backed by the file at once.
+            let first_page_to_map = if program_header.size_in_file != 0 {
+                (program_header.virtual_address + program_header.size_in_file - 1).page_num() + 1
+            } else {
+                program_header.virtual_address.page_num()
+            };
+            let last_page_to_map =
+                (program_header.virtual_address + program_header.size_in_memory - 1).page_num();
 
-            for i in 0..last_page_to_map - last_mapped_page {
-                address_space.map_page(page_aligned_start_address + (i + 1) * PAGE_SIZE);
+            if last_page_to_map >= first_page_to_map {
+                address_space
+                    .map_range(
+                        VirtualAddress::from_page_num(first_page_to_map),
+                        last_page_to_map - first_page_to_map + 1
+                    )
+                    .map_err(|_| ElfError::InvalidSegmentAddress)?;
             }
 
             if program_header.size_in_file < program_header.size_in_memory {
@@ -513,5 +1108,372 @@ fn process_from_elf_file(mut file: ElfFile) -> Result<ProcessID, ElfError> {
         }
     }
 
-    Ok(create_process(address_space, file.header.program_entry))
+    if !address_space.has_room_for_user_stack() {
+        return Err(ElfError::AddressSpaceLimitExceeded);
+    }
+
+    create_process(
+        address_space,
+        file.header.program_entry,
+        arguments,
+        environment,
+        name,
+        inheritance
+    ).ok_or(ElfError::AddressSpaceLimitExceeded)
+}
+
+/// Tests for the parsing and validation of ELF files.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The highest userspace address used in the tests.
+    const USERSPACE_MAX: usize = 0x00007fffffffffff;
+
+    /// The user stack area used in the tests.
+    const STACK_AREA: MemoryArea<VirtualAddress> =
+        MemoryArea::new(VirtualAddress::from_const(0x00007f8000000000), 0x8000000000);
+
+    /// A valid header of a little endian x86_64 executable.
+    const HEADER: [u8; HEADER_SIZE] = [
+        0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, // identification
+        2, 0, // type
+        0x3e, 0, // instruction set
+        1, 0, 0, 0, // version
+        0x78, 0x56, 0x34, 0x12, 0, 0, 0, 0, // entry point
+        0x40, 0, 0, 0, 0, 0, 0, 0, // program header offset
+        0, 0, 0, 0, 0, 0, 0, 0, // section header offset
+        0, 0, 0, 0, // flags
+        0x40, 0, // header size
+        0x38, 0, // program header entry size
+        3, 0, // program header entry amount
+        0x40, 0, // section header entry size
+        0, 0, // section header entry amount
+        0, 0 // name string table index
+    ];
+
+    /// A loadable, readable and executable program header.
+    const PROGRAM_HEADER: [u8; PROGRAM_HEADER_SIZE] = [
+        1, 0, 0, 0, // type
+        5, 0, 0, 0, // flags
+        0, 0x10, 0, 0, 0, 0, 0, 0, // offset
+        0, 0x10, 0x40, 0, 0, 0, 0, 0, // virtual address
+        0, 0x10, 0x40, 0, 0, 0, 0, 0, // physical address
+        0x34, 0x12, 0, 0, 0, 0, 0, 0, // size in file
+        0, 0x20, 0, 0, 0, 0, 0, 0, // size in memory
+        0, 0x10, 0, 0, 0, 0, 0, 0 // alignment
+    ];
+
+    /// Returns the test header with the byte at `index` replaced.
+    fn header_with(index: usize, value: u8) -> [u8; HEADER_SIZE] {
+        let mut header = HEADER;
+        header[index] = value;
+        header
+    }
+
+    /// Decides whether the address is in userspace for the tests.
+    fn is_userspace(address: VirtualAddress) -> bool {
+        address.as_usize() <= USERSPACE_MAX
+    }
+
+    /// Creates a loadable program header with the given properties.
+    fn segment(virtual_address: usize, size: usize, offset: usize, align: usize) -> ProgramHeader {
+        ProgramHeader {
+            segment_type: SegmentType::Load,
+            flags: READABLE,
+            offset,
+            virtual_address: VirtualAddress::from_usize(virtual_address),
+            size_in_file: size,
+            size_in_memory: size,
+            align
+        }
+    }
+
+    /// Tests that a valid header is parsed correctly.
+    #[test]
+    fn test_parse_header() {
+        let header = Header::parse(&HEADER).unwrap();
+
+        assert_eq!(header.elf_class, ELFClass::Bit64);
+        assert_eq!(header.endianness, Endianness::Little);
+        assert_eq!(header.elf_type, ElfType::Executable);
+        assert_eq!(header.instruction_set, InstructionSet::x86_64);
+        assert_eq!(header.program_entry, VirtualAddress::from_usize(0x12345678));
+        assert_eq!(header.program_header_offset, 0x40);
+        assert_eq!(header.program_header_entry_size, 0x38);
+        assert_eq!(header.program_header_entry_num, 3);
+    }
+
+    /// Tests that files without the magic number are rejected.
+    #[test]
+    fn test_parse_header_without_magic() {
+        assert_eq!(Header::parse(&[]).unwrap_err(), ElfError::NotAnElfFile);
+        assert_eq!(Header::parse(b"#!/bin/sh\n").unwrap_err(), ElfError::NotAnElfFile);
+        assert_eq!(
+            Header::parse(&header_with(1, b'e')).unwrap_err(),
+            ElfError::NotAnElfFile
+        );
+    }
+
+    /// Tests that short reads are reported instead of reading past the end.
+    #[test]
+    fn test_parse_truncated_header() {
+        assert_eq!(
+            Header::parse(&HEADER[..4]).unwrap_err(),
+            ElfError::Truncated {
+                offset: 4,
+                length: 1
+            }
+        );
+        assert_eq!(
+            Header::parse(&HEADER[..36]).unwrap_err(),
+            ElfError::Truncated {
+                offset: 32,
+                length: 8
+            }
+        );
+        assert_eq!(
+            Header::parse(&HEADER[..HEADER_SIZE - 7]).unwrap_err(),
+            ElfError::Truncated {
+                offset: 56,
+                length: 2
+            }
+        );
+    }
+
+    /// Tests that headers for other machines are rejected.
+    #[test]
+    fn test_parse_foreign_header() {
+        assert_eq!(
+            Header::parse(&header_with(4, 1)).unwrap_err(),
+            ElfError::WrongClass(1)
+        );
+        assert_eq!(
+            Header::parse(&header_with(4, 7)).unwrap_err(),
+            ElfError::WrongClass(7)
+        );
+        assert_eq!(
+            Header::parse(&header_with(5, 2)).unwrap_err(),
+            ElfError::WrongEndianness(2)
+        );
+        assert_eq!(
+            Header::parse(&header_with(6, 2)).unwrap_err(),
+            ElfError::UnknownVersion(2)
+        );
+        assert_eq!(
+            Header::parse(&header_with(7, 3)).unwrap_err(),
+            ElfError::WrongAbi(3)
+        );
+        assert_eq!(
+            Header::parse(&header_with(18, 0x28)).unwrap_err(),
+            ElfError::WrongInstructionSet(0x28)
+        );
+        assert_eq!(
+            Header::parse(&header_with(19, 0xff)).unwrap_err(),
+            ElfError::WrongInstructionSet(0xff3e)
+        );
+    }
+
+    /// Tests that headers that don't describe an executable are rejected.
+    #[test]
+    fn test_parse_non_executable_header() {
+        assert_eq!(
+            Header::parse(&header_with(16, 3)).unwrap_err(),
+            ElfError::NotExecutable(3)
+        );
+        assert_eq!(
+            Header::parse(&header_with(32, 0)).unwrap_err(),
+            ElfError::MissingProgramHeaders
+        );
+        assert_eq!(
+            Header::parse(&header_with(54, 0x20)).unwrap_err(),
+            ElfError::InvalidProgramHeaderSize(0x20)
+        );
+    }
+
+    /// Tests that a program header is parsed correctly.
+    #[test]
+    fn test_parse_program_header() {
+        let header = ProgramHeader::parse(&PROGRAM_HEADER, 0x40, Endianness::Little).unwrap();
+
+        assert_eq!(header.segment_type, SegmentType::Load);
+        assert_eq!(header.flags, READABLE | EXECUTABLE);
+        assert_eq!(header.offset, 0x1000);
+        assert_eq!(header.virtual_address, VirtualAddress::from_usize(0x401000));
+        assert_eq!(header.size_in_file, 0x1234);
+        assert_eq!(header.size_in_memory, 0x2000);
+        assert_eq!(header.align, 0x1000);
+    }
+
+    /// Tests that program headers are decoded with the given endianness.
+    #[test]
+    fn test_parse_big_endian_program_header() {
+        let header = ProgramHeader::parse(&PROGRAM_HEADER, 0, Endianness::Big).unwrap();
+
+        assert_eq!(header.segment_type, SegmentType::Other(0x01000000));
+        assert_eq!(header.offset, 0x0010000000000000);
+    }
+
+    /// Tests that unknown segment types and flags are preserved or ignored.
+    #[test]
+    fn test_parse_unknown_program_header_values() {
+        let mut bytes = PROGRAM_HEADER;
+        bytes[..8].copy_from_slice(&[0x51, 0xe5, 0x74, 0x64, 0xff, 0, 0, 0]);
+
+        let header = ProgramHeader::parse(&bytes, 0, Endianness::Little).unwrap();
+
+        assert_eq!(header.segment_type, SegmentType::Other(0x6474e551));
+        assert_eq!(header.flags, READABLE | WRITABLE | EXECUTABLE);
+    }
+
+    /// Tests that truncated program headers report the file offset.
+    #[test]
+    fn test_parse_truncated_program_header() {
+        assert_eq!(
+            ProgramHeader::parse(&PROGRAM_HEADER[..50], 0x40, Endianness::Little).unwrap_err(),
+            ElfError::Truncated {
+                offset: 0x40 + 48,
+                length: 8
+            }
+        );
+    }
+
+    /// Tests that section headers are parsed correctly.
+    #[test]
+    fn test_parse_section_header() {
+        let mut bytes = [0u8; SECTION_HEADER_SIZE];
+        bytes[4] = 7;
+        bytes[24..26].copy_from_slice(&[0x70, 0x02]);
+        bytes[32] = 0x24;
+
+        let header = SectionHeader::parse(&bytes, 0, Endianness::Little).unwrap();
+
+        assert_eq!(header.section_type, SECTION_TYPE_NOTE);
+        assert_eq!(header.offset, 0x270);
+        assert_eq!(header.size, 0x24);
+    }
+
+    /// Tests that the build-id is found among other notes.
+    #[test]
+    fn test_find_build_id() {
+        let notes = [
+            4, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0, b'G', b'N', b'U', 0, 1, 2, 3, 4, // ABI tag
+            4, 0, 0, 0, 5, 0, 0, 0, 3, 0, 0, 0, b'G', b'N', b'U', 0, // build-id header
+            0xde, 0xad, 0xbe, 0xef, 0x42, 0, 0, 0 // build-id and padding
+        ];
+
+        let build_id = find_build_id(&notes, Endianness::Little).unwrap();
+
+        assert_eq!(&build_id.0[..], &[0xde, 0xad, 0xbe, 0xef, 0x42][..]);
+    }
+
+    /// Tests that notes of other vendors and truncated notes are ignored.
+    #[test]
+    fn test_find_build_id_in_invalid_notes() {
+        let foreign = [
+            4, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, b'X', b'Y', b'Z', 0, 1, 2, 0, 0
+        ];
+        let truncated = [4, 0, 0, 0, 20, 0, 0, 0, 3, 0, 0, 0, b'G', b'N', b'U', 0, 1, 2];
+
+        assert_eq!(find_build_id(&foreign, Endianness::Little), None);
+        assert_eq!(find_build_id(&truncated, Endianness::Little), None);
+        assert_eq!(find_build_id(&[1, 2], Endianness::Little), None);
+    }
+
+    /// Tests that ordinary segments are accepted.
+    #[test]
+    fn test_valid_segment() {
+        assert!(segment(0x400000, 0x2000, 0, 0x1000).validate(is_userspace, STACK_AREA).is_ok());
+        assert!(segment(0x401080, 0x10, 0x1080, 0x1000).validate(is_userspace, STACK_AREA).is_ok());
+        assert!(segment(0x400000, 0, 0, 0).validate(is_userspace, STACK_AREA).is_ok());
+    }
+
+    /// Tests that segments in kernel space are rejected.
+    #[test]
+    fn test_kernel_space_segment() {
+        assert_eq!(
+            segment(0xffff800000000000, 0x1000, 0, 0x1000).validate(is_userspace, STACK_AREA),
+            Err(ElfError::InvalidSegmentAddress)
+        );
+    }
+
+    /// Tests that segments reaching from userspace into kernel space are
+    /// rejected.
+    #[test]
+    fn test_segment_crossing_into_kernel_space() {
+        assert_eq!(
+            segment(0x00007fffffff0000, 0x20000, 0, 0x1000).validate(is_userspace, STACK_AREA),
+            Err(ElfError::InvalidSegmentAddress)
+        );
+    }
+
+    /// Tests that segments whose end address overflows are rejected.
+    #[test]
+    fn test_overflowing_segment() {
+        assert_eq!(
+            segment(0x1000, usize::max_value(), 0, 0x1000).validate(is_userspace, STACK_AREA),
+            Err(ElfError::InvalidSegmentAddress)
+        );
+    }
+
+    /// Tests that segments overlapping the user stack area are rejected.
+    #[test]
+    fn test_stack_overlapping_segment() {
+        assert_eq!(
+            segment(0x00007f7ffffff000, 0x2000, 0, 0x1000).validate(is_userspace, STACK_AREA),
+            Err(ElfError::InvalidSegmentAddress)
+        );
+    }
+
+    /// Tests that segments with bad alignments are rejected.
+    #[test]
+    fn test_misaligned_segment() {
+        assert_eq!(
+            segment(0x400000, 0x1000, 0, 0x1800).validate(is_userspace, STACK_AREA),
+            Err(ElfError::InvalidSegmentAlignment)
+        );
+        assert_eq!(
+            segment(0x400010, 0x1000, 0x20, 0x1000).validate(is_userspace, STACK_AREA),
+            Err(ElfError::InvalidSegmentAlignment)
+        );
+    }
+
+    /// Tests that segments that are larger in the file than in memory are
+    /// rejected.
+    #[test]
+    fn test_file_size_larger_than_memory_size() {
+        let mut header = segment(0x400000, 0x1000, 0, 0x1000);
+        header.size_in_file = 0x2000;
+
+        assert_eq!(
+            header.validate(is_userspace, STACK_AREA),
+            Err(ElfError::InvalidFile)
+        );
+    }
+
+    /// Tests that only whole pages backed by the file are cacheable.
+    #[test]
+    fn test_cacheable_pages() {
+        let aligned = segment(0x400000, 0x3000, 0, 0x1000);
+        let unaligned = segment(0x400800, 0x3000, 0x800, 0x1000);
+        let small = segment(0x400800, 0x400, 0x800, 0x1000);
+        let shifted = segment(0x400800, 0x3000, 0, 0x800);
+
+        assert_eq!(aligned.cacheable_pages(), (0, 0x3000));
+        assert_eq!(unaligned.cacheable_pages(), (0x800, 0x2800));
+        assert_eq!(small.cacheable_pages(), (0, 0));
+        assert_eq!(shifted.cacheable_pages(), (0, 0));
+    }
+
+    /// Tests the validation of entry points.
+    #[test]
+    fn test_entry_point() {
+        let valid = VirtualAddress::from_usize(0x401000);
+        let in_kernel = VirtualAddress::from_usize(0xffffffff80000000);
+        let in_stack = VirtualAddress::from_usize(0x00007f8000001000);
+
+        assert!(validate_entry_point(valid, is_userspace, STACK_AREA).is_ok());
+        assert!(validate_entry_point(in_kernel, is_userspace, STACK_AREA).is_err());
+        assert!(validate_entry_point(in_stack, is_userspace, STACK_AREA).is_err());
+    }
 }
@@ -0,0 +1,268 @@
+//! This module implements event queues.
+//!
+//! An event queue keeps track of the readiness of the file descriptors that
+//! are registered with it, so that waiting for many descriptors does not
+//! require passing all of them for every wait.
+//!
+//! The queue waits on the wait queues of the registered descriptors. When
+//! one of them is woken, the descriptor is added to the ready list of the
+//! event queue and only the descriptors in the ready list are checked when
+//! events are collected. Descriptors that are reported are moved to the end
+//! of the list, so that all of them get their turn.
+
+use alloc::arc::{Arc, Weak};
+use alloc::btree_map::BTreeMap;
+use alloc::vec_deque::VecDeque;
+use alloc::Vec;
+use file_handle::PollEvents;
+use multitasking::descriptor_table::FileDescriptor;
+use multitasking::wait_queue::{Notify, WaitQueue};
+use sync::Mutex;
+
+/// The interest of an event queue in a single file descriptor.
+struct Interest {
+    /// The descriptor the interest is in.
+    descriptor: FileDescriptor,
+    /// The events that are of interest.
+    events: PollEvents,
+    /// Whether events are only reported when the descriptor is woken.
+    edge_triggered: bool,
+    /// The data that is reported together with the events.
+    user_data: u64
+}
+
+/// An event reported by an event queue.
+#[repr(C)]
+pub struct ReadyEvent {
+    /// The data that was registered with the descriptor.
+    pub user_data: u64,
+    /// The events that are ready.
+    pub events: u16
+}
+
+/// The registrations of an event queue that may have events to report.
+struct ReadyList {
+    /// The registrations in the order they became ready.
+    registrations: Mutex<VecDeque<usize>>,
+    /// Woken when registrations become ready.
+    waiters: WaitQueue
+}
+
+impl ReadyList {
+    /// Adds the registration to the end of the list, unless it is in the
+    /// list already.
+    fn push(&self, registration: usize) {
+        let mut registrations = self.registrations.lock();
+
+        // UNOPTIMIZED
+        if !registrations.contains(&registration) {
+            registrations.push_back(registration);
+        }
+    }
+
+    /// Takes the first registration out of the list.
+    fn pop(&self) -> Option<usize> {
+        self.registrations.lock().pop_front()
+    }
+}
+
+impl Notify for ReadyList {
+    fn notify(&self, registration: usize) {
+        self.push(registration);
+        self.waiters.wake_all();
+    }
+}
+
+/// Adds an event queue to the wait queues of a registered descriptor.
+pub struct Watch {
+    /// The ready list of the event queue.
+    ready_list: Weak<Notify>,
+    /// The registration of the descriptor.
+    registration: usize
+}
+
+impl Watch {
+    /// Adds the registration to the ready list when the queue is woken.
+    pub fn on(&self, queue: &WaitQueue) {
+        queue.add_object(self.ready_list.clone(), self.registration);
+    }
+}
+
+/// A queue of readiness events for a set of file descriptors.
+pub struct EventQueue {
+    /// The registered interests indexed by their registration.
+    interests: BTreeMap<usize, Interest>,
+    /// The registrations of the descriptors.
+    registrations: BTreeMap<FileDescriptor, usize>,
+    /// The registration of the next registered descriptor.
+    next_registration: usize,
+    /// The registrations that may have events to report.
+    ready_list: Arc<ReadyList>
+}
+
+impl EventQueue {
+    /// Creates a new event queue without any registered descriptors.
+    pub fn new() -> EventQueue {
+        EventQueue {
+            interests: BTreeMap::new(),
+            registrations: BTreeMap::new(),
+            next_registration: 0,
+            ready_list: Arc::new(ReadyList {
+                registrations: Mutex::new(VecDeque::new()),
+                waiters: WaitQueue::new()
+            })
+        }
+    }
+
+    /// Registers interest in the events of the descriptor.
+    ///
+    /// Returns false if the descriptor was already registered.
+    pub fn register(
+        &mut self,
+        descriptor: FileDescriptor,
+        events: PollEvents,
+        edge_triggered: bool,
+        user_data: u64
+    ) -> bool {
+        if self.registrations.contains_key(&descriptor) {
+            return false;
+        }
+
+        let registration = self.next_registration;
+        self.next_registration += 1;
+
+        self.registrations.insert(descriptor, registration);
+        self.interests.insert(
+            registration,
+            Interest {
+                descriptor,
+                events,
+                edge_triggered,
+                user_data
+            }
+        );
+
+        // The descriptor is checked once, which also starts watching it.
+        self.ready_list.notify(registration);
+
+        true
+    }
+
+    /// Changes the interest in the events of the descriptor.
+    ///
+    /// Returns false if the descriptor was not registered.
+    pub fn modify(
+        &mut self,
+        descriptor: FileDescriptor,
+        events: PollEvents,
+        edge_triggered: bool,
+        user_data: u64
+    ) -> bool {
+        let registration = match self.registrations.get(&descriptor) {
+            Some(&registration) => registration,
+            None => return false
+        };
+
+        let interest = self
+            .interests
+            .get_mut(&registration)
+            .expect("A registered descriptor has no interest.");
+        interest.events = events;
+        interest.edge_triggered = edge_triggered;
+        interest.user_data = user_data;
+
+        self.ready_list.notify(registration);
+
+        true
+    }
+
+    /// Removes the interest in the descriptor.
+    ///
+    /// Returns false if the descriptor was not registered.
+    pub fn unregister(&mut self, descriptor: FileDescriptor) -> bool {
+        // Notifications for the registration are ignored from now on.
+        match self.registrations.remove(&descriptor) {
+            Some(registration) => {
+                self.interests.remove(&registration);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Returns the queue that is woken when descriptors may have become
+    /// ready.
+    pub fn wait_queue(&self) -> &WaitQueue {
+        &self.ready_list.waiters
+    }
+
+    /// Collects the ready events into `output` and returns their number.
+    ///
+    /// `check` is used to check the readiness of a descriptor, after adding
+    /// the watch to the wait queue of the descriptor. If it returns `None`,
+    /// the descriptor is no longer valid and is unregistered.
+    ///
+    /// Level-triggered descriptors stay in the ready list while they are
+    /// ready, edge-triggered ones only report their events once per wake-up
+    /// of their wait queue.
+    pub fn collect<F>(&mut self, mut check: F, output: &mut [ReadyEvent]) -> usize
+    where
+        F: FnMut(FileDescriptor, &Watch) -> Option<PollEvents>
+    {
+        let ready_list: Arc<Notify> = self.ready_list.clone();
+        let ready_list = Arc::downgrade(&ready_list);
+        let mut count = 0;
+        let mut still_ready = Vec::new();
+        let mut closed_descriptors = Vec::new();
+
+        while count < output.len() {
+            let registration = match self.ready_list.pop() {
+                Some(registration) => registration,
+                None => break
+            };
+
+            let interest = match self.interests.get(&registration) {
+                Some(interest) => interest,
+                None => continue
+            };
+
+            let watch = Watch {
+                ready_list: ready_list.clone(),
+                registration
+            };
+
+            let ready = match check(interest.descriptor, &watch) {
+                Some(events) => events & interest.events,
+                None => {
+                    closed_descriptors.push(interest.descriptor);
+                    continue;
+                }
+            };
+
+            if ready.is_empty() {
+                continue;
+            }
+
+            output[count] = ReadyEvent {
+                user_data: interest.user_data,
+                events: ready.bits()
+            };
+            count += 1;
+
+            if !interest.edge_triggered {
+                still_ready.push(registration);
+            }
+        }
+
+        // Reported descriptors come after the ones that didn't get a turn.
+        for registration in still_ready {
+            self.ready_list.push(registration);
+        }
+
+        for descriptor in closed_descriptors {
+            self.unregister(descriptor);
+        }
+
+        count
+    }
+}
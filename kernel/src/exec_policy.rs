@@ -0,0 +1,141 @@
+//! Decides whether the executables in the initramfs may be run.
+//!
+//! If the kernel was built with a public key in `VEOS_EXEC_PUBLIC_KEY`, ELF
+//! files are verified against the detached signatures that
+//! `mkinitramfs --sign` adds. The `exec_policy` option on the kernel command
+//! line selects what happens if a signature is missing or invalid:
+//!
+//! - `off`: Signatures aren't checked. This is the default without a key.
+//! - `warn`: A warning is logged and the file is executed anyway. This is the
+//!   default with a key.
+//! - `enforce`: The file isn't executed.
+//!
+//! Only ELF files are signed. Scripts are run by a signed interpreter, but
+//! their content isn't verified.
+
+use alloc::string::String;
+use boot;
+use core::str;
+use elf::ElfError;
+use veos_crypto::ed25519::{self, PublicKey, PUBLIC_KEY_SIZE, SIGNATURE_SIZE};
+use veos_initramfs::SIGNATURE_SUFFIX;
+use vfs;
+
+/// The public key the kernel was built with in hexadecimal.
+const PUBLIC_KEY: Option<&str> = option_env!("VEOS_EXEC_PUBLIC_KEY");
+
+/// What happens if an executable isn't signed correctly.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Policy {
+    /// Signatures aren't checked.
+    Off,
+    /// A warning is logged.
+    Warn,
+    /// The executable isn't run.
+    Enforce
+}
+
+/// Returns the policy selected on the kernel command line.
+pub fn policy() -> Policy {
+    match boot::get_option_value("exec_policy") {
+        Some("off") => Policy::Off,
+        Some("warn") => Policy::Warn,
+        Some("enforce") => Policy::Enforce,
+        // Fail closed, a typo should not disable the checks.
+        Some(_) => Policy::Enforce,
+        None => {
+            if public_key().is_some() {
+                Policy::Warn
+            } else {
+                Policy::Off
+            }
+        }
+    }
+}
+
+/// Logs the policy and whether the kernel has a public key.
+pub fn log_policy() {
+    let policy = policy();
+
+    if let Some(value) = boot::get_option_value("exec_policy") {
+        if value != "off" && value != "warn" && value != "enforce" {
+            warn!("Unknown exec policy \"{}\", enforcing signatures.", value);
+        }
+    }
+
+    match (policy, public_key().is_some()) {
+        (Policy::Off, _) => (),
+        (_, true) => info!("Executables are verified ({:?}).", policy),
+        (_, false) => warn!(
+            "Executables are verified ({:?}), but the kernel has no valid public key.",
+            policy
+        )
+    }
+}
+
+/// Checks whether the file at the normalized path with the given content may
+/// be run.
+pub fn check(path: &str, content: &[u8]) -> Result<(), ElfError> {
+    let policy = policy();
+
+    if policy == Policy::Off {
+        return Ok(());
+    }
+
+    match verify(path, content) {
+        Ok(()) => Ok(()),
+        Err(reason) => {
+            if policy == Policy::Enforce {
+                warn!("Refusing to execute {}, because {}.", path, reason);
+                Err(ElfError::InvalidSignature)
+            } else {
+                warn!("Executing {}, although {}.", path, reason);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Verifies the signature of the file at the path with the given content.
+///
+/// Returns the reason if the signature isn't valid.
+fn verify(path: &str, content: &[u8]) -> Result<(), &'static str> {
+    let public_key = public_key().ok_or("the kernel has no valid public key")?;
+
+    let mut signature_path = String::from(path);
+    signature_path.push_str(SIGNATURE_SUFFIX);
+
+    let mut signature_file = vfs::open(&signature_path).map_err(|_| "it has no signature")?;
+
+    if signature_file.len() != SIGNATURE_SIZE as u64 {
+        return Err("its signature is malformed");
+    }
+
+    let mut signature = [0; SIGNATURE_SIZE];
+    signature_file
+        .read(&mut signature)
+        .map_err(|_| "its signature can't be read")?;
+
+    if ed25519::verify(&public_key, content, &signature) {
+        Ok(())
+    } else {
+        Err("its signature is invalid")
+    }
+}
+
+/// Parses the public key the kernel was built with.
+fn public_key() -> Option<PublicKey> {
+    let hex = PUBLIC_KEY?.as_bytes();
+
+    if hex.len() != PUBLIC_KEY_SIZE * 2 {
+        return None;
+    }
+
+    let mut public_key = [0; PUBLIC_KEY_SIZE];
+    for (byte, digits) in public_key.iter_mut().zip(hex.chunks(2)) {
+        let digits = str::from_utf8(digits).ok()?;
+        *byte = u8::from_str_radix(digits, 16).ok()?;
+    }
+
+    Some(public_key)
+}
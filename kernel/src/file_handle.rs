@@ -1,5 +1,9 @@
 //! This modules aims to offer an abstraction for accessing files.
 
+use alloc::Vec;
+use core::cmp::min;
+use multitasking::wait_queue::WaitQueue;
+
 /// Abstracts the different kinds of errors that can occur with file operations.
 #[derive(Debug)]
 pub enum FileError {
@@ -10,7 +14,15 @@ pub enum FileError {
     /// The file was not found.
     FileNotFound,
     /// The filesystem is invalid.
-    InvalidFilesystem
+    InvalidFilesystem,
+    /// The operation would block, but the file was opened as non-blocking.
+    WouldBlock,
+    /// The operation is not supported by the file.
+    NotSupported,
+    /// The device of the file was detached from its driver.
+    DeviceRemoved,
+    /// An argument of the operation is invalid.
+    InvalidArgument
 }
 
 /// A result of a file operation.
@@ -27,14 +39,46 @@ pub enum SeekFrom {
     Current(i64)
 }
 
+bitflags! {
+    /// The flags that can be passed when opening a file.
+    pub flags OpenFlags: u32 {
+        /// Operations on the file fail with `WouldBlock` instead of blocking.
+        const NONBLOCKING = 1 << 0
+    }
+}
+
+bitflags! {
+    /// The readiness events of a file.
+    pub flags PollEvents: u16 {
+        /// Data can be read from the file without blocking.
+        const POLL_IN = 1 << 0,
+        /// Data can be written to the file without blocking.
+        const POLL_OUT = 1 << 2
+    }
+}
+
 /// Everything that abstracts a file should implement this.
-pub trait FileHandle {
+pub trait FileHandle: Send {
     /// Sets the current seek position. Returns the offset from the beginning.
     fn seek(&mut self, position: SeekFrom) -> Result<u64>;
 
     /// Reads `length` bytes into `buffer`.
     fn read(&mut self, buffer: &mut [u8]) -> Result<()>;
 
+    /// Reads at most `length` bytes into `buffer` and returns how many were
+    /// read.
+    ///
+    /// Files that receive their data over time return the data they already
+    /// have instead of waiting until `buffer` can be filled.
+    fn read_available(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        self.read(buffer).map(|()| buffer.len())
+    }
+
+    /// Writes the contents of `buffer` to the file.
+    fn write(&mut self, _buffer: &[u8]) -> Result<()> {
+        Err(FileError::NotSupported)
+    }
+
     /// Reads `length` bytes into `buffer` at offset `position` from the
     /// beginning.
     fn read_at(&mut self, buffer: &mut [u8], position: u64) -> Result<()> {
@@ -57,4 +101,102 @@ pub trait FileHandle {
 
         size
     }
+
+    /// Returns the amount of bytes between the seek position and the end.
+    fn remaining(&mut self) -> Result<u64> {
+        let current_seek = self.seek(SeekFrom::Current(0))?;
+
+        Ok(self.len() - current_seek)
+    }
+
+    /// Performs a request specific to the kind of file, like `ioctl` on
+    /// Unix.
+    ///
+    /// `argument` holds the data of the request and receives its result.
+    fn control(&mut self, _request: u32, _argument: &mut [u8]) -> Result<()> {
+        Err(FileError::NotSupported)
+    }
+
+    /// Returns the events that are currently ready on the file.
+    ///
+    /// Only events that are known to be ready are returned, so files that
+    /// can be read or written without blocking have to say so.
+    fn poll(&mut self) -> PollEvents {
+        PollEvents::empty()
+    }
+
+    /// Returns the queue that is woken when the events that are ready on the
+    /// file may have changed.
+    ///
+    /// Files without a queue never change which events are ready.
+    fn wait_queue(&mut self) -> Option<&WaitQueue> {
+        None
+    }
+}
+
+/// Returns the offset moved by the signed distance.
+pub fn offset_by(offset: u64, distance: i64) -> Result<u64> {
+    if distance >= 0 {
+        offset
+            .checked_add(distance as u64)
+            .ok_or(FileError::SeekPastEnd)
+    } else {
+        offset
+            .checked_sub(distance.wrapping_neg() as u64)
+            .ok_or(FileError::SeekBeforeStart)
+    }
+}
+
+/// A file whose content is held in memory.
+pub struct MemoryFile {
+    /// The content of the file.
+    content: Vec<u8>,
+    /// The current seek position.
+    offset: u64
+}
+
+impl MemoryFile {
+    /// Creates a file with the given content.
+    pub fn new(content: Vec<u8>) -> MemoryFile {
+        MemoryFile { content, offset: 0 }
+    }
+}
+
+impl FileHandle for MemoryFile {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64> {
+        let size = self.content.len() as u64;
+        let offset = match position {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(distance) => offset_by(self.offset, distance)?,
+            SeekFrom::End(distance) => offset_by(size, distance)?
+        };
+
+        if offset > size {
+            Err(FileError::SeekPastEnd)
+        } else {
+            self.offset = offset;
+            Ok(offset)
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let start = min(self.offset, self.content.len() as u64) as usize;
+
+        if start + buffer.len() > self.content.len() {
+            return Err(FileError::SeekPastEnd);
+        }
+
+        buffer.copy_from_slice(&self.content[start..start + buffer.len()]);
+        self.offset += buffer.len() as u64;
+
+        Ok(())
+    }
+
+    fn len(&mut self) -> u64 {
+        self.content.len() as u64
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        POLL_IN
+    }
 }
@@ -1,19 +1,17 @@
 //! This modules is responsible for reading the initramfs.
+//!
+//! The format itself is defined and parsed by the `veos_initramfs` crate,
+//! which `mkinitramfs` uses to write it. The initramfs is the filesystem
+//! mounted at `/`.
 
 use alloc::boxed::Box;
+use alloc::string::String;
 use arch::{self, Architecture};
-use core::mem::size_of;
-use core::{ptr, slice, str};
-use file_handle::{FileError, FileHandle, Result, SeekFrom};
-use memory::{MemoryArea, VirtualAddress};
-
-/// The magic number that identifies a VeOS initramfs.
-const MAGIC: [u8; 8] = [
-    'V' as u8, 'e' as u8, 'O' as u8, 'S' as u8, 'i' as u8, 'r' as u8, 'f' as u8, 's' as u8,
-];
-
-/// The size of a single metadata object within the initramfs.
-const FILE_METADATA_SIZE: usize = size_of::<u64>() * 4;
+use core::{ptr, result, slice};
+use file_handle::{FileError, FileHandle, PollEvents, Result, SeekFrom, POLL_IN};
+use memory::{Address, MemoryArea, VirtualAddress};
+use veos_initramfs::{FormatError, Initramfs};
+use vfs::FileSystem;
 
 /// Represents a file in the initramfs.
 pub struct FileDescriptor {
@@ -97,150 +95,65 @@ impl FileHandle for FileDescriptor {
             unsafe {
                 ptr::copy_nonoverlapping(source, buffer.as_mut_ptr(), buffer.len());
             }
+            self.current_offset += buffer.len() as u64;
             Ok(())
         }
     }
-}
-
-/// Represents the metadata of a file.
-struct FileMetadata {
-    /// The name of the file.
-    name: &'static str,
-    /// The start address of the file data.
-    start: VirtualAddress,
-    /// The length of the file.
-    length: usize
-}
 
-/// An iterator through the file metadata.
-struct FileIterator {
-    /// The address of the file metadata that is returned next.
-    current_file_metadata_address: VirtualAddress,
-    /// The address of the highest file number that can be returned.
-    max_address: VirtualAddress
+    fn poll(&mut self) -> PollEvents {
+        POLL_IN
+    }
 }
 
-impl Iterator for FileIterator {
-    type Item = FileMetadata;
-
-    fn next(&mut self) -> Option<FileMetadata> {
-        let area = arch::Current::get_initramfs_area();
-        let start = area.start_address();
-        let length = area.length();
-
-        loop {
-            if self.current_file_metadata_address < self.max_address {
-                let name_offset =
-                    unsafe { read_u64_big_endian(self.current_file_metadata_address) };
-
-                self.current_file_metadata_address += size_of::<u64>();
-
-                let name_length =
-                    unsafe { read_u64_big_endian(self.current_file_metadata_address) };
-
-                self.current_file_metadata_address += size_of::<u64>();
-
-                let content_offset =
-                    unsafe { read_u64_big_endian(self.current_file_metadata_address) };
-
-                self.current_file_metadata_address += size_of::<u64>();
-
-                let content_length =
-                    unsafe { read_u64_big_endian(self.current_file_metadata_address) };
-
-                self.current_file_metadata_address += size_of::<u64>();
-
-                if name_offset + name_length <= length as u64
-                    && content_offset + content_length <= length as u64
-                {
-                    let name_slice = unsafe {
-                        slice::from_raw_parts(
-                            (start + name_offset as usize).as_ptr(),
-                            name_length as usize
-                        )
-                    };
-                    let name = str::from_utf8(name_slice);
-
-                    if name.is_err() {
-                        continue;
-                    }
-
-                    break Some(FileMetadata {
-                        name: name.unwrap(),
-                        start: start + content_offset as usize,
-                        length: content_length as usize
-                    });
-                }
-            } else {
-                break None;
-            }
-        }
+/// Checks that the initramfs and all of its files can be read.
+pub fn init() {
+    match parse().and_then(|initramfs| initramfs.validate()) {
+        Ok(file_count) => info!("The initramfs contains {} files.", file_count),
+        Err(error) => error!("The initramfs is corrupt: {:?}", error)
     }
 }
 
-/// Returns an iterator through the file metadata.
-fn get_file_iterator() -> Result<FileIterator> {
-    if !initramfs_valid() {
-        Err(FileError::InvalidFilesystem)
-    } else {
-        let start = arch::Current::get_initramfs_area().start_address();
+/// Parses the initramfs.
+fn parse() -> result::Result<Initramfs<'static>, FormatError> {
+    let area = arch::Current::get_initramfs_area();
 
-        let first_metadata = start + size_of::<[u8; 8]>() + size_of::<u64>();
-        let amount_of_files = unsafe { read_u64_big_endian(start + size_of::<[u8; 8]>()) } as usize;
+    // The initramfs stays mapped and unchanged while the kernel runs.
+    let data = unsafe { slice::from_raw_parts(area.start_address().as_ptr(), area.length()) };
 
-        Ok(FileIterator {
-            current_file_metadata_address: first_metadata,
-            max_address: first_metadata + FILE_METADATA_SIZE * amount_of_files
-        })
-    }
+    Initramfs::parse(data)
 }
 
-/// Reads the u64 at the given address.
-///
-/// # Safety
-/// - Make sure that the address is contained within the initramfs.
-unsafe fn read_u64_big_endian(address: VirtualAddress) -> u64 {
-    let bytes: [u8; size_of::<u64>()] = *(address.as_ptr());
-    let mut result: u64 = 0;
-
-    for i in 0..size_of::<u64>() {
-        result |= (bytes[i] as u64) << ((size_of::<u64>() - i - 1) * 8);
-    }
-
-    result
+/// Returns the parsed initramfs.
+fn get_initramfs() -> Result<Initramfs<'static>> {
+    parse().map_err(|_| FileError::InvalidFilesystem)
 }
 
-/// Checks whether the initramfs is valid.
-fn initramfs_valid() -> bool {
-    let area = arch::Current::get_initramfs_area();
-    let start = area.start_address();
-    let length = area.length();
+/// Returns the file descriptor for the file with the given name.
+pub fn open(name: &str) -> Result<Box<FileHandle>> {
+    let file = get_initramfs()?.find(name).ok_or(FileError::FileNotFound)?;
+
+    Ok(Box::new(FileDescriptor {
+        memory_area: MemoryArea::new(
+            VirtualAddress::from_usize(file.content.as_ptr() as usize),
+            file.content.len()
+        ),
+        current_offset: 0
+    }))
+}
 
-    if length < size_of::<[u8; 8]>() + size_of::<u64>() {
-        false
-    } else {
-        let magic_in_file: [u8; 8] = unsafe { *(start.as_ptr()) };
+/// The filesystem that contains the files of the initramfs.
+pub struct InitramfsFileSystem;
 
-        let amount_of_files = unsafe { read_u64_big_endian(start + size_of::<[u8; 8]>()) };
+impl FileSystem for InitramfsFileSystem {
+    fn open(&self, path: &str) -> Result<Box<FileHandle>> {
+        // The names in the initramfs are absolute paths.
+        let mut name = String::from("/");
+        name.push_str(path);
 
-        magic_in_file == MAGIC
-            && length
-                >= size_of::<[u8; 8]>()
-                    + size_of::<u64>()
-                    + FILE_METADATA_SIZE * amount_of_files as usize
+        open(&name)
     }
-}
 
-/// Returns the file descriptor for the file with the given name.
-pub fn open(name: &str) -> Result<Box<FileHandle>> {
-    for file in get_file_iterator()? {
-        if file.name == name {
-            return Ok(Box::new(FileDescriptor {
-                memory_area: MemoryArea::new(file.start, file.length),
-                current_offset: 0
-            }));
-        }
+    fn is_immutable(&self) -> bool {
+        true
     }
-
-    Err(FileError::FileNotFound)
 }
@@ -0,0 +1,192 @@
+//! Measures how late interrupts are handled.
+//!
+//! The architecture records a latency whenever it knows when an interrupt
+//! should have arrived, such as for the timer, whose deadline the kernel set
+//! itself. The latency is the time from that expectation until the handler
+//! was entered, so it includes the time interrupts were disabled on the CPU.
+//!
+//! For every vector the worst latency and a histogram are kept and shown in
+//! `/proc/irq_latency`. A new worst latency above `REPORT_THRESHOLD_MICROS` is
+//! also logged, so that it shows up next to what the kernel was doing.
+
+use alloc::string::String;
+use alloc::Vec;
+use core::fmt::Write;
+use core::time::Duration;
+use procfs;
+use sync::Mutex;
+
+/// The number of interrupt vectors.
+const VECTORS: usize = 256;
+
+/// The number of buckets of the histograms.
+///
+/// Bucket `i` counts the latencies below `2^i` microseconds that weren't
+/// counted in a lower bucket. The last bucket counts all longer latencies.
+const HISTOGRAM_BUCKETS: usize = 16;
+
+/// The latency in microseconds above which a new worst latency is logged.
+const REPORT_THRESHOLD_MICROS: u64 = 100;
+
+/// The latencies of one vector.
+#[derive(Clone, Copy)]
+struct Latencies {
+    /// The number of recorded latencies.
+    count: u64,
+    /// The sum of all recorded latencies in microseconds.
+    total_micros: u64,
+    /// The worst recorded latency in microseconds.
+    worst_micros: u64,
+    /// The number of latencies in every bucket.
+    histogram: [u64; HISTOGRAM_BUCKETS]
+}
+
+impl Latencies {
+    /// Creates empty statistics.
+    const fn new() -> Latencies {
+        Latencies {
+            count: 0,
+            total_micros: 0,
+            worst_micros: 0,
+            histogram: [0; HISTOGRAM_BUCKETS]
+        }
+    }
+
+    /// Records the latency and returns true if it is the worst so far.
+    fn record(&mut self, micros: u64) -> bool {
+        let is_worst = micros > self.worst_micros;
+
+        self.count += 1;
+        self.total_micros = self.total_micros.saturating_add(micros);
+        self.histogram[bucket_of(micros)] += 1;
+
+        if is_worst {
+            self.worst_micros = micros;
+        }
+
+        is_worst
+    }
+
+    /// Returns the average latency in microseconds.
+    fn average_micros(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.total_micros / self.count
+        }
+    }
+}
+
+/// The latencies of all vectors.
+static LATENCIES: Mutex<[Latencies; VECTORS]> = Mutex::new([Latencies::new(); VECTORS]);
+
+/// Makes the latencies available in `/proc/irq_latency`.
+pub fn init() {
+    assert_has_not_been_called!("The interrupt latencies should only be registered once.");
+
+    procfs::register("irq_latency", describe);
+}
+
+/// Records the latency of an interrupt with the given vector.
+///
+/// This is meant to be called by the interrupt handler right after it was
+/// entered.
+pub fn record(vector: u8, latency: Duration) {
+    let micros = as_micros(latency);
+    let is_worst = LATENCIES.lock()[vector as usize].record(micros);
+
+    if is_worst && micros > REPORT_THRESHOLD_MICROS {
+        info!(
+            "New worst latency of {} us for interrupt vector {:#x}.",
+            micros, vector
+        );
+    }
+}
+
+/// Returns the histogram bucket of the latency in microseconds.
+fn bucket_of(micros: u64) -> usize {
+    let bits = 64 - micros.leading_zeros() as usize;
+
+    if bits < HISTOGRAM_BUCKETS {
+        bits
+    } else {
+        HISTOGRAM_BUCKETS - 1
+    }
+}
+
+/// Generates the content of `/proc/irq_latency`.
+fn describe() -> String {
+    // The used statistics are copied, so that formatting doesn't keep
+    // interrupts disabled.
+    let used: Vec<(usize, Latencies)> = LATENCIES
+        .lock()
+        .iter()
+        .enumerate()
+        .filter(|&(_, latencies)| latencies.count > 0)
+        .map(|(vector, &latencies)| (vector, latencies))
+        .collect();
+    let mut text = String::new();
+
+    for &(vector, ref latencies) in used.iter() {
+        writeln!(text, "vector {:#x}:", vector).unwrap();
+        writeln!(text, "  count: {}", latencies.count).unwrap();
+        writeln!(text, "  average: {} us", latencies.average_micros()).unwrap();
+        writeln!(text, "  worst: {} us", latencies.worst_micros).unwrap();
+
+        for (bucket, &count) in latencies.histogram.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+
+            if bucket == HISTOGRAM_BUCKETS - 1 {
+                writeln!(text, "  {} us and more: {}", 1u64 << (bucket - 1), count).unwrap();
+            } else {
+                writeln!(text, "  below {} us: {}", 1u64 << bucket, count).unwrap();
+            }
+        }
+    }
+
+    text
+}
+
+/// Returns the duration in microseconds.
+fn as_micros(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_mul(1_000_000)
+        .saturating_add(u64::from(duration.subsec_nanos() / 1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that latencies are sorted into power of two buckets.
+    #[test]
+    fn test_bucket_of() {
+        assert_eq!(bucket_of(0), 0);
+        assert_eq!(bucket_of(1), 1);
+        assert_eq!(bucket_of(3), 2);
+        assert_eq!(bucket_of(4), 3);
+        assert_eq!(
+            bucket_of(1 << (HISTOGRAM_BUCKETS - 2)),
+            HISTOGRAM_BUCKETS - 1
+        );
+        assert_eq!(bucket_of(u64::max_value()), HISTOGRAM_BUCKETS - 1);
+    }
+
+    /// Tests that the worst and average latencies are tracked.
+    #[test]
+    fn test_record() {
+        let mut latencies = Latencies::new();
+
+        assert!(latencies.record(10));
+        assert!(!latencies.record(2));
+        assert!(latencies.record(30));
+
+        assert_eq!(latencies.count, 3);
+        assert_eq!(latencies.worst_micros, 30);
+        assert_eq!(latencies.average_micros(), 14);
+        assert_eq!(latencies.histogram[bucket_of(2)], 1);
+    }
+}
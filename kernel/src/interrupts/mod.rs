@@ -4,9 +4,18 @@
 //! They should instead
 //! be called by the architecture specific interrupt handlers.
 
+pub mod latency;
+
 use arch::{self, schedule, Architecture};
-use memory::VirtualAddress;
-use multitasking::CURRENT_THREAD;
+use coredump;
+use crashdump;
+use debug_shell;
+use keyboard;
+use memory::{Address, VirtualAddress};
+use multitasking::backtrace::{print_kernel_backtrace, print_user_backtrace};
+use multitasking::scheduler;
+use multitasking::trace::{self, Registers, ResumeMode, StopReason};
+use multitasking::{get_current_process, CURRENT_THREAD};
 
 /// The timer interrupt handler for the system.
 pub fn timer_interrupt() {
@@ -15,23 +24,99 @@ pub fn timer_interrupt() {
 
 /// The keyboard interrupt handler.
 pub fn keyboard_interrupt(scancode: u8) {
-    if scancode == 1 {
-        unsafe { ::sync::disable_preemption() };
-        loop {}
-    }
-    info!("Key: <{}>", scancode);
+    keyboard::add_scancode(scancode);
+}
+
+/// The handler for bytes received on the serial console.
+pub fn serial_input(byte: u8) {
+    debug_shell::add_input(byte);
 }
 
 /// The page fault handler.
-pub fn page_fault_handler(address: VirtualAddress, program_counter: VirtualAddress) {
+///
+/// Faults caused by userspace are handled by `user_fault`, faults in the
+/// kernel halt the CPU.
+pub fn page_fault_handler(
+    address: VirtualAddress,
+    registers: &mut Registers,
+    from_userspace: bool
+) -> ResumeMode {
     unsafe { ::sync::disable_preemption() };
-    let current_thread = CURRENT_THREAD.lock();
 
-    error!(
-        "Page fault in {:?} {:?} at address {:?} (PC: {:?})",
-        current_thread.pid, current_thread.id, address, program_counter
-    );
+    {
+        let current_thread = CURRENT_THREAD.lock();
+
+        error!(
+            "Page fault in {:?} at address {:?} (PC: {:?})",
+            *current_thread,
+            address,
+            VirtualAddress::from_usize(registers.instruction_pointer)
+        );
+    }
 
     error!("Page flags: {:?}", arch::Current::get_page_flags(address));
+
+    if from_userspace {
+        return user_fault(registers);
+    }
+
+    kernel_fault(registers);
+}
+
+/// The handler for fatal faults in the kernel.
+///
+/// Prints a backtrace of the kernel, writes a crash dump and halts the CPU.
+pub fn kernel_fault(registers: &Registers) -> ! {
+    // The fault may have happened while the current thread was locked.
+    if let Some(current_thread) = CURRENT_THREAD.try_lock() {
+        error!("Running thread: {:?}", *current_thread);
+    }
+
+    let program_counter = VirtualAddress::from_usize(registers.instruction_pointer);
+    let frame_pointer = VirtualAddress::from_usize(registers.frame_pointer);
+
+    print_kernel_backtrace(program_counter, frame_pointer);
+
+    crashdump::write(
+        &format_args!("Kernel fault at {:?}", program_counter),
+        program_counter,
+        frame_pointer
+    );
+    ::log::logger().flush();
+
     loop {}
 }
+
+/// The handler for fatal faults in userspace.
+///
+/// A traced process is stopped for its tracer, which decides how the thread
+/// continues. Otherwise a backtrace of the faulting thread is printed, a core
+/// dump is written if the process allows it and the process is killed.
+pub fn user_fault(registers: &mut Registers) -> ResumeMode {
+    if let Some(mode) = trace::stop(StopReason::Fault, registers) {
+        return mode;
+    }
+
+    print_user_backtrace(
+        VirtualAddress::from_usize(registers.instruction_pointer),
+        VirtualAddress::from_usize(registers.frame_pointer)
+    );
+
+    coredump::dump_current_process(registers);
+
+    get_current_process().kill();
+    scheduler::wake_process(CURRENT_THREAD.lock().pid);
+    schedule();
+
+    unreachable!();
+}
+
+/// The handler for breakpoints and finished single steps in userspace.
+///
+/// Outside of a traced process these are treated as fatal faults.
+pub fn user_debug_exception(reason: StopReason, registers: &mut Registers) -> ResumeMode {
+    match trace::stop(reason, registers) {
+        Some(mode) => mode,
+        None => user_fault(registers)
+    }
+}
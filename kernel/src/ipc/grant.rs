@@ -0,0 +1,127 @@
+//! This module implements granting pages to other processes.
+//!
+//! Instead of copying large payloads, a process can grant pages of its
+//! address space to another process. The frames are then mapped into the
+//! receiving process. Lent pages stay mapped in the granting process as
+//! well, while donated pages are replaced by zeroed pages there.
+//!
+//! Every grant holds a reference to its frames until it is accepted, so the
+//! frames stay valid even if the granting process unmaps them in between.
+
+use alloc::btree_map::BTreeMap;
+use alloc::Vec;
+use arch::{self, Architecture};
+use core::mem;
+use memory::PhysicalAddress;
+use multitasking::ProcessID;
+use sync::Mutex;
+
+/// The type of a grant ID.
+pub type GrantID = usize;
+
+/// The maximum number of pages in a single grant.
+pub const MAX_GRANT_PAGES: usize = 1024;
+
+/// Pages granted by one process to another one.
+pub struct Grant {
+    /// The process that granted the pages.
+    pub granter: ProcessID,
+    /// The process that may accept the pages.
+    pub receiver: ProcessID,
+    /// Whether the receiver may write to the pages.
+    pub writable: bool,
+    /// The granted frames, each with a reference held by the grant.
+    frames: Vec<PhysicalAddress>
+}
+
+impl Drop for Grant {
+    fn drop(&mut self) {
+        for &frame in &self.frames {
+            unsafe {
+                arch::Current::release_frame(frame);
+            }
+        }
+    }
+}
+
+impl Grant {
+    /// Creates a new grant of the given frames.
+    ///
+    /// The grant takes over one reference to each frame.
+    pub fn new(
+        granter: ProcessID,
+        receiver: ProcessID,
+        writable: bool,
+        frames: Vec<PhysicalAddress>
+    ) -> Grant {
+        Grant {
+            granter,
+            receiver,
+            writable,
+            frames
+        }
+    }
+
+    /// Returns the granted frames.
+    pub fn frames(&self) -> &[PhysicalAddress] {
+        &self.frames
+    }
+
+    /// Gives up the references to the frames.
+    ///
+    /// This must be called once the references were passed on to mappings.
+    pub fn forget_frames(&mut self) {
+        mem::replace(&mut self.frames, Vec::new());
+    }
+}
+
+lazy_static! {
+    /// The grants that were not accepted yet.
+    static ref GRANTS: Mutex<BTreeMap<GrantID, Grant>> = Mutex::new(BTreeMap::new());
+}
+
+/// Stores the grant until it is accepted and returns its ID.
+pub fn insert(grant: Grant) -> GrantID {
+    let mut grants = GRANTS.lock();
+
+    // UNOPTIMIZED
+    let mut id = 0;
+    while grants.contains_key(&id) {
+        id += 1;
+    }
+
+    grants.insert(id, grant);
+
+    id
+}
+
+/// Takes the grant with the given ID, if it was made to `receiver`.
+pub fn take(id: GrantID, receiver: ProcessID) -> Option<Grant> {
+    let mut grants = GRANTS.lock();
+
+    if grants.get(&id).map_or(false, |grant| grant.receiver == receiver) {
+        grants.remove(&id)
+    } else {
+        None
+    }
+}
+
+/// Withdraws all grants made by or to the process.
+pub fn withdraw_grants(process: ProcessID) {
+    // The grants are dropped after the lock is released.
+    let withdrawn: Vec<Grant> = {
+        let mut grants = GRANTS.lock();
+
+        let ids: Vec<GrantID> = grants
+            .iter()
+            .filter(|&(_, grant)| grant.granter == process || grant.receiver == process)
+            .map(|(&id, _)| id)
+            .collect();
+
+        ids.into_iter()
+            .filter_map(|id| grants.remove(&id))
+            .collect()
+    };
+
+    drop(withdrawn);
+}
@@ -0,0 +1,272 @@
+//! This module implements passing messages between processes.
+//!
+//! A process creates endpoints that other processes send messages to. The
+//! messages sent to the endpoints of a process are kept in a single queue of
+//! that process, from which they are received by endpoint in the order they
+//! were sent. Sending blocks while the queue of the receiver is full and
+//! receiving blocks until a message for the endpoint arrives. Blocked threads
+//! wait on the wait queues of the mailbox, which are woken by the other side.
+//!
+//! Messages are copied, so they are kept small. Larger payloads are passed
+//! through rings or grants instead.
+
+use alloc::btree_map::BTreeMap;
+use alloc::vec_deque::VecDeque;
+use alloc::Vec;
+use multitasking::wait_queue::{Wait, WaitQueue};
+use multitasking::ProcessID;
+use sync::Mutex;
+
+/// The type of an endpoint ID.
+pub type EndpointID = usize;
+
+/// The maximum size of a message in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 256;
+
+/// The maximum number of messages queued for a process.
+pub const MAX_QUEUED_MESSAGES: usize = 32;
+
+/// The maximum number of endpoints of a process.
+pub const MAX_ENDPOINTS: usize = 64;
+
+/// The errors that can occur when passing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageError {
+    /// The process has no endpoint with that ID.
+    UnknownEndpoint,
+    /// The queue of the receiving process is full.
+    QueueFull
+}
+
+/// A message sent to an endpoint.
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    /// The process that sent the message.
+    pub sender: ProcessID,
+    /// The endpoint the message was sent to.
+    pub endpoint: EndpointID,
+    /// The content of the message.
+    pub data: Vec<u8>
+}
+
+/// The endpoints of a process together with the messages sent to them.
+struct Mailbox {
+    /// The number of endpoints of the process.
+    ///
+    /// Endpoints are numbered from zero and only removed together with the
+    /// mailbox.
+    endpoint_count: usize,
+    /// The messages that were not received yet, oldest first.
+    messages: VecDeque<Message>,
+    /// Woken when messages arrive.
+    receivers: WaitQueue,
+    /// Woken when messages are received, so that the queue has room again.
+    senders: WaitQueue
+}
+
+impl Mailbox {
+    /// Creates a mailbox without endpoints.
+    fn new() -> Mailbox {
+        Mailbox {
+            endpoint_count: 0,
+            messages: VecDeque::new(),
+            receivers: WaitQueue::new(),
+            senders: WaitQueue::new()
+        }
+    }
+
+    /// Creates a new endpoint and returns its ID.
+    ///
+    /// Returns `None` if the process has `MAX_ENDPOINTS` endpoints already.
+    fn create_endpoint(&mut self) -> Option<EndpointID> {
+        if self.endpoint_count >= MAX_ENDPOINTS {
+            return None;
+        }
+
+        self.endpoint_count += 1;
+
+        Some(self.endpoint_count - 1)
+    }
+
+    /// Queues the message for its endpoint.
+    fn push(&mut self, message: Message) -> Result<(), MessageError> {
+        if message.endpoint >= self.endpoint_count {
+            return Err(MessageError::UnknownEndpoint);
+        }
+
+        if self.messages.len() >= MAX_QUEUED_MESSAGES {
+            return Err(MessageError::QueueFull);
+        }
+
+        self.messages.push_back(message);
+
+        Ok(())
+    }
+
+    /// Takes the oldest message sent to the endpoint, if there is one.
+    fn pop(&mut self, endpoint: EndpointID) -> Result<Option<Message>, MessageError> {
+        if endpoint >= self.endpoint_count {
+            return Err(MessageError::UnknownEndpoint);
+        }
+
+        let index = self
+            .messages
+            .iter()
+            .position(|message| message.endpoint == endpoint);
+
+        Ok(index.and_then(|index| self.messages.remove(index)))
+    }
+}
+
+lazy_static! {
+    /// The mailboxes of all processes that created endpoints.
+    static ref MAILBOXES: Mutex<BTreeMap<ProcessID, Mailbox>> = Mutex::new(BTreeMap::new());
+}
+
+/// Creates a new endpoint of the process and returns its ID.
+pub fn create_endpoint(process: ProcessID) -> Option<EndpointID> {
+    MAILBOXES
+        .lock()
+        .entry(process)
+        .or_insert_with(Mailbox::new)
+        .create_endpoint()
+}
+
+/// Sends `data` to the endpoint of `receiver`.
+///
+/// This doesn't block. If the queue of the receiver is full, the message
+/// isn't sent and the wait is added to the queue of the senders that wait
+/// for room.
+pub fn send(
+    sender: ProcessID,
+    receiver: ProcessID,
+    endpoint: EndpointID,
+    data: &[u8],
+    wait: &Wait
+) -> Result<(), MessageError> {
+    let mut mailboxes = MAILBOXES.lock();
+    let mailbox = mailboxes
+        .get_mut(&receiver)
+        .ok_or(MessageError::UnknownEndpoint)?;
+
+    let result = mailbox.push(Message {
+        sender,
+        endpoint,
+        data: data.to_vec()
+    });
+
+    match result {
+        Ok(()) => mailbox.receivers.wake_all(),
+        Err(MessageError::QueueFull) => wait.on(&mailbox.senders),
+        Err(MessageError::UnknownEndpoint) => ()
+    }
+
+    result
+}
+
+/// Takes the oldest message sent to the endpoint of the process.
+///
+/// This doesn't block. If there is no message, `None` is returned and the
+/// wait is added to the queue of the receivers that wait for messages.
+pub fn receive(
+    process: ProcessID,
+    endpoint: EndpointID,
+    wait: &Wait
+) -> Result<Option<Message>, MessageError> {
+    let mut mailboxes = MAILBOXES.lock();
+    let mailbox = mailboxes
+        .get_mut(&process)
+        .ok_or(MessageError::UnknownEndpoint)?;
+
+    let result = mailbox.pop(endpoint);
+
+    match result {
+        Ok(Some(_)) => mailbox.senders.wake_all(),
+        Ok(None) => wait.on(&mailbox.receivers),
+        Err(_) => ()
+    }
+
+    result
+}
+
+/// Removes the endpoints of the process together with the messages sent to
+/// them.
+///
+/// Threads that wait for the mailbox are woken, so that they notice.
+pub fn remove_mailbox(process: ProcessID) {
+    // The messages are dropped after the lock is released.
+    let mailbox = MAILBOXES.lock().remove(&process);
+
+    if let Some(ref mailbox) = mailbox {
+        mailbox.receivers.wake_all();
+        mailbox.senders.wake_all();
+    }
+
+    drop(mailbox);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a message from process 1 to the endpoint.
+    fn message(endpoint: EndpointID, data: &[u8]) -> Message {
+        Message {
+            sender: ProcessID::from(1),
+            endpoint,
+            data: data.to_vec()
+        }
+    }
+
+    #[test]
+    fn messages_are_received_by_endpoint() {
+        let mut mailbox = Mailbox::new();
+        let first = mailbox.create_endpoint().unwrap();
+        let second = mailbox.create_endpoint().unwrap();
+
+        assert!(mailbox.push(message(first, b"a")).is_ok());
+        assert!(mailbox.push(message(second, b"b")).is_ok());
+        assert!(mailbox.push(message(first, b"c")).is_ok());
+
+        assert_eq!(mailbox.pop(second), Ok(Some(message(second, b"b"))));
+        assert_eq!(mailbox.pop(second), Ok(None));
+        assert_eq!(mailbox.pop(first), Ok(Some(message(first, b"a"))));
+        assert_eq!(mailbox.pop(first), Ok(Some(message(first, b"c"))));
+    }
+
+    #[test]
+    fn unknown_endpoints_are_rejected() {
+        let mut mailbox = Mailbox::new();
+        let endpoint = mailbox.create_endpoint().unwrap();
+
+        assert_eq!(
+            mailbox.push(message(endpoint + 1, b"a")),
+            Err(MessageError::UnknownEndpoint)
+        );
+        assert_eq!(
+            mailbox.pop(endpoint + 1),
+            Err(MessageError::UnknownEndpoint)
+        );
+    }
+
+    #[test]
+    fn queue_is_limited() {
+        let mut mailbox = Mailbox::new();
+        let endpoint = mailbox.create_endpoint().unwrap();
+
+        for _ in 0..MAX_QUEUED_MESSAGES {
+            assert!(mailbox.push(message(endpoint, b"a")).is_ok());
+        }
+
+        assert_eq!(
+            mailbox.push(message(endpoint, b"a")),
+            Err(MessageError::QueueFull)
+        );
+
+        for _ in 0..MAX_ENDPOINTS - 1 {
+            assert!(mailbox.create_endpoint().is_some());
+        }
+
+        assert_eq!(mailbox.create_endpoint(), None);
+    }
+}
@@ -0,0 +1,5 @@
+//! This module implements the communication between processes.
+
+pub mod grant;
+pub mod message;
+pub mod ring;
@@ -0,0 +1,207 @@
+//! This module implements shared memory ring buffers.
+//!
+//! A ring connects two processes for bulk data transfers. Its memory is
+//! mapped into both of them: the first page holds the head and tail indices,
+//! the remaining pages hold the data. The indices are only managed by
+//! userspace, the kernel only provides the memory and a doorbell for each
+//! side, which the other side rings to wake it up. Ringing a doorbell wakes
+//! the wait queue of its side.
+//!
+//! A ring is created by one process and offered to another one, which then
+//! accepts it. Offering a ring wakes the threads that wait for offers.
+
+use alloc::arc::Arc;
+use alloc::btree_map::BTreeMap;
+use alloc::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use file_handle::{PollEvents, POLL_IN, POLL_OUT};
+use memory::shared::SharedMemory;
+use memory::{VirtualAddress, PAGE_SIZE};
+use multitasking::wait_queue::WaitQueue;
+use multitasking::ProcessID;
+use sync::Mutex;
+
+/// The maximum number of data pages of a ring.
+pub const MAX_DATA_PAGES: usize = 256;
+
+/// The number of pages before the data that hold the indices.
+pub const HEADER_PAGES: usize = 1;
+
+/// One of the two sides of a ring.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    /// The side of the process that created the ring.
+    Creator,
+    /// The side of the process that accepted the ring.
+    Acceptor
+}
+
+impl Side {
+    /// Returns the index of the doorbell of this side.
+    fn index(self) -> usize {
+        match self {
+            Side::Creator => 0,
+            Side::Acceptor => 1
+        }
+    }
+
+    /// Returns the other side.
+    fn peer(self) -> Side {
+        match self {
+            Side::Creator => Side::Acceptor,
+            Side::Acceptor => Side::Creator
+        }
+    }
+}
+
+/// A ring buffer shared between two processes.
+pub struct Ring {
+    /// The memory of the ring, including the header.
+    memory: Arc<SharedMemory>,
+    /// The doorbells of both sides.
+    doorbells: [AtomicBool; 2],
+    /// The queues that are woken when the doorbell of their side is rung.
+    waiters: [WaitQueue; 2]
+}
+
+impl Ring {
+    /// Creates a new ring with the given number of data pages.
+    ///
+    /// Returns `None` if the number of pages is not supported.
+    pub fn new(data_pages: usize) -> Option<Ring> {
+        if data_pages == 0 || data_pages > MAX_DATA_PAGES {
+            return None;
+        }
+
+        Some(Ring {
+            memory: Arc::new(SharedMemory::new(HEADER_PAGES + data_pages)),
+            doorbells: [AtomicBool::new(false), AtomicBool::new(false)],
+            waiters: [WaitQueue::new(), WaitQueue::new()]
+        })
+    }
+
+    /// Returns the memory of the ring.
+    pub fn memory(&self) -> Arc<SharedMemory> {
+        self.memory.clone()
+    }
+
+    /// Returns the size of the data area in bytes.
+    pub fn data_size(&self) -> usize {
+        self.memory.len() - HEADER_PAGES * PAGE_SIZE
+    }
+}
+
+/// The end of a ring that a process refers to through a descriptor.
+pub struct RingEndpoint {
+    /// The ring this is an endpoint of.
+    ring: Arc<Ring>,
+    /// The side of the ring this endpoint belongs to.
+    side: Side,
+    /// The address the ring is mapped at in the process.
+    address: VirtualAddress
+}
+
+impl RingEndpoint {
+    /// Creates a new endpoint for the side of the ring mapped at `address`.
+    pub fn new(ring: Arc<Ring>, side: Side, address: VirtualAddress) -> RingEndpoint {
+        RingEndpoint {
+            ring,
+            side,
+            address
+        }
+    }
+
+    /// Returns the address the ring is mapped at.
+    pub fn address(&self) -> VirtualAddress {
+        self.address
+    }
+
+    /// Returns the size of the data area in bytes.
+    pub fn data_size(&self) -> usize {
+        self.ring.data_size()
+    }
+
+    /// Rings the doorbell of the other side.
+    pub fn notify(&self) {
+        let peer = self.side.peer().index();
+
+        self.ring.doorbells[peer].store(true, Ordering::Release);
+        self.ring.waiters[peer].wake_all();
+    }
+
+    /// Returns true and resets the doorbell, if the other side rang it.
+    pub fn take_notification(&self) -> bool {
+        self.ring.doorbells[self.side.index()].swap(false, Ordering::AcqRel)
+    }
+
+    /// Returns the events that are currently ready on the endpoint.
+    ///
+    /// The endpoint is readable while its doorbell is rung.
+    pub fn poll(&self) -> PollEvents {
+        if self.ring.doorbells[self.side.index()].load(Ordering::Acquire) {
+            POLL_IN | POLL_OUT
+        } else {
+            POLL_OUT
+        }
+    }
+
+    /// Returns the queue that is woken when the other side rings the
+    /// doorbell of this endpoint.
+    pub fn wait_queue(&self) -> &WaitQueue {
+        &self.ring.waiters[self.side.index()]
+    }
+}
+
+/// A ring that was offered to a process but not accepted yet.
+pub struct Offer {
+    /// The process that created the ring.
+    pub creator: ProcessID,
+    /// The offered ring.
+    pub ring: Arc<Ring>
+}
+
+lazy_static! {
+    /// The rings offered to each process, oldest first.
+    static ref OFFERS: Mutex<BTreeMap<ProcessID, Vec<Offer>>> = Mutex::new(BTreeMap::new());
+
+    /// Woken when rings are offered to any process.
+    pub static ref OFFERED: WaitQueue = WaitQueue::new();
+}
+
+/// Offers the ring created by `creator` to `target`.
+pub fn offer(creator: ProcessID, target: ProcessID, ring: Arc<Ring>) {
+    OFFERS
+        .lock()
+        .entry(target)
+        .or_insert_with(Vec::new)
+        .push(Offer { creator, ring });
+
+    OFFERED.wake_all();
+}
+
+/// Takes the oldest ring offered to the process.
+pub fn take_offer(target: ProcessID) -> Option<Offer> {
+    let mut offers = OFFERS.lock();
+
+    let offer = match offers.get_mut(&target) {
+        Some(ref mut pending) if !pending.is_empty() => pending.remove(0),
+        _ => return None
+    };
+
+    if offers.get(&target).map_or(false, |pending| pending.is_empty()) {
+        offers.remove(&target);
+    }
+
+    Some(offer)
+}
+
+/// Withdraws all offers made by or to the process.
+pub fn withdraw_offers(process: ProcessID) {
+    let mut offers = OFFERS.lock();
+
+    offers.remove(&process);
+
+    for pending in offers.values_mut() {
+        pending.retain(|offer| offer.creator != process);
+    }
+}
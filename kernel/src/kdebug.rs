@@ -0,0 +1,160 @@
+//! This module provides hardware watchpoints for debugging.
+//!
+//! A watchpoint raises a debug exception when the watched memory is accessed
+//! in the watched way. The kernel and traced processes share the available
+//! slots, where slots used by the kernel take precedence.
+
+use arch::{self, Architecture};
+use ksymbol;
+use memory::{Address, VirtualAddress};
+use multitasking::get_current_process;
+use multitasking::trace::{self, Registers, ResumeMode, StopReason};
+use sync::Mutex;
+
+/// The amount of watchpoints that can be active at the same time.
+pub const WATCHPOINT_SLOTS: usize = 4;
+
+/// The accesses a watchpoint can watch for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    /// Triggers when the instruction at the address is executed.
+    Execute,
+    /// Triggers when the memory is written.
+    Write,
+    /// Triggers when the memory is read or written.
+    ReadWrite
+}
+
+impl WatchKind {
+    /// Converts the raw syscall argument to a watch kind.
+    pub fn from_raw(kind: usize) -> Option<WatchKind> {
+        match kind {
+            0 => Some(WatchKind::Execute),
+            1 => Some(WatchKind::Write),
+            2 => Some(WatchKind::ReadWrite),
+            _ => None
+        }
+    }
+}
+
+/// A watched memory area.
+#[derive(Debug, Clone, Copy)]
+pub struct Watchpoint {
+    /// The start of the watched area.
+    pub address: VirtualAddress,
+    /// The length of the watched area in bytes.
+    pub length: usize,
+    /// The accesses that trigger the watchpoint.
+    pub kind: WatchKind
+}
+
+impl Watchpoint {
+    /// Creates a watchpoint, if the area can be watched by the hardware.
+    ///
+    /// The length must be 1, 2, 4 or 8 and the address must be aligned to
+    /// it. Execution watchpoints always have a length of 1.
+    pub fn new(address: VirtualAddress, length: usize, kind: WatchKind) -> Option<Watchpoint> {
+        let valid_length = match kind {
+            WatchKind::Execute => length == 1,
+            _ => length == 1 || length == 2 || length == 4 || length == 8
+        };
+
+        if valid_length && address.is_aligned_to(length) {
+            Some(Watchpoint {
+                address,
+                length,
+                kind
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// The watchpoints in all slots.
+pub type WatchpointSet = [Option<Watchpoint>; WATCHPOINT_SLOTS];
+
+/// The watchpoints set by the kernel.
+static KERNEL_WATCHPOINTS: Mutex<WatchpointSet> = Mutex::new([None; WATCHPOINT_SLOTS]);
+
+/// Watches the given memory area for the given kind of accesses.
+///
+/// Hits are logged together with the instruction that caused them. Returns
+/// the slot of the watchpoint or `None` if the area can't be watched or all
+/// slots are used. The watchpoint takes effect immediately on the current
+/// CPU and on the other CPUs after their next context switch.
+pub fn set_watchpoint(address: VirtualAddress, length: usize, kind: WatchKind) -> Option<usize> {
+    let watchpoint = Watchpoint::new(address, length, kind)?;
+
+    let slot = {
+        let mut watchpoints = KERNEL_WATCHPOINTS.lock();
+        let slot = watchpoints
+            .iter()
+            .position(|watchpoint| watchpoint.is_none())?;
+
+        watchpoints[slot] = Some(watchpoint);
+
+        slot
+    };
+
+    load_watchpoints();
+
+    Some(slot)
+}
+
+/// Removes the kernel watchpoint in the given slot.
+pub fn clear_watchpoint(slot: usize) {
+    if slot < WATCHPOINT_SLOTS {
+        KERNEL_WATCHPOINTS.lock()[slot] = None;
+    }
+
+    load_watchpoints();
+}
+
+/// Returns true if the slot is used by a kernel watchpoint.
+pub fn is_kernel_slot(slot: usize) -> bool {
+    slot < WATCHPOINT_SLOTS && KERNEL_WATCHPOINTS.lock()[slot].is_some()
+}
+
+/// Loads the watchpoints of the kernel and the current process into the
+/// hardware.
+///
+/// This should be called after every context switch.
+pub fn load_watchpoints() {
+    let mut watchpoints = *KERNEL_WATCHPOINTS.lock();
+
+    // UNOPTIMIZED: This locks the process list for every context switch.
+    if let Some(ref trace) = get_current_process().trace {
+        for (slot, watchpoint) in watchpoints.iter_mut().enumerate() {
+            if watchpoint.is_none() {
+                *watchpoint = trace.watchpoints[slot];
+            }
+        }
+    }
+
+    arch::Current::load_watchpoints(&watchpoints);
+}
+
+/// Handles a hit of the watchpoint in the given slot.
+///
+/// Hits of watchpoints set by a tracer stop the current thread for the
+/// tracer, all other hits are logged.
+pub fn watchpoint_hit(slot: usize, registers: &mut Registers) -> ResumeMode {
+    let instruction_pointer = VirtualAddress::from_usize(registers.instruction_pointer);
+
+    if !is_kernel_slot(slot) && arch::Current::is_userspace_address(instruction_pointer) {
+        if let Some(mode) = trace::stop(StopReason::Watchpoint(slot), registers) {
+            return mode;
+        }
+    }
+
+    match ksymbol::resolve(instruction_pointer) {
+        Some(symbol) => warn!(
+            "Watchpoint {} hit (PC: {:?} in {})",
+            slot, instruction_pointer, symbol
+        ),
+        None => warn!("Watchpoint {} hit (PC: {:?})", slot, instruction_pointer)
+    }
+
+    ResumeMode::Continue
+}
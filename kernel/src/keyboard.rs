@@ -0,0 +1,471 @@
+//! Buffers the input of the keyboard for userspace.
+//!
+//! The keyboard interrupt adds the scancodes to a queue, which is read
+//! through `/dev/keyboard`. All readers share the queue, so each scancode is
+//! only received once. The scancodes are passed on unchanged, decoding them
+//! is left to userspace.
+//!
+//! The keys that produce a character are also decoded and passed to the
+//! console, which is the standard input of processes.
+//!
+//! The LEDs of the keyboard show which lock keys are on. They are updated
+//! whenever a lock key is pressed or the locks are set through the console,
+//! which can also change how fast held keys repeat.
+
+use alloc::boxed::Box;
+use arch::{self, Architecture};
+use console;
+use core::cmp::min;
+use devfs;
+use file_handle::{FileError, FileHandle, PollEvents, Result, SeekFrom, POLL_IN};
+use multitasking::wait_queue::WaitQueue;
+use sync::Mutex;
+
+/// The name of the keyboard device.
+const DEVICE_NAME: &str = "keyboard";
+
+/// The number of bytes a queue holds.
+const QUEUE_SIZE: usize = 256;
+
+/// The prefix of the scancodes of extended keys.
+const EXTENDED_PREFIX: u8 = 0xe0;
+
+/// The bit that is set in the scancode when a key is released.
+const RELEASED: u8 = 0x80;
+
+/// The scancode of the left shift key.
+const LEFT_SHIFT: u8 = 0x2a;
+
+/// The scancode of the right shift key.
+const RIGHT_SHIFT: u8 = 0x36;
+
+/// The scancode of the caps lock key.
+const CAPS_LOCK_KEY: u8 = 0x3a;
+
+/// The scancode of the num lock key.
+const NUM_LOCK_KEY: u8 = 0x45;
+
+/// The scancode of the scroll lock key.
+const SCROLL_LOCK_KEY: u8 = 0x46;
+
+/// The scancode of the first key of the keypad.
+const FIRST_KEYPAD_KEY: u8 = 0x47;
+
+/// The lowest supported repeat rate in repetitions per second.
+const MIN_REPEAT_RATE: u32 = 2;
+
+/// The highest supported repeat rate in repetitions per second.
+const MAX_REPEAT_RATE: u32 = 30;
+
+/// The shortest supported repeat delay in milliseconds.
+const MIN_REPEAT_DELAY: u32 = 250;
+
+/// The longest supported repeat delay in milliseconds.
+const MAX_REPEAT_DELAY: u32 = 1000;
+
+/// The characters of the keys of scancode set 1, indexed by the scancode.
+///
+/// Keys without a character are zero.
+const CHARACTERS: &[u8] =
+    b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+
+/// The characters of the keys while shift is held.
+const SHIFTED_CHARACTERS: &[u8] =
+    b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+/// The characters of the keypad keys while num lock is on.
+const KEYPAD_CHARACTERS: &[u8] = b"789-456+1230.";
+
+/// The scancodes that weren't read yet.
+static QUEUE: Mutex<Queue> = Mutex::new(Queue::new());
+
+lazy_static! {
+    /// Woken when scancodes arrive.
+    static ref READERS: WaitQueue = WaitQueue::new();
+}
+
+/// The state of the keys that change the decoded characters.
+static KEY_STATE: Mutex<KeyState> = Mutex::new(KeyState {
+    shift: false,
+    locks: Locks { bits: 0 },
+    held_locks: Locks { bits: 0 },
+    extended: false
+});
+
+/// How held keys repeat.
+///
+/// This starts out with the default of PS/2 keyboards.
+static REPEAT: Mutex<Repeat> = Mutex::new(Repeat {
+    rate: 11,
+    delay: 500
+});
+
+bitflags! {
+    /// The lock keys that are on.
+    pub flags Locks: u32 {
+        /// Scroll lock is on.
+        const SCROLL_LOCK = 1 << 0,
+        /// Num lock is on.
+        const NUM_LOCK = 1 << 1,
+        /// Caps lock is on.
+        const CAPS_LOCK = 1 << 2
+    }
+}
+
+/// How held keys repeat.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repeat {
+    /// The number of repetitions per second.
+    pub rate: u32,
+    /// The time in milliseconds until the first repetition.
+    pub delay: u32
+}
+
+/// A fixed size queue of bytes.
+pub struct Queue {
+    /// The storage of the bytes.
+    bytes: [u8; QUEUE_SIZE],
+    /// The index of the oldest byte.
+    start: usize,
+    /// The number of bytes in the queue.
+    length: usize
+}
+
+impl Queue {
+    /// Creates an empty queue.
+    pub const fn new() -> Queue {
+        Queue {
+            bytes: [0; QUEUE_SIZE],
+            start: 0,
+            length: 0
+        }
+    }
+
+    /// Returns the number of bytes in the queue.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Adds the byte to the queue.
+    ///
+    /// Returns false if the queue is full.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.length == QUEUE_SIZE {
+            return false;
+        }
+
+        self.bytes[(self.start + self.length) % QUEUE_SIZE] = byte;
+        self.length += 1;
+
+        true
+    }
+
+    /// Removes the oldest byte from the queue.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.length == 0 {
+            return None;
+        }
+
+        let byte = self.bytes[self.start];
+        self.start = (self.start + 1) % QUEUE_SIZE;
+        self.length -= 1;
+
+        Some(byte)
+    }
+}
+
+/// The state of the keys that change the decoded characters.
+struct KeyState {
+    /// Whether a shift key is held.
+    shift: bool,
+    /// The lock keys that are on.
+    locks: Locks,
+    /// The lock keys that are held.
+    ///
+    /// Held keys repeat, but a lock only changes when its key is pressed.
+    held_locks: Locks,
+    /// Whether the last scancode was the prefix of an extended key.
+    extended: bool
+}
+
+impl KeyState {
+    /// Decodes the scancode to the character of the pressed key.
+    ///
+    /// Returns `None` for released keys and keys without a character.
+    fn decode(&mut self, scancode: u8) -> Option<u8> {
+        // The extended keys are arrow keys, the keypad and additional
+        // modifiers, none of which produce a character here.
+        if self.extended {
+            self.extended = false;
+            return None;
+        }
+
+        if let Some(lock) = lock_of_key(scancode & !RELEASED) {
+            if scancode & RELEASED != 0 {
+                self.held_locks.remove(lock);
+            } else if !self.held_locks.contains(lock) {
+                self.held_locks.insert(lock);
+                self.locks.toggle(lock);
+            }
+
+            return None;
+        }
+
+        match scancode {
+            EXTENDED_PREFIX => self.extended = true,
+            LEFT_SHIFT | RIGHT_SHIFT => self.shift = true,
+            scancode if scancode == LEFT_SHIFT | RELEASED || scancode == RIGHT_SHIFT | RELEASED => {
+                self.shift = false
+            },
+            scancode if scancode >= FIRST_KEYPAD_KEY && scancode & RELEASED == 0 => {
+                if self.locks.contains(NUM_LOCK) {
+                    return KEYPAD_CHARACTERS
+                        .get((scancode - FIRST_KEYPAD_KEY) as usize)
+                        .cloned();
+                }
+            },
+            scancode if scancode & RELEASED == 0 => {
+                let characters = if self.shift {
+                    SHIFTED_CHARACTERS
+                } else {
+                    CHARACTERS
+                };
+                let caps_lock = self.locks.contains(CAPS_LOCK);
+
+                return match characters.get(scancode as usize) {
+                    Some(&0) | None => None,
+                    Some(&character) if caps_lock && character.is_ascii_lowercase() => {
+                        Some(character.to_ascii_uppercase())
+                    },
+                    Some(&character) if caps_lock && character.is_ascii_uppercase() => {
+                        Some(character.to_ascii_lowercase())
+                    },
+                    Some(&character) => Some(character)
+                };
+            },
+            _ => ()
+        }
+
+        None
+    }
+}
+
+/// Returns the lock that the key with the scancode switches.
+fn lock_of_key(scancode: u8) -> Option<Locks> {
+    match scancode {
+        CAPS_LOCK_KEY => Some(CAPS_LOCK),
+        NUM_LOCK_KEY => Some(NUM_LOCK),
+        SCROLL_LOCK_KEY => Some(SCROLL_LOCK),
+        _ => None
+    }
+}
+
+/// The keyboard as a file.
+struct KeyboardDevice;
+
+impl FileHandle for KeyboardDevice {
+    fn seek(&mut self, _position: SeekFrom) -> Result<u64> {
+        Err(FileError::NotSupported)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let mut queue = QUEUE.lock();
+
+        if queue.len() < buffer.len() {
+            return Err(FileError::WouldBlock);
+        }
+
+        for byte in buffer.iter_mut() {
+            *byte = queue.pop().unwrap();
+        }
+
+        Ok(())
+    }
+
+    fn read_available(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let mut queue = QUEUE.lock();
+
+        if queue.len() == 0 && buffer.len() > 0 {
+            return Err(FileError::WouldBlock);
+        }
+
+        let length = min(queue.len(), buffer.len());
+        for byte in buffer[..length].iter_mut() {
+            *byte = queue.pop().unwrap();
+        }
+
+        Ok(length)
+    }
+
+    fn len(&mut self) -> u64 {
+        0
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        if QUEUE.lock().len() > 0 {
+            POLL_IN
+        } else {
+            PollEvents::empty()
+        }
+    }
+
+    fn wait_queue(&mut self) -> Option<&WaitQueue> {
+        Some(&READERS)
+    }
+}
+
+/// Makes the keyboard available as a device file.
+pub fn init() {
+    assert_has_not_been_called!("The keyboard device should only be registered once.");
+
+    devfs::register(
+        DEVICE_NAME,
+        Box::new(|| Box::new(KeyboardDevice) as Box<FileHandle>)
+    );
+
+    // The LEDs may still show the state from before the boot.
+    arch::Current::set_keyboard_leds(locks());
+}
+
+/// Adds a scancode received from the keyboard.
+///
+/// Scancodes that arrive while the queue is full are dropped.
+pub fn add_scancode(scancode: u8) {
+    QUEUE.lock().push(scancode);
+    READERS.wake_all();
+
+    let (character, changed_locks) = {
+        let mut key_state = KEY_STATE.lock();
+        let previous_locks = key_state.locks;
+        let character = key_state.decode(scancode);
+
+        if key_state.locks != previous_locks {
+            (character, Some(key_state.locks))
+        } else {
+            (character, None)
+        }
+    };
+
+    if let Some(locks) = changed_locks {
+        arch::Current::set_keyboard_leds(locks);
+    }
+
+    if let Some(character) = character {
+        console::add_input(character);
+    }
+}
+
+/// Returns the lock keys that are on.
+pub fn locks() -> Locks {
+    KEY_STATE.lock().locks
+}
+
+/// Switches the lock keys on or off and updates the LEDs.
+pub fn set_locks(locks: Locks) {
+    KEY_STATE.lock().locks = locks;
+
+    arch::Current::set_keyboard_leds(locks);
+}
+
+/// Returns how held keys repeat.
+pub fn repeat() -> Repeat {
+    *REPEAT.lock()
+}
+
+/// Changes how held keys repeat.
+///
+/// Returns false if the rate or the delay isn't supported.
+pub fn set_repeat(repeat: Repeat) -> bool {
+    let supported = repeat.rate >= MIN_REPEAT_RATE
+        && repeat.rate <= MAX_REPEAT_RATE
+        && repeat.delay >= MIN_REPEAT_DELAY
+        && repeat.delay <= MAX_REPEAT_DELAY;
+
+    if supported {
+        *REPEAT.lock() = repeat;
+        arch::Current::set_keyboard_repeat(repeat);
+    }
+
+    supported
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the state of a keyboard without pressed keys.
+    fn key_state() -> KeyState {
+        KeyState {
+            shift: false,
+            locks: Locks::empty(),
+            held_locks: Locks::empty(),
+            extended: false
+        }
+    }
+
+    #[test]
+    fn keys_are_decoded() {
+        let mut state = key_state();
+
+        assert_eq!(state.decode(0x1e), Some(b'a'));
+        assert_eq!(state.decode(0x1e | RELEASED), None);
+        assert_eq!(state.decode(0x0b), Some(b'0'));
+        assert_eq!(state.decode(0x1c), Some(b'\n'));
+        assert_eq!(state.decode(0x0e), Some(0x08));
+        assert_eq!(state.decode(0x1d), None);
+        assert_eq!(state.decode(0x58), None);
+    }
+
+    #[test]
+    fn shift_and_caps_lock_change_the_characters() {
+        let mut state = key_state();
+
+        state.decode(LEFT_SHIFT);
+        assert_eq!(state.decode(0x02), Some(b'!'));
+        assert_eq!(state.decode(0x10), Some(b'Q'));
+        state.decode(LEFT_SHIFT | RELEASED);
+        assert_eq!(state.decode(0x02), Some(b'1'));
+
+        state.decode(CAPS_LOCK_KEY);
+        assert_eq!(state.decode(0x10), Some(b'Q'));
+        assert_eq!(state.decode(0x02), Some(b'1'));
+        state.decode(RIGHT_SHIFT);
+        assert_eq!(state.decode(0x10), Some(b'q'));
+    }
+
+    #[test]
+    fn extended_keys_are_ignored() {
+        let mut state = key_state();
+
+        assert_eq!(state.decode(EXTENDED_PREFIX), None);
+        assert_eq!(state.decode(0x1c), None);
+        assert_eq!(state.decode(0x1c), Some(b'\n'));
+    }
+
+    #[test]
+    fn held_lock_keys_only_toggle_once() {
+        let mut state = key_state();
+
+        state.decode(CAPS_LOCK_KEY);
+        state.decode(CAPS_LOCK_KEY);
+        assert_eq!(state.locks, CAPS_LOCK);
+
+        state.decode(CAPS_LOCK_KEY | RELEASED);
+        state.decode(NUM_LOCK_KEY);
+        state.decode(CAPS_LOCK_KEY);
+        assert_eq!(state.locks, NUM_LOCK);
+    }
+
+    #[test]
+    fn keypad_needs_num_lock() {
+        let mut state = key_state();
+
+        assert_eq!(state.decode(0x47), None);
+        state.decode(NUM_LOCK_KEY);
+        assert_eq!(state.decode(0x47), Some(b'7'));
+        assert_eq!(state.decode(0x53), Some(b'.'));
+        assert_eq!(state.decode(0x53 | RELEASED), None);
+        assert_eq!(state.decode(0x57), None);
+    }
+}
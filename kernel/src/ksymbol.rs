@@ -0,0 +1,88 @@
+//! This module resolves kernel addresses to function names.
+//!
+//! The symbol table is generated from the linked kernel by `mksymbols.sh` and
+//! linked into the kernel in a second step. It consists of the number of
+//! symbols, an address sorted array of entries and the names of the
+//! symbols, each prefixed with its length.
+
+use core::{fmt, ptr, slice, str};
+use memory::{Address, VirtualAddress};
+
+/// An entry in the symbol table.
+#[repr(C)]
+struct SymbolEntry {
+    /// The address of the function.
+    address: u64,
+    /// The size of the function in bytes.
+    size: u32,
+    /// The offset of the name in the names area.
+    name_offset: u32
+}
+
+extern "C" {
+    /// The number of symbols, directly followed by the rest of the table.
+    #[link_name = "ksymbols"]
+    static SYMBOL_TABLE: u64;
+}
+
+/// A function containing an address.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    /// The name of the function.
+    pub name: &'static str,
+    /// The offset of the address from the start of the function.
+    pub offset: usize
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// Returns the entries of the symbol table.
+fn entries() -> &'static [SymbolEntry] {
+    unsafe {
+        let count = SYMBOL_TABLE as usize;
+        let start = (&SYMBOL_TABLE as *const u64).offset(1) as *const SymbolEntry;
+
+        slice::from_raw_parts(start, count)
+    }
+}
+
+/// Returns the name of the given symbol table entry.
+fn name_of(entry: &SymbolEntry) -> Option<&'static str> {
+    let entries = entries();
+
+    unsafe {
+        let names = entries.as_ptr().offset(entries.len() as isize) as *const u8;
+        let name = names.offset(entry.name_offset as isize);
+        let length = ptr::read_unaligned(name as *const u16) as usize;
+
+        str::from_utf8(slice::from_raw_parts(name.offset(2), length)).ok()
+    }
+}
+
+/// Returns the kernel function that contains the given address.
+pub fn resolve(address: VirtualAddress) -> Option<Symbol> {
+    let entries = entries();
+    let address = address.as_usize();
+
+    let index = match entries.binary_search_by_key(&(address as u64), |entry| entry.address) {
+        Ok(index) => index,
+        Err(0) => return None,
+        Err(index) => index - 1
+    };
+
+    let entry = &entries[index];
+    let offset = address - entry.address as usize;
+
+    if offset < entry.size as usize {
+        Some(Symbol {
+            name: name_of(entry)?,
+            offset
+        })
+    } else {
+        None
+    }
+}
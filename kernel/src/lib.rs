@@ -34,6 +34,8 @@ extern crate once;
 #[cfg(not(test))]
 extern crate alloc;
 extern crate raw_cpuid;
+extern crate veos_crypto;
+extern crate veos_initramfs;
 #[macro_use]
 extern crate log;
 
@@ -42,15 +44,40 @@ mod macros;
 #[macro_use]
 mod io;
 mod arch;
+mod benchmark;
 mod boot;
+mod config;
+mod console;
+mod coredump;
+mod coverage;
+mod cpufreq;
+mod crashdump;
+mod debug_shell;
+mod devfs;
+mod device;
 mod elf;
+mod event_queue;
+mod exec_policy;
 mod file_handle;
 mod initramfs;
 mod interrupts;
+mod ipc;
+mod kdebug;
+mod keyboard;
+mod ksymbol;
+mod logger;
 mod memory;
 mod multitasking;
+mod ninep;
+mod procfs;
+mod random;
+mod server;
 mod sync;
 mod syscalls;
+mod testing;
+mod thermal;
+mod timer;
+mod vfs;
 
 /// The name of the operating system.
 static OS_NAME: &'static str = "VeOS";
@@ -81,7 +108,7 @@ pub extern "C" fn main(magic_number: u32, information_structure_address: usize)
         sync::disable_preemption();
     }
 
-    arch::Current::init_logger();
+    logger::init();
     log::set_max_level(LOG_LEVEL);
 
     arch::Current::early_init();
@@ -92,8 +119,19 @@ pub extern "C" fn main(magic_number: u32, information_structure_address: usize)
         OS_NAME,
         boot::get_bootloader_name()
     );
+    config::log_features();
+    sync::preemption_audit::init();
+    exec_policy::log_policy();
+    testing::init();
     memory::init();
+    initramfs::init();
+    vfs::init();
+    crashdump::init();
     arch::Current::init();
+    sync::time::init_system_time();
+    memory::huge_pages::init();
+    memory::numa::init();
+    keyboard::init();
 
     let extended_info = raw_cpuid::CpuId::new().get_extended_function_info();
     let unwrapped_info = extended_info.unwrap();
@@ -106,7 +144,33 @@ pub extern "C" fn main(magic_number: u32, information_structure_address: usize)
         arch::Current::get_free_memory_size() / 1024 / 1024
     );
 
-    elf::process_from_initramfs_file("/bin/init").expect("Initprocess could not be loaded");
+    multitasking::stack::preallocate_kernel_stacks();
+
+    if config::BENCHMARK {
+        benchmark::run();
+    }
+
+    multitasking::init();
+    multitasking::reaper::init();
+    multitasking::resource_group::init();
+    cpufreq::init();
+    thermal::init();
+    interrupts::latency::init();
+    debug_shell::init();
+    logger::start_writer();
+    arch::Current::init_drivers();
+
+    // The system can't do without the init process.
+    let mut inheritance = multitasking::Inheritance::from_current();
+    inheritance.essential = true;
+
+    elf::process_from_initramfs_file(
+        "/bin/init",
+        &["/bin/init"],
+        &boot::get_init_environment(),
+        true,
+        inheritance
+    ).expect("Initprocess could not be loaded");
 
     unsafe {
         arch::Current::enter_first_thread();
@@ -125,6 +189,18 @@ pub extern "C" fn main(magic_number: u32, information_structure_address: usize)
 #[no_mangle]
 pub extern "C" fn panic_fmt(info: &PanicInfo) -> ! {
     error!("{}", info);
+    // Buffered output would never be sent after halting.
+    log::logger().flush();
+    crashdump::write(
+        info,
+        memory::VirtualAddress::from_usize(0),
+        arch::Current::get_frame_pointer()
+    );
+    log::logger().flush();
+    coverage::dump();
+    if testing::is_enabled() {
+        testing::report_failure();
+    }
     unsafe {
         sync::disable_preemption();
     }
@@ -139,5 +215,7 @@ pub extern "C" fn panic_fmt(info: &PanicInfo) -> ! {
 #[lang = "oom"]
 #[no_mangle]
 pub extern "C" fn __rust_oom(_err: *const u8) -> ! {
-    unimplemented!()
+    // The frames of the heap are reclaimed by the frame allocator, so this
+    // only happens when the heap area itself is full.
+    memory::oom::out_of_memory()
 }
@@ -0,0 +1,362 @@
+//! The kernel logger.
+//!
+//! Once the log writer thread was started, log records are only formatted
+//! into a ring buffer and the thread writes them to the screen and the serial
+//! port. That way logging doesn't wait for the output devices, which would
+//! distort the timing of the code that logs. Before that, records are written
+//! directly.
+//!
+//! Flushing the logger writes the buffered records directly and waits until
+//! all output was sent, which the panic handler relies on. If the buffer is
+//! full, the oldest record is dropped.
+//!
+//! Each record on the serial port is prefixed with the time it was logged at,
+//! the CPU it was logged on and the current process and thread, if known.
+//!
+//! The most recent written records are also kept in a history, so that the
+//! crash dump can include them.
+
+use alloc::vec_deque::VecDeque;
+use arch::{self, schedule, Architecture};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use core::time::Duration;
+use core::{cmp, mem, str};
+use log::{set_logger, Level, Log, Metadata, Record};
+use multitasking::scheduler::after_context_switch;
+use multitasking::{get_cpu_id, spawn_kernel_thread, Name, ProcessID, ThreadID, ThreadState,
+                   CURRENT_THREAD};
+use sync::time::Timestamp;
+use sync::{enable_preemption, Mutex, PreemptionGuard};
+
+/// The number of records the buffer holds.
+const BUFFER_CAPACITY: usize = 256;
+
+/// The maximum length of a buffered message in bytes.
+///
+/// Longer messages are cut off.
+const MESSAGE_SIZE: usize = 240;
+
+/// The size of the history of written records in bytes.
+const HISTORY_SIZE: usize = 0x4000;
+
+/// The interval in which the log writer checks for new records.
+const WRITE_INTERVAL_MS: u64 = 10;
+
+/// Determines whether all logging should be to the screen.
+const LOG_TO_SCREEN: bool = false;
+
+/// The kernel logger.
+pub static KERNEL_LOGGER: KernelLogger = KernelLogger;
+
+/// Whether records are buffered.
+static BUFFERING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// The records that weren't written yet.
+static BUFFER: Mutex<Option<LogBuffer>> = Mutex::new(None);
+
+/// The most recent written records.
+static HISTORY: Mutex<History> = Mutex::new(History {
+    data: [0; HISTORY_SIZE],
+    written: 0
+});
+
+/// The type of the logger for the kernel.
+pub struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let context = Context::get_current();
+
+        if !BUFFERING.load(Ordering::Acquire) || !buffer(record.level(), context, record.args()) {
+            write_record(record.level(), context, record.args());
+        }
+    }
+
+    fn flush(&self) {
+        // With preemption disabled the records are sent directly.
+        {
+            let _preemption = PreemptionGuard::new();
+            write_buffered();
+        }
+
+        arch::Current::flush_output();
+    }
+}
+
+/// Describes when and where a record was logged.
+#[derive(Clone, Copy)]
+struct Context {
+    /// The time the record was logged at.
+    time: Timestamp,
+    /// The CPU the record was logged on.
+    cpu: usize,
+    /// The process and thread that logged the record, if known.
+    thread: Option<(ProcessID, ThreadID)>
+}
+
+impl Context {
+    /// Returns the context of a record logged right now.
+    fn get_current() -> Context {
+        // The current thread is only known once multitasking is set up, which
+        // is before the log writer is started. It isn't waited for, because
+        // the thread may log while holding its own lock.
+        let thread = if BUFFERING.load(Ordering::Acquire) {
+            CURRENT_THREAD
+                .try_lock()
+                .map(|thread| (thread.pid, thread.id))
+        } else {
+            None
+        };
+
+        Context {
+            time: Timestamp::get_current(),
+            cpu: get_cpu_id(),
+            thread
+        }
+    }
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} cpu{} ", self.time, self.cpu)?;
+
+        match self.thread {
+            Some((pid, tid)) => write!(f, "{}:{}", usize::from(pid), usize::from(tid)),
+            None => write!(f, "-")
+        }
+    }
+}
+
+/// A buffered log record.
+struct Entry {
+    /// The level of the record.
+    level: Level,
+    /// When and where the record was logged.
+    context: Context,
+    /// The length of the message.
+    length: usize,
+    /// Whether the message was cut off.
+    truncated: bool,
+    /// The formatted message.
+    message: [u8; MESSAGE_SIZE]
+}
+
+impl Entry {
+    /// Returns the formatted message.
+    fn message(&self) -> &str {
+        // The message is only ever cut off at character boundaries.
+        str::from_utf8(&self.message[..self.length]).unwrap_or("")
+    }
+}
+
+impl Write for Entry {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        let mut length = cmp::min(string.len(), MESSAGE_SIZE - self.length);
+
+        while !string.is_char_boundary(length) {
+            length -= 1;
+        }
+
+        self.message[self.length..self.length + length]
+            .copy_from_slice(&string.as_bytes()[..length]);
+        self.length += length;
+        self.truncated |= length < string.len();
+
+        Ok(())
+    }
+}
+
+/// The ring buffer of log records.
+struct LogBuffer {
+    /// The records in the order they were logged.
+    entries: VecDeque<Entry>,
+    /// The number of records that were dropped since the last write.
+    dropped: usize
+}
+
+/// The most recent written records as text.
+///
+/// Once it is full, the oldest text is overwritten.
+struct History {
+    /// The text, which starts at `written % HISTORY_SIZE` once it is full.
+    data: [u8; HISTORY_SIZE],
+    /// The number of bytes written since boot.
+    written: usize
+}
+
+impl Write for History {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for &byte in string.as_bytes() {
+            self.data[self.written % HISTORY_SIZE] = byte;
+            self.written += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Calls `read` with the history of written records.
+///
+/// The history is passed as two parts, the older one first. If the history
+/// is locked, because the crash happened while writing to it, `read` isn't
+/// called.
+pub fn read_history<F: FnOnce(&[u8], &[u8])>(read: F) {
+    if let Some(history) = HISTORY.try_lock() {
+        if history.written < HISTORY_SIZE {
+            read(&history.data[..history.written], &[]);
+        } else {
+            let (newer, older) = history.data.split_at(history.written % HISTORY_SIZE);
+            read(older, newer);
+        }
+    }
+}
+
+/// Sets the kernel logger as the global logger.
+pub fn init() {
+    // Ignore the result. If the logger fails to be initialized, logging won't work.
+    match set_logger(&KERNEL_LOGGER) {
+        _ => ()
+    }
+}
+
+/// Starts the log writer thread and buffers all further records.
+///
+/// This must be called once while the address space of the idle process is
+/// active.
+pub fn start_writer() {
+    assert_has_not_been_called!("There should only be one log writer thread.");
+
+    *BUFFER.lock() = Some(LogBuffer {
+        entries: VecDeque::with_capacity(BUFFER_CAPACITY),
+        dropped: 0
+    });
+
+    spawn_kernel_thread(Name::new("log writer"), writer);
+
+    BUFFERING.store(true, Ordering::Release);
+}
+
+/// Adds the record to the buffer.
+///
+/// Returns false if there is no buffer.
+fn buffer(level: Level, context: Context, args: &fmt::Arguments) -> bool {
+    let mut guard = BUFFER.lock();
+    let buffer = match *guard {
+        Some(ref mut buffer) => buffer,
+        None => return false
+    };
+
+    if buffer.entries.len() == BUFFER_CAPACITY {
+        buffer.entries.pop_front();
+        buffer.dropped += 1;
+    }
+
+    let mut entry = Entry {
+        level,
+        context,
+        length: 0,
+        truncated: false,
+        message: [0; MESSAGE_SIZE]
+    };
+    // Writing to an entry never fails, it cuts the message off instead.
+    let _ = entry.write_fmt(*args);
+
+    buffer.entries.push_back(entry);
+
+    true
+}
+
+/// Writes all buffered records.
+fn write_buffered() {
+    loop {
+        // The buffer isn't locked while writing, so logging never waits for
+        // the output.
+        let (entry, dropped) = {
+            let mut guard = BUFFER.lock();
+            let buffer = match *guard {
+                Some(ref mut buffer) => buffer,
+                None => return
+            };
+
+            match buffer.entries.pop_front() {
+                Some(entry) => (entry, mem::replace(&mut buffer.dropped, 0)),
+                None => return
+            }
+        };
+
+        if dropped > 0 {
+            write_record(
+                Level::Warn,
+                entry.context,
+                &format_args!("{} log records were dropped.", dropped)
+            );
+        }
+
+        let ellipsis = if entry.truncated { "..." } else { "" };
+
+        write_record(
+            entry.level,
+            entry.context,
+            &format_args!("{}{}", entry.message(), ellipsis)
+        );
+    }
+}
+
+/// Writes the record to the screen and the serial port.
+fn write_record(level: Level, context: Context, message: &fmt::Arguments) {
+    // Records written while the history is locked on another CPU are left
+    // out instead of waiting.
+    if let Some(mut history) = HISTORY.try_lock() {
+        let _ = writeln!(history, "{} {}: {}", context, level, message);
+    }
+
+    let reset = "\x1b[0m";
+    let red = "\x1b[31m";
+    let yellow = "\x1b[33m";
+    match level {
+        Level::Error => {
+            println!("{}{}{}: {}", red, level, reset, message);
+            serial_println!("{} {}{}{}: {}", context, red, level, reset, message);
+        },
+        Level::Warn => {
+            println!("{}{}{}: {}", yellow, level, reset, message);
+            serial_println!("{} {}{}{}: {}", context, yellow, level, reset, message);
+        },
+        Level::Info => {
+            println!("{}", message);
+            serial_println!("{} {}", context, message);
+        },
+        Level::Debug | Level::Trace => {
+            if LOG_TO_SCREEN {
+                println!("{}: {}", level, message);
+            }
+            serial_println!("{} {}: {}", context, level, message);
+        }
+    }
+}
+
+/// The function the log writer thread runs.
+fn writer() -> ! {
+    // The log writer starts right after a context switch away from another
+    // thread.
+    after_context_switch();
+    unsafe {
+        enable_preemption();
+    }
+
+    loop {
+        write_buffered();
+
+        let wake_time = Timestamp::get_current()
+            .offset(Duration::from_millis(WRITE_INTERVAL_MS))
+            .expect("The time since boot overflowed.");
+
+        CURRENT_THREAD.lock().state = ThreadState::Sleeping(wake_time);
+        schedule();
+    }
+}
@@ -51,6 +51,21 @@ macro_rules! from_raw_str {
     }};
 }
 
+/// Counts the calls of the function it is placed in.
+///
+/// It should be the first statement of the function and is passed the name
+/// of the function. The calls are only counted with the `coverage` feature.
+#[macro_export]
+macro_rules! cover {
+    ($function:ident) => {{
+        #[link_section = ".coverage"]
+        static COUNTER: ::coverage::Counter =
+            ::coverage::Counter::new(concat!(module_path!(), "::", stringify!($function)));
+
+        COUNTER.record_call();
+    }};
+}
+
 /// Converts to a virtual address.
 ///
 /// Converts a given physical address within the kernel part of memory to its
@@ -65,6 +80,19 @@ macro_rules! to_virtual {
     }};
 }
 
+/// Converts to a physical address.
+///
+/// Converts a given virtual address within the kernel image to its
+/// corresponding physical address.
+#[macro_export]
+#[cfg(target_arch = "x86_64")]
+macro_rules! to_physical {
+    ($address:expr) => {{
+        const KERNEL_OFFSET: usize = 0xffff800000000000;
+        $address as usize - KERNEL_OFFSET
+    }};
+}
+
 /// Returns true for a valid virtual address.
 #[macro_export]
 macro_rules! valid_address {
@@ -1,50 +1,258 @@
 //! This module defines address spaces.
 
 use super::address_space_manager::AddressSpaceManager;
-use super::{PageFlags, PhysicalAddress, VirtualAddress};
+use super::huge_pages;
+use super::shared::SharedMemory;
+use super::{Address, MappingError, PageFlags, PhysicalAddress, VirtualAddress};
+use alloc::arc::Arc;
 use alloc::Vec;
 use arch::{self, Architecture};
 use core::mem::size_of_val;
 use core::slice;
-use memory::{MemoryArea, PAGE_SIZE, USER_ACCESSIBLE};
+use memory::{MemoryArea, PAGE_SIZE, USER_ACCESSIBLE, WRITABLE};
+use multitasking::resource_group::{self, GroupID, ROOT_GROUP};
 use multitasking::{Stack, ThreadID};
+use sync::{LockStats, Mutex};
+
+/// The contention statistics of the page table locks of all address spaces.
+static ADDRESS_SPACE_STATS: LockStats = LockStats::new("ADDRESS_SPACE");
+
+/// The address space manager of the current architecture.
+type Manager = <arch::Current as Architecture>::AddressSpaceManager;
 
 /// Represents an address space
 pub struct AddressSpace {
     /// The segments that are part of the address space.
     segments: Vec<Segment>,
+    /// The total size of the user accessible segments.
+    size: usize,
+    /// The maximum total size of the user accessible segments.
+    size_limit: usize,
+    /// The resource group the user accessible segments are charged to.
+    resource_group: GroupID,
+    /// The memory charged to the resource group for a segment that is about
+    /// to be added.
+    reserved: usize,
     /// The address space manager.
-    manager: <arch::Current as Architecture>::AddressSpaceManager
+    ///
+    /// The lock protects the page tables of this address space only, so that
+    /// different address spaces can be mapped concurrently.
+    manager: Mutex<<arch::Current as Architecture>::AddressSpaceManager>
 }
 
 impl Drop for AddressSpace {
     fn drop(&mut self) {
-        for segment in &mut self.segments {
-            segment.unmap(&mut self.manager);
-        }
+        self.unmap_all();
     }
 }
 
 impl AddressSpace {
-    /// Creates a new address space.
-    pub fn new() -> AddressSpace {
-        AddressSpace {
+    /// Creates a new address space whose user accessible segments may not
+    /// exceed `size_limit` bytes in total and are charged to the given
+    /// resource group.
+    pub fn new(size_limit: usize, resource_group: GroupID) -> AddressSpace {
+        let manager =
+            <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::new();
+
+        let mut address_space = AddressSpace {
             segments: Vec::new(),
-            manager:
-                <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::new()
-        }
+            size: 0,
+            size_limit,
+            resource_group,
+            reserved: 0,
+            manager: Mutex::with_stats(manager, &ADDRESS_SPACE_STATS)
+        };
+
+        address_space.set_huge_pages(huge_pages::is_enabled());
+
+        address_space
     }
 
     /// Creates a new address space for the idle threads.
     pub fn idle_address_space() -> AddressSpace {
+        let manager =
+            <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::idle();
+
         AddressSpace {
             segments: Vec::new(),
-            manager:
-                <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::idle(
-                )
+            size: 0,
+            size_limit: usize::max_value(),
+            resource_group: ROOT_GROUP,
+            reserved: 0,
+            manager: Mutex::with_stats(manager, &ADDRESS_SPACE_STATS)
+        }
+    }
+
+    /// Unmaps all segments of the address space.
+    ///
+    /// Only the top level page table is kept until the address space is
+    /// dropped.
+    pub fn unmap_all(&mut self) {
+        let mut manager = self.manager.lock();
+
+        for segment in &mut self.segments {
+            segment.unmap(&mut manager);
+        }
+
+        self.segments.clear();
+        resource_group::uncharge(self.resource_group, self.size);
+        self.size = 0;
+    }
+
+    /// Creates a copy of this address space, which must be the active one.
+    ///
+    /// The user accessible segments share their frames with the copy. Writable
+    /// pages are marked as copy-on-write in both address spaces, so that they
+    /// are only copied once one of them writes to them. Kernel only segments,
+    /// like the kernel stacks, are not part of the copy.
+    ///
+    /// Returns `None` if the copy doesn't fit into the resource group.
+    pub fn fork(&mut self) -> Option<AddressSpace> {
+        // The copy may need all of its memory once the pages are written.
+        if !resource_group::charge(self.resource_group, self.size) {
+            return None;
+        }
+
+        let mut child = AddressSpace::new(self.size_limit, self.resource_group);
+
+        {
+            // The pages of this address space are changed through the active
+            // page table, but the lock still keeps others from changing them.
+            let _manager = self.manager.lock();
+            let mut child_manager = child.manager.lock();
+
+            for segment in &mut self.segments {
+                if !segment.flags.contains(USER_ACCESSIBLE) {
+                    continue;
+                }
+
+                match segment.segment_type {
+                    SegmentType::Shared(ref memory) => {
+                        for (index, &frame) in memory.frames().iter().enumerate() {
+                            let page_address = segment.start_address() + index * PAGE_SIZE;
+
+                            child_manager
+                                .map_page_at(page_address, frame, segment.flags)
+                                .expect("Shared memory couldn't be mapped in a forked copy.");
+                        }
+                    },
+                    SegmentType::Device(start_address) => {
+                        for index in 0..segment.memory_area.length() / PAGE_SIZE {
+                            child_manager
+                                .map_page_at(
+                                    segment.start_address() + index * PAGE_SIZE,
+                                    start_address + index * PAGE_SIZE,
+                                    segment.flags
+                                )
+                                .expect("Device memory couldn't be mapped in a forked copy.");
+                        }
+                    },
+                    SegmentType::FromFile | SegmentType::MemoryOnly => {
+                        child_manager.share_copy_on_write(segment.memory_area, segment.flags);
+                        segment.copy_on_write = true;
+                    },
+                }
+            }
+        }
+
+        child.segments = self
+            .segments
+            .iter()
+            .filter(|segment| segment.flags.contains(USER_ACCESSIBLE))
+            .cloned()
+            .collect();
+        child.size = self.size;
+
+        Some(child)
+    }
+
+    /// Sets whether large anonymous ranges may be backed by huge pages.
+    ///
+    /// Ranges that are mapped already are not affected.
+    pub fn set_huge_pages(&mut self, enabled: bool) {
+        self.manager.lock().set_huge_pages(enabled);
+    }
+
+    /// Returns the total size of the user accessible segments.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the areas and flags of the user accessible segments.
+    ///
+    /// Device memory is left out, because reading it may have side effects.
+    pub fn user_segments(&self) -> Vec<(MemoryArea<VirtualAddress>, PageFlags)> {
+        self.segments
+            .iter()
+            .filter(|segment| segment.flags.contains(USER_ACCESSIBLE) && !segment.is_device())
+            .map(|segment| (segment.memory_area, segment.flags))
+            .collect()
+    }
+
+    /// Sets the maximum total size of the user accessible segments.
+    ///
+    /// Segments that were already added are not affected.
+    pub fn set_size_limit(&mut self, limit: usize) {
+        self.size_limit = limit;
+    }
+
+    /// Moves the user accessible segments to the given resource group.
+    ///
+    /// Returns false if they don't fit into the group.
+    pub fn set_resource_group(&mut self, group: GroupID) -> bool {
+        if !resource_group::move_charge(self.resource_group, group, self.size) {
+            return false;
+        }
+
+        self.resource_group = group;
+
+        true
+    }
+
+    /// Returns true if user accessible segments of the given length can
+    /// still be added without exceeding the size limit or the memory limit
+    /// of the resource group.
+    pub fn has_room_for(&self, length: usize) -> bool {
+        self.size
+            .checked_add(length)
+            .map_or(false, |size| size <= self.size_limit)
+            && (self.reserved >= length || resource_group::fits(self.resource_group, length))
+    }
+
+    /// Charges user accessible memory to the resource group, using up what
+    /// was reserved first.
+    ///
+    /// Returns false if it doesn't fit into the group.
+    fn charge(&mut self, length: usize) -> bool {
+        if self.reserved >= length {
+            self.reserved -= length;
+            true
+        } else {
+            resource_group::charge(self.resource_group, length)
         }
     }
 
+    /// Returns the size of a user stack if no other size is requested.
+    pub fn user_stack_size() -> usize {
+        <Manager as AddressSpaceManager>::USER_STACK_SIZE
+    }
+
+    /// Returns the maximum size of a user stack.
+    pub fn user_stack_max_size() -> usize {
+        <Manager as AddressSpaceManager>::USER_STACK_MAX_SIZE
+    }
+
+    /// Returns the address space area reserved for all user stacks.
+    pub fn user_stack_area() -> MemoryArea<VirtualAddress> {
+        <Manager as AddressSpaceManager>::USER_STACK_AREA
+    }
+
+    /// Returns true if there is enough room left to create a user stack of the
+    /// default size.
+    pub fn has_room_for_user_stack(&self) -> bool {
+        self.has_room_for(AddressSpace::user_stack_size())
+    }
+
     /// Adds the segment to the address space.
     ///
     /// Returns true if the segment was successfully added.
@@ -60,42 +268,312 @@ impl AddressSpace {
                 && arch::Current::is_userspace_address(segment_to_add.end_address()))
         {
             false
+        } else if segment_to_add.flags.contains(USER_ACCESSIBLE)
+            && !(self.has_room_for(segment_to_add.memory_area.length())
+                && self.charge(segment_to_add.memory_area.length()))
+        {
+            false
         } else {
+            if segment_to_add.flags.contains(USER_ACCESSIBLE) {
+                self.size += segment_to_add.memory_area.length();
+            }
             self.segments.push(segment_to_add);
             true
         }
     }
 
+    /// Removes the segment that covers exactly the given area and unmaps it.
+    ///
+    /// Returns true if such a segment existed.
+    pub fn remove_segment(&mut self, area: MemoryArea<VirtualAddress>) -> bool {
+        let position = self.segments.iter().position(|segment| {
+            segment.start_address() == area.start_address()
+                && segment.memory_area.length() == area.length()
+        });
+
+        match position {
+            Some(position) => {
+                let segment = self.segments.remove(position);
+                if segment.flags.contains(USER_ACCESSIBLE) {
+                    self.size -= segment.memory_area.length();
+                    resource_group::uncharge(self.resource_group, segment.memory_area.length());
+                }
+                segment.unmap(&mut self.manager.lock());
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Maps the shared memory into the shared memory area of the address
+    /// space.
+    ///
+    /// Returns the address of the mapping or `None` if there is no room left.
+    pub fn map_shared_memory(
+        &mut self,
+        memory: Arc<SharedMemory>,
+        flags: PageFlags
+    ) -> Option<VirtualAddress> {
+        let area = self.find_free_shared_area(memory.len())?;
+
+        if !self.add_segment(Segment::new(area, flags, SegmentType::Shared(memory.clone()))) {
+            return None;
+        }
+
+        let mut manager = self.manager.lock();
+
+        for (index, &frame) in memory.frames().iter().enumerate() {
+            manager
+                .map_page_at(area.start_address() + index * PAGE_SIZE, frame, flags)
+                .ok()?;
+        }
+
+        Some(area.start_address())
+    }
+
+    /// Maps the given frames into the shared memory area of the address
+    /// space.
+    ///
+    /// The mappings take over one reference to each frame. Returns the
+    /// address of the mapping or `None` if there is no room left.
+    pub fn map_frames(
+        &mut self,
+        frames: &[PhysicalAddress],
+        flags: PageFlags
+    ) -> Option<VirtualAddress> {
+        let area = self.find_free_shared_area(frames.len() * PAGE_SIZE)?;
+
+        if !self.add_segment(Segment::new(area, flags, SegmentType::MemoryOnly)) {
+            return None;
+        }
+
+        let mut manager = self.manager.lock();
+
+        for (index, &frame) in frames.iter().enumerate() {
+            manager
+                .map_page_at(area.start_address() + index * PAGE_SIZE, frame, flags)
+                .ok()?;
+        }
+
+        Some(area.start_address())
+    }
+
+    /// Maps the physical memory of a device into the shared memory area of the
+    /// address space.
+    ///
+    /// The area must be page aligned. Its frames don't belong to the frame
+    /// allocator, so they are never freed. Returns the address of the mapping
+    /// or `None` if there is no room left.
+    pub fn map_device_memory(
+        &mut self,
+        area: MemoryArea<PhysicalAddress>,
+        flags: PageFlags
+    ) -> Option<VirtualAddress> {
+        let virtual_area = self.find_free_shared_area(area.length())?;
+        let segment_type = SegmentType::Device(area.start_address());
+
+        if !self.add_segment(Segment::new(virtual_area, flags, segment_type)) {
+            return None;
+        }
+
+        let mut manager = self.manager.lock();
+
+        for index in 0..area.length() / PAGE_SIZE {
+            manager
+                .map_page_at(
+                    virtual_area.start_address() + index * PAGE_SIZE,
+                    area.start_address() + index * PAGE_SIZE,
+                    flags
+                )
+                .ok()?;
+        }
+
+        Some(virtual_area.start_address())
+    }
+
+    /// Returns the frames of the given user accessible pages with an added
+    /// reference to each of them.
+    ///
+    /// Pages that are not mapped yet are mapped first. If `donate` is set,
+    /// the pages in this address space are replaced by zeroed pages, so that
+    /// only the returned frames keep their content.
+    /// Returns `None` if the area is not page aligned or not part of a single
+    /// user accessible segment.
+    pub fn take_frame_references(
+        &self,
+        area: MemoryArea<VirtualAddress>,
+        donate: bool
+    ) -> Option<Vec<PhysicalAddress>> {
+        if area.length() == 0
+            || area.start_address().offset_in_page() != 0
+            || area.length() % PAGE_SIZE != 0
+        {
+            return None;
+        }
+
+        let (flags, is_shared, is_device) = match self.get_segment(area) {
+            Some(segment) => (segment.flags, segment.is_shared(), segment.is_device()),
+            None => return None
+        };
+
+        // The frames of shared segments are owned by the shared memory and
+        // device memory isn't owned by anyone.
+        if !flags.contains(USER_ACCESSIBLE) || is_device || (donate && is_shared) {
+            return None;
+        }
+
+        let mut frames = Vec::with_capacity(area.length() / PAGE_SIZE);
+        let mut manager = self.manager.lock();
+
+        for index in 0..area.length() / PAGE_SIZE {
+            let page_address = area.start_address() + index * PAGE_SIZE;
+
+            let frame = match manager.translate_address(page_address) {
+                Some(frame) => frame,
+                None => {
+                    manager.map_page(page_address, flags).ok()?;
+                    manager
+                        .translate_address(page_address)
+                        .expect("The just mapped page isn't mapped.")
+                }
+            };
+
+            arch::Current::add_frame_reference(frame);
+
+            if donate {
+                unsafe {
+                    manager.unmap_page(page_address);
+                }
+                manager.zero(MemoryArea::new(page_address, PAGE_SIZE), flags);
+            }
+
+            frames.push(frame);
+        }
+
+        Some(frames)
+    }
+
     /// Writes to the given address in the address space.
-    pub fn write_to(&mut self, buffer: &[u8], address: VirtualAddress) {
+    ///
+    /// Pages that share their frame with the page cache or with a forked
+    /// address space are copied first.
+    pub fn write_to(&self, buffer: &[u8], address: VirtualAddress) {
         let area = MemoryArea::new(address, buffer.len());
-        let segment_flags = { self.get_segment(area).map(|segment| segment.flags) };
+        let segment = {
+            self.get_segment(area)
+                .map(|segment| (segment.flags, segment.may_share_frames()))
+        };
 
-        if let Some(segment_flags) = segment_flags {
-            self.manager.write_to(buffer, address, segment_flags);
+        if let Some((segment_flags, may_share_frames)) = segment {
+            let mut manager = self.manager.lock();
+
+            if may_share_frames && !buffer.is_empty() {
+                AddressSpace::unshare_pages(&mut manager, area, segment_flags);
+            }
+
+            manager.write_to(buffer, address, segment_flags);
         } else {
             self.handle_out_of_segment(area);
         }
     }
 
+    /// Reads from the given address in the address space.
+    ///
+    /// Returns false if the area is not part of a segment or not mapped.
+    pub fn read_from(&self, buffer: &mut [u8], address: VirtualAddress) -> bool {
+        if self.contains_area(MemoryArea::new(address, buffer.len())) {
+            self.manager.lock().read_from(buffer, address)
+        } else {
+            false
+        }
+    }
+
     /// Zeros an already mapped area.
-    pub fn zero_mapped_area(&mut self, area: MemoryArea<VirtualAddress>) {
+    pub fn zero_mapped_area(&self, area: MemoryArea<VirtualAddress>) {
         let segment_flags = { self.get_segment(area).map(|segment| segment.flags) };
 
         if let Some(segment_flags) = segment_flags {
-            self.manager.zero(area, segment_flags);
+            self.manager.lock().zero(area, segment_flags);
         } else {
             self.handle_out_of_segment(area);
         }
     }
 
     /// Writes the given value to the given address in this address space.
-    pub unsafe fn write_val<T>(&mut self, value: T, address: VirtualAddress) {
+    pub unsafe fn write_val<T>(&self, value: T, address: VirtualAddress) {
         let value_ptr = &value as *const T;
         let buffer = slice::from_raw_parts(value_ptr as *const u8, size_of_val(&value));
         self.write_to(buffer, address)
     }
 
+    /// Gives every page in the area its own frame, if its frame is shared.
+    fn unshare_pages(
+        manager: &mut <arch::Current as Architecture>::AddressSpaceManager,
+        area: MemoryArea<VirtualAddress>,
+        flags: PageFlags
+    ) {
+        let first_page_num = area.start_address().page_num();
+        let last_page_num = (area.start_address() + area.length() - 1).page_num();
+        let mut buffer = Vec::new();
+        buffer.resize(PAGE_SIZE, 0u8);
+
+        for page_num in first_page_num..last_page_num + 1 {
+            let page_address = VirtualAddress::from_page_num(page_num);
+
+            let frame = match manager.translate_address(page_address) {
+                Some(frame) => frame,
+                None => continue
+            };
+
+            if !arch::Current::is_frame_shared(frame) {
+                continue;
+            }
+
+            manager.read_from(&mut buffer, page_address);
+            unsafe {
+                manager.unmap_page(page_address);
+            }
+            manager.write_to(&buffer, page_address, flags);
+        }
+    }
+
+    /// Returns a free area of the given length within the shared memory area.
+    fn find_free_shared_area(&self, length: usize) -> Option<MemoryArea<VirtualAddress>> {
+        let shared_area =
+            <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::SHARED_MEMORY_AREA;
+
+        self.find_free_area(shared_area, length)
+    }
+
+    /// Returns the first page aligned area of the given length within `range`
+    /// that doesn't overlap with a segment.
+    fn find_free_area(
+        &self,
+        range: MemoryArea<VirtualAddress>,
+        length: usize
+    ) -> Option<MemoryArea<VirtualAddress>> {
+        // UNOPTIMIZED
+        let mut start_address = range.start_address();
+        loop {
+            let area = MemoryArea::new(start_address, length);
+
+            if start_address.checked_add(length).is_none() || !area.is_contained_in(range) {
+                return None;
+            }
+
+            let overlapping = self
+                .segments
+                .iter()
+                .find(|segment| segment.overlaps_area(area))
+                .map(|segment| segment.end_address());
+
+            match overlapping {
+                Some(end_address) => start_address = end_address.align_up(PAGE_SIZE)?,
+                None => return Some(area)
+            }
+        }
+    }
+
     /// Returns the segment that contains the address with length bytes space
     /// after, if it exists.
     fn get_segment(&self, area: MemoryArea<VirtualAddress>) -> Option<&Segment> {
@@ -108,7 +586,7 @@ impl AddressSpace {
     }
 
     /// Handles the case of accesses outside of a segment.
-    fn handle_out_of_segment(&self, area: MemoryArea<VirtualAddress>) {
+    fn handle_out_of_segment(&self, area: MemoryArea<VirtualAddress>) -> ! {
         panic!("Out of segment access (area: {:?})", area);
     }
 
@@ -122,25 +600,98 @@ impl AddressSpace {
         segment.is_some()
     }
 
-    /// Returns the address of the page table.
+    /// Checks if the given area is completely contained in a single user
+    /// accessible segment.
+    pub fn contains_user_area(&self, area: MemoryArea<VirtualAddress>) -> bool {
+        self.get_segment(area)
+            .map_or(false, |segment| segment.flags.contains(USER_ACCESSIBLE))
+    }
+
+    /// Returns the area of the segment that contains the given address.
+    pub fn segment_area_of(&self, address: VirtualAddress) -> Option<MemoryArea<VirtualAddress>> {
+        self.get_segment(MemoryArea::new(address, 0))
+            .map(|segment| segment.memory_area)
+    }
+
+    /// Checks that the address space shares the kernel half with all other
+    /// address spaces.
     ///
-    /// # Safety
-    /// - Should only be called by architecture specific code.
-    pub unsafe fn get_page_table_address(&self) -> PhysicalAddress {
-        self.manager.get_page_table_address()
+    /// This panics if it doesn't.
+    pub fn check_kernel_half(&self) {
+        self.manager.lock().check_kernel_half();
+    }
+
+    /// Returns the handle that identifies the address space in context
+    /// switches.
+    pub fn handle(&self) -> AddressSpaceHandle {
+        // The level 4 table of an address space never changes.
+        unsafe {
+            let page_table_address = self.manager.without_locking().get_page_table_address();
+
+            AddressSpaceHandle::from_page_table_address(page_table_address)
+        }
     }
 
     /// Maps the given page in the address space.
-    pub fn map_page(&mut self, page_address: VirtualAddress) {
+    ///
+    /// Returns an error if the page can't be mapped with the flags of its
+    /// segment.
+    pub fn map_page(&self, page_address: VirtualAddress) -> Result<(), MappingError> {
+        let segment_flags = {
+            self.get_segment(MemoryArea::new(page_address, 0))
+                .map(|segment| segment.flags)
+        };
+
+        if let Some(segment_flags) = segment_flags {
+            self.manager.lock().map_page(page_address, segment_flags)
+        } else {
+            self.handle_out_of_segment(MemoryArea::new(page_address, 0))
+        }
+    }
+
+    /// Maps `count` consecutive pages starting at the given page in the
+    /// address space.
+    ///
+    /// This is faster than mapping the pages one by one. Returns an error if
+    /// the pages can't be mapped with the flags of their segment.
+    pub fn map_range(
+        &self,
+        first_page_address: VirtualAddress,
+        count: usize
+    ) -> Result<(), MappingError> {
+        let area = MemoryArea::new(first_page_address, count * PAGE_SIZE);
+        let segment_flags = { self.get_segment(area).map(|segment| segment.flags) };
+
+        if let Some(segment_flags) = segment_flags {
+            self.manager
+                .lock()
+                .map_range(first_page_address, count, segment_flags)
+        } else {
+            self.handle_out_of_segment(area)
+        }
+    }
+
+    /// Maps the given page to the given frame in the address space.
+    ///
+    /// The mapping takes over the reference to the frame held by the caller.
+    /// Returns an error if the page can't be mapped with the flags of its
+    /// segment.
+    pub fn map_page_to(
+        &self,
+        page_address: VirtualAddress,
+        frame_address: PhysicalAddress
+    ) -> Result<(), MappingError> {
         let segment_flags = {
             self.get_segment(MemoryArea::new(page_address, 0))
                 .map(|segment| segment.flags)
         };
 
         if let Some(segment_flags) = segment_flags {
-            self.manager.map_page(page_address, segment_flags);
+            self.manager
+                .lock()
+                .map_page_at(page_address, frame_address, segment_flags)
         } else {
-            self.handle_out_of_segment(MemoryArea::new(page_address, 0));
+            self.handle_out_of_segment(MemoryArea::new(page_address, 0))
         }
     }
 
@@ -148,8 +699,36 @@ impl AddressSpace {
     ///
     /// # Safety
     /// - Nothing should reference the unmapped pages.
-    pub unsafe fn unmap_page(&mut self, start_address: VirtualAddress) {
-        self.manager.unmap_page(start_address);
+    pub unsafe fn unmap_page(&self, start_address: VirtualAddress) {
+        self.manager.lock().unmap_page(start_address);
+    }
+
+    /// Unmaps `count` consecutive pages starting at the given page in the
+    /// address space, skipping pages that aren't mapped.
+    ///
+    /// # Safety
+    /// - Nothing should reference the unmapped pages.
+    pub unsafe fn unmap_range(&self, first_page_address: VirtualAddress, count: usize) {
+        self.manager.lock().unmap_range(first_page_address, count);
+    }
+
+    /// Unmaps the given page in the address space and returns the frame it
+    /// was mapped to.
+    ///
+    /// The reference of the mapping to the frame is passed to the caller.
+    ///
+    /// # Safety
+    /// - Nothing should reference the unmapped pages.
+    pub unsafe fn unmap_page_keeping_frame(
+        &self,
+        page_address: VirtualAddress
+    ) -> Option<PhysicalAddress> {
+        let mut manager = self.manager.lock();
+        let frame_address = manager.translate_address(page_address)?;
+
+        manager.unmap_page_without_freeing(page_address);
+
+        Some(frame_address)
     }
 
     /// Creates a new kernel stack.
@@ -157,30 +736,81 @@ impl AddressSpace {
         <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::create_kernel_stack(id, self)
     }
 
-    /// Creates a new user stack.
-    pub fn create_user_stack(&mut self, id: ThreadID) -> Stack {
-        <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::create_user_stack(id, self)
+    /// Creates a new user stack with the given maximum size.
+    ///
+    /// The size is rounded up to whole pages and the stack is placed in the
+    /// first free part of the user stack area, leaving an unmapped guard page
+    /// below it. Returns `None` if the size is zero or too large, or if there
+    /// is no room left.
+    pub fn create_user_stack(&mut self, size: usize) -> Option<Stack> {
+        let size = size.checked_add(PAGE_SIZE - 1)? / PAGE_SIZE * PAGE_SIZE;
+
+        if size == 0 || size > AddressSpace::user_stack_max_size() || !self.has_room_for(size) {
+            return None;
+        }
+
+        let area = self.find_free_area(AddressSpace::user_stack_area(), size + PAGE_SIZE)?;
+        let stack_area = MemoryArea::new(area.start_address() + PAGE_SIZE, size);
+
+        // Adding the stack segment can't fail once its memory is charged.
+        if !resource_group::charge(self.resource_group, size) {
+            return None;
+        }
+        self.reserved = size;
+
+        let stack = <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::create_user_stack(stack_area, self);
+
+        Some(stack)
+    }
+}
+
+/// Identifies the page tables of an address space.
+///
+/// Threads with equal handles run in the same address space, so switching
+/// between them doesn't need to switch the page tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressSpaceHandle(PhysicalAddress);
+
+impl AddressSpaceHandle {
+    /// Creates the handle of the address space with the given page table.
+    ///
+    /// # Safety
+    /// - Should only be called by architecture specific code.
+    pub unsafe fn from_page_table_address(address: PhysicalAddress) -> AddressSpaceHandle {
+        AddressSpaceHandle(address)
+    }
+
+    /// Returns the address of the page table of the address space.
+    pub fn page_table_address(&self) -> PhysicalAddress {
+        self.0
     }
 }
 
 /// All types of segments that are possible.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SegmentType {
     /// The content of the segment was read from a file.
     FromFile,
     /// The content of the segment is only in memory.
-    MemoryOnly
+    MemoryOnly,
+    /// The segment maps memory that is shared with other address spaces.
+    Shared(Arc<SharedMemory>),
+    /// The segment maps the memory of a device starting at the given physical
+    /// address.
+    Device(PhysicalAddress)
 }
 
 /// Represents a segment of memory in the address space.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Segment {
     /// The memory area of the segment.
     memory_area: MemoryArea<VirtualAddress>,
     /// The flags this segment is mapped with.
     flags: PageFlags,
     /// The type of the segment.
-    segment_type: SegmentType
+    segment_type: SegmentType,
+    /// Whether the segment may share frames with a forked address space.
+    copy_on_write: bool
 }
 
 impl Segment {
@@ -193,13 +823,19 @@ impl Segment {
         Segment {
             memory_area,
             flags,
-            segment_type
+            segment_type,
+            copy_on_write: false
         }
     }
 
     /// Returns true if the intersection of the segments is not empty.
     fn overlaps(&self, other: &Segment) -> bool {
-        self.memory_area.overlaps_with(other.memory_area)
+        self.overlaps_area(other.memory_area)
+    }
+
+    /// Returns true if the intersection with the area is not empty.
+    fn overlaps_area(&self, area: MemoryArea<VirtualAddress>) -> bool {
+        self.memory_area.overlaps_with(area)
     }
 
     /// Checks whether this segment contains the given memory area.
@@ -207,6 +843,36 @@ impl Segment {
         area.is_contained_in(self.memory_area)
     }
 
+    /// Returns true if the segment maps shared memory.
+    fn is_shared(&self) -> bool {
+        match self.segment_type {
+            SegmentType::Shared(_) => true,
+            _ => false
+        }
+    }
+
+    /// Returns true if the segment maps the memory of a device.
+    fn is_device(&self) -> bool {
+        match self.segment_type {
+            SegmentType::Device(_) => true,
+            _ => false
+        }
+    }
+
+    /// Returns true if the segment may map frames of the page cache.
+    fn may_be_cached(&self) -> bool {
+        match self.segment_type {
+            SegmentType::FromFile => !self.flags.contains(WRITABLE),
+            _ => false
+        }
+    }
+
+    /// Returns true if the segment may map frames that are also mapped
+    /// elsewhere, so that writes have to copy them first.
+    fn may_share_frames(&self) -> bool {
+        self.may_be_cached() || self.copy_on_write
+    }
+
     /// Returns the start address of this segment.
     fn start_address(&self) -> VirtualAddress {
         self.memory_area.start_address()
@@ -220,16 +886,19 @@ impl Segment {
     /// Unmaps this segment.
     fn unmap(&self, manager: &mut <arch::Current as Architecture>::AddressSpaceManager) {
         let pages_in_segment = (self.memory_area.length() - 1) / PAGE_SIZE + 1;
-        for page_num in 0..pages_in_segment {
-            unsafe {
-                match self.segment_type {
-                    SegmentType::FromFile => {
-                        manager.unmap_page(self.start_address() + page_num * PAGE_SIZE)
-                    },
-                    SegmentType::MemoryOnly => {
-                        manager.unmap_page_unchecked(self.start_address() + page_num * PAGE_SIZE)
-                    },
-                }
+
+        unsafe {
+            match self.segment_type {
+                SegmentType::FromFile | SegmentType::MemoryOnly => {
+                    manager.unmap_range(self.start_address(), pages_in_segment)
+                },
+                SegmentType::Shared(_) | SegmentType::Device(_) => {
+                    for page_num in 0..pages_in_segment {
+                        manager.unmap_page_without_freeing(
+                            self.start_address() + page_num * PAGE_SIZE
+                        );
+                    }
+                },
             }
         }
     }
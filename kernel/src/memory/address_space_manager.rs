@@ -1,22 +1,47 @@
 //! This module defines what an address space manager can do.
 
-use super::{MemoryArea, PageFlags, PhysicalAddress, VirtualAddress, PAGE_SIZE};
+use super::{MappingError, MemoryArea, PageFlags, PhysicalAddress, VirtualAddress, PAGE_SIZE};
 use multitasking::{Stack, ThreadID};
 use memory::AddressSpace;
 
 /// This trait should be implemented by any architecture specific address space
 /// manager.
 pub trait AddressSpaceManager: Send {
+    /// The size of a user mode stack if no other size is requested.
+    const USER_STACK_SIZE: usize;
+
+    /// The maximum size of a user mode stack.
+    const USER_STACK_MAX_SIZE: usize;
+
+    /// The address space area reserved for all user mode stacks.
+    const USER_STACK_AREA: MemoryArea<VirtualAddress>;
+
+    /// The address space area reserved for shared memory.
+    const SHARED_MEMORY_AREA: MemoryArea<VirtualAddress>;
+
     /// Creates a new address space manager.
     fn new() -> Self;
 
     /// Creates a new address space manager for the idle process.
     fn idle() -> Self;
 
+    /// Sets whether large ranges may be mapped with huge pages.
+    ///
+    /// This only affects ranges that are mapped afterwards.
+    fn set_huge_pages(&mut self, enabled: bool);
+
     /// Writes the data in `buffer` to the `address` in the target address
     /// space setting the given flags.
     fn write_to(&mut self, buffer: &[u8], address: VirtualAddress, flags: PageFlags);
 
+    /// Reads the data at `address` in the target address space into `buffer`.
+    ///
+    /// Returns false if a part of the area is not mapped.
+    fn read_from(&mut self, buffer: &mut [u8], address: VirtualAddress) -> bool;
+
+    /// Returns the physical address the given address is mapped to.
+    fn translate_address(&mut self, address: VirtualAddress) -> Option<PhysicalAddress>;
+
     /// Returns the address of the page table.
     ///
     /// # Safety
@@ -24,7 +49,37 @@ pub trait AddressSpaceManager: Send {
     unsafe fn get_page_table_address(&self) -> PhysicalAddress; // TODO: Find something better than exposing this publicly.
 
     /// Maps the given page in the managed address space.
-    fn map_page(&mut self, page_address: VirtualAddress, flags: PageFlags);
+    ///
+    /// Returns an error without mapping anything if the page can't be mapped
+    /// with the given flags.
+    fn map_page(
+        &mut self,
+        page_address: VirtualAddress,
+        flags: PageFlags
+    ) -> Result<(), MappingError>;
+
+    /// Maps `count` consecutive pages starting at `first_page_address` in the
+    /// managed address space.
+    ///
+    /// Returns an error without mapping anything if the pages can't be mapped
+    /// with the given flags.
+    fn map_range(
+        &mut self,
+        first_page_address: VirtualAddress,
+        count: usize,
+        flags: PageFlags
+    ) -> Result<(), MappingError>;
+
+    /// Maps the given page to the given frame in the managed address space.
+    ///
+    /// Returns an error without mapping anything if the page can't be mapped
+    /// with the given flags.
+    fn map_page_at(
+        &mut self,
+        page_address: VirtualAddress,
+        frame_address: PhysicalAddress,
+        flags: PageFlags
+    ) -> Result<(), MappingError>;
 
     /// Unmaps the given page in the managed address space.
     ///
@@ -39,18 +94,51 @@ pub trait AddressSpaceManager: Send {
     /// - Nothing should reference the unmapped pages.
     unsafe fn unmap_page_unchecked(&mut self, start_address: VirtualAddress); // TODO: Check if this is necessary.
 
+    /// Unmaps `count` consecutive pages starting at `first_page_address` in
+    /// the managed address space, skipping pages that aren't mapped.
+    ///
+    /// # Safety
+    /// - Nothing should reference the unmapped pages.
+    unsafe fn unmap_range(&mut self, first_page_address: VirtualAddress, count: usize);
+
+    /// Unmaps the given page in the managed address space without
+    /// deallocating the frame it was mapped to.
+    ///
+    /// # Safety
+    /// - Nothing should reference the unmapped pages.
+    unsafe fn unmap_page_without_freeing(&mut self, start_address: VirtualAddress);
+
+    /// Maps the pages of the given area of the active address space to the
+    /// same frames in the managed address space.
+    ///
+    /// Each shared frame gets another reference. If the flags are writable,
+    /// the pages are marked as copy-on-write in both address spaces instead.
+    fn share_copy_on_write(&mut self, area: MemoryArea<VirtualAddress>, flags: PageFlags);
+
+    /// Checks that the managed address space shares the kernel half with all
+    /// other address spaces.
+    ///
+    /// This panics if it doesn't.
+    fn check_kernel_half(&mut self);
+
     /// Creates a new kernel stack.
     ///
     /// This assumes that the given thread id is unused.
     fn create_kernel_stack(id: ThreadID, address_space: &mut AddressSpace) -> Stack;
 
-    /// Creates a new user mode stack.
+    /// Creates a new user mode stack that occupies the given area.
     ///
-    /// This assumes that the given thread id is unused.
-    fn create_user_stack(id: ThreadID, address_space: &mut AddressSpace) -> Stack;
+    /// This assumes that the area is page aligned and not part of a segment.
+    fn create_user_stack(
+        area: MemoryArea<VirtualAddress>,
+        address_space: &mut AddressSpace
+    ) -> Stack;
 
-    /// Creates a new idle process stack.
-    fn create_idle_stack(cpu_id: usize) -> Stack;
+    /// Creates the kernel stack of a thread of the idle process.
+    ///
+    /// The stack is mapped in the currently active address space, which
+    /// should be the one of the idle process.
+    fn create_kernel_thread_stack(id: ThreadID) -> Stack;
 
     /// Zeroes the given area in the managed address space.
     fn zero(&mut self, area: MemoryArea<VirtualAddress>, flags: PageFlags) {
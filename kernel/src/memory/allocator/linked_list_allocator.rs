@@ -110,9 +110,12 @@ impl Node {
         let next_node_start = align(aligned_address + size, align_of::<Node>());
 
         // Map all the necessary pages.
-        while next_node_start + size_of::<Node>() > *end_address {
-            arch::Current::map_page(*end_address, READABLE | WRITABLE);
-            *end_address = (*end_address) + PAGE_SIZE;
+        let required_end_address = next_node_start + size_of::<Node>();
+        if required_end_address > *end_address {
+            let pages_to_map = (required_end_address - *end_address - 1) / PAGE_SIZE + 1;
+
+            arch::Current::map_range(*end_address, pages_to_map, READABLE | WRITABLE);
+            *end_address += pages_to_map * PAGE_SIZE;
         }
 
         if self.next_node.is_none()
@@ -167,10 +170,13 @@ impl Node {
                 // Shrink the heap.
                 let last_address =
                     VirtualAddress::from_usize(last_node as *mut Node as usize) + size_of::<Node>();
-                while (*end_address) - PAGE_SIZE > last_address {
-                    *end_address -= PAGE_SIZE;
+                if (*end_address) - PAGE_SIZE > last_address {
+                    let pages_to_unmap = ((*end_address) - PAGE_SIZE - last_address - 1)
+                        / PAGE_SIZE + 1;
+
+                    *end_address -= pages_to_unmap * PAGE_SIZE;
                     unsafe {
-                        arch::Current::unmap_page(*end_address);
+                        arch::Current::unmap_range(*end_address, pages_to_unmap);
                     }
                 }
             }
@@ -5,42 +5,48 @@ mod linked_list_allocator;
 use self::linked_list_allocator::LinkedListAllocator;
 use alloc::allocator::{GlobalAlloc, Layout, Opaque};
 use arch::{self, Architecture};
+use memory::tracking::{self, Kind};
 use memory::{Address, VirtualAddress};
 use sync::mutex::Mutex;
+use sync::LockStats;
 
 pub struct Allocator;
 
 unsafe impl GlobalAlloc for Allocator {
     // TODO: Read more on this trait and possibly make it more efficient.
     unsafe fn alloc(&self, layout: Layout) -> *mut Opaque {
-        ALLOCATOR
+        let ptr = ALLOCATOR
             .lock()
-            .allocate_first_fit(layout.size(), layout.align()) as *mut Opaque
+            .allocate_first_fit(layout.size(), layout.align());
+
+        tracking::record_allocation(Kind::Heap, ptr as usize, layout.size());
+
+        ptr as *mut Opaque
     }
 
     unsafe fn dealloc(&self, ptr: *mut Opaque, layout: Layout) {
+        tracking::record_deallocation(Kind::Heap, ptr as usize);
+
         ALLOCATOR
             .lock()
             .free(ptr as *mut u8, layout.size(), layout.align());
     }
 }
 
+/// The contention statistics of the kernel heap allocator.
+static ALLOCATOR_STATS: LockStats = LockStats::new("ALLOCATOR");
+
 lazy_static! {
     /// The kernel heap allocator.
     static ref ALLOCATOR: Mutex<LinkedListAllocator> =
-        Mutex::new(LinkedListAllocator::new(arch::Current::HEAP_AREA));
+        Mutex::with_stats(LinkedListAllocator::new(arch::Current::HEAP_AREA), &ALLOCATOR_STATS);
 }
 
 /// Aligns the given address to the given alignment.
 ///
 /// The alignment must be a power of two.
 fn align(address: VirtualAddress, alignment: usize) -> VirtualAddress {
-    debug_assert!(alignment.is_power_of_two());
-
-    if address.as_usize() % alignment == 0 {
-        address
-    } else {
-        let alignment_bitmask = !(alignment - 1);
-        VirtualAddress::from_usize((address.as_usize() & alignment_bitmask) + alignment)
-    }
+    address
+        .align_up(alignment)
+        .expect("Heap addresses can always be aligned.")
 }
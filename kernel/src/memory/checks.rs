@@ -0,0 +1,56 @@
+//! This module checks the invariants of the page tables for debugging.
+//!
+//! The checks only run with the `page_table_checks` feature. The current page
+//! table is checked after the memory initialization, periodically while the
+//! processors are idle and whenever a process requests it. The latter two also
+//! check that all address spaces share the kernel half.
+
+use arch::{self, Architecture};
+use core::time::Duration;
+use multitasking;
+use sync::time::Timestamp;
+use sync::Mutex;
+
+/// The time in seconds between two periodic checks.
+const CHECK_INTERVAL_SECS: u64 = 1;
+
+lazy_static! {
+    /// The time when the next periodic check is due.
+    static ref NEXT_CHECK: Mutex<Timestamp> =
+        Mutex::new(Timestamp::from_duration(Duration::new(0, 0)));
+}
+
+/// Checks the current page table.
+///
+/// This panics with the first entry that violates an invariant.
+pub fn check() {
+    arch::Current::check_page_tables();
+}
+
+/// Checks the current page table and the kernel half of all address spaces.
+///
+/// # Note
+/// This locks the process list, so it must not be held by the caller.
+pub fn check_all() {
+    check();
+    multitasking::check_kernel_halves();
+}
+
+/// Checks all page tables, if the last check was long enough ago.
+pub fn check_periodically() {
+    let now = Timestamp::get_current();
+
+    {
+        let mut next_check = NEXT_CHECK.lock();
+
+        if now < *next_check {
+            return;
+        }
+
+        *next_check = now
+            .offset(Duration::from_secs(CHECK_INTERVAL_SECS))
+            .unwrap_or(now);
+    }
+
+    check_all();
+}
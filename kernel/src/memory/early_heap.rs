@@ -0,0 +1,80 @@
+//! Provides allocations before the heap exists.
+//!
+//! The early heap is a bump allocator over an area reserved right after the
+//! kernel. Allocations are never freed. When the free list is initialized,
+//! only the used part of the area stays reserved and the rest becomes free
+//! memory, so no more allocations are possible after that.
+
+use super::{Address, MemoryArea, PhysicalAddress, PAGE_SIZE};
+use arch::{self, Architecture};
+use core::mem::{align_of, size_of};
+use core::{ptr, slice};
+use sync::Mutex;
+
+/// The state of the early heap.
+struct EarlyHeap {
+    /// The number of bytes in use.
+    used: usize,
+    /// Whether the early heap was finished.
+    finished: bool
+}
+
+/// The state of the early heap.
+static EARLY_HEAP: Mutex<EarlyHeap> = Mutex::new(EarlyHeap {
+    used: 0,
+    finished: false
+});
+
+/// Allocates a slice containing `count` copies of `value`.
+///
+/// # Panics
+/// Panics if the early heap is exhausted or was already finished.
+pub fn allocate_slice<T: Copy>(count: usize, value: T) -> &'static mut [T] {
+    let area = arch::Current::get_early_heap_area();
+    let mut early_heap = EARLY_HEAP.lock();
+
+    assert!(
+        !early_heap.finished,
+        "The early heap can't be used after the free list was initialized."
+    );
+
+    let start = (early_heap.used + align_of::<T>() - 1) / align_of::<T>() * align_of::<T>();
+    let end = count
+        .checked_mul(size_of::<T>())
+        .and_then(|size| size.checked_add(start))
+        .expect("The early heap allocation is too large.");
+
+    assert!(end <= area.length(), "The early heap is exhausted.");
+
+    early_heap.used = end;
+
+    unsafe {
+        let pointer: *mut T = (area.start_address() + start).to_virtual().as_mut_ptr();
+
+        for i in 0..count {
+            ptr::write(pointer.offset(i as isize), value);
+        }
+
+        slice::from_raw_parts_mut(pointer, count)
+    }
+}
+
+/// Returns the page aligned part of the early heap that is in use.
+pub fn get_used_area() -> MemoryArea<PhysicalAddress> {
+    let area = arch::Current::get_early_heap_area();
+    let used = EARLY_HEAP.lock().used;
+
+    let end = (area.start_address() + used)
+        .align_up(PAGE_SIZE)
+        .expect("The early heap reaches the end of the address space.");
+
+    MemoryArea::from_start_and_end(area.start_address(), end)
+}
+
+/// Prevents further allocations and returns the part of the early heap that
+/// must stay reserved.
+pub fn finish() -> MemoryArea<PhysicalAddress> {
+    EARLY_HEAP.lock().finished = true;
+
+    get_used_area()
+}
@@ -0,0 +1,72 @@
+//! Backs large anonymous memory regions with huge pages.
+//!
+//! When a process maps a run of anonymous memory that is aligned to and at
+//! least as large as a huge page (2 MiB on x86_64), the architecture backs it
+//! with a single huge page if enough consecutive frames are free. A huge page
+//! takes a single TLB entry instead of hundreds. It is split back into normal
+//! pages when only a part of it is unmapped or its permissions change.
+//!
+//! `thp=off` on the kernel command line disables huge pages for all
+//! processes. A single process can opt out with the `EXEC_NO_HUGE_PAGES` flag
+//! of the exec syscall. `/proc/hugepages` shows how they are used.
+
+use alloc::string::String;
+use arch::{self, Architecture};
+use boot;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use procfs;
+
+/// Statistics about the huge pages.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Statistics {
+    /// The number of huge pages that are currently mapped.
+    pub mapped: usize,
+    /// The number of huge pages that were split into normal pages.
+    pub split: usize,
+    /// The number of times a huge page couldn't be allocated.
+    pub failed: usize
+}
+
+/// Whether new address spaces use huge pages.
+static ENABLED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Reads whether huge pages are enabled from the command line.
+pub fn init() {
+    assert_has_not_been_called!("Huge pages should only be initialized once.");
+
+    let enabled = match boot::get_option_value("thp") {
+        Some("on") | None => true,
+        Some("off") => false,
+        Some(value) => {
+            warn!("Invalid option thp={}, using huge pages.", value);
+            true
+        }
+    };
+
+    if !enabled {
+        info!("Huge pages are disabled.");
+    }
+
+    ENABLED.store(enabled, Ordering::Relaxed);
+
+    procfs::register("hugepages", describe);
+}
+
+/// Returns true if new address spaces use huge pages.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Generates the content of `/proc/hugepages`.
+fn describe() -> String {
+    let statistics = arch::Current::get_huge_page_statistics();
+    let mut text = String::new();
+
+    writeln!(text, "enabled: {}", is_enabled()).unwrap();
+    writeln!(text, "mapped: {}", statistics.mapped).unwrap();
+    writeln!(text, "split: {}", statistics.split).unwrap();
+    writeln!(text, "failed: {}", statistics.failed).unwrap();
+
+    text
+}
@@ -0,0 +1,185 @@
+//! Tests the free physical memory during boot.
+//!
+//! With `memtest` on the kernel command line, every free frame of the memory
+//! map is tested before the frame allocator takes it over. Every frame is
+//! filled with a few fixed patterns and with its own addresses and read back.
+//! Frames that don't return what was written are recorded as defective and
+//! left out of the memory map, so they are never allocated.
+//!
+//! This catches stuck bits and broken address lines, but not faults that only
+//! show up under load or after some time.
+
+use super::{Address, MemoryArea, PhysicalAddress, PAGE_SIZE};
+use arch::{self, Architecture};
+use boot;
+use core::ptr;
+use sync::Mutex;
+
+/// The maximum number of defective areas that are recorded.
+///
+/// Defective frames that don't fit are merged into the last area.
+pub const MAX_BAD_AREAS: usize = 16;
+
+/// The patterns every word is tested with.
+const PATTERNS: [u64; 4] = [
+    0x0000_0000_0000_0000,
+    0xffff_ffff_ffff_ffff,
+    0x5555_5555_5555_5555,
+    0xaaaa_aaaa_aaaa_aaaa
+];
+
+/// The areas that failed the test, sorted by their address.
+struct BadAreas {
+    /// The recorded areas.
+    areas: [MemoryArea<PhysicalAddress>; MAX_BAD_AREAS],
+    /// The number of recorded areas.
+    len: usize
+}
+
+impl BadAreas {
+    /// Creates an empty record.
+    const fn new() -> BadAreas {
+        BadAreas {
+            areas: [MemoryArea::new(PhysicalAddress::from_const(0), 0); MAX_BAD_AREAS],
+            len: 0
+        }
+    }
+
+    /// Records the defective frame.
+    ///
+    /// The frame must lie above all frames recorded so far.
+    fn add(&mut self, frame: MemoryArea<PhysicalAddress>) {
+        if self.len > 0 {
+            let last = &mut self.areas[self.len - 1];
+
+            // If there is no room left, the frames in between are excluded,
+            // too.
+            if last.end_address() == frame.start_address() || self.len == MAX_BAD_AREAS {
+                *last = MemoryArea::from_start_and_end(last.start_address(), frame.end_address());
+                return;
+            }
+        }
+
+        self.areas[self.len] = frame;
+        self.len += 1;
+    }
+}
+
+/// The areas that failed the test.
+static BAD_AREAS: Mutex<BadAreas> = Mutex::new(BadAreas::new());
+
+/// Tests the free memory if `memtest` is on the kernel command line.
+///
+/// This must run before the frame allocator is initialized.
+pub fn run() {
+    if !boot::has_option("memtest") {
+        return;
+    }
+
+    info!("Testing the free memory...");
+
+    let mut tested_frames = 0;
+    let mut bad_frames = 0;
+
+    for area in boot::get_memory_map() {
+        let mut frame_address = area.start_address();
+
+        while frame_address < area.end_address() {
+            let base = frame_address.as_usize() as u64;
+            let passed = unsafe {
+                arch::Current::access_frame(frame_address, |words| test_words(words, base))
+            };
+
+            if !passed {
+                warn!("The frame at {:?} is defective.", frame_address);
+                BAD_AREAS
+                    .lock()
+                    .add(MemoryArea::new(frame_address, PAGE_SIZE));
+                bad_frames += 1;
+            }
+
+            tested_frames += 1;
+            frame_address += PAGE_SIZE;
+        }
+    }
+
+    info!(
+        "Tested {} frames, {} of them are defective.",
+        tested_frames, bad_frames
+    );
+}
+
+/// Returns the areas that failed the test.
+///
+/// Unused entries are empty.
+pub fn get_bad_areas() -> [MemoryArea<PhysicalAddress>; MAX_BAD_AREAS] {
+    BAD_AREAS.lock().areas
+}
+
+/// Tests the words, which start at the physical address `base`.
+///
+/// Returns false if any of them doesn't keep a written value.
+fn test_words(words: &mut [u64], base: u64) -> bool {
+    let address_of = |index: usize| base.wrapping_add((index * 8) as u64);
+
+    PATTERNS
+        .iter()
+        .all(|&pattern| test_pattern(words, |_| pattern))
+        && test_pattern(words, &address_of)
+        && test_pattern(words, |index| !address_of(index))
+}
+
+/// Fills the words with the values the pattern returns for their index and
+/// checks that they read back the same.
+fn test_pattern<F: Fn(usize) -> u64>(words: &mut [u64], pattern: F) -> bool {
+    for (index, word) in words.iter_mut().enumerate() {
+        unsafe { ptr::write_volatile(word, pattern(index)) };
+    }
+
+    words
+        .iter()
+        .enumerate()
+        .all(|(index, word)| unsafe { ptr::read_volatile(word) } == pattern(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that working memory passes.
+    #[test]
+    fn test_working_memory() {
+        let mut words = [0x1234u64; 512];
+
+        assert!(test_words(&mut words, 0x1000));
+        assert_eq!(words[1], !0x1008);
+    }
+
+    /// Tests that adjacent frames are merged and that frames beyond the
+    /// limit are merged into the last area.
+    #[test]
+    fn test_bad_areas() {
+        let frame = |number: usize| {
+            MemoryArea::new(PhysicalAddress::from_usize(number * PAGE_SIZE), PAGE_SIZE)
+        };
+        let mut bad_areas = BadAreas::new();
+
+        bad_areas.add(frame(1));
+        bad_areas.add(frame(2));
+        bad_areas.add(frame(5));
+
+        assert_eq!(bad_areas.len, 2);
+        assert_eq!(bad_areas.areas[0].length(), 2 * PAGE_SIZE);
+        assert_eq!(bad_areas.areas[1].start_address(), frame(5).start_address());
+
+        for number in 0..MAX_BAD_AREAS {
+            bad_areas.add(frame(10 + 2 * number));
+        }
+
+        assert_eq!(bad_areas.len, MAX_BAD_AREAS);
+        assert_eq!(
+            bad_areas.areas[MAX_BAD_AREAS - 1].end_address(),
+            frame(10 + 2 * (MAX_BAD_AREAS - 1)).end_address()
+        );
+    }
+}
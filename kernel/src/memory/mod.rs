@@ -3,6 +3,16 @@
 pub mod address_space;
 pub mod address_space_manager;
 pub mod allocator;
+pub mod checks;
+pub mod early_heap;
+pub mod huge_pages;
+pub mod memtest;
+pub mod numa;
+pub mod oom;
+pub mod page_cache;
+pub mod physical;
+pub mod shared;
+pub mod tracking;
 
 pub use self::address_space::AddressSpace;
 pub use self::address_space_manager::AddressSpaceManager;
@@ -33,6 +43,44 @@ pub trait Address: PartialOrd + Ord + Add<usize, Output = Self> + Sized + Clone
     fn offset_in_page(self) -> usize {
         self.as_usize() % PAGE_SIZE
     }
+
+    /// Adds `offset` to the address, returning `None` on overflow.
+    fn checked_add(self, offset: usize) -> Option<Self> {
+        self.as_usize().checked_add(offset).map(Self::from_usize)
+    }
+
+    /// Adds `offset` to the address, stopping at the highest address.
+    fn saturating_add(self, offset: usize) -> Self {
+        Self::from_usize(self.as_usize().saturating_add(offset))
+    }
+
+    /// Returns the distance from `base` to the address.
+    ///
+    /// Returns `None` if the address is below `base`.
+    fn checked_offset_from(self, base: Self) -> Option<usize> {
+        self.as_usize().checked_sub(base.as_usize())
+    }
+
+    /// Aligns the address to the next multiple of `alignment`, rounded up.
+    ///
+    /// Returns `None` if the aligned address doesn't fit into the address
+    /// type. `alignment` must be a power of two.
+    fn align_up(self, alignment: usize) -> Option<Self> {
+        debug_assert!(alignment.is_power_of_two());
+
+        self.as_usize()
+            .checked_add(alignment - 1)
+            .map(|address| Self::from_usize(address & !(alignment - 1)))
+    }
+
+    /// Checks whether the address is a multiple of `alignment`.
+    ///
+    /// `alignment` must be a power of two.
+    fn is_aligned_to(self, alignment: usize) -> bool {
+        debug_assert!(alignment.is_power_of_two());
+
+        self.as_usize() & (alignment - 1) == 0
+    }
 }
 
 /// Represents a physical address.
@@ -242,7 +290,7 @@ impl<AddressType: Address> MemoryArea<AddressType> {
     }
 
     /// Checks if the address is contained within the segment.
-    fn contains(&self, address: AddressType) -> bool {
+    pub fn contains(&self, address: AddressType) -> bool {
         self.start_address() <= address && address < self.end_address()
     }
 
@@ -271,16 +319,6 @@ impl MemoryArea<PhysicalAddress> {
     }
 }
 
-impl MemoryArea<VirtualAddress> {
-    /// Creates a constant empty default value for a memory area.
-    pub const fn const_default() -> MemoryArea<VirtualAddress> {
-        MemoryArea {
-            start_address: VirtualAddress::from_const(0),
-            length: 0
-        }
-    }
-}
-
 impl<AddressType: Address + fmt::Debug> fmt::Debug for MemoryArea<AddressType> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -306,19 +344,100 @@ bitflags! {
         /// Set if the page should be accessible from user mode.
         const USER_ACCESSIBLE = 1 << 4,
         /// Set if the page is currently present.
-        const PRESENT = 1 << 5
+        const PRESENT = 1 << 5,
+        /// Set if writes to the page may be combined and delayed, which
+        /// suits frame buffers. `NO_CACHE` takes precedence.
+        const WRITE_COMBINING = 1 << 6,
+        /// Set if writes to the page should reach memory right away, while
+        /// reads are still cached. The other cache flags take precedence.
+        const WRITE_THROUGH = 1 << 7
     }
 }
 
+/// The reasons why pages can't be mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingError {
+    /// The pages are not in the canonical part of the address space.
+    NonCanonicalAddress,
+    /// User accessible pages would lie in the kernel part of the address
+    /// space.
+    UserAccessibleKernelPage
+}
+
 /// Initializes the memory managing part of the kernel.
 #[cfg(not(test))]
 pub fn init() {
     assert_has_not_been_called!("Memory state should only be initialized once.");
 
+    memtest::run();
+
     arch::Current::memory_init();
+
+    if ::config::PAGE_TABLE_CHECKS {
+        checks::check();
+    }
 }
 
-/// This function gets called when the system is out of memory.
-pub fn oom() -> ! {
-    panic!("Out of memory!");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that additions past the highest address are detected.
+    #[test]
+    fn test_checked_add() {
+        let max = usize::max_value();
+        let address = VirtualAddress::from_usize(max - 0x10);
+
+        assert_eq!(address.checked_add(0x10), Some(VirtualAddress::from_usize(max)));
+        assert_eq!(address.checked_add(0x11), None);
+        assert_eq!(address.checked_add(max), None);
+        assert_eq!(address.checked_add(0), Some(address));
+    }
+
+    /// Tests that additions past the highest address stop there.
+    #[test]
+    fn test_saturating_add() {
+        let max = usize::max_value();
+        let address = PhysicalAddress::from_usize(max - 0x10);
+
+        assert_eq!(address.saturating_add(0x8), PhysicalAddress::from_usize(max - 0x8));
+        assert_eq!(address.saturating_add(max), PhysicalAddress::from_usize(max));
+    }
+
+    /// Tests offsets from addresses above and below the address.
+    #[test]
+    fn test_checked_offset_from() {
+        let base = VirtualAddress::from_usize(0x1000);
+
+        assert_eq!(VirtualAddress::from_usize(0x1234).checked_offset_from(base), Some(0x234));
+        assert_eq!(base.checked_offset_from(base), Some(0));
+        assert_eq!(VirtualAddress::from_usize(0xfff).checked_offset_from(base), None);
+    }
+
+    /// Tests rounding up to alignments, including at the highest address.
+    #[test]
+    fn test_align_up() {
+        let max = usize::max_value();
+        let aligned = PhysicalAddress::from_usize(0x2000);
+
+        assert_eq!(aligned.align_up(PAGE_SIZE), Some(aligned));
+        assert_eq!(PhysicalAddress::from_usize(0x1001).align_up(PAGE_SIZE), Some(aligned));
+        assert_eq!(PhysicalAddress::from_usize(0x1fff).align_up(PAGE_SIZE), Some(aligned));
+        assert_eq!(aligned.align_up(8), Some(aligned));
+
+        let last_page = PhysicalAddress::from_usize(max - (PAGE_SIZE - 1));
+        assert_eq!(last_page.align_up(PAGE_SIZE), Some(last_page));
+        assert_eq!((last_page + 1).align_up(PAGE_SIZE), None);
+        assert_eq!(PhysicalAddress::from_usize(max).align_up(PAGE_SIZE), None);
+    }
+
+    /// Tests alignment checks.
+    #[test]
+    fn test_is_aligned_to() {
+        assert!(VirtualAddress::from_usize(0).is_aligned_to(PAGE_SIZE));
+        assert!(VirtualAddress::from_usize(0x3000).is_aligned_to(PAGE_SIZE));
+        assert!(!VirtualAddress::from_usize(0x3008).is_aligned_to(PAGE_SIZE));
+        assert!(VirtualAddress::from_usize(0x3008).is_aligned_to(8));
+        assert!(VirtualAddress::from_usize(usize::max_value()).is_aligned_to(1));
+    }
 }
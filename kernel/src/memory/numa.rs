@@ -0,0 +1,373 @@
+//! Knows which memory and CPUs belong to which NUMA node.
+//!
+//! The architecture reads the topology from the firmware. Every memory range
+//! and CPU belongs to a proximity domain, and the distances between the
+//! domains tell how expensive it is to access memory of another domain.
+//! Machines without that information are treated as a single node that
+//! contains all memory.
+//!
+//! The memory ranges of the nodes are the zones of the frame allocator. It
+//! prefers the zone of the node the allocating CPU belongs to, which places
+//! per-CPU structures and the memory of user processes on their local node.
+//! `/proc/numa` and the `get_node_info` syscall show the topology.
+
+use super::{Address, MemoryArea, PhysicalAddress, PAGE_SIZE};
+use alloc::string::String;
+use alloc::Vec;
+use arch::{self, Architecture};
+use boot;
+use core::cmp::{max, min};
+use core::fmt::Write;
+use procfs;
+use sync::OnceCell;
+
+/// The maximum number of nodes that are told apart.
+///
+/// Nodes beyond that are ignored.
+pub const MAX_NODES: usize = 64;
+
+/// The distance of a node to itself, relative to which other distances are
+/// given.
+pub const LOCAL_DISTANCE: u8 = 10;
+
+/// The distance between two nodes if the firmware doesn't provide one.
+pub const REMOTE_DISTANCE: u8 = 20;
+
+/// A memory range of a proximity domain as reported by the firmware.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryAffinity {
+    /// The proximity domain of the range.
+    pub domain: u32,
+    /// The physical memory of the range.
+    pub area: MemoryArea<PhysicalAddress>
+}
+
+/// A CPU of a proximity domain as reported by the firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuAffinity {
+    /// The proximity domain of the CPU.
+    pub domain: u32,
+    /// The ID of the CPU, as returned by `get_cpu_id`.
+    pub cpu_id: u32
+}
+
+/// The distances between the proximity domains as reported by the firmware.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Distances {
+    /// The number of domains in the matrix.
+    pub count: usize,
+    /// The distance from domain `i` to domain `j` at `i * count + j`.
+    pub matrix: Vec<u8>
+}
+
+/// A NUMA node.
+#[derive(Debug, Clone)]
+pub struct Node {
+    /// The proximity domain of the node.
+    pub domain: u32,
+    /// The physical memory of the node.
+    pub memory: Vec<MemoryArea<PhysicalAddress>>,
+    /// The IDs of the CPUs of the node.
+    pub cpus: Vec<u32>
+}
+
+impl Node {
+    /// Returns the size of the memory of the node in bytes.
+    pub fn memory_size(&self) -> usize {
+        self.memory.iter().map(|area| area.length()).sum()
+    }
+
+    /// Returns the address of the first whole frame of the area that belongs
+    /// to the node.
+    pub fn first_frame_in(&self, area: MemoryArea<PhysicalAddress>) -> Option<PhysicalAddress> {
+        self.memory
+            .iter()
+            .filter_map(|range| {
+                let start = max(area.start_address(), range.start_address()).align_up(PAGE_SIZE)?;
+                let end = min(area.end_address(), range.end_address());
+
+                if start < end && end - start >= PAGE_SIZE {
+                    Some(start)
+                } else {
+                    None
+                }
+            })
+            .min()
+    }
+}
+
+/// The information about a node that the `get_node_info` syscall returns.
+#[repr(C)]
+pub struct NodeInfo {
+    /// The size of the memory of the node in bytes.
+    pub memory: u64,
+    /// The number of CPUs of the node.
+    pub cpu_count: u64,
+    /// The distances to the other nodes.
+    pub distances: [u8; MAX_NODES]
+}
+
+/// The NUMA nodes of the machine.
+#[derive(Debug)]
+pub struct Topology {
+    /// The nodes, sorted by their proximity domain.
+    nodes: Vec<Node>,
+    /// The distance from node `i` to node `j` at `i * nodes.len() + j`.
+    distances: Vec<u8>
+}
+
+impl Topology {
+    /// Creates the topology from the affinities reported by the firmware.
+    pub fn new(
+        memory: &[MemoryAffinity],
+        cpus: &[CpuAffinity],
+        distances: Option<&Distances>
+    ) -> Topology {
+        let mut domains: Vec<u32> = memory
+            .iter()
+            .map(|affinity| affinity.domain)
+            .chain(cpus.iter().map(|affinity| affinity.domain))
+            .collect();
+        domains.sort();
+        domains.dedup();
+
+        if domains.len() > MAX_NODES {
+            warn!("Ignoring {} NUMA nodes.", domains.len() - MAX_NODES);
+            domains.truncate(MAX_NODES);
+        }
+
+        let nodes: Vec<Node> = domains
+            .iter()
+            .map(|&domain| Node {
+                domain,
+                memory: memory
+                    .iter()
+                    .filter(|affinity| affinity.domain == domain)
+                    .map(|affinity| affinity.area)
+                    .collect(),
+                cpus: cpus
+                    .iter()
+                    .filter(|affinity| affinity.domain == domain)
+                    .map(|affinity| affinity.cpu_id)
+                    .collect()
+            })
+            .collect();
+
+        let mut matrix = Vec::with_capacity(nodes.len() * nodes.len());
+        for from in &nodes {
+            for to in &nodes {
+                matrix.push(domain_distance(distances, from.domain, to.domain));
+            }
+        }
+
+        Topology {
+            nodes,
+            distances: matrix
+        }
+    }
+
+    /// Creates the topology of a machine with a single node that contains
+    /// the given memory.
+    pub fn single(memory: Vec<MemoryArea<PhysicalAddress>>) -> Topology {
+        let mut nodes = Vec::with_capacity(1);
+        nodes.push(Node {
+            domain: 0,
+            memory,
+            cpus: Vec::new()
+        });
+
+        Topology {
+            nodes,
+            distances: [LOCAL_DISTANCE].to_vec()
+        }
+    }
+
+    /// Returns the nodes.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Returns the distance from one node to another.
+    pub fn distance(&self, from: usize, to: usize) -> u8 {
+        self.distances[from * self.nodes.len() + to]
+    }
+
+    /// Returns the index of the node the CPU belongs to.
+    pub fn node_of_cpu(&self, cpu_id: u32) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| node.cpus.contains(&cpu_id))
+    }
+
+    /// Returns the index of the node the physical address belongs to.
+    pub fn node_of_address(&self, address: PhysicalAddress) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| node.memory.iter().any(|area| area.contains(address)))
+    }
+
+    /// Returns the information about the node for the `get_node_info`
+    /// syscall.
+    pub fn node_info(&self, index: usize) -> Option<NodeInfo> {
+        let node = self.nodes.get(index)?;
+        let mut distances = [0; MAX_NODES];
+
+        for (other, distance) in distances.iter_mut().enumerate().take(self.nodes.len()) {
+            *distance = self.distance(index, other);
+        }
+
+        Some(NodeInfo {
+            memory: node.memory_size() as u64,
+            cpu_count: node.cpus.len() as u64,
+            distances
+        })
+    }
+}
+
+/// Returns the distance between two proximity domains.
+fn domain_distance(distances: Option<&Distances>, from: u32, to: u32) -> u8 {
+    let (from, to) = (from as usize, to as usize);
+
+    match distances {
+        Some(distances) if from < distances.count && to < distances.count => {
+            distances.matrix[from * distances.count + to]
+        },
+        _ if from == to => LOCAL_DISTANCE,
+        _ => REMOTE_DISTANCE
+    }
+}
+
+/// The NUMA nodes of the machine.
+static TOPOLOGY: OnceCell<Topology> = OnceCell::new();
+
+/// Reads the NUMA topology of the machine.
+///
+/// Until this is called, the frame allocator doesn't know about nodes.
+pub fn init() {
+    assert_has_not_been_called!("The NUMA topology should only be read once.");
+
+    let topology = arch::Current::get_numa_topology()
+        .unwrap_or_else(|| Topology::single(boot::get_memory_map().collect()));
+
+    if topology.nodes().len() > 1 {
+        info!("Found {} NUMA nodes.", topology.nodes().len());
+    } else {
+        debug!("The machine has a single NUMA node.");
+    }
+
+    assert!(TOPOLOGY.set(topology).is_ok());
+
+    procfs::register("numa", describe);
+}
+
+/// Returns the NUMA topology, once it was read.
+pub fn get_topology() -> Option<&'static Topology> {
+    TOPOLOGY.get()
+}
+
+/// Returns the index of the node of the current CPU.
+pub fn current_node() -> usize {
+    get_topology()
+        .and_then(|topology| topology.node_of_cpu(arch::Current::get_cpu_id() as u32))
+        .unwrap_or(0)
+}
+
+/// Generates the content of `/proc/numa`.
+fn describe() -> String {
+    let topology = TOPOLOGY.expect("The NUMA topology wasn't read yet.");
+    let mut text = String::new();
+
+    for (index, node) in topology.nodes().iter().enumerate() {
+        writeln!(text, "node {} (domain {}):", index, node.domain).unwrap();
+        writeln!(text, "  memory: {} MiB", node.memory_size() / 1024 / 1024).unwrap();
+        write!(text, "  cpus:").unwrap();
+        for cpu in &node.cpus {
+            write!(text, " {}", cpu).unwrap();
+        }
+        writeln!(text).unwrap();
+        write!(text, "  distances:").unwrap();
+        for other in 0..topology.nodes().len() {
+            write!(text, " {}", topology.distance(index, other)).unwrap();
+        }
+        writeln!(text).unwrap();
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the memory affinity of the given domain.
+    fn memory(domain: u32, start: usize, length: usize) -> MemoryAffinity {
+        MemoryAffinity {
+            domain,
+            area: MemoryArea::new(PhysicalAddress::from_usize(start), length)
+        }
+    }
+
+    /// Returns the CPU affinity of the given domain.
+    fn cpu(domain: u32, cpu_id: u32) -> CpuAffinity {
+        CpuAffinity { domain, cpu_id }
+    }
+
+    #[test]
+    fn nodes_are_sorted_by_domain() {
+        let topology = Topology::new(
+            &[memory(1, 0x100000, 0x100000), memory(0, 0, 0x100000)],
+            &[cpu(1, 2), cpu(0, 0), cpu(1, 3)],
+            None
+        );
+
+        assert_eq!(topology.nodes().len(), 2);
+        assert_eq!(topology.nodes()[0].domain, 0);
+        assert_eq!(topology.nodes()[1].cpus[..], [2, 3]);
+        assert_eq!(topology.node_of_cpu(3), Some(1));
+        assert_eq!(
+            topology.node_of_address(PhysicalAddress::from_usize(0x180000)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn distances_are_taken_from_the_matrix() {
+        let distances = Distances {
+            count: 3,
+            matrix: [10, 21, 31, 21, 10, 17, 31, 17, 10].to_vec()
+        };
+        let topology = Topology::new(
+            &[memory(0, 0, 0x1000), memory(2, 0x1000, 0x1000)],
+            &[],
+            Some(&distances)
+        );
+
+        assert_eq!(topology.distance(0, 1), 31);
+        assert_eq!(topology.distance(1, 1), 10);
+    }
+
+    #[test]
+    fn distances_have_defaults() {
+        let topology = Topology::new(&[memory(0, 0, 0x1000)], &[cpu(5, 1)], None);
+
+        assert_eq!(topology.distance(0, 0), LOCAL_DISTANCE);
+        assert_eq!(topology.distance(0, 1), REMOTE_DISTANCE);
+    }
+
+    #[test]
+    fn first_frame_is_in_the_node() {
+        let node = Node {
+            domain: 0,
+            memory: [MemoryArea::new(PhysicalAddress::from_usize(0x3800), 0x4000)].to_vec(),
+            cpus: Vec::new()
+        };
+        let area = |start, length| MemoryArea::new(PhysicalAddress::from_usize(start), length);
+
+        assert_eq!(
+            node.first_frame_in(area(0, 0x10000)),
+            Some(PhysicalAddress::from_usize(0x4000))
+        );
+        assert_eq!(node.first_frame_in(area(0x7000, 0x1000)), None);
+        assert_eq!(node.first_frame_in(area(0x8000, 0x1000)), None);
+    }
+}
@@ -0,0 +1,139 @@
+//! Handles running out of physical memory.
+//!
+//! Memory is reclaimed from userspace by killing a process, whose address
+//! space is released once its last thread is gone. Releasing memory needs
+//! locks that the code allocating a frame may already hold, such as those of
+//! the page table or the process list, so the frame allocator can't reclaim
+//! memory itself. Instead it holds back `RESERVED_FRAMES` frames. When all
+//! other frames are gone, it opens the reserve, so that allocations still
+//! succeed while the reaper thread, which holds no locks, reclaims memory.
+//!
+//! The reaper first trims the page cache. If that doesn't free enough frames,
+//! it kills the user process with the lowest priority, preferring the one with
+//! the largest address space among equal priorities, and waits until it is
+//! gone before killing another one. Essential processes, like init, are never
+//! killed. The kernel heap doesn't have to be
+//! shrunk, because it unmaps its unused pages as soon as they are freed.
+//!
+//! The kernel only panics if the reserve runs out as well or if there is no
+//! user process left to kill.
+
+use super::{page_cache, PAGE_SIZE};
+use arch::{self, Architecture};
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use multitasking::{self, ProcessID};
+use sync::Mutex;
+
+/// The number of frames that are only allocated while memory is reclaimed.
+const RESERVED_FRAMES: usize = 256;
+
+/// The number of free frames at which the reserve is closed again.
+const RECOVERED_FRAMES: usize = 2 * RESERVED_FRAMES;
+
+/// Whether the reserved frames may be allocated.
+static RESERVE_OPEN: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// The process that was killed last to reclaim its memory.
+static VICTIM: Mutex<Option<ProcessID>> = Mutex::new(None);
+
+/// Returns the number of frames that may not be allocated right now.
+pub fn reserved_frames() -> usize {
+    if RESERVE_OPEN.load(Ordering::Acquire) {
+        0
+    } else {
+        RESERVED_FRAMES
+    }
+}
+
+/// Called by the frame allocator when it couldn't allocate a frame.
+///
+/// `free_frames` is the number of frames that were left. If there were any,
+/// they are reserved and the reserve is opened, so that the allocation can be
+/// retried. Otherwise the kernel panics.
+pub fn out_of_frames(free_frames: usize) {
+    if free_frames == 0 {
+        out_of_memory();
+    }
+
+    RESERVE_OPEN.store(true, Ordering::Release);
+}
+
+/// Reclaims memory if the reserve is open.
+///
+/// This is called regularly by the reaper thread. It must not be called while
+/// holding any locks.
+pub fn reclaim() {
+    if !RESERVE_OPEN.load(Ordering::Acquire) {
+        return;
+    }
+
+    page_cache::trim();
+
+    if arch::Current::get_free_memory_size() / PAGE_SIZE >= RECOVERED_FRAMES {
+        RESERVE_OPEN.store(false, Ordering::Release);
+        info!("Recovered from running out of memory.");
+        return;
+    }
+
+    let mut victim = VICTIM.lock();
+
+    if let Some(pid) = *victim {
+        if multitasking::is_dying(pid) {
+            // Its memory is released once its threads are gone.
+            return;
+        }
+    }
+
+    match multitasking::kill_for_memory() {
+        Some(pid) => {
+            warn!("Out of memory, killed {:?} to reclaim its memory.", pid);
+            *victim = Some(pid);
+        },
+        None => out_of_memory()
+    }
+}
+
+/// Selects the process to kill out of the given processes, which are given
+/// as their ID, priority and the size of their address space.
+///
+/// Returns `None` if there are no processes.
+pub fn select_victim<I>(processes: I) -> Option<ProcessID>
+where
+    I: Iterator<Item = (ProcessID, i32, usize)>
+{
+    processes
+        .min_by(|&(_, priority, size), &(_, other_priority, other_size)| {
+            priority.cmp(&other_priority).then(other_size.cmp(&size))
+        })
+        .map(|(pid, _, _)| pid)
+}
+
+/// Panics, because no memory is left that could be reclaimed.
+pub fn out_of_memory() -> ! {
+    panic!("Out of memory!");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the process with the lowest priority is selected and the
+    /// largest one among equal priorities.
+    #[test]
+    fn test_select_victim() {
+        let processes = [
+            (ProcessID::from(1), 3, 0x4000),
+            (ProcessID::from(2), 1, 0x1000),
+            (ProcessID::from(3), 1, 0x8000),
+            (ProcessID::from(4), 0, 0x2000),
+            (ProcessID::from(5), 0, 0x2000)
+        ];
+
+        assert_eq!(select_victim(processes.iter().cloned()), Some(4.into()));
+        assert_eq!(
+            select_victim(processes[..3].iter().cloned()),
+            Some(3.into())
+        );
+        assert_eq!(select_victim(processes[..0].iter().cloned()), None);
+    }
+}
@@ -0,0 +1,97 @@
+//! Shares the read-only pages of executables between processes.
+//!
+//! When a read-only page of an initramfs file is loaded for a process, its
+//! frame is remembered by the file name and the offset of the page in the
+//! file. Later processes started from the same file map that frame instead
+//! of loading their own copy. Address spaces copy such a frame before the
+//! kernel writes to it, so the other processes don't see the write.
+//!
+//! The cache holds a reference to every frame in it. Frames that no process
+//! maps anymore are dropped from the cache by `trim`.
+
+use super::PhysicalAddress;
+use alloc::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::Vec;
+use arch::{self, Architecture};
+use sync::Mutex;
+
+lazy_static! {
+    /// The cached frames of every file by the offset of their page.
+    static ref FILES: Mutex<BTreeMap<String, BTreeMap<u64, PhysicalAddress>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Returns the cached frame for the page at the given offset of the file.
+///
+/// A reference to the frame is added for the caller.
+pub fn get(file: &str, offset: u64) -> Option<PhysicalAddress> {
+    let files = FILES.lock();
+    let frame = *files.get(file)?.get(&offset)?;
+
+    // The reference is added while the cache is locked, so that `trim` can't
+    // drop the frame in between.
+    arch::Current::add_frame_reference(frame);
+
+    Some(frame)
+}
+
+/// Caches the frame for the page at the given offset of the file.
+///
+/// The cache takes over the reference of the caller to the frame.
+pub fn insert(file: &str, offset: u64, frame: PhysicalAddress) {
+    let mut files = FILES.lock();
+
+    if !files.contains_key(file) {
+        files.insert(String::from(file), BTreeMap::new());
+    }
+
+    let pages = files.get_mut(file).unwrap();
+
+    if pages.contains_key(&offset) {
+        // Another process cached the page in the meantime.
+        unsafe {
+            arch::Current::release_frame(frame);
+        }
+    } else {
+        pages.insert(offset, frame);
+    }
+}
+
+/// Drops the frames from the cache that aren't mapped anymore.
+///
+/// Returns the number of dropped frames.
+pub fn trim() -> usize {
+    let mut files = FILES.lock();
+    let mut dropped = 0;
+
+    for pages in files.values_mut() {
+        let unused: Vec<u64> = pages
+            .iter()
+            .filter(|&(_, &frame)| !arch::Current::is_frame_shared(frame))
+            .map(|(&offset, _)| offset)
+            .collect();
+
+        for offset in unused {
+            let frame = pages.remove(&offset).unwrap();
+
+            unsafe {
+                arch::Current::release_frame(frame);
+            }
+
+            dropped += 1;
+        }
+    }
+
+    let empty_files: Vec<String> = files
+        .iter()
+        .filter(|&(_, pages)| pages.is_empty())
+        .map(|(file, _)| file.clone())
+        .collect();
+
+    for file in empty_files {
+        files.remove(&file);
+    }
+
+    dropped
+}
@@ -0,0 +1,306 @@
+//! Accesses physical memory at its kernel address.
+//!
+//! Every physical address has a kernel address at a fixed offset, see
+//! `PhysicalAddress::to_virtual`, but only some of these pages are mapped.
+//! `PhysBox` and `PhysSlice` check that the accessed area is aligned and lies
+//! within the part of the kernel address space reserved for physical memory.
+//! They map the pages that aren't mapped yet and refuse pages that are mapped
+//! executable, so physical memory is only ever accessed as data.
+//!
+//! Reads and writes are volatile, which makes both types usable for memory
+//! mapped devices. The mappings are shared between all boxes, so they stay
+//! when a box is dropped. Dropping a writable box fences its writes instead.
+
+use super::{Address, MemoryArea, PageFlags, PhysicalAddress, VirtualAddress, EXECUTABLE,
+            PAGE_SIZE, PRESENT, READABLE, WRITABLE};
+use arch::{self, Architecture};
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ptr;
+use core::slice;
+use core::sync::atomic::{fence, Ordering};
+
+/// A value of type `T` in physical memory.
+pub struct PhysBox<T: Copy> {
+    /// The accessed memory.
+    window: Window,
+    /// The type of the value.
+    value_type: PhantomData<T>
+}
+
+/// Consecutive values of type `T` in physical memory.
+pub struct PhysSlice<T: Copy> {
+    /// The accessed memory.
+    window: Window,
+    /// The number of values.
+    len: usize,
+    /// The type of the values.
+    value_type: PhantomData<T>
+}
+
+/// An area of physical memory that is accessible at its kernel address.
+struct Window {
+    /// The kernel address of the area.
+    address: VirtualAddress,
+    /// Whether the area may be written to.
+    writable: bool
+}
+
+impl Window {
+    /// Makes the physical area accessible with the given flags.
+    ///
+    /// Returns `None` if the area isn't aligned to `alignment`, lies outside of
+    /// the physical memory window or is already mapped in another way.
+    unsafe fn map(
+        area: MemoryArea<PhysicalAddress>,
+        alignment: usize,
+        flags: PageFlags
+    ) -> Option<Window> {
+        assert!(
+            !flags.contains(EXECUTABLE),
+            "Physical memory can't be mapped executable."
+        );
+
+        let start = area.start_address();
+        let limit = arch::Current::PHYSICAL_WINDOW_SIZE;
+
+        if !is_valid_area(start, area.length(), alignment, limit) {
+            return None;
+        }
+
+        let address = start.to_virtual();
+        let kernel_area = MemoryArea::new(address, area.length());
+
+        // The initramfs is mapped inside of the window.
+        if kernel_area.overlaps_with(arch::Current::get_initramfs_area()) {
+            return None;
+        }
+
+        arch::Current::map_physical_area(area, flags);
+
+        // All present pages are readable.
+        let required_flags = (flags - READABLE) | PRESENT;
+
+        let mut page_address = address.page_align_down();
+        while page_address < kernel_area.end_address() {
+            let page_flags = arch::Current::get_page_flags(page_address);
+
+            if !page_flags.contains(required_flags) || page_flags.contains(EXECUTABLE) {
+                return None;
+            }
+
+            page_address += PAGE_SIZE;
+        }
+
+        Some(Window {
+            address,
+            writable: flags.contains(WRITABLE)
+        })
+    }
+
+    /// Makes the physical area accessible for reading during boot.
+    ///
+    /// Returns `None` if the area isn't aligned to `alignment` or lies outside
+    /// of the memory mapped during boot.
+    fn early(area: MemoryArea<PhysicalAddress>, alignment: usize) -> Option<Window> {
+        let start = area.start_address();
+        let limit = arch::Current::BOOT_MAPPED_SIZE;
+
+        if is_valid_area(start, area.length(), alignment, limit) {
+            Some(Window {
+                address: start.to_virtual(),
+                writable: false
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a pointer to the value of type `T` at the given offset.
+    fn pointer<T>(&self, offset: usize) -> *mut T {
+        (self.address + offset).as_mut_ptr()
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        if self.writable {
+            fence(Ordering::SeqCst);
+        }
+    }
+}
+
+/// Returns true if the area of `length` bytes at `start` starts at a multiple
+/// of `alignment` and ends below `limit`.
+fn is_valid_area(start: PhysicalAddress, length: usize, alignment: usize, limit: usize) -> bool {
+    start.is_aligned_to(alignment)
+        && start
+            .checked_add(length)
+            .map_or(false, |end| end.as_usize() <= limit)
+}
+
+impl<T: Copy> PhysBox<T> {
+    /// Maps the value at the physical address with the given flags.
+    ///
+    /// The value can only be written if the flags contain `WRITABLE`.
+    /// Returns `None` if the value is misaligned, lies outside of the
+    /// physical memory window or is already mapped without the flags.
+    ///
+    /// # Safety
+    /// - The memory must hold a valid value of type `T`.
+    /// - Nothing else may rely on the value not changing while it's writable.
+    pub unsafe fn map(address: PhysicalAddress, flags: PageFlags) -> Option<PhysBox<T>> {
+        let area = MemoryArea::new(address, size_of::<T>());
+
+        Window::map(area, align_of::<T>(), flags).map(PhysBox::new)
+    }
+
+    /// Accesses the value at the physical address for reading during boot,
+    /// before the memory is initialized.
+    ///
+    /// Returns `None` if the value is misaligned or lies outside of the
+    /// memory mapped during boot.
+    ///
+    /// # Safety
+    /// - The memory must hold a valid value of type `T`.
+    pub unsafe fn early(address: PhysicalAddress) -> Option<PhysBox<T>> {
+        let area = MemoryArea::new(address, size_of::<T>());
+
+        Window::early(area, align_of::<T>()).map(PhysBox::new)
+    }
+
+    /// Creates a box for the value in the window.
+    fn new(window: Window) -> PhysBox<T> {
+        PhysBox {
+            window,
+            value_type: PhantomData
+        }
+    }
+
+    /// Reads the value.
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(self.window.pointer(0)) }
+    }
+
+    /// Writes the value.
+    ///
+    /// Panics if the box isn't writable.
+    pub fn write(&self, value: T) {
+        assert!(self.window.writable, "Writing to read-only memory.");
+
+        unsafe { ptr::write_volatile(self.window.pointer(0), value) }
+    }
+}
+
+impl<T: Copy> PhysSlice<T> {
+    /// Maps `len` consecutive values at the physical address with the given
+    /// flags.
+    ///
+    /// The values can only be written if the flags contain `WRITABLE`.
+    /// Returns `None` if the values are misaligned, lie outside of the
+    /// physical memory window or are already mapped without the flags.
+    ///
+    /// # Safety
+    /// - The memory must hold valid values of type `T`.
+    /// - Nothing else may rely on the values not changing while they're
+    ///   writable.
+    pub unsafe fn map(
+        address: PhysicalAddress,
+        len: usize,
+        flags: PageFlags
+    ) -> Option<PhysSlice<T>> {
+        let area = MemoryArea::new(address, len.checked_mul(size_of::<T>())?);
+
+        Window::map(area, align_of::<T>(), flags).map(|window| PhysSlice::new(window, len))
+    }
+
+    /// Accesses `len` consecutive values at the physical address for reading
+    /// during boot, before the memory is initialized.
+    ///
+    /// Returns `None` if the values are misaligned or lie outside of the
+    /// memory mapped during boot.
+    ///
+    /// # Safety
+    /// - The memory must hold valid values of type `T`.
+    pub unsafe fn early(address: PhysicalAddress, len: usize) -> Option<PhysSlice<T>> {
+        let area = MemoryArea::new(address, len.checked_mul(size_of::<T>())?);
+
+        Window::early(area, align_of::<T>()).map(|window| PhysSlice::new(window, len))
+    }
+
+    /// Creates a slice of `len` values in the window.
+    fn new(window: Window, len: usize) -> PhysSlice<T> {
+        PhysSlice {
+            window,
+            len,
+            value_type: PhantomData
+        }
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads the value at the given index.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn read(&self, index: usize) -> T {
+        assert!(index < self.len, "Index {} out of bounds.", index);
+
+        unsafe { ptr::read_volatile(self.window.pointer(index * size_of::<T>())) }
+    }
+
+    /// Writes the value at the given index.
+    ///
+    /// Panics if the index is out of bounds or the slice isn't writable.
+    pub fn write(&self, index: usize, value: T) {
+        assert!(index < self.len, "Index {} out of bounds.", index);
+        assert!(self.window.writable, "Writing to read-only memory.");
+
+        unsafe { ptr::write_volatile(self.window.pointer(index * size_of::<T>()), value) }
+    }
+
+    /// Returns the values as a regular slice.
+    ///
+    /// The slice isn't read with volatile reads, so this is only meant for
+    /// memory that nothing changes while it's borrowed, such as firmware
+    /// tables.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.window.pointer(0), self.len) }
+    }
+}
+
+// The boxes only hand out copies of the values.
+unsafe impl<T: Copy + Send> Send for PhysBox<T> {}
+unsafe impl<T: Copy + Send> Sync for PhysBox<T> {}
+unsafe impl<T: Copy + Send> Send for PhysSlice<T> {}
+unsafe impl<T: Copy + Send> Sync for PhysSlice<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that misaligned areas and areas beyond the limit are refused.
+    #[test]
+    fn test_valid_areas() {
+        let address = PhysicalAddress::from_usize;
+
+        assert!(is_valid_area(address(0x1000), 8, 8, 0x2000));
+        assert!(is_valid_area(address(0x1ff8), 8, 8, 0x2000));
+        assert!(is_valid_area(address(0x1001), 8, 1, 0x2000));
+        assert!(!is_valid_area(address(0x1004), 8, 8, 0x2000));
+        assert!(!is_valid_area(address(0x1ffc), 8, 4, 0x2000));
+        assert!(!is_valid_area(
+            address(usize::max_value() - 3),
+            8,
+            4,
+            usize::max_value()
+        ));
+    }
+}
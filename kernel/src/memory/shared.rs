@@ -0,0 +1,50 @@
+//! This module implements memory that can be mapped into multiple address
+//! spaces.
+//!
+//! The frames of shared memory are owned by the `SharedMemory` object instead
+//! of the address spaces it is mapped into. Every mapping holds a reference
+//! to it, so the frames are only released after the last mapping is gone.
+
+use super::{PhysicalAddress, PAGE_SIZE};
+use alloc::Vec;
+use arch::{self, Architecture};
+
+/// Physical memory that can be shared between address spaces.
+#[derive(Debug)]
+pub struct SharedMemory {
+    /// The frames backing the memory.
+    frames: Vec<PhysicalAddress>
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        for &frame in &self.frames {
+            unsafe {
+                arch::Current::release_frame(frame);
+            }
+        }
+    }
+}
+
+impl SharedMemory {
+    /// Allocates zeroed shared memory of the given number of pages.
+    pub fn new(pages: usize) -> SharedMemory {
+        let mut frames = Vec::with_capacity(pages);
+
+        for _ in 0..pages {
+            frames.push(arch::Current::allocate_zeroed_frame());
+        }
+
+        SharedMemory { frames }
+    }
+
+    /// Returns the frames backing the memory.
+    pub fn frames(&self) -> &[PhysicalAddress] {
+        &self.frames
+    }
+
+    /// Returns the size of the memory in bytes.
+    pub fn len(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+}
@@ -0,0 +1,371 @@
+//! This module tracks the live allocations of the kernel for hunting leaks.
+//!
+//! The tracking only happens with the `alloc_tracking` feature. Every heap
+//! allocation and every page frame allocation is recorded together with the
+//! return addresses of its callers, its site. `dump` logs the outstanding
+//! allocations grouped by their sites.
+//!
+//! The records are kept in fixed size tables, so that recording never
+//! allocates. Allocations that don't fit into the tables are only counted.
+
+use alloc::Vec;
+use config;
+use ksymbol;
+use memory::{Address, VirtualAddress};
+use multitasking::backtrace::collect_callers;
+use sync::Mutex;
+
+/// The number of return addresses that make up a site.
+///
+/// The first ones usually belong to the allocator itself.
+const SITE_DEPTH: usize = 8;
+
+/// The maximum number of distinct sites per kind of allocation.
+const MAX_SITES: usize = 256;
+
+/// The maximum number of live allocations tracked per kind of allocation.
+const MAX_ALLOCATIONS: usize = 4096;
+
+/// The kinds of allocations that are tracked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// An allocation on the kernel heap.
+    Heap,
+    /// An allocation of a page frame.
+    Frame
+}
+
+/// The return addresses of the callers of an allocation.
+type Site = [VirtualAddress; SITE_DEPTH];
+
+/// A live allocation.
+#[derive(Clone, Copy)]
+struct Allocation {
+    /// The address of the allocation, zero if the entry is empty.
+    address: usize,
+    /// The size of the allocation in bytes.
+    size: u32,
+    /// The index of the site of the allocation.
+    site: u16
+}
+
+impl Allocation {
+    /// An empty entry in the table.
+    const EMPTY: Allocation = Allocation {
+        address: 0,
+        size: 0,
+        site: 0
+    };
+}
+
+/// The live allocations of one kind.
+struct Tracker {
+    /// The live allocations in a hash table with linear probing.
+    allocations: [Allocation; MAX_ALLOCATIONS],
+    /// The sites in a hash table with linear probing.
+    sites: [Option<Site>; MAX_SITES],
+    /// The number of allocations in the table.
+    length: usize,
+    /// The number of live allocations that aren't in the table.
+    untracked: usize
+}
+
+impl Tracker {
+    /// Creates an empty tracker.
+    const fn new() -> Tracker {
+        Tracker {
+            allocations: [Allocation::EMPTY; MAX_ALLOCATIONS],
+            sites: [None; MAX_SITES],
+            length: 0,
+            untracked: 0
+        }
+    }
+
+    /// Records an allocation at the given address.
+    fn insert(&mut self, address: usize, size: usize, site: &Site) {
+        // One entry always stays empty, so that every probe sequence ends.
+        let site = match self.site_index(site) {
+            Some(site) if self.length < MAX_ALLOCATIONS - 1 => site,
+            _ => {
+                self.untracked += 1;
+                return;
+            }
+        };
+
+        let start = hash(address) % MAX_ALLOCATIONS;
+        let index = (0..MAX_ALLOCATIONS)
+            .map(|i| (start + i) % MAX_ALLOCATIONS)
+            .find(|&index| self.allocations[index].address == 0)
+            .expect("The allocation table always has an empty entry.");
+
+        self.allocations[index] = Allocation {
+            address,
+            size: size as u32,
+            site
+        };
+        self.length += 1;
+    }
+
+    /// Removes the allocation at the given address.
+    ///
+    /// Returns false if the allocation wasn't tracked.
+    fn remove(&mut self, address: usize) -> bool {
+        let start = hash(address) % MAX_ALLOCATIONS;
+        let mut index = match (0..MAX_ALLOCATIONS)
+            .map(|i| (start + i) % MAX_ALLOCATIONS)
+            .take_while(|&index| self.allocations[index].address != 0)
+            .find(|&index| self.allocations[index].address == address)
+        {
+            Some(index) => index,
+            None => return false
+        };
+
+        // Entries after the removed one are moved back, so that no probe
+        // sequence is interrupted by the new gap.
+        let mut next = index;
+        loop {
+            next = (next + 1) % MAX_ALLOCATIONS;
+
+            let entry = self.allocations[next];
+            if entry.address == 0 {
+                break;
+            }
+
+            let home = hash(entry.address) % MAX_ALLOCATIONS;
+            let distance_to_gap = (index + MAX_ALLOCATIONS - home) % MAX_ALLOCATIONS;
+            let distance_to_entry = (next + MAX_ALLOCATIONS - home) % MAX_ALLOCATIONS;
+
+            if distance_to_gap < distance_to_entry {
+                self.allocations[index] = entry;
+                index = next;
+            }
+        }
+
+        self.allocations[index] = Allocation::EMPTY;
+        self.length -= 1;
+
+        true
+    }
+
+    /// Returns the index of the given site, adding it if it is new.
+    ///
+    /// Returns `None` if the site is new and there's no space left.
+    fn site_index(&mut self, site: &Site) -> Option<u16> {
+        let start = site.iter().fold(0, |hash_value, address| {
+            hash(hash_value ^ address.as_usize())
+        }) % MAX_SITES;
+
+        for i in 0..MAX_SITES {
+            let index = (start + i) % MAX_SITES;
+
+            match self.sites[index] {
+                Some(ref existing) if existing == site => return Some(index as u16),
+                Some(_) => (),
+                None => {
+                    self.sites[index] = Some(*site);
+                    return Some(index as u16);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Sums up the live allocations per site into `summary`.
+    ///
+    /// `summary` needs a capacity of `MAX_SITES`, so that this doesn't
+    /// allocate.
+    fn summarize(&self, summary: &mut Vec<SiteSummary>) {
+        for (index, site) in self.sites.iter().enumerate() {
+            if let Some(site) = *site {
+                summary.push(SiteSummary {
+                    site,
+                    index: index as u16,
+                    count: 0,
+                    bytes: 0
+                });
+            }
+        }
+
+        for allocation in self.allocations.iter().filter(|entry| entry.address != 0) {
+            if let Some(entry) = summary
+                .iter_mut()
+                .find(|entry| entry.index == allocation.site)
+            {
+                entry.count += 1;
+                entry.bytes += allocation.size as usize;
+            }
+        }
+
+        summary.retain(|entry| entry.count > 0);
+    }
+}
+
+/// The live allocations of a site.
+struct SiteSummary {
+    /// The return addresses of the site.
+    site: Site,
+    /// The index of the site in the tracker.
+    index: u16,
+    /// The number of live allocations.
+    count: usize,
+    /// The size of the live allocations in bytes.
+    bytes: usize
+}
+
+/// The tracked heap allocations.
+static HEAP: Mutex<Tracker> = Mutex::new(Tracker::new());
+
+/// The tracked page frame allocations.
+static FRAMES: Mutex<Tracker> = Mutex::new(Tracker::new());
+
+/// Returns the tracker for the given kind of allocation.
+fn tracker(kind: Kind) -> &'static Mutex<Tracker> {
+    match kind {
+        Kind::Heap => &HEAP,
+        Kind::Frame => &FRAMES
+    }
+}
+
+/// Mixes the bits of the value, so that aligned addresses spread out.
+fn hash(value: usize) -> usize {
+    let value = value as u64;
+    let mixed = (value ^ (value >> 29)).wrapping_mul(0xbf58476d1ce4e5b9);
+
+    (mixed ^ (mixed >> 32)) as usize
+}
+
+/// Records an allocation of the given kind.
+///
+/// This must be called by the allocator itself, because the site starts at
+/// its caller.
+pub fn record_allocation(kind: Kind, address: usize, size: usize) {
+    if !config::ALLOC_TRACKING || address == 0 {
+        return;
+    }
+
+    let mut site = [VirtualAddress::from_usize(0); SITE_DEPTH];
+    collect_callers(&mut site);
+
+    tracker(kind).lock().insert(address, size, &site);
+}
+
+/// Records that the allocation of the given kind at the address was freed.
+pub fn record_deallocation(kind: Kind, address: usize) {
+    if !config::ALLOC_TRACKING {
+        return;
+    }
+
+    let mut tracker = tracker(kind).lock();
+
+    if !tracker.remove(address) && tracker.untracked > 0 {
+        tracker.untracked -= 1;
+    }
+}
+
+/// Logs the outstanding allocations grouped by their sites.
+///
+/// Returns false if the kernel doesn't track allocations.
+pub fn dump() -> bool {
+    if !config::ALLOC_TRACKING {
+        return false;
+    }
+
+    dump_kind(Kind::Heap, "heap allocations");
+    dump_kind(Kind::Frame, "page frames");
+
+    true
+}
+
+/// Logs the outstanding allocations of the given kind.
+fn dump_kind(kind: Kind, description: &str) {
+    // The summary is allocated up front, because the heap can't be used
+    // while the tracker is locked.
+    let mut summary = Vec::with_capacity(MAX_SITES);
+
+    let untracked = {
+        let tracker = tracker(kind).lock();
+        tracker.summarize(&mut summary);
+        tracker.untracked
+    };
+
+    summary.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+
+    let count: usize = summary.iter().map(|entry| entry.count).sum();
+    let bytes: usize = summary.iter().map(|entry| entry.bytes).sum();
+
+    info!(
+        "Outstanding {}: {} ({} bytes), {} untracked.",
+        description, count, bytes, untracked
+    );
+
+    for entry in summary {
+        info!("{} ({} bytes) from:", entry.count, entry.bytes);
+
+        for &address in entry
+            .site
+            .iter()
+            .take_while(|address| address.as_usize() != 0)
+        {
+            match ksymbol::resolve(address) {
+                Some(symbol) => info!("    {:?} ({})", address, symbol),
+                None => info!("    {:?} (unknown function)", address)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a site that only consists of the given address.
+    fn site(address: usize) -> Site {
+        let mut site = [VirtualAddress::from_usize(0); SITE_DEPTH];
+        site[0] = VirtualAddress::from_usize(address);
+        site
+    }
+
+    /// Tests that allocations are summed up per site and can be removed.
+    #[test]
+    fn test_insert_remove() {
+        let mut tracker = Tracker::new();
+
+        for i in 1..100 {
+            tracker.insert(i * 0x1000, 16, &site(i % 3));
+        }
+
+        for i in 1..50 {
+            assert!(tracker.remove(i * 0x1000));
+        }
+        assert!(!tracker.remove(0x1000));
+
+        let mut summary = Vec::with_capacity(MAX_SITES);
+        tracker.summarize(&mut summary);
+
+        assert_eq!(summary.len(), 3);
+        assert_eq!(summary.iter().map(|entry| entry.count).sum::<usize>(), 50);
+        assert_eq!(summary.iter().map(|entry| entry.bytes).sum::<usize>(), 800);
+
+        for i in 50..100 {
+            assert!(tracker.remove(i * 0x1000));
+        }
+        assert!(tracker.allocations.iter().all(|entry| entry.address == 0));
+    }
+
+    /// Tests that allocations beyond the capacity are only counted.
+    #[test]
+    fn test_overflow() {
+        let mut tracker = Tracker::new();
+
+        for i in 1..MAX_ALLOCATIONS + 10 {
+            tracker.insert(i * 8, 8, &site(1));
+        }
+        assert_eq!(tracker.untracked, 10);
+
+        for i in 1..MAX_SITES + 6 {
+            tracker.site_index(&site(i + 1));
+        }
+        assert_eq!(tracker.site_index(&site(MAX_SITES + 100)), None);
+    }
+}
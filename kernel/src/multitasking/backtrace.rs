@@ -0,0 +1,199 @@
+//! This module prints backtraces of user threads and the kernel.
+//!
+//! The backtraces rely on frame pointers, so they are a best-effort attempt
+//! and stop at the first frame that doesn't look valid.
+
+use super::get_current_process;
+use arch::{self, Architecture};
+use core::mem::size_of;
+use ksymbol;
+use memory::{Address, AddressSpace, VirtualAddress, PRESENT, USER_ACCESSIBLE};
+
+/// The maximum amount of frames printed in a backtrace.
+const MAX_FRAMES: usize = 32;
+
+/// The maximum distance in bytes above the stack pointer at which
+/// `collect_callers` still follows frames.
+const MAX_CALLER_DISTANCE: usize = 0x10000;
+
+/// Prints a backtrace of the current user thread.
+///
+/// The walk starts at the given program counter and follows the chain of
+/// frame pointers starting at `frame_pointer`.
+///
+/// # Note
+/// This locks the process list, so it must not be held by the caller.
+pub fn print_user_backtrace(program_counter: VirtualAddress, frame_pointer: VirtualAddress) {
+    let pcb = get_current_process();
+
+    match pcb.build_id {
+        Some(ref build_id) => error!("Backtrace of {} (build-id {}):", pcb.name, build_id),
+        None => error!("Backtrace of {}:", pcb.name)
+    }
+
+    walk_frames(
+        program_counter,
+        frame_pointer,
+        is_user_readable,
+        |index, address| print_user_frame(&pcb.address_space, index, address)
+    );
+}
+
+/// Prints a backtrace of the kernel.
+///
+/// The walk starts at the given program counter and follows the chain of
+/// frame pointers starting at `frame_pointer`. The addresses are resolved to
+/// function names using the kernel symbol table.
+pub fn print_kernel_backtrace(program_counter: VirtualAddress, frame_pointer: VirtualAddress) {
+    error!("Kernel backtrace:");
+
+    walk_frames(
+        program_counter,
+        frame_pointer,
+        is_kernel_readable,
+        print_kernel_frame
+    );
+}
+
+/// Stores a backtrace of the kernel in `frames` and returns the number of
+/// frames found.
+///
+/// The walk starts at the given program counter and follows the chain of
+/// frame pointers starting at `frame_pointer`.
+pub fn collect_kernel_backtrace(
+    program_counter: VirtualAddress,
+    frame_pointer: VirtualAddress,
+    frames: &mut [VirtualAddress]
+) -> usize {
+    let mut count = 0;
+
+    walk_frames(
+        program_counter,
+        frame_pointer,
+        is_kernel_readable,
+        |index, address| {
+            if index < frames.len() {
+                frames[index] = address;
+                count = index + 1;
+            }
+        }
+    );
+
+    count
+}
+
+/// Stores the return addresses of the callers of the calling function in
+/// `callers` and sets the remaining entries to zero.
+///
+/// Unlike the printed backtraces, this doesn't lock the page tables, so the
+/// allocators can use it. Instead only frames shortly above the current stack
+/// pointer are followed.
+#[inline(never)]
+pub fn collect_callers(callers: &mut [VirtualAddress]) {
+    let frame_pointer = arch::Current::get_frame_pointer();
+    let stack_pointer = &frame_pointer as *const _ as usize;
+
+    for caller in callers.iter_mut() {
+        *caller = VirtualAddress::from_usize(0);
+    }
+
+    walk_frames(
+        VirtualAddress::from_usize(0),
+        frame_pointer,
+        |address| {
+            address.as_usize() >= stack_pointer
+                && address.as_usize() - stack_pointer < MAX_CALLER_DISTANCE
+        },
+        |index, address| {
+            // The first frame is the missing program counter and the second
+            // one lies in the calling function.
+            if index >= 2 && index - 2 < callers.len() {
+                callers[index - 2] = address;
+            }
+        }
+    );
+}
+
+/// Calls `print` for the program counter and every return address found by
+/// following the frame pointers.
+fn walk_frames<R, P>(
+    program_counter: VirtualAddress,
+    frame_pointer: VirtualAddress,
+    is_readable: R,
+    mut print: P
+) where
+    R: Fn(VirtualAddress) -> bool,
+    P: FnMut(usize, VirtualAddress)
+{
+    print(0, program_counter);
+
+    let mut frame_pointer = frame_pointer;
+
+    for index in 1..MAX_FRAMES {
+        let return_address_location = match frame_pointer.checked_add(size_of::<usize>()) {
+            Some(location) => location,
+            None => break
+        };
+
+        if !frame_pointer.is_aligned_to(size_of::<usize>())
+            || !is_readable(frame_pointer)
+            || !is_readable(return_address_location)
+        {
+            break;
+        }
+
+        let (next_frame_pointer, return_address): (usize, usize) = unsafe {
+            (
+                *frame_pointer.as_ptr(),
+                *return_address_location.as_ptr()
+            )
+        };
+
+        if return_address == 0 {
+            break;
+        }
+
+        print(index, VirtualAddress::from_usize(return_address));
+
+        // The stack grows down, so the frames of callers are above.
+        if next_frame_pointer <= frame_pointer.as_usize() {
+            break;
+        }
+
+        frame_pointer = VirtualAddress::from_usize(next_frame_pointer);
+    }
+}
+
+/// Prints a single frame of a user backtrace.
+fn print_user_frame(address_space: &AddressSpace, index: usize, address: VirtualAddress) {
+    match address_space.segment_area_of(address) {
+        Some(segment) => error!(
+            "  #{:<2} {:?} (segment {:?} + {:#x})",
+            index,
+            address,
+            segment.start_address(),
+            address - segment.start_address()
+        ),
+        None => error!("  #{:<2} {:?} (outside of all segments)", index, address)
+    }
+}
+
+/// Prints a single frame of a kernel backtrace.
+fn print_kernel_frame(index: usize, address: VirtualAddress) {
+    match ksymbol::resolve(address) {
+        Some(symbol) => error!("  #{:<2} {:?} ({})", index, address, symbol),
+        None => error!("  #{:<2} {:?} (unknown function)", index, address)
+    }
+}
+
+/// Returns true if the given user address can be read without faulting.
+fn is_user_readable(address: VirtualAddress) -> bool {
+    arch::Current::is_userspace_address(address)
+        && arch::Current::get_page_flags(address).contains(PRESENT | USER_ACCESSIBLE)
+}
+
+/// Returns true if the given kernel address can be read without faulting.
+fn is_kernel_readable(address: VirtualAddress) -> bool {
+    !arch::Current::is_userspace_address(address)
+        && arch::Current::get_page_flags(address).contains(PRESENT)
+}
@@ -0,0 +1,42 @@
+//! This module defines the capabilities of processes.
+//!
+//! Capabilities grant access to privileged operations. A new process
+//! inherits the capabilities of its creator and capabilities can only ever
+//! be dropped, never regained.
+
+bitflags! {
+    /// A set of capabilities.
+    pub flags Capabilities: u64 {
+        /// Allows changing the root directory of the process.
+        const SET_ROOT = 1 << 0,
+        /// Allows tracing other processes.
+        const TRACE = 1 << 1,
+        /// Allows starting servers with the capabilities and hardware
+        /// resources requested by their manifest.
+        const SPAWN_SERVER = 1 << 2,
+        /// Allows registering services under any name.
+        const REGISTER_SERVICE = 1 << 3,
+        /// Allows suspending the machine.
+        const POWER = 1 << 4,
+        /// Allows taking CPUs offline and bringing them back online.
+        const CPU_HOTPLUG = 1 << 5,
+        /// Allows raising the priority of threads above the default priority.
+        const RAISE_PRIORITY = 1 << 6
+    }
+}
+
+/// Returns the capability with the given name.
+///
+/// The names are the lowercase names of the constants.
+pub fn from_name(name: &str) -> Option<Capabilities> {
+    match name {
+        "set_root" => Some(SET_ROOT),
+        "trace" => Some(TRACE),
+        "spawn_server" => Some(SPAWN_SERVER),
+        "register_service" => Some(REGISTER_SERVICE),
+        "power" => Some(POWER),
+        "cpu_hotplug" => Some(CPU_HOTPLUG),
+        "raise_priority" => Some(RAISE_PRIORITY),
+        _ => None
+    }
+}
@@ -0,0 +1,244 @@
+//! This module defines the table of open files of a process.
+
+use alloc::boxed::Box;
+use alloc::btree_map::BTreeMap;
+use console::{Console, ConsoleInput};
+use event_queue::{EventQueue, ReadyEvent};
+use file_handle::{FileHandle, OpenFlags, PollEvents, POLL_IN};
+use ipc::ring::RingEndpoint;
+use multitasking::signal::{self, SignalSet};
+use multitasking::wait_queue::WaitQueue;
+use timer::Timer;
+
+/// The type of a file descriptor.
+pub type FileDescriptor = usize;
+
+/// The descriptor of the standard input.
+pub const STDIN: FileDescriptor = 0;
+
+/// The descriptor of the standard output.
+pub const STDOUT: FileDescriptor = 1;
+
+/// The descriptor of the standard error output.
+pub const STDERR: FileDescriptor = 2;
+
+/// A file that was opened by a process.
+pub struct OpenFile {
+    /// The handle to the underlying file.
+    pub handle: Box<FileHandle>,
+    /// The flags the file was opened with.
+    pub flags: OpenFlags
+}
+
+impl OpenFile {
+    /// Creates a new open file from the handle and the flags.
+    pub fn new(handle: Box<FileHandle>, flags: OpenFlags) -> OpenFile {
+        OpenFile { handle, flags }
+    }
+
+    /// Returns true if operations on the file should not block.
+    pub fn is_nonblocking(&self) -> bool {
+        self.flags.contains(::file_handle::NONBLOCKING)
+    }
+}
+
+/// The kernel objects a file descriptor can refer to.
+pub enum Descriptor {
+    /// An open file.
+    File(OpenFile),
+    /// An event queue.
+    EventQueue(EventQueue),
+    /// An endpoint of a shared memory ring.
+    Ring(RingEndpoint),
+    /// A timer.
+    Timer(Timer),
+    /// A descriptor that receives the signals within the set.
+    Signals(SignalSet)
+}
+
+/// Maps file descriptors to the objects they refer to.
+pub struct DescriptorTable {
+    /// The objects referred to by the descriptors.
+    descriptors: BTreeMap<FileDescriptor, Descriptor>
+}
+
+impl DescriptorTable {
+    /// Creates a new empty descriptor table.
+    pub fn new() -> DescriptorTable {
+        DescriptorTable {
+            descriptors: BTreeMap::new()
+        }
+    }
+
+    /// Creates a new descriptor table with the standard streams opened.
+    pub fn with_standard_streams() -> DescriptorTable {
+        let mut table = DescriptorTable::new();
+
+        let input = OpenFile::new(Box::new(ConsoleInput::new()), OpenFlags::empty());
+        table.descriptors.insert(STDIN, Descriptor::File(input));
+
+        for &descriptor in &[STDOUT, STDERR] {
+            table.descriptors.insert(
+                descriptor,
+                Descriptor::File(OpenFile::new(Box::new(Console), OpenFlags::empty()))
+            );
+        }
+
+        table
+    }
+
+    /// Inserts the object into the table and returns its descriptor.
+    ///
+    /// The lowest unused descriptor is chosen.
+    pub fn insert(&mut self, object: Descriptor) -> FileDescriptor {
+        // UNOPTIMIZED
+        let mut descriptor = 0;
+        while self.descriptors.contains_key(&descriptor) {
+            descriptor += 1;
+        }
+
+        self.descriptors.insert(descriptor, object);
+
+        descriptor
+    }
+
+    /// Returns the file referred to by the descriptor.
+    pub fn file_mut(&mut self, descriptor: FileDescriptor) -> Option<&mut OpenFile> {
+        match self.descriptors.get_mut(&descriptor) {
+            Some(&mut Descriptor::File(ref mut file)) => Some(file),
+            _ => None
+        }
+    }
+
+    /// Returns the event queue referred to by the descriptor.
+    pub fn event_queue_mut(&mut self, descriptor: FileDescriptor) -> Option<&mut EventQueue> {
+        match self.descriptors.get_mut(&descriptor) {
+            Some(&mut Descriptor::EventQueue(ref mut queue)) => Some(queue),
+            _ => None
+        }
+    }
+
+    /// Returns the ring endpoint referred to by the descriptor.
+    pub fn ring(&self, descriptor: FileDescriptor) -> Option<&RingEndpoint> {
+        match self.descriptors.get(&descriptor) {
+            Some(&Descriptor::Ring(ref endpoint)) => Some(endpoint),
+            _ => None
+        }
+    }
+
+    /// Returns the timer referred to by the descriptor.
+    pub fn timer_mut(&mut self, descriptor: FileDescriptor) -> Option<&mut Timer> {
+        match self.descriptors.get_mut(&descriptor) {
+            Some(&mut Descriptor::Timer(ref mut timer)) => Some(timer),
+            _ => None
+        }
+    }
+
+    /// Returns the signal set of the signal descriptor.
+    pub fn signal_mask(&self, descriptor: FileDescriptor) -> Option<SignalSet> {
+        match self.descriptors.get(&descriptor) {
+            Some(&Descriptor::Signals(mask)) => Some(mask),
+            _ => None
+        }
+    }
+
+    /// Returns the events that are currently ready on the descriptor.
+    ///
+    /// Signal descriptors are readable while one of their signals is in
+    /// `pending_signals`.
+    /// Returns `None` if the descriptor refers to an event queue or nothing.
+    pub fn poll(
+        &mut self,
+        descriptor: FileDescriptor,
+        pending_signals: SignalSet
+    ) -> Option<PollEvents> {
+        match self.descriptors.get_mut(&descriptor) {
+            Some(&mut Descriptor::File(ref mut file)) => Some(file.handle.poll()),
+            Some(&mut Descriptor::Ring(ref endpoint)) => Some(endpoint.poll()),
+            Some(&mut Descriptor::Timer(ref mut timer)) => Some(timer.poll()),
+            Some(&mut Descriptor::Signals(mask)) => {
+                if pending_signals.intersects(mask) {
+                    Some(POLL_IN)
+                } else {
+                    Some(PollEvents::empty())
+                }
+            },
+            _ => None
+        }
+    }
+
+    /// Returns the queue that is woken when the events that are ready on the
+    /// descriptor may have changed.
+    ///
+    /// Returns `None` if the ready events never change or the descriptor
+    /// refers to an event queue or nothing.
+    pub fn wait_queue(&mut self, descriptor: FileDescriptor) -> Option<&WaitQueue> {
+        match self.descriptors.get_mut(&descriptor) {
+            Some(&mut Descriptor::File(ref mut file)) => file.handle.wait_queue(),
+            Some(&mut Descriptor::Ring(ref endpoint)) => Some(endpoint.wait_queue()),
+            Some(&mut Descriptor::Timer(ref mut timer)) => Some(timer.wait_queue()),
+            Some(&mut Descriptor::Signals(_)) => Some(&signal::RAISED),
+            _ => None
+        }
+    }
+
+    /// Collects the events of the event queue referred to by `descriptor`.
+    ///
+    /// Returns `None` if the descriptor does not refer to an event queue.
+    pub fn collect_events(
+        &mut self,
+        descriptor: FileDescriptor,
+        output: &mut [ReadyEvent],
+        pending_signals: SignalSet
+    ) -> Option<usize> {
+        // The queue is taken out of the table while the other descriptors
+        // are checked.
+        let mut queue = match self.descriptors.remove(&descriptor) {
+            Some(Descriptor::EventQueue(queue)) => queue,
+            Some(other) => {
+                self.descriptors.insert(descriptor, other);
+                return None;
+            },
+            None => return None
+        };
+
+        let count = queue.collect(
+            |registered, watch| {
+                if let Some(wait_queue) = self.wait_queue(registered) {
+                    watch.on(wait_queue);
+                }
+
+                self.poll(registered, pending_signals)
+            },
+            output
+        );
+
+        self.descriptors
+            .insert(descriptor, Descriptor::EventQueue(queue));
+
+        Some(count)
+    }
+
+    /// Removes the object referred to by the descriptor from the table.
+    ///
+    /// The event queues of the table lose their interest in the descriptor,
+    /// so that an object that reuses it isn't watched by them.
+    pub fn remove(&mut self, descriptor: FileDescriptor) -> Option<Descriptor> {
+        let object = self.descriptors.remove(&descriptor);
+
+        if object.is_some() {
+            for other in self.descriptors.values_mut() {
+                if let Descriptor::EventQueue(ref mut queue) = *other {
+                    queue.unregister(descriptor);
+                }
+            }
+        }
+
+        object
+    }
+
+    /// Returns the amount of open descriptors.
+    pub fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+}
@@ -0,0 +1,189 @@
+//! This module implements futexes.
+//!
+//! A futex lets a thread wait until another thread of its process changes a
+//! 32 bit value in memory. The waiting thread only sleeps if the value still
+//! has the value it expects, which makes it possible to build locks and
+//! one-time initialization in userspace that only enter the kernel when
+//! they actually have to wait.
+//!
+//! Futexes are private to a process, the same address in two processes
+//! names two different futexes.
+//!
+//! A thread is added to the waiters before it reads the value, so a thread
+//! that changes the value and then wakes the futex either lets the waiting
+//! thread see the new value or finds it among the waiters.
+
+use alloc::Vec;
+use memory::VirtualAddress;
+use multitasking::wait_queue::Waker;
+use multitasking::{ProcessID, ThreadID};
+use sync::Mutex;
+
+/// A thread that waits on a futex.
+struct Waiter {
+    /// The process of the thread.
+    pid: ProcessID,
+    /// The waiting thread.
+    thread: ThreadID,
+    /// The address of the value.
+    address: VirtualAddress,
+    /// Wakes the thread during its current wait.
+    waker: Waker,
+    /// Whether the thread was woken.
+    woken: bool
+}
+
+lazy_static! {
+    /// All threads that wait on a futex in the order they started waiting.
+    static ref WAITERS: Mutex<Vec<Waiter>> = Mutex::new(Vec::new());
+}
+
+/// Lets the thread wait on the futex at the address.
+///
+/// If the thread already waits on it, only the waker is replaced, so that a
+/// wake-up between two waits of the thread isn't lost. The caller has to
+/// check the value of the futex after the thread was added.
+pub fn wait(pid: ProcessID, thread: ThreadID, address: VirtualAddress, waker: Waker) {
+    let mut waiters = WAITERS.lock();
+
+    match waiters
+        .iter_mut()
+        .find(|waiter| waiter.pid == pid && waiter.thread == thread)
+    {
+        Some(waiter) => {
+            waiter.waker = waker;
+            return;
+        },
+        None => ()
+    }
+
+    waiters.push(Waiter {
+        pid,
+        thread,
+        address,
+        waker,
+        woken: false
+    });
+}
+
+/// Returns true if the waiting thread was woken.
+pub fn is_woken(pid: ProcessID, thread: ThreadID) -> bool {
+    WAITERS
+        .lock()
+        .iter()
+        .any(|waiter| waiter.pid == pid && waiter.thread == thread && waiter.woken)
+}
+
+/// Stops the thread from waiting.
+///
+/// This must be called once the thread stopped waiting, whether it was
+/// woken or not. Returns true if it was woken.
+pub fn stop_waiting(pid: ProcessID, thread: ThreadID) -> bool {
+    remove_waiter(&mut WAITERS.lock(), pid, thread)
+}
+
+/// Wakes up to `count` threads waiting on the futex at the address.
+///
+/// The threads that waited the longest are woken first. Returns the number
+/// of woken threads.
+pub fn wake(pid: ProcessID, address: VirtualAddress, count: usize) -> usize {
+    // The threads are made ready once the waiters are unlocked again.
+    let wakers = wake_waiters(&mut WAITERS.lock(), pid, address, count);
+
+    for waker in &wakers {
+        waker.wake();
+    }
+
+    wakers.len()
+}
+
+/// Removes the waiter for the thread and returns true if it was woken.
+fn remove_waiter(waiters: &mut Vec<Waiter>, pid: ProcessID, thread: ThreadID) -> bool {
+    match waiters
+        .iter()
+        .position(|waiter| waiter.pid == pid && waiter.thread == thread)
+    {
+        Some(index) => waiters.remove(index).woken,
+        None => false
+    }
+}
+
+/// Marks up to `count` of the waiters on the address as woken and returns
+/// their wakers.
+fn wake_waiters(
+    waiters: &mut Vec<Waiter>,
+    pid: ProcessID,
+    address: VirtualAddress,
+    count: usize
+) -> Vec<Waker> {
+    let mut wakers = Vec::new();
+
+    for waiter in waiters.iter_mut() {
+        if wakers.len() == count {
+            break;
+        }
+
+        if waiter.pid == pid && waiter.address == address && !waiter.woken {
+            waiter.woken = true;
+            wakers.push(waiter.waker);
+        }
+    }
+
+    wakers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::Address;
+
+    /// Returns a waiter that wasn't woken yet.
+    fn waiter(pid: usize, thread: usize, address: usize) -> Waiter {
+        Waiter {
+            pid: pid.into(),
+            thread: thread.into(),
+            address: VirtualAddress::from_usize(address),
+            waker: Waker::new(pid.into(), thread.into(), 0),
+            woken: false
+        }
+    }
+
+    /// Tests that only the given number of waiters on the address of the
+    /// process are woken, in the order they started waiting.
+    #[test]
+    fn wakes_matching_waiters_in_order() {
+        let mut waiters = Vec::new();
+        waiters.push(waiter(1, 0, 0x1000));
+        waiters.push(waiter(1, 1, 0x2000));
+        waiters.push(waiter(2, 0, 0x1000));
+        waiters.push(waiter(1, 2, 0x1000));
+        waiters.push(waiter(1, 3, 0x1000));
+
+        let address = VirtualAddress::from_usize(0x1000);
+        let wakers = wake_waiters(&mut waiters, 1.into(), address, 2);
+        assert_eq!(wakers, [waiters[0].waker, waiters[3].waker]);
+
+        let woken: Vec<bool> = waiters.iter().map(|waiter| waiter.woken).collect();
+        assert_eq!(woken, [true, false, false, true, false]);
+
+        let wakers = wake_waiters(&mut waiters, 1.into(), address, 5);
+        assert_eq!(wakers, [waiters[4].waker]);
+        assert!(wake_waiters(&mut waiters, 1.into(), address, 5).is_empty());
+    }
+
+    /// Tests that removing a waiter reports whether it was woken.
+    #[test]
+    fn removal_reports_wake_up() {
+        let mut waiters = Vec::new();
+        waiters.push(waiter(1, 0, 0x1000));
+        waiters.push(waiter(1, 1, 0x1000));
+
+        let address = VirtualAddress::from_usize(0x1000);
+        wake_waiters(&mut waiters, 1.into(), address, 1);
+
+        assert!(remove_waiter(&mut waiters, 1.into(), 0.into()));
+        assert!(!remove_waiter(&mut waiters, 1.into(), 1.into()));
+        assert!(!remove_waiter(&mut waiters, 1.into(), 1.into()));
+        assert!(waiters.is_empty());
+    }
+}
@@ -0,0 +1,37 @@
+//! This module defines the resources granted to a process.
+//!
+//! Grants are given to servers through their manifest. They are checked
+//! whenever a process wants to access hardware directly or register a
+//! service.
+
+use alloc::string::String;
+use alloc::Vec;
+use memory::{MemoryArea, PhysicalAddress};
+
+/// The resources granted to a process.
+#[derive(Debug, Clone, Default)]
+pub struct Grants {
+    /// The physical memory areas the process may map.
+    pub mmio: Vec<MemoryArea<PhysicalAddress>>,
+    /// The interrupts the process may handle.
+    pub irqs: Vec<u8>,
+    /// The names under which the process may register services.
+    pub services: Vec<String>
+}
+
+impl Grants {
+    /// Returns true if the process may map the given physical memory area.
+    pub fn allows_mmio(&self, area: MemoryArea<PhysicalAddress>) -> bool {
+        self.mmio.iter().any(|&granted| area.is_contained_in(granted))
+    }
+
+    /// Returns true if the process may handle the given interrupt.
+    pub fn allows_irq(&self, irq: u8) -> bool {
+        self.irqs.contains(&irq)
+    }
+
+    /// Returns true if the process may register a service with the given name.
+    pub fn allows_service(&self, name: &str) -> bool {
+        self.services.iter().any(|granted| granted == name)
+    }
+}
@@ -0,0 +1,132 @@
+//! This module takes CPUs offline and brings them back online.
+//!
+//! It exists to test how the scheduler copes with a changing number of CPUs.
+//! The boot CPU is always online. The other CPUs start out offline, because
+//! the kernel doesn't start them during boot.
+//!
+//! A CPU that goes offline only switches to its idle thread from then on.
+//! The idle thread moves the ready threads of the CPU to the boot CPU, from
+//! where idle CPUs take them over, and parks the CPU. Timers don't need to
+//! move: sleeping threads and the timeouts of wait queues are shared by all
+//! CPUs and the timer interrupt of a parked CPU stays off until it is started
+//! again.
+
+use super::wait_queue::{Wait, WaitQueue};
+use super::{get_cpu_num, scheduler};
+use arch::{self, Architecture};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The ID of the CPU the kernel boots on.
+pub const BOOT_CPU: usize = 0;
+
+/// The CPU doesn't run.
+const OFFLINE: usize = 0;
+
+/// The CPU runs threads.
+const ONLINE: usize = 1;
+
+/// The CPU runs until its idle thread parks it.
+const GOING_OFFLINE: usize = 2;
+
+/// The CPU is being started.
+const GOING_ONLINE: usize = 3;
+
+cpu_local! {
+    /// Whether the CPU is online, offline or changing between the two.
+    static ref STATE: AtomicUsize = |cpu_id| {
+        AtomicUsize::new(if cpu_id == BOOT_CPU { ONLINE } else { OFFLINE })
+    };
+}
+
+lazy_static! {
+    /// Woken when a CPU went offline.
+    static ref WENT_OFFLINE: WaitQueue = WaitQueue::new();
+}
+
+/// The reasons why a CPU can't be taken offline or brought online.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugError {
+    /// The CPU doesn't exist, is the boot CPU or is already in the requested
+    /// state.
+    InvalidCpu,
+    /// The CPU didn't start.
+    NotResponding
+}
+
+/// Takes the CPU offline and returns once it stopped running threads.
+pub fn cpu_down(cpu_id: usize) -> Result<(), HotplugError> {
+    if cpu_id == BOOT_CPU || cpu_id >= get_cpu_num() {
+        return Err(HotplugError::InvalidCpu);
+    }
+
+    let state = STATE.get_specific(cpu_id);
+
+    if state.compare_and_swap(ONLINE, GOING_OFFLINE, Ordering::AcqRel) != ONLINE {
+        return Err(HotplugError::InvalidCpu);
+    }
+
+    loop {
+        let wait = Wait::new();
+        wait.on(&WENT_OFFLINE);
+
+        if state.load(Ordering::Acquire) == OFFLINE {
+            return Ok(());
+        }
+
+        wait.sleep(None);
+    }
+}
+
+/// Brings the CPU online and returns once it runs.
+pub fn cpu_up(cpu_id: usize) -> Result<(), HotplugError> {
+    if cpu_id >= get_cpu_num() {
+        return Err(HotplugError::InvalidCpu);
+    }
+
+    let state = STATE.get_specific(cpu_id);
+
+    if state.compare_and_swap(OFFLINE, GOING_ONLINE, Ordering::AcqRel) != OFFLINE {
+        return Err(HotplugError::InvalidCpu);
+    }
+
+    if arch::Current::start_cpu(cpu_id) {
+        state.store(ONLINE, Ordering::Release);
+        Ok(())
+    } else {
+        state.store(OFFLINE, Ordering::Release);
+        Err(HotplugError::NotResponding)
+    }
+}
+
+/// Returns true if the current CPU should stop running threads.
+pub fn is_going_offline() -> bool {
+    STATE.load(Ordering::Acquire) == GOING_OFFLINE
+}
+
+/// Returns the number of CPUs that aren't offline.
+pub fn online_count() -> usize {
+    (0..get_cpu_num())
+        .filter(|&cpu_id| STATE.get_specific(cpu_id).load(Ordering::Acquire) != OFFLINE)
+        .count()
+}
+
+/// Moves the ready threads of the current CPU away and parks it until it is
+/// brought online again.
+///
+/// This must only be called by the idle thread of a CPU that goes offline.
+pub fn park_current_cpu() {
+    unsafe {
+        arch::Current::disable_interrupts();
+    }
+
+    STATE.store(OFFLINE, Ordering::Release);
+    WENT_OFFLINE.wake_all();
+
+    // The threads woken above are in the ready list of this CPU as well.
+    scheduler::move_ready_threads(BOOT_CPU);
+
+    unsafe {
+        arch::Current::park_cpu();
+        arch::Current::enable_interrupts();
+    }
+}
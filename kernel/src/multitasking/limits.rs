@@ -0,0 +1,138 @@
+//! This module defines the resource limits of processes.
+
+/// The default limit for the size of the address space of a process.
+pub const DEFAULT_ADDRESS_SPACE_LIMIT: usize = 1024 * 1024 * 1024;
+
+/// The default limit for the amount of open files of a process.
+const DEFAULT_OPEN_FILES_LIMIT: usize = 64;
+
+/// The default limit for the amount of threads of a process.
+const DEFAULT_THREADS_LIMIT: usize = 256;
+
+/// The resources of a process that can be limited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    /// The total size of the segments in the address space in bytes.
+    AddressSpaceSize,
+    /// The amount of open file descriptors.
+    OpenFiles,
+    /// The amount of threads.
+    Threads,
+    /// The CPU time used by all threads in seconds.
+    CpuTime,
+    /// The size of the core dump written when the process crashes in bytes.
+    CoreSize
+}
+
+impl Resource {
+    /// Converts the raw syscall argument to a resource.
+    pub fn from_raw(resource: usize) -> Option<Resource> {
+        match resource {
+            0 => Some(Resource::AddressSpaceSize),
+            1 => Some(Resource::OpenFiles),
+            2 => Some(Resource::Threads),
+            3 => Some(Resource::CpuTime),
+            4 => Some(Resource::CoreSize),
+            _ => None
+        }
+    }
+}
+
+/// A limit on a resource.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    /// The limit that is currently enforced.
+    pub soft: usize,
+    /// The value up to which the soft limit may be raised.
+    pub hard: usize
+}
+
+impl Limit {
+    /// Creates a limit where the soft and hard limit are the same.
+    pub const fn new(limit: usize) -> Limit {
+        Limit {
+            soft: limit,
+            hard: limit
+        }
+    }
+}
+
+/// The resource limits of a process.
+#[derive(Debug, Clone)]
+pub struct ResourceLimits {
+    /// The limit for the address space size.
+    address_space_size: Limit,
+    /// The limit for the amount of open files.
+    open_files: Limit,
+    /// The limit for the amount of threads.
+    threads: Limit,
+    /// The limit for the used CPU time in seconds.
+    cpu_time: Limit,
+    /// The limit for the size of core dumps.
+    core_size: Limit
+}
+
+impl Default for ResourceLimits {
+    fn default() -> ResourceLimits {
+        ResourceLimits {
+            address_space_size: Limit::new(DEFAULT_ADDRESS_SPACE_LIMIT),
+            open_files: Limit::new(DEFAULT_OPEN_FILES_LIMIT),
+            threads: Limit::new(DEFAULT_THREADS_LIMIT),
+            cpu_time: Limit::new(usize::max_value()),
+            core_size: Limit {
+                soft: 0,
+                hard: usize::max_value()
+            }
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Creates limits that don't restrict anything.
+    pub fn unlimited() -> ResourceLimits {
+        ResourceLimits {
+            address_space_size: Limit::new(usize::max_value()),
+            open_files: Limit::new(usize::max_value()),
+            threads: Limit::new(usize::max_value()),
+            cpu_time: Limit::new(usize::max_value()),
+            core_size: Limit::new(usize::max_value())
+        }
+    }
+
+    /// Returns the limit for the given resource.
+    pub fn get(&self, resource: Resource) -> Limit {
+        match resource {
+            Resource::AddressSpaceSize => self.address_space_size,
+            Resource::OpenFiles => self.open_files,
+            Resource::Threads => self.threads,
+            Resource::CpuTime => self.cpu_time,
+            Resource::CoreSize => self.core_size
+        }
+    }
+
+    /// Changes the limit for the given resource.
+    ///
+    /// The soft limit may not exceed the hard limit and the hard limit may
+    /// only be lowered. Returns false if the limit was not changed.
+    pub fn set(&mut self, resource: Resource, limit: Limit) -> bool {
+        let current = match resource {
+            Resource::AddressSpaceSize => &mut self.address_space_size,
+            Resource::OpenFiles => &mut self.open_files,
+            Resource::Threads => &mut self.threads,
+            Resource::CpuTime => &mut self.cpu_time,
+            Resource::CoreSize => &mut self.core_size
+        };
+
+        if limit.soft > limit.hard || limit.hard > current.hard {
+            false
+        } else {
+            *current = limit;
+            true
+        }
+    }
+
+    /// Returns true if `amount` is within the soft limit of the resource.
+    pub fn allows(&self, resource: Resource, amount: usize) -> bool {
+        amount <= self.get(resource).soft
+    }
+}
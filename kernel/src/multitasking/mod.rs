@@ -1,22 +1,44 @@
 //! Manages multitasking in the operating system.
 
+pub mod backtrace;
+pub mod capabilities;
 mod cpu_local;
+pub mod descriptor_table;
+pub mod futex;
+pub mod grants;
+pub mod hotplug;
+pub mod limits;
+pub mod name;
 mod pcb;
+pub mod pid_namespace;
+pub mod ready_queue;
+pub mod reaper;
+pub mod resource_group;
 pub mod scheduler;
+pub mod service;
+pub mod signal;
 pub mod stack;
 mod tcb;
+pub mod trace;
+pub mod wait_queue;
 
 pub use self::cpu_local::{CPULocal, CPULocalMut};
-pub use self::pcb::{get_current_process, PCB};
+pub use self::name::Name;
+pub use self::pcb::{get_current_process, get_process, ExitStatus, Inheritance, ProcessLock, PCB};
 pub use self::scheduler::CURRENT_THREAD;
 pub use self::stack::{Stack, StackType};
 pub use self::tcb::{ThreadState, TCB};
 use alloc::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::Vec;
 use arch::{self, Architecture};
+use core::fmt::Write;
 use memory::address_space::AddressSpace;
+use memory::oom;
 use memory::VirtualAddress;
+use procfs;
 use sync::mutex::MutexGuard;
-use sync::Mutex;
+use sync::{LockStats, Mutex};
 
 /// The type of a process ID.
 #[repr(transparent)]
@@ -52,14 +74,17 @@ impl From<ThreadID> for usize {
     }
 }
 
+/// The contention statistics of the process list.
+static PROCESS_LIST_STATS: LockStats = LockStats::new("PROCESS_LIST");
+
 lazy_static! {
     /// The list of all the currently running processes.
-    static ref PROCESS_LIST: Mutex<BTreeMap<ProcessID, PCB>> = Mutex::new({
+    static ref PROCESS_LIST: Mutex<BTreeMap<ProcessID, PCB>> = Mutex::with_stats({
         let mut map = BTreeMap::new();
         map.insert(0.into(), PCB::idle_pcb());
 
         map
-    });
+    }, &PROCESS_LIST_STATS);
 }
 
 /// Finds an unused process ID.
@@ -72,16 +97,173 @@ fn find_pid(list: &MutexGuard<BTreeMap<ProcessID, PCB>>) -> ProcessID {
     pid.into()
 }
 
-/// Creates a new process.
-pub fn create_process(address_space: AddressSpace, entry_address: VirtualAddress) -> ProcessID {
-    let mut pcb = PCB::new(address_space);
+/// Removes the process from the list together with its zombie children, which
+/// can't be waited for anymore.
+///
+/// The other children of the process are left without a parent.
+fn remove_process(process_list: &mut BTreeMap<ProcessID, PCB>, pid: ProcessID) {
+    let mut to_remove = Vec::new();
+    to_remove.push(pid);
+
+    while let Some(pid) = to_remove.pop() {
+        if let Some(pcb) = process_list.remove(&pid) {
+            pid_namespace::unregister(pid, pcb.pid_namespace);
+        }
+
+        for (&child, pcb) in process_list.iter_mut() {
+            if pcb.parent == Some(pid) {
+                pcb.parent = None;
+
+                if pcb.is_zombie() {
+                    to_remove.push(child);
+                }
+            }
+        }
+    }
+}
+
+/// Checks that every process shares the kernel half of its address space.
+///
+/// # Note
+/// This locks the process list, so it must not be held by the caller.
+pub fn check_kernel_halves() {
+    for pcb in PROCESS_LIST.lock().values() {
+        pcb.address_space.check_kernel_half();
+    }
+}
+
+/// Returns true if `child` was created by `parent` and wasn't reaped yet.
+pub fn is_child(parent: ProcessID, child: ProcessID) -> bool {
+    PROCESS_LIST
+        .lock()
+        .get(&child)
+        .map_or(false, |pcb| pcb.parent == Some(parent))
+}
+
+/// Returns true if `descendant` was created by `ancestor` or by one of the
+/// processes `ancestor` created, directly or not.
+pub fn is_descendant(ancestor: ProcessID, descendant: ProcessID) -> bool {
+    let process_list = PROCESS_LIST.lock();
+    let mut current = descendant;
+
+    while let Some(parent) = process_list.get(&current).and_then(|pcb| pcb.parent) {
+        if parent == ancestor {
+            return true;
+        }
+
+        current = parent;
+    }
+
+    false
+}
 
+/// Removes the child of `parent` if it ended and returns how it ended.
+///
+/// Returns `None` if the child is still running or isn't a child of `parent`.
+pub fn reap_child(parent: ProcessID, child: ProcessID) -> Option<ExitStatus> {
     let mut process_list = PROCESS_LIST.lock();
-    let id = find_pid(&process_list);
 
-    let first_tcb = TCB::in_process(id, 0.into(), entry_address, &mut pcb);
+    let status = match process_list.get(&child) {
+        Some(pcb) if pcb.parent == Some(parent) => pcb.exit_status(),
+        _ => None
+    };
 
-    scheduler::READY_LIST.lock().push(first_tcb);
+    if status.is_some() {
+        remove_process(&mut process_list, child);
+    }
+
+    status
+}
+
+/// Kills the user process that the system can best do without when it runs
+/// out of memory.
+///
+/// Essential processes, like the idle threads and the init process, are never
+/// killed. Returns the ID of the killed process or `None` if no other process
+/// is left.
+pub fn kill_for_memory() -> Option<ProcessID> {
+    let mut process_list = PROCESS_LIST.lock();
+
+    let victim = oom::select_victim(
+        process_list
+            .iter()
+            .filter(|&(_, pcb)| !pcb.essential && !pcb.is_dead())
+            .map(|(&pid, pcb)| (pid, pcb.priority, pcb.address_space.size()))
+    )?;
+
+    process_list
+        .get_mut(&victim)
+        .expect("The killed process doesn't exist.")
+        .kill();
+    scheduler::wake_process(victim);
+
+    Some(victim)
+}
+
+/// Returns true if the process was killed, but still holds its memory,
+/// because not all of its threads ended yet.
+pub fn is_dying(pid: ProcessID) -> bool {
+    PROCESS_LIST
+        .lock()
+        .get(&pid)
+        .map_or(false, |pcb| pcb.is_dead() && !pcb.is_zombie())
+}
+
+/// Makes the processes and the threads of every CPU visible in procfs.
+pub fn init() {
+    procfs::register("processes", describe_processes);
+    procfs::register("threads", scheduler::describe_threads);
+}
+
+/// Lists all processes with their state, thread count and size.
+pub fn describe_processes() -> String {
+    let mut text = String::new();
+
+    writeln!(
+        text,
+        "{:>5} {:<7} {:>7} {:>10} NAME",
+        "PID", "STATE", "THREADS", "SIZE"
+    ).unwrap();
+
+    for (pid, pcb) in PROCESS_LIST.lock().iter() {
+        let state = if pcb.is_zombie() {
+            "zombie"
+        } else if pcb.is_dead() {
+            "dying"
+        } else {
+            "alive"
+        };
+
+        writeln!(
+            text,
+            "{:>5} {:<7} {:>7} {:>6} KiB {}",
+            pid.0,
+            state,
+            pcb.thread_count(),
+            pcb.address_space.size() / 1024,
+            pcb.name
+        ).unwrap();
+    }
+
+    text
+}
+
+/// Adds the new process to the list and queues its first thread.
+///
+/// The process is registered in its namespace, or in a new namespace within
+/// it if `new_pid_namespace` is set, before its first thread can run.
+fn add_process(
+    process_list: &mut BTreeMap<ProcessID, PCB>,
+    id: ProcessID,
+    mut pcb: PCB,
+    new_pid_namespace: bool,
+    first_tcb: TCB
+) {
+    if new_pid_namespace {
+        pcb.pid_namespace = pid_namespace::create_namespace(pcb.pid_namespace);
+    }
+
+    pid_namespace::register(id, pcb.pid_namespace);
 
     assert!(
         process_list.insert(id, pcb).is_none(),
@@ -89,9 +271,106 @@ pub fn create_process(address_space: AddressSpace, entry_address: VirtualAddress
         id
     );
 
+    scheduler::READY_LIST.lock().push(first_tcb);
+}
+
+/// Creates a new process with the given name, arguments and environment.
+///
+/// Returns `None` if there is no room for the user stack of the new process.
+pub fn create_process(
+    address_space: AddressSpace,
+    entry_address: VirtualAddress,
+    arguments: &[&str],
+    environment: &[&str],
+    name: Name,
+    inheritance: Inheritance
+) -> Option<ProcessID> {
+    let new_pid_namespace = inheritance.new_pid_namespace;
+    let mut pcb = PCB::new(address_space, inheritance);
+    pcb.name = name;
+    pcb.environment = environment.iter().map(|&variable| String::from(variable)).collect();
+
+    let mut process_list = PROCESS_LIST.lock();
+    let id = find_pid(&process_list);
+
+    let first_tcb = match TCB::with_program_arguments(
+        id,
+        0.into(),
+        entry_address,
+        &mut pcb,
+        arguments,
+        environment
+    ) {
+        Some(tcb) => tcb,
+        None => {
+            // The process never had a thread, so it can be dropped.
+            pcb.remove_thread(0.into());
+            return None;
+        }
+    };
+
+    add_process(&mut process_list, id, pcb, new_pid_namespace, first_tcb);
+
+    Some(id)
+}
+
+/// Creates a process with the given copy of the current address space, in
+/// which a copy of the current thread continues after its syscall.
+///
+/// `user_stack` is the copy of the user stack of the current thread. Only the
+/// current thread is copied. Descriptors can't be shared between processes,
+/// so the new process starts with fresh standard streams.
+pub fn fork_current_thread(
+    address_space: AddressSpace,
+    user_stack: Stack,
+    name: Name,
+    environment: Vec<String>,
+    inheritance: Inheritance
+) -> ProcessID {
+    let priority = CURRENT_THREAD.lock().priority;
+    let new_pid_namespace = inheritance.new_pid_namespace;
+    let mut pcb = PCB::new(address_space, inheritance);
+    pcb.name = name;
+    pcb.environment = environment;
+    pcb.priority = priority;
+
+    let mut process_list = PROCESS_LIST.lock();
+    let id = find_pid(&process_list);
+
+    let mut first_tcb = TCB::forked(id, 0.into(), &mut pcb, user_stack);
+    first_tcb.priority = priority;
+
+    add_process(&mut process_list, id, pcb, new_pid_namespace, first_tcb);
+
     id
 }
 
+/// Creates a thread of the idle process that runs the given function in
+/// kernel mode.
+///
+/// The function starts right after a context switch, so it must call
+/// `scheduler::after_context_switch` and enable preemption first. This must
+/// be called while the address space of the idle process is active.
+pub fn spawn_kernel_thread(name: Name, function: fn() -> !) {
+    let id = {
+        let mut process_list = PROCESS_LIST.lock();
+        let pcb = process_list
+            .get_mut(&0.into())
+            .expect("The idle process doesn't exist.");
+        let id = pcb
+            .find_thread_id()
+            .expect("There is no thread ID left for a kernel thread.");
+
+        pcb.add_thread(id);
+
+        id
+    };
+
+    let thread = TCB::kernel_thread(id, name, function);
+
+    scheduler::READY_LIST.lock().push(thread);
+}
+
 /// Returns the id of the current cpu.
 pub fn get_cpu_id() -> usize {
     arch::Current::get_cpu_id()
@@ -0,0 +1,100 @@
+//! This module provides the names of threads and processes.
+//!
+//! Names are only used for diagnostics. They are stored inline with a bounded
+//! length, so that copying and printing them never allocates.
+
+use core::cmp::min;
+use core::{fmt, str};
+
+/// The maximum length of a name in bytes.
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// The name of a thread or a process.
+#[derive(Clone, Copy)]
+pub struct Name {
+    /// The bytes of the name, of which the first `length` are used.
+    bytes: [u8; MAX_NAME_LENGTH],
+    /// The length of the name in bytes.
+    length: usize
+}
+
+impl Name {
+    /// Creates an empty name.
+    pub const fn empty() -> Name {
+        Name {
+            bytes: [0; MAX_NAME_LENGTH],
+            length: 0
+        }
+    }
+
+    /// Creates a name from the given string.
+    ///
+    /// Strings longer than `MAX_NAME_LENGTH` are truncated at the last
+    /// character boundary that fits.
+    pub fn new(name: &str) -> Name {
+        let mut length = min(name.len(), MAX_NAME_LENGTH);
+
+        while !name.is_char_boundary(length) {
+            length -= 1;
+        }
+
+        let mut bytes = [0; MAX_NAME_LENGTH];
+        bytes[..length].copy_from_slice(&name.as_bytes()[..length]);
+
+        Name { bytes, length }
+    }
+
+    /// Creates a name from the file name of the given path.
+    pub fn from_path(path: &str) -> Name {
+        Name::new(path.rsplit('/').next().unwrap_or(path))
+    }
+
+    /// Returns the name as a string.
+    pub fn as_str(&self) -> &str {
+        // The bytes were copied from a string up to a character boundary.
+        unsafe { str::from_utf8_unchecked(&self.bytes[..self.length]) }
+    }
+
+    /// Returns true if the name is empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that long names are truncated at character boundaries.
+    #[test]
+    fn test_truncation() {
+        let long_name = "abcdefghijklmnopqrstuvwxyz0123456789";
+        assert_eq!(Name::new(long_name).as_str(), &long_name[..MAX_NAME_LENGTH]);
+
+        let multibyte_name = "abcdefghijklmnopqrstuvwxyz01234\u{e4}";
+        assert_eq!(
+            Name::new(multibyte_name).as_str(),
+            "abcdefghijklmnopqrstuvwxyz01234"
+        );
+    }
+
+    /// Tests that names from paths only contain the file name.
+    #[test]
+    fn test_from_path() {
+        assert_eq!(Name::from_path("/bin/init").as_str(), "init");
+        assert_eq!(Name::from_path("init").as_str(), "init");
+        assert!(Name::from_path("/bin/").is_empty());
+    }
+}
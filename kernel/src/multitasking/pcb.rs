@@ -1,10 +1,26 @@
 //! This module defines a process control block (PCB).
 
-use alloc::BTreeMap;
+use alloc::string::String;
+use alloc::{BTreeMap, BTreeSet, Vec};
 use arch::schedule;
 use core::cmp::max;
+use core::iter;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
+use elf::BuildId;
 use memory::address_space::AddressSpace;
+use multitasking::capabilities::Capabilities;
+use multitasking::descriptor_table::DescriptorTable;
+use multitasking::grants::Grants;
+use multitasking::limits::{Limit, Resource, ResourceLimits};
+use multitasking::name::Name;
+use multitasking::pid_namespace::{NamespaceID, ROOT_NAMESPACE};
+use multitasking::ready_queue::{DEFAULT_PRIORITY, MAX_PRIORITY};
+use multitasking::resource_group::{GroupID, ROOT_GROUP};
+use multitasking::scheduler;
+use multitasking::signal::{self, SignalSet, CPU_TIME_LIMIT_EXCEEDED};
+use multitasking::trace::TraceState;
+use multitasking::wait_queue::WaitQueue;
 use multitasking::{get_cpu_num, ProcessID, ThreadID, CURRENT_THREAD, PROCESS_LIST};
 use sync::mutex::MutexGuard;
 
@@ -14,17 +30,136 @@ enum ProcessState {
     /// The process is currently active.
     Active,
     /// The process is dead.
-    Dead
+    Dead,
+    /// All threads of the process exited, but its parent didn't learn how it
+    /// ended yet.
+    Zombie
+}
+
+/// Describes how a process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process exited with the given code.
+    Exited(i32),
+    /// The process was killed, for example because it crashed.
+    Killed
+}
+
+/// What a new process starts with, apart from its address space and its
+/// first thread.
+///
+/// The process is complete before its first thread can run, so it never
+/// runs with more rights than it gets.
+#[derive(Clone)]
+pub struct Inheritance {
+    /// The process that creates the new process.
+    pub parent: Option<ProcessID>,
+    /// The resource group the process belongs to.
+    pub resource_group: GroupID,
+    /// The process ID namespace the process is created in.
+    pub pid_namespace: NamespaceID,
+    /// Whether the process gets a new namespace within `pid_namespace`.
+    pub new_pid_namespace: bool,
+    /// The directory that paths used by the process are relative to.
+    pub root: String,
+    /// The privileged operations the process may perform.
+    pub capabilities: Capabilities,
+    /// The resource limits of the process.
+    pub limits: ResourceLimits,
+    /// The hardware resources the process may access directly.
+    pub grants: Grants,
+    /// Whether the system can't do without the process.
+    pub essential: bool
+}
+
+impl Inheritance {
+    /// Returns what a process created by the current process inherits.
+    ///
+    /// Processes created by the kernel aren't restricted, all others get the
+    /// resource group, namespace, root directory, capabilities and limits of
+    /// their creator.
+    pub fn from_current() -> Inheritance {
+        let pid = CURRENT_THREAD.lock().pid;
+
+        if pid == 0.into() {
+            return Inheritance {
+                parent: None,
+                resource_group: ROOT_GROUP,
+                pid_namespace: ROOT_NAMESPACE,
+                new_pid_namespace: false,
+                root: String::from("/"),
+                capabilities: Capabilities::all(),
+                limits: ResourceLimits::default(),
+                grants: Grants::default(),
+                essential: false
+            };
+        }
+
+        let pcb = get_current_process();
+
+        Inheritance {
+            parent: Some(pid),
+            resource_group: pcb.resource_group,
+            pid_namespace: pcb.pid_namespace,
+            new_pid_namespace: false,
+            root: pcb.root.clone(),
+            capabilities: pcb.capabilities,
+            limits: pcb.limits.clone(),
+            grants: Grants::default(),
+            essential: false
+        }
+    }
 }
 
 /// A process control block (PCB) holds all data required to manage a process.
 pub struct PCB {
     /// The address space of the process.
     pub address_space: AddressSpace,
-    /// The amount of currently existing threads within this process.
-    pub thread_count: usize,
+    /// The IDs of the currently existing threads within this process.
+    threads: BTreeSet<ThreadID>,
+    /// Woken when threads of this process exit.
+    pub thread_exits: WaitQueue,
+    /// Woken when children of this process end.
+    pub child_exits: WaitQueue,
+    /// The files opened by this process.
+    pub descriptors: DescriptorTable,
+    /// The resource limits of this process.
+    limits: ResourceLimits,
+    /// The CPU time used by all threads of this process.
+    cpu_time: Duration,
+    /// The signals that were raised but not yet handled.
+    pending_signals: SignalSet,
+    /// The resource group the process belongs to.
+    pub resource_group: GroupID,
+    /// The process that created this process.
+    pub parent: Option<ProcessID>,
+    /// The process ID namespace the process belongs to.
+    pub pid_namespace: NamespaceID,
+    /// The directory that paths used by the process are relative to.
+    pub root: String,
+    /// The privileged operations the process may perform.
+    pub capabilities: Capabilities,
+    /// The hardware resources the process may access directly.
+    pub grants: Grants,
+    /// The build-id of the executable of the process, used for crash reports.
+    pub build_id: Option<BuildId>,
+    /// The name of the process, used for diagnostics.
+    pub name: Name,
+    /// The environment variables the process was started with, which are
+    /// passed on to the processes it starts.
+    pub environment: Vec<String>,
+    /// The tracing state, if the process is traced.
+    pub trace: Option<TraceState>,
+    /// The priority one of its threads set last, which decides which process
+    /// is killed when the system runs out of memory.
+    pub priority: i32,
+    /// Whether the system can't do without the process, which keeps it from
+    /// being killed when the system runs out of memory.
+    pub essential: bool,
     /// The state of the process.
     state: ProcessState,
+    /// The code the process exited with or `None` if it was killed.
+    exit_code: Option<i32>,
     /// The highest ID of a thread within this process.
     highest_thread_id: ThreadID
 }
@@ -36,13 +171,38 @@ impl Drop for PCB {
 }
 
 impl PCB {
-    /// Creates a new PCB with the given parameters.
-    pub fn new(address_space: AddressSpace) -> PCB {
+    /// Creates a new PCB with the given address space and inherited
+    /// settings.
+    ///
+    /// The size of the address space is limited by the inherited limits.
+    pub fn new(mut address_space: AddressSpace, inheritance: Inheritance) -> PCB {
+        let limits = inheritance.limits;
+        address_space.set_size_limit(limits.get(Resource::AddressSpaceSize).soft);
+
         PCB {
             address_space,
-            thread_count: 1,
+            threads: iter::once(0.into()).collect(),
+            thread_exits: WaitQueue::new(),
+            child_exits: WaitQueue::new(),
+            descriptors: DescriptorTable::with_standard_streams(),
+            limits,
+            cpu_time: Duration::new(0, 0),
+            pending_signals: SignalSet::empty(),
+            resource_group: inheritance.resource_group,
+            parent: inheritance.parent,
+            pid_namespace: inheritance.pid_namespace,
+            root: inheritance.root,
+            capabilities: inheritance.capabilities,
+            grants: inheritance.grants,
+            build_id: None,
+            name: Name::empty(),
+            environment: Vec::new(),
+            trace: None,
+            priority: DEFAULT_PRIORITY,
+            essential: inheritance.essential,
             highest_thread_id: 0.into(),
-            state: ProcessState::Active
+            state: ProcessState::Active,
+            exit_code: None
         }
     }
 
@@ -51,9 +211,28 @@ impl PCB {
         assert_has_not_been_called!("There should only be one idle PCB.");
         PCB {
             address_space: AddressSpace::idle_address_space(),
-            thread_count: get_cpu_num(),
+            threads: (0..get_cpu_num()).map(|id| id.into()).collect(),
+            thread_exits: WaitQueue::new(),
+            child_exits: WaitQueue::new(),
+            descriptors: DescriptorTable::new(),
+            limits: ResourceLimits::unlimited(),
+            cpu_time: Duration::new(0, 0),
+            pending_signals: SignalSet::empty(),
+            resource_group: ROOT_GROUP,
+            parent: None,
+            pid_namespace: ROOT_NAMESPACE,
+            root: String::from("/"),
+            capabilities: Capabilities::all(),
+            grants: Grants::default(),
+            build_id: None,
+            name: Name::new("idle"),
+            environment: Vec::new(),
+            trace: None,
+            priority: MAX_PRIORITY,
+            essential: true,
             highest_thread_id: (get_cpu_num() - 1).into(),
-            state: ProcessState::Active
+            state: ProcessState::Active,
+            exit_code: None
         }
     }
 
@@ -67,12 +246,130 @@ impl PCB {
     pub fn add_thread(&mut self, id: ThreadID) {
         self.highest_thread_id = max(self.highest_thread_id, id);
 
-        self.thread_count += 1;
+        self.threads.insert(id);
+    }
+
+    /// Removes a thread that exited from the process.
+    pub fn remove_thread(&mut self, id: ThreadID) {
+        self.threads.remove(&id);
+        self.thread_exits.wake_all();
+    }
+
+    /// Returns the number of threads within the process.
+    pub fn thread_count(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Returns true if the thread with the given ID still exists.
+    ///
+    /// Thread IDs are not reused, so once this returns false for a thread, it
+    /// does so forever.
+    pub fn has_thread(&self, id: ThreadID) -> bool {
+        self.threads.contains(&id)
+    }
+
+    /// Returns the limit of the given resource.
+    pub fn get_limit(&self, resource: Resource) -> Limit {
+        self.limits.get(resource)
+    }
+
+    /// Changes the limit of the given resource.
+    ///
+    /// Returns false if the limit could not be changed.
+    pub fn set_limit(&mut self, resource: Resource, limit: Limit) -> bool {
+        if self.limits.set(resource, limit) {
+            if resource == Resource::AddressSpaceSize {
+                self.address_space.set_size_limit(limit.soft);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns true if another file can be opened within the limits.
+    pub fn can_open_file(&self) -> bool {
+        self.limits
+            .allows(Resource::OpenFiles, self.descriptors.len() + 1)
+    }
+
+    /// Returns true if another thread can be created within the thread limit.
+    ///
+    /// Whether there is room for its stack is checked when the stack is
+    /// created.
+    pub fn can_create_thread(&self) -> bool {
+        self.limits.allows(Resource::Threads, self.threads.len() + 1)
+    }
+
+    /// Adds CPU time used by one of the threads of this process.
+    ///
+    /// Exceeding the soft CPU time limit raises a signal, exceeding the hard
+    /// limit kills the process.
+    pub fn account_cpu_time(&mut self, time: Duration) {
+        let previous_seconds = self.cpu_time.as_secs();
+        self.cpu_time = self.cpu_time.checked_add(time).unwrap_or(self.cpu_time);
+        let seconds = self.cpu_time.as_secs();
+
+        let limit = self.limits.get(Resource::CpuTime);
+
+        if seconds >= limit.hard as u64 {
+            warn!("A process exceeded its hard CPU time limit and is killed.");
+            self.kill();
+        } else if seconds >= limit.soft as u64 && previous_seconds < limit.soft as u64 {
+            self.raise_signal(CPU_TIME_LIMIT_EXCEEDED);
+        }
+    }
+
+    /// Returns the CPU time used by all threads of this process.
+    pub fn cpu_time(&self) -> Duration {
+        self.cpu_time
+    }
+
+    /// Marks the given signals as pending for this process.
+    pub fn raise_signal(&mut self, signals: SignalSet) {
+        self.pending_signals |= signals;
+        signal::RAISED.wake_all();
+    }
+
+    /// Returns the signals that are pending for this process.
+    pub fn pending_signals(&self) -> SignalSet {
+        self.pending_signals
+    }
+
+    /// Returns the pending signals within `mask` and marks them as handled.
+    pub fn take_signals(&mut self, mask: SignalSet) -> SignalSet {
+        let signals = self.pending_signals & mask;
+        self.pending_signals.remove(signals);
+
+        signals
+    }
+
+    /// Resolves the path as seen by the process to the global path.
+    ///
+    /// Returns `None` if the path is not absolute or could escape the root.
+    pub fn resolve_path(&self, path: &str) -> Option<String> {
+        if !path.starts_with('/') || path.split('/').any(|component| component == "..") {
+            return None;
+        }
+
+        if self.root == "/" {
+            Some(String::from(path))
+        } else {
+            let mut resolved = self.root.clone();
+            resolved.push_str(path);
+            Some(resolved)
+        }
     }
 
     /// Returns true if the process is dead.
     pub fn is_dead(&self) -> bool {
-        self.state == ProcessState::Dead
+        self.state != ProcessState::Active
+    }
+
+    /// Returns true if all threads of the process exited and it only waits
+    /// for its parent to learn how it ended.
+    pub fn is_zombie(&self) -> bool {
+        self.state == ProcessState::Zombie
     }
 
     /// Marks this process as dead.
@@ -80,22 +377,59 @@ impl PCB {
     /// This will cause the scheduler to not schedule any threads of this
     /// process anymore.
     pub fn kill(&mut self) {
-        self.state = ProcessState::Dead;
+        if self.state == ProcessState::Active {
+            self.state = ProcessState::Dead;
+        }
     }
 
-    /// Marks this process as dead.
+    /// Marks this process as dead after it exited with the given code.
+    ///
+    /// This has no effect if the process is dead already.
+    pub fn exit(&mut self, code: i32) {
+        if self.state == ProcessState::Active {
+            self.exit_code = Some(code);
+            self.state = ProcessState::Dead;
+        }
+    }
+
+    /// Returns how the process ended, once it is a zombie.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        if self.is_zombie() {
+            Some(self.exit_code.map_or(ExitStatus::Killed, ExitStatus::Exited))
+        } else {
+            None
+        }
+    }
+
+    /// Turns the process, whose threads all exited, into a zombie.
+    ///
+    /// Everything but how the process ended is released, so that only the
+    /// PCB itself remains until the parent waits for the process.
+    pub fn make_zombie(&mut self) {
+        assert!(self.is_droppable());
+
+        self.address_space.unmap_all();
+        self.descriptors = DescriptorTable::new();
+        self.grants = Grants::default();
+        self.environment = Vec::new();
+        self.trace = None;
+        self.state = ProcessState::Zombie;
+    }
+
+    /// Marks this process, which must be the current one, as dead.
     ///
     /// This will cause the scheduler to not schedule any threads of this
     /// process anymore. The scheduler will be invoked immediately.
     pub fn kill_immediately(&mut self) -> ! {
-        self.state = ProcessState::Dead;
+        self.kill();
+        scheduler::wake_process(CURRENT_THREAD.lock().pid);
         schedule();
         unreachable!();
     }
 
     /// Determines if this process can be dropped.
     pub fn is_droppable(&self) -> bool {
-        self.thread_count == 0
+        self.threads.is_empty()
     }
 }
 
@@ -123,6 +457,17 @@ impl<'a> DerefMut for ProcessLock<'a> {
     }
 }
 
+/// Returns a lock of the process with the given ID, if it exists.
+pub fn get_process<'a>(pid: ProcessID) -> Option<ProcessLock<'a>> {
+    let guard = PROCESS_LIST.lock();
+
+    if guard.contains_key(&pid) {
+        Some(ProcessLock { guard, key: pid })
+    } else {
+        None
+    }
+}
+
 /// Returns a lock of the current process.
 pub fn get_current_process<'a>() -> ProcessLock<'a> {
     let pid = CURRENT_THREAD.lock().pid;
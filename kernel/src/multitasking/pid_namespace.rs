@@ -0,0 +1,135 @@
+//! This module implements process ID namespaces.
+//!
+//! A process in a namespace only sees the processes of its own namespace and
+//! its descendant namespaces. The first process in a new namespace sees
+//! itself as process 1. Process IDs are translated whenever they cross the
+//! syscall boundary.
+
+use alloc::btree_map::BTreeMap;
+use multitasking::ProcessID;
+use sync::Mutex;
+
+/// The type of a namespace ID.
+pub type NamespaceID = usize;
+
+/// The ID of the namespace that contains all processes.
+///
+/// Within the root namespace, process IDs are not translated.
+pub const ROOT_NAMESPACE: NamespaceID = 0;
+
+/// A process ID namespace.
+struct PidNamespace {
+    /// The namespace this namespace was created in.
+    parent: NamespaceID,
+    /// Maps the global process IDs to the IDs within this namespace.
+    local_ids: BTreeMap<ProcessID, usize>,
+    /// Maps the IDs within this namespace to the global process IDs.
+    global_ids: BTreeMap<usize, ProcessID>,
+    /// The next ID to assign within this namespace.
+    next_local_id: usize
+}
+
+lazy_static! {
+    /// All namespaces except for the root namespace.
+    static ref NAMESPACES: Mutex<BTreeMap<NamespaceID, PidNamespace>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Creates a new namespace within `parent` and returns its ID.
+pub fn create_namespace(parent: NamespaceID) -> NamespaceID {
+    let mut namespaces = NAMESPACES.lock();
+
+    // UNOPTIMIZED
+    let mut id = ROOT_NAMESPACE + 1;
+    while namespaces.contains_key(&id) {
+        id += 1;
+    }
+
+    namespaces.insert(
+        id,
+        PidNamespace {
+            parent,
+            local_ids: BTreeMap::new(),
+            global_ids: BTreeMap::new(),
+            next_local_id: 1
+        }
+    );
+
+    id
+}
+
+/// Makes the process visible in the namespace and all its ancestors.
+pub fn register(pid: ProcessID, namespace: NamespaceID) {
+    let mut namespaces = NAMESPACES.lock();
+    let mut current = namespace;
+
+    while current != ROOT_NAMESPACE {
+        let namespace = namespaces
+            .get_mut(&current)
+            .expect("Process registered in a non existing namespace.");
+
+        let local_id = namespace.next_local_id;
+        namespace.next_local_id += 1;
+
+        namespace.local_ids.insert(pid, local_id);
+        namespace.global_ids.insert(local_id, pid);
+
+        current = namespace.parent;
+    }
+}
+
+/// Removes the process from the namespace and all its ancestors.
+///
+/// Namespaces that don't contain any processes anymore are removed.
+pub fn unregister(pid: ProcessID, namespace: NamespaceID) {
+    let mut namespaces = NAMESPACES.lock();
+    let mut current = namespace;
+
+    while current != ROOT_NAMESPACE {
+        let (parent, is_empty) = {
+            let namespace = namespaces
+                .get_mut(&current)
+                .expect("Process unregistered from a non existing namespace.");
+
+            if let Some(local_id) = namespace.local_ids.remove(&pid) {
+                namespace.global_ids.remove(&local_id);
+            }
+
+            (namespace.parent, namespace.local_ids.is_empty())
+        };
+
+        if is_empty {
+            namespaces.remove(&current);
+        }
+
+        current = parent;
+    }
+}
+
+/// Translates a global process ID to the ID seen within the namespace.
+///
+/// Returns `None` if the process is not visible within the namespace.
+pub fn to_local(namespace: NamespaceID, pid: ProcessID) -> Option<usize> {
+    if namespace == ROOT_NAMESPACE {
+        Some(pid.into())
+    } else {
+        NAMESPACES
+            .lock()
+            .get(&namespace)
+            .and_then(|namespace| namespace.local_ids.get(&pid).cloned())
+    }
+}
+
+/// Translates an ID seen within the namespace to the global process ID.
+///
+/// Returns `None` if no such process is visible within the namespace.
+pub fn to_global(namespace: NamespaceID, local_id: usize) -> Option<ProcessID> {
+    if namespace == ROOT_NAMESPACE {
+        Some(local_id.into())
+    } else {
+        NAMESPACES
+            .lock()
+            .get(&namespace)
+            .and_then(|namespace| namespace.global_ids.get(&local_id).cloned())
+    }
+}
@@ -0,0 +1,241 @@
+//! This module implements the queue of threads that are ready to run.
+//!
+//! There is a FIFO queue for every priority level, so threads of the same
+//! priority take turns. Threads that wait for a long time are aged: their
+//! priority is raised by one level for every `AGING_INTERVAL_MS` they wait,
+//! so even the lowest priority eventually runs next to busy threads of the
+//! highest one. The raised priority only applies while the thread waits and
+//! is reset once it ran.
+
+use super::scheduler::SchedulingClass;
+use super::TCB;
+use alloc::vec_deque::VecDeque;
+use alloc::Vec;
+use sync::time::Timestamp;
+
+/// The number of priority levels.
+pub const PRIORITY_LEVELS: usize = 8;
+
+/// The lowest priority of a thread.
+pub const MIN_PRIORITY: i32 = 0;
+
+/// The highest priority of a thread.
+pub const MAX_PRIORITY: i32 = PRIORITY_LEVELS as i32 - 1;
+
+/// The priority new processes start with.
+pub const DEFAULT_PRIORITY: i32 = 3;
+
+/// The time after which a waiting thread is raised by one priority level.
+const AGING_INTERVAL_MS: u64 = 100;
+
+/// A queued item together with the time it was queued.
+struct Entry<T> {
+    /// The time the item became ready.
+    ready_since: Timestamp,
+    /// The queued item.
+    item: T
+}
+
+/// The queue of ready items, ordered by priority and waiting time.
+pub struct ReadyQueue<T> {
+    /// The FIFO queue of every priority level, lowest priority first.
+    levels: Vec<VecDeque<Entry<T>>>,
+    /// The number of queued items.
+    len: usize
+}
+
+/// Returns true if the priority is one of the priority levels.
+pub fn is_valid_priority(priority: i32) -> bool {
+    priority >= MIN_PRIORITY && priority <= MAX_PRIORITY
+}
+
+/// Returns the priority of an item of the given level that waited since
+/// `ready_since`.
+fn aged_priority(level: usize, ready_since: Timestamp, now: Timestamp) -> i32 {
+    let waited = now.checked_sub(ready_since).unwrap_or_default();
+    let waited_ms = waited
+        .as_secs()
+        .saturating_mul(1000)
+        .saturating_add(u64::from(waited.subsec_millis()));
+    let raised_levels = waited_ms / AGING_INTERVAL_MS;
+
+    if raised_levels >= (MAX_PRIORITY as usize - level) as u64 {
+        MAX_PRIORITY
+    } else {
+        level as i32 + raised_levels as i32
+    }
+}
+
+impl<T> ReadyQueue<T> {
+    /// Creates an empty queue.
+    pub fn new() -> ReadyQueue<T> {
+        ReadyQueue {
+            levels: (0..PRIORITY_LEVELS).map(|_| VecDeque::new()).collect(),
+            len: 0
+        }
+    }
+
+    /// Returns the number of queued items.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no item is queued.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Calls `f` for every queued item, lowest priority level first.
+    pub fn for_each<F: FnMut(&T)>(&self, mut f: F) {
+        for queue in self.levels.iter() {
+            for entry in queue.iter() {
+                f(&entry.item);
+            }
+        }
+    }
+
+    /// Queues the item with the given priority as of `now`.
+    ///
+    /// Priorities outside of the priority levels are clamped.
+    fn push_at(&mut self, item: T, priority: i32, now: Timestamp) {
+        let level = if priority < MIN_PRIORITY {
+            MIN_PRIORITY
+        } else if priority > MAX_PRIORITY {
+            MAX_PRIORITY
+        } else {
+            priority
+        };
+
+        self.levels[level as usize].push_back(Entry {
+            ready_since: now,
+            item
+        });
+        self.len += 1;
+    }
+
+    /// Returns the level of the item that runs next as of `now` and its aged
+    /// priority.
+    ///
+    /// This is the item with the highest aged priority. Of items with the
+    /// same aged priority, the one that waited longest runs first. Only the
+    /// first item of every level needs to be considered, because it waited
+    /// longest within its level.
+    fn first_at(&self, now: Timestamp) -> Option<(usize, i32)> {
+        let mut first: Option<(usize, i32, Timestamp)> = None;
+
+        for (level, queue) in self.levels.iter().enumerate() {
+            if let Some(entry) = queue.front() {
+                let priority = aged_priority(level, entry.ready_since, now);
+                let is_first = match first {
+                    Some((_, first_priority, first_since)) => {
+                        priority > first_priority
+                            || (priority == first_priority && entry.ready_since < first_since)
+                    },
+                    None => true
+                };
+
+                if is_first {
+                    first = Some((level, priority, entry.ready_since));
+                }
+            }
+        }
+
+        first.map(|(level, priority, _)| (level, priority))
+    }
+
+    /// Removes the item that runs next as of `now`.
+    fn pop_at(&mut self, now: Timestamp) -> Option<T> {
+        let (level, _) = self.first_at(now)?;
+        let entry = self.levels[level].pop_front()?;
+        self.len -= 1;
+
+        Some(entry.item)
+    }
+}
+
+impl ReadyQueue<TCB> {
+    /// Queues the thread with its priority.
+    pub fn push(&mut self, thread: TCB) {
+        let priority = thread.priority;
+
+        self.push_at(thread, priority, Timestamp::get_current());
+    }
+
+    /// Removes the thread that runs next.
+    pub fn pop(&mut self) -> Option<TCB> {
+        self.pop_at(Timestamp::get_current())
+    }
+
+    /// Returns the scheduling key of the thread that runs next, including the
+    /// priority it gained while waiting.
+    pub fn peek_key(&self) -> Option<(SchedulingClass, i32)> {
+        let (level, priority) = self.first_at(Timestamp::get_current())?;
+        let class = self.levels[level].front()?.item.class;
+
+        Some((class, priority))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+
+    /// Returns the timestamp the given number of milliseconds after boot.
+    fn at(ms: u64) -> Timestamp {
+        Timestamp::from_duration(Duration::from_millis(ms))
+    }
+
+    /// Tests that higher priorities come first and equal ones in FIFO order.
+    #[test]
+    fn test_order() {
+        let mut queue = ReadyQueue::new();
+
+        queue.push_at("low", 1, at(0));
+        queue.push_at("high 1", 5, at(0));
+        queue.push_at("high 2", 5, at(0));
+        queue.push_at("clamped", 100, at(0));
+
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue.pop_at(at(0)), Some("clamped"));
+        assert_eq!(queue.pop_at(at(0)), Some("high 1"));
+        assert_eq!(queue.pop_at(at(0)), Some("high 2"));
+        assert_eq!(queue.pop_at(at(0)), Some("low"));
+        assert_eq!(queue.pop_at(at(0)), None);
+        assert!(queue.is_empty());
+    }
+
+    /// Tests that waiting raises the priority up to the highest one.
+    #[test]
+    fn test_aged_priority() {
+        assert_eq!(aged_priority(2, at(0), at(0)), 2);
+        assert_eq!(aged_priority(2, at(0), at(AGING_INTERVAL_MS - 1)), 2);
+        assert_eq!(aged_priority(2, at(0), at(3 * AGING_INTERVAL_MS)), 5);
+        assert_eq!(aged_priority(2, at(0), at(60_000)), MAX_PRIORITY);
+        assert_eq!(aged_priority(2, at(10), at(0)), 2);
+    }
+
+    /// Tests that a thread of the lowest priority eventually runs before
+    /// threads of the highest priority that keep becoming ready.
+    #[test]
+    fn test_no_starvation() {
+        let mut queue = ReadyQueue::new();
+        queue.push_at("background", MIN_PRIORITY, at(0));
+
+        let mut now = 0;
+        loop {
+            queue.push_at("interactive", MAX_PRIORITY, at(now));
+            now += 10;
+
+            match queue.pop_at(at(now)) {
+                Some("interactive") => (),
+                Some(_) => break,
+                None => panic!("The queue ran empty.")
+            }
+
+            assert!(now < 1000, "The background thread starved.");
+        }
+
+        assert!(now >= MAX_PRIORITY as u64 * AGING_INTERVAL_MS);
+    }
+}
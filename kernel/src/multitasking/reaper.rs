@@ -0,0 +1,114 @@
+//! Destroys dead threads.
+//!
+//! A thread can't free its own kernel stack while it is still running on it.
+//! Instead the scheduler hands dead threads to the reaper thread after it
+//! switched away from them. The reaper drops them, which frees their stacks
+//! and removes their process once its last thread is gone. Afterwards, the
+//! pages of the page cache that the removed processes were the last to use are
+//! freed. The reaper also reclaims memory when the system runs out of it, see
+//! `memory::oom`.
+//!
+//! The dead threads are passed in a lock-free list, so the scheduler never
+//! waits for the reaper.
+
+use super::scheduler::after_context_switch;
+use super::{spawn_kernel_thread, Name, ThreadState, CURRENT_THREAD, TCB};
+use alloc::boxed::Box;
+use arch::schedule;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use core::time::Duration;
+use memory::{oom, page_cache};
+use sync::enable_preemption;
+use sync::time::Timestamp;
+
+/// The interval in which the reaper checks for dead threads.
+const REAP_INTERVAL_MS: u64 = 10;
+
+/// The address of the most recently added dead thread or zero.
+static DEAD_THREADS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A dead thread in the list of threads to destroy.
+struct DeadThread {
+    /// The thread itself.
+    thread: TCB,
+    /// The address of the next dead thread or zero.
+    next: usize
+}
+
+/// Creates the reaper thread.
+///
+/// This must be called once while the address space of the idle process is
+/// active.
+pub fn init() {
+    assert_has_not_been_called!("There should only be one reaper thread.");
+
+    spawn_kernel_thread(Name::new("reaper"), reaper);
+}
+
+/// Hands the dead thread over to the reaper.
+///
+/// The thread must not be running anymore.
+pub fn enqueue(thread: TCB) {
+    let node = Box::into_raw(Box::new(DeadThread { thread, next: 0 }));
+
+    loop {
+        let head = DEAD_THREADS.load(Ordering::Relaxed);
+
+        unsafe {
+            (*node).next = head;
+        }
+
+        if DEAD_THREADS.compare_and_swap(head, node as usize, Ordering::Release) == head {
+            break;
+        }
+    }
+}
+
+/// Destroys all dead threads that were handed over so far.
+fn reap() {
+    // Taking the whole list at once means there is no other consumer that
+    // could observe a node while it is freed.
+    let mut address = DEAD_THREADS.swap(0, Ordering::Acquire);
+
+    if address == 0 {
+        return;
+    }
+
+    while address != 0 {
+        let node = unsafe { Box::from_raw(address as *mut DeadThread) };
+        address = node.next;
+
+        trace!("Reaping {:?}", node.thread);
+
+        // Dropping the thread frees its stacks and its process if it was the
+        // last thread.
+        drop(node);
+    }
+
+    let dropped = page_cache::trim();
+    if dropped > 0 {
+        trace!("Dropped {} pages from the page cache", dropped);
+    }
+}
+
+/// The function the reaper thread runs.
+fn reaper() -> ! {
+    // The reaper starts right after a context switch away from another
+    // thread.
+    after_context_switch();
+    unsafe {
+        enable_preemption();
+    }
+
+    loop {
+        reap();
+        oom::reclaim();
+
+        let wake_time = Timestamp::get_current()
+            .offset(Duration::from_millis(REAP_INTERVAL_MS))
+            .expect("The time since boot overflowed.");
+
+        CURRENT_THREAD.lock().state = ThreadState::Sleeping(wake_time);
+        schedule();
+    }
+}
@@ -0,0 +1,293 @@
+//! This module implements hierarchical resource groups.
+//!
+//! Every process belongs to a resource group. A group limits the memory
+//! used by all processes within it and its subgroups and determines the
+//! share of CPU time its processes get.
+//!
+//! The user accessible segments of an address space are charged to the group
+//! of its process when they are added, so memory can never be reserved beyond
+//! the limit of a group or any of its ancestors. A process manages the
+//! subgroups of its own group, but never its own group or one outside of it.
+
+use alloc::btree_map::BTreeMap;
+use alloc::string::String;
+use core::fmt::Write;
+use multitasking::{ProcessID, PROCESS_LIST};
+use procfs;
+use sync::{Lazy, RwLock};
+
+/// The type of a resource group ID.
+pub type GroupID = usize;
+
+/// The ID of the root group, which contains all other groups.
+pub const ROOT_GROUP: GroupID = 0;
+
+/// The amount of CPU shares that corresponds to the default time quantum.
+pub const DEFAULT_CPU_SHARES: u32 = 1024;
+
+/// A named group of processes that share resource limits.
+struct ResourceGroup {
+    /// The name of the group.
+    name: String,
+    /// The group this group is a subgroup of.
+    parent: Option<GroupID>,
+    /// The maximum memory used by all processes in this group and its
+    /// subgroups in bytes.
+    memory_limit: usize,
+    /// The memory used by all processes in this group and its subgroups in
+    /// bytes.
+    memory_used: usize,
+    /// The relative share of CPU time for processes in this group.
+    cpu_shares: u32
+}
+
+/// All currently existing resource groups.
+static RESOURCE_GROUPS: Lazy<RwLock<BTreeMap<GroupID, ResourceGroup>>> = Lazy::new(initial_groups);
+
+/// Returns the groups that exist at boot, which is only the root group.
+fn initial_groups() -> RwLock<BTreeMap<GroupID, ResourceGroup>> {
+    let mut map = BTreeMap::new();
+    map.insert(
+        ROOT_GROUP,
+        ResourceGroup {
+            name: String::from("root"),
+            parent: None,
+            memory_limit: usize::max_value(),
+            memory_used: 0,
+            cpu_shares: DEFAULT_CPU_SHARES
+        }
+    );
+
+    RwLock::new(map)
+}
+
+/// Makes the resource groups visible in procfs.
+pub fn init() {
+    procfs::register("resource_groups", describe);
+}
+
+/// Creates a new subgroup of `parent` and returns its ID.
+///
+/// A subgroup never gets more CPU shares than its parent. Returns `None` if
+/// the parent doesn't exist or the name is already used.
+pub fn create_group(
+    name: &str,
+    parent: GroupID,
+    memory_limit: usize,
+    cpu_shares: u32
+) -> Option<GroupID> {
+    let mut groups = RESOURCE_GROUPS.write();
+
+    if groups.values().any(|group| group.name == name) {
+        return None;
+    }
+
+    let parent_shares = groups.get(&parent)?.cpu_shares;
+
+    // UNOPTIMIZED
+    let mut id = ROOT_GROUP + 1;
+    while groups.contains_key(&id) {
+        id += 1;
+    }
+
+    groups.insert(
+        id,
+        ResourceGroup {
+            name: String::from(name),
+            parent: Some(parent),
+            memory_limit,
+            memory_used: 0,
+            cpu_shares: cpu_shares.max(1).min(parent_shares)
+        }
+    );
+
+    Some(id)
+}
+
+/// Changes the limits of the given group.
+///
+/// The group never gets more CPU shares than its parent. Returns false if the
+/// group doesn't exist or is the root group.
+pub fn set_group_limits(id: GroupID, memory_limit: usize, cpu_shares: u32) -> bool {
+    let mut groups = RESOURCE_GROUPS.write();
+
+    let parent_shares = match groups.get(&id).and_then(|group| group.parent) {
+        Some(parent) => groups[&parent].cpu_shares,
+        None => return false
+    };
+
+    let group = groups.get_mut(&id).unwrap();
+    group.memory_limit = memory_limit;
+    group.cpu_shares = cpu_shares.max(1).min(parent_shares);
+
+    true
+}
+
+/// Returns true if the group exists and is `ancestor` or one of its
+/// subgroups.
+pub fn is_within_group(id: GroupID, ancestor: GroupID) -> bool {
+    let groups = RESOURCE_GROUPS.read();
+
+    groups.contains_key(&id) && is_within(&groups, id, ancestor)
+}
+
+/// Returns true if `group` is `ancestor` or one of its subgroups.
+fn is_within(
+    groups: &BTreeMap<GroupID, ResourceGroup>,
+    mut group: GroupID,
+    ancestor: GroupID
+) -> bool {
+    loop {
+        if group == ancestor {
+            return true;
+        }
+
+        let parent = groups.get(&group).and_then(|group| group.parent);
+
+        match parent {
+            Some(parent) => group = parent,
+            None => return false
+        }
+    }
+}
+
+/// Returns true if `amount` more bytes fit into the group and all of its
+/// ancestors.
+fn has_room(groups: &BTreeMap<GroupID, ResourceGroup>, id: GroupID, amount: usize) -> bool {
+    let mut current = Some(id);
+
+    while let Some(group_id) = current {
+        let group = match groups.get(&group_id) {
+            Some(group) => group,
+            None => return false
+        };
+
+        match group.memory_used.checked_add(amount) {
+            Some(used) if used <= group.memory_limit => (),
+            _ => return false
+        }
+
+        current = group.parent;
+    }
+
+    true
+}
+
+/// Adds `amount` to the memory used by the group and all of its ancestors.
+fn add_usage(groups: &mut BTreeMap<GroupID, ResourceGroup>, id: GroupID, amount: usize) {
+    let mut current = Some(id);
+
+    while let Some(group_id) = current {
+        current = groups.get_mut(&group_id).and_then(|group| {
+            group.memory_used += amount;
+            group.parent
+        });
+    }
+}
+
+/// Subtracts `amount` from the memory used by the group and all of its
+/// ancestors.
+fn remove_usage(groups: &mut BTreeMap<GroupID, ResourceGroup>, id: GroupID, amount: usize) {
+    let mut current = Some(id);
+
+    while let Some(group_id) = current {
+        current = groups.get_mut(&group_id).and_then(|group| {
+            group.memory_used = group.memory_used.saturating_sub(amount);
+            group.parent
+        });
+    }
+}
+
+/// Returns true if `amount` more bytes of memory fit into the group and all
+/// of its ancestors.
+pub fn fits(id: GroupID, amount: usize) -> bool {
+    has_room(&RESOURCE_GROUPS.read(), id, amount)
+}
+
+/// Charges `amount` bytes of memory to the group.
+///
+/// Returns false without charging anything if that would exceed the limit of
+/// the group or any of its ancestors.
+pub fn charge(id: GroupID, amount: usize) -> bool {
+    let mut groups = RESOURCE_GROUPS.write();
+
+    if !has_room(&groups, id, amount) {
+        return false;
+    }
+
+    add_usage(&mut groups, id, amount);
+
+    true
+}
+
+/// Returns `amount` bytes of memory that were charged to the group.
+pub fn uncharge(id: GroupID, amount: usize) {
+    remove_usage(&mut RESOURCE_GROUPS.write(), id, amount);
+}
+
+/// Moves `amount` bytes of memory that were charged to `from` to the group
+/// `to`.
+///
+/// Returns false without moving anything if `to` doesn't have room for it.
+pub fn move_charge(from: GroupID, to: GroupID, amount: usize) -> bool {
+    let mut groups = RESOURCE_GROUPS.write();
+
+    // The memory stays charged to the ancestors both groups share.
+    remove_usage(&mut groups, from, amount);
+
+    if has_room(&groups, to, amount) {
+        add_usage(&mut groups, to, amount);
+        true
+    } else {
+        add_usage(&mut groups, from, amount);
+        false
+    }
+}
+
+/// Returns the CPU shares of the group of the given process.
+///
+/// # Note
+/// This locks the process list, so it must not be held by the caller.
+pub fn cpu_shares_of(pid: ProcessID) -> u32 {
+    let group_id = match PROCESS_LIST.lock().get(&pid) {
+        Some(pcb) => pcb.resource_group,
+        None => ROOT_GROUP
+    };
+
+    RESOURCE_GROUPS
+        .read()
+        .get(&group_id)
+        .map_or(DEFAULT_CPU_SHARES, |group| group.cpu_shares)
+}
+
+/// Describes the groups with their limits and the memory they use.
+fn describe() -> String {
+    let mut text = String::new();
+
+    writeln!(
+        text,
+        "{:>5} {:>6} {:>14} {:>14} {:>6} NAME",
+        "ID", "PARENT", "USED KIB", "LIMIT KIB", "SHARES"
+    ).unwrap();
+
+    for (id, group) in RESOURCE_GROUPS.read().iter() {
+        write!(text, "{:>5} ", id).unwrap();
+
+        match group.parent {
+            Some(parent) => write!(text, "{:>6} ", parent).unwrap(),
+            None => write!(text, "{:>6} ", "-").unwrap()
+        }
+
+        write!(text, "{:>14} ", group.memory_used / 1024).unwrap();
+
+        if group.memory_limit == usize::max_value() {
+            write!(text, "{:>14} ", "unlimited").unwrap();
+        } else {
+            write!(text, "{:>14} ", group.memory_limit / 1024).unwrap();
+        }
+
+        writeln!(text, "{:>6} {}", group.cpu_shares, group.name).unwrap();
+    }
+
+    text
+}
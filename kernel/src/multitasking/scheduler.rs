@@ -1,17 +1,68 @@
 //! This module implements a scheduler.
+//!
+//! Every thread belongs to a scheduling class. The idle thread of a CPU is
+//! the only thread of the idle class and is kept apart from the ready list,
+//! so it only runs if there is no ready thread. Other threads are ordered by
+//! their priority, which rises while they wait in the ready list, see
+//! `ready_queue`. A CPU that would otherwise go idle takes a ready thread from
+//! the CPU with the most waiting threads.
+//!
+//! Threads that wait for events sleep without a wake time, until a wait
+//! queue wakes them, see `wait_queue`. The scheduler keeps track of their
+//! waits, so that a wake-up that arrives before the thread actually went to
+//! sleep keeps it from sleeping.
 
+use super::hotplug;
+use super::ready_queue::ReadyQueue;
+use super::reaper;
 use super::tcb::SleepTimeSortedTCB;
-use super::{ThreadState, TCB};
+use super::wait_queue;
+use super::{get_cpu_id, get_cpu_num, ProcessID, ThreadID, ThreadState, PROCESS_LIST, TCB};
 use alloc::binary_heap::BinaryHeap;
+use alloc::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::Vec;
 use arch::{self, schedule, Architecture};
-use core::mem::swap;
+use core::cmp::min;
+use core::fmt::Write;
+use core::mem::{replace, swap};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use core::time::Duration;
+use kdebug;
 use sync::time::Timestamp;
-use sync::Mutex;
-use sync::{disable_preemption, enable_preemption, restore_preemption_state};
-use x86_64::instructions::halt;
+use sync::{enable_preemption, PreemptionGuard};
+use sync::{LockStats, Mutex};
+
+/// The interval in which idle CPUs look for work on other CPUs.
+const REBALANCE_INTERVAL_MS: u64 = 10;
+
+/// The number of frames an idle CPU zeroes before it looks for work again.
+const ZEROING_BATCH: usize = 8;
+
+/// The scheduling classes in ascending order of precedence.
+///
+/// A thread of a higher class always runs before any thread of a lower class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SchedulingClass {
+    /// The class of the idle threads, which only run if nothing else can.
+    ///
+    /// Idle threads are never part of a ready list.
+    Idle,
+    /// The class of all other threads.
+    Normal
+}
+
+/// The contention statistics of the ready lists of all CPUs.
+static READY_LIST_STATS: LockStats = LockStats::new("READY_LIST");
 
 cpu_local! {
-    pub static ref READY_LIST: Mutex<BinaryHeap<TCB>> = |_| Mutex::new(BinaryHeap::new());
+    pub static ref READY_LIST: Mutex<ReadyQueue<TCB>> =
+        |_| Mutex::with_stats(ReadyQueue::new(), &READY_LIST_STATS);
+}
+
+cpu_local! {
+    /// Holds the idle thread of the CPU while it isn't running.
+    static ref IDLE_THREAD: Mutex<Option<TCB>> = |_| Mutex::new(None);
 }
 
 lazy_static! {
@@ -19,11 +70,36 @@ lazy_static! {
         Mutex::new(BinaryHeap::new());
 }
 
+/// The current wait of a thread that waits for events.
+struct WaitState {
+    /// The ticket of the wait.
+    ticket: usize,
+    /// Whether the thread was woken during the wait.
+    woken: bool
+}
+
+lazy_static! {
+    /// The threads that currently wait for events.
+    ///
+    /// To keep the lock order, `SLEEPING_LIST` is never locked while this
+    /// is locked.
+    static ref WAITING: Mutex<BTreeMap<(ProcessID, ThreadID), WaitState>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// The ticket of the next wait.
+static NEXT_TICKET: AtomicUsize = ATOMIC_USIZE_INIT;
+
 cpu_local! {
     /// Holds the TCB of the currently running thread.
     pub static ref CURRENT_THREAD: Mutex<TCB> = |cpu_id| Mutex::new(TCB::idle_tcb(cpu_id));
 }
 
+cpu_local! {
+    /// The time the idle thread of the CPU ran since boot.
+    static ref IDLE_TIME: Mutex<Duration> = |_| Mutex::new(Duration::new(0, 0));
+}
+
 cpu_local! {
     /// Holds the TCB of the previously running thread during context switches.
     static mut ref OLD_THREAD: Option<TCB> = |_| None;
@@ -37,25 +113,58 @@ pub unsafe fn schedule_next_thread() {
     check_sleeping_processes();
 
     // No interrupts during scheduling (this essentially locks OLD_THREAD).
-    let preemption_state = disable_preemption();
+    let mut preemption = PreemptionGuard::new();
 
     debug_assert!(OLD_THREAD.is_none());
 
+    account_cpu_time();
+
+    // A CPU that goes offline only runs its idle thread, which parks it.
+    let going_offline = hotplug::is_going_offline();
+
+    let current_key = {
+        let current_thread = CURRENT_THREAD.lock();
+
+        // The idle thread can always continue.
+        if current_thread.is_idle()
+            || (!going_offline && current_thread.is_running() && !current_thread.is_dead())
+        {
+            Some(current_thread.scheduling_key())
+        } else {
+            None
+        }
+    };
+
+    // A CPU that would otherwise run its idle thread helps out other CPUs.
+    if !going_offline
+        && current_key.map_or(true, |(class, _)| class == SchedulingClass::Idle)
+        && READY_LIST.lock().is_empty()
+    {
+        if let Some(thread) = steal_thread() {
+            READY_LIST.lock().push(thread);
+        }
+    }
+
     let mut ready_list = READY_LIST.lock();
 
-    // Scheduling is needed if:
-    // There is another thread to schedule.
-    let schedule_needed = ready_list.peek().is_some();
-    // And it has at least the same priority.
-    let schedule_needed = schedule_needed && ready_list.peek().unwrap() >= &CURRENT_THREAD.lock();
-    // Or the current thread can't run anymore.
-    let schedule_needed =
-        schedule_needed || !CURRENT_THREAD.lock().is_running() || CURRENT_THREAD.lock().is_dead();
+    let next_key = if going_offline {
+        None
+    } else {
+        ready_list.peek_key()
+    };
+    let decision = decide(current_key, next_key);
 
     // Only switch if actually needed.
-    if schedule_needed {
+    if decision != Decision::Continue {
         // Move the new thread to the temporary spot for old threads.
-        (*OLD_THREAD).set(Some(ready_list.pop().unwrap()));
+        let next_thread = if decision == Decision::SwitchToReady {
+            ready_list.pop()
+        } else {
+            IDLE_THREAD.lock().take()
+        };
+        (*OLD_THREAD).set(Some(
+            next_thread.expect("The thread to switch to doesn't exist.")
+        ));
 
         // Make sure no locks are held when switching.
         drop(ready_list);
@@ -82,18 +191,24 @@ pub unsafe fn schedule_next_thread() {
         CURRENT_THREAD.lock().set_running();
 
         // This is where the actual switch happens.
+        let old_thread = OLD_THREAD.as_mut().as_mut().unwrap();
+        let new_thread = CURRENT_THREAD.without_locking();
+        let (old_address_space, new_address_space) =
+            (old_thread.address_space, new_thread.address_space);
+
+        // The time until the switch belongs to the old thread.
+        preemption.end_audit_region();
+
         arch::Current::switch_context(
-            &mut OLD_THREAD.as_mut().as_mut().unwrap().context,
-            &CURRENT_THREAD.without_locking().context
+            &mut old_thread.context,
+            &new_thread.context,
+            old_address_space,
+            new_address_space
         );
 
         after_context_switch();
-    } else {
-        // Ensure that the correct drop order is used.
-        drop(ready_list);
+        preemption.restart_audit_region();
     }
-
-    restore_preemption_state(&preemption_state);
 }
 
 /// This function should get called after calling `context_switch` to perform
@@ -101,29 +216,330 @@ pub unsafe fn schedule_next_thread() {
 pub fn after_context_switch() {
     if OLD_THREAD.is_some() {
         if OLD_THREAD.as_ref().unwrap().is_dead() {
-            unsafe {
-                // Drop the old thread.
-                OLD_THREAD.as_mut().take();
-            }
+            // The old thread can't be dropped here, because that may take
+            // longer than a context switch should.
+            let old_thread = unsafe { OLD_THREAD.as_mut().take().unwrap() };
+            // A thread that dies during a wait never ends it.
+            WAITING.lock().remove(&(old_thread.pid, old_thread.id));
+            reaper::enqueue(old_thread);
         } else {
             let old_thread = unsafe { OLD_THREAD.as_mut().take().unwrap() };
             return_old_thread_to_queue(old_thread);
         }
     }
+    kdebug::load_watchpoints();
     arch::Current::interrupt_in(CURRENT_THREAD.lock().get_quantum());
 }
 
+/// Adds the time the current thread was running to its process.
+fn account_cpu_time() {
+    let now = Timestamp::get_current();
+
+    let (pid, running_time, is_idle) = {
+        let mut current_thread = CURRENT_THREAD.lock();
+        let running_time = now
+            .checked_sub(current_thread.running_since)
+            .unwrap_or_default();
+        current_thread.running_since = now;
+
+        (current_thread.pid, running_time, current_thread.is_idle())
+    };
+
+    if is_idle {
+        *IDLE_TIME.lock() += running_time;
+    }
+
+    let killed = match PROCESS_LIST.lock().get_mut(&pid) {
+        Some(pcb) => {
+            let was_dead = pcb.is_dead();
+            pcb.account_cpu_time(running_time);
+            !was_dead && pcb.is_dead()
+        },
+        None => false
+    };
+
+    // Other threads of a process that exceeded its CPU time limit may wait.
+    if killed {
+        wake_process(pid);
+    }
+}
+
+/// Returns the time the given CPU was idle since boot.
+///
+/// This is only updated when the CPU schedules, so the current idle period
+/// isn't included yet.
+pub fn get_idle_time(cpu_id: usize) -> Duration {
+    *IDLE_TIME.get_specific(cpu_id).lock()
+}
+
+/// Lists the running, ready and sleeping threads of all CPUs.
+///
+/// A running thread that is locked by its CPU right now is left out.
+pub fn describe_threads() -> String {
+    let mut text = String::new();
+
+    for cpu_id in 0..get_cpu_num() {
+        writeln!(text, "CPU {}:", cpu_id).unwrap();
+
+        match CURRENT_THREAD.get_specific(cpu_id).try_lock() {
+            Some(thread) => writeln!(text, "  running: {:?}", *thread).unwrap(),
+            None => writeln!(text, "  running: (busy)").unwrap()
+        }
+
+        READY_LIST.get_specific(cpu_id).lock().for_each(|thread| {
+            writeln!(text, "  ready: {:?}", thread).unwrap();
+        });
+    }
+
+    for thread in SLEEPING_LIST.lock().iter() {
+        writeln!(text, "sleeping: {:?}", thread.0).unwrap();
+    }
+
+    text
+}
+
 /// Returns the old thread to the corresponding queue after switching the
 /// context.
 fn return_old_thread_to_queue(thread: TCB) {
+    if thread.is_idle() {
+        debug_assert!(!thread.is_dead(), "The idle thread died.");
+
+        let mut idle_thread = IDLE_THREAD.lock();
+        debug_assert!(idle_thread.is_none(), "There is more than one idle thread.");
+        *idle_thread = Some(thread);
+
+        return;
+    }
+
     match thread.state {
         ThreadState::Ready => READY_LIST.lock().push(thread),
-        ThreadState::Sleeping(_) => SLEEPING_LIST.lock().push(SleepTimeSortedTCB(thread)),
+        ThreadState::Sleeping(_) => {
+            let mut sleeping_list = SLEEPING_LIST.lock();
+
+            // A thread that was woken before it went to sleep doesn't sleep.
+            if is_woken_during_wait(thread.pid, thread.id) {
+                READY_LIST.lock().push(thread);
+            } else {
+                sleeping_list.push(SleepTimeSortedTCB(thread));
+            }
+        },
         _ => panic!("Running or dead thread is being returned to a queue.")
     }
 }
 
+/// Starts a wait of the thread for events and returns the ticket of the
+/// wait.
+pub fn start_wait(pid: ProcessID, thread: ThreadID) -> usize {
+    let ticket = NEXT_TICKET.fetch_add(1, Ordering::Relaxed);
+
+    WAITING.lock().insert(
+        (pid, thread),
+        WaitState {
+            ticket,
+            woken: false
+        }
+    );
+
+    ticket
+}
+
+/// Ends the wait of the thread with the ticket.
+pub fn end_wait(pid: ProcessID, thread: ThreadID, ticket: usize) {
+    let mut waiting = WAITING.lock();
+
+    if waiting
+        .get(&(pid, thread))
+        .map_or(false, |wait| wait.ticket == ticket)
+    {
+        waiting.remove(&(pid, thread));
+    }
+}
+
+/// Returns true if the thread was woken during its wait with the ticket.
+pub fn is_woken(pid: ProcessID, thread: ThreadID, ticket: usize) -> bool {
+    WAITING
+        .lock()
+        .get(&(pid, thread))
+        .map_or(false, |wait| wait.ticket == ticket && wait.woken)
+}
+
+/// Returns true if the thread was woken during its current wait.
+fn is_woken_during_wait(pid: ProcessID, thread: ThreadID) -> bool {
+    WAITING
+        .lock()
+        .get(&(pid, thread))
+        .map_or(false, |wait| wait.woken)
+}
+
+/// Wakes the thread, if it is still in its wait with the ticket.
+///
+/// A sleeping thread is moved to the ready list of this CPU. A thread that
+/// didn't go to sleep yet doesn't go to sleep anymore.
+pub fn wake(pid: ProcessID, thread: ThreadID, ticket: usize) {
+    let woken_threads = {
+        let mut sleeping_list = SLEEPING_LIST.lock();
+
+        match WAITING.lock().get_mut(&(pid, thread)) {
+            Some(wait) => {
+                if wait.ticket != ticket || wait.woken {
+                    return;
+                }
+
+                wait.woken = true;
+            },
+            None => return
+        }
+
+        take_sleeping_threads(&mut sleeping_list, |sleeping| {
+            sleeping.pid == pid && sleeping.id == thread
+        })
+    };
+
+    for thread in woken_threads {
+        READY_LIST.lock().push(thread);
+    }
+}
+
+/// Wakes all waiting threads of the process, so that they notice that it
+/// was killed.
+pub fn wake_process(pid: ProcessID) {
+    let woken_threads = {
+        let mut sleeping_list = SLEEPING_LIST.lock();
+
+        let mut waiting = WAITING.lock();
+        let mut threads = Vec::new();
+
+        for (&(waiting_pid, thread), wait) in waiting.iter_mut() {
+            if waiting_pid == pid {
+                wait.woken = true;
+                threads.push(thread);
+            }
+        }
+
+        take_sleeping_threads(&mut sleeping_list, |sleeping| {
+            sleeping.pid == pid && threads.contains(&sleeping.id)
+        })
+    };
+
+    for thread in woken_threads {
+        READY_LIST.lock().push(thread);
+    }
+}
+
+/// Takes the threads that match the predicate out of the sleeping list.
+fn take_sleeping_threads<F>(
+    sleeping_list: &mut BinaryHeap<SleepTimeSortedTCB>,
+    predicate: F
+) -> Vec<TCB>
+where
+    F: Fn(&TCB) -> bool
+{
+    // UNOPTIMIZED
+    let (taken, kept): (Vec<_>, Vec<_>) = replace(sleeping_list, BinaryHeap::new())
+        .into_vec()
+        .into_iter()
+        .partition(|sleeping| predicate(&sleeping.0));
+
+    *sleeping_list = BinaryHeap::from(kept);
+
+    taken.into_iter().map(|sleeping| sleeping.0).collect()
+}
+
+/// What the scheduler does at a scheduling point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    /// The current thread keeps running.
+    Continue,
+    /// The first thread of the ready list runs next.
+    SwitchToReady,
+    /// The idle thread runs next.
+    SwitchToIdle
+}
+
+/// Decides which thread runs next.
+///
+/// `current` is the scheduling key of the current thread or `None` if it
+/// can't continue to run. `next_ready` is the key of the first thread in the
+/// ready list, if there is one, with the priority it gained while waiting.
+fn decide(
+    current: Option<(SchedulingClass, i32)>,
+    next_ready: Option<(SchedulingClass, i32)>
+) -> Decision {
+    debug_assert!(
+        next_ready.map_or(true, |(class, _)| class != SchedulingClass::Idle),
+        "An idle thread is in the ready list."
+    );
+
+    match (current, next_ready) {
+        (Some(current), Some(next)) if next < current => Decision::Continue,
+        (_, Some(_)) => Decision::SwitchToReady,
+        (Some(_), None) => Decision::Continue,
+        (None, None) => Decision::SwitchToIdle
+    }
+}
+
+/// Returns the CPU whose waiting threads should be taken over by the given
+/// CPU, given the number of ready threads of every CPU.
+///
+/// This is the CPU with the most waiting threads, if any CPU has some.
+fn busiest_cpu(ready_counts: &[usize], cpu_id: usize) -> Option<usize> {
+    ready_counts
+        .iter()
+        .enumerate()
+        .filter(|&(id, &count)| id != cpu_id && count > 0)
+        .max_by_key(|&(_, &count)| count)
+        .map(|(id, _)| id)
+}
+
+/// Returns the number of ready threads of every CPU.
+fn ready_counts() -> Vec<usize> {
+    (0..get_cpu_num())
+        .map(|cpu_id| READY_LIST.get_specific(cpu_id).lock().len())
+        .collect()
+}
+
+/// Takes the first ready thread of the busiest other CPU.
+///
+/// The ready lists are locked one at a time, so the numbers may already be
+/// outdated when the thread is taken.
+fn steal_thread() -> Option<TCB> {
+    if get_cpu_num() < 2 {
+        return None;
+    }
+
+    let cpu_id = busiest_cpu(&ready_counts(), get_cpu_id())?;
+    let thread = READY_LIST.get_specific(cpu_id).lock().pop();
+
+    if let Some(ref thread) = thread {
+        trace!("Taking {:?} over from CPU {}", thread, cpu_id);
+    }
+
+    thread
+}
+
+/// Moves the ready threads of the current CPU to the ready list of the given
+/// CPU.
+pub fn move_ready_threads(cpu_id: usize) {
+    let mut threads = Vec::new();
+
+    {
+        let mut ready_list = READY_LIST.lock();
+
+        while let Some(thread) = ready_list.pop() {
+            threads.push(thread);
+        }
+    }
+
+    let mut target = READY_LIST.get_specific(cpu_id).lock();
+
+    for thread in threads {
+        target.push(thread);
+    }
+}
+
 /// Updates the status for processes that were sleeping.
+///
+/// Wait queues whose timeouts expired are woken as well.
 fn check_sleeping_processes() {
     {
         let mut sleeping_list = SLEEPING_LIST.lock();
@@ -142,6 +558,35 @@ fn check_sleeping_processes() {
             }
         }
     }
+
+    wait_queue::wake_expired();
+}
+
+/// Zeroes up to `ZEROING_BATCH` free frames ahead of time.
+///
+/// Returns true if any frame was zeroed, so there may be more to do.
+fn zero_free_frames() -> bool {
+    let mut zeroed = 0;
+
+    while zeroed < ZEROING_BATCH && arch::Current::zero_free_frame() {
+        zeroed += 1;
+    }
+
+    zeroed > 0
+}
+
+/// Returns the time at which the next sleeping thread or wait queue has to
+/// be woken.
+fn next_wake_time() -> Option<Timestamp> {
+    let next_thread = SLEEPING_LIST
+        .lock()
+        .peek()
+        .map(|thread| thread.get_wake_time());
+
+    match (next_thread, wait_queue::next_timeout()) {
+        (Some(thread_time), Some(timeout)) => Some(min(thread_time, timeout)),
+        (thread_time, timeout) => thread_time.or(timeout)
+    }
 }
 
 /// This function gets executed whenever there is nothing else to execute.
@@ -150,7 +595,8 @@ fn check_sleeping_processes() {
 ///
 /// Once it's done performing it's initial cleanup, it sleeps in a loop,
 /// performing periodic cleanup. It should also be interruptable as often as
-/// possible.
+/// possible. Before sleeping, it zeroes free frames for later zeroed
+/// allocations.
 pub fn idle() -> ! {
     // TODO: Peform initial cleanup here.
     unsafe {
@@ -158,20 +604,129 @@ pub fn idle() -> ! {
         schedule();
     }
     loop {
+        if hotplug::is_going_offline() {
+            hotplug::park_current_cpu();
+        }
+
         // TODO: Perform periodic cleanup here.
+        if ::config::PAGE_TABLE_CHECKS {
+            ::memory::checks::check_periodically();
+        }
+
         unsafe {
+            if let Some(wake_time) = next_wake_time() {
+                let current_time = Timestamp::get_current();
+                if let Some(sleep_duration) = wake_time.checked_sub(current_time) {
+                    arch::Current::interrupt_in(sleep_duration);
+                } else {
+                    schedule();
+                }
+            } else if get_cpu_num() > 1 {
+                arch::Current::interrupt_in(Duration::from_millis(REBALANCE_INTERVAL_MS));
+            }
+            // Threads that were woken by interrupts are in the ready list.
+            if !READY_LIST.lock().is_empty()
+                || (get_cpu_num() > 1 && busiest_cpu(&ready_counts(), get_cpu_id()).is_some())
             {
-                if let Some(next_wake_thread) = SLEEPING_LIST.lock().peek() {
-                    let current_time = Timestamp::get_current();
-                    let wake_time = next_wake_thread.get_wake_time();
-                    if let Some(sleep_duration) = wake_time.checked_sub(current_time) {
-                        arch::Current::interrupt_in(sleep_duration);
-                    } else {
-                        schedule();
-                    }
+                schedule();
+            }
+            if !zero_free_frames() {
+                // A thread that is woken in between must not be missed.
+                arch::Current::disable_interrupts();
+                if READY_LIST.lock().is_empty() {
+                    arch::Current::enable_interrupts_and_halt();
+                } else {
+                    arch::Current::enable_interrupts();
                 }
             }
-            halt();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The scheduling key of a normal thread with the given priority.
+    fn normal(priority: i32) -> Option<(SchedulingClass, i32)> {
+        Some((SchedulingClass::Normal, priority))
+    }
+
+    /// The scheduling key of the idle thread.
+    fn idle() -> Option<(SchedulingClass, i32)> {
+        Some((SchedulingClass::Idle, i32::min_value()))
+    }
+
+    /// Tests that the idle thread never runs while other threads are ready.
+    #[test]
+    fn test_idle_only_without_work() {
+        assert_eq!(
+            decide(idle(), normal(i32::min_value())),
+            Decision::SwitchToReady
+        );
+        assert_eq!(decide(None, normal(1)), Decision::SwitchToReady);
+        assert_eq!(decide(idle(), None), Decision::Continue);
+        assert_eq!(decide(None, None), Decision::SwitchToIdle);
+        assert_eq!(decide(normal(1), None), Decision::Continue);
+    }
+
+    /// Tests that higher priorities run first and equal priorities take turns.
+    #[test]
+    fn test_priorities() {
+        assert_eq!(decide(normal(2), normal(1)), Decision::Continue);
+        assert_eq!(decide(normal(1), normal(2)), Decision::SwitchToReady);
+        assert_eq!(decide(normal(1), normal(1)), Decision::SwitchToReady);
+    }
+
+    /// Tests that idle CPUs take over threads from the busiest other CPU.
+    #[test]
+    fn test_busiest_cpu() {
+        assert_eq!(busiest_cpu(&[0, 0, 0, 0], 0), None);
+        assert_eq!(busiest_cpu(&[5, 0, 0, 0], 0), None);
+        assert_eq!(busiest_cpu(&[0, 1, 3, 2], 0), Some(2));
+        assert_eq!(busiest_cpu(&[4, 1, 0, 2], 2), Some(0));
+    }
+
+    /// Simulates a CPU with two threads of the same priority that block one
+    /// after the other until one of them is woken up again.
+    #[test]
+    fn test_simulation() {
+        let expected = [
+            SchedulingClass::Normal,
+            SchedulingClass::Normal,
+            SchedulingClass::Normal,
+            SchedulingClass::Idle,
+            SchedulingClass::Idle,
+            SchedulingClass::Normal
+        ];
+        let mut ready = 2;
+        let mut current = idle();
+
+        for (step, &expected_class) in expected.iter().enumerate() {
+            if step == 5 {
+                ready += 1;
+            }
+
+            let running = if step == 2 || step == 3 {
+                None
+            } else {
+                current
+            };
+            let next = if ready > 0 { normal(1) } else { None };
+
+            match decide(running, next) {
+                Decision::Continue => (),
+                Decision::SwitchToReady => {
+                    if running.map_or(false, |(class, _)| class == SchedulingClass::Normal) {
+                        ready += 1;
+                    }
+                    ready -= 1;
+                    current = normal(1);
+                },
+                Decision::SwitchToIdle => current = idle()
+            }
+
+            assert_eq!(current.map(|(class, _)| class), Some(expected_class));
         }
     }
 }
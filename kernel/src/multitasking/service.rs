@@ -0,0 +1,63 @@
+//! This module implements the registry of named services.
+//!
+//! Servers register their IPC endpoints under a name, so that clients can
+//! find them without knowing their process ID. A process may only register
+//! the names granted by its manifest, unless it has the `REGISTER_SERVICE`
+//! capability. A name stays registered until its server exits.
+
+use alloc::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::Vec;
+use multitasking::ProcessID;
+use sync::Mutex;
+
+/// The maximum length of a service name.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// A registered service.
+#[derive(Debug, Clone, Copy)]
+pub struct Service {
+    /// The process that provides the service.
+    pub process: ProcessID,
+    /// The endpoint of the service within the process.
+    pub endpoint: usize
+}
+
+lazy_static! {
+    /// Maps the names of all registered services to the services.
+    static ref SERVICES: Mutex<BTreeMap<String, Service>> = Mutex::new(BTreeMap::new());
+}
+
+/// Registers the service under the given name.
+///
+/// Returns false if the name is already in use.
+pub fn register(name: &str, service: Service) -> bool {
+    let mut services = SERVICES.lock();
+
+    if services.contains_key(name) {
+        false
+    } else {
+        services.insert(String::from(name), service);
+        true
+    }
+}
+
+/// Returns the service registered under the given name.
+pub fn lookup(name: &str) -> Option<Service> {
+    SERVICES.lock().get(name).cloned()
+}
+
+/// Removes all services of the given process.
+pub fn unregister_process(process: ProcessID) {
+    let mut services = SERVICES.lock();
+
+    let names: Vec<String> = services
+        .iter()
+        .filter(|&(_, service)| service.process == process)
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    for name in names {
+        services.remove(&name);
+    }
+}
@@ -0,0 +1,20 @@
+//! This module defines the signals that can be raised for a process.
+//!
+//! There are no userspace signal handlers yet, so raised signals are only
+//! recorded as pending in the process. A process can receive its pending
+//! signals by reading a signal descriptor.
+
+use multitasking::wait_queue::WaitQueue;
+
+bitflags! {
+    /// A set of signals.
+    pub flags SignalSet: u64 {
+        /// The process exceeded its soft CPU time limit.
+        const CPU_TIME_LIMIT_EXCEEDED = 1 << 0
+    }
+}
+
+lazy_static! {
+    /// Woken when signals are raised for any process.
+    pub static ref RAISED: WaitQueue = WaitQueue::new();
+}
@@ -1,11 +1,56 @@
 //! Provides functionality to manage multiple stacks.
 
+use alloc::Vec;
 use arch::{self, Architecture};
 use core::cmp::{max, min};
 use core::fmt;
 use core::mem::size_of;
 use memory::address_space::{AddressSpace, Segment, SegmentType};
-use memory::{MemoryArea, VirtualAddress, READABLE, USER_ACCESSIBLE, WRITABLE};
+use memory::{Address, MemoryArea, PhysicalAddress, VirtualAddress, PAGE_SIZE, READABLE,
+             USER_ACCESSIBLE, WRITABLE};
+use sync::Mutex;
+
+/// The maximum number of frames kept in the kernel stack pool.
+const KERNEL_STACK_POOL_SIZE: usize = 64;
+
+lazy_static! {
+    /// Frames that are ready to be used for kernel stacks.
+    ///
+    /// Frames of kernel stacks that shrink are returned here, so creating
+    /// threads doesn't need to go through the frame allocator.
+    static ref KERNEL_STACK_POOL: Mutex<Vec<PhysicalAddress>> = Mutex::new(Vec::new());
+}
+
+/// Fills the kernel stack pool.
+pub fn preallocate_kernel_stacks() {
+    let mut pool = KERNEL_STACK_POOL.lock();
+
+    while pool.len() < KERNEL_STACK_POOL_SIZE {
+        pool.push(arch::Current::allocate_zeroed_frame());
+    }
+}
+
+/// Takes a frame for a kernel stack from the pool.
+fn take_kernel_stack_frame() -> PhysicalAddress {
+    let frame = KERNEL_STACK_POOL.lock().pop();
+
+    frame.unwrap_or_else(|| arch::Current::allocate_zeroed_frame())
+}
+
+/// Returns a frame of a kernel stack to the pool.
+///
+/// # Safety
+/// - The frame must not be used anymore.
+unsafe fn return_kernel_stack_frame(frame: PhysicalAddress) {
+    let mut pool = KERNEL_STACK_POOL.lock();
+
+    if pool.len() < KERNEL_STACK_POOL_SIZE {
+        pool.push(frame);
+    } else {
+        drop(pool);
+        arch::Current::release_frame(frame);
+    }
+}
 
 // NOTE: For now only full descending stacks are supported.
 /// Represents the different types of stacks that exist.
@@ -22,7 +67,7 @@ pub enum StackType {
 }
 
 /// Determines the type of accesses possible for this stack.
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub enum AccessType {
     /// The stack can be accessed by usermode code.
     UserAccessible,
@@ -80,6 +125,31 @@ impl Stack {
         }
     }
 
+    /// Pushes the given bytes to the stack pointed to in the given address
+    /// space.
+    ///
+    /// Afterwards the stack pointer is aligned to `alignment`, which must be a
+    /// power of two.
+    pub fn push_bytes_in(
+        address_space: &mut AddressSpace,
+        stack_pointer: &mut VirtualAddress,
+        bytes: &[u8],
+        alignment: usize
+    ) {
+        match arch::Current::STACK_TYPE {
+            StackType::FullDescending => {
+                *stack_pointer -= bytes.len();
+                if bytes.len() > 0 {
+                    address_space.write_to(bytes, *stack_pointer);
+                }
+                *stack_pointer = VirtualAddress::from_usize(
+                    stack_pointer.as_usize() & !(alignment - 1)
+                );
+            },
+            _ => unimplemented!("Currently only Full Descending stacks are implemented")
+        }
+    }
+
     /// Creates a new stack of size zero with the given start address.
     pub fn new(
         initial_size: usize,
@@ -139,14 +209,28 @@ impl Stack {
                 // This should be one less, but the range is exclusive.
                 let last_page_to_map = self.bottom_address.page_num();
 
-                // TODO: flags shouldn't be passed, it should be segment checked instead.
-                let mut map_fn = |page_address, flags| match address_space {
-                    Some(ref mut address_space) => address_space.map_page(page_address),
-                    None => arch::Current::map_page(page_address, flags)
-                };
+                if last_page_to_map > first_page_to_map {
+                    let first_page_address = VirtualAddress::from_page_num(first_page_to_map);
+                    let count = last_page_to_map - first_page_to_map;
 
-                for page_num in first_page_to_map..last_page_to_map {
-                    map_fn(VirtualAddress::from_page_num(page_num), flags);
+                    match address_space {
+                        Some(ref mut address_space) => {
+                            if self.access_type == AccessType::KernelOnly {
+                                for i in 0..count {
+                                    let frame = take_kernel_stack_frame();
+                                    address_space
+                                        .map_page_to(first_page_address + i * PAGE_SIZE, frame)
+                                        .expect("The stack pages must be mappable.");
+                                }
+                            } else {
+                                address_space
+                                    .map_range(first_page_address, count)
+                                    .expect("The stack pages must be mappable.");
+                            }
+                        },
+                        // TODO: flags shouldn't be passed, it should be segment checked instead.
+                        None => arch::Current::map_range(first_page_address, count, flags)
+                    }
                 }
 
                 self.bottom_address = new_bottom;
@@ -166,15 +250,30 @@ impl Stack {
                 // This should be one less, but the range is exclusive.
                 let last_page_to_unmap = new_bottom.page_num();
 
-                let mut unmap_fn = |page_address| unsafe {
-                    match address_space {
-                        Some(ref mut address_space) => address_space.unmap_page(page_address),
-                        None => arch::Current::unmap_page(page_address)
-                    }
-                };
+                if last_page_to_unmap > first_page_to_unmap {
+                    let first_page_address = VirtualAddress::from_page_num(first_page_to_unmap);
+                    let count = last_page_to_unmap - first_page_to_unmap;
 
-                for page_num in first_page_to_unmap..last_page_to_unmap {
-                    unmap_fn(VirtualAddress::from_page_num(page_num));
+                    unsafe {
+                        match address_space {
+                            Some(ref mut address_space) => {
+                                if self.access_type == AccessType::KernelOnly {
+                                    for i in 0..count {
+                                        let page_address = first_page_address + i * PAGE_SIZE;
+
+                                        if let Some(frame) =
+                                            address_space.unmap_page_keeping_frame(page_address)
+                                        {
+                                            return_kernel_stack_frame(frame);
+                                        }
+                                    }
+                                } else {
+                                    address_space.unmap_range(first_page_address, count);
+                                }
+                            },
+                            None => arch::Current::unmap_range(first_page_address, count)
+                        }
+                    }
                 }
 
                 self.bottom_address = new_bottom;
@@ -183,6 +282,30 @@ impl Stack {
         }
     }
 
+    /// Returns the area the stack can grow into.
+    pub fn area(&self) -> MemoryArea<VirtualAddress> {
+        match arch::Current::STACK_TYPE {
+            StackType::FullDescending => {
+                MemoryArea::new(self.top_address - self.max_size, self.max_size)
+            },
+            _ => unimplemented!("Currently only Full Descending stacks are implemented")
+        }
+    }
+
+    /// Returns a stack that covers the same area as this one.
+    ///
+    /// Nothing is mapped, so the new stack is only usable in a forked copy of
+    /// the address space of this stack.
+    pub fn duplicate(&self) -> Stack {
+        Stack {
+            top_address: self.top_address,
+            bottom_address: self.bottom_address,
+            max_size: self.max_size,
+            base_stack_pointer: self.base_stack_pointer,
+            access_type: self.access_type
+        }
+    }
+
     /// Resizes the stack to the given size.
     pub fn resize(&mut self, new_size: usize, address_space: Option<&mut AddressSpace>) {
         let current_size = (self.top_address - self.bottom_address) as isize;
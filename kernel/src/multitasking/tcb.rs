@@ -1,12 +1,20 @@
 //! This module defines thread control blocks (TCBs).
 
+use super::name::Name;
+use super::ready_queue::DEFAULT_PRIORITY;
+use super::resource_group::{cpu_shares_of, DEFAULT_CPU_SHARES};
+use super::scheduler::{idle, SchedulingClass};
+use super::{futex, service};
 use super::stack::AccessType;
-use super::{ProcessID, Stack, ThreadID, PCB, PROCESS_LIST};
+use super::{remove_process, ProcessID, Stack, ThreadID, PCB, PROCESS_LIST};
+use alloc::Vec;
+use ipc::{grant, message, ring};
 use arch::{self, Architecture};
 use core::cmp::Ordering;
 use core::fmt;
 use core::time::Duration;
-use memory::{VirtualAddress, AddressSpaceManager};
+use memory::address_space::{AddressSpace, AddressSpaceHandle};
+use memory::{Address, VirtualAddress, AddressSpaceManager};
 use sync::time::Timestamp;
 
 /// Represents the possible states a thread can have.
@@ -30,28 +38,46 @@ pub struct TCB {
     pub id: ThreadID,
     /// The ID of the process that the thread belongs to.
     pub pid: ProcessID,
+    /// The name of the thread, used for diagnostics.
+    ///
+    /// Threads start with the name of their process.
+    pub name: Name,
     /// The stack used during kernel operations.
     pub kernel_stack: Stack,
     /// The usermode stack.
     pub user_stack: Stack,
     /// The state of the thread.
     pub state: ThreadState,
-    /// The priority of the thread.
+    /// The scheduling class of the thread.
+    pub class: SchedulingClass,
+    /// The priority of the thread within its scheduling class.
+    ///
+    /// This is one of the levels of `ready_queue`, except for idle threads.
     pub priority: i32,
+    /// The time at which the thread started running the last time.
+    pub running_since: Timestamp,
+    /// The address space the thread runs in.
+    pub address_space: AddressSpaceHandle,
     /// The architecture specific context of this thread.
     pub context: <arch::Current as Architecture>::Context
 }
 
 impl fmt::Debug for TCB {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.pid == 0.into() {
+        if self.is_idle() {
             write!(f, "Thread <IDLE on CPU {}> ({:?})", self.id.0, self.state)
-        } else {
+        } else if self.name.is_empty() {
             write!(
                 f,
                 "Thread <{:?}, {:?}> ({:?})",
                 self.id, self.pid, self.state
             )
+        } else {
+            write!(
+                f,
+                "Thread <{:?}, {:?}, {}> ({:?})",
+                self.id, self.pid, self.name, self.state
+            )
         }
     }
 }
@@ -67,7 +93,7 @@ impl Eq for TCB {}
 
 impl Ord for TCB {
     fn cmp(&self, other: &TCB) -> Ordering {
-        self.priority.cmp(&other.priority)
+        self.scheduling_key().cmp(&other.scheduling_key())
     }
 }
 
@@ -81,21 +107,48 @@ impl Drop for TCB {
     fn drop(&mut self) {
         let mut process_list = PROCESS_LIST.lock();
 
-        let drop_pcb = {
+        let (drop_pcb, parent) = {
             let pcb = process_list
                 .get_mut(&self.pid)
                 .expect("Process of the thread doesn't exist.");
 
-            pcb.thread_count -= 1;
+            pcb.remove_thread(self.id);
+            futex::stop_waiting(self.pid, self.id);
 
             self.kernel_stack.resize(0, Some(&mut pcb.address_space));
             self.user_stack.resize(0, Some(&mut pcb.address_space));
+            pcb.address_space.remove_segment(self.user_stack.area());
 
-            pcb.is_droppable()
+            (pcb.is_droppable(), pcb.parent)
         };
 
         if drop_pcb {
-            process_list.remove(&self.pid);
+            service::unregister_process(self.pid);
+            ring::withdraw_offers(self.pid);
+            grant::withdraw_grants(self.pid);
+            message::remove_mailbox(self.pid);
+
+            // Release the processes traced by this process.
+            for pcb in process_list.values_mut() {
+                if pcb.trace.as_ref().map_or(false, |trace| trace.tracer == self.pid) {
+                    pcb.trace = None;
+                }
+            }
+
+            // The process is kept as a zombie until its parent learned how it
+            // ended.
+            if let Some(parent) = parent {
+                process_list
+                    .get_mut(&self.pid)
+                    .expect("Process of the thread doesn't exist.")
+                    .make_zombie();
+
+                if let Some(parent) = process_list.get(&parent) {
+                    parent.child_exits.wake_all();
+                }
+            } else {
+                remove_process(&mut process_list, self.pid);
+            }
         }
     }
 }
@@ -103,16 +156,22 @@ impl Drop for TCB {
 impl TCB {
     /// Creates a new thread in the given process at the given start address.
     pub fn in_process(pid: ProcessID, id: ThreadID, pc: VirtualAddress, pcb: &mut PCB) -> TCB {
-        TCB::in_process_with_arguments(pid, id, pc, pcb, 0, 0, 0, 0, 0)
+        let user_stack = pcb
+            .address_space
+            .create_user_stack(AddressSpace::user_stack_size())
+            .expect("There is no room for the user stack.");
+
+        TCB::in_process_with_arguments(pid, id, pc, pcb, user_stack, 0, 0, 0, 0, 0)
     }
 
     /// Creates a new thread in the given process at the given start address
-    /// with the given arguments.
+    /// that uses the given user stack and receives the given arguments.
     pub fn in_process_with_arguments(
         pid: ProcessID,
         id: ThreadID,
         pc: VirtualAddress,
         pcb: &mut PCB,
+        user_stack: Stack,
         arg1: usize,
         arg2: usize,
         arg3: usize,
@@ -121,18 +180,121 @@ impl TCB {
     ) -> TCB {
         let kernel_stack = pcb.address_space.create_kernel_stack(id);
 
-        let user_stack = pcb.address_space.create_user_stack(id);
-
         let stack_pointer = user_stack.base_stack_pointer;
+
+        TCB::from_stacks(
+            pid,
+            id,
+            pc,
+            pcb,
+            kernel_stack,
+            user_stack,
+            stack_pointer,
+            arg1,
+            arg2,
+            arg3,
+            arg4,
+            arg5
+        )
+    }
+
+    /// Creates a new thread in the given process at the given start address
+    /// that receives the given program arguments and environment variables.
+    ///
+    /// The arguments and environment variables are placed on the user stack
+    /// as null terminated strings, followed by a null terminated array of
+    /// pointers to the arguments, which is directly followed by a null
+    /// terminated array of pointers to the environment variables. The thread
+    /// gets the amount of arguments as its first and the address of the
+    /// argument array as its second argument.
+    ///
+    /// Returns `None` if there is no room for the user stack.
+    pub fn with_program_arguments(
+        pid: ProcessID,
+        id: ThreadID,
+        pc: VirtualAddress,
+        pcb: &mut PCB,
+        arguments: &[&str],
+        environment: &[&str]
+    ) -> Option<TCB> {
+        let user_stack = pcb
+            .address_space
+            .create_user_stack(AddressSpace::user_stack_size())?;
+
+        let kernel_stack = pcb.address_space.create_kernel_stack(id);
+
+        let mut stack_pointer = user_stack.base_stack_pointer;
+
+        let argument_addresses =
+            push_strings(&mut pcb.address_space, &mut stack_pointer, arguments);
+        let environment_addresses =
+            push_strings(&mut pcb.address_space, &mut stack_pointer, environment);
+
+        Stack::push_bytes_in(&mut pcb.address_space, &mut stack_pointer, &[], 16);
+
+        // Keep the stack pointer 16 byte aligned after the arrays and their
+        // two null pointers were pushed.
+        if (argument_addresses.len() + environment_addresses.len()) % 2 == 1 {
+            Stack::push_in(&mut pcb.address_space, &mut stack_pointer, 0usize);
+        }
+
+        for addresses in &[environment_addresses, argument_addresses] {
+            Stack::push_in(&mut pcb.address_space, &mut stack_pointer, 0usize);
+            for address in addresses.iter().rev() {
+                Stack::push_in(
+                    &mut pcb.address_space,
+                    &mut stack_pointer,
+                    address.as_usize()
+                );
+            }
+        }
+
+        let argument_vector = stack_pointer;
+
+        Some(TCB::from_stacks(
+            pid,
+            id,
+            pc,
+            pcb,
+            kernel_stack,
+            user_stack,
+            stack_pointer,
+            arguments.len(),
+            argument_vector.as_usize(),
+            0,
+            0,
+            0
+        ))
+    }
+
+    /// Creates a new thread using the given stacks.
+    fn from_stacks(
+        pid: ProcessID,
+        id: ThreadID,
+        pc: VirtualAddress,
+        pcb: &mut PCB,
+        kernel_stack: Stack,
+        user_stack: Stack,
+        stack_pointer: VirtualAddress,
+        arg1: usize,
+        arg2: usize,
+        arg3: usize,
+        arg4: usize,
+        arg5: usize
+    ) -> TCB {
         let kernel_stack_pointer = kernel_stack.base_stack_pointer;
 
         TCB {
             id,
             pid,
+            name: pcb.name,
             kernel_stack,
             user_stack,
             state: ThreadState::Ready,
-            priority: 1,
+            class: SchedulingClass::Normal,
+            priority: DEFAULT_PRIORITY,
+            running_since: Timestamp::from_duration(Duration::new(0, 0)),
+            address_space: pcb.address_space.handle(),
             context: <<arch::Current as Architecture>::Context as arch::Context>::new(
                 pc,
                 stack_pointer,
@@ -147,18 +309,56 @@ impl TCB {
         }
     }
 
+    /// Creates a copy of the current thread in the given forked process.
+    ///
+    /// The new thread continues where the current thread returns from its
+    /// syscall and uses the given copy of its user stack.
+    pub fn forked(pid: ProcessID, id: ThreadID, pcb: &mut PCB, user_stack: Stack) -> TCB {
+        let kernel_stack = pcb.address_space.create_kernel_stack(id);
+        let kernel_stack_pointer = kernel_stack.base_stack_pointer;
+
+        TCB {
+            id,
+            pid,
+            name: pcb.name,
+            kernel_stack,
+            user_stack,
+            state: ThreadState::Ready,
+            class: SchedulingClass::Normal,
+            priority: DEFAULT_PRIORITY,
+            running_since: Timestamp::from_duration(Duration::new(0, 0)),
+            address_space: pcb.address_space.handle(),
+            context: <<arch::Current as Architecture>::Context as arch::Context>::fork(
+                kernel_stack_pointer,
+                &mut pcb.address_space
+            )
+        }
+    }
+
     /// Creates a new TCB for an idle thread.
     pub fn idle_tcb(cpu_id: usize) -> TCB {
-        let id: ThreadID = cpu_id.into();
+        let mut tcb = TCB::kernel_thread(cpu_id.into(), Name::new("idle"), idle);
 
+        tcb.class = SchedulingClass::Idle;
+        tcb.priority = i32::min_value();
+
+        tcb
+    }
+
+    /// Creates a new TCB for a thread of the idle process that runs the given
+    /// function in kernel mode.
+    ///
+    /// The thread ID must already be added to the idle process.
+    pub fn kernel_thread(id: ThreadID, name: Name, function: fn() -> !) -> TCB {
         // NOTE: This assumes that the idle address space is currently active.
-        let kernel_stack = <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::create_idle_stack(cpu_id);
+        let kernel_stack = <<arch::Current as Architecture>::AddressSpaceManager as AddressSpaceManager>::create_kernel_thread_stack(id);
 
         let stack_pointer = kernel_stack.base_stack_pointer;
 
         TCB {
             id,
             pid: 0.into(),
+            name,
             kernel_stack,
             user_stack: Stack::new(
                 0,
@@ -168,13 +368,29 @@ impl TCB {
                 None
             ),
             state: ThreadState::Ready,
-            priority: i32::min_value(),
-            context: <<arch::Current as Architecture>::Context as arch::Context>::idle(
+            class: SchedulingClass::Normal,
+            priority: DEFAULT_PRIORITY,
+            running_since: Timestamp::from_duration(Duration::new(0, 0)),
+            address_space: arch::Current::get_current_address_space(),
+            context: <<arch::Current as Architecture>::Context as arch::Context>::kernel_thread(
+                function,
                 stack_pointer
             )
         }
     }
 
+    /// Returns the key by which the scheduler orders threads.
+    ///
+    /// Threads with a greater key run first.
+    pub fn scheduling_key(&self) -> (SchedulingClass, i32) {
+        (self.class, self.priority)
+    }
+
+    /// Returns true if this is the idle thread of a CPU.
+    pub fn is_idle(&self) -> bool {
+        self.class == SchedulingClass::Idle
+    }
+
     /// Returns true if the thread state is dead.
     pub fn is_dead(&self) -> bool {
         let process_list = PROCESS_LIST.lock();
@@ -202,6 +418,7 @@ impl TCB {
         debug_assert!(!self.is_dead(), "Trying to run a dead thread: {:?}", self);
 
         self.state = ThreadState::Running;
+        self.running_since = Timestamp::get_current();
     }
 
     /// Marks this thread as dead.
@@ -212,11 +429,31 @@ impl TCB {
     }
 
     /// Returns the time quantum this process should run.
+    ///
+    /// The quantum is scaled by the CPU shares of the resource group.
     pub fn get_quantum(&self) -> Duration {
-        Duration::from_millis(150)
+        Duration::from_millis(150) * cpu_shares_of(self.pid) / DEFAULT_CPU_SHARES
     }
 }
 
+/// Pushes the strings null terminated onto the stack and returns their
+/// addresses.
+fn push_strings(
+    address_space: &mut AddressSpace,
+    stack_pointer: &mut VirtualAddress,
+    strings: &[&str]
+) -> Vec<VirtualAddress> {
+    let mut addresses = Vec::with_capacity(strings.len());
+
+    for string in strings {
+        Stack::push_in(address_space, stack_pointer, 0u8);
+        Stack::push_bytes_in(address_space, stack_pointer, string.as_bytes(), 1);
+        addresses.push(*stack_pointer);
+    }
+
+    addresses
+}
+
 /// A TCB that is sorted by its sleep time (shortest first).
 pub struct SleepTimeSortedTCB(pub TCB);
 
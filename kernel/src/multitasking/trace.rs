@@ -0,0 +1,237 @@
+//! This module implements the tracing of processes by other processes.
+//!
+//! A traced process stops whenever one of its threads faults, hits a
+//! breakpoint or watchpoint, finishes a single step or, if requested,
+//! performs a syscall.
+//! The tracer can then inspect and modify the stopped thread before resuming
+//! it.
+
+use kdebug::{WatchpointSet, WATCHPOINT_SLOTS};
+use multitasking::wait_queue::{Wait, WaitQueue};
+use multitasking::{get_current_process, ProcessID, ThreadID, CURRENT_THREAD};
+
+/// The reasons for a traced thread to stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    /// The thread caused a fault that would kill the process.
+    Fault,
+    /// The thread hit a breakpoint.
+    Breakpoint,
+    /// The thread executed a single instruction after being resumed with
+    /// `ResumeMode::SingleStep`.
+    SingleStep,
+    /// The thread is about to perform a syscall.
+    Syscall,
+    /// The thread hit the watchpoint in the given slot.
+    Watchpoint(usize)
+}
+
+impl StopReason {
+    /// Converts the stop reason to the value passed to userspace.
+    pub fn to_raw(self) -> u64 {
+        match self {
+            StopReason::Fault => 0,
+            StopReason::Breakpoint => 1,
+            StopReason::SingleStep => 2,
+            StopReason::Syscall => 3,
+            StopReason::Watchpoint(_) => 4
+        }
+    }
+
+    /// Returns the additional information passed to userspace.
+    ///
+    /// For watchpoint stops this is the slot of the watchpoint.
+    pub fn detail(self) -> u64 {
+        match self {
+            StopReason::Watchpoint(slot) => slot as u64,
+            _ => 0
+        }
+    }
+}
+
+/// The ways a stopped thread can be resumed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResumeMode {
+    /// The thread continues running normally.
+    Continue,
+    /// The thread stops again after executing a single instruction.
+    ///
+    /// Threads stopped at a syscall continue normally instead.
+    SingleStep
+}
+
+impl ResumeMode {
+    /// Converts the raw syscall argument to a resume mode.
+    pub fn from_raw(mode: usize) -> Option<ResumeMode> {
+        match mode {
+            0 => Some(ResumeMode::Continue),
+            1 => Some(ResumeMode::SingleStep),
+            _ => None
+        }
+    }
+}
+
+/// The registers of a stopped thread that are visible to the tracer.
+///
+/// Only the registers relevant to the stop reason are valid. For syscall
+/// stops these are the syscall number and arguments, for all other stops the
+/// remaining registers. Changes to the frame pointer are ignored.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Registers {
+    /// The address of the next instruction to execute.
+    pub instruction_pointer: usize,
+    /// The stack pointer of the thread.
+    pub stack_pointer: usize,
+    /// The frame pointer of the thread.
+    pub frame_pointer: usize,
+    /// The flags register of the thread.
+    pub flags: usize,
+    /// The number of the syscall that is about to be performed.
+    pub syscall_number: usize,
+    /// The arguments of the syscall that is about to be performed.
+    pub syscall_arguments: [usize; 6]
+}
+
+/// Describes a stopped thread of a traced process.
+#[derive(Debug, Clone, Copy)]
+pub struct Stop {
+    /// The thread that stopped.
+    pub thread: ThreadID,
+    /// The reason the thread stopped.
+    pub reason: StopReason,
+    /// The registers of the stopped thread.
+    pub registers: Registers,
+    /// Set by the tracer to resume the thread.
+    pub resume: Option<ResumeMode>
+}
+
+/// The tracing state of a traced process.
+pub struct TraceState {
+    /// The process that traces this process.
+    pub tracer: ProcessID,
+    /// Whether the threads stop before every syscall.
+    pub report_syscalls: bool,
+    /// The watchpoints set by the tracer.
+    ///
+    /// Changes take effect when the threads are scheduled the next time.
+    pub watchpoints: WatchpointSet,
+    /// The currently stopped thread, if any.
+    ///
+    /// Only one thread can be stopped at a time, other threads that want to
+    /// stop wait until it was resumed.
+    pub stop: Option<Stop>,
+    /// Woken when a thread stops or is resumed and when the tracing ends.
+    pub changes: WaitQueue
+}
+
+impl TraceState {
+    /// Creates the tracing state for a process traced by `tracer`.
+    pub fn new(tracer: ProcessID, report_syscalls: bool) -> TraceState {
+        TraceState {
+            tracer,
+            report_syscalls,
+            watchpoints: [None; WATCHPOINT_SLOTS],
+            stop: None,
+            changes: WaitQueue::new()
+        }
+    }
+
+    /// Resumes the stopped thread with the given mode.
+    ///
+    /// Returns false if no thread is stopped or it was resumed already.
+    pub fn resume(&mut self, mode: ResumeMode) -> bool {
+        match self.stop {
+            Some(ref mut stop) if stop.resume.is_none() => stop.resume = Some(mode),
+            _ => return false
+        }
+
+        self.changes.wake_all();
+
+        true
+    }
+}
+
+impl Drop for TraceState {
+    fn drop(&mut self) {
+        // Both the tracer and stopped threads notice that the tracing ended.
+        self.changes.wake_all();
+    }
+}
+
+/// Returns true if the current process is traced with syscall reporting.
+pub fn reports_syscalls() -> bool {
+    get_current_process()
+        .trace
+        .as_ref()
+        .map_or(false, |trace| trace.report_syscalls)
+}
+
+/// Stops the current thread and waits until the tracer resumes it.
+///
+/// The registers are updated with the changes made by the tracer.
+/// Returns `None` if the current process isn't traced. If the tracer
+/// detaches while the thread is stopped, the thread continues normally.
+pub fn stop(reason: StopReason, registers: &mut Registers) -> Option<ResumeMode> {
+    let thread = CURRENT_THREAD.lock().id;
+
+    // Wait until no other thread of the process is stopped.
+    loop {
+        let wait = Wait::new();
+
+        {
+            let mut pcb = get_current_process();
+
+            match pcb.trace {
+                Some(ref mut trace) => {
+                    if trace.stop.is_none() {
+                        trace.stop = Some(Stop {
+                            thread,
+                            reason,
+                            registers: *registers,
+                            resume: None
+                        });
+                        trace.changes.wake_all();
+                        break;
+                    } else {
+                        wait.on(&trace.changes);
+                    }
+                },
+                None => return None
+            }
+        }
+
+        wait.sleep(None);
+    }
+
+    // Wait until the tracer resumes the thread.
+    loop {
+        let wait = Wait::new();
+
+        {
+            let mut pcb = get_current_process();
+
+            match pcb.trace {
+                Some(ref mut trace) => {
+                    let resume = trace.stop.as_ref().and_then(|stop| stop.resume);
+
+                    if let Some(mode) = resume {
+                        let stop = trace.stop.take().expect("The resumed stop vanished.");
+
+                        *registers = stop.registers;
+
+                        // Let other threads that want to stop do so.
+                        trace.changes.wake_all();
+
+                        return Some(mode);
+                    }
+
+                    wait.on(&trace.changes);
+                },
+                None => return Some(ResumeMode::Continue)
+            }
+        }
+
+        wait.sleep(None);
+    }
+}
@@ -0,0 +1,295 @@
+//! This module implements wait queues.
+//!
+//! A thread that waits for an event starts a `Wait`, adds itself to the wait
+//! queues of the objects the event can happen on and only then checks
+//! whether it happened already. If it didn't, the thread sleeps until
+//! whoever causes the event wakes one of the queues, which puts the thread
+//! back on a ready list right away.
+//!
+//! A wake-up that arrives after the thread was added to a queue but before
+//! it went to sleep isn't lost, the scheduler keeps the thread from sleeping
+//! then. Every wait has its own ticket, so a queue that still holds a thread
+//! from an earlier wait doesn't wake it during a later one.
+//!
+//! Besides threads, objects like event queues can wait on a queue to be
+//! notified of the events of the files they watch. Queues of events that
+//! happen at a certain time, like the expiration of a timer, are woken by
+//! timeouts.
+
+use super::scheduler;
+use super::{ProcessID, ThreadID, ThreadState, CURRENT_THREAD};
+use alloc::arc::{Arc, Weak};
+use alloc::btree_map::BTreeMap;
+use alloc::Vec;
+use arch::schedule;
+use core::mem::replace;
+use sync::time::Timestamp;
+use sync::Mutex;
+
+lazy_static! {
+    /// The wait queues that are woken at a certain time.
+    static ref TIMEOUTS: Mutex<BTreeMap<Timestamp, Vec<Weak<WaitQueue>>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// An object that is notified when a wait queue it waits on is woken.
+pub trait Notify: Send + Sync {
+    /// Handles the wake-up of a queue the object was added to with `key`.
+    fn notify(&self, key: usize);
+}
+
+/// Wakes a thread during one of its waits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Waker {
+    /// The process of the thread.
+    pid: ProcessID,
+    /// The waiting thread.
+    thread: ThreadID,
+    /// The ticket of the wait.
+    ticket: usize
+}
+
+impl Waker {
+    /// Creates a waker for the wait of the thread with the ticket.
+    pub fn new(pid: ProcessID, thread: ThreadID, ticket: usize) -> Waker {
+        Waker {
+            pid,
+            thread,
+            ticket
+        }
+    }
+
+    /// Wakes the thread, if it is still in the wait.
+    pub fn wake(&self) {
+        scheduler::wake(self.pid, self.thread, self.ticket);
+    }
+}
+
+/// Something that waits on a wait queue.
+enum Waiter {
+    /// A thread during one of its waits.
+    Thread(Waker),
+    /// An object that is notified with the key.
+    Object(Weak<Notify>, usize)
+}
+
+impl Waiter {
+    /// Returns true if the waiter is an earlier entry for the same waiter as
+    /// `other` or an object that no longer exists.
+    fn is_replaced_by(&self, other: &Waiter) -> bool {
+        match (self, other) {
+            (&Waiter::Thread(ref waker), &Waiter::Thread(ref other)) => {
+                waker.pid == other.pid && waker.thread == other.thread
+            },
+            (&Waiter::Object(ref object, key), &Waiter::Object(ref other, other_key)) => {
+                match (object.upgrade(), other.upgrade()) {
+                    (Some(object), Some(other)) => key == other_key && Arc::ptr_eq(&object, &other),
+                    (None, _) => true,
+                    (Some(_), None) => false
+                }
+            },
+            (&Waiter::Object(ref object, _), _) => object.upgrade().is_none(),
+            _ => false
+        }
+    }
+
+    /// Wakes the thread or notifies the object.
+    fn wake(self) {
+        match self {
+            Waiter::Thread(waker) => waker.wake(),
+            Waiter::Object(object, key) => {
+                if let Some(object) = object.upgrade() {
+                    object.notify(key);
+                }
+            },
+        }
+    }
+}
+
+/// The threads and objects that wait for an event.
+pub struct WaitQueue {
+    /// The waiters in the order they were added.
+    waiters: Mutex<Vec<Waiter>>
+}
+
+impl WaitQueue {
+    /// Creates a queue without waiters.
+    pub fn new() -> WaitQueue {
+        WaitQueue {
+            waiters: Mutex::new(Vec::new())
+        }
+    }
+
+    /// Adds the waiter, replacing earlier entries for it.
+    fn add(&self, waiter: Waiter) {
+        let mut waiters = self.waiters.lock();
+
+        waiters.retain(|existing| !existing.is_replaced_by(&waiter));
+        waiters.push(waiter);
+    }
+
+    /// Adds the object, which is notified with `key` when the queue is woken
+    /// next.
+    ///
+    /// Like threads, objects are removed when they are notified, so they
+    /// have to add themselves again for the next event.
+    pub fn add_object(&self, object: Weak<Notify>, key: usize) {
+        self.add(Waiter::Object(object, key));
+    }
+
+    /// Wakes all waiting threads and notifies all waiting objects.
+    ///
+    /// All waiters are removed from the queue.
+    pub fn wake_all(&self) {
+        // The waiters are woken once the queue is unlocked again, so that
+        // notified objects can add themselves again.
+        let waiters = replace(&mut *self.waiters.lock(), Vec::new());
+
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+}
+
+/// A wait of the current thread for events.
+///
+/// The wait ends when it is dropped.
+pub struct Wait {
+    /// Wakes the thread during this wait.
+    waker: Waker
+}
+
+impl Wait {
+    /// Starts a wait of the current thread.
+    pub fn new() -> Wait {
+        let (pid, thread) = {
+            let current_thread = CURRENT_THREAD.lock();
+
+            (current_thread.pid, current_thread.id)
+        };
+
+        Wait {
+            waker: Waker::new(pid, thread, scheduler::start_wait(pid, thread))
+        }
+    }
+
+    /// Adds the thread to the queue, so that waking the queue ends the sleep
+    /// of this wait.
+    pub fn on(&self, queue: &WaitQueue) {
+        queue.add(Waiter::Thread(self.waker));
+    }
+
+    /// Returns what wakes the thread during this wait.
+    pub fn waker(&self) -> Waker {
+        self.waker
+    }
+
+    /// Returns true if the thread was woken during this wait.
+    pub fn is_woken(&self) -> bool {
+        scheduler::is_woken(self.waker.pid, self.waker.thread, self.waker.ticket)
+    }
+
+    /// Sleeps until the thread is woken or the deadline passes.
+    ///
+    /// This returns right away if the thread was woken already.
+    pub fn sleep(&self, deadline: Option<Timestamp>) {
+        if self.is_woken() {
+            return;
+        }
+
+        let wake_time = deadline.unwrap_or_else(Timestamp::max_value);
+
+        CURRENT_THREAD.lock().state = ThreadState::Sleeping(wake_time);
+        schedule();
+    }
+}
+
+impl Drop for Wait {
+    fn drop(&mut self) {
+        scheduler::end_wait(self.waker.pid, self.waker.thread, self.waker.ticket);
+    }
+}
+
+/// Wakes the queue at the given time, unless it no longer exists then.
+pub fn wake_at(time: Timestamp, queue: Weak<WaitQueue>) {
+    TIMEOUTS
+        .lock()
+        .entry(time)
+        .or_insert_with(Vec::new)
+        .push(queue);
+}
+
+/// Wakes the queues whose timeouts expired.
+pub fn wake_expired() {
+    let now = Timestamp::get_current();
+    let mut expired = Vec::new();
+
+    {
+        let mut timeouts = TIMEOUTS.lock();
+
+        loop {
+            let time = match timeouts.keys().next() {
+                Some(&time) if time <= now => time,
+                _ => break
+            };
+
+            expired.extend(timeouts.remove(&time).unwrap());
+        }
+    }
+
+    for queue in expired {
+        if let Some(queue) = queue.upgrade() {
+            queue.wake_all();
+        }
+    }
+}
+
+/// Returns the time of the next timeout, if there is one.
+pub fn next_timeout() -> Option<Timestamp> {
+    TIMEOUTS.lock().keys().next().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An object that ignores notifications.
+    struct Ignore;
+
+    impl Notify for Ignore {
+        fn notify(&self, _key: usize) {}
+    }
+
+    /// Returns a waiter for the thread during the wait with the ticket.
+    fn thread(pid: usize, thread: usize, ticket: usize) -> Waiter {
+        Waiter::Thread(Waker::new(pid.into(), thread.into(), ticket))
+    }
+
+    /// Tests that a thread is only in a queue once, for its latest wait.
+    #[test]
+    fn threads_replace_earlier_waits() {
+        assert!(thread(1, 0, 1).is_replaced_by(&thread(1, 0, 2)));
+        assert!(!thread(1, 0, 1).is_replaced_by(&thread(1, 1, 2)));
+        assert!(!thread(1, 0, 1).is_replaced_by(&thread(2, 0, 2)));
+    }
+
+    /// Tests that objects are in a queue once for each key and that objects
+    /// that no longer exist are removed.
+    #[test]
+    fn objects_replace_earlier_entries() {
+        let object: Arc<Notify> = Arc::new(Ignore);
+        let other: Arc<Notify> = Arc::new(Ignore);
+        let entry = |object: &Arc<Notify>, key| Waiter::Object(Arc::downgrade(object), key);
+
+        assert!(entry(&object, 1).is_replaced_by(&entry(&object, 1)));
+        assert!(!entry(&object, 1).is_replaced_by(&entry(&object, 2)));
+        assert!(!entry(&object, 1).is_replaced_by(&entry(&other, 1)));
+        assert!(!entry(&object, 1).is_replaced_by(&thread(1, 0, 1)));
+
+        let dead = entry(&other, 1);
+        drop(other);
+
+        assert!(dead.is_replaced_by(&thread(1, 0, 1)));
+        assert!(dead.is_replaced_by(&entry(&object, 1)));
+    }
+}
@@ -0,0 +1,684 @@
+//! Makes a directory of the host available below `/host/`.
+//!
+//! The host directory is accessed with the 9P2000.L protocol over a transport
+//! that a driver mounts once it found its device. This lets new versions of
+//! the user programs run without rebuilding the initramfs.
+//!
+//! The share is read-only. Only a single request is pending at a time and
+//! every request is answered before the next one is sent.
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use alloc::Vec;
+use core::cmp::min;
+use file_handle::{offset_by, FileError, FileHandle, PollEvents, Result, SeekFrom, POLL_IN};
+use sync::Mutex;
+use vfs::{self, FileSystem};
+
+/// The directory the host directory is mounted at.
+pub const HOST_DIRECTORY: &str = "/host/";
+
+/// The maximum size of a message in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 8192;
+
+/// The protocol version that is spoken.
+const VERSION: &str = "9P2000.L";
+
+/// The tag of the version request.
+const NO_TAG: u16 = 0xffff;
+
+/// The tag of all other requests.
+const TAG: u16 = 1;
+
+/// The file ID that stands for no file.
+const NO_FID: u32 = 0xffff_ffff;
+
+/// The file ID of the root of the share.
+const ROOT_FID: u32 = 0;
+
+/// The maximum number of names walked with one request.
+const MAX_WALK_NAMES: usize = 16;
+
+/// The size of the header of every message.
+const HEADER_SIZE: usize = 7;
+
+/// The size of the header of a read response.
+const READ_HEADER_SIZE: usize = HEADER_SIZE + 4;
+
+/// The error number that tells that a file doesn't exist.
+const ENOENT: u32 = 2;
+
+/// The request mask of the attributes that are needed.
+const GETATTR_BASIC: u64 = 0x7ff;
+
+/// The mask of the file type in the mode.
+const MODE_TYPE_MASK: u32 = 0o170_000;
+
+/// The file type of regular files.
+const MODE_REGULAR: u32 = 0o100_000;
+
+/// The flags to open files for reading.
+const OPEN_READ_ONLY: u32 = 0;
+
+/// The types of the messages that are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum MessageType {
+    /// The error response.
+    Rlerror = 7,
+    /// Opens a file.
+    Tlopen = 12,
+    /// Gets the attributes of a file.
+    Tgetattr = 24,
+    /// Negotiates the protocol version and the message size.
+    Tversion = 100,
+    /// Attaches to the root of the share.
+    Tattach = 104,
+    /// Walks to a file.
+    Twalk = 110,
+    /// Reads from a file.
+    Tread = 116,
+    /// Forgets a file ID.
+    Tclunk = 120
+}
+
+/// Exchanges messages with the server.
+pub trait Transport: Send {
+    /// Sends the request and receives the response.
+    ///
+    /// Returns the number of bytes written to the response buffer.
+    fn transact(&mut self, request: &[u8], response: &mut [u8]) -> Result<usize>;
+}
+
+/// Reads the fields of a response.
+struct Reader<'a> {
+    /// The remaining fields.
+    data: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+    /// Returns the next bytes.
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        if length > self.data.len() {
+            return Err(FileError::InvalidFilesystem);
+        }
+
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Returns the next little endian integer of the given size.
+    fn integer(&mut self, size: usize) -> Result<u64> {
+        Ok(self
+            .bytes(size)?
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | u64::from(byte)))
+    }
+
+    /// Returns the next 16 bit integer.
+    fn u16(&mut self) -> Result<u16> {
+        self.integer(2).map(|value| value as u16)
+    }
+
+    /// Returns the next 32 bit integer.
+    fn u32(&mut self) -> Result<u32> {
+        self.integer(4).map(|value| value as u32)
+    }
+
+    /// Returns the next 64 bit integer.
+    fn u64(&mut self) -> Result<u64> {
+        self.integer(8)
+    }
+}
+
+/// Appends the value as a little endian integer of the given size.
+fn put_integer(message: &mut Vec<u8>, value: u64, size: usize) {
+    for i in 0..size {
+        message.push((value >> (8 * i)) as u8);
+    }
+}
+
+/// Appends the string with its length.
+fn put_string(message: &mut Vec<u8>, string: &str) {
+    put_integer(message, string.len() as u64, 2);
+    message.extend_from_slice(string.as_bytes());
+}
+
+/// A connection to the server.
+struct Client {
+    /// The transport to the server.
+    transport: Box<Transport>,
+    /// The negotiated maximum message size.
+    message_size: usize,
+    /// The request that is built.
+    request: Vec<u8>,
+    /// The buffer for the response.
+    response: Vec<u8>,
+    /// The next file ID that was never used.
+    next_fid: u32,
+    /// The file IDs that can be reused.
+    free_fids: Vec<u32>
+}
+
+/// The connection to the host, once it is mounted.
+static CLIENT: Mutex<Option<Client>> = Mutex::new(None);
+
+impl Client {
+    /// Connects to the server and attaches to the root of the share.
+    fn new(transport: Box<Transport>) -> Result<Client> {
+        let mut client = Client {
+            transport,
+            message_size: MAX_MESSAGE_SIZE,
+            request: Vec::with_capacity(MAX_MESSAGE_SIZE),
+            response: Vec::new(),
+            next_fid: ROOT_FID + 1,
+            free_fids: Vec::new()
+        };
+        client.response.resize(MAX_MESSAGE_SIZE, 0);
+
+        client.start(MessageType::Tversion, NO_TAG);
+        put_integer(&mut client.request, MAX_MESSAGE_SIZE as u64, 4);
+        put_string(&mut client.request, VERSION);
+        let message_size = {
+            let mut response = client.transact()?;
+            let message_size = response.u32()? as usize;
+            let version_length = response.u16()? as usize;
+            let version = response.bytes(version_length)?;
+
+            if version != VERSION.as_bytes() || message_size <= READ_HEADER_SIZE {
+                return Err(FileError::InvalidFilesystem);
+            }
+
+            message_size
+        };
+        client.message_size = min(message_size, MAX_MESSAGE_SIZE);
+
+        client.start(MessageType::Tattach, TAG);
+        put_integer(&mut client.request, u64::from(ROOT_FID), 4);
+        put_integer(&mut client.request, u64::from(NO_FID), 4);
+        put_string(&mut client.request, "root");
+        put_string(&mut client.request, "");
+        put_integer(&mut client.request, 0, 4);
+        client.transact()?;
+
+        Ok(client)
+    }
+
+    /// Starts a new request of the given type.
+    fn start(&mut self, message_type: MessageType, tag: u16) {
+        self.request.clear();
+
+        // The size is filled in once the request is complete.
+        put_integer(&mut self.request, 0, 4);
+        self.request.push(message_type as u8);
+        put_integer(&mut self.request, u64::from(tag), 2);
+    }
+
+    /// Sends the request and returns the fields of the response.
+    fn transact(&mut self) -> Result<Reader> {
+        let size = self.request.len();
+        for (i, byte) in self.request[..4].iter_mut().enumerate() {
+            *byte = (size >> (8 * i)) as u8;
+        }
+
+        let request_type = self.request[4];
+        let message_size = self.message_size;
+        let length = self
+            .transport
+            .transact(&self.request, &mut self.response[..message_size])?;
+
+        let mut response = Reader {
+            data: &self.response[..min(length, message_size)]
+        };
+        let size = response.u32()? as usize;
+        let response_type = response.bytes(1)?[0];
+        response.u16()?;
+
+        if size < HEADER_SIZE || size > length {
+            return Err(FileError::InvalidFilesystem);
+        }
+        response.data = &response.data[..size - HEADER_SIZE];
+
+        if response_type == MessageType::Rlerror as u8 {
+            match response.u32()? {
+                ENOENT => Err(FileError::FileNotFound),
+                _ => Err(FileError::InvalidFilesystem)
+            }
+        } else if response_type != request_type + 1 {
+            Err(FileError::InvalidFilesystem)
+        } else {
+            Ok(response)
+        }
+    }
+
+    /// Returns an unused file ID.
+    fn allocate_fid(&mut self) -> u32 {
+        match self.free_fids.pop() {
+            Some(fid) => fid,
+            None => {
+                self.next_fid += 1;
+                self.next_fid - 1
+            }
+        }
+    }
+
+    /// Tells the server to forget the file ID and makes it reusable.
+    fn clunk(&mut self, fid: u32) {
+        self.start(MessageType::Tclunk, TAG);
+        put_integer(&mut self.request, u64::from(fid), 4);
+
+        // The server forgets the file ID even if it reports an error.
+        if self.transact().is_err() {
+            warn!("Closing a file on the host failed.");
+        }
+
+        self.free_fids.push(fid);
+    }
+
+    /// Walks from the root to the file with the given path into a new file
+    /// ID.
+    fn walk(&mut self, path: &str) -> Result<u32> {
+        let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+        let fid = self.allocate_fid();
+        let mut source_fid = ROOT_FID;
+        let mut walked_names = 0;
+
+        // The first walk clones the root, even if there are no names.
+        loop {
+            let chunk = &names[walked_names..min(names.len(), walked_names + MAX_WALK_NAMES)];
+
+            self.start(MessageType::Twalk, TAG);
+            put_integer(&mut self.request, u64::from(source_fid), 4);
+            put_integer(&mut self.request, u64::from(fid), 4);
+            put_integer(&mut self.request, chunk.len() as u64, 2);
+            for name in chunk {
+                put_string(&mut self.request, name);
+            }
+
+            // The new file ID is only changed if all names were walked.
+            match self.transact().and_then(|mut response| response.u16()) {
+                Ok(count) if count as usize == chunk.len() => (),
+                walked => {
+                    if source_fid == fid {
+                        self.clunk(fid);
+                    } else {
+                        self.free_fids.push(fid);
+                    }
+
+                    return Err(walked.err().unwrap_or(FileError::FileNotFound));
+                }
+            }
+
+            source_fid = fid;
+            walked_names += chunk.len();
+
+            if walked_names == names.len() {
+                return Ok(fid);
+            }
+        }
+    }
+
+    /// Opens the regular file with the given path for reading.
+    ///
+    /// Returns the file ID, the size of the file and the maximum number of
+    /// bytes per read.
+    fn open(&mut self, path: &str) -> Result<(u32, u64, usize)> {
+        let fid = self.walk(path)?;
+
+        match self.open_fid(fid) {
+            Ok((size, io_unit)) => Ok((fid, size, io_unit)),
+            Err(error) => {
+                self.clunk(fid);
+                Err(error)
+            }
+        }
+    }
+
+    /// Opens the file with the given file ID for reading.
+    ///
+    /// Returns the size of the file and the maximum number of bytes per read.
+    fn open_fid(&mut self, fid: u32) -> Result<(u64, usize)> {
+        self.start(MessageType::Tgetattr, TAG);
+        put_integer(&mut self.request, u64::from(fid), 4);
+        put_integer(&mut self.request, GETATTR_BASIC, 8);
+        let (mode, size) = {
+            let mut response = self.transact()?;
+
+            // The valid mask, the qid, the IDs of the owner and the number
+            // of links and the device are skipped.
+            response.bytes(8 + 13)?;
+            let mode = response.u32()?;
+            response.bytes(4 + 4 + 8 + 8)?;
+            (mode, response.u64()?)
+        };
+
+        if mode & MODE_TYPE_MASK != MODE_REGULAR {
+            return Err(FileError::NotSupported);
+        }
+
+        self.start(MessageType::Tlopen, TAG);
+        put_integer(&mut self.request, u64::from(fid), 4);
+        put_integer(&mut self.request, u64::from(OPEN_READ_ONLY), 4);
+        let io_unit = {
+            let mut response = self.transact()?;
+            response.bytes(13)?;
+            response.u32()? as usize
+        };
+
+        let max_read = self.message_size - READ_HEADER_SIZE;
+        let io_unit = if io_unit == 0 {
+            max_read
+        } else {
+            min(io_unit, max_read)
+        };
+
+        Ok((size, io_unit))
+    }
+
+    /// Reads from the file at the given offset.
+    ///
+    /// Returns the number of bytes read, which is zero at the end of the
+    /// file.
+    fn read(&mut self, fid: u32, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        self.start(MessageType::Tread, TAG);
+        put_integer(&mut self.request, u64::from(fid), 4);
+        put_integer(&mut self.request, offset, 8);
+        put_integer(&mut self.request, buffer.len() as u64, 4);
+
+        let mut response = self.transact()?;
+        let count = response.u32()? as usize;
+
+        if count > buffer.len() {
+            return Err(FileError::InvalidFilesystem);
+        }
+
+        buffer[..count].copy_from_slice(response.bytes(count)?);
+
+        Ok(count)
+    }
+}
+
+/// A file on the host.
+struct HostFile {
+    /// The file ID of the file.
+    fid: u32,
+    /// The size of the file when it was opened.
+    size: u64,
+    /// The maximum number of bytes per read.
+    io_unit: usize,
+    /// The current seek position.
+    offset: u64
+}
+
+impl FileHandle for HostFile {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64> {
+        let offset = match position {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(distance) => offset_by(self.offset, distance)?,
+            SeekFrom::End(distance) => offset_by(self.size, distance)?
+        };
+
+        if offset > self.size {
+            Err(FileError::SeekPastEnd)
+        } else {
+            self.offset = offset;
+            Ok(offset)
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<()> {
+        if self.offset.saturating_add(buffer.len() as u64) > self.size {
+            return Err(FileError::SeekPastEnd);
+        }
+
+        let mut client = CLIENT.lock();
+        let client = client.as_mut().ok_or(FileError::DeviceRemoved)?;
+        let mut done = 0;
+
+        while done < buffer.len() {
+            let end = min(buffer.len(), done + self.io_unit);
+            let count = client.read(self.fid, self.offset, &mut buffer[done..end])?;
+
+            // The file shrank since it was opened.
+            if count == 0 {
+                return Err(FileError::SeekPastEnd);
+            }
+
+            done += count;
+            self.offset += count as u64;
+        }
+
+        Ok(())
+    }
+
+    fn poll(&mut self) -> PollEvents {
+        POLL_IN
+    }
+}
+
+impl Drop for HostFile {
+    fn drop(&mut self) {
+        if let Some(ref mut client) = *CLIENT.lock() {
+            client.clunk(self.fid);
+        }
+    }
+}
+
+/// Mounts the host directory that is reached through the transport.
+///
+/// The tag names the host directory and is only used for messages.
+pub fn mount(tag: &str, transport: Box<Transport>) {
+    let mut mounted = CLIENT.lock();
+
+    if mounted.is_some() {
+        warn!(
+            "Ignoring the host directory {}, another one is mounted.",
+            tag
+        );
+        return;
+    }
+
+    match Client::new(transport) {
+        Ok(client) => {
+            info!("Mounted the host directory {} at {}.", tag, HOST_DIRECTORY);
+            *mounted = Some(client);
+            vfs::mount(HOST_DIRECTORY, Arc::new(HostFileSystem));
+        },
+        Err(error) => warn!("Mounting the host directory {} failed: {:?}", tag, error)
+    }
+}
+
+/// The filesystem that contains the files of the mounted host directory.
+struct HostFileSystem;
+
+impl FileSystem for HostFileSystem {
+    fn open(&self, path: &str) -> Result<Box<FileHandle>> {
+        let (fid, size, io_unit) = CLIENT
+            .lock()
+            .as_mut()
+            .ok_or(FileError::FileNotFound)?
+            .open(path)?;
+
+        Ok(Box::new(HostFile {
+            fid,
+            size,
+            io_unit,
+            offset: 0
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The names on the path to the only file of the test server.
+    const PATH: [&str; 2] = ["dir", "file"];
+
+    /// The content of the only file of the test server.
+    const CONTENT: &[u8] = b"Hello from the host!";
+
+    /// A server that shares a single file at `PATH`.
+    struct TestServer {
+        /// The number of walked names per file ID.
+        fids: Vec<(u32, usize)>
+    }
+
+    impl TestServer {
+        /// Returns the response fields for the request.
+        fn respond(&mut self, request: &mut Reader) -> Result<(MessageType, Vec<u8>)> {
+            let mut fields = Vec::new();
+            let request_type = request.bytes(1)?[0];
+            request.u16()?;
+
+            if request_type == MessageType::Tversion as u8 {
+                put_integer(&mut fields, request.u32()?.into(), 4);
+                put_string(&mut fields, VERSION);
+                return Ok((MessageType::Tversion, fields));
+            }
+
+            let fid = request.u32()?;
+            let depth = self
+                .fids
+                .iter()
+                .find(|&&(existing, _)| existing == fid)
+                .map(|&(_, depth)| depth);
+
+            let message_type = match request_type {
+                t if t == MessageType::Tattach as u8 => {
+                    self.fids.push((fid, 0));
+                    MessageType::Tattach
+                },
+                t if t == MessageType::Twalk as u8 => {
+                    let new_fid = request.u32()?;
+                    let mut depth = depth.ok_or(FileError::InvalidFilesystem)?;
+                    let count = request.u16()?;
+                    let mut walked = 0;
+
+                    for _ in 0..count {
+                        let length = request.u16()? as usize;
+                        let name = request.bytes(length)?;
+
+                        if depth == PATH.len() || name != PATH[depth].as_bytes() {
+                            break;
+                        }
+                        depth += 1;
+                        walked += 1;
+                    }
+
+                    if walked == count {
+                        self.fids.retain(|&(existing, _)| existing != new_fid);
+                        self.fids.push((new_fid, depth));
+                    }
+                    put_integer(&mut fields, u64::from(walked), 2);
+                    MessageType::Twalk
+                },
+                t if t == MessageType::Tgetattr as u8 => {
+                    let mode = if depth == Some(PATH.len()) {
+                        MODE_REGULAR
+                    } else {
+                        0o040_000
+                    };
+                    fields.resize(8 + 13, 0);
+                    put_integer(&mut fields, u64::from(mode), 4);
+                    fields.resize(fields.len() + 4 + 4 + 8 + 8, 0);
+                    put_integer(&mut fields, CONTENT.len() as u64, 8);
+                    MessageType::Tgetattr
+                },
+                t if t == MessageType::Tlopen as u8 => {
+                    fields.resize(13, 0);
+                    put_integer(&mut fields, 4, 4);
+                    MessageType::Tlopen
+                },
+                t if t == MessageType::Tread as u8 => {
+                    let offset = request.u64()? as usize;
+                    let count = request.u32()? as usize;
+                    let data = &CONTENT[min(offset, CONTENT.len())..];
+                    let data = &data[..min(count, data.len())];
+                    put_integer(&mut fields, data.len() as u64, 4);
+                    fields.extend_from_slice(data);
+                    MessageType::Tread
+                },
+                t if t == MessageType::Tclunk as u8 => {
+                    self.fids.retain(|&(existing, _)| existing != fid);
+                    MessageType::Tclunk
+                },
+                _ => return Err(FileError::NotSupported)
+            };
+
+            Ok((message_type, fields))
+        }
+    }
+
+    impl Transport for TestServer {
+        fn transact(&mut self, request: &[u8], response: &mut [u8]) -> Result<usize> {
+            let mut reader = Reader {
+                data: &request[4..]
+            };
+            let tag = u64::from(
+                Reader {
+                    data: &request[5..7]
+                }
+                .u16()?
+            );
+            let mut message = Vec::new();
+
+            match self.respond(&mut reader) {
+                Ok((message_type, fields)) => {
+                    put_integer(&mut message, (HEADER_SIZE + fields.len()) as u64, 4);
+                    message.push(message_type as u8 + 1);
+                    put_integer(&mut message, tag, 2);
+                    message.extend_from_slice(&fields);
+                },
+                Err(_) => {
+                    put_integer(&mut message, (HEADER_SIZE + 4) as u64, 4);
+                    message.push(MessageType::Rlerror as u8);
+                    put_integer(&mut message, tag, 2);
+                    put_integer(&mut message, u64::from(ENOENT), 4);
+                }
+            }
+
+            response[..message.len()].copy_from_slice(&message);
+            Ok(message.len())
+        }
+    }
+
+    /// Tests that files are found and read in chunks of the I/O unit.
+    #[test]
+    fn test_open_read() {
+        let mut client = Client::new(Box::new(TestServer { fids: Vec::new() })).unwrap();
+
+        let (fid, size, io_unit) = client.open("dir/file").unwrap();
+        assert_eq!(size, CONTENT.len() as u64);
+        assert_eq!(io_unit, 4);
+
+        let mut buffer = [0; 8];
+        assert_eq!(client.read(fid, 6, &mut buffer[..io_unit]).unwrap(), 4);
+        assert_eq!(&buffer[..4], &CONTENT[6..10]);
+        assert_eq!(client.read(fid, size, &mut buffer).unwrap(), 0);
+
+        client.clunk(fid);
+        assert_eq!(client.allocate_fid(), fid);
+    }
+
+    /// Tests that missing files and directories can't be opened.
+    #[test]
+    fn test_open_errors() {
+        let mut client = Client::new(Box::new(TestServer { fids: Vec::new() })).unwrap();
+
+        match client.open("dir/missing") {
+            Err(FileError::FileNotFound) => (),
+            _ => panic!("A missing file was opened.")
+        }
+        match client.open("dir") {
+            Err(FileError::NotSupported) => (),
+            _ => panic!("A directory was opened.")
+        }
+
+        // The file ID of the first attempt is reused by the second one.
+        assert_eq!(client.next_fid, ROOT_FID + 2);
+        assert_eq!(client.allocate_fid(), ROOT_FID + 1);
+    }
+}
@@ -0,0 +1,57 @@
+//! Makes kernel state available as text files below `/proc/`.
+//!
+//! Subsystems register a file by name together with a function that
+//! generates its content. The content is generated when the file is opened,
+//! so a file shows the state at that time until it is opened again.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::Vec;
+use file_handle::{FileError, FileHandle, MemoryFile, Result};
+use sync::RwLock;
+use vfs::FileSystem;
+
+/// The directory that contains the files.
+pub const PROC_DIRECTORY: &str = "/proc/";
+
+/// Generates the content of a file.
+pub type Generator = fn() -> String;
+
+lazy_static! {
+    /// All registered files with the functions that generate them.
+    static ref FILES: RwLock<Vec<(&'static str, Generator)>> = RwLock::new(Vec::new());
+}
+
+/// Registers a file with the given name.
+///
+/// # Panics
+/// Panics if a file with that name was already registered.
+pub fn register(name: &'static str, generate: Generator) {
+    let mut files = FILES.write();
+
+    assert!(
+        files.iter().all(|&(file_name, _)| file_name != name),
+        "The file {} was registered twice.",
+        name
+    );
+
+    files.push((name, generate));
+}
+
+/// The filesystem that contains the registered files.
+pub struct ProcFileSystem;
+
+impl FileSystem for ProcFileSystem {
+    fn open(&self, name: &str) -> Result<Box<FileHandle>> {
+        let generate = FILES
+            .read()
+            .iter()
+            .find(|&&(file_name, _)| file_name == name)
+            .map(|&(_, generate)| generate)
+            .ok_or(FileError::FileNotFound)?;
+
+        // The lock is released before generating, so that the generator can
+        // take other locks.
+        Ok(Box::new(MemoryFile::new(generate().into_bytes())))
+    }
+}
@@ -0,0 +1,95 @@
+//! Provides the entropy pool of the kernel.
+//!
+//! Entropy sources add their data together with an estimate of the entropy
+//! it contains. The data is hashed into the pool and once enough entropy was
+//! credited, the random number generator is reseeded from the pool.
+//!
+//! Until the first reseed the generator is only seeded from the boot time, so
+//! its output is predictable. `is_seeded` tells whether that happened.
+
+use core::mem;
+use core::time::Duration;
+use sync::time::Timestamp;
+use sync::Mutex;
+use veos_crypto::rng::Rng;
+use veos_crypto::sha256::{self, Sha256};
+
+/// The entropy in bits that needs to be credited before a reseed.
+const RESEED_BITS: usize = 256;
+
+/// The entropy pool with the generator it seeds.
+struct Pool {
+    /// The data that was added since the last reseed.
+    hasher: Sha256,
+    /// The entropy in bits that was credited since the last reseed.
+    credited_bits: usize,
+    /// The number of reseeds so far.
+    reseeds: usize,
+    /// The generator that produces the random bytes.
+    rng: Rng
+}
+
+lazy_static! {
+    /// The entropy pool of the kernel.
+    static ref POOL: Mutex<Pool> = Mutex::new(Pool {
+        hasher: Sha256::new(),
+        credited_bits: 0,
+        reseeds: 0,
+        rng: Rng::new(&boot_seed())
+    });
+}
+
+/// Returns a seed derived from the current time.
+///
+/// It contains hardly any entropy and only serves until the first reseed.
+fn boot_seed() -> sha256::Digest {
+    let since_boot = Timestamp::get_current() - Timestamp::from_duration(Duration::new(0, 0));
+    let seconds = since_boot.as_secs();
+    let nanoseconds = since_boot.subsec_nanos();
+
+    let mut bytes = [0; 12];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = if i < 8 {
+            (seconds >> (8 * i)) as u8
+        } else {
+            (nanoseconds >> (8 * (i - 8))) as u8
+        };
+    }
+
+    sha256::sha256(&bytes)
+}
+
+/// Adds the data to the pool, crediting it with the given entropy in bits.
+///
+/// Data that is hard to predict, but whose entropy can't be estimated, can be
+/// credited with zero bits.
+pub fn add_entropy(data: &[u8], bits: usize) {
+    let mut pool = POOL.lock();
+
+    pool.hasher.update(data);
+    pool.credited_bits += bits;
+
+    if pool.credited_bits >= RESEED_BITS {
+        let seed = mem::replace(&mut pool.hasher, Sha256::new()).finish();
+
+        pool.rng.reseed(&seed);
+        pool.credited_bits = 0;
+        pool.reseeds += 1;
+
+        if pool.reseeds == 1 {
+            debug!("The entropy pool is seeded.");
+        }
+    }
+}
+
+/// Fills the buffer with random bytes.
+///
+/// This never blocks, even if the pool isn't seeded yet.
+pub fn fill(buffer: &mut [u8]) {
+    POOL.lock().rng.fill(buffer);
+}
+
+/// Returns true if enough entropy was credited to seed the generator.
+pub fn is_seeded() -> bool {
+    POOL.lock().reseeds > 0
+}
@@ -0,0 +1,261 @@
+//! This module reads the manifests that describe server processes.
+//!
+//! Following the microkernel model, drivers and other services run as
+//! separate processes. A manifest in the initramfs names the executable of a
+//! server together with the capabilities and hardware resources it needs.
+//!
+//! A manifest consists of lines of the form `key value`. Empty lines and
+//! lines starting with `#` are ignored. The supported keys are:
+//!
+//! - `binary <path>`: The executable of the server, required exactly once.
+//! - `argument <value>`: An additional argument passed to the server.
+//! - `capability <name>`: A capability the server needs.
+//! - `mmio <address> <length>`: A physical memory area the server may map.
+//! - `irq <number>`: An interrupt the server may handle.
+//! - `service <name>`: A name the server may register a service under.
+//! - `essential <true|false>`: Whether the system can't do without the
+//!   server, which keeps it from being killed when memory runs out.
+//!
+//! Numbers can be given in decimal or in hexadecimal with a `0x` prefix.
+
+use alloc::string::String;
+use alloc::Vec;
+use core::str;
+use initramfs;
+use memory::{Address, MemoryArea, PhysicalAddress};
+use multitasking::capabilities::{self, Capabilities};
+use multitasking::grants::Grants;
+use multitasking::service;
+
+/// The maximum size of a manifest file.
+const MAX_MANIFEST_SIZE: usize = 4096;
+
+/// The errors that can occur while reading a manifest.
+#[derive(Debug, PartialEq)]
+pub enum ManifestError {
+    /// The manifest file doesn't exist.
+    FileNotExistant,
+    /// The manifest file is larger than `MAX_MANIFEST_SIZE`.
+    TooLarge,
+    /// The manifest is not valid UTF-8.
+    InvalidEncoding,
+    /// The manifest doesn't name a binary.
+    MissingBinary,
+    /// The manifest names more than one binary.
+    DuplicateBinary,
+    /// The line with the given number uses an unknown key.
+    UnknownKey(usize),
+    /// The line with the given number has an invalid value.
+    InvalidValue(usize),
+    /// The line with the given number requests an unknown capability.
+    UnknownCapability(usize)
+}
+
+/// The description of a server process.
+#[derive(Debug)]
+pub struct Manifest {
+    /// The path of the executable of the server.
+    pub binary: String,
+    /// The arguments passed to the server after its path.
+    pub arguments: Vec<String>,
+    /// The capabilities the server needs.
+    pub capabilities: Capabilities,
+    /// The hardware resources the server may access directly.
+    pub grants: Grants,
+    /// Whether the server is never killed to reclaim memory.
+    pub essential: bool
+}
+
+impl Manifest {
+    /// Reads the manifest at the given path in the initramfs.
+    pub fn from_initramfs(path: &str) -> Result<Manifest, ManifestError> {
+        let mut file_handle =
+            initramfs::open(path).map_err(|_| ManifestError::FileNotExistant)?;
+
+        let length = file_handle.len() as usize;
+
+        if length > MAX_MANIFEST_SIZE {
+            return Err(ManifestError::TooLarge);
+        }
+
+        let mut buffer = [0u8; MAX_MANIFEST_SIZE];
+        let text = &mut buffer[..length];
+
+        file_handle
+            .read(text)
+            .map_err(|_| ManifestError::FileNotExistant)?;
+
+        Manifest::parse(str::from_utf8(text).map_err(|_| ManifestError::InvalidEncoding)?)
+    }
+
+    /// Parses the text of a manifest.
+    pub fn parse(text: &str) -> Result<Manifest, ManifestError> {
+        let mut binary = None;
+        let mut arguments = Vec::new();
+        let mut capabilities = Capabilities::empty();
+        let mut grants = Grants::default();
+        let mut essential = false;
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.find(|c: char| c.is_whitespace()) {
+                Some(split) => (&line[..split], line[split..].trim()),
+                None => return Err(ManifestError::InvalidValue(line_number))
+            };
+
+            match key {
+                "binary" => {
+                    if binary.is_some() {
+                        return Err(ManifestError::DuplicateBinary);
+                    }
+
+                    binary = Some(String::from(value));
+                },
+                "argument" => arguments.push(String::from(value)),
+                "capability" => {
+                    capabilities |= capabilities::from_name(value)
+                        .ok_or(ManifestError::UnknownCapability(line_number))?;
+                },
+                "mmio" => {
+                    let mut values = value.split_whitespace();
+
+                    let (address, length) = match (values.next(), values.next(), values.next()) {
+                        (Some(address), Some(length), None) => (
+                            parse_number(address).ok_or(ManifestError::InvalidValue(line_number))?,
+                            parse_number(length).ok_or(ManifestError::InvalidValue(line_number))?
+                        ),
+                        _ => return Err(ManifestError::InvalidValue(line_number))
+                    };
+
+                    if length == 0 || address.checked_add(length).is_none() {
+                        return Err(ManifestError::InvalidValue(line_number));
+                    }
+
+                    grants.mmio.push(MemoryArea::new(
+                        PhysicalAddress::from_usize(address),
+                        length
+                    ));
+                },
+                "irq" => match parse_number(value) {
+                    Some(irq) if irq <= u8::max_value() as usize => grants.irqs.push(irq as u8),
+                    _ => return Err(ManifestError::InvalidValue(line_number))
+                },
+                "service" => {
+                    if value.len() > service::MAX_NAME_LENGTH {
+                        return Err(ManifestError::InvalidValue(line_number));
+                    }
+
+                    grants.services.push(String::from(value));
+                },
+                "essential" => {
+                    essential = value
+                        .parse()
+                        .map_err(|_| ManifestError::InvalidValue(line_number))?;
+                },
+                _ => return Err(ManifestError::UnknownKey(line_number))
+            }
+        }
+
+        Ok(Manifest {
+            binary: binary.ok_or(ManifestError::MissingBinary)?,
+            arguments,
+            capabilities,
+            grants,
+            essential
+        })
+    }
+}
+
+/// Parses a decimal number or a hexadecimal number prefixed with `0x`.
+fn parse_number(text: &str) -> Option<usize> {
+    if text.starts_with("0x") {
+        usize::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multitasking::capabilities::{SET_ROOT, TRACE};
+
+    /// Tests that a complete manifest is parsed.
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = Manifest::parse(
+            "# A serial driver.\n\
+             binary /bin/serial\n\
+             \n\
+             argument --port\n\
+             argument 1\n\
+             capability set_root\n\
+             capability trace\n\
+             mmio 0xfebc0000 0x1000\n\
+             irq 4\n\
+             service serial\n\
+             essential true\n"
+        ).unwrap();
+
+        assert_eq!(manifest.binary, "/bin/serial");
+        assert_eq!(manifest.arguments, ["--port", "1"]);
+        assert_eq!(manifest.capabilities, SET_ROOT | TRACE);
+        assert_eq!(manifest.grants.mmio.len(), 1);
+        assert_eq!(
+            manifest.grants.mmio[0].start_address(),
+            PhysicalAddress::from_usize(0xfebc0000)
+        );
+        assert_eq!(manifest.grants.mmio[0].length(), 0x1000);
+        assert_eq!(manifest.grants.irqs, [4]);
+        assert_eq!(manifest.grants.services, ["serial"]);
+        assert!(manifest.essential);
+    }
+
+    /// Tests that manifests must name exactly one binary.
+    #[test]
+    fn test_binary_required_once() {
+        assert_eq!(
+            Manifest::parse("irq 4\n").unwrap_err(),
+            ManifestError::MissingBinary
+        );
+        assert_eq!(
+            Manifest::parse("binary /bin/a\nbinary /bin/b\n").unwrap_err(),
+            ManifestError::DuplicateBinary
+        );
+    }
+
+    /// Tests that invalid lines are reported with their line number.
+    #[test]
+    fn test_invalid_lines() {
+        assert_eq!(
+            Manifest::parse("binary /bin/a\nport 4\n").unwrap_err(),
+            ManifestError::UnknownKey(2)
+        );
+        assert_eq!(
+            Manifest::parse("binary /bin/a\ncapability everything\n").unwrap_err(),
+            ManifestError::UnknownCapability(2)
+        );
+        assert_eq!(
+            Manifest::parse("binary /bin/a\nirq 256\n").unwrap_err(),
+            ManifestError::InvalidValue(2)
+        );
+        assert_eq!(
+            Manifest::parse("binary /bin/a\nessential maybe\n").unwrap_err(),
+            ManifestError::InvalidValue(2)
+        );
+        assert_eq!(
+            Manifest::parse("binary /bin/a\nmmio 0x1000\n").unwrap_err(),
+            ManifestError::InvalidValue(2)
+        );
+        assert_eq!(
+            Manifest::parse("binary /bin/a\nmmio 0x1000 0\n").unwrap_err(),
+            ManifestError::InvalidValue(2)
+        );
+    }
+}
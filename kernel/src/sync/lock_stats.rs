@@ -0,0 +1,227 @@
+//! This module collects statistics about the contention of named locks.
+//!
+//! The statistics are only collected with the `lock_stats` feature. A lock is
+//! named by creating it with a static `LockStats`. Every acquisition of the
+//! lock is counted, together with whether it had to wait for another holder
+//! and for how long the lock was held. `dump` logs the locks that were
+//! contended the most, which are the candidates for splitting.
+//!
+//! The statistics are linked into a list when they are first used, so that
+//! recording never locks or allocates.
+
+use alloc::Vec;
+use config;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use sync::time::Timestamp;
+
+/// The maximum number of locks that `dump` logs.
+const MAX_DUMPED_LOCKS: usize = 16;
+
+/// The address of the most recently used statistics or zero.
+static FIRST_STATS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The statistics of a named lock.
+pub struct LockStats {
+    /// The name of the lock.
+    name: &'static str,
+    /// The number of times the lock was acquired.
+    acquisitions: AtomicUsize,
+    /// The number of acquisitions that had to wait for another holder.
+    contended: AtomicUsize,
+    /// The longest time the lock was held in nanoseconds.
+    max_hold_nanos: AtomicUsize,
+    /// Whether the statistics are part of the list of all statistics.
+    registered: AtomicBool,
+    /// The address of the next statistics in the list or zero.
+    next: AtomicUsize
+}
+
+/// A copy of the statistics of a lock at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Snapshot {
+    /// The name of the lock.
+    name: &'static str,
+    /// The number of times the lock was acquired.
+    acquisitions: usize,
+    /// The number of acquisitions that had to wait for another holder.
+    contended: usize,
+    /// The longest time the lock was held in nanoseconds.
+    max_hold_nanos: usize
+}
+
+impl LockStats {
+    /// Creates empty statistics for the lock with the given name.
+    pub const fn new(name: &'static str) -> LockStats {
+        LockStats {
+            name,
+            acquisitions: AtomicUsize::new(0),
+            contended: AtomicUsize::new(0),
+            max_hold_nanos: AtomicUsize::new(0),
+            registered: AtomicBool::new(false),
+            next: AtomicUsize::new(0)
+        }
+    }
+
+    /// Records an acquisition of the lock.
+    ///
+    /// `contended` is true if the lock had to wait for another holder.
+    /// Returns the time of the acquisition, which is passed to
+    /// `record_release`, or `None` if no statistics are collected.
+    pub fn record_acquisition(&'static self, contended: bool) -> Option<Timestamp> {
+        if !config::LOCK_STATS {
+            return None;
+        }
+
+        self.register();
+
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+
+        if contended {
+            self.contended.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Some(Timestamp::get_current())
+    }
+
+    /// Records that the lock acquired at the given time was released.
+    pub fn record_release(&self, acquired_at: Timestamp) {
+        let hold_time = Timestamp::get_current() - acquired_at;
+        let nanos = hold_time
+            .as_secs()
+            .saturating_mul(1_000_000_000)
+            .saturating_add(hold_time.subsec_nanos() as u64) as usize;
+
+        update_max(&self.max_hold_nanos, nanos);
+    }
+
+    /// Adds the statistics to the list of all statistics, unless they are
+    /// part of it already.
+    fn register(&'static self) {
+        if self
+            .registered
+            .compare_and_swap(false, true, Ordering::Relaxed)
+        {
+            return;
+        }
+
+        loop {
+            let first = FIRST_STATS.load(Ordering::Relaxed);
+            self.next.store(first, Ordering::Relaxed);
+
+            let address = self as *const LockStats as usize;
+            if FIRST_STATS.compare_and_swap(first, address, Ordering::Release) == first {
+                break;
+            }
+        }
+    }
+
+    /// Returns a copy of the current statistics.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            name: self.name,
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+            max_hold_nanos: self.max_hold_nanos.load(Ordering::Relaxed)
+        }
+    }
+}
+
+/// Raises the value to at least `value`.
+fn update_max(maximum: &AtomicUsize, value: usize) {
+    let mut current = maximum.load(Ordering::Relaxed);
+
+    while current < value {
+        let previous = maximum.compare_and_swap(current, value, Ordering::Relaxed);
+
+        if previous == current {
+            break;
+        }
+
+        current = previous;
+    }
+}
+
+/// Sorts the snapshots so that the most contended locks come first.
+///
+/// Locks with the same contention are ordered by their acquisitions.
+fn sort_by_contention(snapshots: &mut [Snapshot]) {
+    snapshots.sort_by(|a, b| (b.contended, b.acquisitions).cmp(&(a.contended, a.acquisitions)));
+}
+
+/// Logs the statistics of the most contended locks.
+///
+/// Returns false if the kernel doesn't collect lock statistics.
+pub fn dump() -> bool {
+    if !config::LOCK_STATS {
+        return false;
+    }
+
+    let mut snapshots = Vec::new();
+    let mut address = FIRST_STATS.load(Ordering::Acquire);
+
+    while address != 0 {
+        let stats = unsafe { &*(address as *const LockStats) };
+
+        snapshots.push(stats.snapshot());
+        address = stats.next.load(Ordering::Relaxed);
+    }
+
+    sort_by_contention(&mut snapshots);
+
+    info!("Lock statistics of {} named locks:", snapshots.len());
+
+    for snapshot in snapshots.iter().take(MAX_DUMPED_LOCKS) {
+        info!(
+            "{}: {} acquisitions, {} contended, held up to {}µs",
+            snapshot.name,
+            snapshot.acquisitions,
+            snapshot.contended,
+            snapshot.max_hold_nanos / 1000
+        );
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a snapshot with the given counts.
+    fn snapshot(name: &'static str, acquisitions: usize, contended: usize) -> Snapshot {
+        Snapshot {
+            name,
+            acquisitions,
+            contended,
+            max_hold_nanos: 0
+        }
+    }
+
+    /// Tests that the most contended locks are sorted first.
+    #[test]
+    fn sorted_by_contention() {
+        let mut snapshots = [
+            snapshot("a", 100, 1),
+            snapshot("b", 10, 5),
+            snapshot("c", 200, 1),
+            snapshot("d", 5, 0)
+        ];
+
+        sort_by_contention(&mut snapshots);
+
+        let names: Vec<&str> = snapshots.iter().map(|snapshot| snapshot.name).collect();
+        assert_eq!(names, ["b", "c", "a", "d"]);
+    }
+
+    /// Tests that the maximum only grows.
+    #[test]
+    fn maximum_only_grows() {
+        let maximum = AtomicUsize::new(5);
+
+        update_max(&maximum, 3);
+        assert_eq!(maximum.load(Ordering::Relaxed), 5);
+
+        update_max(&maximum, 8);
+        assert_eq!(maximum.load(Ordering::Relaxed), 8);
+    }
+}
@@ -1,46 +1,103 @@
 //! Handles synchronization within the kernel.
 
+pub mod lock_stats;
 pub mod mutex;
+pub mod once_cell;
+pub mod preemption_audit;
+pub mod rwlock;
 pub mod time;
 
+pub use self::lock_stats::LockStats;
 pub use self::mutex::Mutex;
+pub use self::once_cell::{Lazy, OnceCell};
+pub use self::rwlock::RwLock;
+use self::preemption_audit::Region;
 use arch::{self, Architecture};
+use config;
 
-/// Saves the state when disabling preemtion, so it can be restored later.
-#[derive(Default)]
-pub struct PreemptionState {
-    /// Saves whether interrupts were enabled, when preemtion was disabled.
-    interrupts_enabled: bool
+/// Keeps preemption disabled until it is dropped.
+///
+/// Dropping the guard restores the state from before it was created, so
+/// guards have to be dropped in the reverse order of their creation.
+pub struct PreemptionGuard {
+    /// Whether interrupts were enabled before the guard was created.
+    interrupts_enabled: bool,
+    /// The audited region that the guard started.
+    region: Option<Region>
 }
 
-impl PreemptionState {
-    /// Reads the current state of preemptability.
-    fn current() -> PreemptionState {
-        PreemptionState {
-            interrupts_enabled: arch::Current::get_interrupt_state()
+impl PreemptionGuard {
+    /// Disables preemption until the returned guard is dropped.
+    pub fn new() -> PreemptionGuard {
+        let interrupts_enabled = arch::Current::get_interrupt_state();
+
+        unsafe {
+            arch::Current::disable_interrupts();
+        }
+
+        let mut guard = PreemptionGuard {
+            interrupts_enabled,
+            region: None
+        };
+        guard.restart_audit_region();
+
+        guard
+    }
+
+    /// Ends the audited region before the current thread is switched away
+    /// from.
+    pub fn end_audit_region(&mut self) {
+        if let Some(region) = self.region.take() {
+            region.end();
         }
     }
 
-    /// Statically returns a default preemption state.
-    const fn default() -> PreemptionState {
-        PreemptionState {
-            interrupts_enabled: false
+    /// Starts a new audited region once the current thread runs again.
+    pub fn restart_audit_region(&mut self) {
+        if config::PREEMPTION_AUDIT && self.interrupts_enabled {
+            self.region = Some(Region::begin());
         }
     }
+}
+
+impl Drop for PreemptionGuard {
+    fn drop(&mut self) {
+        self.end_audit_region();
+
+        unsafe {
+            arch::Current::set_interrupt_state(self.interrupts_enabled);
+        }
+    }
+}
 
-    /// Restores the saved preemption state.
-    unsafe fn restore(&self) {
-        // TODO: Do this on a drop?
-        arch::Current::set_interrupt_state(self.interrupts_enabled);
+/// Keeps interrupts disabled until it is dropped.
+///
+/// Preemption happens through interrupts, so this keeps the current thread
+/// running as well. Unlike a `PreemptionGuard` it is never audited, which
+/// suits hardware accesses that must not be interrupted and suspending the
+/// machine, which takes long on purpose.
+pub struct InterruptGuard {
+    /// Whether interrupts were enabled before the guard was created.
+    interrupts_enabled: bool
+}
+
+impl InterruptGuard {
+    /// Disables interrupts until the returned guard is dropped.
+    pub fn new() -> InterruptGuard {
+        let interrupts_enabled = arch::Current::get_interrupt_state();
+
+        unsafe {
+            arch::Current::disable_interrupts();
+        }
+
+        InterruptGuard { interrupts_enabled }
     }
+}
 
-    /// Copies the preemption state.
-    ///
-    /// # Safety
-    /// - Make sure that every preemption state is properly restored only once.
-    pub unsafe fn copy(&self) -> PreemptionState {
-        PreemptionState {
-            interrupts_enabled: self.interrupts_enabled
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        unsafe {
+            arch::Current::set_interrupt_state(self.interrupts_enabled);
         }
     }
 }
@@ -60,31 +117,20 @@ pub unsafe fn cpu_halt() {
     arch::Current::cpu_halt();
 }
 
-/// Disables preemption and returns the previous state.
+/// Unconditionally disables preemption.
 ///
 /// # Safety
-/// - The returned `PreemptionState` must be restored.
-pub unsafe fn disable_preemption() -> PreemptionState {
-    let state = PreemptionState::current();
-
+/// This should only be done during initialization and on paths that never
+/// return. Otherwise a `PreemptionGuard` should be used.
+pub unsafe fn disable_preemption() {
     arch::Current::disable_interrupts();
-
-    state
 }
 
 /// Unconditionally enables preemption.
 ///
 /// # Safety
-/// This should only be done during initialization. Otherwise the preemption
-/// state that was returned by the disable function should be restored.
+/// This should only be done during initialization. Otherwise the
+/// `PreemptionGuard` that disabled preemption should be dropped.
 pub unsafe fn enable_preemption() {
     arch::Current::enable_interrupts();
 }
-
-/// Reenables preemption to the saved state.
-///
-/// # Safety
-/// - No locks should be held when restoring the `PreemptionState`.
-pub unsafe fn restore_preemption_state(state: &PreemptionState) {
-    state.restore();
-}
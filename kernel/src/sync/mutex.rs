@@ -3,7 +3,9 @@
 //! This is a modification of the Mutex code from the spin crate (see
 //! https://crates.io/crates/spin).
 
-use super::{cpu_relax, disable_preemption, restore_preemption_state, PreemptionState};
+use super::lock_stats::LockStats;
+use super::time::Timestamp;
+use super::{cpu_relax, PreemptionGuard};
 use core::cell::UnsafeCell;
 use core::default::Default;
 use core::fmt;
@@ -35,7 +37,8 @@ use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 ///
 pub struct Mutex<T: ?Sized> {
     lock: AtomicBool,
-    preemption_state: UnsafeCell<PreemptionState>,
+    /// The contention statistics of the lock, if it is named.
+    stats: Option<&'static LockStats>,
     data: UnsafeCell<T>
 }
 
@@ -44,8 +47,15 @@ pub struct Mutex<T: ?Sized> {
 /// When the guard falls out of scope it will release the lock.
 pub struct MutexGuard<'a, T: ?Sized + 'a> {
     lock: &'a AtomicBool,
-    preemption_state: &'a PreemptionState,
-    data: &'a mut T
+    /// The statistics of the lock together with the time it was acquired, if
+    /// they are collected.
+    stats: Option<(&'a LockStats, Timestamp)>,
+    data: &'a mut T,
+    /// Keeps preemption disabled while the lock is held.
+    ///
+    /// Fields are dropped after `drop` ran, so this happens after the lock
+    /// was released.
+    _preemption: PreemptionGuard
 }
 
 // Same unsafe impls as `std::sync::Mutex`
@@ -57,7 +67,19 @@ impl<T> Mutex<T> {
     pub const fn new(user_data: T) -> Mutex<T> {
         Mutex {
             lock: ATOMIC_BOOL_INIT,
-            preemption_state: UnsafeCell::new(PreemptionState::default()),
+            stats: None,
+            data: UnsafeCell::new(user_data)
+        }
+    }
+
+    /// Creates a new spinlock wrapping the supplied data, whose contention is
+    /// recorded in the given statistics.
+    ///
+    /// The statistics are only collected with the `lock_stats` feature.
+    pub const fn with_stats(user_data: T, stats: &'static LockStats) -> Mutex<T> {
+        Mutex {
+            lock: ATOMIC_BOOL_INIT,
+            stats: Some(stats),
             data: UnsafeCell::new(user_data)
         }
     }
@@ -73,32 +95,38 @@ impl<T> Mutex<T> {
 }
 
 impl<T: ?Sized> Mutex<T> {
-    fn obtain_lock(&self) {
+    /// Obtains the lock and returns the guard that keeps preemption disabled
+    /// together with whether another holder had to be waited for.
+    fn obtain_lock(&self) -> (PreemptionGuard, bool) {
         // while self.lock.compare_and_swap(false, true, Ordering::Acquire) != false
         //
-        let mut preemption_state;
+        let mut contended = false;
         loop {
-            unsafe {
-                preemption_state = disable_preemption();
-            }
+            let preemption = PreemptionGuard::new();
             let lock_switch = !self.lock.compare_and_swap(false, true, Ordering::Acquire);
             if lock_switch {
-                break;
+                return (preemption, contended);
             } else {
-                unsafe {
-                    restore_preemption_state(&preemption_state);
-                }
+                drop(preemption);
             }
 
+            contended = true;
+
             // Wait until the lock looks unlocked before retrying
             while self.lock.load(Ordering::Relaxed) {
                 cpu_relax();
             }
         }
+    }
 
-        unsafe {
-            *self.preemption_state.get() = preemption_state;
-        }
+    /// Records the acquisition of the lock and returns what the guard needs
+    /// to record the release.
+    fn record_acquisition(&self, contended: bool) -> Option<(&LockStats, Timestamp)> {
+        self.stats.and_then(|stats| {
+            stats
+                .record_acquisition(contended)
+                .map(|acquired_at| (stats as &LockStats, acquired_at))
+        })
     }
 
     /// Locks the spinlock and returns a guard.
@@ -106,11 +134,12 @@ impl<T: ?Sized> Mutex<T> {
     /// The returned value may be dereferenced for data access
     /// and the lock will be dropped when the guard falls out of scope.
     pub fn lock(&self) -> MutexGuard<T> {
-        self.obtain_lock();
+        let (preemption, contended) = self.obtain_lock();
         MutexGuard {
             lock: &self.lock,
-            preemption_state: unsafe { &*self.preemption_state.get() },
-            data: unsafe { &mut *self.data.get() }
+            stats: self.record_acquisition(contended),
+            data: unsafe { &mut *self.data.get() },
+            _preemption: preemption
         }
     }
 
@@ -131,22 +160,17 @@ impl<T: ?Sized> Mutex<T> {
     /// Otherwise it returns
     /// a guard within Some.
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        let preemption_state = unsafe { disable_preemption() };
+        let preemption = PreemptionGuard::new();
         let lock_switch = !self.lock.compare_and_swap(false, true, Ordering::Acquire);
         // if self.lock.compare_and_swap(false, true, Ordering::Acquire) == false
         if lock_switch {
-            unsafe {
-                *self.preemption_state.get() = preemption_state;
-            }
             Some(MutexGuard {
                 lock: &self.lock,
-                preemption_state: unsafe { &*self.preemption_state.get() },
-                data: unsafe { &mut *self.data.get() }
+                stats: self.record_acquisition(false),
+                data: unsafe { &mut *self.data.get() },
+                _preemption: preemption
             })
         } else {
-            unsafe {
-                restore_preemption_state(&preemption_state);
-            }
             None
         }
     }
@@ -184,9 +208,10 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
     /// The dropping of the MutexGuard will release the lock it was created
     /// from.
     fn drop(&mut self) {
-        self.lock.store(false, Ordering::Release);
-        unsafe {
-            restore_preemption_state(self.preemption_state);
+        if let Some((stats, acquired_at)) = self.stats {
+            stats.record_release(acquired_at);
         }
+
+        self.lock.store(false, Ordering::Release);
     }
 }
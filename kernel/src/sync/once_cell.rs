@@ -0,0 +1,201 @@
+//! This module provides a cell that can be written only once.
+//!
+//! It is meant for values that are set once during boot and only read
+//! afterwards. Unlike a `static mut`, reading the value before it is set or
+//! setting it twice can be detected, and reading it never requires a lock.
+//!
+//! `Lazy` builds on it for values that are computed on their first use
+//! instead.
+
+use super::cpu_relax;
+#[cfg(not(test))]
+use super::InterruptGuard;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+/// The cell doesn't contain a value yet.
+const UNINITIALIZED: usize = 0;
+
+/// The value of the cell is being written.
+const INITIALIZING: usize = 1;
+
+/// The cell contains a value.
+const INITIALIZED: usize = 2;
+
+/// A cell that can be written once and read without locking afterwards.
+pub struct OnceCell<T> {
+    /// The state of the cell.
+    state: AtomicUsize,
+    /// The value of the cell.
+    value: UnsafeCell<Option<T>>
+}
+
+// The value is only written once before it can be read, so sharing the cell
+// is as safe as sharing the value.
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+
+impl<T> OnceCell<T> {
+    /// Creates a new empty cell.
+    pub const fn new() -> OnceCell<T> {
+        OnceCell {
+            state: ATOMIC_USIZE_INIT,
+            value: UnsafeCell::new(None)
+        }
+    }
+
+    /// Sets the value of the cell.
+    ///
+    /// Returns the value as an error if the cell was already set.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self
+            .state
+            .compare_and_swap(UNINITIALIZED, INITIALIZING, Ordering::Acquire)
+            != UNINITIALIZED
+        {
+            return Err(value);
+        }
+
+        unsafe { *self.value.get() = Some(value) };
+
+        self.state.store(INITIALIZED, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Returns the value of the cell, setting it to the result of `init` if
+    /// it wasn't set yet.
+    ///
+    /// If another CPU is setting the value, this waits for it. `init` must
+    /// not use the cell itself.
+    pub fn get_or_init<F: FnOnce() -> T>(&self, init: F) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+
+        {
+            // An interrupt handler that used the cell during the
+            // initialization would wait for it forever on the same CPU.
+            // Tests run in userspace, where interrupts can't be disabled.
+            #[cfg(not(test))]
+            let _interrupts = InterruptGuard::new();
+
+            let previous_state =
+                self.state
+                    .compare_and_swap(UNINITIALIZED, INITIALIZING, Ordering::Acquire);
+
+            if previous_state == UNINITIALIZED {
+                unsafe { *self.value.get() = Some(init()) };
+
+                self.state.store(INITIALIZED, Ordering::Release);
+            }
+        }
+
+        loop {
+            if let Some(value) = self.get() {
+                return value;
+            }
+
+            cpu_relax();
+        }
+    }
+
+    /// Returns the value of the cell, if it was set.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == INITIALIZED {
+            unsafe { (*self.value.get()).as_ref() }
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value of the cell.
+    ///
+    /// # Panics
+    /// Panics with the given message if the cell wasn't set yet.
+    pub fn expect(&self, message: &str) -> &T {
+        match self.get() {
+            Some(value) => value,
+            None => panic!("{}", message)
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(value) => write!(f, "OnceCell({:?})", value),
+            None => write!(f, "OnceCell(<uninitialized>)")
+        }
+    }
+}
+
+/// A value that is computed when it is first used.
+///
+/// Unlike with `lazy_static!`, the initialization function is an ordinary
+/// function, so the same type works for any static.
+pub struct Lazy<T> {
+    /// The computed value.
+    cell: OnceCell<T>,
+    /// The function that computes the value.
+    init: fn() -> T
+}
+
+impl<T> Lazy<T> {
+    /// Creates a value that is computed by `init` on its first use.
+    pub const fn new(init: fn() -> T) -> Lazy<T> {
+        Lazy {
+            cell: OnceCell::new(),
+            init
+        }
+    }
+}
+
+impl<T> Deref for Lazy<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.cell.get_or_init(self.init)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the value of the lazy test value.
+    fn forty_two() -> u32 {
+        42
+    }
+
+    /// Tests that a cell is only set once.
+    #[test]
+    fn test_set_once() {
+        let cell = OnceCell::new();
+
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.set(1), Ok(()));
+        assert_eq!(cell.set(2), Err(2));
+        assert_eq!(cell.get(), Some(&1));
+    }
+
+    /// Tests that the initialization only runs for an empty cell.
+    #[test]
+    fn test_get_or_init() {
+        let cell = OnceCell::new();
+
+        assert_eq!(*cell.get_or_init(|| 1), 1);
+        assert_eq!(*cell.get_or_init(|| 2), 1);
+    }
+
+    /// Tests that a lazy value is computed on its first use.
+    #[test]
+    fn test_lazy() {
+        let lazy = Lazy::new(forty_two);
+
+        assert_eq!(lazy.cell.get(), None);
+        assert_eq!(*lazy, 42);
+        assert_eq!(lazy.cell.get(), Some(&42));
+    }
+}
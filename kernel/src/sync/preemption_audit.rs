@@ -0,0 +1,111 @@
+//! Finds long regions in which preemption is disabled.
+//!
+//! With the `preemption_audit` feature, disabling preemption while it was
+//! enabled records the time and the callers. When the state is restored, a
+//! region that lasted longer than the threshold is reported together with the
+//! callers that disabled preemption and those that restored it. The threshold
+//! in microseconds is set with `preemption_audit_us=` on the kernel command
+//! line.
+//!
+//! A region that spans a context switch is audited as two regions, one in
+//! each of the threads.
+
+use boot;
+use config;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use ksymbol;
+use memory::{Address, VirtualAddress};
+use multitasking::backtrace::collect_callers;
+use sync::time::Timestamp;
+
+/// The number of return addresses recorded for each end of a region.
+///
+/// The first ones usually belong to the locks.
+const CALLER_DEPTH: usize = 6;
+
+/// The threshold used if none is given on the command line.
+const DEFAULT_THRESHOLD_MICROS: usize = 1000;
+
+/// The time in microseconds after which a region is reported.
+static THRESHOLD_MICROS: AtomicUsize = AtomicUsize::new(DEFAULT_THRESHOLD_MICROS);
+
+/// A region in which preemption is disabled.
+#[derive(Clone, Copy)]
+pub struct Region {
+    /// The time preemption was disabled.
+    start: Timestamp,
+    /// The callers that disabled preemption.
+    callers: [VirtualAddress; CALLER_DEPTH]
+}
+
+impl Region {
+    /// Starts a region in the callers of the calling function.
+    #[inline(never)]
+    pub fn begin() -> Region {
+        let mut callers = [VirtualAddress::from_usize(0); CALLER_DEPTH];
+        collect_callers(&mut callers);
+
+        Region {
+            start: Timestamp::get_current(),
+            callers
+        }
+    }
+
+    /// Ends the region and reports it if it lasted too long.
+    ///
+    /// This must be called before preemption is enabled again, so that
+    /// reporting doesn't start another region.
+    #[inline(never)]
+    pub fn end(&self) {
+        let duration = Timestamp::get_current()
+            .checked_sub(self.start)
+            .unwrap_or_default();
+        let micros = duration.as_secs() as usize * 1_000_000 + duration.subsec_micros() as usize;
+
+        if micros <= THRESHOLD_MICROS.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut restorers = [VirtualAddress::from_usize(0); CALLER_DEPTH];
+        collect_callers(&mut restorers);
+
+        warn!("Preemption was disabled for {} us.", micros);
+        warn!("  Disabled by:");
+        print_callers(&self.callers);
+        warn!("  Restored by:");
+        print_callers(&restorers);
+    }
+}
+
+/// Reads the threshold from the command line.
+///
+/// This must be called once after the boot information was read.
+pub fn init() {
+    assert_has_not_been_called!("The preemption audit should only be initialized once.");
+
+    if !config::PREEMPTION_AUDIT {
+        return;
+    }
+
+    if let Some(value) = boot::get_option_value("preemption_audit_us") {
+        match value.parse() {
+            Ok(threshold) => THRESHOLD_MICROS.store(threshold, Ordering::Relaxed),
+            Err(_) => warn!("Invalid option preemption_audit_us={}.", value)
+        }
+    }
+
+    info!(
+        "Reporting regions with preemption disabled for more than {} us.",
+        THRESHOLD_MICROS.load(Ordering::Relaxed)
+    );
+}
+
+/// Prints the recorded return addresses.
+fn print_callers(callers: &[VirtualAddress]) {
+    for &address in callers.iter().take_while(|address| address.as_usize() != 0) {
+        match ksymbol::resolve(address) {
+            Some(symbol) => warn!("    {:?} ({})", address, symbol),
+            None => warn!("    {:?} (unknown function)", address)
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Handles shared reading and exclusive writing of data.
+//!
+//! Any number of readers can hold the lock at the same time, while a writer
+//! holds it alone. Writers are preferred: once a writer waits, no new readers
+//! are let in, so a steady stream of readers can't keep it waiting forever.
+//! This also means that a reader must never lock the same lock again while
+//! it holds it, because a writer could start waiting in between.
+//!
+//! Like the mutex, the lock keeps preemption disabled while it is held.
+
+use super::lock_stats::LockStats;
+use super::time::Timestamp;
+use super::{cpu_relax, PreemptionGuard};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut, Drop};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+/// The bit of the state that is set while a writer holds the lock.
+///
+/// The other bits count the readers.
+const WRITER: usize = !(::core::usize::MAX >> 1);
+
+/// A lock that is shared by readers and exclusive to writers.
+pub struct RwLock<T: ?Sized> {
+    /// The number of readers and whether a writer holds the lock.
+    state: AtomicUsize,
+    /// The number of writers that wait for the lock.
+    waiting_writers: AtomicUsize,
+    /// The contention statistics of the lock, if it is named.
+    stats: Option<&'static LockStats>,
+    /// The protected data.
+    data: UnsafeCell<T>
+}
+
+/// A guard through which the protected data can be read.
+///
+/// The read lock is released when the guard is dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    /// The state of the lock.
+    state: &'a AtomicUsize,
+    /// The statistics of the lock together with the time it was acquired, if
+    /// they are collected.
+    stats: Option<(&'a LockStats, Timestamp)>,
+    /// The protected data.
+    data: &'a T,
+    /// Keeps preemption disabled until the lock was released.
+    _preemption: PreemptionGuard
+}
+
+/// A guard through which the protected data can be written.
+///
+/// The write lock is released when the guard is dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    /// The state of the lock.
+    state: &'a AtomicUsize,
+    /// The statistics of the lock together with the time it was acquired, if
+    /// they are collected.
+    stats: Option<(&'a LockStats, Timestamp)>,
+    /// The protected data.
+    data: &'a mut T,
+    /// Keeps preemption disabled until the lock was released.
+    _preemption: PreemptionGuard
+}
+
+// Readers on different CPUs share the data, so it has to be `Sync` as well.
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new lock around the data.
+    pub const fn new(data: T) -> RwLock<T> {
+        RwLock {
+            state: ATOMIC_USIZE_INIT,
+            waiting_writers: ATOMIC_USIZE_INIT,
+            stats: None,
+            data: UnsafeCell::new(data)
+        }
+    }
+
+    /// Creates a new lock around the data, whose contention is recorded in
+    /// the given statistics.
+    ///
+    /// The statistics are only collected with the `lock_stats` feature.
+    pub const fn with_stats(data: T, stats: &'static LockStats) -> RwLock<T> {
+        RwLock {
+            state: ATOMIC_USIZE_INIT,
+            waiting_writers: ATOMIC_USIZE_INIT,
+            stats: Some(stats),
+            data: UnsafeCell::new(data)
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Records the acquisition of the lock and returns what the guard needs
+    /// to record the release.
+    fn record_acquisition(&self, contended: bool) -> Option<(&LockStats, Timestamp)> {
+        self.stats.and_then(|stats| {
+            stats
+                .record_acquisition(contended)
+                .map(|acquired_at| (stats as &LockStats, acquired_at))
+        })
+    }
+
+    /// Adds a reader, unless a writer holds the lock or waits for it.
+    fn try_add_reader(&self) -> bool {
+        if self.waiting_writers.load(Ordering::Relaxed) != 0 {
+            return false;
+        }
+
+        let state = self.state.load(Ordering::Relaxed);
+
+        if state & WRITER != 0 {
+            return false;
+        }
+
+        self.state
+            .compare_and_swap(state, state + 1, Ordering::Acquire)
+            == state
+    }
+
+    /// Makes the caller the writer, unless anyone else holds the lock.
+    fn try_add_writer(&self) -> bool {
+        self.state.compare_and_swap(0, WRITER, Ordering::Acquire) == 0
+    }
+
+    /// Locks the data for reading and returns a guard.
+    ///
+    /// This waits while a writer holds the lock or waits for it.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        let mut contended = false;
+
+        loop {
+            let preemption = PreemptionGuard::new();
+
+            if self.try_add_reader() {
+                return RwLockReadGuard {
+                    state: &self.state,
+                    stats: self.record_acquisition(contended),
+                    data: unsafe { &*self.data.get() },
+                    _preemption: preemption
+                };
+            }
+
+            drop(preemption);
+            contended = true;
+
+            // Wait until the writers are done before retrying.
+            while self.state.load(Ordering::Relaxed) & WRITER != 0
+                || self.waiting_writers.load(Ordering::Relaxed) != 0
+            {
+                cpu_relax();
+            }
+        }
+    }
+
+    /// Locks the data for writing and returns a guard.
+    ///
+    /// This waits until all readers and other writers released the lock.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let mut contended = false;
+
+        // From now on no new readers are let in.
+        self.waiting_writers.fetch_add(1, Ordering::Relaxed);
+
+        loop {
+            let preemption = PreemptionGuard::new();
+
+            if self.try_add_writer() {
+                self.waiting_writers.fetch_sub(1, Ordering::Relaxed);
+
+                return RwLockWriteGuard {
+                    state: &self.state,
+                    stats: self.record_acquisition(contended),
+                    data: unsafe { &mut *self.data.get() },
+                    _preemption: preemption
+                };
+            }
+
+            drop(preemption);
+            contended = true;
+
+            // Wait until the lock looks free before retrying.
+            while self.state.load(Ordering::Relaxed) != 0 {
+                cpu_relax();
+            }
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        if let Some((stats, acquired_at)) = self.stats {
+            stats.record_release(acquired_at);
+        }
+
+        self.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        if let Some((stats, acquired_at)) = self.stats {
+            stats.record_release(acquired_at);
+        }
+
+        self.state.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Locking disables interrupts, which isn't possible in userspace, so the
+    // tests only use the functions that change the state of the lock.
+
+    /// Tests that any number of readers can hold the lock at the same time.
+    #[test]
+    fn test_readers_share() {
+        let lock = RwLock::new(0);
+
+        assert!(lock.try_add_reader());
+        assert!(lock.try_add_reader());
+        assert_eq!(lock.state.load(Ordering::Relaxed), 2);
+    }
+
+    /// Tests that a writer holds the lock alone.
+    #[test]
+    fn test_writer_exclusive() {
+        let lock = RwLock::new(0);
+
+        assert!(lock.try_add_reader());
+        assert!(!lock.try_add_writer());
+
+        lock.state.fetch_sub(1, Ordering::Release);
+
+        assert!(lock.try_add_writer());
+        assert!(!lock.try_add_writer());
+        assert!(!lock.try_add_reader());
+    }
+
+    /// Tests that no new readers are let in while a writer waits.
+    #[test]
+    fn test_writer_preferred() {
+        let lock = RwLock::new(0);
+
+        assert!(lock.try_add_reader());
+
+        lock.waiting_writers.fetch_add(1, Ordering::Relaxed);
+
+        assert!(!lock.try_add_reader());
+        assert!(!lock.try_add_writer());
+
+        lock.state.fetch_sub(1, Ordering::Release);
+
+        assert!(lock.try_add_writer());
+    }
+}
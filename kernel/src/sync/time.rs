@@ -3,8 +3,12 @@
 use arch::{self, Architecture};
 use core::fmt;
 use core::ops;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use core::time::Duration;
 
+/// The number of nanoseconds between the Unix epoch and the boot.
+static BOOT_TIME_NANOS: AtomicUsize = ATOMIC_USIZE_INIT;
+
 /// Represents a timestamp within the kernel.
 ///
 /// Currently that is the `Duration` since boot.
@@ -17,9 +21,13 @@ impl fmt::Debug for Timestamp {
     }
 }
 
+/// Formats the timestamp as `[ssss.uuuuuu]`.
+///
+/// The seconds are padded, so that the timestamps in consecutive log lines
+/// line up.
 impl fmt::Display for Timestamp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[{:>6}.{:06}]", self.0.as_secs(), self.0.subsec_micros())
+        write!(f, "[{:04}.{:06}]", self.0.as_secs(), self.0.subsec_micros())
     }
 }
 
@@ -37,11 +45,21 @@ impl Timestamp {
         Timestamp(duration)
     }
 
+    /// Returns the latest time stamp, which is never reached.
+    pub fn max_value() -> Timestamp {
+        Timestamp(Duration::new(u64::max_value(), 999_999_999))
+    }
+
     /// Returns the current time stamp.
     pub fn get_current() -> Timestamp {
         arch::Current::get_current_timestamp()
     }
 
+    /// Returns the `Duration` since boot.
+    pub fn since_boot(self) -> Duration {
+        self.0
+    }
+
     /// Offsets the time stamp by the given amount.
     pub fn offset(self, duration: Duration) -> Option<Timestamp> {
         self.0
@@ -54,3 +72,42 @@ impl Timestamp {
         self.0.checked_sub(other.0)
     }
 }
+
+/// Reads the wall clock time from the hardware clock.
+///
+/// If the hardware clock can't be read, the system time starts at the Unix
+/// epoch.
+pub fn init_system_time() {
+    let boot_time = match arch::Current::read_hardware_clock() {
+        Some(seconds) => {
+            let since_boot = Timestamp::get_current().0;
+
+            Duration::from_secs(seconds)
+                .checked_sub(since_boot)
+                .unwrap_or(Duration::from_secs(0))
+        },
+        None => {
+            warn!("The hardware clock couldn't be read, the system time starts at 1970.");
+            Duration::from_secs(0)
+        }
+    };
+
+    let nanos = boot_time.as_secs() as usize * 1_000_000_000 + boot_time.subsec_nanos() as usize;
+    BOOT_TIME_NANOS.store(nanos, Ordering::Relaxed);
+
+    info!(
+        "The system time at boot was {} seconds after the Unix epoch.",
+        boot_time.as_secs()
+    );
+}
+
+/// Returns the time since the Unix epoch.
+pub fn get_system_time() -> Duration {
+    let boot_time = BOOT_TIME_NANOS.load(Ordering::Relaxed) as u64;
+    let boot_time = Duration::new(
+        boot_time / 1_000_000_000,
+        (boot_time % 1_000_000_000) as u32
+    );
+
+    boot_time + Timestamp::get_current().0
+}
@@ -0,0 +1,96 @@
+//! Defines the errors that syscalls report.
+//!
+//! Syscalls return a negative value on failure. `-1` means that the error is
+//! not further specified, the other values name the cause and must stay in
+//! sync with `veos_std::Error`.
+
+use elf::ElfError;
+use file_handle::FileError;
+use multitasking::hotplug::HotplugError;
+use server::ManifestError;
+
+/// The cause of a failed syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallError {
+    /// The error is not further specified.
+    Unspecified = 1,
+    /// The file doesn't exist.
+    NotFound = 2,
+    /// The file is not a valid executable.
+    InvalidExecutable = 3,
+    /// There is not enough memory or a memory limit was reached.
+    NoMemory = 4,
+    /// The process lacks a capability needed for the operation.
+    PermissionDenied = 5,
+    /// An argument is invalid.
+    InvalidArgument = 6,
+    /// A resource limit other than a memory limit was reached.
+    LimitExceeded = 7,
+    /// The device was removed.
+    NoDevice = 8
+}
+
+impl SyscallError {
+    /// Returns the value the syscall returns for this error.
+    pub fn as_return_value(self) -> isize {
+        -(self as isize)
+    }
+
+    /// Returns true if the value is a success value or the value of a known
+    /// error.
+    pub fn is_valid_return_value(value: isize) -> bool {
+        // `NoDevice` is the last error.
+        value >= SyscallError::NoDevice.as_return_value()
+    }
+}
+
+impl From<ElfError> for SyscallError {
+    fn from(error: ElfError) -> SyscallError {
+        match error {
+            ElfError::FileNotExistant => SyscallError::NotFound,
+            ElfError::AddressSpaceLimitExceeded => SyscallError::NoMemory,
+            ElfError::InvalidSignature => SyscallError::PermissionDenied,
+            _ => SyscallError::InvalidExecutable
+        }
+    }
+}
+
+impl From<FileError> for SyscallError {
+    fn from(error: FileError) -> SyscallError {
+        match error {
+            FileError::FileNotFound => SyscallError::NotFound,
+            FileError::DeviceRemoved => SyscallError::NoDevice,
+            FileError::InvalidArgument => SyscallError::InvalidArgument,
+            _ => SyscallError::Unspecified
+        }
+    }
+}
+
+impl From<ManifestError> for SyscallError {
+    fn from(error: ManifestError) -> SyscallError {
+        match error {
+            ManifestError::FileNotExistant => SyscallError::NotFound,
+            _ => SyscallError::InvalidArgument
+        }
+    }
+}
+
+impl From<HotplugError> for SyscallError {
+    fn from(error: HotplugError) -> SyscallError {
+        match error {
+            HotplugError::InvalidCpu => SyscallError::InvalidArgument,
+            HotplugError::NotResponding => SyscallError::NoDevice
+        }
+    }
+}
+
+/// Converts the result of a syscall to its return value.
+pub fn to_return_value(result: Result<usize, SyscallError>) -> isize {
+    match result {
+        Ok(value) => {
+            assert!(value as isize >= 0, "Syscall result too large.");
+            value as isize
+        },
+        Err(error) => error.as_return_value()
+    }
+}
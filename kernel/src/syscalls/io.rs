@@ -0,0 +1,609 @@
+//! This module handles the system calls that deal with file descriptors.
+
+use super::error::{to_return_value, SyscallError};
+use super::{is_valid_user_area, user_slice, wait_until};
+use core::cmp::min;
+use core::slice;
+use core::time::Duration;
+use event_queue::{EventQueue, ReadyEvent};
+use file_handle::{FileError, OpenFlags, PollEvents, SeekFrom};
+use memory::VirtualAddress;
+use multitasking::descriptor_table::{Descriptor, FileDescriptor, OpenFile};
+use multitasking::get_current_process;
+use multitasking::signal::{self, SignalSet};
+use timer::Timer;
+use vfs;
+
+/// The size of the kernel buffer used to move data between files.
+const TRANSFER_CHUNK_SIZE: usize = 512;
+
+/// The maximum size of the argument of a control request.
+const MAX_CONTROL_ARGUMENT_SIZE: usize = 64;
+
+/// The layout of a single entry passed to the poll syscall.
+#[repr(C)]
+struct PollEntry {
+    /// The file descriptor to check.
+    descriptor: u64,
+    /// The events that are waited for.
+    events: u16,
+    /// The events that are ready.
+    ready_events: u16
+}
+
+/// Seek relative to the start of the file.
+const SEEK_START: usize = 0;
+
+/// Seek relative to the current position.
+const SEEK_CURRENT: usize = 1;
+
+/// Seek relative to the end of the file.
+const SEEK_END: usize = 2;
+
+/// The operations that can be performed on an event queue.
+#[derive(Debug, PartialEq)]
+enum EventQueueOperation {
+    /// Registers a new descriptor.
+    Add,
+    /// Changes the registration of a descriptor.
+    Modify,
+    /// Removes the registration of a descriptor.
+    Delete
+}
+
+impl EventQueueOperation {
+    /// Converts the raw syscall argument to an operation.
+    fn from_raw(operation: usize) -> Option<EventQueueOperation> {
+        match operation {
+            0 => Some(EventQueueOperation::Add),
+            1 => Some(EventQueueOperation::Modify),
+            2 => Some(EventQueueOperation::Delete),
+            _ => None
+        }
+    }
+}
+
+pub fn open(name_ptr: VirtualAddress, name_length: usize, flags: u32) -> isize {
+    cover!(open);
+
+    to_return_value(open_file(name_ptr, name_length, flags))
+}
+
+/// Opens the file with the given name and returns its descriptor.
+fn open_file(
+    name_ptr: VirtualAddress,
+    name_length: usize,
+    flags: u32
+) -> Result<usize, SyscallError> {
+    if !is_valid_user_area(name_ptr, name_length) {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let flags = OpenFlags::from_bits(flags).ok_or(SyscallError::InvalidArgument)?;
+    let name = from_raw_str!(name_ptr, name_length).map_err(|_| SyscallError::InvalidArgument)?;
+    let path = get_current_process()
+        .resolve_path(name)
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    let handle = vfs::open(&path)?;
+    let mut pcb = get_current_process();
+
+    if !pcb.can_open_file() {
+        return Err(SyscallError::LimitExceeded);
+    }
+
+    let descriptor = pcb
+        .descriptors
+        .insert(Descriptor::File(OpenFile::new(handle, flags)));
+
+    Ok(descriptor as usize)
+}
+
+pub fn poll(entries_ptr: VirtualAddress, entry_count: usize, timeout_ms: isize) -> isize {
+    cover!(poll);
+
+    let entries: &mut [PollEntry] = match unsafe { user_slice(entries_ptr, entry_count) } {
+        Some(entries) => entries,
+        None => return SyscallError::InvalidArgument.as_return_value()
+    };
+
+    wait_until(timeout_ms, |wait| {
+        let mut pcb = get_current_process();
+        let pending_signals = pcb.pending_signals();
+        let mut ready_count = 0;
+
+        for entry in entries.iter_mut() {
+            let requested_events = PollEvents::from_bits_truncate(entry.events);
+            let descriptor = entry.descriptor as FileDescriptor;
+
+            if let Some(queue) = pcb.descriptors.wait_queue(descriptor) {
+                wait.on(queue);
+            }
+
+            let ready_events = match pcb.descriptors.poll(descriptor, pending_signals) {
+                Some(events) => events & requested_events,
+                None => return Some(SyscallError::InvalidArgument.as_return_value())
+            };
+
+            entry.ready_events = ready_events.bits();
+
+            if !ready_events.is_empty() {
+                ready_count += 1;
+            }
+        }
+
+        if ready_count > 0 {
+            Some(ready_count)
+        } else {
+            None
+        }
+    })
+}
+
+pub fn evq_create() -> isize {
+    cover!(evq_create);
+
+    let mut pcb = get_current_process();
+
+    if !pcb.can_open_file() {
+        return SyscallError::LimitExceeded.as_return_value();
+    }
+
+    let descriptor = pcb
+        .descriptors
+        .insert(Descriptor::EventQueue(EventQueue::new()));
+
+    descriptor as isize
+}
+
+pub fn evq_ctl(
+    queue: FileDescriptor,
+    operation: usize,
+    descriptor: FileDescriptor,
+    events: u16,
+    edge_triggered: bool,
+    user_data: u64
+) -> isize {
+    cover!(evq_ctl);
+
+    let operation = match EventQueueOperation::from_raw(operation) {
+        Some(operation) => operation,
+        None => return SyscallError::InvalidArgument.as_return_value()
+    };
+    let events = PollEvents::from_bits_truncate(events);
+
+    let mut pcb = get_current_process();
+    let pending_signals = pcb.pending_signals();
+
+    if operation != EventQueueOperation::Delete
+        && pcb.descriptors.poll(descriptor, pending_signals).is_none()
+    {
+        return SyscallError::InvalidArgument.as_return_value();
+    }
+
+    let queue = match pcb.descriptors.event_queue_mut(queue) {
+        Some(queue) => queue,
+        None => return SyscallError::InvalidArgument.as_return_value()
+    };
+
+    let success = match operation {
+        EventQueueOperation::Add => queue.register(descriptor, events, edge_triggered, user_data),
+        EventQueueOperation::Modify => queue.modify(descriptor, events, edge_triggered, user_data),
+        EventQueueOperation::Delete => queue.unregister(descriptor)
+    };
+
+    if success {
+        0
+    } else {
+        SyscallError::InvalidArgument.as_return_value()
+    }
+}
+
+pub fn evq_wait(
+    queue: FileDescriptor,
+    events_ptr: VirtualAddress,
+    max_events: usize,
+    timeout_ms: isize
+) -> isize {
+    cover!(evq_wait);
+
+    let events: &mut [ReadyEvent] = match unsafe { user_slice(events_ptr, max_events) } {
+        Some(events) => events,
+        None => return SyscallError::InvalidArgument.as_return_value()
+    };
+
+    wait_until(timeout_ms, |wait| {
+        let mut pcb = get_current_process();
+        let pending_signals = pcb.pending_signals();
+
+        match pcb.descriptors.event_queue_mut(queue) {
+            Some(event_queue) => wait.on(event_queue.wait_queue()),
+            None => return Some(SyscallError::InvalidArgument.as_return_value())
+        }
+
+        match pcb.descriptors.collect_events(queue, events, pending_signals) {
+            Some(0) => None,
+            Some(count) => Some(count as isize),
+            None => Some(SyscallError::InvalidArgument.as_return_value())
+        }
+    })
+}
+
+/// Moves up to `count` bytes from the input to the output file within the
+/// kernel.
+///
+/// Returns the number of bytes that were moved, which is zero at the end of
+/// the input. A chunk that was read but couldn't be written is lost, the
+/// bytes moved before it are returned then.
+pub fn sendfile(
+    out_descriptor: FileDescriptor,
+    in_descriptor: FileDescriptor,
+    count: usize
+) -> isize {
+    cover!(sendfile);
+
+    // Inputs that have an end stop there instead of failing.
+    let count = match remaining(in_descriptor) {
+        Some(remaining) => min(count as u64, remaining) as usize,
+        None => count
+    };
+
+    // The process is only locked for a single chunk at a time, so the other
+    // threads can use it while one of the files blocks.
+    let mut chunk = [0u8; TRANSFER_CHUNK_SIZE];
+    let mut transferred = 0;
+
+    while transferred < count {
+        let chunk_size = min(count - transferred, TRANSFER_CHUNK_SIZE);
+
+        let read = read_chunk(in_descriptor, &mut chunk[..chunk_size]);
+        let result = if read < 0 {
+            read
+        } else {
+            write_chunk(out_descriptor, &chunk[..read as usize])
+        };
+
+        if result < 0 {
+            if transferred == 0 {
+                return result;
+            }
+
+            break;
+        }
+
+        transferred += read as usize;
+
+        // Inputs without an end stop once they have no more data for now.
+        if (read as usize) < chunk_size {
+            break;
+        }
+    }
+
+    transferred as isize
+}
+
+pub fn read(descriptor: FileDescriptor, buffer_ptr: VirtualAddress, length: usize) -> isize {
+    cover!(read);
+
+    if !is_valid_user_area(buffer_ptr, length) {
+        return SyscallError::InvalidArgument.as_return_value();
+    }
+
+    // Reads from files that have an end stop there instead of failing.
+    let length = match remaining(descriptor) {
+        Some(remaining) => min(length as u64, remaining) as usize,
+        None => length
+    };
+
+    // The data passes through a kernel buffer, so that the process isn't
+    // locked while user pages are faulted in.
+    let mut chunk = [0u8; TRANSFER_CHUNK_SIZE];
+    let mut transferred = 0;
+
+    while transferred < length {
+        let chunk_size = min(length - transferred, TRANSFER_CHUNK_SIZE);
+
+        let result = read_chunk(descriptor, &mut chunk[..chunk_size]);
+        if result < 0 {
+            if transferred == 0 {
+                return result;
+            }
+
+            break;
+        }
+
+        let read = result as usize;
+
+        unsafe {
+            let destination: *mut u8 = (buffer_ptr + transferred).as_mut_ptr();
+            slice::from_raw_parts_mut(destination, read).copy_from_slice(&chunk[..read]);
+        }
+
+        transferred += read;
+
+        // Files without an end return what they have instead of waiting until
+        // the buffer is full.
+        if read < chunk_size {
+            break;
+        }
+    }
+
+    transferred as isize
+}
+
+/// Returns the number of bytes after the seek position of the file.
+///
+/// Returns `None` for files without a seek position, such as the console.
+fn remaining(descriptor: FileDescriptor) -> Option<u64> {
+    get_current_process()
+        .descriptors
+        .file_mut(descriptor)
+        .and_then(|file| file.handle.remaining().ok())
+}
+
+/// Reads into the chunk from the file, waiting while the file has no data.
+///
+/// Returns the number of read bytes, which is less than the size of the chunk
+/// if the file had no more data, or the error value of the syscall.
+fn read_chunk(descriptor: FileDescriptor, chunk: &mut [u8]) -> isize {
+    wait_until(-1, |wait| {
+        let mut pcb = get_current_process();
+        let file = match pcb.descriptors.file_mut(descriptor) {
+            Some(file) => file,
+            None => return Some(SyscallError::InvalidArgument.as_return_value())
+        };
+
+        if let Some(queue) = file.handle.wait_queue() {
+            wait.on(queue);
+        }
+
+        match file.handle.read_available(chunk) {
+            Ok(read) => Some(read as isize),
+            Err(FileError::WouldBlock) if !file.is_nonblocking() => None,
+            Err(error) => Some(SyscallError::from(error).as_return_value())
+        }
+    })
+}
+
+pub fn write(descriptor: FileDescriptor, buffer_ptr: VirtualAddress, length: usize) -> isize {
+    cover!(write);
+
+    if !is_valid_user_area(buffer_ptr, length) {
+        return SyscallError::InvalidArgument.as_return_value();
+    }
+
+    let mut chunk = [0u8; TRANSFER_CHUNK_SIZE];
+    let mut transferred = 0;
+
+    while transferred < length {
+        let chunk_size = min(length - transferred, TRANSFER_CHUNK_SIZE);
+
+        unsafe {
+            let source: *const u8 = (buffer_ptr + transferred).as_ptr();
+            chunk[..chunk_size].copy_from_slice(slice::from_raw_parts(source, chunk_size));
+        }
+
+        let result = write_chunk(descriptor, &chunk[..chunk_size]);
+        if result < 0 {
+            if transferred == 0 {
+                return result;
+            }
+
+            break;
+        }
+
+        transferred += chunk_size;
+    }
+
+    transferred as isize
+}
+
+/// Writes the chunk to the file, waiting while the file would block.
+///
+/// Returns 0 on success and the error value of the syscall otherwise.
+fn write_chunk(descriptor: FileDescriptor, chunk: &[u8]) -> isize {
+    wait_until(-1, |wait| {
+        let mut pcb = get_current_process();
+        let file = match pcb.descriptors.file_mut(descriptor) {
+            Some(file) => file,
+            None => return Some(SyscallError::InvalidArgument.as_return_value())
+        };
+
+        if let Some(queue) = file.handle.wait_queue() {
+            wait.on(queue);
+        }
+
+        match file.handle.write(chunk) {
+            Ok(()) => Some(0),
+            Err(FileError::WouldBlock) if !file.is_nonblocking() => None,
+            Err(error) => Some(SyscallError::from(error).as_return_value())
+        }
+    })
+}
+
+/// Closes the descriptor.
+pub fn close(descriptor: FileDescriptor) -> Result<usize, SyscallError> {
+    cover!(close);
+
+    // The object is dropped after the process is unlocked again.
+    let object = get_current_process().descriptors.remove(descriptor);
+
+    match object {
+        Some(_) => Ok(0),
+        None => Err(SyscallError::InvalidArgument)
+    }
+}
+
+/// Moves the seek position of the file and returns the new position.
+///
+/// `whence` is one of `SEEK_START`, `SEEK_CURRENT` and `SEEK_END`.
+pub fn seek(descriptor: FileDescriptor, offset: i64, whence: usize) -> Result<usize, SyscallError> {
+    cover!(seek);
+
+    let position = match whence {
+        SEEK_START if offset >= 0 => SeekFrom::Start(offset as u64),
+        SEEK_CURRENT => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return Err(SyscallError::InvalidArgument)
+    };
+
+    let mut pcb = get_current_process();
+    let file = pcb
+        .descriptors
+        .file_mut(descriptor)
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    match file.handle.seek(position) {
+        Ok(position) => Ok(position as usize),
+        Err(FileError::NotSupported)
+        | Err(FileError::SeekBeforeStart)
+        | Err(FileError::SeekPastEnd) => Err(SyscallError::InvalidArgument),
+        Err(error) => Err(SyscallError::from(error))
+    }
+}
+
+/// Performs a request specific to the kind of the file, like `ioctl` on
+/// Unix.
+///
+/// The argument is copied to the kernel for the file and back afterwards, so
+/// the file doesn't access user memory while the process is locked.
+pub fn control(
+    descriptor: FileDescriptor,
+    request: u32,
+    argument_ptr: VirtualAddress,
+    argument_length: usize
+) -> Result<usize, SyscallError> {
+    cover!(control);
+
+    if argument_length > MAX_CONTROL_ARGUMENT_SIZE
+        || !is_valid_user_area(argument_ptr, argument_length)
+    {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let mut buffer = [0u8; MAX_CONTROL_ARGUMENT_SIZE];
+    let argument = &mut buffer[..argument_length];
+
+    unsafe {
+        let source: *const u8 = argument_ptr.as_ptr();
+        argument.copy_from_slice(slice::from_raw_parts(source, argument_length));
+    }
+
+    {
+        let mut pcb = get_current_process();
+        let file = pcb
+            .descriptors
+            .file_mut(descriptor)
+            .ok_or(SyscallError::InvalidArgument)?;
+
+        match file.handle.control(request, argument) {
+            Ok(()) => (),
+            Err(FileError::NotSupported) => return Err(SyscallError::InvalidArgument),
+            Err(error) => return Err(SyscallError::from(error))
+        }
+    }
+
+    unsafe {
+        let destination: *mut u8 = argument_ptr.as_mut_ptr();
+        slice::from_raw_parts_mut(destination, argument_length).copy_from_slice(argument);
+    }
+
+    Ok(0)
+}
+
+pub fn timer_create() -> isize {
+    cover!(timer_create);
+
+    let mut pcb = get_current_process();
+
+    if !pcb.can_open_file() {
+        return SyscallError::LimitExceeded.as_return_value();
+    }
+
+    let descriptor = pcb.descriptors.insert(Descriptor::Timer(Timer::new()));
+
+    descriptor as isize
+}
+
+pub fn timer_set(descriptor: FileDescriptor, initial_ms: usize, interval_ms: usize) -> isize {
+    cover!(timer_set);
+
+    let to_duration = |ms: usize| {
+        if ms == 0 {
+            None
+        } else {
+            Some(Duration::from_millis(ms as u64))
+        }
+    };
+
+    match get_current_process().descriptors.timer_mut(descriptor) {
+        Some(timer) => {
+            if timer.set(to_duration(initial_ms), to_duration(interval_ms)) {
+                0
+            } else {
+                SyscallError::InvalidArgument.as_return_value()
+            }
+        },
+        None => SyscallError::InvalidArgument.as_return_value()
+    }
+}
+
+pub fn timer_read(descriptor: FileDescriptor, timeout_ms: isize) -> isize {
+    cover!(timer_read);
+
+    wait_until(timeout_ms, |wait| {
+        match get_current_process().descriptors.timer_mut(descriptor) {
+            Some(timer) => {
+                wait.on(timer.wait_queue());
+
+                match timer.take_expirations() {
+                    0 => None,
+                    expirations => Some(min(expirations, isize::max_value() as u64) as isize)
+                }
+            },
+            None => Some(SyscallError::InvalidArgument.as_return_value())
+        }
+    })
+}
+
+pub fn signal_descriptor_create(mask: u64) -> isize {
+    cover!(signal_descriptor_create);
+
+    let mask = match SignalSet::from_bits(mask) {
+        Some(mask) if !mask.is_empty() => mask,
+        _ => return SyscallError::InvalidArgument.as_return_value()
+    };
+
+    let mut pcb = get_current_process();
+
+    if !pcb.can_open_file() {
+        return SyscallError::LimitExceeded.as_return_value();
+    }
+
+    let descriptor = pcb.descriptors.insert(Descriptor::Signals(mask));
+
+    descriptor as isize
+}
+
+pub fn signal_read(descriptor: FileDescriptor, timeout_ms: isize) -> isize {
+    cover!(signal_read);
+
+    wait_until(timeout_ms, |wait| {
+        wait.on(&signal::RAISED);
+
+        let mut pcb = get_current_process();
+
+        match pcb.descriptors.signal_mask(descriptor) {
+            Some(mask) => {
+                let signals = pcb.take_signals(mask);
+
+                if signals.is_empty() {
+                    None
+                } else {
+                    Some(signals.bits() as isize)
+                }
+            },
+            None => Some(SyscallError::InvalidArgument.as_return_value())
+        }
+    })
+}
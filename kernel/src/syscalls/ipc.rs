@@ -0,0 +1,335 @@
+//! This module handles the system calls for communication between processes.
+
+use super::error::SyscallError;
+use super::{is_valid_user_area, user_slice, wait_until};
+use alloc::arc::Arc;
+use core::cmp::min;
+use core::mem::size_of;
+use core::ptr;
+use ipc::grant::{self, Grant, MAX_GRANT_PAGES};
+use ipc::message::{self, MessageError, MAX_MESSAGE_SIZE};
+use ipc::ring::{self, Ring, RingEndpoint, Side, HEADER_PAGES};
+use memory::{Address, MemoryArea, VirtualAddress, PAGE_SIZE, READABLE, USER_ACCESSIBLE,
+             WRITABLE};
+use multitasking::descriptor_table::Descriptor;
+use multitasking::pid_namespace;
+use multitasking::{get_current_process, get_process, ProcessID, CURRENT_THREAD};
+
+/// The grant flag that replaces the granted pages with zeroed pages.
+const GRANT_DONATE: usize = 1 << 0;
+
+/// The grant flag that maps the pages read only for the receiver.
+const GRANT_READ_ONLY: usize = 1 << 1;
+
+/// The layout of the information about a ring passed to userspace.
+#[repr(C)]
+struct RingInfo {
+    /// The descriptor referring to the ring.
+    descriptor: u64,
+    /// The address of the header with the indices.
+    header_address: u64,
+    /// The address of the data area.
+    data_address: u64,
+    /// The size of the data area in bytes.
+    data_size: u64,
+    /// The ID of the process on the other side of the ring.
+    peer: u64
+}
+
+/// The layout of the information about a received message passed to
+/// userspace.
+#[repr(C)]
+struct MessageInfo {
+    /// The ID of the process that sent the message.
+    sender: u64,
+    /// The length of the message, which may be more than was copied.
+    length: u64
+}
+
+/// Maps the ring into the current process and creates a descriptor for it.
+///
+/// `peer` is the global ID of the process on the other side of the ring.
+fn open_ring(ring: Arc<Ring>, side: Side, peer: ProcessID, info_ptr: VirtualAddress) -> bool {
+    let mut pcb = get_current_process();
+
+    if !pcb.can_open_file() {
+        return false;
+    }
+
+    let address = match pcb
+        .address_space
+        .map_shared_memory(ring.memory(), READABLE | WRITABLE | USER_ACCESSIBLE)
+    {
+        Some(address) => address,
+        None => return false
+    };
+
+    let data_size = ring.data_size();
+    let descriptor = pcb
+        .descriptors
+        .insert(Descriptor::Ring(RingEndpoint::new(ring, side, address)));
+
+    // A peer outside of the namespace is reported as 0.
+    let peer = pid_namespace::to_local(pcb.pid_namespace, peer).unwrap_or(0);
+
+    unsafe {
+        *info_ptr.as_mut_ptr() = RingInfo {
+            descriptor: descriptor as u64,
+            header_address: address.as_usize() as u64,
+            data_address: (address + HEADER_PAGES * PAGE_SIZE).as_usize() as u64,
+            data_size: data_size as u64,
+            peer: peer as u64
+        };
+    }
+
+    true
+}
+
+pub fn ring_create(data_pages: usize, peer: usize, info_ptr: VirtualAddress) -> isize {
+    cover!(ring_create);
+
+    if !is_valid_user_area(info_ptr, size_of::<RingInfo>()) {
+        return -1;
+    }
+
+    let creator = CURRENT_THREAD.lock().pid;
+    let namespace = get_current_process().pid_namespace;
+
+    let peer = match pid_namespace::to_global(namespace, peer) {
+        Some(peer) => peer,
+        None => return -1
+    };
+
+    if peer == creator || get_process(peer).map_or(true, |pcb| pcb.is_dead()) {
+        return -1;
+    }
+
+    let ring = match Ring::new(data_pages) {
+        Some(ring) => Arc::new(ring),
+        None => return -1
+    };
+
+    if !open_ring(ring.clone(), Side::Creator, peer, info_ptr) {
+        return -1;
+    }
+
+    ring::offer(creator, peer, ring);
+
+    0
+}
+
+pub fn ring_accept(info_ptr: VirtualAddress, timeout_ms: isize) -> isize {
+    cover!(ring_accept);
+
+    if !is_valid_user_area(info_ptr, size_of::<RingInfo>()) {
+        return -1;
+    }
+
+    let pid = CURRENT_THREAD.lock().pid;
+
+    wait_until(timeout_ms, |wait| {
+        wait.on(&ring::OFFERED);
+
+        ring::take_offer(pid).map(|offer| {
+            if open_ring(offer.ring, Side::Acceptor, offer.creator, info_ptr) {
+                1
+            } else {
+                -1
+            }
+        })
+    })
+}
+
+pub fn ring_notify(descriptor: usize) -> isize {
+    cover!(ring_notify);
+
+    match get_current_process().descriptors.ring(descriptor) {
+        Some(endpoint) => {
+            endpoint.notify();
+            0
+        },
+        None => -1
+    }
+}
+
+pub fn ring_wait(descriptor: usize, timeout_ms: isize) -> isize {
+    cover!(ring_wait);
+
+    wait_until(timeout_ms, |wait| {
+        match get_current_process().descriptors.ring(descriptor) {
+            Some(endpoint) => {
+                wait.on(endpoint.wait_queue());
+
+                if endpoint.take_notification() {
+                    Some(1)
+                } else {
+                    None
+                }
+            },
+            None => Some(-1)
+        }
+    })
+}
+
+pub fn grant_pages(receiver: usize, address: VirtualAddress, pages: usize, flags: usize) -> isize {
+    cover!(grant_pages);
+
+    if flags & !(GRANT_DONATE | GRANT_READ_ONLY) != 0 || pages == 0 || pages > MAX_GRANT_PAGES {
+        return -1;
+    }
+
+    let granter = CURRENT_THREAD.lock().pid;
+    let namespace = get_current_process().pid_namespace;
+
+    let receiver = match pid_namespace::to_global(namespace, receiver) {
+        Some(receiver) => receiver,
+        None => return -1
+    };
+
+    if receiver == granter || get_process(receiver).map_or(true, |pcb| pcb.is_dead()) {
+        return -1;
+    }
+
+    let area = MemoryArea::new(address, pages * PAGE_SIZE);
+    let frames = match get_current_process()
+        .address_space
+        .take_frame_references(area, flags & GRANT_DONATE != 0)
+    {
+        Some(frames) => frames,
+        None => return -1
+    };
+
+    let writable = flags & GRANT_READ_ONLY == 0;
+
+    grant::insert(Grant::new(granter, receiver, writable, frames)) as isize
+}
+
+pub fn accept_grant(id: usize) -> isize {
+    cover!(accept_grant);
+
+    let receiver = CURRENT_THREAD.lock().pid;
+
+    let mut grant = match grant::take(id, receiver) {
+        Some(grant) => grant,
+        None => return -1
+    };
+
+    let flags = if grant.writable {
+        READABLE | WRITABLE | USER_ACCESSIBLE
+    } else {
+        READABLE | USER_ACCESSIBLE
+    };
+
+    let address = get_current_process()
+        .address_space
+        .map_frames(grant.frames(), flags);
+
+    match address {
+        Some(address) => {
+            // The references of the grant now belong to the mappings.
+            grant.forget_frames();
+            address.as_usize() as isize
+        },
+        None => -1
+    }
+}
+
+pub fn endpoint_create() -> isize {
+    cover!(endpoint_create);
+
+    let pid = CURRENT_THREAD.lock().pid;
+
+    match message::create_endpoint(pid) {
+        Some(endpoint) => endpoint as isize,
+        None => SyscallError::LimitExceeded.as_return_value()
+    }
+}
+
+pub fn message_send(
+    receiver: usize,
+    endpoint: usize,
+    data_ptr: VirtualAddress,
+    length: usize,
+    timeout_ms: isize
+) -> isize {
+    cover!(message_send);
+
+    let data = match unsafe { user_slice::<u8>(data_ptr, length) } {
+        Some(data) if length <= MAX_MESSAGE_SIZE => data,
+        _ => return SyscallError::InvalidArgument.as_return_value()
+    };
+
+    let sender = CURRENT_THREAD.lock().pid;
+    let namespace = get_current_process().pid_namespace;
+
+    let receiver = match pid_namespace::to_global(namespace, receiver) {
+        Some(receiver) => receiver,
+        None => return SyscallError::InvalidArgument.as_return_value()
+    };
+
+    // The message is copied while the process is not locked, because user
+    // pages may have to be faulted in.
+    let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+    buffer[..length].copy_from_slice(data);
+
+    wait_until(timeout_ms, |wait| {
+        match message::send(sender, receiver, endpoint, &buffer[..length], wait) {
+            Ok(()) => Some(1),
+            Err(MessageError::QueueFull) => None,
+            Err(MessageError::UnknownEndpoint) => {
+                Some(SyscallError::InvalidArgument.as_return_value())
+            },
+        }
+    })
+}
+
+pub fn message_receive(
+    endpoint: usize,
+    buffer_ptr: VirtualAddress,
+    length: usize,
+    info_ptr: VirtualAddress,
+    timeout_ms: isize
+) -> isize {
+    cover!(message_receive);
+
+    if !is_valid_user_area(buffer_ptr, length)
+        || !is_valid_user_area(info_ptr, size_of::<MessageInfo>())
+    {
+        return SyscallError::InvalidArgument.as_return_value();
+    }
+
+    let pid = CURRENT_THREAD.lock().pid;
+    let namespace = get_current_process().pid_namespace;
+
+    wait_until(timeout_ms, |wait| {
+        match message::receive(pid, endpoint, wait) {
+            Ok(Some(message)) => {
+                // A sender outside of the namespace is reported as 0.
+                let sender = pid_namespace::to_local(namespace, message.sender).unwrap_or(0);
+                let copied = min(length, message.data.len());
+
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        message.data.as_ptr(),
+                        buffer_ptr.as_mut_ptr(),
+                        copied
+                    );
+                    ptr::write_unaligned(
+                        info_ptr.as_mut_ptr(),
+                        MessageInfo {
+                            sender: sender as u64,
+                            length: message.data.len() as u64
+                        }
+                    );
+                }
+
+                Some(1)
+            },
+            Ok(None) => None,
+            Err(MessageError::UnknownEndpoint) => {
+                Some(SyscallError::InvalidArgument.as_return_value())
+            },
+            Err(MessageError::QueueFull) => unreachable!("Receiving doesn't fill the queue.")
+        }
+    })
+}
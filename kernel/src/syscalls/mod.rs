@@ -1,12 +1,87 @@
 //! This module handles system calls.
 
-use arch::schedule;
+mod error;
+mod io;
+mod ipc;
+mod trace;
+
+use self::error::{to_return_value, SyscallError};
+use self::io::{close, control, evq_create, evq_ctl, evq_wait, open, poll, read, seek, sendfile,
+               signal_descriptor_create, signal_read, timer_create, timer_read, timer_set, write};
+use self::ipc::{accept_grant, endpoint_create, grant_pages, message_receive, message_send,
+                ring_accept, ring_create, ring_notify, ring_wait};
+use self::trace::{trace_attach, trace_clear_watchpoint, trace_detach, trace_read_memory,
+                  trace_resume, trace_set_registers, trace_set_watchpoint, trace_wait,
+                  trace_write_memory};
+use alloc::Vec;
+use arch::{self, schedule, Architecture};
+use boot;
+use core::cmp::{max, min};
+use core::fmt::Write;
+use core::mem::{align_of, size_of};
+use core::ptr;
+use core::slice;
 use core::time::Duration;
+use cpufreq::{self, Governor};
 use elf;
-use memory::{Address, MemoryArea, VirtualAddress};
-use multitasking::scheduler::READY_LIST;
-use multitasking::{get_current_process, CURRENT_THREAD, TCB};
-use sync::time::Timestamp;
+use memory::numa::{self, NodeInfo};
+use memory::{Address, AddressSpace, MemoryArea, PhysicalAddress, VirtualAddress, NO_CACHE,
+             PAGE_SIZE, READABLE, USER_ACCESSIBLE, WRITABLE, WRITE_COMBINING};
+use multitasking::capabilities::{Capabilities, CPU_HOTPLUG, POWER, RAISE_PRIORITY,
+                                 REGISTER_SERVICE, SET_ROOT, SPAWN_SERVER};
+use multitasking::futex;
+use multitasking::hotplug;
+use multitasking::limits::{Limit, Resource};
+use multitasking::name::{Name, MAX_NAME_LENGTH};
+use multitasking::pid_namespace;
+use multitasking::ready_queue;
+use multitasking::resource_group::{self, GroupID};
+use multitasking::scheduler::{self, READY_LIST};
+use multitasking::service::{self, Service};
+use multitasking::trace::{self, Registers, StopReason};
+use multitasking::wait_queue::Wait;
+use multitasking::{self, get_current_process, ExitStatus, Inheritance, ProcessID, ThreadID,
+                   CURRENT_THREAD, TCB};
+use random;
+use server::Manifest;
+use sync::time::{self, Timestamp};
+use testing;
+
+/// The exec flag that creates a new process ID namespace for the new process.
+const EXEC_NEW_PID_NAMESPACE: usize = 1 << 0;
+
+/// The exec flag that keeps the new process from using huge pages.
+const EXEC_NO_HUGE_PAGES: usize = 1 << 1;
+
+/// The clock that counts the time since boot.
+const MONOTONIC_CLOCK: usize = 0;
+
+/// The clock that counts the time since the Unix epoch.
+const SYSTEM_CLOCK: usize = 1;
+
+/// The cache mode that maps device memory uncached.
+const MMIO_UNCACHED: usize = 0;
+
+/// The cache mode that maps device memory write-combining.
+const MMIO_WRITE_COMBINING: usize = 1;
+
+/// The layout of a time as passed to userspace.
+#[repr(C)]
+struct ClockTime {
+    /// The whole seconds.
+    seconds: u64,
+    /// The nanoseconds within the second.
+    nanoseconds: u64
+}
+
+/// The layout of how a child process ended as passed to userspace.
+#[repr(C)]
+struct WaitStatus {
+    /// 1 if the process exited and 0 if it was killed.
+    exited: u32,
+    /// The code the process exited with.
+    code: i32
+}
 
 /// This function accepts the syscalls and calls the corresponding handlers.
 pub fn syscall_handler(
@@ -17,15 +92,88 @@ pub fn syscall_handler(
     arg4: usize,
     arg5: usize,
     arg6: usize
+) -> isize {
+    if trace::reports_syscalls() {
+        let mut registers = Registers {
+            syscall_number: num as usize,
+            syscall_arguments: [arg1, arg2, arg3, arg4, arg5, arg6],
+            ..Default::default()
+        };
+
+        trace::stop(StopReason::Syscall, &mut registers);
+
+        let num = if registers.syscall_number <= u16::max_value() as usize {
+            registers.syscall_number as u16
+        } else {
+            unknown_syscall(u16::max_value());
+        };
+        let arguments = registers.syscall_arguments;
+
+        let result = dispatch(
+            num,
+            arguments[0],
+            arguments[1],
+            arguments[2],
+            arguments[3],
+            arguments[4],
+            arguments[5]
+        );
+
+        check_syscall_exit(num, &arguments, result);
+
+        result
+    } else {
+        let result = dispatch(num, arg1, arg2, arg3, arg4, arg5, arg6);
+
+        check_syscall_exit(num, &[arg1, arg2, arg3, arg4, arg5, arg6], result);
+
+        result
+    }
+}
+
+/// Checks invariants that must hold whenever a syscall returns in test mode.
+///
+/// The syscall fuzzer relies on these checks to find syscalls that mishandle
+/// invalid arguments.
+fn check_syscall_exit(num: u16, arguments: &[usize; 6], result: isize) {
+    if !testing::is_enabled() {
+        return;
+    }
+
+    assert!(
+        SyscallError::is_valid_return_value(result),
+        "Syscall {} with the arguments {:?} returned the unknown error {}.",
+        num,
+        arguments,
+        result
+    );
+    assert!(
+        CURRENT_THREAD.try_lock().is_some(),
+        "Syscall {} with the arguments {:?} returned with the current thread locked.",
+        num,
+        arguments
+    );
+}
+
+/// Calls the handler of the given syscall.
+fn dispatch(
+    num: u16,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    arg4: usize,
+    arg5: usize,
+    arg6: usize
 ) -> isize {
     match num {
         0 => print_char(arg1 as u8 as char),
-        1 => kill_process(),
+        1 => exit_process(0),
         2 => return_pid(),
-        3 => exec(VirtualAddress::from_usize(arg1), arg2),
+        3 => exec(VirtualAddress::from_usize(arg1), arg2, arg3),
         4 => sleep(arg1, arg2),
         5 => create_thread(
             VirtualAddress::from_usize(arg1),
+            AddressSpace::user_stack_size(),
             arg2,
             arg3,
             arg4,
@@ -33,99 +181,690 @@ pub fn syscall_handler(
             arg6
         ),
         6 => kill_thread(),
+        7 => open(VirtualAddress::from_usize(arg1), arg2, arg3 as u32),
+        8 => poll(VirtualAddress::from_usize(arg1), arg2, arg3 as isize),
+        9 => evq_create(),
+        10 => evq_ctl(arg1, arg2, arg3, arg4 as u16, arg5 != 0, arg6 as u64),
+        11 => evq_wait(arg1, VirtualAddress::from_usize(arg2), arg3, arg4 as isize),
+        12 => sendfile(arg1, arg2, arg3),
+        13 => get_resource_limit(arg1, VirtualAddress::from_usize(arg2)),
+        14 => set_resource_limit(arg1, arg2, arg3),
+        15 => create_resource_group(VirtualAddress::from_usize(arg1), arg2, arg3, arg4, arg5 as u32),
+        16 => set_resource_group_limits(arg1, arg2, arg3 as u32),
+        17 => join_resource_group(arg1),
+        18 => set_root(VirtualAddress::from_usize(arg1), arg2),
+        19 => drop_capabilities(arg1 as u64),
+        20 => trace_attach(arg1, arg2),
+        21 => trace_detach(arg1),
+        22 => trace_wait(arg1, VirtualAddress::from_usize(arg2), arg3 as isize),
+        23 => trace_read_memory(
+            arg1,
+            VirtualAddress::from_usize(arg2),
+            VirtualAddress::from_usize(arg3),
+            arg4
+        ),
+        24 => trace_write_memory(
+            arg1,
+            VirtualAddress::from_usize(arg2),
+            VirtualAddress::from_usize(arg3),
+            arg4
+        ),
+        25 => trace_set_registers(arg1, VirtualAddress::from_usize(arg2)),
+        26 => trace_resume(arg1, arg2),
+        27 => trace_set_watchpoint(arg1, arg2, VirtualAddress::from_usize(arg3), arg4, arg5),
+        28 => trace_clear_watchpoint(arg1, arg2),
+        29 => spawn_server(VirtualAddress::from_usize(arg1), arg2),
+        30 => register_service(VirtualAddress::from_usize(arg1), arg2, arg3),
+        31 => lookup_service(
+            VirtualAddress::from_usize(arg1),
+            arg2,
+            VirtualAddress::from_usize(arg3)
+        ),
+        32 => ring_create(arg1, arg2, VirtualAddress::from_usize(arg3)),
+        33 => ring_accept(VirtualAddress::from_usize(arg1), arg2 as isize),
+        34 => ring_notify(arg1),
+        35 => ring_wait(arg1, arg2 as isize),
+        36 => grant_pages(arg1, VirtualAddress::from_usize(arg2), arg3, arg4),
+        37 => accept_grant(arg1),
+        38 => timer_create(),
+        39 => timer_set(arg1, arg2, arg3),
+        40 => timer_read(arg1, arg2 as isize),
+        41 => signal_descriptor_create(arg1 as u64),
+        42 => signal_read(arg1, arg2 as isize),
+        43 => check_page_tables(),
+        44 => set_thread_name(VirtualAddress::from_usize(arg1), arg2),
+        45 => join_thread(arg1, arg2 as isize),
+        46 => create_thread(
+            VirtualAddress::from_usize(arg1),
+            arg2,
+            arg3,
+            arg4,
+            arg5,
+            arg6,
+            0
+        ),
+        47 => dump_allocations(),
+        48 => get_random(VirtualAddress::from_usize(arg1), arg2),
+        49 => read(arg1, VirtualAddress::from_usize(arg2), arg3),
+        50 => write(arg1, VirtualAddress::from_usize(arg2), arg3),
+        51 => to_return_value(suspend()),
+        52 => to_return_value(set_governor(arg1)),
+        53 => to_return_value(get_node_info(arg1, VirtualAddress::from_usize(arg2))),
+        54 => endpoint_create(),
+        55 => message_send(
+            arg1,
+            arg2,
+            VirtualAddress::from_usize(arg3),
+            arg4,
+            arg5 as isize
+        ),
+        56 => message_receive(
+            arg1,
+            VirtualAddress::from_usize(arg2),
+            arg3,
+            VirtualAddress::from_usize(arg4),
+            arg5 as isize
+        ),
+        57 => exit_process(arg1 as i32),
+        58 => wait(arg1, VirtualAddress::from_usize(arg2), arg3 as isize),
+        59 => dump_lock_stats(),
+        60 => fork(),
+        61 => futex_wait(VirtualAddress::from_usize(arg1), arg2 as u32, arg3 as isize),
+        62 => to_return_value(futex_wake(VirtualAddress::from_usize(arg1), arg2)),
+        63 => to_return_value(get_clock(arg1, VirtualAddress::from_usize(arg2))),
+        64 => to_return_value(close(arg1)),
+        65 => to_return_value(seek(arg1, arg2 as i64, arg3)),
+        66 => dump_coverage(),
+        67 => to_return_value(set_priority(arg1 as i32)),
+        68 => to_return_value(map_device_memory(arg1, arg2, arg3)),
+        69 => to_return_value(control(
+            arg1,
+            arg2 as u32,
+            VirtualAddress::from_usize(arg3),
+            arg4
+        )),
+        70 => to_return_value(cpu_down(arg1)),
+        71 => to_return_value(cpu_up(arg1)),
         _ => unknown_syscall(num)
     }
 }
 
+fn check_page_tables() -> isize {
+    cover!(check_page_tables);
+
+    if !::config::PAGE_TABLE_CHECKS {
+        return -1;
+    }
+
+    ::memory::checks::check_all();
+
+    0
+}
+
+fn dump_allocations() -> isize {
+    cover!(dump_allocations);
+
+    if !::memory::tracking::dump() {
+        return -1;
+    }
+
+    0
+}
+
+fn dump_lock_stats() -> isize {
+    cover!(dump_lock_stats);
+
+    if !::sync::lock_stats::dump() {
+        return -1;
+    }
+
+    0
+}
+
+fn dump_coverage() -> isize {
+    cover!(dump_coverage);
+
+    if !::coverage::dump() {
+        return -1;
+    }
+
+    0
+}
+
+/// Fills the user buffer with random bytes from the entropy pool.
+fn get_random(buffer_ptr: VirtualAddress, length: usize) -> isize {
+    cover!(get_random);
+
+    if !is_valid_user_area(buffer_ptr, length) {
+        return SyscallError::InvalidArgument.as_return_value();
+    }
+
+    // The bytes are generated into a kernel buffer first, so that the pool
+    // isn't locked while user pages are faulted in.
+    let mut chunk = [0; 256];
+    let mut offset = 0;
+
+    while offset < length {
+        let chunk_length = min(chunk.len(), length - offset);
+        random::fill(&mut chunk[..chunk_length]);
+
+        unsafe {
+            let destination: *mut u8 = (buffer_ptr + offset).as_mut_ptr();
+            slice::from_raw_parts_mut(destination, chunk_length)
+                .copy_from_slice(&chunk[..chunk_length]);
+        }
+
+        offset += chunk_length;
+    }
+
+    0
+}
+
+/// Suspends the machine to RAM until it is woken up again.
+///
+/// This is only allowed with the `suspend=on` command line option, because
+/// not all devices survive sleeping.
+fn suspend() -> Result<usize, SyscallError> {
+    cover!(suspend);
+
+    if !get_current_process().capabilities.contains(POWER)
+        || boot::get_option_value("suspend") != Some("on")
+    {
+        return Err(SyscallError::PermissionDenied);
+    }
+
+    if arch::Current::suspend() {
+        Ok(0)
+    } else {
+        Err(SyscallError::NoDevice)
+    }
+}
+
+/// Selects the governor that scales the frequency of the CPU.
+fn set_governor(governor: usize) -> Result<usize, SyscallError> {
+    cover!(set_governor);
+
+    if !get_current_process().capabilities.contains(POWER) {
+        return Err(SyscallError::PermissionDenied);
+    }
+
+    let governor = Governor::from_number(governor).ok_or(SyscallError::InvalidArgument)?;
+
+    if cpufreq::set_governor(governor) {
+        Ok(0)
+    } else {
+        Err(SyscallError::NoDevice)
+    }
+}
+
+/// Takes the CPU with the given ID offline.
+///
+/// Returns once the CPU stopped running threads.
+fn cpu_down(cpu_id: usize) -> Result<usize, SyscallError> {
+    cover!(cpu_down);
+
+    if !get_current_process().capabilities.contains(CPU_HOTPLUG) {
+        return Err(SyscallError::PermissionDenied);
+    }
+
+    hotplug::cpu_down(cpu_id)?;
+
+    Ok(0)
+}
+
+/// Brings the CPU with the given ID online.
+fn cpu_up(cpu_id: usize) -> Result<usize, SyscallError> {
+    cover!(cpu_up);
+
+    if !get_current_process().capabilities.contains(CPU_HOTPLUG) {
+        return Err(SyscallError::PermissionDenied);
+    }
+
+    hotplug::cpu_up(cpu_id)?;
+
+    Ok(0)
+}
+
+/// Writes the information about the NUMA node with the given index to
+/// `info_ptr`.
+///
+/// Returns the number of nodes.
+fn get_node_info(node: usize, info_ptr: VirtualAddress) -> Result<usize, SyscallError> {
+    cover!(get_node_info);
+
+    if !is_valid_user_area(info_ptr, size_of::<NodeInfo>()) {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let topology = numa::get_topology().expect("The NUMA topology wasn't read yet.");
+    let info = topology
+        .node_info(node)
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    unsafe {
+        ptr::write_unaligned(info_ptr.as_mut_ptr(), info);
+    }
+
+    Ok(topology.nodes().len())
+}
+
 fn print_char(character: char) -> isize {
+    cover!(print_char);
+
     print!("{}", character);
+    if testing::is_enabled() {
+        serial_print!("{}", character);
+    }
     0
 }
 
-fn kill_process() -> isize {
-    get_current_process().kill();
+fn exit_process(code: i32) -> isize {
+    cover!(exit_process);
+
+    get_current_process().exit(code);
+    scheduler::wake_process(CURRENT_THREAD.lock().pid);
 
     schedule();
     0
 }
 
 fn return_pid() -> isize {
+    cover!(return_pid);
+
     let pid = CURRENT_THREAD.lock().pid;
-    let pid: usize = pid.into();
+    let namespace = get_current_process().pid_namespace;
+
+    let pid = pid_namespace::to_local(namespace, pid)
+        .expect("A process is not visible in its own namespace.");
 
     pid as isize
 }
 
-fn exec(name_ptr: VirtualAddress, name_length: usize) -> isize {
-    let name_ptr_valid = {
-        let pcb = get_current_process();
+/// Waits until the child process with the given ID ended and writes how it
+/// ended to `status_ptr`.
+///
+/// The child is removed afterwards. Returns 1 once the child ended and 0 on
+/// timeout.
+fn wait(pid: usize, status_ptr: VirtualAddress, timeout_ms: isize) -> isize {
+    cover!(wait);
+
+    if !is_valid_user_area(status_ptr, size_of::<WaitStatus>()) {
+        return SyscallError::InvalidArgument.as_return_value();
+    }
+
+    let parent = CURRENT_THREAD.lock().pid;
+    let namespace = get_current_process().pid_namespace;
+
+    let child = match pid_namespace::to_global(namespace, pid) {
+        Some(child) => child,
+        None => return SyscallError::InvalidArgument.as_return_value()
+    };
+
+    wait_until(timeout_ms, |wait| {
+        wait.on(&get_current_process().child_exits);
+
+        // Another thread of the parent may have reaped the child already.
+        if !multitasking::is_child(parent, child) {
+            return Some(SyscallError::InvalidArgument.as_return_value());
+        }
+
+        multitasking::reap_child(parent, child).map(|status| {
+            let status = match status {
+                ExitStatus::Exited(code) => WaitStatus { exited: 1, code },
+                ExitStatus::Killed => WaitStatus { exited: 0, code: 0 }
+            };
+
+            unsafe {
+                ptr::write_unaligned(status_ptr.as_mut_ptr(), status);
+            }
+
+            1
+        })
+    })
+}
+
+fn fork() -> isize {
+    cover!(fork);
+
+    to_return_value(fork_process())
+}
 
-        pcb.address_space
-            .contains_area(MemoryArea::new(name_ptr, name_length))
+/// Creates a copy of the current process in which only the current thread
+/// runs.
+///
+/// Returns the ID of the child as seen by the current process. In the child
+/// the syscall returns zero.
+fn fork_process() -> Result<usize, SyscallError> {
+    let (address_space, name, environment) = {
+        let mut pcb = get_current_process();
+
+        (pcb.address_space.fork(), pcb.name, pcb.environment.clone())
     };
+    let address_space = address_space.ok_or(SyscallError::NoMemory)?;
+    // The copy of the stack unmaps it when dropped, so it is only made once
+    // the fork can't fail anymore.
+    let user_stack = CURRENT_THREAD.lock().user_stack.duplicate();
 
-    if name_ptr_valid {
-        let name = from_raw_str!(name_ptr, name_length);
+    let process_id = multitasking::fork_current_thread(
+        address_space,
+        user_stack,
+        name,
+        environment,
+        Inheritance::from_current()
+    );
 
-        if let Ok(name) = name {
-            let process_id = elf::process_from_initramfs_file(name);
+    let namespace = get_current_process().pid_namespace;
+    let pid = pid_namespace::to_local(namespace, process_id)
+        .expect("A child process is not visible in the namespace of its parent.");
 
-            if let Ok(process_id) = process_id {
-                let pid: usize = process_id.into();
+    Ok(pid)
+}
 
-                assert!(pid as isize > 0, "Process ID too large.");
+fn exec(name_ptr: VirtualAddress, name_length: usize, flags: usize) -> isize {
+    cover!(exec);
 
-                pid as isize
-            } else {
-                -1
+    to_return_value(exec_file(name_ptr, name_length, flags))
+}
+
+/// Starts the executable with the given name as a child of the current
+/// process.
+///
+/// Returns the ID of the child as seen by the current process.
+fn exec_file(
+    name_ptr: VirtualAddress,
+    name_length: usize,
+    flags: usize
+) -> Result<usize, SyscallError> {
+    if !is_valid_user_area(name_ptr, name_length) || flags & !(EXEC_NEW_PID_NAMESPACE | EXEC_NO_HUGE_PAGES) != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let name = from_raw_str!(name_ptr, name_length).map_err(|_| SyscallError::InvalidArgument)?;
+    let mut inheritance = Inheritance::from_current();
+    inheritance.new_pid_namespace = flags & EXEC_NEW_PID_NAMESPACE != 0;
+    let process_id = load_executable(name, flags & EXEC_NO_HUGE_PAGES == 0, inheritance)?;
+
+    let namespace = get_current_process().pid_namespace;
+    let pid = pid_namespace::to_local(namespace, process_id)
+        .expect("A child process is not visible in the namespace of its parent.");
+
+    Ok(pid)
+}
+
+/// Creates a new process from the executable with the given name.
+///
+/// If the executable is a script, its interpreter is started with the path
+/// of the script as the last argument. The new process gets the environment
+/// of the current process and the given inheritance and only uses huge pages
+/// if `allow_huge_pages` is set.
+fn load_executable(
+    name: &str,
+    allow_huge_pages: bool,
+    inheritance: Inheritance
+) -> Result<ProcessID, SyscallError> {
+    let path = get_current_process()
+        .resolve_path(name)
+        .ok_or(SyscallError::InvalidArgument)?;
+    let environment = get_current_process().environment.clone();
+    let environment: Vec<&str> = environment.iter().map(|variable| variable.as_str()).collect();
+
+    let process_id = match elf::script_interpreter(&path)? {
+        Some(interpreter) => {
+            let interpreter_path = get_current_process()
+                .resolve_path(&interpreter.path)
+                .ok_or(SyscallError::InvalidExecutable)?;
+
+            let mut arguments = Vec::with_capacity(3);
+            arguments.push(interpreter.path.as_str());
+            if let Some(ref argument) = interpreter.argument {
+                arguments.push(argument.as_str());
             }
-        } else {
-            -1
-        }
-    } else {
-        -1
+            arguments.push(name);
+
+            elf::process_from_initramfs_file(
+                &interpreter_path,
+                &arguments,
+                &environment,
+                allow_huge_pages,
+                inheritance
+            )?
+        },
+        None => elf::process_from_initramfs_file(
+            &path,
+            &[name],
+            &environment,
+            allow_huge_pages,
+            inheritance
+        )?
+    };
+
+    Ok(process_id)
+}
+
+fn spawn_server(manifest_ptr: VirtualAddress, manifest_length: usize) -> isize {
+    cover!(spawn_server);
+
+    to_return_value(spawn_server_from_manifest(manifest_ptr, manifest_length))
+}
+
+/// Starts the server described by the manifest at the given path as a child
+/// of the current process.
+///
+/// Returns the ID of the server as seen by the current process.
+fn spawn_server_from_manifest(
+    manifest_ptr: VirtualAddress,
+    manifest_length: usize
+) -> Result<usize, SyscallError> {
+    if !is_valid_user_area(manifest_ptr, manifest_length) {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let path =
+        from_raw_str!(manifest_ptr, manifest_length).map_err(|_| SyscallError::InvalidArgument)?;
+
+    let (manifest_path, capabilities) = {
+        let pcb = get_current_process();
+
+        (pcb.resolve_path(path), pcb.capabilities)
+    };
+
+    if !capabilities.contains(SPAWN_SERVER) {
+        return Err(SyscallError::PermissionDenied);
+    }
+
+    let manifest_path = manifest_path.ok_or(SyscallError::InvalidArgument)?;
+    let manifest = Manifest::from_initramfs(&manifest_path)?;
+
+    // A server can't get capabilities its creator doesn't have.
+    if !capabilities.contains(manifest.capabilities) {
+        return Err(SyscallError::PermissionDenied);
     }
+
+    let binary_path = get_current_process()
+        .resolve_path(&manifest.binary)
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    let mut arguments = Vec::with_capacity(manifest.arguments.len() + 1);
+    arguments.push(manifest.binary.as_str());
+    for argument in &manifest.arguments {
+        arguments.push(argument.as_str());
+    }
+
+    let environment = get_current_process().environment.clone();
+    let environment: Vec<&str> = environment.iter().map(|variable| variable.as_str()).collect();
+
+    let mut inheritance = Inheritance::from_current();
+    inheritance.capabilities = manifest.capabilities;
+    inheritance.grants = manifest.grants.clone();
+    inheritance.essential = manifest.essential;
+
+    let process_id = elf::process_from_initramfs_file(
+        &binary_path,
+        &arguments,
+        &environment,
+        true,
+        inheritance
+    )?;
+
+    let namespace = get_current_process().pid_namespace;
+    let pid = pid_namespace::to_local(namespace, process_id)
+        .expect("A server is not visible in the namespace of its creator.");
+
+    Ok(pid)
 }
 
+/// Creates a new thread in the current process with a user stack of the given
+/// size.
+///
+/// Returns the ID of the new thread.
 fn create_thread(
     start_address: VirtualAddress,
+    stack_size: usize,
     arg1: usize,
     arg2: usize,
     arg3: usize,
     arg4: usize,
     arg5: usize
 ) -> isize {
-    let pid = CURRENT_THREAD.lock().pid;
+    cover!(create_thread);
+
+    if stack_size == 0 || stack_size > AddressSpace::user_stack_max_size() {
+        return SyscallError::InvalidArgument.as_return_value();
+    }
+
+    let (pid, priority) = {
+        let current_thread = CURRENT_THREAD.lock();
+
+        (current_thread.pid, current_thread.priority)
+    };
     let mut pcb = get_current_process();
-    let id = pcb.find_thread_id();
 
-    match id {
-        Some(id) => {
-            let thread = TCB::in_process_with_arguments(
-                pid,
-                id,
-                start_address,
-                &mut pcb,
-                arg1,
-                arg2,
-                arg3,
-                arg4,
-                arg5
-            );
+    if !pcb.can_create_thread() {
+        return SyscallError::LimitExceeded.as_return_value();
+    }
 
-            pcb.add_thread(id);
+    let id = match pcb.find_thread_id() {
+        Some(id) => id,
+        None => return SyscallError::LimitExceeded.as_return_value()
+    };
 
-            READY_LIST.lock().push(thread);
+    let user_stack = match pcb.address_space.create_user_stack(stack_size) {
+        Some(user_stack) => user_stack,
+        None => return SyscallError::NoMemory.as_return_value()
+    };
 
-            let tid: usize = id.into();
+    let mut thread = TCB::in_process_with_arguments(
+        pid,
+        id,
+        start_address,
+        &mut pcb,
+        user_stack,
+        arg1,
+        arg2,
+        arg3,
+        arg4,
+        arg5
+    );
+    thread.priority = priority;
 
-            tid as isize
-        },
-        None => -1
+    pcb.add_thread(id);
+
+    READY_LIST.lock().push(thread);
+
+    let tid: usize = id.into();
+
+    tid as isize
+}
+
+/// Waits until the thread with the given ID in the current process exited.
+///
+/// Returns 1 once the thread exited and 0 if the timeout expired first.
+fn join_thread(id: usize, timeout_ms: isize) -> isize {
+    cover!(join_thread);
+
+    let id: ThreadID = id.into();
+
+    if CURRENT_THREAD.lock().id == id {
+        return SyscallError::InvalidArgument.as_return_value();
+    }
+
+    wait_until(timeout_ms, |wait| {
+        let pcb = get_current_process();
+
+        if pcb.has_thread(id) {
+            wait.on(&pcb.thread_exits);
+            None
+        } else {
+            Some(1)
+        }
+    })
+}
+
+/// Waits until the futex at the address is woken, unless it doesn't hold
+/// the expected value.
+///
+/// Returns 1 if the thread was woken or the value differed and 0 if the
+/// timeout expired.
+fn futex_wait(address: VirtualAddress, expected: u32, timeout_ms: isize) -> isize {
+    cover!(futex_wait);
+
+    let aligned = address.as_usize() % align_of::<u32>() == 0;
+
+    if !aligned || !is_valid_user_area(address, size_of::<u32>()) {
+        return SyscallError::InvalidArgument.as_return_value();
+    }
+
+    let (pid, thread) = {
+        let current_thread = CURRENT_THREAD.lock();
+        (current_thread.pid, current_thread.id)
+    };
+
+    let mut value_checked = false;
+
+    let result = wait_until(timeout_ms, |wait| {
+        futex::wait(pid, thread, address, wait.waker());
+
+        // The value is read after the thread was added, so that a wake-up
+        // after the value was changed can't be missed.
+        if !value_checked {
+            value_checked = true;
+
+            if unsafe { ptr::read_volatile(address.as_ptr::<u32>()) } != expected {
+                return Some(1);
+            }
+        }
+
+        if futex::is_woken(pid, thread) {
+            Some(1)
+        } else {
+            None
+        }
+    });
+
+    // A wake-up that raced with the timeout still counts.
+    if futex::stop_waiting(pid, thread) {
+        1
+    } else {
+        result
+    }
+}
+
+/// Wakes up to `count` threads waiting on the futex at the address.
+fn futex_wake(address: VirtualAddress, count: usize) -> Result<usize, SyscallError> {
+    cover!(futex_wake);
+
+    if address.as_usize() % align_of::<u32>() != 0 {
+        return Err(SyscallError::InvalidArgument);
     }
+
+    let pid = CURRENT_THREAD.lock().pid;
+
+    Ok(futex::wake(pid, address, count))
 }
 
 fn kill_thread() -> isize {
+    cover!(kill_thread);
+
     CURRENT_THREAD.lock().kill();
 
     schedule();
@@ -133,7 +872,123 @@ fn kill_thread() -> isize {
     0
 }
 
+fn set_thread_name(name_ptr: VirtualAddress, name_length: usize) -> isize {
+    cover!(set_thread_name);
+
+    if name_length > MAX_NAME_LENGTH || !is_valid_user_area(name_ptr, name_length) {
+        return -1;
+    }
+
+    let name = match from_raw_str!(name_ptr, name_length) {
+        Ok(name) => Name::new(name),
+        Err(_) => return -1
+    };
+
+    CURRENT_THREAD.lock().name = name;
+
+    0
+}
+
+/// Sets the priority of the current thread.
+///
+/// The new priority applies right away, so a thread that lowers its priority
+/// yields to ready threads of a higher one. Raising it above both its current
+/// and the default priority needs the `RAISE_PRIORITY` capability.
+fn set_priority(priority: i32) -> Result<usize, SyscallError> {
+    cover!(set_priority);
+
+    if !ready_queue::is_valid_priority(priority) {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let current_priority = CURRENT_THREAD.lock().priority;
+
+    if priority > max(current_priority, ready_queue::DEFAULT_PRIORITY)
+        && !get_current_process().capabilities.contains(RAISE_PRIORITY)
+    {
+        return Err(SyscallError::PermissionDenied);
+    }
+
+    CURRENT_THREAD.lock().priority = priority;
+    get_current_process().priority = priority;
+
+    schedule();
+
+    Ok(0)
+}
+
+/// Maps `length` bytes of device memory at the page aligned physical
+/// `address` into the current process with the given cache mode.
+///
+/// The memory must be granted to the process by its manifest and must not
+/// be RAM that the kernel hands out. Returns the address of the mapping.
+fn map_device_memory(
+    address: usize,
+    length: usize,
+    cache_mode: usize
+) -> Result<usize, SyscallError> {
+    cover!(map_device_memory);
+
+    let cache_flags = match cache_mode {
+        MMIO_UNCACHED => NO_CACHE,
+        MMIO_WRITE_COMBINING => WRITE_COMBINING,
+        _ => return Err(SyscallError::InvalidArgument)
+    };
+
+    let address = PhysicalAddress::from_usize(address);
+    let length = length
+        .checked_add(PAGE_SIZE - 1)
+        .map(|length| length / PAGE_SIZE * PAGE_SIZE)
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    if length == 0 || address.offset_in_page() != 0 || address.checked_add(length).is_none() {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let area = MemoryArea::new(address, length);
+    let mut pcb = get_current_process();
+
+    if !pcb.grants.allows_mmio(area) || boot::overlaps_available(area) {
+        return Err(SyscallError::PermissionDenied);
+    }
+
+    let flags = READABLE | WRITABLE | USER_ACCESSIBLE | cache_flags;
+
+    pcb.address_space
+        .map_device_memory(area, flags)
+        .map(|address| address.as_usize())
+        .ok_or(SyscallError::NoMemory)
+}
+
+/// Writes the current time of the given clock to the user buffer.
+fn get_clock(clock: usize, time_ptr: VirtualAddress) -> Result<usize, SyscallError> {
+    cover!(get_clock);
+
+    if !is_valid_user_area(time_ptr, size_of::<ClockTime>()) {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let time = match clock {
+        MONOTONIC_CLOCK => Timestamp::get_current().since_boot(),
+        SYSTEM_CLOCK => time::get_system_time(),
+        _ => return Err(SyscallError::InvalidArgument)
+    };
+
+    let time = ClockTime {
+        seconds: time.as_secs(),
+        nanoseconds: time.subsec_nanos() as u64
+    };
+
+    unsafe {
+        ptr::write_unaligned(time_ptr.as_mut_ptr(), time);
+    }
+
+    Ok(0)
+}
+
 fn sleep(seconds: usize, nanoseconds: usize) -> isize {
+    cover!(sleep);
+
     // Check if the duration is valid
     let seconds = seconds as u64;
     let nanoseconds = nanoseconds as u32;
@@ -162,6 +1017,281 @@ fn sleep(seconds: usize, nanoseconds: usize) -> isize {
     0
 }
 
+fn get_resource_limit(resource: usize, limit_ptr: VirtualAddress) -> isize {
+    cover!(get_resource_limit);
+
+    let resource = match Resource::from_raw(resource) {
+        Some(resource) => resource,
+        None => return -1
+    };
+
+    if !is_valid_user_area(limit_ptr, 2 * size_of::<u64>()) {
+        return -1;
+    }
+
+    let limit = get_current_process().get_limit(resource);
+
+    unsafe {
+        let limit_ptr: *mut u64 = limit_ptr.as_mut_ptr();
+        *limit_ptr = limit.soft as u64;
+        *limit_ptr.offset(1) = limit.hard as u64;
+    }
+
+    0
+}
+
+fn set_resource_limit(resource: usize, soft: usize, hard: usize) -> isize {
+    cover!(set_resource_limit);
+
+    let resource = match Resource::from_raw(resource) {
+        Some(resource) => resource,
+        None => return -1
+    };
+
+    if get_current_process().set_limit(resource, Limit { soft, hard }) {
+        0
+    } else {
+        -1
+    }
+}
+
+fn create_resource_group(
+    name_ptr: VirtualAddress,
+    name_length: usize,
+    parent: GroupID,
+    memory_limit: usize,
+    cpu_shares: u32
+) -> isize {
+    cover!(create_resource_group);
+
+    if !is_valid_user_area(name_ptr, name_length) {
+        return -1;
+    }
+
+    let name = match from_raw_str!(name_ptr, name_length) {
+        Ok(name) => name,
+        Err(_) => return -1
+    };
+
+    // Only subgroups of the own group can be created.
+    if !resource_group::is_within_group(parent, get_current_process().resource_group) {
+        return -1;
+    }
+
+    match resource_group::create_group(name, parent, memory_limit, cpu_shares) {
+        Some(id) => id as isize,
+        None => -1
+    }
+}
+
+fn set_resource_group_limits(group: GroupID, memory_limit: usize, cpu_shares: u32) -> isize {
+    cover!(set_resource_group_limits);
+
+    let own_group = get_current_process().resource_group;
+
+    // The own group is limited by whoever created it.
+    if group == own_group || !resource_group::is_within_group(group, own_group) {
+        return -1;
+    }
+
+    if resource_group::set_group_limits(group, memory_limit, cpu_shares) {
+        0
+    } else {
+        -1
+    }
+}
+
+fn join_resource_group(group: GroupID) -> isize {
+    cover!(join_resource_group);
+
+    let mut pcb = get_current_process();
+
+    // Joining an outside group would escape the limits of the own group.
+    if !resource_group::is_within_group(group, pcb.resource_group)
+        || !pcb.address_space.set_resource_group(group)
+    {
+        return -1;
+    }
+
+    pcb.resource_group = group;
+
+    0
+}
+
+fn set_root(path_ptr: VirtualAddress, path_length: usize) -> isize {
+    cover!(set_root);
+
+    if !is_valid_user_area(path_ptr, path_length) {
+        return -1;
+    }
+
+    let path = match from_raw_str!(path_ptr, path_length) {
+        Ok(path) => path,
+        Err(_) => return -1
+    };
+
+    let mut pcb = get_current_process();
+
+    if !pcb.capabilities.contains(SET_ROOT) {
+        return -1;
+    }
+
+    match pcb.resolve_path(path) {
+        Some(mut root) => {
+            while root.len() > 1 && root.ends_with('/') {
+                root.pop();
+            }
+
+            pcb.root = root;
+            0
+        },
+        None => -1
+    }
+}
+
+fn drop_capabilities(capabilities: u64) -> isize {
+    cover!(drop_capabilities);
+
+    let mut pcb = get_current_process();
+
+    pcb.capabilities.remove(Capabilities::from_bits_truncate(capabilities));
+
+    0
+}
+
+fn register_service(name_ptr: VirtualAddress, name_length: usize, endpoint: usize) -> isize {
+    cover!(register_service);
+
+    if name_length == 0
+        || name_length > service::MAX_NAME_LENGTH
+        || !is_valid_user_area(name_ptr, name_length)
+    {
+        return -1;
+    }
+
+    let name = match from_raw_str!(name_ptr, name_length) {
+        Ok(name) => name,
+        Err(_) => return -1
+    };
+
+    let process = CURRENT_THREAD.lock().pid;
+    let allowed = {
+        let pcb = get_current_process();
+
+        pcb.capabilities.contains(REGISTER_SERVICE) || pcb.grants.allows_service(name)
+    };
+
+    if allowed && service::register(name, Service { process, endpoint }) {
+        0
+    } else {
+        -1
+    }
+}
+
+fn lookup_service(
+    name_ptr: VirtualAddress,
+    name_length: usize,
+    endpoint_ptr: VirtualAddress
+) -> isize {
+    cover!(lookup_service);
+
+    if !is_valid_user_area(name_ptr, name_length)
+        || !is_valid_user_area(endpoint_ptr, size_of::<u64>())
+    {
+        return -1;
+    }
+
+    let name = match from_raw_str!(name_ptr, name_length) {
+        Ok(name) => name,
+        Err(_) => return -1
+    };
+
+    let service = match service::lookup(name) {
+        Some(service) => service,
+        None => return -1
+    };
+
+    // Services of processes outside of the namespace are not visible.
+    let namespace = get_current_process().pid_namespace;
+    let pid = match pid_namespace::to_local(namespace, service.process) {
+        Some(pid) => pid,
+        None => return -1
+    };
+
+    unsafe {
+        *endpoint_ptr.as_mut_ptr::<u64>() = service.endpoint as u64;
+    }
+
+    pid as isize
+}
+
+/// Blocks the current thread until `check` returns a result.
+///
+/// `check` adds the thread to the wait queues of everything it checks before
+/// checking it, so that the thread sleeps until a new check can succeed. If
+/// the timeout expires first, 0 is returned. A negative timeout means
+/// waiting indefinitely.
+fn wait_until<F>(timeout_ms: isize, mut check: F) -> isize
+where
+    F: FnMut(&Wait) -> Option<isize>
+{
+    let deadline = if timeout_ms < 0 {
+        None
+    } else {
+        match Timestamp::get_current().offset(Duration::from_millis(timeout_ms as u64)) {
+            Some(deadline) => Some(deadline),
+            None => return -1
+        }
+    };
+
+    loop {
+        let wait = Wait::new();
+
+        if let Some(result) = check(&wait) {
+            return result;
+        }
+
+        if deadline.map_or(false, |deadline| Timestamp::get_current() >= deadline) {
+            return 0;
+        }
+
+        wait.sleep(deadline);
+    }
+}
+
+/// Checks if the area is a valid user accessible part of the current process
+/// address space.
+fn is_valid_user_area(address: VirtualAddress, length: usize) -> bool {
+    let valid = address.checked_add(length).is_some()
+        && get_current_process()
+            .address_space
+            .contains_user_area(MemoryArea::new(address, length));
+
+    if valid && testing::is_enabled() {
+        assert!(
+            arch::Current::is_userspace_address(address),
+            "The kernel address {:?} passed the user area validation.",
+            address
+        );
+    }
+
+    valid
+}
+
+/// Returns the user array at `address` if it is valid.
+///
+/// # Safety
+/// - `T` must be valid for any bit pattern.
+unsafe fn user_slice<'a, T>(address: VirtualAddress, count: usize) -> Option<&'a mut [T]> {
+    let length = count.checked_mul(size_of::<T>())?;
+
+    if is_valid_user_area(address, length) {
+        Some(slice::from_raw_parts_mut(address.as_mut_ptr(), count))
+    } else {
+        None
+    }
+}
+
 fn unknown_syscall(num: u16) -> ! {
     if cfg!(debug) {
         panic!("The syscall {} is not known.", num);
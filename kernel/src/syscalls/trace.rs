@@ -0,0 +1,353 @@
+//! This module handles the system calls that trace other processes.
+
+use super::{user_slice, wait_until};
+use arch::{self, Architecture};
+use core::cmp::min;
+use kdebug::{self, WatchKind, Watchpoint, WATCHPOINT_SLOTS};
+use memory::{Address, MemoryArea, VirtualAddress};
+use multitasking::capabilities::TRACE;
+use multitasking::pid_namespace;
+use multitasking::trace::{Registers, ResumeMode, TraceState};
+use multitasking::{self, get_current_process, get_process, ProcessID, ProcessLock,
+                   CURRENT_THREAD};
+
+/// The attach flag that makes the traced process stop before every syscall.
+const TRACE_SYSCALLS: usize = 1 << 0;
+
+/// The size of the kernel buffer used to move data between address spaces.
+const TRANSFER_CHUNK_SIZE: usize = 512;
+
+/// The layout of a stop reported to the tracer.
+#[repr(C)]
+struct StopReport {
+    /// The reason the thread stopped.
+    reason: u64,
+    /// Additional information about the stop reason.
+    detail: u64,
+    /// The ID of the stopped thread.
+    thread: u64,
+    /// The registers of the stopped thread.
+    registers: Registers
+}
+
+/// Returns the process with the given ID, if it is traced by the current
+/// process.
+///
+/// The ID is interpreted within the namespace of the current process.
+fn get_tracee<'a>(local_id: usize) -> Option<ProcessLock<'a>> {
+    let tracer = CURRENT_THREAD.lock().pid;
+    let namespace = get_current_process().pid_namespace;
+    let pid = pid_namespace::to_global(namespace, local_id)?;
+
+    let pcb = get_process(pid)?;
+
+    if pcb.trace.as_ref().map_or(false, |trace| trace.tracer == tracer) {
+        Some(pcb)
+    } else {
+        None
+    }
+}
+
+pub fn trace_attach(pid: usize, flags: usize) -> isize {
+    cover!(trace_attach);
+
+    if flags & !TRACE_SYSCALLS != 0 {
+        return -1;
+    }
+
+    let tracer = CURRENT_THREAD.lock().pid;
+    let (namespace, root, capabilities) = {
+        let pcb = get_current_process();
+
+        (pcb.pid_namespace, pcb.root.clone(), pcb.capabilities)
+    };
+
+    if !capabilities.contains(TRACE) {
+        return -1;
+    }
+
+    let pid: ProcessID = match pid_namespace::to_global(namespace, pid) {
+        Some(pid) => pid,
+        None => return -1
+    };
+
+    if pid == tracer {
+        return -1;
+    }
+
+    // The ancestry is checked before the process list is locked for the
+    // tracee.
+    let is_descendant = multitasking::is_descendant(tracer, pid);
+
+    match get_process(pid) {
+        Some(mut pcb) => {
+            // A process may not gain capabilities by tracing another process.
+            if pcb.trace.is_some() || pcb.is_dead() || !capabilities.contains(pcb.capabilities) {
+                return -1;
+            }
+
+            // Processes that weren't started by the tracer must at least
+            // share its namespace and root directory.
+            if !is_descendant && (pcb.pid_namespace != namespace || pcb.root != root) {
+                return -1;
+            }
+
+            pcb.trace = Some(TraceState::new(tracer, flags & TRACE_SYSCALLS != 0));
+
+            0
+        },
+        None => -1
+    }
+}
+
+pub fn trace_detach(pid: usize) -> isize {
+    cover!(trace_detach);
+
+    match get_tracee(pid) {
+        Some(mut pcb) => {
+            pcb.trace = None;
+            0
+        },
+        None => -1
+    }
+}
+
+pub fn trace_wait(pid: usize, report_ptr: VirtualAddress, timeout_ms: isize) -> isize {
+    cover!(trace_wait);
+
+    let report: &mut [StopReport] = match unsafe { user_slice(report_ptr, 1) } {
+        Some(report) => report,
+        None => return -1
+    };
+
+    wait_until(timeout_ms, |wait| {
+        let pcb = match get_tracee(pid) {
+            Some(pcb) => pcb,
+            None => return Some(-1)
+        };
+
+        if let Some(ref trace) = pcb.trace {
+            wait.on(&trace.changes);
+        }
+
+        match pcb.trace.as_ref().and_then(|trace| trace.stop) {
+            Some(stop) => if stop.resume.is_none() {
+                let thread: usize = stop.thread.into();
+
+                report[0] = StopReport {
+                    reason: stop.reason.to_raw(),
+                    detail: stop.reason.detail(),
+                    thread: thread as u64,
+                    registers: stop.registers
+                };
+
+                Some(1)
+            } else {
+                None
+            },
+            None => None
+        }
+    })
+}
+
+pub fn trace_read_memory(
+    pid: usize,
+    address: VirtualAddress,
+    buffer_ptr: VirtualAddress,
+    length: usize
+) -> isize {
+    cover!(trace_read_memory);
+
+    let buffer: &mut [u8] = match unsafe { user_slice(buffer_ptr, length) } {
+        Some(buffer) => buffer,
+        None => return -1
+    };
+
+    if address.as_usize().checked_add(length).is_none() {
+        return -1;
+    }
+
+    let mut chunk = [0u8; TRANSFER_CHUNK_SIZE];
+    let mut transferred = 0;
+
+    while transferred < length {
+        let chunk_size = min(length - transferred, TRANSFER_CHUNK_SIZE);
+
+        let success = match get_tracee(pid) {
+            Some(pcb) => pcb
+                .address_space
+                .read_from(&mut chunk[..chunk_size], address + transferred),
+            None => return -1
+        };
+
+        if !success {
+            break;
+        }
+
+        buffer[transferred..transferred + chunk_size].copy_from_slice(&chunk[..chunk_size]);
+        transferred += chunk_size;
+    }
+
+    if transferred == 0 && length != 0 {
+        -1
+    } else {
+        transferred as isize
+    }
+}
+
+pub fn trace_write_memory(
+    pid: usize,
+    address: VirtualAddress,
+    buffer_ptr: VirtualAddress,
+    length: usize
+) -> isize {
+    cover!(trace_write_memory);
+
+    let buffer: &[u8] = match unsafe { user_slice(buffer_ptr, length) } {
+        Some(buffer) => buffer,
+        None => return -1
+    };
+
+    if address.as_usize().checked_add(length).is_none() {
+        return -1;
+    }
+
+    let mut chunk = [0u8; TRANSFER_CHUNK_SIZE];
+    let mut transferred = 0;
+
+    while transferred < length {
+        let chunk_size = min(length - transferred, TRANSFER_CHUNK_SIZE);
+        let chunk_address = address + transferred;
+
+        chunk[..chunk_size].copy_from_slice(&buffer[transferred..transferred + chunk_size]);
+
+        match get_tracee(pid) {
+            Some(mut pcb) => {
+                if !pcb
+                    .address_space
+                    .contains_area(MemoryArea::new(chunk_address, chunk_size))
+                {
+                    break;
+                }
+
+                pcb.address_space.write_to(&chunk[..chunk_size], chunk_address);
+            },
+            None => return -1
+        }
+
+        transferred += chunk_size;
+    }
+
+    if transferred == 0 && length != 0 {
+        -1
+    } else {
+        transferred as isize
+    }
+}
+
+pub fn trace_set_registers(pid: usize, registers_ptr: VirtualAddress) -> isize {
+    cover!(trace_set_registers);
+
+    let registers: Registers = match unsafe { user_slice(registers_ptr, 1) } {
+        Some(registers) => registers[0],
+        None => return -1
+    };
+
+    let instruction_pointer = VirtualAddress::from_usize(registers.instruction_pointer);
+    let stack_pointer = VirtualAddress::from_usize(registers.stack_pointer);
+
+    if !arch::Current::is_userspace_address(instruction_pointer)
+        || !arch::Current::is_userspace_address(stack_pointer)
+    {
+        return -1;
+    }
+
+    match get_tracee(pid) {
+        Some(mut pcb) => match pcb.trace.as_mut().and_then(|trace| trace.stop.as_mut()) {
+            Some(stop) => if stop.resume.is_none() {
+                stop.registers = registers;
+                0
+            } else {
+                -1
+            },
+            None => -1
+        },
+        None => -1
+    }
+}
+
+pub fn trace_resume(pid: usize, mode: usize) -> isize {
+    cover!(trace_resume);
+
+    let mode = match ResumeMode::from_raw(mode) {
+        Some(mode) => mode,
+        None => return -1
+    };
+
+    match get_tracee(pid) {
+        Some(mut pcb) => match pcb.trace.as_mut() {
+            Some(trace) => if trace.resume(mode) {
+                0
+            } else {
+                -1
+            },
+            None => -1
+        },
+        None => -1
+    }
+}
+
+pub fn trace_set_watchpoint(
+    pid: usize,
+    slot: usize,
+    address: VirtualAddress,
+    length: usize,
+    kind: usize
+) -> isize {
+    cover!(trace_set_watchpoint);
+
+    let watchpoint = match WatchKind::from_raw(kind)
+        .and_then(|kind| Watchpoint::new(address, length, kind))
+    {
+        Some(watchpoint) => watchpoint,
+        None => return -1
+    };
+
+    if slot >= WATCHPOINT_SLOTS
+        || kdebug::is_kernel_slot(slot)
+        || !arch::Current::is_userspace_address(address)
+        || !arch::Current::is_userspace_address(address + (length - 1))
+    {
+        return -1;
+    }
+
+    match get_tracee(pid) {
+        Some(mut pcb) => match pcb.trace.as_mut() {
+            Some(trace) => {
+                trace.watchpoints[slot] = Some(watchpoint);
+                0
+            },
+            None => -1
+        },
+        None => -1
+    }
+}
+
+pub fn trace_clear_watchpoint(pid: usize, slot: usize) -> isize {
+    cover!(trace_clear_watchpoint);
+
+    if slot >= WATCHPOINT_SLOTS {
+        return -1;
+    }
+
+    match get_tracee(pid) {
+        Some(mut pcb) => match pcb.trace.as_mut() {
+            Some(trace) => {
+                trace.watchpoints[slot] = None;
+                0
+            },
+            None => -1
+        },
+        None => -1
+    }
+}
@@ -0,0 +1,45 @@
+//! Supports running the whole system in automated tests.
+//!
+//! The kernel runs in test mode if `test` is on its command line. In test mode
+//! the output of user programs is mirrored to the serial port, where the test
+//! runner on the host checks it, and panics exit the emulator with a failure
+//! code instead of halting.
+
+use arch::{self, Architecture};
+use boot;
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+/// The exit code passed to the emulator after a panic.
+///
+/// QEMU turns it into the exit status `(FAILURE_EXIT_CODE << 1) | 1`.
+const FAILURE_EXIT_CODE: u8 = 1;
+
+/// Whether the kernel runs in test mode.
+static TEST_MODE: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Enables test mode if it was requested on the command line.
+pub fn init() {
+    if !boot::has_option("test") {
+        return;
+    }
+
+    TEST_MODE.store(true, Ordering::Release);
+    info!("Running in test mode.");
+
+    // Lets the test runner check that panics are reported.
+    if boot::has_option("test_panic") {
+        panic!("A panic was requested on the command line.");
+    }
+}
+
+/// Returns true if the kernel runs in test mode.
+pub fn is_enabled() -> bool {
+    TEST_MODE.load(Ordering::Acquire)
+}
+
+/// Exits the emulator, reporting a failure to the test runner.
+///
+/// Returns if the emulator has no exit device.
+pub fn report_failure() {
+    arch::Current::exit_emulator(FAILURE_EXIT_CODE);
+}
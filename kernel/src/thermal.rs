@@ -0,0 +1,255 @@
+//! Watches the temperature of the CPU.
+//!
+//! A kernel thread reads the thermal sensor of the CPU once per second. A
+//! warning is logged when the temperature reaches the warning threshold.
+//! At the critical threshold, the CPU is throttled to its slowest
+//! performance state until it cooled down again.
+//!
+//! The thresholds are given in degrees Celsius with
+//! `thermal_warning=<temperature>` and `thermal_critical=<temperature>` on
+//! the kernel command line. `/proc/thermal` shows the current temperature.
+
+use alloc::string::String;
+use arch::{self, schedule, Architecture};
+use boot;
+use core::fmt::Write;
+use core::time::Duration;
+use cpufreq;
+use multitasking::scheduler::after_context_switch;
+use multitasking::{spawn_kernel_thread, Name, ThreadState, CURRENT_THREAD};
+use procfs;
+use sync::enable_preemption;
+use sync::time::Timestamp;
+use sync::Mutex;
+
+/// The interval in which the temperature is read.
+const SAMPLE_INTERVAL_MS: u64 = 1000;
+
+/// The default warning threshold in degrees Celsius.
+const DEFAULT_WARNING: u32 = 85;
+
+/// The default critical threshold in degrees Celsius.
+const DEFAULT_CRITICAL: u32 = 95;
+
+/// How far the temperature must fall below a threshold to leave its level.
+///
+/// This keeps a temperature around a threshold from switching the level on
+/// every reading.
+const HYSTERESIS: u32 = 5;
+
+/// How hot the CPU is relative to the thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    /// Below the warning threshold.
+    Normal,
+    /// Above the warning threshold.
+    Warning,
+    /// Above the critical threshold.
+    Critical
+}
+
+impl Level {
+    /// Returns the name of the level.
+    fn name(self) -> &'static str {
+        match self {
+            Level::Normal => "normal",
+            Level::Warning => "warning",
+            Level::Critical => "critical"
+        }
+    }
+}
+
+/// The thresholds in degrees Celsius.
+#[derive(Debug, Clone, Copy)]
+struct Thresholds {
+    /// The temperature at which a warning is logged.
+    warning: u32,
+    /// The temperature at which the CPU is throttled.
+    critical: u32
+}
+
+/// The state of the thermal monitoring.
+struct Thermal {
+    /// The thresholds.
+    thresholds: Thresholds,
+    /// The temperature at the last reading.
+    temperature: u32,
+    /// The highest temperature that was read.
+    highest: u32,
+    /// The level at the last reading.
+    level: Level
+}
+
+impl Thermal {
+    /// Reads the temperature and reacts to a change of the level.
+    fn sample(&mut self) {
+        let temperature = match arch::Current::get_cpu_temperature() {
+            Some(temperature) => temperature,
+            None => return
+        };
+
+        self.temperature = temperature;
+        self.highest = self.highest.max(temperature);
+
+        let level = classify(temperature, self.thresholds, self.level);
+        if level == self.level {
+            return;
+        }
+
+        match level {
+            Level::Critical => {
+                error!("The CPU is critically hot ({} degrees).", temperature);
+
+                if !cpufreq::set_throttled(true) {
+                    error!("The CPU can't be throttled.");
+                }
+            },
+            Level::Warning if self.level == Level::Normal => {
+                warn!("The CPU is getting hot ({} degrees).", temperature);
+            },
+            _ => info!("The CPU cooled down to {} degrees.", temperature)
+        }
+
+        if self.level == Level::Critical {
+            cpufreq::set_throttled(false);
+        }
+
+        self.level = level;
+    }
+}
+
+/// The thermal monitoring, if the CPU has a sensor.
+static THERMAL: Mutex<Option<Thermal>> = Mutex::new(None);
+
+/// Starts watching the temperature of the CPU.
+///
+/// This must be called once while the address space of the idle process is
+/// active.
+pub fn init() {
+    assert_has_not_been_called!("The thermal monitoring should only be started once.");
+
+    procfs::register("thermal", describe);
+
+    let temperature = match arch::Current::get_cpu_temperature() {
+        Some(temperature) => temperature,
+        None => {
+            debug!("The CPU has no thermal sensor.");
+            return;
+        }
+    };
+
+    let warning = threshold_from_command_line("thermal_warning", DEFAULT_WARNING);
+    let critical = threshold_from_command_line("thermal_critical", DEFAULT_CRITICAL);
+
+    info!(
+        "The CPU is at {} degrees Celsius (warning at {}, critical at {}).",
+        temperature, warning, critical
+    );
+
+    let mut thermal = Thermal {
+        thresholds: Thresholds { warning, critical },
+        temperature,
+        highest: temperature,
+        level: Level::Normal
+    };
+    thermal.sample();
+
+    *THERMAL.lock() = Some(thermal);
+
+    spawn_kernel_thread(Name::new("thermal"), monitor_thread);
+}
+
+/// Returns the level of the temperature.
+///
+/// The previous level is kept until the temperature falls clearly below its
+/// threshold.
+fn classify(temperature: u32, thresholds: Thresholds, previous: Level) -> Level {
+    let reached = |threshold: u32, level: Level| {
+        temperature >= threshold || (previous >= level && temperature + HYSTERESIS > threshold)
+    };
+
+    if reached(thresholds.critical, Level::Critical) {
+        Level::Critical
+    } else if reached(thresholds.warning, Level::Warning) {
+        Level::Warning
+    } else {
+        Level::Normal
+    }
+}
+
+/// Reads a threshold from the command line.
+fn threshold_from_command_line(key: &str, default: u32) -> u32 {
+    match boot::get_option_value(key) {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            warn!("Invalid temperature {}={}, using {}.", key, value, default);
+            default
+        }),
+        None => default
+    }
+}
+
+/// Generates the content of `/proc/thermal`.
+fn describe() -> String {
+    let mut text = String::new();
+
+    match *THERMAL.lock() {
+        Some(ref thermal) => {
+            writeln!(text, "temperature: {} C", thermal.temperature).unwrap();
+            writeln!(text, "highest: {} C", thermal.highest).unwrap();
+            writeln!(text, "warning: {} C", thermal.thresholds.warning).unwrap();
+            writeln!(text, "critical: {} C", thermal.thresholds.critical).unwrap();
+            writeln!(text, "level: {}", thermal.level.name()).unwrap();
+        },
+        None => writeln!(text, "unsupported").unwrap()
+    }
+
+    text
+}
+
+/// The function the monitoring thread runs.
+fn monitor_thread() -> ! {
+    // The thread starts right after a context switch away from another
+    // thread.
+    after_context_switch();
+    unsafe {
+        enable_preemption();
+    }
+
+    loop {
+        if let Some(ref mut thermal) = *THERMAL.lock() {
+            thermal.sample();
+        }
+
+        let wake_time = Timestamp::get_current()
+            .offset(Duration::from_millis(SAMPLE_INTERVAL_MS))
+            .expect("The time since boot overflowed.");
+
+        CURRENT_THREAD.lock().state = ThreadState::Sleeping(wake_time);
+        schedule();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: Thresholds = Thresholds {
+        warning: 80,
+        critical: 90
+    };
+
+    #[test]
+    fn levels_follow_the_thresholds() {
+        assert_eq!(classify(50, THRESHOLDS, Level::Normal), Level::Normal);
+        assert_eq!(classify(80, THRESHOLDS, Level::Normal), Level::Warning);
+        assert_eq!(classify(95, THRESHOLDS, Level::Normal), Level::Critical);
+    }
+
+    #[test]
+    fn levels_are_left_below_the_hysteresis() {
+        assert_eq!(classify(86, THRESHOLDS, Level::Critical), Level::Critical);
+        assert_eq!(classify(85, THRESHOLDS, Level::Critical), Level::Warning);
+        assert_eq!(classify(76, THRESHOLDS, Level::Warning), Level::Warning);
+        assert_eq!(classify(75, THRESHOLDS, Level::Critical), Level::Normal);
+    }
+}
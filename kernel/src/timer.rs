@@ -0,0 +1,141 @@
+//! This module implements timers that processes refer to by descriptors.
+//!
+//! A timer becomes readable when it expires, so that timeouts can be waited
+//! for together with other descriptors using `poll` or event queues.
+//! Reading a timer returns the number of expirations since the last read and
+//! resets it. Waiting for a timer wakes its wait queue at the next
+//! expiration.
+
+use alloc::arc::Arc;
+use core::time::Duration;
+use file_handle::{PollEvents, POLL_IN};
+use multitasking::wait_queue::{self, WaitQueue};
+use sync::time::Timestamp;
+
+/// A one-shot or periodic timer.
+pub struct Timer {
+    /// The time of the next expiration, if the timer is armed.
+    next_expiration: Option<Timestamp>,
+    /// The time between two expirations of a periodic timer.
+    interval: Option<Duration>,
+    /// The number of expirations since the last read.
+    expirations: u64,
+    /// Woken when the timer expires.
+    waiters: Arc<WaitQueue>,
+    /// The expiration at which the waiters are woken, if any.
+    wake_time: Option<Timestamp>
+}
+
+impl Timer {
+    /// Creates a new disarmed timer.
+    pub fn new() -> Timer {
+        Timer {
+            next_expiration: None,
+            interval: None,
+            expirations: 0,
+            waiters: Arc::new(WaitQueue::new()),
+            wake_time: None
+        }
+    }
+
+    /// Arms the timer to expire after `initial` and then every `interval`.
+    ///
+    /// A timer without an interval only expires once. Passing no initial
+    /// duration disarms the timer. Returns false if the expiration is too far
+    /// in the future.
+    pub fn set(&mut self, initial: Option<Duration>, interval: Option<Duration>) -> bool {
+        let next_expiration = match initial {
+            Some(initial) => match Timestamp::get_current().offset(initial) {
+                Some(expiration) => Some(expiration),
+                None => return false
+            },
+            None => None
+        };
+
+        self.next_expiration = next_expiration;
+        self.interval = interval;
+        self.expirations = 0;
+
+        // Waiters wait for the new expiration now.
+        self.waiters.wake_all();
+
+        true
+    }
+
+    /// Accounts for all expirations up to now.
+    fn update(&mut self) {
+        let now = Timestamp::get_current();
+
+        let expiration = match self.next_expiration {
+            Some(expiration) if expiration <= now => expiration,
+            _ => return
+        };
+
+        self.next_expiration = match self.interval {
+            Some(interval) => {
+                let missed = as_millis(now - expiration) / as_millis(interval).max(1);
+
+                self.expirations += 1 + missed;
+                offset_by(expiration, interval, missed + 1)
+            },
+            None => {
+                self.expirations += 1;
+                None
+            }
+        };
+    }
+
+    /// Returns the number of expirations since the last call and resets it.
+    pub fn take_expirations(&mut self) -> u64 {
+        self.update();
+
+        let expirations = self.expirations;
+        self.expirations = 0;
+
+        expirations
+    }
+
+    /// Returns the events that are currently ready on the timer.
+    ///
+    /// The timer is readable while it has expirations that weren't taken.
+    pub fn poll(&mut self) -> PollEvents {
+        self.update();
+
+        if self.expirations > 0 {
+            POLL_IN
+        } else {
+            PollEvents::empty()
+        }
+    }
+
+    /// Returns the queue that is woken when the timer expires.
+    pub fn wait_queue(&mut self) -> &WaitQueue {
+        self.update();
+
+        if let Some(expiration) = self.next_expiration {
+            if self.wake_time != Some(expiration) {
+                wait_queue::wake_at(expiration, Arc::downgrade(&self.waiters));
+                self.wake_time = Some(expiration);
+            }
+        }
+
+        &self.waiters
+    }
+}
+
+/// Returns the duration in milliseconds.
+fn as_millis(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_mul(1000)
+        .saturating_add(u64::from(duration.subsec_millis()))
+}
+
+/// Offsets the time stamp by `count` times `interval`.
+fn offset_by(timestamp: Timestamp, interval: Duration, count: u64) -> Option<Timestamp> {
+    let count = count.min(u64::from(u32::max_value())) as u32;
+
+    interval
+        .checked_mul(count)
+        .and_then(|offset| timestamp.offset(offset))
+}
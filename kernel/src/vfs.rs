@@ -0,0 +1,179 @@
+//! Combines all filesystems into a single tree of files.
+//!
+//! Every filesystem is mounted at a directory and sees the paths below that
+//! directory relative to its own root. A path belongs to the filesystem with
+//! the longest mount point that contains it, so the initramfs mounted at `/`
+//! receives all paths that no other filesystem claims.
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::Vec;
+use coredump;
+use devfs;
+use file_handle::{FileError, FileHandle, Result};
+use initramfs;
+use procfs;
+use sync::Mutex;
+
+/// A filesystem that can be mounted.
+pub trait FileSystem: Send + Sync {
+    /// Opens the file at the given path relative to the root of the
+    /// filesystem.
+    ///
+    /// The path has no leading slash and contains no empty or `.` components.
+    fn open(&self, path: &str) -> Result<Box<FileHandle>>;
+
+    /// Returns true if the files of the filesystem never change.
+    fn is_immutable(&self) -> bool {
+        false
+    }
+}
+
+/// A filesystem together with the directory it is mounted at.
+struct MountPoint {
+    /// The normalized path of the directory.
+    path: String,
+    /// The mounted filesystem.
+    file_system: Arc<FileSystem>
+}
+
+lazy_static! {
+    /// All mounted filesystems.
+    static ref MOUNT_POINTS: Mutex<Vec<MountPoint>> = Mutex::new(Vec::new());
+}
+
+/// Mounts the filesystems that are always available.
+pub fn init() {
+    mount("/", Arc::new(initramfs::InitramfsFileSystem));
+    mount(devfs::DEVICE_DIRECTORY, Arc::new(devfs::DeviceFileSystem));
+    mount(procfs::PROC_DIRECTORY, Arc::new(procfs::ProcFileSystem));
+    mount(coredump::CORE_DIRECTORY, Arc::new(coredump::CoreFileSystem));
+}
+
+/// Mounts the filesystem at the given directory.
+///
+/// # Panics
+/// Panics if the path is invalid or another filesystem is mounted there.
+pub fn mount(path: &str, file_system: Arc<FileSystem>) {
+    let path = normalize(path).expect("Invalid mount point.");
+    let mut mount_points = MOUNT_POINTS.lock();
+
+    assert!(
+        mount_points
+            .iter()
+            .all(|mount_point| mount_point.path != path),
+        "Two filesystems were mounted at {}.",
+        path
+    );
+
+    mount_points.push(MountPoint { path, file_system });
+}
+
+/// Opens the file at the given path.
+pub fn open(path: &str) -> Result<Box<FileHandle>> {
+    let (file_system, relative_path) = resolve(path).ok_or(FileError::FileNotFound)?;
+
+    // The lock is released before opening, so that filesystems can mount
+    // others or block while opening.
+    file_system.open(&relative_path)
+}
+
+/// Returns true if the file at the given path can never change.
+pub fn is_immutable(path: &str) -> bool {
+    resolve(path).map_or(false, |(file_system, _)| file_system.is_immutable())
+}
+
+/// Returns the filesystem that contains the path and the path relative to
+/// its mount point.
+fn resolve(path: &str) -> Option<(Arc<FileSystem>, String)> {
+    let path = normalize(path)?;
+    let mount_points = MOUNT_POINTS.lock();
+
+    let mount_point = mount_points
+        .iter()
+        .filter(|mount_point| relative_to(&mount_point.path, &path).is_some())
+        .max_by_key(|mount_point| mount_point.path.len())?;
+
+    let relative_path = String::from(relative_to(&mount_point.path, &path)?);
+    let file_system = mount_point.file_system.clone();
+
+    Some((file_system, relative_path))
+}
+
+/// Returns the path without empty or `.` components and trailing slashes.
+///
+/// Returns `None` if the path isn't absolute or refers to a parent directory.
+pub fn normalize(path: &str) -> Option<String> {
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut normalized = String::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => return None,
+            _ => {
+                normalized.push('/');
+                normalized.push_str(component);
+            }
+        }
+    }
+
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+
+    Some(normalized)
+}
+
+/// Returns the path relative to the mount point, if it lies below it.
+///
+/// Both paths must be normalized.
+fn relative_to<'a>(mount_point: &str, path: &'a str) -> Option<&'a str> {
+    if mount_point == "/" {
+        Some(&path[1..])
+    } else if path == mount_point {
+        Some("")
+    } else if path.starts_with(mount_point) && path[mount_point.len()..].starts_with('/') {
+        Some(&path[mount_point.len() + 1..])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that paths are normalized.
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize("/"), Some(String::from("/")));
+        assert_eq!(normalize("//"), Some(String::from("/")));
+        assert_eq!(normalize("/dev/"), Some(String::from("/dev")));
+        assert_eq!(normalize("/bin//./init"), Some(String::from("/bin/init")));
+    }
+
+    /// Tests that relative paths and parent directories are rejected.
+    #[test]
+    fn invalid_paths() {
+        assert_eq!(normalize(""), None);
+        assert_eq!(normalize("bin/init"), None);
+        assert_eq!(normalize("/bin/../init"), None);
+    }
+
+    /// Tests that paths are only claimed by mount points on component
+    /// boundaries.
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative_to("/", "/bin/init"), Some("bin/init"));
+        assert_eq!(relative_to("/", "/"), Some(""));
+        assert_eq!(relative_to("/dev", "/dev/console"), Some("console"));
+        assert_eq!(relative_to("/dev", "/dev"), Some(""));
+        assert_eq!(relative_to("/dev", "/devices/console"), None);
+        assert_eq!(relative_to("/dev", "/bin/init"), None);
+    }
+}
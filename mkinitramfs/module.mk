@@ -1,10 +1,19 @@
 MKINITRAMFS := mkinitramfs/target/release/mkinitramfs
 BUILD_DIRS += mkinitramfs/target
 FMT_DIRS += mkinitramfs
+MKINITRAMFS_FLAGS :=
+
+ifneq ($(SIGNING_KEY),)
+MKINITRAMFS_FLAGS += --sign $(SIGNING_KEY)
+
+# The kernel is built with the public key to verify the signatures.
+$(KERNEL_LIB): $(MKINITRAMFS) $(SIGNING_KEY)
+$(KERNEL_LIB): export VEOS_EXEC_PUBLIC_KEY = $(shell $(MKINITRAMFS) --public-key $(SIGNING_KEY))
+endif
 
 $(TARGET_DIR)/boot/initramfs: $(MKINITRAMFS) $(TARGET_DIR)/conf/mkinitramfs $(patsubst %,$(TARGET_DIR)%,$(INITRAMFS_FILES))
 	@mkdir -p $(shell dirname $@)
-	$(MKINITRAMFS) $(TARGET_DIR)/conf/mkinitramfs $(TARGET_DIR)/boot/initramfs $(TARGET_DIR)
+	$(MKINITRAMFS) $(MKINITRAMFS_FLAGS) $(TARGET_DIR)/conf/mkinitramfs $(TARGET_DIR)/boot/initramfs $(TARGET_DIR)
 
-$(MKINITRAMFS): $(shell find mkinitramfs/src -name "*.rs") mkinitramfs/Cargo.toml
+$(MKINITRAMFS): $(shell find mkinitramfs/src -name "*.rs") mkinitramfs/Cargo.toml $(INITRAMFS_CRATE_FILES) $(CRYPTO_CRATE_FILES)
 	cd mkinitramfs && cargo build --release
\ No newline at end of file
@@ -1,20 +1,24 @@
-#![feature(const_size_of)]
-
 //! This crate is the initramfs creator for VeOS.
+//!
+//! With `--sign`, a detached Ed25519 signature is added for every ELF file,
+//! which the kernel can verify before executing the file. The kernel is built
+//! with the public key printed by `--public-key`.
 
-extern crate byteorder;
+extern crate veos_crypto;
+extern crate veos_initramfs;
 
 use std::env::args;
 use std::fmt::Display;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::io::{ErrorKind, SeekFrom};
-use std::mem::{size_of, size_of_val};
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use veos_crypto::ed25519::{self, SecretKey, Signature, SECRET_KEY_SIZE};
+use veos_initramfs::{Writer, CURRENT_VERSION, LEGACY_VERSION, SIGNATURE_SUFFIX};
 
-use byteorder::{BigEndian, WriteBytesExt};
+/// The magic number at the start of ELF files.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
 
 /// Whether to force the creation of the initramfs.
 const FORCE: bool = false;
@@ -22,26 +26,60 @@ const FORCE: bool = false;
 /// Whether to overwrite the target if it exists.
 const OVERWRITE: bool = true;
 
-/// The magic number at the beginning of the output file.
-const MAGIC: [u8; 8] = [
-    'V' as u8, 'e' as u8, 'O' as u8, 'S' as u8, 'i' as u8, 'r' as u8, 'f' as u8, 's' as u8,
-];
-
-/// The offset at which the file metadata begins.
-const FILE_METADATA_OFFSET: usize = size_of::<[u8; 8]>() + size_of::<u64>();
-
-/// The size of a file metadata object.
-const FILE_METADATA_SIZE: usize = size_of::<u64>() * 4;
-
-/// The error message if there is a seek error.
-const COULD_NOT_SEEK_TARGET: &str = "Could not seek target file";
-
 /// The error message if there is a write error.
 const COULD_NOT_WRITE_TO_TARGET: &str = "Could not write to target file";
 
 /// The main entry point for the application.
 fn main() {
-    let config_path = if let Some(path) = args().nth(1) {
+    let mut arguments: Vec<String> = args().collect();
+
+    if arguments.get(1).map(String::as_str) == Some("--public-key") {
+        let path = arguments
+            .get(2)
+            .unwrap_or_else(|| print_usage("--public-key needs a key file."));
+        let public_key = ed25519::public_key(&read_secret_key(path));
+
+        for byte in public_key.iter() {
+            print!("{:02x}", byte);
+        }
+        println!();
+
+        return;
+    }
+
+    let version = match arguments.iter().position(|argument| argument == "--format-version") {
+        Some(index) => {
+            let version = arguments
+                .get(index + 1)
+                .and_then(|version| version.parse().ok())
+                .unwrap_or_else(|| print_usage("--format-version needs a version number."));
+
+            if !veos_initramfs::is_supported(version) {
+                print_usage(&format!("Unsupported format version {}.", version));
+            }
+
+            arguments.drain(index..index + 2);
+
+            version
+        },
+        None => CURRENT_VERSION
+    };
+
+    let secret_key = match arguments.iter().position(|argument| argument == "--sign") {
+        Some(index) => {
+            let path = arguments
+                .get(index + 1)
+                .cloned()
+                .unwrap_or_else(|| print_usage("--sign needs a key file."));
+
+            arguments.drain(index..index + 2);
+
+            Some(read_secret_key(&path))
+        },
+        None => None
+    };
+
+    let config_path = if let Some(path) = arguments.get(1).cloned() {
         if Path::new(&path).is_file() {
             path
         } else {
@@ -51,7 +89,7 @@ fn main() {
         print_usage("Not enough arguments supplied.");
     };
 
-    let out_path = if let Some(path) = args().nth(2) {
+    let out_path = if let Some(path) = arguments.get(2).cloned() {
         if !Path::new(&path).exists() || OVERWRITE || FORCE {
             path
         } else {
@@ -61,7 +99,7 @@ fn main() {
         print_usage("Not enough arguments supplied.");
     };
 
-    let base_path = if let Some(path) = args().nth(3) {
+    let base_path = if let Some(path) = arguments.get(3).cloned() {
         if Path::new(&path).is_dir() {
             path
         } else {
@@ -75,105 +113,42 @@ fn main() {
 
     let file_list = get_file_list(&base_path, &content);
 
-    let mut file = File::create(out_path).unwrap_or_exit("Could not create target file");
-
-    write_file_header(&mut file, &file_list).unwrap_or_exit(COULD_NOT_WRITE_TO_TARGET);
+    let signatures = match secret_key {
+        Some(ref secret_key) => sign_files(secret_key, &file_list),
+        None => Vec::new()
+    };
 
-    for (file_num, &(ref original_path, ref actual_path)) in file_list.iter().enumerate() {
-        write_file(&mut file, file_num, original_path, actual_path);
-    }
-}
+    let file = File::create(out_path).unwrap_or_exit("Could not create target file");
 
-/// Writes the file to the initramfs file.
-///
-/// The file name parameter specifies the name within the initramfs, while the file_path parameter specifies the path to the source file.
-fn write_file(file: &mut File, file_num: usize, file_name: &str, file_path: &Path) {
-    let file_metadata_start = FILE_METADATA_OFFSET + file_num * FILE_METADATA_SIZE;
-
-    // Write file name.
-    let name_position = file
-        .seek(SeekFrom::End(0))
-        .unwrap_or_exit(COULD_NOT_SEEK_TARGET);
-    file.write(file_name.as_bytes())
+    let mut writer = Writer::new(file, version, file_list.len() + signatures.len())
         .unwrap_or_exit(COULD_NOT_WRITE_TO_TARGET);
 
-    // Write file name metadata.
-    file.seek(SeekFrom::Start(file_metadata_start as u64))
-        .unwrap_or_exit(COULD_NOT_SEEK_TARGET);
-    file.write_u64::<BigEndian>(name_position)
-        .unwrap_or_exit(COULD_NOT_WRITE_TO_TARGET);
-    file.write_u64::<BigEndian>(file_name.len() as u64)
-        .unwrap_or_exit(COULD_NOT_WRITE_TO_TARGET);
+    for (original_path, actual_path) in &file_list {
+        let mut source_file = File::open(actual_path)
+            .unwrap_or_exit(&format!("Could not open {}", actual_path.display()));
 
-    // Write file content.
-    let content_position = file
-        .seek(SeekFrom::End(0))
-        .unwrap_or_exit(COULD_NOT_SEEK_TARGET);
-    let mut source_file =
-        File::open(file_path).unwrap_or_exit(&format!("Could not open {}", file_path.display()));
-
-    let mut buffer = [0u8; 1024]; // Read a KiB at a time.
-    loop {
-        match source_file.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(num) => {
-                file.write_all(&buffer[0..num])
-                    .unwrap_or_exit(COULD_NOT_WRITE_TO_TARGET);
-            }
-            Err(error) => match error.kind() {
-                ErrorKind::Interrupted => (),
-                _ => exit_with_error(&format!("Could not read {}", file_path.display()), error),
-            },
-        }
+        writer
+            .add_file(original_path, &mut source_file)
+            .unwrap_or_exit(&format!("Could not add {}", actual_path.display()));
     }
 
-    // Write file content metadata.
-    file.seek(SeekFrom::Start(
-        (file_metadata_start + size_of::<u64>() * 2) as u64,
-    )).unwrap_or_exit(COULD_NOT_SEEK_TARGET);
-    file.write_u64::<BigEndian>(content_position)
-        .unwrap_or_exit(COULD_NOT_WRITE_TO_TARGET);
-    file.write_u64::<BigEndian>(source_file
-        .metadata()
-        .unwrap_or_exit(&format!("Could not read length of {}", file_path.display()))
-        .len() as u64)
-        .unwrap_or_exit(COULD_NOT_WRITE_TO_TARGET);
-}
-
-/// Writes the header information to the file.
-///
-/// Returns the size of the header.
-fn write_file_header(file: &mut File, file_list: &Vec<(&str, PathBuf)>) -> io::Result<u64> {
-    // First write the magic number in the header.
-    let mut bytes_written = 0;
-    while bytes_written != size_of_val(&MAGIC) {
-        bytes_written += file.write(&MAGIC[..])?;
+    for (name, signature) in &signatures {
+        writer
+            .add_file(name, &mut &signature[..])
+            .unwrap_or_exit(&format!("Could not add {}", name));
     }
 
-    // Next write the number of files (as a big endian u64).
-    file.write_u64::<BigEndian>(file_list.len() as u64)?;
-
-    // Now the files are listed in the following way:
-    // First u64 (big endian) is the offset (from beginning) of the file name.
-    // Second u64 (big endian) is the length of the file name.
-    // Third u64 (big endian) is the offset (from beginning) of the file content.
-    // Fourth u64 (big endian) is the length of the file content.
-
-    // This function just reserves enough space for the file metadata.
-    let header_len = FILE_METADATA_OFFSET + FILE_METADATA_SIZE * file_list.len();
-    file.set_len(header_len as u64)?;
-
-    Ok(header_len as u64)
+    writer.finish().unwrap_or_exit(COULD_NOT_WRITE_TO_TARGET);
 }
 
 /// Gets a list of all the valid files in the config file.
-fn get_file_list<'a>(base_path: &String, content: &'a String) -> Vec<(&'a str, PathBuf)> {
+fn get_file_list<'a>(base_path: &str, content: &'a str) -> Vec<(&'a str, PathBuf)> {
     let base_path = Path::new(base_path);
     content
         .lines()
         .map(|line| (line, line.trim_matches('/')))
         .map(|(original_line, line)| (original_line, base_path.join(line)))
-        .filter(|&(_, ref path)| {
+        .filter(|(_, path)| {
             if !path.is_file() {
                 eprintln!("File {} not found.", path.display());
                 if !FORCE {
@@ -187,6 +162,46 @@ fn get_file_list<'a>(base_path: &String, content: &'a String) -> Vec<(&'a str, P
         .collect()
 }
 
+/// Reads the secret signing key, which consists of 32 random bytes.
+fn read_secret_key(path: &str) -> SecretKey {
+    let mut content = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut content))
+        .unwrap_or_exit("Could not read the key file");
+
+    if content.len() != SECRET_KEY_SIZE {
+        print_usage(&format!("The key file must contain exactly {} bytes.", SECRET_KEY_SIZE));
+    }
+
+    let mut secret_key = [0; SECRET_KEY_SIZE];
+    secret_key.copy_from_slice(&content);
+
+    secret_key
+}
+
+/// Signs all ELF files in the list.
+///
+/// Returns the names of the signature files together with their contents.
+fn sign_files(secret_key: &SecretKey, file_list: &[(&str, PathBuf)]) -> Vec<(String, Signature)> {
+    let mut signatures = Vec::new();
+
+    for &(original_path, ref actual_path) in file_list {
+        let mut content = Vec::new();
+        File::open(actual_path)
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .unwrap_or_exit(&format!("Could not read {}", actual_path.display()));
+
+        if content.starts_with(ELF_MAGIC) {
+            signatures.push((
+                format!("{}{}", original_path, SIGNATURE_SUFFIX),
+                ed25519::sign(secret_key, &content)
+            ));
+        }
+    }
+
+    signatures
+}
+
 /// Reads the file into a string.
 fn get_content(path: &str) -> io::Result<String> {
     let mut file = File::open(path)?;
@@ -223,11 +238,22 @@ fn exit_with_error<E: Display>(message: &str, error: E) -> ! {
 /// Prints usage information and exits.
 fn print_usage(error: &str) -> ! {
     eprintln!("{}", error);
-    eprintln!("");
+    eprintln!();
     eprintln!("Usage:");
-    eprintln!("mkinitramfs config_path target_path [base_path]");
+    eprintln!(
+        "mkinitramfs [--format-version version] [--sign key_path] config_path target_path \
+         [base_path]"
+    );
+    eprintln!("mkinitramfs --public-key key_path");
     eprintln!("    config_path is the path to the mkinitramfs configuration file.");
     eprintln!("    target_path is the path to the output file.");
     eprintln!("    base_path is the path that all the listed files start from. Default is \"/\".");
+    eprintln!(
+        "    version is the format version to write, from {} to {}. Default is {}.",
+        LEGACY_VERSION, CURRENT_VERSION, CURRENT_VERSION
+    );
+    eprintln!("    key_path is the file with the secret key that ELF files are signed with.");
+    eprintln!("    --public-key prints the public key that belongs to the secret key.");
     exit(1)
 }
+
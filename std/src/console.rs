@@ -0,0 +1,197 @@
+//! Controls the console that the standard streams are connected to.
+//!
+//! In canonical mode, which is the default, the kernel echoes the typed
+//! characters and passes them on line by line, after backspace edited them.
+//! In raw mode every typed character can be read right away and nothing is
+//! echoed, which suits programs like editors that handle the keys themselves.
+//! The console returns to canonical mode once the process reading from it
+//! exits.
+//!
+//! The console also controls the keyboard, whose LEDs follow the lock keys.
+
+use core::mem::size_of;
+use core::slice;
+use error::Error;
+use io::{control, STDOUT};
+
+/// The request that returns the size of the console.
+const GET_SIZE_REQUEST: u32 = 0;
+
+/// The request that returns the position of the cursor.
+const GET_CURSOR_REQUEST: u32 = 1;
+
+/// The request that moves the cursor.
+const SET_CURSOR_REQUEST: u32 = 2;
+
+/// The request that returns the mode.
+const GET_MODE_REQUEST: u32 = 3;
+
+/// The request that sets the mode.
+const SET_MODE_REQUEST: u32 = 4;
+
+/// The request that returns the lock keys that are on.
+const GET_LOCKS_REQUEST: u32 = 5;
+
+/// The request that switches the lock keys.
+const SET_LOCKS_REQUEST: u32 = 6;
+
+/// The request that returns how held keys repeat.
+const GET_REPEAT_REQUEST: u32 = 7;
+
+/// The request that sets how held keys repeat.
+const SET_REPEAT_REQUEST: u32 = 8;
+
+/// The bit of scroll lock in the lock requests.
+const SCROLL_LOCK_BIT: u32 = 1 << 0;
+
+/// The bit of num lock in the lock requests.
+const NUM_LOCK_BIT: u32 = 1 << 1;
+
+/// The bit of caps lock in the lock requests.
+const CAPS_LOCK_BIT: u32 = 1 << 2;
+
+/// A position on the console, counted from the top left corner.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// The row.
+    pub row: u32,
+    /// The column.
+    pub column: u32,
+}
+
+/// The size of the console in characters.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    /// The number of rows.
+    pub rows: u32,
+    /// The number of columns.
+    pub columns: u32,
+}
+
+/// How the console passes on the typed characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Lines are echoed and edited by the kernel.
+    Canonical,
+    /// Characters are passed on unchanged as soon as they are typed.
+    Raw,
+}
+
+/// The lock keys of the keyboard that are on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Locks {
+    /// Whether caps lock is on.
+    pub caps_lock: bool,
+    /// Whether num lock is on.
+    pub num_lock: bool,
+    /// Whether scroll lock is on.
+    pub scroll_lock: bool,
+}
+
+/// How the keyboard repeats held keys.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repeat {
+    /// The number of repetitions per second, from 2 to 30.
+    pub rate: u32,
+    /// The time in milliseconds until the first repetition, from 250 to
+    /// 1000.
+    pub delay: u32,
+}
+
+/// Performs the console request with the given value as its argument.
+fn request<T: Copy>(request: u32, mut value: T) -> Result<T, Error> {
+    let argument =
+        unsafe { slice::from_raw_parts_mut(&mut value as *mut T as *mut u8, size_of::<T>()) };
+
+    control(STDOUT, request, argument)?;
+
+    Ok(value)
+}
+
+/// Returns the number of rows and columns of the console.
+pub fn size() -> Result<Size, Error> {
+    request(
+        GET_SIZE_REQUEST,
+        Size {
+            rows: 0,
+            columns: 0,
+        },
+    )
+}
+
+/// Returns the position at which the next character is written.
+pub fn cursor() -> Result<Position, Error> {
+    request(GET_CURSOR_REQUEST, Position { row: 0, column: 0 })
+}
+
+/// Moves the position at which the next character is written.
+///
+/// Fails with `Error::InvalidArgument` if the position is outside of the
+/// console.
+pub fn set_cursor(position: Position) -> Result<(), Error> {
+    request(SET_CURSOR_REQUEST, position).map(|_| ())
+}
+
+/// Returns the current mode of the console.
+pub fn mode() -> Result<Mode, Error> {
+    match request(GET_MODE_REQUEST, 0u32)? {
+        0 => Ok(Mode::Canonical),
+        _ => Ok(Mode::Raw),
+    }
+}
+
+/// Switches the console to the mode.
+///
+/// Switching to raw mode passes on the characters of an unfinished line.
+pub fn set_mode(mode: Mode) -> Result<(), Error> {
+    let mode = match mode {
+        Mode::Canonical => 0u32,
+        Mode::Raw => 1,
+    };
+
+    request(SET_MODE_REQUEST, mode).map(|_| ())
+}
+
+/// Returns the lock keys that are on.
+pub fn locks() -> Result<Locks, Error> {
+    let bits = request(GET_LOCKS_REQUEST, 0u32)?;
+
+    Ok(Locks {
+        caps_lock: bits & CAPS_LOCK_BIT != 0,
+        num_lock: bits & NUM_LOCK_BIT != 0,
+        scroll_lock: bits & SCROLL_LOCK_BIT != 0,
+    })
+}
+
+/// Switches the lock keys and their LEDs.
+pub fn set_locks(locks: Locks) -> Result<(), Error> {
+    let mut bits = 0;
+
+    if locks.caps_lock {
+        bits |= CAPS_LOCK_BIT;
+    }
+    if locks.num_lock {
+        bits |= NUM_LOCK_BIT;
+    }
+    if locks.scroll_lock {
+        bits |= SCROLL_LOCK_BIT;
+    }
+
+    request(SET_LOCKS_REQUEST, bits).map(|_| ())
+}
+
+/// Returns how the keyboard repeats held keys.
+pub fn repeat() -> Result<Repeat, Error> {
+    request(GET_REPEAT_REQUEST, Repeat { rate: 0, delay: 0 })
+}
+
+/// Changes how the keyboard repeats held keys.
+///
+/// Fails with `Error::InvalidArgument` if the rate or the delay is out of
+/// range. The keyboard may round them to values it supports.
+pub fn set_repeat(repeat: Repeat) -> Result<(), Error> {
+    request(SET_REPEAT_REQUEST, repeat).map(|_| ())
+}
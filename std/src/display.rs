@@ -0,0 +1,540 @@
+//! Allows drawing to the screen through the display server.
+//!
+//! The display server owns the screen. Its clients draw into surfaces, which
+//! are character cells in memory that is shared with the server. After
+//! drawing, a client reports the damaged area of the surface and the server
+//! copies it to the screen. Surfaces are stacked in the order they were
+//! created, the surface with the focus is on top and receives the keyboard
+//! input.
+//!
+//! A client connects by creating two rings to the server. The first one
+//! carries requests to the server, the second one carries events back to the
+//! client. Each process can only have a single connection.
+
+use core::ptr;
+use core::time::Duration;
+use ipc::grant::{self, GrantId};
+use ipc::Ring;
+use service;
+use thread;
+use Error;
+
+/// The name the display server registers its service under.
+pub const SERVICE_NAME: &str = "display";
+
+/// The size of every message in bytes.
+pub const MESSAGE_SIZE: usize = 32;
+
+/// The number of pages of the memory of a surface.
+pub const SURFACE_PAGES: usize = 4;
+
+/// The maximum number of cells of a surface.
+pub const SURFACE_CELLS: usize = SURFACE_PAGES * 4096 / 2;
+
+/// The time to wait before retrying to send a message through a full ring.
+const FULL_RING_WAIT_MS: u64 = 1;
+
+/// A character on the screen.
+///
+/// The low byte is the character in code page 437, the high byte is its VGA
+/// color code.
+pub type Cell = u16;
+
+/// Identifies a surface of a client.
+pub type SurfaceId = u32;
+
+/// Returns the VGA color code for the given foreground and background colors.
+pub fn color_code(foreground: u8, background: u8) -> u8 {
+    (background & 0xf) << 4 | foreground & 0xf
+}
+
+/// Returns the cell for the character with the given color code.
+pub fn cell(character: u8, color_code: u8) -> Cell {
+    Cell::from(character) | Cell::from(color_code) << 8
+}
+
+/// The memory of a surface.
+///
+/// It is shared with the display server, so it must not be used for anything
+/// else afterwards.
+#[repr(C, align(4096))]
+pub struct SurfaceMemory(pub [Cell; SURFACE_CELLS]);
+
+impl SurfaceMemory {
+    /// Creates memory for a surface.
+    pub const fn new() -> SurfaceMemory {
+        SurfaceMemory([0; SURFACE_CELLS])
+    }
+}
+
+/// A rectangle of character cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rectangle {
+    /// The first column.
+    pub x: u16,
+    /// The first row.
+    pub y: u16,
+    /// The number of columns.
+    pub width: u16,
+    /// The number of rows.
+    pub height: u16,
+}
+
+impl Rectangle {
+    /// Returns true if the rectangle contains no cells.
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Returns true if the rectangle contains the given cell.
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x
+            && y >= self.y
+            && u32::from(x) < u32::from(self.x) + u32::from(self.width)
+            && u32::from(y) < u32::from(self.y) + u32::from(self.height)
+    }
+
+    /// Returns the cells that are part of both rectangles.
+    pub fn intersection(&self, other: &Rectangle) -> Rectangle {
+        let left = u32::from(self.x.max(other.x));
+        let top = u32::from(self.y.max(other.y));
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        if left >= right || top >= bottom {
+            Rectangle::default()
+        } else {
+            Rectangle {
+                x: left as u16,
+                y: top as u16,
+                width: (right - left) as u16,
+                height: (bottom - top) as u16,
+            }
+        }
+    }
+
+    /// Returns the smallest rectangle that contains both rectangles.
+    pub fn union(&self, other: &Rectangle) -> Rectangle {
+        if self.is_empty() {
+            return *other;
+        } else if other.is_empty() {
+            return *self;
+        }
+
+        let left = self.x.min(other.x);
+        let top = self.y.min(other.y);
+
+        Rectangle {
+            x: left,
+            y: top,
+            width: (self.right().max(other.right()) - u32::from(left)) as u16,
+            height: (self.bottom().max(other.bottom()) - u32::from(top)) as u16,
+        }
+    }
+
+    /// Returns the column after the last one.
+    fn right(&self) -> u32 {
+        u32::from(self.x) + u32::from(self.width)
+    }
+
+    /// Returns the row after the last one.
+    fn bottom(&self) -> u32 {
+        u32::from(self.y) + u32::from(self.height)
+    }
+
+    /// Packs the rectangle into a single word.
+    fn to_word(&self) -> u64 {
+        u64::from(self.x)
+            | u64::from(self.y) << 16
+            | u64::from(self.width) << 32
+            | u64::from(self.height) << 48
+    }
+
+    /// Unpacks a rectangle packed by `to_word`.
+    fn from_word(word: u64) -> Rectangle {
+        Rectangle {
+            x: word as u16,
+            y: (word >> 16) as u16,
+            width: (word >> 32) as u16,
+            height: (word >> 48) as u16,
+        }
+    }
+}
+
+/// A request from a client to the display server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// Creates a surface that covers the area of the screen.
+    ///
+    /// The memory of the surface is passed as a grant of `SURFACE_PAGES`
+    /// pages. It holds the cells of the surface row by row.
+    CreateSurface {
+        surface: SurfaceId,
+        grant: GrantId,
+        area: Rectangle,
+    },
+    /// Tells that the cells in the area of the surface changed.
+    Damage { surface: SurfaceId, area: Rectangle },
+    /// Removes the surface from the screen.
+    DestroySurface { surface: SurfaceId },
+}
+
+impl Request {
+    /// Encodes the request as a message.
+    pub fn encode(&self) -> [u8; MESSAGE_SIZE] {
+        let words = match *self {
+            Request::CreateSurface {
+                surface,
+                grant,
+                area,
+            } => [header(0, surface), grant, area.to_word(), 0],
+            Request::Damage { surface, area } => [header(1, surface), 0, area.to_word(), 0],
+            Request::DestroySurface { surface } => [header(2, surface), 0, 0, 0],
+        };
+
+        encode_words(&words)
+    }
+
+    /// Decodes a message that contains a request.
+    pub fn decode(message: &[u8; MESSAGE_SIZE]) -> Option<Request> {
+        let words = decode_words(message);
+        let surface = (words[0] >> 32) as SurfaceId;
+
+        match words[0] as u32 {
+            0 => Some(Request::CreateSurface {
+                surface,
+                grant: words[1],
+                area: Rectangle::from_word(words[2]),
+            }),
+            1 => Some(Request::Damage {
+                surface,
+                area: Rectangle::from_word(words[2]),
+            }),
+            2 => Some(Request::DestroySurface { surface }),
+            _ => None,
+        }
+    }
+}
+
+/// An event sent by the display server to a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A key was pressed or released while the surface had the focus.
+    ///
+    /// The scancode is from scancode set 1.
+    Key { surface: SurfaceId, scancode: u8 },
+    /// The surface gained or lost the focus.
+    Focus { surface: SurfaceId, focused: bool },
+}
+
+impl Event {
+    /// Encodes the event as a message.
+    pub fn encode(&self) -> [u8; MESSAGE_SIZE] {
+        let words = match *self {
+            Event::Key { surface, scancode } => [header(0, surface), u64::from(scancode), 0, 0],
+            Event::Focus { surface, focused } => [header(1, surface), focused as u64, 0, 0],
+        };
+
+        encode_words(&words)
+    }
+
+    /// Decodes a message that contains an event.
+    pub fn decode(message: &[u8; MESSAGE_SIZE]) -> Option<Event> {
+        let words = decode_words(message);
+        let surface = (words[0] >> 32) as SurfaceId;
+
+        match words[0] as u32 {
+            0 => Some(Event::Key {
+                surface,
+                scancode: words[1] as u8,
+            }),
+            1 => Some(Event::Focus {
+                surface,
+                focused: words[1] != 0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the first word of a message of the given kind.
+fn header(kind: u32, surface: SurfaceId) -> u64 {
+    u64::from(kind) | u64::from(surface) << 32
+}
+
+/// Encodes the words of a message in little endian byte order.
+fn encode_words(words: &[u64; MESSAGE_SIZE / 8]) -> [u8; MESSAGE_SIZE] {
+    let mut message = [0; MESSAGE_SIZE];
+
+    for (index, byte) in message.iter_mut().enumerate() {
+        *byte = (words[index / 8] >> (8 * (index % 8))) as u8;
+    }
+
+    message
+}
+
+/// Decodes the words of a message in little endian byte order.
+fn decode_words(message: &[u8; MESSAGE_SIZE]) -> [u64; MESSAGE_SIZE / 8] {
+    let mut words = [0; MESSAGE_SIZE / 8];
+
+    for (index, &byte) in message.iter().enumerate() {
+        words[index / 8] |= u64::from(byte) << (8 * (index % 8));
+    }
+
+    words
+}
+
+/// Passes whole messages through a ring.
+#[derive(Debug)]
+pub struct Channel {
+    /// The ring the messages are passed through.
+    ring: Ring,
+    /// The start of a message that was only partially received.
+    received: [u8; MESSAGE_SIZE],
+    /// The number of bytes of the message that were received.
+    received_length: usize,
+}
+
+impl Channel {
+    /// Creates a channel through the ring.
+    pub fn new(ring: Ring) -> Channel {
+        Channel {
+            ring,
+            received: [0; MESSAGE_SIZE],
+            received_length: 0,
+        }
+    }
+
+    /// Returns the ring of the channel.
+    pub fn ring(&self) -> &Ring {
+        &self.ring
+    }
+
+    /// Sends the message and rings the doorbell of the other side, unless the
+    /// ring is full.
+    ///
+    /// Returns false if the ring was full.
+    pub fn try_send(&self, message: &[u8; MESSAGE_SIZE]) -> Result<bool, Error> {
+        if self.ring.free_space() < MESSAGE_SIZE {
+            return Ok(false);
+        }
+
+        self.ring.write(message);
+        self.ring.notify()?;
+
+        Ok(true)
+    }
+
+    /// Sends the message and rings the doorbell of the other side.
+    ///
+    /// While the ring is full, this waits for the other side to make room.
+    pub fn send(&self, message: &[u8; MESSAGE_SIZE]) -> Result<(), Error> {
+        while !self.try_send(message)? {
+            thread::sleep(Duration::from_millis(FULL_RING_WAIT_MS));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the next message, if it was received completely.
+    pub fn receive(&mut self) -> Option<[u8; MESSAGE_SIZE]> {
+        self.received_length += self.ring.read(&mut self.received[self.received_length..]);
+
+        if self.received_length == MESSAGE_SIZE {
+            self.received_length = 0;
+
+            Some(self.received)
+        } else {
+            None
+        }
+    }
+}
+
+/// A surface of the current process.
+pub struct Surface {
+    /// The ID of the surface.
+    id: SurfaceId,
+    /// The area of the screen the surface covers.
+    area: Rectangle,
+    /// The cells of the surface.
+    memory: &'static mut SurfaceMemory,
+}
+
+impl Surface {
+    /// Returns the ID of the surface.
+    pub fn id(&self) -> SurfaceId {
+        self.id
+    }
+
+    /// Returns the number of columns of the surface.
+    pub fn width(&self) -> u16 {
+        self.area.width
+    }
+
+    /// Returns the number of rows of the surface.
+    pub fn height(&self) -> u16 {
+        self.area.height
+    }
+
+    /// Returns the area of the surface, relative to the surface itself.
+    pub fn bounds(&self) -> Rectangle {
+        Rectangle {
+            x: 0,
+            y: 0,
+            width: self.area.width,
+            height: self.area.height,
+        }
+    }
+
+    /// Sets the cell at the given position.
+    ///
+    /// Cells outside of the surface are ignored.
+    pub fn set(&mut self, x: u16, y: u16, cell: Cell) {
+        if self.bounds().contains(x, y) {
+            let index = usize::from(y) * usize::from(self.area.width) + usize::from(x);
+
+            // The server reads the cells concurrently.
+            unsafe {
+                ptr::write_volatile(&mut self.memory.0[index], cell);
+            }
+        }
+    }
+
+    /// Sets all cells in the area to the given cell.
+    pub fn fill(&mut self, area: Rectangle, cell: Cell) {
+        let area = area.intersection(&self.bounds());
+
+        for y in area.y..area.y + area.height {
+            for x in area.x..area.x + area.width {
+                self.set(x, y, cell);
+            }
+        }
+    }
+
+    /// Writes the text starting at the given position with the given color
+    /// code.
+    ///
+    /// Characters that don't fit into the row are cut off.
+    pub fn write_str(&mut self, x: u16, y: u16, text: &str, color: u8) {
+        for (offset, byte) in text.bytes().enumerate() {
+            if offset >= usize::from(self.area.width) {
+                break;
+            }
+
+            self.set(x + offset as u16, y, cell(byte, color));
+        }
+    }
+}
+
+/// A connection to the display server.
+#[derive(Debug)]
+pub struct Display {
+    /// Carries the requests to the server.
+    requests: Channel,
+    /// Carries the events from the server.
+    events: Channel,
+    /// The ID of the display server process.
+    server: u64,
+    /// The ID of the next created surface.
+    next_surface: SurfaceId,
+}
+
+impl Display {
+    /// Connects to the display server.
+    pub fn connect() -> Result<Display, Error> {
+        // The lookup only fails if the server isn't running.
+        let server = service::lookup(SERVICE_NAME)
+            .map_err(|_| Error::NotFound)?
+            .pid;
+
+        // The server tells the rings apart by their order.
+        let requests = Ring::create(1, server)?;
+        let events = Ring::create(1, server)?;
+
+        Ok(Display {
+            requests: Channel::new(requests),
+            events: Channel::new(events),
+            server,
+            next_surface: 0,
+        })
+    }
+
+    /// Returns the descriptor that is readable when events arrive.
+    pub fn descriptor(&self) -> u64 {
+        self.events.ring().descriptor()
+    }
+
+    /// Creates a surface that covers the area of the screen.
+    ///
+    /// The surface is cleared and gets the focus.
+    pub fn create_surface(
+        &mut self,
+        memory: &'static mut SurfaceMemory,
+        area: Rectangle,
+    ) -> Result<Surface, Error> {
+        if usize::from(area.width) * usize::from(area.height) > SURFACE_CELLS {
+            return Err(Error::InvalidArgument);
+        }
+
+        let address = &*memory as *const SurfaceMemory as u64;
+        let grant = grant::grant(self.server, address, SURFACE_PAGES, 0)?;
+        let id = self.next_surface;
+        self.next_surface += 1;
+
+        let mut surface = Surface { id, area, memory };
+        let bounds = surface.bounds();
+        surface.fill(bounds, 0);
+
+        self.requests.send(
+            &Request::CreateSurface {
+                surface: id,
+                grant,
+                area,
+            }
+            .encode(),
+        )?;
+
+        Ok(surface)
+    }
+
+    /// Tells the server that the cells in the area of the surface changed.
+    pub fn damage(&self, surface: &Surface, area: Rectangle) -> Result<(), Error> {
+        self.requests.send(
+            &Request::Damage {
+                surface: surface.id,
+                area,
+            }
+            .encode(),
+        )
+    }
+
+    /// Removes the surface from the screen.
+    pub fn destroy_surface(&self, surface: Surface) -> Result<(), Error> {
+        self.requests.send(
+            &Request::DestroySurface {
+                surface: surface.id,
+            }
+            .encode(),
+        )
+    }
+
+    /// Returns the next event from the server.
+    ///
+    /// If `timeout` is `None` this waits indefinitely. Returns `None` if the
+    /// timeout expired first.
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<Event>, Error> {
+        loop {
+            if let Some(message) = self.events.receive() {
+                if let Some(event) = Event::decode(&message) {
+                    return Ok(Some(event));
+                }
+
+                continue;
+            }
+
+            if !self.events.ring().wait(timeout)? {
+                return Ok(None);
+            }
+        }
+    }
+}
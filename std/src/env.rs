@@ -0,0 +1,196 @@
+//! Inspects the arguments and the environment of the process.
+//!
+//! The kernel places the arguments and the environment variables on the stack
+//! of the first thread as null terminated strings. `_start` receives the
+//! number of arguments and a null terminated array of pointers to them, which
+//! is directly followed by a null terminated array of pointers to the
+//! environment variables in the `key=value` form. The strings stay there for
+//! the whole lifetime of the process, so they are handed out as `&'static str`.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use core::{slice, str};
+
+/// The address of the argument array.
+static ARGUMENTS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The number of arguments.
+static ARGUMENT_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The address of the environment variable array.
+static ENVIRONMENT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The number of environment variables.
+static ENVIRONMENT_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Remembers where the arguments and the environment variables are.
+///
+/// # Safety
+/// - This must only be called by `_start` with the values it received from
+///   the kernel.
+pub(crate) unsafe fn init(argument_count: isize, arguments: *const *const u8) {
+    if arguments.is_null() || argument_count < 0 {
+        return;
+    }
+
+    let environment = arguments.offset(argument_count + 1);
+    let mut environment_count = 0;
+
+    while !(*environment.offset(environment_count)).is_null() {
+        environment_count += 1;
+    }
+
+    ARGUMENTS.store(arguments as usize, Ordering::Release);
+    ARGUMENT_COUNT.store(argument_count as usize, Ordering::Release);
+    ENVIRONMENT.store(environment as usize, Ordering::Release);
+    ENVIRONMENT_COUNT.store(environment_count as usize, Ordering::Release);
+}
+
+/// Returns the arguments the process was started with.
+///
+/// The first argument is usually the path of the executable.
+///
+/// # Panics
+/// The iterator panics if an argument is not valid UTF-8.
+pub fn args() -> Args {
+    Args {
+        pointers: pointers(&ARGUMENTS, &ARGUMENT_COUNT).iter(),
+    }
+}
+
+/// Returns the environment variables of the process as `(key, value)` pairs.
+///
+/// # Panics
+/// The iterator panics if a variable is not valid UTF-8.
+pub fn vars() -> Vars {
+    Vars {
+        pointers: pointers(&ENVIRONMENT, &ENVIRONMENT_COUNT).iter(),
+    }
+}
+
+/// Returns the value of the environment variable with the given key.
+pub fn var(key: &str) -> Result<&'static str, VarError> {
+    for &pointer in pointers(&ENVIRONMENT, &ENVIRONMENT_COUNT) {
+        if let Some((variable_key, value)) = split_variable(unsafe { c_str(pointer) }) {
+            if variable_key == key.as_bytes() {
+                return str::from_utf8(value).map_err(|_| VarError::NotUnicode);
+            }
+        }
+    }
+
+    Err(VarError::NotPresent)
+}
+
+/// Returns the path of the executable of the process.
+///
+/// This is the first argument the process was started with.
+pub fn current_exe() -> Result<&'static str, VarError> {
+    let pointer = pointers(&ARGUMENTS, &ARGUMENT_COUNT)
+        .first()
+        .ok_or(VarError::NotPresent)?;
+
+    str::from_utf8(unsafe { c_str(*pointer) }).map_err(|_| VarError::NotUnicode)
+}
+
+/// The errors that can occur when reading an environment variable.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VarError {
+    /// The variable is not set.
+    NotPresent,
+    /// The variable is not valid UTF-8.
+    NotUnicode,
+}
+
+impl fmt::Display for VarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VarError::NotPresent => write!(f, "environment variable not found"),
+            VarError::NotUnicode => write!(f, "environment variable was not valid unicode"),
+        }
+    }
+}
+
+/// An iterator through the arguments of the process.
+pub struct Args {
+    /// The pointers to the remaining arguments.
+    pointers: slice::Iter<'static, *const u8>,
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        self.pointers
+            .next()
+            .map(|&pointer| to_str(unsafe { c_str(pointer) }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pointers.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Args {}
+
+/// An iterator through the environment variables of the process.
+pub struct Vars {
+    /// The pointers to the remaining variables.
+    pointers: slice::Iter<'static, *const u8>,
+}
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<(&'static str, &'static str)> {
+        // Variables without an `=` are skipped.
+        while let Some(&pointer) = self.pointers.next() {
+            if let Some((key, value)) = split_variable(unsafe { c_str(pointer) }) {
+                return Some((to_str(key), to_str(value)));
+            }
+        }
+
+        None
+    }
+}
+
+/// Splits the variable at the first `=` into the key and the value.
+///
+/// A leading `=` is part of the key, so the key is never empty.
+fn split_variable(variable: &[u8]) -> Option<(&[u8], &[u8])> {
+    variable
+        .iter()
+        .skip(1)
+        .position(|&byte| byte == b'=')
+        .map(|index| (&variable[..index + 1], &variable[index + 2..]))
+}
+
+/// Returns the array of string pointers at the address with the count.
+fn pointers(address: &AtomicUsize, count: &AtomicUsize) -> &'static [*const u8] {
+    let address = address.load(Ordering::Acquire);
+    let count = count.load(Ordering::Acquire);
+
+    if address == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(address as *const *const u8, count) }
+    }
+}
+
+/// Converts the bytes to a string, panicking if they aren't valid UTF-8.
+fn to_str(bytes: &'static [u8]) -> &'static str {
+    str::from_utf8(bytes).expect("The argument or environment variable is not valid UTF-8.")
+}
+
+/// Returns the bytes of the null terminated string at the address.
+///
+/// # Safety
+/// - The address must point to a null terminated string that is never freed.
+unsafe fn c_str(address: *const u8) -> &'static [u8] {
+    let mut length = 0;
+
+    while *address.offset(length) != 0 {
+        length += 1;
+    }
+
+    slice::from_raw_parts(address, length as usize)
+}
@@ -0,0 +1,60 @@
+//! The error type of syscalls that report why they failed.
+
+use core::fmt;
+
+/// The reason a syscall failed.
+///
+/// The kernel reports these as negative return values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The error is not further specified.
+    Unspecified,
+    /// The file doesn't exist.
+    NotFound,
+    /// The file is not a valid executable.
+    InvalidExecutable,
+    /// There is not enough memory or a memory limit was reached.
+    NoMemory,
+    /// The process lacks a capability needed for the operation.
+    PermissionDenied,
+    /// An argument is invalid.
+    InvalidArgument,
+    /// A resource limit other than a memory limit was reached.
+    LimitExceeded,
+    /// The device was removed.
+    NoDevice,
+}
+
+impl Error {
+    /// Converts the raw return value of a syscall to a result.
+    pub(crate) fn from_syscall_result(result: u64) -> Result<u64, Error> {
+        match result as i64 {
+            result if result >= 0 => Ok(result as u64),
+            -2 => Err(Error::NotFound),
+            -3 => Err(Error::InvalidExecutable),
+            -4 => Err(Error::NoMemory),
+            -5 => Err(Error::PermissionDenied),
+            -6 => Err(Error::InvalidArgument),
+            -7 => Err(Error::LimitExceeded),
+            -8 => Err(Error::NoDevice),
+            _ => Err(Error::Unspecified),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            Error::Unspecified => "unspecified error",
+            Error::NotFound => "file not found",
+            Error::InvalidExecutable => "invalid executable",
+            Error::NoMemory => "out of memory",
+            Error::PermissionDenied => "permission denied",
+            Error::InvalidArgument => "invalid argument",
+            Error::LimitExceeded => "resource limit exceeded",
+            Error::NoDevice => "device removed",
+        };
+
+        f.write_str(description)
+    }
+}
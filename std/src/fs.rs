@@ -0,0 +1,103 @@
+//! Accesses files by their path.
+//!
+//! A `File` owns its descriptor and closes it when it's dropped. Reads and
+//! writes advance the seek position of the file, so consecutive reads return
+//! consecutive parts of it.
+
+use core::fmt;
+use io::{self, FileDescriptor, SeekFrom};
+use Error;
+
+/// An open file.
+#[derive(Debug)]
+pub struct File {
+    /// The descriptor of the file.
+    descriptor: FileDescriptor,
+}
+
+impl File {
+    /// Opens the file at the given path.
+    ///
+    /// Relative paths are resolved against the working directory.
+    pub fn open(path: &str) -> Result<File, Error> {
+        File::open_with_flags(path, 0)
+    }
+
+    /// Opens the file at the given path with the given flags, such as
+    /// `io::O_NONBLOCK`.
+    pub fn open_with_flags(path: &str, flags: u32) -> Result<File, Error> {
+        io::open(path, flags).map(|descriptor| File { descriptor })
+    }
+
+    /// Returns the descriptor of the file.
+    pub fn descriptor(&self) -> FileDescriptor {
+        self.descriptor
+    }
+
+    /// Reads from the file until `buffer` is full or the file ends.
+    ///
+    /// Returns the number of read bytes, which is zero at the end of the
+    /// file.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        io::read(self.descriptor, buffer)
+    }
+
+    /// Fills `buffer` from the file.
+    ///
+    /// Fails if the file ends before `buffer` is full.
+    pub fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        if self.read(buffer)? == buffer.len() {
+            Ok(())
+        } else {
+            Err(Error::InvalidArgument)
+        }
+    }
+
+    /// Writes the contents of `buffer` to the file.
+    ///
+    /// Returns the number of written bytes.
+    pub fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+        io::write(self.descriptor, buffer)
+    }
+
+    /// Writes all of `buffer` to the file.
+    pub fn write_all(&mut self, mut buffer: &[u8]) -> Result<(), Error> {
+        while !buffer.is_empty() {
+            match self.write(buffer)? {
+                0 => return Err(Error::Unspecified),
+                written => buffer = &buffer[written..],
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the seek position and returns the new position.
+    pub fn seek(&mut self, position: SeekFrom) -> Result<u64, Error> {
+        io::seek(self.descriptor, position)
+    }
+
+    /// Returns the size of the file in bytes.
+    ///
+    /// The seek position is left unchanged.
+    pub fn len(&mut self) -> Result<u64, Error> {
+        let position = self.seek(SeekFrom::Current(0))?;
+        let length = self.seek(SeekFrom::End(0))?;
+        self.seek(SeekFrom::Start(position))?;
+
+        Ok(length)
+    }
+}
+
+impl fmt::Write for File {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        // There is nothing sensible to do if closing fails.
+        let _ = io::close(self.descriptor);
+    }
+}
@@ -2,10 +2,320 @@
 
 use core::fmt;
 use core::fmt::Write;
+use core::str;
+use core::time::Duration;
+use Error;
 
 /// The number of the print char syscall.
 const PRINT_CHAR_SYSCALL: u64 = 0;
 
+/// The number of the open syscall.
+const OPEN_SYSCALL_NUM: u64 = 7;
+
+/// The number of the poll syscall.
+const POLL_SYSCALL_NUM: u64 = 8;
+
+/// The number of the syscall to create an event queue.
+const EVQ_CREATE_SYSCALL_NUM: u64 = 9;
+
+/// The number of the syscall to control an event queue.
+const EVQ_CTL_SYSCALL_NUM: u64 = 10;
+
+/// The number of the syscall to wait on an event queue.
+const EVQ_WAIT_SYSCALL_NUM: u64 = 11;
+
+/// The number of the sendfile syscall.
+const SENDFILE_SYSCALL_NUM: u64 = 12;
+
+/// The number of the syscall to create a timer.
+const TIMER_CREATE_SYSCALL_NUM: u64 = 38;
+
+/// The number of the syscall to arm or disarm a timer.
+const TIMER_SET_SYSCALL_NUM: u64 = 39;
+
+/// The number of the syscall to read the expirations of a timer.
+const TIMER_READ_SYSCALL_NUM: u64 = 40;
+
+/// The number of the syscall to read from a file.
+const READ_SYSCALL_NUM: u64 = 49;
+
+/// The number of the syscall to write to a file.
+const WRITE_SYSCALL_NUM: u64 = 50;
+
+/// The number of the syscall to close a descriptor.
+const CLOSE_SYSCALL_NUM: u64 = 64;
+
+/// The number of the syscall to move the seek position of a file.
+const SEEK_SYSCALL_NUM: u64 = 65;
+
+/// The number of the syscall to perform a request specific to a file.
+const CONTROL_SYSCALL_NUM: u64 = 69;
+
+/// Operations on the opened file fail instead of blocking.
+pub const O_NONBLOCK: u32 = 1 << 0;
+
+/// Data can be read without blocking.
+pub const POLL_IN: u16 = 1 << 0;
+
+/// Data can be written without blocking.
+pub const POLL_OUT: u16 = 1 << 2;
+
+/// Refers to a file opened by the current process.
+pub type FileDescriptor = u64;
+
+/// The descriptor of the standard input.
+pub const STDIN: FileDescriptor = 0;
+
+/// The descriptor of the standard output.
+pub const STDOUT: FileDescriptor = 1;
+
+/// The descriptor of the standard error output.
+pub const STDERR: FileDescriptor = 2;
+
+/// The position to seek to, relative to a point in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    /// The offset from the start of the file.
+    Start(u64),
+    /// The offset from the end of the file.
+    End(i64),
+    /// The offset from the current position.
+    Current(i64),
+}
+
+/// A file descriptor together with the events to wait for.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PollFd {
+    /// The file descriptor to check.
+    pub descriptor: FileDescriptor,
+    /// The events that are waited for.
+    pub events: u16,
+    /// The events that were ready when `poll` returned.
+    pub ready_events: u16,
+}
+
+/// An event reported by an event queue.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Event {
+    /// The data that was registered together with the descriptor.
+    pub user_data: u64,
+    /// The events that are ready.
+    pub events: u16,
+}
+
+/// Determines when an event queue reports a descriptor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Report the descriptor as long as it is ready.
+    Level,
+    /// Report the descriptor only when it becomes ready.
+    Edge,
+}
+
+/// A kernel queue that reports readiness events of registered descriptors.
+#[derive(Debug)]
+pub struct EventQueue {
+    /// The descriptor of the queue.
+    descriptor: FileDescriptor,
+}
+
+impl EventQueue {
+    /// Creates a new event queue.
+    pub fn new() -> Result<EventQueue, Error> {
+        let result = unsafe { syscall!(EVQ_CREATE_SYSCALL_NUM) };
+        Error::from_syscall_result(result).map(|descriptor| EventQueue { descriptor })
+    }
+
+    /// Registers interest in `events` on `descriptor`.
+    ///
+    /// `user_data` is reported together with the events of the descriptor.
+    pub fn add(
+        &self,
+        descriptor: FileDescriptor,
+        events: u16,
+        trigger: Trigger,
+        user_data: u64,
+    ) -> Result<(), Error> {
+        self.control(0, descriptor, events, trigger, user_data)
+    }
+
+    /// Changes the registration of `descriptor`.
+    pub fn modify(
+        &self,
+        descriptor: FileDescriptor,
+        events: u16,
+        trigger: Trigger,
+        user_data: u64,
+    ) -> Result<(), Error> {
+        self.control(1, descriptor, events, trigger, user_data)
+    }
+
+    /// Removes the registration of `descriptor`.
+    pub fn delete(&self, descriptor: FileDescriptor) -> Result<(), Error> {
+        self.control(2, descriptor, 0, Trigger::Level, 0)
+    }
+
+    /// Waits until events are ready and stores them in `events`.
+    ///
+    /// If `timeout` is `None` this waits indefinitely. Returns the number of
+    /// stored events, which is zero if the timeout expired.
+    pub fn wait(&self, events: &mut [Event], timeout: Option<Duration>) -> Result<usize, Error> {
+        let result = unsafe {
+            syscall!(
+                EVQ_WAIT_SYSCALL_NUM,
+                self.descriptor,
+                events.as_mut_ptr() as u64,
+                events.len() as u64,
+                timeout_to_ms(timeout) as u64
+            )
+        };
+        Error::from_syscall_result(result).map(|result| result as usize)
+    }
+
+    /// Performs the given control operation.
+    fn control(
+        &self,
+        operation: u64,
+        descriptor: FileDescriptor,
+        events: u16,
+        trigger: Trigger,
+        user_data: u64,
+    ) -> Result<(), Error> {
+        let result = unsafe {
+            syscall!(
+                EVQ_CTL_SYSCALL_NUM,
+                self.descriptor,
+                operation,
+                descriptor,
+                events as u64,
+                (trigger == Trigger::Edge) as u64,
+                user_data
+            )
+        };
+        Error::from_syscall_result(result).map(|_| ())
+    }
+}
+
+/// A timer that becomes readable when it expires.
+///
+/// The descriptor of the timer can be used with `poll` and event queues.
+#[derive(Debug)]
+pub struct Timer {
+    /// The descriptor of the timer.
+    descriptor: FileDescriptor,
+}
+
+impl Timer {
+    /// Creates a new disarmed timer.
+    pub fn new() -> Result<Timer, Error> {
+        let result = unsafe { syscall!(TIMER_CREATE_SYSCALL_NUM) };
+        Error::from_syscall_result(result).map(|descriptor| Timer { descriptor })
+    }
+
+    /// Returns the descriptor of the timer.
+    pub fn descriptor(&self) -> FileDescriptor {
+        self.descriptor
+    }
+
+    /// Arms the timer to expire after `initial` and then every `interval`.
+    ///
+    /// Without an interval the timer only expires once. Passing no initial
+    /// duration disarms the timer. Expirations that were not read yet are
+    /// discarded.
+    pub fn set(&self, initial: Option<Duration>, interval: Option<Duration>) -> Result<(), Error> {
+        let result = unsafe {
+            syscall!(
+                TIMER_SET_SYSCALL_NUM,
+                self.descriptor,
+                timer_ms(initial),
+                timer_ms(interval)
+            )
+        };
+        Error::from_syscall_result(result).map(|_| ())
+    }
+
+    /// Waits until the timer expired and returns the number of expirations
+    /// since the last read.
+    ///
+    /// If `timeout` is `None` this waits indefinitely. Returns zero if the
+    /// timeout expired first.
+    pub fn read(&self, timeout: Option<Duration>) -> Result<u64, Error> {
+        let result = unsafe {
+            syscall!(
+                TIMER_READ_SYSCALL_NUM,
+                self.descriptor,
+                timeout_to_ms(timeout) as u64
+            )
+        };
+        Error::from_syscall_result(result)
+    }
+}
+
+impl PollFd {
+    /// Creates a new entry waiting for `events` on `descriptor`.
+    pub fn new(descriptor: FileDescriptor, events: u16) -> PollFd {
+        PollFd {
+            descriptor,
+            events,
+            ready_events: 0,
+        }
+    }
+}
+
+/// The standard input of the process.
+///
+/// For processes started on the console this is the keyboard. The input
+/// goes to the process that read from it last.
+#[derive(Debug, Clone, Copy)]
+pub struct Stdin;
+
+impl Stdin {
+    /// Reads the characters that were typed into `buffer`.
+    ///
+    /// This waits until at least one character was typed and returns how
+    /// many were read.
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        read(STDIN, buffer)
+    }
+
+    /// Reads a line and returns it without the line break.
+    ///
+    /// In the canonical mode of the console, the kernel echoes the typed
+    /// characters and handles backspace, so this only returns once the whole
+    /// line was typed. Characters that don't fit into `buffer` are dropped.
+    pub fn read_line<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a str, Error> {
+        let mut length = 0;
+        let mut character = [0];
+
+        while self.read(&mut character)? == 1 {
+            match character[0] {
+                b'\n' => break,
+                byte if length < buffer.len() => {
+                    buffer[length] = byte;
+                    length += 1;
+                }
+                _ => (),
+            }
+        }
+
+        str::from_utf8(&buffer[..length]).map_err(|_| Error::InvalidArgument)
+    }
+}
+
+/// Returns the standard input of the process.
+pub fn stdin() -> Stdin {
+    Stdin
+}
+
+/// Reads a line from the standard input.
+///
+/// See `Stdin::read_line` for details.
+pub fn read_line(buffer: &mut [u8]) -> Result<&str, Error> {
+    stdin().read_line(buffer)
+}
+
 /// A dummy struct to implement fmt::Write on.
 struct StdOut;
 
@@ -44,3 +354,138 @@ fn print_char(character: char) {
         syscall!(PRINT_CHAR_SYSCALL, character as u64);
     }
 }
+
+/// Opens the file with the given name using the given flags.
+pub fn open(name: &str, flags: u32) -> Result<FileDescriptor, Error> {
+    let name_ptr = name as *const str as *const usize as u64;
+    let result = unsafe { syscall!(OPEN_SYSCALL_NUM, name_ptr, name.len() as u64, flags as u64) };
+
+    Error::from_syscall_result(result)
+}
+
+/// Reads from the file until `buffer` is full.
+///
+/// This waits for files that have no data yet, unless they were opened with
+/// `O_NONBLOCK`. Returns the number of read bytes, which can be less than
+/// the size of `buffer` if the file ended or, for files like the console,
+/// once the data that arrived so far was read.
+pub fn read(descriptor: FileDescriptor, buffer: &mut [u8]) -> Result<usize, Error> {
+    let result = unsafe {
+        syscall!(
+            READ_SYSCALL_NUM,
+            descriptor,
+            buffer.as_mut_ptr() as u64,
+            buffer.len() as u64
+        )
+    };
+
+    Error::from_syscall_result(result).map(|length| length as usize)
+}
+
+/// Writes the contents of `buffer` to the file.
+///
+/// Returns the number of written bytes.
+pub fn write(descriptor: FileDescriptor, buffer: &[u8]) -> Result<usize, Error> {
+    let result = unsafe {
+        syscall!(
+            WRITE_SYSCALL_NUM,
+            descriptor,
+            buffer.as_ptr() as u64,
+            buffer.len() as u64
+        )
+    };
+
+    Error::from_syscall_result(result).map(|length| length as usize)
+}
+
+/// Closes the descriptor.
+pub fn close(descriptor: FileDescriptor) -> Result<(), Error> {
+    let result = unsafe { syscall!(CLOSE_SYSCALL_NUM, descriptor) };
+
+    Error::from_syscall_result(result).map(|_| ())
+}
+
+/// Moves the seek position of the file and returns the new position.
+///
+/// Fails for files that have no seek position, such as the console.
+pub fn seek(descriptor: FileDescriptor, position: SeekFrom) -> Result<u64, Error> {
+    let (offset, whence) = match position {
+        SeekFrom::Start(offset) => (offset, 0),
+        SeekFrom::Current(offset) => (offset as u64, 1),
+        SeekFrom::End(offset) => (offset as u64, 2),
+    };
+
+    let result = unsafe { syscall!(SEEK_SYSCALL_NUM, descriptor, offset, whence) };
+
+    Error::from_syscall_result(result)
+}
+
+/// Performs a request specific to the kind of the file, like `ioctl` on
+/// Unix.
+///
+/// `argument` holds the data of the request and receives its result. It may
+/// be at most 64 bytes long. Fails with `Error::InvalidArgument` if the file
+/// doesn't know the request. See `console` for the requests of the console.
+pub fn control(descriptor: FileDescriptor, request: u32, argument: &mut [u8]) -> Result<(), Error> {
+    let result = unsafe {
+        syscall!(
+            CONTROL_SYSCALL_NUM,
+            descriptor,
+            request as u64,
+            argument.as_mut_ptr() as u64,
+            argument.len() as u64
+        )
+    };
+
+    Error::from_syscall_result(result).map(|_| ())
+}
+
+/// Waits until at least one of the given descriptors is ready.
+///
+/// If `timeout` is `None` this waits indefinitely. Returns the number of
+/// ready descriptors, which is zero if the timeout expired.
+pub fn poll(descriptors: &mut [PollFd], timeout: Option<Duration>) -> Result<usize, Error> {
+    let result = unsafe {
+        syscall!(
+            POLL_SYSCALL_NUM,
+            descriptors.as_mut_ptr() as u64,
+            descriptors.len() as u64,
+            timeout_to_ms(timeout) as u64
+        )
+    };
+    Error::from_syscall_result(result).map(|result| result as usize)
+}
+
+/// Moves up to `count` bytes from `input` to `output` within the kernel.
+///
+/// Returns the number of bytes that were moved, which is zero at the end of
+/// `input`. This waits while either file would block, unless it was opened
+/// with `O_NONBLOCK`.
+pub fn sendfile(
+    output: FileDescriptor,
+    input: FileDescriptor,
+    count: usize,
+) -> Result<usize, Error> {
+    let result = unsafe { syscall!(SENDFILE_SYSCALL_NUM, output, input, count as u64) };
+    Error::from_syscall_result(result).map(|result| result as usize)
+}
+
+/// Converts a timer duration to milliseconds, where zero means no duration.
+fn timer_ms(duration: Option<Duration>) -> u64 {
+    match duration {
+        Some(duration) => timeout_to_ms(Some(duration)).max(1) as u64,
+        None => 0,
+    }
+}
+
+/// Converts a timeout to milliseconds, where a negative value means no timeout.
+pub(crate) fn timeout_to_ms(timeout: Option<Duration>) -> i64 {
+    match timeout {
+        Some(timeout) => timeout
+            .as_secs()
+            .saturating_mul(1000)
+            .saturating_add((timeout.subsec_nanos() / 1_000_000) as u64)
+            .min(i64::max_value() as u64) as i64,
+        None => -1,
+    }
+}
@@ -0,0 +1,52 @@
+//! Allows passing pages to other processes without copying them.
+//!
+//! The granting process creates a grant for some of its pages and passes its
+//! ID to the receiving process, for example through a ring. The receiver
+//! then accepts the grant, which maps the pages into its address space.
+
+use Error;
+
+/// The number of the syscall to grant pages.
+const GRANT_PAGES_SYSCALL_NUM: u64 = 36;
+
+/// The number of the syscall to accept a grant.
+const ACCEPT_GRANT_SYSCALL_NUM: u64 = 37;
+
+/// Replaces the granted pages with zeroed pages in the granting process.
+///
+/// Without this flag, the pages are lent and stay shared between both
+/// processes.
+pub const GRANT_DONATE: u64 = 1 << 0;
+
+/// Maps the granted pages read only in the receiving process.
+pub const GRANT_READ_ONLY: u64 = 1 << 1;
+
+/// The maximum number of pages in a single grant.
+pub const MAX_GRANT_PAGES: usize = 1024;
+
+/// Identifies a grant that was not accepted yet.
+pub type GrantId = u64;
+
+/// Grants `pages` pages starting at the page aligned `address` to the
+/// process `receiver`.
+///
+/// The pages must be part of a single mapping of the current process.
+pub fn grant(receiver: u64, address: u64, pages: usize, flags: u64) -> Result<GrantId, Error> {
+    let result = unsafe {
+        syscall!(
+            GRANT_PAGES_SYSCALL_NUM,
+            receiver,
+            address,
+            pages as u64,
+            flags
+        )
+    };
+    Error::from_syscall_result(result).map(|result| result as GrantId)
+}
+
+/// Accepts the grant with the given ID and returns the address the pages are
+/// mapped at.
+pub fn accept(id: GrantId) -> Result<u64, Error> {
+    let result = unsafe { syscall!(ACCEPT_GRANT_SYSCALL_NUM, id) };
+    Error::from_syscall_result(result)
+}
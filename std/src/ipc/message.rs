@@ -0,0 +1,125 @@
+//! Passes small messages to endpoints of other processes.
+//!
+//! A server creates an endpoint and usually registers it as a service, so
+//! that clients can find it with `service::lookup`. The messages sent to the
+//! endpoints of a process wait in a queue of that process until they are
+//! received. Larger payloads should be passed through rings or grants.
+
+use core::time::Duration;
+use error::Error;
+use io::timeout_to_ms;
+
+/// The number of the syscall to create an endpoint.
+const ENDPOINT_CREATE_SYSCALL_NUM: u64 = 54;
+
+/// The number of the syscall to send a message.
+const MESSAGE_SEND_SYSCALL_NUM: u64 = 55;
+
+/// The number of the syscall to receive a message.
+const MESSAGE_RECEIVE_SYSCALL_NUM: u64 = 56;
+
+/// The maximum size of a message in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 256;
+
+/// The layout of the information about a message returned by the kernel.
+#[repr(C)]
+#[derive(Default)]
+struct MessageInfo {
+    /// The ID of the process that sent the message.
+    sender: u64,
+    /// The length of the message.
+    length: u64,
+}
+
+/// A received message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message {
+    /// The ID of the process that sent the message.
+    ///
+    /// This is 0 if the process is not visible to the current process.
+    pub sender: u64,
+    /// The length of the message.
+    ///
+    /// If this is larger than the buffer, the rest of the message was
+    /// dropped.
+    pub length: usize,
+}
+
+/// An endpoint of the current process that other processes send messages to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Endpoint {
+    /// The ID of the endpoint within the current process.
+    id: u64,
+}
+
+impl Endpoint {
+    /// Creates a new endpoint.
+    pub fn create() -> Result<Endpoint, Error> {
+        let result = unsafe { syscall!(ENDPOINT_CREATE_SYSCALL_NUM) };
+
+        Error::from_syscall_result(result).map(|id| Endpoint { id })
+    }
+
+    /// Returns the ID of the endpoint, which senders pass to `send`.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Receives the oldest message sent to the endpoint into `buffer`.
+    ///
+    /// If `timeout` is `None` this waits indefinitely. Returns `None` if the
+    /// timeout expired first.
+    pub fn receive(
+        &self,
+        buffer: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> Result<Option<Message>, Error> {
+        let mut info = MessageInfo::default();
+        let result = unsafe {
+            syscall!(
+                MESSAGE_RECEIVE_SYSCALL_NUM,
+                self.id,
+                buffer.as_mut_ptr() as u64,
+                buffer.len() as u64,
+                &mut info as *mut MessageInfo as u64,
+                timeout_to_ms(timeout) as u64
+            )
+        };
+
+        Error::from_syscall_result(result).map(|received| {
+            if received > 0 {
+                Some(Message {
+                    sender: info.sender,
+                    length: info.length as usize,
+                })
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Sends `data` to the endpoint with the given ID of the process `pid`.
+///
+/// This waits while the queue of the receiving process is full. If `timeout`
+/// is `None` this waits indefinitely. Returns false if the timeout expired
+/// first.
+pub fn send(
+    pid: u64,
+    endpoint: u64,
+    data: &[u8],
+    timeout: Option<Duration>,
+) -> Result<bool, Error> {
+    let result = unsafe {
+        syscall!(
+            MESSAGE_SEND_SYSCALL_NUM,
+            pid,
+            endpoint,
+            data.as_ptr() as u64,
+            data.len() as u64,
+            timeout_to_ms(timeout) as u64
+        )
+    };
+
+    Error::from_syscall_result(result).map(|sent| sent > 0)
+}
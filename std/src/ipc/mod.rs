@@ -0,0 +1,7 @@
+//! Allows communicating with other processes.
+
+pub mod grant;
+pub mod message;
+mod ring;
+
+pub use self::ring::Ring;
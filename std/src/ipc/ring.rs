@@ -0,0 +1,231 @@
+//! Shared memory ring buffers for bulk data transfers between two processes.
+//!
+//! A ring has a single producer and a single consumer. The producer appends
+//! data at the head index and the consumer removes data at the tail index.
+//! Both indices only ever grow and are taken modulo the data size. After
+//! changing an index, a side can ring the doorbell of the other side to
+//! wake it up.
+
+use core::cmp::min;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use io::{timeout_to_ms, FileDescriptor};
+use Error;
+
+/// The number of the syscall to create a ring.
+const RING_CREATE_SYSCALL_NUM: u64 = 32;
+
+/// The number of the syscall to accept a ring.
+const RING_ACCEPT_SYSCALL_NUM: u64 = 33;
+
+/// The number of the syscall to ring the doorbell of the other side.
+const RING_NOTIFY_SYSCALL_NUM: u64 = 34;
+
+/// The number of the syscall to wait for the doorbell.
+const RING_WAIT_SYSCALL_NUM: u64 = 35;
+
+/// The layout of the information about a ring returned by the kernel.
+#[repr(C)]
+#[derive(Default)]
+struct RingInfo {
+    /// The descriptor referring to the ring.
+    descriptor: u64,
+    /// The address of the header with the indices.
+    header_address: u64,
+    /// The address of the data area.
+    data_address: u64,
+    /// The size of the data area in bytes.
+    data_size: u64,
+    /// The ID of the process on the other side of the ring.
+    peer: u64,
+}
+
+/// The indices at the start of the shared memory.
+///
+/// They are placed on different cache lines, so that both sides don't
+/// contend for the same line.
+#[repr(C)]
+struct Header {
+    /// The total number of bytes written.
+    head: AtomicUsize,
+    /// Separates the head from the tail.
+    _padding: [u64; 7],
+    /// The total number of bytes read.
+    tail: AtomicUsize,
+}
+
+/// A ring buffer shared with another process.
+#[derive(Debug)]
+pub struct Ring {
+    /// The descriptor referring to the ring.
+    descriptor: FileDescriptor,
+    /// The header with the indices.
+    header: *const Header,
+    /// The start of the data area.
+    data: *mut u8,
+    /// The size of the data area.
+    size: usize,
+    /// The ID of the process on the other side.
+    peer: u64,
+}
+
+impl Ring {
+    /// Creates a ring with `data_pages` pages of data and offers it to the
+    /// process `peer`.
+    ///
+    /// The ring can be used right away, even before `peer` accepted it.
+    pub fn create(data_pages: usize, peer: u64) -> Result<Ring, Error> {
+        let mut info = RingInfo::default();
+        let info_ptr = &mut info as *mut RingInfo as u64;
+        let result =
+            unsafe { syscall!(RING_CREATE_SYSCALL_NUM, data_pages as u64, peer, info_ptr) };
+        Error::from_syscall_result(result).map(|_| Ring::from_info(&info))
+    }
+
+    /// Accepts the oldest ring offered to the current process.
+    ///
+    /// If `timeout` is `None` this waits indefinitely. Returns `None` if the
+    /// timeout expired first.
+    pub fn accept(timeout: Option<Duration>) -> Result<Option<Ring>, Error> {
+        let mut info = RingInfo::default();
+        let info_ptr = &mut info as *mut RingInfo as u64;
+        let result = unsafe {
+            syscall!(
+                RING_ACCEPT_SYSCALL_NUM,
+                info_ptr,
+                timeout_to_ms(timeout) as u64
+            )
+        };
+        Error::from_syscall_result(result).map(|accepted| {
+            if accepted == 0 {
+                None
+            } else {
+                Some(Ring::from_info(&info))
+            }
+        })
+    }
+
+    /// Creates the ring described by the information from the kernel.
+    fn from_info(info: &RingInfo) -> Ring {
+        Ring {
+            descriptor: info.descriptor,
+            header: info.header_address as *const Header,
+            data: info.data_address as *mut u8,
+            size: info.data_size as usize,
+            peer: info.peer,
+        }
+    }
+
+    /// Returns the descriptor of the ring.
+    ///
+    /// The descriptor is readable while the doorbell is rung, so it can be
+    /// used with `poll` and event queues.
+    pub fn descriptor(&self) -> FileDescriptor {
+        self.descriptor
+    }
+
+    /// Returns the ID of the process on the other side of the ring.
+    ///
+    /// This is 0 if the process is not visible to the current process.
+    pub fn peer(&self) -> u64 {
+        self.peer
+    }
+
+    /// Returns the size of the data area in bytes.
+    pub fn capacity(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the number of bytes that can currently be written.
+    ///
+    /// This must only be called by the producer.
+    pub fn free_space(&self) -> usize {
+        let head = self.header().head.load(Ordering::Relaxed);
+        let tail = self.header().tail.load(Ordering::Acquire);
+
+        self.size - head.wrapping_sub(tail)
+    }
+
+    /// Returns the header of the ring.
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    /// Writes as much of `data` as fits into the ring and returns the number
+    /// of written bytes.
+    ///
+    /// This must only be called by the producer.
+    pub fn write(&self, data: &[u8]) -> usize {
+        let head = self.header().head.load(Ordering::Relaxed);
+        let tail = self.header().tail.load(Ordering::Acquire);
+        let length = min(data.len(), self.size - head.wrapping_sub(tail));
+
+        self.copy(head, length, |ring, position, offset, count| unsafe {
+            ptr::copy_nonoverlapping(data[offset..].as_ptr(), ring.add(position), count);
+        });
+
+        self.header()
+            .head
+            .store(head.wrapping_add(length), Ordering::Release);
+
+        length
+    }
+
+    /// Reads as much data as is available into `buffer` and returns the
+    /// number of read bytes.
+    ///
+    /// This must only be called by the consumer.
+    pub fn read(&self, buffer: &mut [u8]) -> usize {
+        let tail = self.header().tail.load(Ordering::Relaxed);
+        let head = self.header().head.load(Ordering::Acquire);
+        let length = min(buffer.len(), head.wrapping_sub(tail));
+
+        self.copy(tail, length, |ring, position, offset, count| unsafe {
+            ptr::copy_nonoverlapping(ring.add(position), buffer[offset..].as_mut_ptr(), count);
+        });
+
+        self.header()
+            .tail
+            .store(tail.wrapping_add(length), Ordering::Release);
+
+        length
+    }
+
+    /// Calls `copy` for the at most two parts of the data area that
+    /// `length` bytes starting at `index` occupy.
+    fn copy<F>(&self, index: usize, length: usize, mut copy: F)
+    where
+        F: FnMut(*mut u8, usize, usize, usize),
+    {
+        let position = index % self.size;
+        let first_part = min(length, self.size - position);
+
+        copy(self.data, position, 0, first_part);
+
+        if first_part < length {
+            copy(self.data, 0, first_part, length - first_part);
+        }
+    }
+
+    /// Rings the doorbell of the other side.
+    pub fn notify(&self) -> Result<(), Error> {
+        let result = unsafe { syscall!(RING_NOTIFY_SYSCALL_NUM, self.descriptor) };
+        Error::from_syscall_result(result).map(|_| ())
+    }
+
+    /// Waits until the other side rings the doorbell and resets it.
+    ///
+    /// If `timeout` is `None` this waits indefinitely. Returns false if the
+    /// timeout expired first.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool, Error> {
+        let result = unsafe {
+            syscall!(
+                RING_WAIT_SYSCALL_NUM,
+                self.descriptor,
+                timeout_to_ms(timeout) as u64
+            )
+        };
+        Error::from_syscall_result(result).map(|result| result > 0)
+    }
+}
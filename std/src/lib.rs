@@ -3,6 +3,8 @@
 #![feature(lang_items)]
 #![feature(panic_implementation)]
 #![feature(naked_functions)]
+#![feature(const_fn)]
+#![feature(integer_atomics)]
 #![no_std]
 
 /// Makes a syscall with the given arguments.
@@ -95,11 +97,29 @@ macro_rules! syscall {
 
 #[macro_use]
 pub mod io;
+pub mod console;
+pub mod display;
+pub mod env;
+mod error;
+pub mod fs;
+pub mod ipc;
+pub mod mmio;
+pub mod power;
 pub mod process;
+pub mod random;
+pub mod service;
+pub mod signal;
+pub mod sync;
+pub mod syscall;
+pub mod sysinfo;
 pub mod thread;
+pub mod time;
+pub mod trace;
+
+pub use error::Error;
 
 use core::panic::PanicInfo;
-use process::exit;
+use process::{exit, exit_with_code, PANIC_EXIT_CODE};
 
 extern "Rust" {
     /// The function that the program provides as a start.
@@ -111,8 +131,9 @@ extern "Rust" {
 /// This should perform initialization and call main. After main returns, it should exit.
 #[start]
 #[no_mangle]
-pub fn _start(_: isize, _: *const *const u8) -> isize {
+pub fn _start(argument_count: isize, arguments: *const *const u8) -> isize {
     unsafe {
+        env::init(argument_count, arguments);
         main();
     }
     exit();
@@ -125,10 +146,10 @@ extern "C" fn eh_personality() {
 
 /// The panic handler of the program.
 ///
-/// This exits after printing some debug information.
+/// This exits with `PANIC_EXIT_CODE` after printing some debug information.
 #[panic_implementation]
 #[no_mangle]
 pub extern "C" fn panic_fmt(info: &PanicInfo) -> ! {
     println!("{}", info);
-    exit();
+    exit_with_code(PANIC_EXIT_CODE);
 }
@@ -0,0 +1,37 @@
+//! Maps the memory of devices into the current process.
+//!
+//! Drivers and the display server access memory mapped registers and frame
+//! buffers directly. A process may only map the physical memory areas that
+//! its server manifest grants it with `mmio` lines.
+
+use error::Error;
+
+/// The number of the syscall to map device memory.
+const MAP_DEVICE_MEMORY_SYSCALL_NUM: u64 = 68;
+
+/// Decides how accesses to device memory are cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Every access goes to the device in program order, which suits
+    /// registers.
+    Uncached,
+    /// Writes may be combined and delayed, which suits frame buffers.
+    WriteCombining,
+}
+
+/// Maps `length` bytes of device memory at the page aligned physical
+/// `address` and returns the address of the mapping.
+///
+/// The length is rounded up to whole pages. Fails with
+/// `Error::PermissionDenied` if the memory isn't granted to the process or
+/// is RAM.
+pub fn map(address: u64, length: usize, cache_mode: CacheMode) -> Result<*mut u8, Error> {
+    let mode = match cache_mode {
+        CacheMode::Uncached => 0u64,
+        CacheMode::WriteCombining => 1,
+    };
+
+    let result = unsafe { syscall!(MAP_DEVICE_MEMORY_SYSCALL_NUM, address, length as u64, mode) };
+
+    Error::from_syscall_result(result).map(|address| address as *mut u8)
+}
@@ -0,0 +1,75 @@
+//! This module controls the power state of the machine.
+
+use error::Error;
+
+/// The number of the syscall to suspend the machine.
+const SUSPEND_SYSCALL_NUM: u64 = 51;
+
+/// The number of the syscall to select the CPU frequency governor.
+const SET_GOVERNOR_SYSCALL_NUM: u64 = 52;
+
+/// The number of the syscall to take a CPU offline.
+const CPU_DOWN_SYSCALL_NUM: u64 = 70;
+
+/// The number of the syscall to bring a CPU online.
+const CPU_UP_SYSCALL_NUM: u64 = 71;
+
+/// Decides how the kernel scales the frequency of the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Governor {
+    /// Always runs the CPU at its highest frequency.
+    Performance,
+    /// Always runs the CPU at its lowest frequency.
+    Powersave,
+    /// Adjusts the frequency to the load of the CPU.
+    OnDemand,
+}
+
+/// Suspends the machine to RAM and returns once it woke up again.
+///
+/// This requires the `CAP_POWER` capability and `suspend=on` on the kernel
+/// command line. Devices whose state the kernel doesn't restore may not work
+/// after waking up.
+pub fn suspend() -> Result<(), Error> {
+    let result = unsafe { syscall!(SUSPEND_SYSCALL_NUM) };
+
+    Error::from_syscall_result(result).map(|_| ())
+}
+
+/// Selects the governor that scales the frequency of the CPU.
+///
+/// This requires the `CAP_POWER` capability. The current state can be read
+/// from `/proc/cpufreq`.
+pub fn set_governor(governor: Governor) -> Result<(), Error> {
+    let number = match governor {
+        Governor::Performance => 0u64,
+        Governor::Powersave => 1,
+        Governor::OnDemand => 2,
+    };
+
+    let result = unsafe { syscall!(SET_GOVERNOR_SYSCALL_NUM, number) };
+
+    Error::from_syscall_result(result).map(|_| ())
+}
+
+/// Takes the CPU with the given ID offline.
+///
+/// This requires the `CAP_CPU_HOTPLUG` capability. The threads of the CPU
+/// move to the other CPUs before this returns. The boot CPU, CPU 0, can't be
+/// taken offline.
+pub fn cpu_down(cpu: usize) -> Result<(), Error> {
+    let result = unsafe { syscall!(CPU_DOWN_SYSCALL_NUM, cpu as u64) };
+
+    Error::from_syscall_result(result).map(|_| ())
+}
+
+/// Brings the CPU with the given ID online.
+///
+/// This requires the `CAP_CPU_HOTPLUG` capability. CPUs other than the boot
+/// CPU are offline until they are brought online. Fails with
+/// `Error::NoDevice` if the CPU doesn't start.
+pub fn cpu_up(cpu: usize) -> Result<(), Error> {
+    let result = unsafe { syscall!(CPU_UP_SYSCALL_NUM, cpu as u64) };
+
+    Error::from_syscall_result(result).map(|_| ())
+}
@@ -1,5 +1,9 @@
 //! Handles process related system calls.
 
+use core::time::Duration;
+use io::timeout_to_ms;
+use Error;
+
 /// The number of the exit syscall.
 const EXIT_SYSCALL_NUM: u64 = 1;
 
@@ -9,6 +13,128 @@ const GET_PID_SYSCALL_NUM: u64 = 2;
 /// The number of the exec syscall.
 const EXEC_SYSCALL_NUM: u64 = 3;
 
+/// The number of the syscall to get a resource limit.
+const GET_RESOURCE_LIMIT_SYSCALL_NUM: u64 = 13;
+
+/// The number of the syscall to set a resource limit.
+const SET_RESOURCE_LIMIT_SYSCALL_NUM: u64 = 14;
+
+/// The number of the syscall to create a resource group.
+const CREATE_RESOURCE_GROUP_SYSCALL_NUM: u64 = 15;
+
+/// The number of the syscall to change the limits of a resource group.
+const SET_RESOURCE_GROUP_LIMITS_SYSCALL_NUM: u64 = 16;
+
+/// The number of the syscall to move the current process to a resource group.
+const JOIN_RESOURCE_GROUP_SYSCALL_NUM: u64 = 17;
+
+/// The exec flag that starts the new process in a new process ID namespace.
+///
+/// The new process sees itself as process 1 and can't see any process
+/// outside of its namespace.
+pub const NEW_PID_NAMESPACE: u64 = 1 << 0;
+
+/// The exec flag that keeps the new process from using huge pages.
+///
+/// Without it, large anonymous memory regions of the new process may be
+/// backed by huge pages, unless they are disabled for the whole system.
+pub const NO_HUGE_PAGES: u64 = 1 << 1;
+
+/// The number of the syscall to change the root directory.
+const SET_ROOT_SYSCALL_NUM: u64 = 18;
+
+/// The number of the syscall to drop capabilities.
+const DROP_CAPABILITIES_SYSCALL_NUM: u64 = 19;
+
+/// The number of the syscall to spawn servers.
+const SPAWN_SERVER_SYSCALL_NUM: u64 = 29;
+
+/// The number of the syscall to check the page tables.
+const CHECK_PAGE_TABLES_SYSCALL_NUM: u64 = 43;
+
+/// The number of the syscall to dump the outstanding kernel allocations.
+const DUMP_ALLOCATIONS_SYSCALL_NUM: u64 = 47;
+
+/// The number of the syscall to dump the lock statistics of the kernel.
+const DUMP_LOCK_STATS_SYSCALL_NUM: u64 = 59;
+
+/// The number of the syscall to dump the call counts of kernel functions.
+const DUMP_COVERAGE_SYSCALL_NUM: u64 = 66;
+
+/// The number of the syscall to exit with an exit code.
+const EXIT_WITH_CODE_SYSCALL_NUM: u64 = 57;
+
+/// The number of the syscall to wait for a child process.
+const WAIT_SYSCALL_NUM: u64 = 58;
+
+/// The number of the syscall to fork the current process.
+const FORK_SYSCALL_NUM: u64 = 60;
+
+/// The capability to change the root directory.
+pub const CAP_SET_ROOT: u64 = 1 << 0;
+
+/// The capability to trace other processes.
+pub const CAP_TRACE: u64 = 1 << 1;
+
+/// The capability to spawn servers from manifests.
+pub const CAP_SPAWN_SERVER: u64 = 1 << 2;
+
+/// The capability to register services under any name.
+pub const CAP_REGISTER_SERVICE: u64 = 1 << 3;
+
+/// The capability to suspend the machine.
+pub const CAP_POWER: u64 = 1 << 4;
+
+/// The capability to take CPUs offline and bring them back online.
+pub const CAP_CPU_HOTPLUG: u64 = 1 << 5;
+
+/// The capability to raise the priority of threads above the default
+/// priority.
+pub const CAP_RAISE_PRIORITY: u64 = 1 << 6;
+
+/// The code a process exits with after it panicked.
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+/// The ID of a resource group.
+pub type GroupId = u64;
+
+/// The ID of the root resource group, which contains all other groups.
+pub const ROOT_GROUP: GroupId = 0;
+
+/// The CPU shares that correspond to the default time slice.
+pub const DEFAULT_CPU_SHARES: u32 = 1024;
+
+/// The resources of a process that can be limited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    /// The total size of the address space in bytes.
+    AddressSpaceSize = 0,
+    /// The amount of open file descriptors.
+    OpenFiles = 1,
+    /// The amount of threads.
+    Threads = 2,
+    /// The CPU time used by all threads in seconds.
+    ///
+    /// Exceeding the soft limit raises a signal, exceeding the hard limit
+    /// kills the process.
+    CpuTime = 3,
+    /// The size of the core dump written when the process crashes in bytes.
+    ///
+    /// The soft limit is zero by default, so no dumps are written. The dumps
+    /// can be read from `/core/<pid>`.
+    CoreSize = 4,
+}
+
+/// A limit on a resource.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limit {
+    /// The limit that is currently enforced.
+    pub soft: u64,
+    /// The value up to which the soft limit may be raised.
+    pub hard: u64,
+}
+
 /// The possible types of errors that are process related.
 #[derive(Debug)]
 pub enum ProcessError {
@@ -16,7 +142,28 @@ pub enum ProcessError {
     Unspecified,
 }
 
+/// Describes how a child process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process exited with the given code.
+    Exited(i32),
+    /// The process was killed, for example because it crashed.
+    Killed,
+}
+
+/// The layout of how a child process ended as returned by the kernel.
+#[repr(C)]
+#[derive(Default)]
+struct WaitStatus {
+    /// 1 if the process exited and 0 if it was killed.
+    exited: u32,
+    /// The code the process exited with.
+    code: i32,
+}
+
 /// Exits the current process.
+///
+/// This is the same as exiting with the code 0.
 pub fn exit() -> ! {
     unsafe {
         syscall!(EXIT_SYSCALL_NUM);
@@ -24,18 +171,269 @@ pub fn exit() -> ! {
     unreachable!();
 }
 
+/// Exits the current process with the given code.
+///
+/// The parent of the process learns the code by waiting for it.
+pub fn exit_with_code(code: i32) -> ! {
+    unsafe {
+        syscall!(EXIT_WITH_CODE_SYSCALL_NUM, code as u64);
+    }
+    unreachable!();
+}
+
+/// Waits until the child process with the given ID ended and returns how it
+/// ended.
+///
+/// The child process is removed afterwards, so it can only be waited for
+/// once. If `timeout` is `None` this waits indefinitely. Returns `None` if
+/// the timeout expired first.
+pub fn wait(pid: u64, timeout: Option<Duration>) -> Result<Option<ExitStatus>, Error> {
+    let mut status = WaitStatus::default();
+    let result = unsafe {
+        syscall!(
+            WAIT_SYSCALL_NUM,
+            pid,
+            &mut status as *mut WaitStatus as u64,
+            timeout_to_ms(timeout) as u64
+        )
+    };
+
+    Error::from_syscall_result(result).map(|ended| {
+        if ended == 0 {
+            None
+        } else if status.exited != 0 {
+            Some(ExitStatus::Exited(status.code))
+        } else {
+            Some(ExitStatus::Killed)
+        }
+    })
+}
+
 /// Returns the ID of the current process.
 pub fn get_pid() -> u64 {
     unsafe { syscall!(GET_PID_SYSCALL_NUM) as u64 }
 }
 
 /// Creates a new process from the given executable.
-pub fn exec(name: &str) -> Result<u64, ProcessError> {
+pub fn exec(name: &str) -> Result<u64, Error> {
+    exec_with_flags(name, 0)
+}
+
+/// Executes the given file as a new process using the given flags.
+///
+/// The supported flags are `NEW_PID_NAMESPACE` and `NO_HUGE_PAGES`.
+pub fn exec_with_flags(name: &str, flags: u64) -> Result<u64, Error> {
     let name_ptr = name as *const str as *const usize as u64;
-    let result = unsafe { syscall!(EXEC_SYSCALL_NUM, name_ptr, name.len() as u64) as i64 };
+    let result = unsafe { syscall!(EXEC_SYSCALL_NUM, name_ptr, name.len() as u64, flags) };
+
+    Error::from_syscall_result(result)
+}
+
+/// Creates a copy of the current process.
+///
+/// Only the calling thread is copied. The memory of the copy is shared with
+/// the current process until either of them writes to it. Descriptors are
+/// not copied, the new process starts with fresh standard streams.
+/// Returns the ID of the new process in the current process and zero in the
+/// new process.
+pub fn fork() -> Result<u64, Error> {
+    let result = unsafe { syscall!(FORK_SYSCALL_NUM) };
+
+    Error::from_syscall_result(result)
+}
+
+/// Spawns the server described by the manifest at the given path.
+///
+/// The server gets exactly the capabilities and hardware resources listed in
+/// its manifest. This requires the `CAP_SPAWN_SERVER` capability and all
+/// capabilities the server needs.
+pub fn spawn_server(manifest: &str) -> Result<u64, Error> {
+    let manifest_ptr = manifest as *const str as *const usize as u64;
+    let result = unsafe {
+        syscall!(
+            SPAWN_SERVER_SYSCALL_NUM,
+            manifest_ptr,
+            manifest.len() as u64
+        )
+    };
+
+    Error::from_syscall_result(result)
+}
+
+/// Returns the limit of the given resource for the current process.
+pub fn get_limit(resource: Resource) -> Result<Limit, ProcessError> {
+    let mut limit = Limit::default();
+    let limit_ptr = &mut limit as *mut Limit as u64;
+    let result =
+        unsafe { syscall!(GET_RESOURCE_LIMIT_SYSCALL_NUM, resource as u64, limit_ptr) as i64 };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(limit)
+    }
+}
+
+/// Sets the limit of the given resource for the current process.
+///
+/// The soft limit may not exceed the hard limit and the hard limit can only
+/// be lowered.
+pub fn set_limit(resource: Resource, limit: Limit) -> Result<(), ProcessError> {
+    let result = unsafe {
+        syscall!(
+            SET_RESOURCE_LIMIT_SYSCALL_NUM,
+            resource as u64,
+            limit.soft,
+            limit.hard
+        ) as i64
+    };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Creates a new resource group as a subgroup of `parent`.
+///
+/// All processes in the group and its subgroups together may not use more
+/// than `memory_limit` bytes. The time slices of the processes in the group
+/// are scaled by `cpu_shares` relative to `DEFAULT_CPU_SHARES`, but never
+/// exceed the shares of the parent. The parent must be the group of the
+/// current process or one of its subgroups.
+pub fn create_resource_group(
+    name: &str,
+    parent: GroupId,
+    memory_limit: u64,
+    cpu_shares: u32,
+) -> Result<GroupId, ProcessError> {
+    let name_ptr = name as *const str as *const usize as u64;
+    let result = unsafe {
+        syscall!(
+            CREATE_RESOURCE_GROUP_SYSCALL_NUM,
+            name_ptr,
+            name.len() as u64,
+            parent,
+            memory_limit,
+            cpu_shares as u64
+        ) as i64
+    };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(result as GroupId)
+    }
+}
+
+/// Changes the limits of the given resource group.
+///
+/// Only subgroups of the group of the current process can be changed.
+pub fn set_resource_group_limits(
+    group: GroupId,
+    memory_limit: u64,
+    cpu_shares: u32,
+) -> Result<(), ProcessError> {
+    let result = unsafe {
+        syscall!(
+            SET_RESOURCE_GROUP_LIMITS_SYSCALL_NUM,
+            group,
+            memory_limit,
+            cpu_shares as u64
+        ) as i64
+    };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Moves the current process to the given resource group.
+///
+/// The group must be the group of the current process or one of its
+/// subgroups, and its memory limit must leave room for the current process.
+/// Processes created by the current process afterwards are placed in the
+/// same group.
+pub fn join_resource_group(group: GroupId) -> Result<(), ProcessError> {
+    let result = unsafe { syscall!(JOIN_RESOURCE_GROUP_SYSCALL_NUM, group) as i64 };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Changes the root directory of the current process.
+///
+/// The path is resolved relative to the current root, so a process can
+/// never leave its root. New processes inherit the root directory.
+/// This requires the `CAP_SET_ROOT` capability.
+pub fn set_root(path: &str) -> Result<(), ProcessError> {
+    let path_ptr = path as *const str as *const usize as u64;
+    let result = unsafe { syscall!(SET_ROOT_SYSCALL_NUM, path_ptr, path.len() as u64) as i64 };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Drops the given capabilities of the current process.
+///
+/// Dropped capabilities can not be regained and are not passed on to new
+/// processes.
+pub fn drop_capabilities(capabilities: u64) {
+    unsafe {
+        syscall!(DROP_CAPABILITIES_SYSCALL_NUM, capabilities);
+    }
+}
+
+/// Verifies the invariants of the page tables of the current process.
+///
+/// The kernel panics if an invariant is violated. This fails if the kernel
+/// was built without page table checks.
+pub fn check_page_tables() -> Result<(), ProcessError> {
+    let result = unsafe { syscall!(CHECK_PAGE_TABLES_SYSCALL_NUM) as i64 };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Makes the kernel log its outstanding allocations grouped by call site.
+///
+/// This fails if the kernel was built without allocation tracking.
+pub fn dump_kernel_allocations() -> Result<(), ProcessError> {
+    let result = unsafe { syscall!(DUMP_ALLOCATIONS_SYSCALL_NUM) as i64 };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Makes the kernel log the contention statistics of its most contended
+/// locks.
+///
+/// This fails if the kernel was built without lock statistics.
+pub fn dump_kernel_lock_stats() -> Result<(), ProcessError> {
+    let result = unsafe { syscall!(DUMP_LOCK_STATS_SYSCALL_NUM) as i64 };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Makes the kernel write the call counts of its instrumented functions to
+/// the serial port.
+///
+/// This fails if the kernel was built without coverage counters.
+pub fn dump_kernel_coverage() -> Result<(), ProcessError> {
+    let result = unsafe { syscall!(DUMP_COVERAGE_SYSCALL_NUM) as i64 };
     if result < 0 {
         Err(ProcessError::Unspecified)
     } else {
-        Ok(result as u64)
+        Ok(())
     }
 }
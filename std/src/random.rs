@@ -0,0 +1,34 @@
+//! This module provides random bytes from the entropy pool of the kernel.
+
+use error::Error;
+
+/// The number of the syscall to get random bytes.
+const GET_RANDOM_SYSCALL_NUM: u64 = 48;
+
+/// Fills the buffer with random bytes.
+///
+/// This never blocks. Shortly after boot the kernel may not have gathered
+/// enough entropy yet, so the bytes should not be used for long-lived keys
+/// then.
+pub fn fill(buffer: &mut [u8]) -> Result<(), Error> {
+    let result = unsafe {
+        syscall!(
+            GET_RANDOM_SYSCALL_NUM,
+            buffer.as_mut_ptr() as u64,
+            buffer.len() as u64
+        )
+    };
+
+    Error::from_syscall_result(result).map(|_| ())
+}
+
+/// Returns a random `u64`.
+pub fn random_u64() -> Result<u64, Error> {
+    let mut bytes = [0; 8];
+    fill(&mut bytes)?;
+
+    Ok(bytes
+        .iter()
+        .enumerate()
+        .fold(0, |value, (i, &byte)| value | u64::from(byte) << (8 * i)))
+}
@@ -0,0 +1,69 @@
+//! Allows servers to register named services and clients to find them.
+//!
+//! A service is identified by the process providing it and an endpoint
+//! within that process.
+
+use process::ProcessError;
+
+/// The number of the syscall to register a service.
+const REGISTER_SERVICE_SYSCALL_NUM: u64 = 30;
+
+/// The number of the syscall to look up a service.
+const LOOKUP_SERVICE_SYSCALL_NUM: u64 = 31;
+
+/// The maximum length of a service name.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// A service registered by a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Service {
+    /// The ID of the process providing the service.
+    pub pid: u64,
+    /// The endpoint of the service within the process.
+    pub endpoint: u64,
+}
+
+/// Registers the given endpoint of the current process under the name.
+///
+/// This fails if the name is already registered or the current process may
+/// not use the name. Names are granted by the manifest of a server or by the
+/// `CAP_REGISTER_SERVICE` capability.
+pub fn register(name: &str, endpoint: u64) -> Result<(), ProcessError> {
+    let name_ptr = name as *const str as *const usize as u64;
+    let result = unsafe {
+        syscall!(
+            REGISTER_SERVICE_SYSCALL_NUM,
+            name_ptr,
+            name.len() as u64,
+            endpoint
+        ) as i64
+    };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the service registered under the given name.
+pub fn lookup(name: &str) -> Result<Service, ProcessError> {
+    let name_ptr = name as *const str as *const usize as u64;
+    let mut endpoint = 0u64;
+    let endpoint_ptr = &mut endpoint as *mut u64 as u64;
+    let result = unsafe {
+        syscall!(
+            LOOKUP_SERVICE_SYSCALL_NUM,
+            name_ptr,
+            name.len() as u64,
+            endpoint_ptr
+        ) as i64
+    };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(Service {
+            pid: result as u64,
+            endpoint,
+        })
+    }
+}
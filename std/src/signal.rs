@@ -0,0 +1,55 @@
+//! This module allows receiving signals through descriptors.
+//!
+//! The descriptor of a signal descriptor becomes readable while one of its
+//! signals is pending, so signals can be handled in the same event loop as
+//! other descriptors.
+
+use core::time::Duration;
+use io::{timeout_to_ms, FileDescriptor};
+use Error;
+
+/// The number of the syscall to create a signal descriptor.
+const SIGNAL_DESCRIPTOR_CREATE_SYSCALL_NUM: u64 = 41;
+
+/// The number of the syscall to read pending signals.
+const SIGNAL_READ_SYSCALL_NUM: u64 = 42;
+
+/// The process exceeded its CPU time limit.
+pub const SIG_CPU_TIME_LIMIT_EXCEEDED: u64 = 1 << 0;
+
+/// A descriptor that receives the signals of a set.
+#[derive(Debug)]
+pub struct SignalDescriptor {
+    /// The descriptor receiving the signals.
+    descriptor: FileDescriptor,
+}
+
+impl SignalDescriptor {
+    /// Creates a descriptor that receives the signals in `mask`.
+    pub fn new(mask: u64) -> Result<SignalDescriptor, Error> {
+        let result = unsafe { syscall!(SIGNAL_DESCRIPTOR_CREATE_SYSCALL_NUM, mask) };
+        Error::from_syscall_result(result).map(|descriptor| SignalDescriptor { descriptor })
+    }
+
+    /// Returns the underlying descriptor.
+    pub fn descriptor(&self) -> FileDescriptor {
+        self.descriptor
+    }
+
+    /// Waits until one of the signals is pending and returns the pending
+    /// signals of the set.
+    ///
+    /// The returned signals are no longer pending afterwards. If `timeout` is
+    /// `None` this waits indefinitely. Returns zero if the timeout expired
+    /// first.
+    pub fn read(&self, timeout: Option<Duration>) -> Result<u64, Error> {
+        let result = unsafe {
+            syscall!(
+                SIGNAL_READ_SYSCALL_NUM,
+                self.descriptor,
+                timeout_to_ms(timeout) as u64
+            )
+        };
+        Error::from_syscall_result(result)
+    }
+}
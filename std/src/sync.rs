@@ -0,0 +1,257 @@
+//! Synchronization primitives built on futexes.
+//!
+//! A futex is a 32 bit value in memory that threads can wait on while it
+//! holds an expected value. The primitives here only make a syscall when a
+//! thread actually has to wait or another thread waits for it, so they never
+//! spin.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+use core::u32;
+use io::timeout_to_ms;
+
+/// The number of the syscall to wait on a futex.
+const FUTEX_WAIT_SYSCALL_NUM: u64 = 61;
+
+/// The number of the syscall to wake the threads waiting on a futex.
+const FUTEX_WAKE_SYSCALL_NUM: u64 = 62;
+
+/// Waits until the futex is woken, unless it doesn't hold the expected
+/// value.
+///
+/// If `timeout` is `None` this waits indefinitely. This may also return
+/// without being woken, so callers must check the value again.
+pub fn futex_wait(futex: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+    unsafe {
+        syscall!(
+            FUTEX_WAIT_SYSCALL_NUM,
+            futex as *const AtomicU32 as u64,
+            expected as u64,
+            timeout_to_ms(timeout) as u64
+        );
+    }
+}
+
+/// Wakes up to `count` threads waiting on the futex and returns the number
+/// of woken threads.
+pub fn futex_wake(futex: &AtomicU32, count: usize) -> usize {
+    let result = unsafe {
+        syscall!(
+            FUTEX_WAKE_SYSCALL_NUM,
+            futex as *const AtomicU32 as u64,
+            count as u64
+        )
+    };
+
+    if (result as i64) < 0 {
+        0
+    } else {
+        result as usize
+    }
+}
+
+/// The initialization didn't start yet.
+const INCOMPLETE: u32 = 0;
+
+/// A thread runs the initialization.
+const RUNNING: u32 = 1;
+
+/// A thread runs the initialization and others wait for it.
+const WAITING: u32 = 2;
+
+/// The initialization is complete.
+const COMPLETE: u32 = 3;
+
+/// Runs an initialization exactly once.
+pub struct Once {
+    /// The state of the initialization.
+    state: AtomicU32,
+}
+
+impl Once {
+    /// Creates a new `Once` whose initialization didn't run yet.
+    pub const fn new() -> Once {
+        Once {
+            state: AtomicU32::new(INCOMPLETE),
+        }
+    }
+
+    /// Runs the function, unless an initialization already ran.
+    ///
+    /// If another thread runs its initialization at the same time, this waits
+    /// until it's complete.
+    pub fn call_once<F: FnOnce()>(&self, function: F) {
+        if !self.begin() {
+            return;
+        }
+
+        function();
+
+        if self.state.swap(COMPLETE, Ordering::Release) == WAITING {
+            futex_wake(&self.state, usize::max_value());
+        }
+    }
+
+    /// Returns true if the initialization is complete.
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Returns true if the current thread should run the initialization.
+    ///
+    /// Returns false once the initialization is complete, waiting for the
+    /// thread that runs it if necessary.
+    fn begin(&self) -> bool {
+        loop {
+            match self
+                .state
+                .compare_and_swap(INCOMPLETE, RUNNING, Ordering::Acquire)
+            {
+                INCOMPLETE => return true,
+                COMPLETE => return false,
+                state => {
+                    // Tell the running thread that it has to wake the waiters.
+                    if state == RUNNING {
+                        self.state
+                            .compare_and_swap(RUNNING, WAITING, Ordering::Relaxed);
+                    }
+
+                    // This returns at once if the initialization completed in
+                    // the meantime.
+                    futex_wait(&self.state, WAITING, None);
+                }
+            }
+        }
+    }
+}
+
+/// A value that is initialized once.
+pub struct OnceLock<T> {
+    /// Guards the initialization of the value.
+    once: Once,
+    /// The value, once it's initialized.
+    value: UnsafeCell<Option<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for OnceLock<T> {}
+unsafe impl<T: Send> Send for OnceLock<T> {}
+
+impl<T> OnceLock<T> {
+    /// Creates a new `OnceLock` without a value.
+    pub const fn new() -> OnceLock<T> {
+        OnceLock {
+            once: Once::new(),
+            value: UnsafeCell::new(None),
+        }
+    }
+
+    /// Returns the value if it's initialized.
+    pub fn get(&self) -> Option<&T> {
+        if self.once.is_completed() {
+            unsafe { (*self.value.get()).as_ref() }
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value, initializing it with the function first if
+    /// necessary.
+    pub fn get_or_init<F: FnOnce() -> T>(&self, function: F) -> &T {
+        let value = &self.value;
+
+        self.once.call_once(|| unsafe {
+            *value.get() = Some(function());
+        });
+
+        unsafe { (*self.value.get()).as_ref() }.expect("The initialization didn't store a value.")
+    }
+
+    /// Initializes the value, unless it's already initialized.
+    ///
+    /// Returns the given value back if it wasn't used.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+
+        {
+            let slot = &self.value;
+            let new_value = &mut value;
+
+            self.once.call_once(|| unsafe {
+                *slot.get() = new_value.take();
+            });
+        }
+
+        match value {
+            Some(value) => Err(value),
+            None => Ok(()),
+        }
+    }
+}
+
+/// No thread is parked and no wake-up is pending.
+const EMPTY: u32 = 0;
+
+/// A wake-up is pending.
+const NOTIFIED: u32 = 1;
+
+/// A thread is parked.
+const PARKED: u32 = u32::MAX;
+
+/// Lets a thread sleep until another thread wakes it.
+///
+/// An `unpark` that comes before the matching `park` isn't lost, the `park`
+/// returns at once instead. Only one thread may park on a parker at a time.
+pub struct Parker {
+    /// Whether a thread is parked or a wake-up is pending.
+    state: AtomicU32,
+}
+
+impl Parker {
+    /// Creates a new parker without a pending wake-up.
+    pub const fn new() -> Parker {
+        Parker {
+            state: AtomicU32::new(EMPTY),
+        }
+    }
+
+    /// Sleeps until `unpark` is called, unless it was called already.
+    pub fn park(&self) {
+        // Turns `NOTIFIED` into `EMPTY` and `EMPTY` into `PARKED`.
+        if self.state.fetch_sub(1, Ordering::Acquire) == NOTIFIED {
+            return;
+        }
+
+        loop {
+            futex_wait(&self.state, PARKED, None);
+
+            if self
+                .state
+                .compare_and_swap(NOTIFIED, EMPTY, Ordering::Acquire)
+                == NOTIFIED
+            {
+                return;
+            }
+        }
+    }
+
+    /// Sleeps until `unpark` is called or the timeout expires.
+    ///
+    /// Returns true if the thread was woken by `unpark`.
+    pub fn park_timeout(&self, timeout: Duration) -> bool {
+        if self.state.fetch_sub(1, Ordering::Acquire) == NOTIFIED {
+            return true;
+        }
+
+        futex_wait(&self.state, PARKED, Some(timeout));
+
+        self.state.swap(EMPTY, Ordering::Acquire) == NOTIFIED
+    }
+
+    /// Wakes the parked thread or lets its next `park` return at once.
+    pub fn unpark(&self) {
+        if self.state.swap(NOTIFIED, Ordering::Release) == PARKED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Makes raw syscalls.
+//!
+//! This is meant for programs that test the kernel. All other programs should
+//! use the wrappers in the other modules.
+
+/// The number of the last syscall the kernel knows.
+pub const LAST_SYSCALL_NUM: u64 = 69;
+
+/// Makes the syscall with the given number and arguments and returns its
+/// unchanged result.
+///
+/// # Safety
+/// - Depending on the syscall and its arguments, the kernel may write to any
+///   user accessible memory of the process, end the current thread or end
+///   the whole process.
+pub unsafe fn raw(num: u64, arguments: [u64; 6]) -> u64 {
+    syscall!(
+        num,
+        arguments[0],
+        arguments[1],
+        arguments[2],
+        arguments[3],
+        arguments[4],
+        arguments[5]
+    )
+}
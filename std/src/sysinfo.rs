@@ -0,0 +1,51 @@
+//! This module provides information about the machine.
+
+use error::Error;
+
+/// The number of the syscall to get information about a NUMA node.
+const GET_NODE_INFO_SYSCALL_NUM: u64 = 53;
+
+/// The maximum number of NUMA nodes that the kernel tells apart.
+pub const MAX_NODES: usize = 64;
+
+/// Information about a NUMA node.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct NodeInfo {
+    /// The size of the memory of the node in bytes.
+    pub memory: u64,
+    /// The number of CPUs of the node.
+    pub cpu_count: u64,
+    /// The distances to the other nodes.
+    ///
+    /// The distance of a node to itself is 10, the distances to other nodes
+    /// are relative to that.
+    pub distances: [u8; MAX_NODES],
+}
+
+/// Returns the information about the NUMA node with the given index.
+///
+/// The number of nodes of the machine is returned along with it. Machines
+/// without NUMA information have a single node that contains all memory.
+pub fn node_info(node: usize) -> Result<(NodeInfo, usize), Error> {
+    let mut info = NodeInfo {
+        memory: 0,
+        cpu_count: 0,
+        distances: [0; MAX_NODES],
+    };
+
+    let result = unsafe {
+        syscall!(
+            GET_NODE_INFO_SYSCALL_NUM,
+            node as u64,
+            &mut info as *mut NodeInfo as u64
+        )
+    };
+
+    Error::from_syscall_result(result).map(|count| (info, count as usize))
+}
+
+/// Returns the number of NUMA nodes of the machine.
+pub fn node_count() -> Result<usize, Error> {
+    node_info(0).map(|(_, count)| count)
+}
@@ -1,6 +1,7 @@
 //! Handles thread related syscalls.
 
 use core::time::Duration;
+use Error;
 
 /// The number of the exit syscall.
 const SLEEP_SYSCALL_NUM: u64 = 4;
@@ -11,6 +12,32 @@ const NEW_THREAD_SYSCALL_NUM: u64 = 5;
 /// Kills the current thread.
 const KILL_THREAD_SYSCALL_NUM: u64 = 6;
 
+/// The number of the syscall to set the name of the current thread.
+const SET_THREAD_NAME_SYSCALL_NUM: u64 = 44;
+
+/// The number of the syscall to wait for a thread to exit.
+const JOIN_THREAD_SYSCALL_NUM: u64 = 45;
+
+/// The number of the syscall to create a new thread with a given stack size.
+const NEW_THREAD_WITH_STACK_SYSCALL_NUM: u64 = 46;
+
+/// The number of the syscall to set the priority of the current thread.
+const SET_PRIORITY_SYSCALL_NUM: u64 = 67;
+
+/// The maximum length of a thread name in bytes.
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// The lowest priority of a thread.
+pub const MIN_PRIORITY: i32 = 0;
+
+/// The highest priority of a thread.
+pub const MAX_PRIORITY: i32 = 7;
+
+/// The priority that processes start with.
+///
+/// New threads start with the priority of the thread that created them.
+pub const DEFAULT_PRIORITY: i32 = 3;
+
 /// Lets the current thread sleep for `ms` milliseconds.
 pub fn sleep(duration: Duration) {
     unsafe {
@@ -37,6 +64,99 @@ pub fn new_thread(function: fn(u64, u64, u64, u64), arg1: u64, arg2: u64, arg3:
     }
 }
 
+/// Spawns a new thread running the given function and returns a handle to
+/// it.
+///
+/// The thread exits when the function returns. Dropping the handle detaches
+/// the thread.
+///
+/// # Panics
+/// Panics if the thread could not be created.
+pub fn spawn(function: fn()) -> JoinHandle {
+    match Builder::new().spawn(function) {
+        Ok(handle) => handle,
+        Err(error) => panic!("Could not spawn a thread: {}", error),
+    }
+}
+
+/// Configures a new thread before spawning it.
+#[derive(Debug, Default)]
+pub struct Builder {
+    /// The size of the stack of the new thread in bytes.
+    stack_size: Option<usize>,
+}
+
+impl Builder {
+    /// Creates a builder for a thread with the default configuration.
+    pub fn new() -> Builder {
+        Builder { stack_size: None }
+    }
+
+    /// Sets the size of the stack of the new thread in bytes.
+    ///
+    /// The size is rounded up to whole pages. Spawning fails if it exceeds
+    /// the maximum stack size or the memory limits of the process.
+    pub fn stack_size(mut self, size: usize) -> Builder {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Spawns a new thread running the given function and returns a handle
+    /// to it.
+    ///
+    /// The thread exits when the function returns. Dropping the handle
+    /// detaches the thread.
+    pub fn spawn(self, function: fn()) -> Result<JoinHandle, Error> {
+        let result = unsafe {
+            match self.stack_size {
+                Some(size) => syscall!(
+                    NEW_THREAD_WITH_STACK_SYSCALL_NUM,
+                    thread_start as u64,
+                    size as u64,
+                    function as u64,
+                    0,
+                    0,
+                    0
+                ),
+                None => syscall!(
+                    NEW_THREAD_SYSCALL_NUM,
+                    thread_start as u64,
+                    function as u64,
+                    0,
+                    0,
+                    0,
+                    0
+                ),
+            }
+        };
+
+        Error::from_syscall_result(result).map(|id| JoinHandle { id })
+    }
+}
+
+/// An owned permission to wait for a thread to exit.
+#[derive(Debug)]
+pub struct JoinHandle {
+    /// The ID of the thread within the process.
+    id: u64,
+}
+
+impl JoinHandle {
+    /// Returns the ID of the thread within the process.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Waits for the thread to exit.
+    ///
+    /// Fails if the current thread tries to join itself.
+    pub fn join(self) -> Result<(), Error> {
+        let result = unsafe { syscall!(JOIN_THREAD_SYSCALL_NUM, self.id, -1i64 as u64) };
+
+        Error::from_syscall_result(result).map(|_| ())
+    }
+}
+
 /// Kills the current thread.
 pub fn kill_thread() {
     unsafe {
@@ -44,6 +164,38 @@ pub fn kill_thread() {
     }
 }
 
+/// Sets the name of the current thread.
+///
+/// The name is only used for diagnostics. New threads start with the name of
+/// their process. Returns false if the name is longer than `MAX_NAME_LENGTH`.
+pub fn set_name(name: &str) -> bool {
+    let name_ptr = name as *const str as *const usize as u64;
+    let result =
+        unsafe { syscall!(SET_THREAD_NAME_SYSCALL_NUM, name_ptr, name.len() as u64) as i64 };
+
+    result == 0
+}
+
+/// Sets the priority of the current thread.
+///
+/// Ready threads of a higher priority run first, but threads that wait for
+/// long are temporarily raised, so low priorities only delay threads. Fails
+/// if the priority lies outside of `MIN_PRIORITY` and `MAX_PRIORITY`.
+/// Raising the priority above both the current and the default priority
+/// requires the `CAP_RAISE_PRIORITY` capability.
+pub fn set_priority(priority: i32) -> Result<(), Error> {
+    let result = unsafe { syscall!(SET_PRIORITY_SYSCALL_NUM, priority as u64) };
+
+    Error::from_syscall_result(result).map(|_| ())
+}
+
+/// The entry point of threads created by `spawn`.
+extern "C" fn thread_start(function: fn()) {
+    function();
+
+    kill_thread();
+}
+
 /// Used internally to create and exit new threads.
 extern "C" fn new_thread_creator(
     function: fn(u64, u64, u64, u64),
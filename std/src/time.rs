@@ -0,0 +1,175 @@
+//! Measures time.
+//!
+//! `Instant` is monotonic and meant for measuring how long something took.
+//! `SystemTime` is the wall clock time, which is read from the hardware clock
+//! at boot and may differ from the actual time.
+
+use core::fmt;
+use core::ops::{Add, Sub};
+pub use core::time::Duration;
+
+/// The number of the syscall to read a clock.
+const GET_CLOCK_SYSCALL_NUM: u64 = 63;
+
+/// The clock that counts the time since boot.
+const MONOTONIC_CLOCK: u64 = 0;
+
+/// The clock that counts the time since the Unix epoch.
+const SYSTEM_CLOCK: u64 = 1;
+
+/// The layout of a time as passed by the kernel.
+#[repr(C)]
+#[derive(Default)]
+struct ClockTime {
+    /// The whole seconds.
+    seconds: u64,
+    /// The nanoseconds within the second.
+    nanoseconds: u64,
+}
+
+/// Reads the given clock.
+fn get_clock(clock: u64) -> Duration {
+    let mut time = ClockTime::default();
+    let time_ptr = &mut time as *mut ClockTime as u64;
+
+    let result = unsafe { syscall!(GET_CLOCK_SYSCALL_NUM, clock, time_ptr) as i64 };
+    assert!(result == 0, "Could not read clock {}.", clock);
+
+    Duration::new(time.seconds, time.nanoseconds as u32)
+}
+
+/// A point in time that is only meaningful compared to other instants.
+///
+/// Instants never go backwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// Returns the current instant.
+    pub fn now() -> Instant {
+        Instant(get_clock(MONOTONIC_CLOCK))
+    }
+
+    /// Returns the time that passed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the time that passed between the earlier instant and this one.
+    ///
+    /// Returns zero if the earlier instant is actually later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.0
+            .checked_sub(earlier.0)
+            .unwrap_or_else(|| Duration::new(0, 0))
+    }
+
+    /// Returns the instant the duration after this one, unless it overflows.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Instant)
+    }
+
+    /// Returns the instant the duration before this one, unless it lies before
+    /// boot.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("Overflow when adding a duration to an instant.")
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("Underflow when subtracting a duration from an instant.")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// A point in wall clock time.
+///
+/// The system time can't be set, but the hardware clock it's based on may be
+/// wrong, so it shouldn't be used to measure durations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SystemTime {
+    /// The whole seconds since the Unix epoch.
+    seconds: u64,
+    /// The nanoseconds within the second.
+    nanoseconds: u32,
+}
+
+/// The start of the first of January 1970 in UTC.
+pub const UNIX_EPOCH: SystemTime = SystemTime {
+    seconds: 0,
+    nanoseconds: 0,
+};
+
+impl SystemTime {
+    /// Returns the current system time.
+    pub fn now() -> SystemTime {
+        let since_epoch = get_clock(SYSTEM_CLOCK);
+
+        SystemTime {
+            seconds: since_epoch.as_secs(),
+            nanoseconds: since_epoch.subsec_nanos(),
+        }
+    }
+
+    /// Returns the time that passed between the earlier time and this one.
+    ///
+    /// Fails with the time that passed in the other direction if the earlier
+    /// time is actually later.
+    pub fn duration_since(&self, earlier: SystemTime) -> Result<Duration, SystemTimeError> {
+        let this = self.since_epoch();
+        let earlier = earlier.since_epoch();
+
+        match this.checked_sub(earlier) {
+            Some(duration) => Ok(duration),
+            None => Err(SystemTimeError(earlier - this)),
+        }
+    }
+
+    /// Returns the time that passed since this time.
+    pub fn elapsed(&self) -> Result<Duration, SystemTimeError> {
+        SystemTime::now().duration_since(*self)
+    }
+
+    /// Returns the time since the Unix epoch.
+    fn since_epoch(&self) -> Duration {
+        Duration::new(self.seconds, self.nanoseconds)
+    }
+}
+
+/// The error returned when a system time lies after the time it's compared
+/// to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SystemTimeError(Duration);
+
+impl SystemTimeError {
+    /// Returns how much later the time was.
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl fmt::Display for SystemTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "second time provided was later than self")
+    }
+}
@@ -0,0 +1,271 @@
+//! Allows tracing other processes, which is the basis for debuggers.
+//!
+//! A traced process stops whenever one of its threads faults, hits a
+//! breakpoint or watchpoint, finishes a single step or, if requested,
+//! performs a syscall.
+//! While stopped, its memory and registers can be inspected and modified.
+
+use process::ProcessError;
+
+/// The number of the syscall to attach to a process.
+const TRACE_ATTACH_SYSCALL_NUM: u64 = 20;
+
+/// The number of the syscall to detach from a process.
+const TRACE_DETACH_SYSCALL_NUM: u64 = 21;
+
+/// The number of the syscall to wait for a traced process to stop.
+const TRACE_WAIT_SYSCALL_NUM: u64 = 22;
+
+/// The number of the syscall to read the memory of a traced process.
+const TRACE_READ_MEMORY_SYSCALL_NUM: u64 = 23;
+
+/// The number of the syscall to write the memory of a traced process.
+const TRACE_WRITE_MEMORY_SYSCALL_NUM: u64 = 24;
+
+/// The number of the syscall to set the registers of a stopped thread.
+const TRACE_SET_REGISTERS_SYSCALL_NUM: u64 = 25;
+
+/// The number of the syscall to resume a stopped thread.
+const TRACE_RESUME_SYSCALL_NUM: u64 = 26;
+
+/// The number of the syscall to set a watchpoint in a traced process.
+const TRACE_SET_WATCHPOINT_SYSCALL_NUM: u64 = 27;
+
+/// The number of the syscall to clear a watchpoint in a traced process.
+const TRACE_CLEAR_WATCHPOINT_SYSCALL_NUM: u64 = 28;
+
+/// The amount of watchpoint slots.
+///
+/// Slots used by the kernel are not available to tracers.
+pub const WATCHPOINT_SLOTS: usize = 4;
+
+/// The attach flag that makes the traced process stop before every syscall.
+pub const TRACE_SYSCALLS: u64 = 1 << 0;
+
+/// The reasons for a traced thread to stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    /// The thread caused a fault that would otherwise kill the process.
+    Fault,
+    /// The thread hit a breakpoint.
+    Breakpoint,
+    /// The thread executed a single instruction.
+    SingleStep,
+    /// The thread is about to perform a syscall.
+    Syscall,
+    /// The thread hit the watchpoint in the given slot.
+    Watchpoint(usize),
+}
+
+impl StopReason {
+    /// Converts the values passed by the kernel to a stop reason.
+    fn from_raw(reason: u64, detail: u64) -> Option<StopReason> {
+        match reason {
+            0 => Some(StopReason::Fault),
+            1 => Some(StopReason::Breakpoint),
+            2 => Some(StopReason::SingleStep),
+            3 => Some(StopReason::Syscall),
+            4 => Some(StopReason::Watchpoint(detail as usize)),
+            _ => None,
+        }
+    }
+}
+
+/// The accesses a watchpoint can watch for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    /// Triggers when the instruction at the address is executed.
+    Execute = 0,
+    /// Triggers when the memory is written.
+    Write = 1,
+    /// Triggers when the memory is read or written.
+    ReadWrite = 2,
+}
+
+/// The ways a stopped thread can be resumed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResumeMode {
+    /// The thread continues running normally.
+    Continue = 0,
+    /// The thread stops again after executing a single instruction.
+    ///
+    /// Threads stopped at a syscall continue normally instead.
+    SingleStep = 1,
+}
+
+/// The registers of a stopped thread.
+///
+/// For syscall stops only the syscall number and arguments are valid, for
+/// all other stops only the remaining registers. Changes to the frame pointer
+/// are ignored.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Registers {
+    /// The address of the next instruction to execute.
+    pub instruction_pointer: u64,
+    /// The stack pointer of the thread.
+    pub stack_pointer: u64,
+    /// The frame pointer of the thread.
+    pub frame_pointer: u64,
+    /// The flags register of the thread.
+    pub flags: u64,
+    /// The number of the syscall that is about to be performed.
+    pub syscall_number: u64,
+    /// The arguments of the syscall that is about to be performed.
+    pub syscall_arguments: [u64; 6],
+}
+
+/// A stopped thread of a traced process.
+#[derive(Debug, Clone, Copy)]
+pub struct Stop {
+    /// The reason the thread stopped.
+    pub reason: StopReason,
+    /// The ID of the stopped thread.
+    pub thread: u64,
+    /// The registers of the stopped thread.
+    pub registers: Registers,
+}
+
+/// The layout of a stop as reported by the kernel.
+#[repr(C)]
+#[derive(Default)]
+struct StopReport {
+    reason: u64,
+    detail: u64,
+    thread: u64,
+    registers: Registers,
+}
+
+/// Converts the result of a syscall that returns nothing on success.
+fn to_result(result: i64) -> Result<(), ProcessError> {
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(())
+    }
+}
+
+/// Starts tracing the given process.
+///
+/// This requires the `CAP_TRACE` capability and the traced process may not
+/// have capabilities the current process lacks. Unless the current process
+/// started it, directly or not, the traced process must be in the same PID
+/// namespace and have the same root directory. Currently the only supported
+/// flag is `TRACE_SYSCALLS`.
+pub fn attach(pid: u64, flags: u64) -> Result<(), ProcessError> {
+    to_result(unsafe { syscall!(TRACE_ATTACH_SYSCALL_NUM, pid, flags) as i64 })
+}
+
+/// Stops tracing the given process.
+///
+/// A stopped thread of the process continues running normally.
+pub fn detach(pid: u64) -> Result<(), ProcessError> {
+    to_result(unsafe { syscall!(TRACE_DETACH_SYSCALL_NUM, pid) as i64 })
+}
+
+/// Waits until a thread of the traced process stops.
+///
+/// Returns `None` if the timeout expired first. A negative timeout waits
+/// indefinitely.
+pub fn wait(pid: u64, timeout_ms: i64) -> Result<Option<Stop>, ProcessError> {
+    let mut report = StopReport::default();
+    let report_ptr = &mut report as *mut StopReport as u64;
+    let result =
+        unsafe { syscall!(TRACE_WAIT_SYSCALL_NUM, pid, report_ptr, timeout_ms as u64) as i64 };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else if result == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(Stop {
+            reason: StopReason::from_raw(report.reason, report.detail)
+                .ok_or(ProcessError::Unspecified)?,
+            thread: report.thread,
+            registers: report.registers,
+        }))
+    }
+}
+
+/// Reads the memory of the traced process at `address` into `buffer`.
+///
+/// Returns the number of bytes read, which is less than the buffer size if
+/// the end of the readable memory was reached.
+pub fn read_memory(pid: u64, address: u64, buffer: &mut [u8]) -> Result<usize, ProcessError> {
+    let result = unsafe {
+        syscall!(
+            TRACE_READ_MEMORY_SYSCALL_NUM,
+            pid,
+            address,
+            buffer.as_mut_ptr() as u64,
+            buffer.len() as u64
+        ) as i64
+    };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Writes `buffer` to the memory of the traced process at `address`.
+///
+/// This also works for read only memory, so it can be used to insert
+/// breakpoints. Returns the number of bytes written.
+pub fn write_memory(pid: u64, address: u64, buffer: &[u8]) -> Result<usize, ProcessError> {
+    let result = unsafe {
+        syscall!(
+            TRACE_WRITE_MEMORY_SYSCALL_NUM,
+            pid,
+            address,
+            buffer.as_ptr() as u64,
+            buffer.len() as u64
+        ) as i64
+    };
+    if result < 0 {
+        Err(ProcessError::Unspecified)
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Changes the registers of the stopped thread of the traced process.
+///
+/// The new registers take effect when the thread is resumed.
+pub fn set_registers(pid: u64, registers: &Registers) -> Result<(), ProcessError> {
+    let registers_ptr = registers as *const Registers as u64;
+    to_result(unsafe { syscall!(TRACE_SET_REGISTERS_SYSCALL_NUM, pid, registers_ptr) as i64 })
+}
+
+/// Resumes the stopped thread of the traced process.
+pub fn resume(pid: u64, mode: ResumeMode) -> Result<(), ProcessError> {
+    to_result(unsafe { syscall!(TRACE_RESUME_SYSCALL_NUM, pid, mode as u64) as i64 })
+}
+
+/// Watches the given memory of the traced process.
+///
+/// The length must be 1, 2, 4 or 8 and the address must be aligned to it.
+/// Execution watchpoints always have a length of 1. Running threads notice
+/// the change when they are scheduled the next time.
+pub fn set_watchpoint(
+    pid: u64,
+    slot: usize,
+    address: u64,
+    length: u64,
+    kind: WatchKind,
+) -> Result<(), ProcessError> {
+    to_result(unsafe {
+        syscall!(
+            TRACE_SET_WATCHPOINT_SYSCALL_NUM,
+            pid,
+            slot as u64,
+            address,
+            length,
+            kind as u64
+        ) as i64
+    })
+}
+
+/// Removes the watchpoint in the given slot of the traced process.
+pub fn clear_watchpoint(pid: u64, slot: usize) -> Result<(), ProcessError> {
+    to_result(unsafe { syscall!(TRACE_CLEAR_WATCHPOINT_SYSCALL_NUM, pid, slot as u64) as i64 })
+}
@@ -0,0 +1,14 @@
+TARGET_FILES += $(TARGET_DIR)/bin/syscall-fuzz
+BUILD_DIRS += syscall-fuzz/target
+INITRAMFS_FILES += /bin/syscall-fuzz
+FMT_DIRS += syscall-fuzz
+
+$(TARGET_DIR)/bin/syscall-fuzz: syscall-fuzz/target/$(BUILD_TARGET)/$(BUILD_TYPE)/syscall-fuzz
+	@mkdir -p $(shell dirname $@)
+	cp $< $@
+
+syscall-fuzz/target/$(BUILD_TARGET)/$(BUILD_TYPE)/syscall-fuzz: syscall-fuzz/target/$(BUILD_TARGET)/$(BUILD_TYPE)/libsyscall_fuzz.a
+	$(LINKER) $(LINKER_FLAGS) $< -o $@
+
+syscall-fuzz/target/$(BUILD_TARGET)/$(BUILD_TYPE)/libsyscall_fuzz.a: $(shell find syscall-fuzz/src -name "*.rs") syscall-fuzz/Cargo.toml $(STD_FILES)
+	cd syscall-fuzz && $(RUST_COMPILER) build $(RUST_COMPILER_FLAGS)
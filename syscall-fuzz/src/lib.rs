@@ -0,0 +1,207 @@
+//! Makes syscalls with random numbers and arguments to check that the kernel
+//! survives them.
+//!
+//! The random numbers are derived from the `syscall_fuzz_seed` environment
+//! variable, so a run can be repeated exactly. `syscall_fuzz_iterations` sets
+//! the number of syscalls. In test mode the kernel checks its invariants after
+//! every syscall and panics if one of them is violated.
+//!
+//! All capabilities are dropped first, so privileged syscalls only exercise
+//! their permission checks. Timeouts are always zero, so the fuzzer never
+//! blocks.
+
+#![no_std]
+
+#[macro_use]
+extern crate veos_std;
+#[allow(unused_extern_crates)]
+extern crate rlibc;
+
+use veos_std::time::Instant;
+use veos_std::{env, process, syscall};
+
+/// The number of syscalls made if no other number is given.
+const DEFAULT_ITERATIONS: u64 = 10000;
+
+/// The number of syscalls between two progress messages.
+const PROGRESS_INTERVAL: u64 = 1000;
+
+/// The syscalls that are never made.
+///
+/// They print random characters, end the thread or the process, sleep for a
+/// random time, run code at random addresses, change the resource groups
+/// that other processes are part of, flood the log with allocations or the
+/// serial port with coverage counts, suspend the machine or copy the fuzzer.
+const SKIPPED_SYSCALLS: &[u64] = &[0, 1, 4, 5, 6, 15, 16, 17, 46, 47, 51, 57, 59, 60, 66];
+
+/// The syscalls that can block together with the index of their timeout
+/// argument.
+const TIMEOUT_ARGUMENTS: &[(u64, usize)] = &[
+    (8, 2),
+    (11, 3),
+    (22, 2),
+    (33, 1),
+    (35, 1),
+    (40, 1),
+    (42, 1),
+    (45, 1),
+    (55, 4),
+    (56, 4),
+    (58, 2),
+    (61, 2)
+];
+
+/// The number of the syscall that sets a resource limit.
+const SET_RESOURCE_LIMIT_SYSCALL_NUM: u64 = 14;
+
+/// The raw value of the CPU time resource, whose limit kills the process.
+const CPU_TIME_RESOURCE: u64 = 3;
+
+/// The number of the syscall that reads from a file.
+const READ_SYSCALL_NUM: u64 = 49;
+
+/// The descriptor of the standard input, which waits for the keyboard.
+const STDIN: u64 = 0;
+
+/// The size of the buffer that valid pointer arguments point into.
+const BUFFER_SIZE: usize = 4096;
+
+/// The buffer that valid pointer arguments point into.
+static mut BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+/// A xorshift64* pseudo random number generator.
+struct Random {
+    /// The current state, which is never zero.
+    state: u64
+}
+
+impl Random {
+    /// Creates a generator from the given seed.
+    fn new(seed: u64) -> Random {
+        // A zero state would only ever produce zeros.
+        let state = if seed == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            seed
+        };
+
+        Random { state }
+    }
+
+    /// Returns the next random number.
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a random number below the given bound.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+#[no_mangle]
+pub fn main() {
+    let seed = number_from_env("syscall_fuzz_seed", 0);
+    let iterations = number_from_env("syscall_fuzz_iterations", DEFAULT_ITERATIONS);
+
+    println!("Fuzzing {} syscalls with seed {}.", iterations, seed);
+
+    process::drop_capabilities(u64::max_value());
+
+    let mut random = Random::new(seed);
+
+    let buffer_address = unsafe {
+        for byte in BUFFER.iter_mut() {
+            *byte = random.next() as u8;
+        }
+
+        BUFFER.as_ptr() as u64
+    };
+
+    let start = Instant::now();
+
+    for iteration in 1..iterations + 1 {
+        let (num, arguments) = next_syscall(&mut random, buffer_address);
+
+        // The result doesn't matter, only that the kernel survives.
+        unsafe {
+            syscall::raw(num, arguments);
+        }
+
+        if iteration % PROGRESS_INTERVAL == 0 {
+            println!("Made {} syscalls.", iteration);
+        }
+    }
+
+    let elapsed = start.elapsed();
+
+    println!(
+        "The kernel survived {} fuzzed syscalls in {}.{:03}s.",
+        iterations,
+        elapsed.as_secs(),
+        elapsed.subsec_nanos() / 1_000_000
+    );
+}
+
+/// Chooses the next syscall and its arguments.
+fn next_syscall(random: &mut Random, buffer_address: u64) -> (u64, [u64; 6]) {
+    let num = loop {
+        let num = random.below(syscall::LAST_SYSCALL_NUM + 1);
+
+        if !SKIPPED_SYSCALLS.contains(&num) {
+            break num;
+        }
+    };
+
+    let mut arguments = [0; 6];
+    for argument in arguments.iter_mut() {
+        *argument = next_argument(random, buffer_address);
+    }
+
+    for &(timeout_num, index) in TIMEOUT_ARGUMENTS {
+        if num == timeout_num {
+            arguments[index] = 0;
+        }
+    }
+
+    if num == SET_RESOURCE_LIMIT_SYSCALL_NUM && arguments[0] == CPU_TIME_RESOURCE {
+        arguments[0] = CPU_TIME_RESOURCE + 1;
+    }
+
+    if num == READ_SYSCALL_NUM && arguments[0] == STDIN {
+        arguments[0] = u64::max_value();
+    }
+
+    (num, arguments)
+}
+
+/// Chooses a random argument, preferring values that are likely to hit edge
+/// cases in the argument validation.
+fn next_argument(random: &mut Random, buffer_address: u64) -> u64 {
+    match random.below(8) {
+        0 => 0,
+        1 => random.below(16),
+        2 => u64::max_value() - random.below(16),
+        // A valid pointer.
+        3 => buffer_address + random.below(BUFFER_SIZE as u64),
+        // A pointer near the end of the buffer, so most areas reach past it.
+        4 => buffer_address + BUFFER_SIZE as u64 - random.below(16),
+        // A kernel address.
+        5 => 0xffff_8000_0000_0000 + random.below(1 << 47),
+        // A non-canonical address.
+        6 => 0x0000_8000_0000_0000 + random.below(1 << 47),
+        _ => random.next()
+    }
+}
+
+/// Returns the number in the given environment variable or the default if it
+/// isn't set or not a number.
+fn number_from_env(key: &str, default: u64) -> u64 {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,14 @@
+TARGET_FILES += $(TARGET_DIR)/bin/test-runner
+BUILD_DIRS += test-runner/target
+INITRAMFS_FILES += /bin/test-runner
+FMT_DIRS += test-runner
+
+$(TARGET_DIR)/bin/test-runner: test-runner/target/$(BUILD_TARGET)/$(BUILD_TYPE)/test-runner
+	@mkdir -p $(shell dirname $@)
+	cp $< $@
+
+test-runner/target/$(BUILD_TARGET)/$(BUILD_TYPE)/test-runner: test-runner/target/$(BUILD_TARGET)/$(BUILD_TYPE)/libtest_runner.a
+	$(LINKER) $(LINKER_FLAGS) $< -o $@
+
+test-runner/target/$(BUILD_TARGET)/$(BUILD_TYPE)/libtest_runner.a: $(shell find test-runner/src -name "*.rs") test-runner/Cargo.toml $(STD_FILES) $(HARNESS_FILES)
+	cd test-runner && $(RUST_COMPILER) build $(RUST_COMPILER_FLAGS)
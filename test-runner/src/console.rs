@@ -0,0 +1,90 @@
+//! Tests the console requests of `veos_std::console`.
+
+use veos_std::console::{self, Locks, Mode, Position, Repeat};
+use veos_std::io::{self, STDOUT};
+use veos_std::Error;
+
+veos_tests! {
+    /// Checks that the console reports a size that contains the cursor.
+    fn size_contains_cursor() {
+        let size = console::size().expect("The console size couldn't be read.");
+        let cursor = console::cursor().expect("The cursor couldn't be read.");
+
+        check!(size.rows > 0 && size.columns > 0);
+        check!(cursor.row < size.rows && cursor.column < size.columns);
+    }
+
+    /// Checks that the cursor can be moved within the console only.
+    fn set_cursor() {
+        let size = console::size().expect("The console size couldn't be read.");
+        let cursor = console::cursor().expect("The cursor couldn't be read.");
+
+        check_eq!(console::set_cursor(cursor), Ok(()));
+        check_eq!(console::cursor(), Ok(cursor));
+        check_eq!(
+            console::set_cursor(Position {
+                row: size.rows,
+                column: 0
+            }),
+            Err(Error::InvalidArgument)
+        );
+    }
+
+    /// Checks that the mode can be switched and starts out canonical.
+    fn switch_mode() {
+        check_eq!(console::mode(), Ok(Mode::Canonical));
+        check_eq!(console::set_mode(Mode::Raw), Ok(()));
+        check_eq!(console::mode(), Ok(Mode::Raw));
+        check_eq!(console::set_mode(Mode::Canonical), Ok(()));
+        check_eq!(console::mode(), Ok(Mode::Canonical));
+    }
+
+    /// Checks that the lock keys can be switched.
+    fn switch_locks() {
+        let previous = console::locks().expect("The lock keys couldn't be read.");
+        let locks = Locks {
+            caps_lock: true,
+            num_lock: false,
+            scroll_lock: true,
+        };
+
+        check_eq!(console::set_locks(locks), Ok(()));
+        check_eq!(console::locks(), Ok(locks));
+        check_eq!(console::set_locks(previous), Ok(()));
+    }
+
+    /// Checks that the key repeat can be changed within the supported range.
+    fn set_repeat() {
+        let previous = console::repeat().expect("The key repeat couldn't be read.");
+        let repeat = Repeat {
+            rate: 20,
+            delay: 750,
+        };
+
+        check_eq!(console::set_repeat(repeat), Ok(()));
+        check_eq!(console::repeat(), Ok(repeat));
+        check_eq!(
+            console::set_repeat(Repeat {
+                rate: 31,
+                delay: 750,
+            }),
+            Err(Error::InvalidArgument)
+        );
+        check_eq!(
+            console::set_repeat(Repeat {
+                rate: 20,
+                delay: 100,
+            }),
+            Err(Error::InvalidArgument)
+        );
+        check_eq!(console::set_repeat(previous), Ok(()));
+    }
+
+    /// Checks that unknown requests and wrongly sized arguments are refused.
+    fn invalid_requests() {
+        let mut argument = [0u8; 8];
+
+        check_eq!(io::control(STDOUT, 99, &mut argument), Err(Error::InvalidArgument));
+        check_eq!(io::control(STDOUT, 0, &mut argument[..4]), Err(Error::InvalidArgument));
+    }
+}
@@ -0,0 +1,46 @@
+//! Tests taking CPUs offline and bringing them online with `veos_std::power`.
+
+use veos_std::power;
+use veos_std::thread;
+use veos_std::Error;
+
+/// The CPU the kernel boots on, which is always online.
+const BOOT_CPU: usize = 0;
+
+/// A CPU that is started on demand, if the machine has more than one.
+const OTHER_CPU: usize = 1;
+
+/// Runs in the spawned threads.
+fn do_nothing() {}
+
+veos_tests! {
+    /// Checks that the boot CPU can't be taken offline or started again.
+    fn boot_cpu_stays_online() {
+        check_eq!(power::cpu_down(BOOT_CPU), Err(Error::InvalidArgument));
+        check_eq!(power::cpu_up(BOOT_CPU), Err(Error::InvalidArgument));
+    }
+
+    /// Checks that CPUs that don't exist are refused.
+    fn missing_cpu() {
+        check_eq!(power::cpu_down(usize::max_value()), Err(Error::InvalidArgument));
+        check_eq!(power::cpu_up(usize::max_value()), Err(Error::InvalidArgument));
+    }
+
+    /// Checks that a CPU can be brought online and taken offline again twice
+    /// in a row, while threads keep running.
+    fn cpu_up_and_down() {
+        for _ in 0..2 {
+            match power::cpu_up(OTHER_CPU) {
+                // The machine only has one CPU.
+                Err(Error::InvalidArgument) => return,
+                result => check_eq!(result, Ok(()))
+            }
+
+            check_eq!(power::cpu_up(OTHER_CPU), Err(Error::InvalidArgument));
+            check!(thread::spawn(do_nothing).join().is_ok());
+
+            check_eq!(power::cpu_down(OTHER_CPU), Ok(()));
+            check_eq!(power::cpu_down(OTHER_CPU), Err(Error::InvalidArgument));
+        }
+    }
+}
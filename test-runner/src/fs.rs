@@ -0,0 +1,65 @@
+//! Tests reading files with `veos_std::fs`.
+
+use veos_std::fs::File;
+use veos_std::io::{self, SeekFrom};
+use veos_std::Error;
+
+/// A file that is always in the initramfs.
+const TEST_FILE: &str = "/etc/servers/test.manifest";
+
+/// The first line of the file.
+const FIRST_LINE: &[u8] = b"# The test program, started as a server by init.\n";
+
+/// The size of the buffers the file is read into, which is larger than the
+/// file.
+const BUFFER_SIZE: usize = 256;
+
+veos_tests! {
+    /// Checks that opening a file that doesn't exist fails.
+    fn open_missing_file() {
+        check_eq!(File::open("/does/not/exist").err(), Some(Error::NotFound));
+    }
+
+    /// Checks that reads return the file in order and stop at its end.
+    fn read_file() {
+        let mut file = File::open(TEST_FILE).expect("Could not open the test file.");
+        let mut buffer = [0; BUFFER_SIZE];
+
+        check!(file.read_exact(&mut buffer[..FIRST_LINE.len()]).is_ok());
+        check_eq!(&buffer[..FIRST_LINE.len()], FIRST_LINE);
+
+        let rest_length = file.read(&mut buffer).expect("Could not read the test file.");
+        check!(buffer[..rest_length].starts_with(b"binary /bin/test"));
+        check_eq!(file.len(), Ok((FIRST_LINE.len() + rest_length) as u64));
+
+        check_eq!(file.read(&mut buffer), Ok(0));
+    }
+
+    /// Checks that seeking moves the position that is read from.
+    fn seek_file() {
+        let mut file = File::open(TEST_FILE).expect("Could not open the test file.");
+        let length = file.len().expect("Could not get the length of the test file.");
+        let mut buffer = [0; 4];
+
+        check_eq!(file.seek(SeekFrom::Start(2)), Ok(2));
+        check_eq!(file.read(&mut buffer), Ok(buffer.len()));
+        check_eq!(&buffer, b"The ");
+
+        check_eq!(file.seek(SeekFrom::Current(-4)), Ok(2));
+        check_eq!(file.seek(SeekFrom::End(0)), Ok(length));
+        check_eq!(file.seek(SeekFrom::Current(1)), Err(Error::InvalidArgument));
+        check_eq!(file.seek(SeekFrom::End(-(length as i64) - 1)), Err(Error::InvalidArgument));
+    }
+
+    /// Checks that the initramfs can't be written.
+    fn write_read_only_file() {
+        let mut file = File::open(TEST_FILE).expect("Could not open the test file.");
+
+        check!(file.write(b"test").is_err());
+    }
+
+    /// Checks that the console has no seek position.
+    fn seek_console() {
+        check!(io::seek(io::STDOUT, SeekFrom::Start(0)).is_err());
+    }
+}
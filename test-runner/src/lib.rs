@@ -0,0 +1,37 @@
+//! Runs the tests of the standard library against the running kernel.
+//!
+//! The tests run if `run_tests=<filter>` is on the kernel command line, init
+//! then starts this program. Only the tests whose names contain the filter
+//! run, `run_tests=all` runs all of them.
+
+#![no_std]
+
+#[macro_use]
+extern crate veos_std;
+#[macro_use]
+extern crate veos_test;
+#[allow(unused_extern_crates)]
+extern crate rlibc;
+
+mod console;
+mod cpu;
+mod fs;
+mod mmio;
+mod process;
+mod sync;
+mod thread;
+mod time;
+
+#[no_mangle]
+pub fn main() {
+    veos_test::run(&[
+        console::TESTS,
+        cpu::TESTS,
+        fs::TESTS,
+        mmio::TESTS,
+        process::TESTS,
+        sync::TESTS,
+        thread::TESTS,
+        time::TESTS
+    ]);
+}
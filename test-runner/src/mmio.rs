@@ -0,0 +1,29 @@
+//! Tests mapping device memory with `veos_std::mmio`.
+
+use veos_std::mmio::{self, CacheMode};
+use veos_std::Error;
+
+/// The physical address of the legacy VGA text buffer.
+const VGA_BUFFER_ADDRESS: u64 = 0xb8000;
+
+veos_tests! {
+    /// Checks that memory that isn't granted can't be mapped.
+    fn map_without_grant() {
+        check_eq!(
+            mmio::map(VGA_BUFFER_ADDRESS, 4096, CacheMode::WriteCombining),
+            Err(Error::PermissionDenied)
+        );
+    }
+
+    /// Checks that misaligned and empty areas are refused.
+    fn map_invalid_area() {
+        check_eq!(
+            mmio::map(VGA_BUFFER_ADDRESS + 1, 4096, CacheMode::Uncached),
+            Err(Error::InvalidArgument)
+        );
+        check_eq!(
+            mmio::map(VGA_BUFFER_ADDRESS, 0, CacheMode::Uncached),
+            Err(Error::InvalidArgument)
+        );
+    }
+}
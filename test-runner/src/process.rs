@@ -0,0 +1,26 @@
+//! Tests creating processes with `veos_std::process`.
+
+use veos_std::process::{self, ExitStatus};
+
+/// The number of processes that `spawn_many` runs at the same time.
+const PROCESS_COUNT: usize = 32;
+
+veos_tests! {
+    /// Checks that many processes can run at the same time and that every
+    /// one of them is waited for with its own exit code.
+    fn spawn_many() {
+        let mut pids = [0; PROCESS_COUNT];
+
+        for (index, pid) in pids.iter_mut().enumerate() {
+            *pid = match process::fork() {
+                Ok(0) => process::exit_with_code(index as i32),
+                Ok(pid) => pid,
+                Err(error) => panic!("Could not fork: {}", error)
+            };
+        }
+
+        for (index, &pid) in pids.iter().enumerate() {
+            check_eq!(process::wait(pid, None), Ok(Some(ExitStatus::Exited(index as i32))));
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Tests the synchronization primitives of `veos_std::sync`.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use veos_std::sync::{Once, OnceLock, Parker};
+use veos_std::thread;
+use veos_std::time::Instant;
+
+/// The parker that the thread of `unpark_from_thread` unparks.
+static PARKER: Parker = Parker::new();
+
+veos_tests! {
+    /// Checks that a `Once` runs its initialization only once.
+    fn once_runs_once() {
+        let once = Once::new();
+        let calls = AtomicUsize::new(0);
+
+        for _ in 0..3 {
+            once.call_once(|| {
+                calls.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+
+        check!(once.is_completed());
+        check_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    /// Checks that a `OnceLock` keeps its first value.
+    fn once_lock_keeps_first_value() {
+        let lock = OnceLock::new();
+
+        check_eq!(lock.get(), None);
+        check_eq!(*lock.get_or_init(|| 1), 1);
+        check_eq!(*lock.get_or_init(|| 2), 1);
+        check_eq!(lock.set(3), Err(3));
+        check_eq!(lock.get(), Some(&1));
+    }
+
+    /// Checks that an earlier `unpark` isn't lost.
+    fn unpark_before_park() {
+        let parker = Parker::new();
+
+        parker.unpark();
+        parker.park();
+        check!(!parker.park_timeout(Duration::from_millis(10)));
+    }
+
+    /// Checks that `park_timeout` waits for the timeout.
+    fn park_timeout_expires() {
+        let parker = Parker::new();
+        let start = Instant::now();
+
+        check!(!parker.park_timeout(Duration::from_millis(50)));
+        check!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    /// Checks that another thread can wake a parked thread.
+    fn unpark_from_thread() {
+        let thread = thread::spawn(|| PARKER.unpark());
+
+        PARKER.park();
+        check!(thread.join().is_ok());
+    }
+}
@@ -0,0 +1,58 @@
+//! Tests creating threads with `veos_std::thread`.
+
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use veos_std::process;
+use veos_std::thread::{self, Builder};
+use veos_std::Error;
+
+/// Set by the threads of the tests.
+static THREAD_RAN: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Runs in the spawned threads.
+fn set_thread_ran() {
+    THREAD_RAN.store(true, Ordering::Release);
+}
+
+veos_tests! {
+    /// Checks that joining waits until the thread ran.
+    fn spawn_and_join() {
+        let thread = thread::spawn(set_thread_ran);
+
+        check!(thread.join().is_ok());
+        check!(THREAD_RAN.load(Ordering::Acquire));
+    }
+
+    /// Checks that threads can have larger stacks.
+    fn spawn_with_stack_size() {
+        let thread = Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(set_thread_ran)
+            .expect("Could not spawn the thread.");
+
+        check!(thread.join().is_ok());
+        check!(THREAD_RAN.load(Ordering::Acquire));
+    }
+
+    /// Checks that a thread can be named.
+    fn set_name() {
+        check!(thread::set_name("tested"));
+    }
+
+    /// Checks that only valid priorities can be set.
+    fn set_priority() {
+        check!(thread::set_priority(thread::MAX_PRIORITY).is_ok());
+        check!(thread::set_priority(thread::MIN_PRIORITY).is_ok());
+        check!(thread::set_priority(thread::MAX_PRIORITY + 1).is_err());
+        check!(thread::set_priority(-1).is_err());
+    }
+
+    /// Checks that only the default priority can be regained without the
+    /// capability to raise priorities.
+    fn raise_priority_needs_capability() {
+        check!(thread::set_priority(thread::MIN_PRIORITY).is_ok());
+        process::drop_capabilities(process::CAP_RAISE_PRIORITY);
+
+        check_eq!(thread::set_priority(thread::MAX_PRIORITY), Err(Error::PermissionDenied));
+        check!(thread::set_priority(thread::DEFAULT_PRIORITY).is_ok());
+    }
+}
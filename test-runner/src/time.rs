@@ -0,0 +1,37 @@
+//! Tests the clocks of `veos_std::time`.
+
+use core::time::Duration;
+use veos_std::thread;
+use veos_std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The seconds between the Unix epoch and the year 2000.
+const YEAR_2000: u64 = 946_684_800;
+
+veos_tests! {
+    /// Checks that instants don't go backwards.
+    fn instants_are_monotonic() {
+        let earlier = Instant::now();
+        let later = Instant::now();
+
+        check!(later >= earlier);
+        check_eq!(earlier.duration_since(later), Duration::new(0, 0));
+    }
+
+    /// Checks that sleeping takes at least the given time.
+    fn sleep_takes_time() {
+        let start = Instant::now();
+
+        thread::sleep(Duration::from_millis(20));
+        check!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    /// Checks that the system time lies after the year 2000, which the
+    /// hardware clock always reports.
+    fn system_time_is_plausible() {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("The system time lies before the epoch.");
+
+        check!(since_epoch.as_secs() >= YEAR_2000);
+    }
+}
@@ -1,12 +1,16 @@
-TARGET_FILES += $(TARGET_DIR)/bin/test
+TARGET_FILES += $(TARGET_DIR)/bin/test $(TARGET_DIR)/etc/servers/test.manifest
 BUILD_DIRS += test/target
-INITRAMFS_FILES += /bin/test
+INITRAMFS_FILES += /bin/test /etc/servers/test.manifest
 FMT_DIRS += test
 
 $(TARGET_DIR)/bin/test: test/target/$(BUILD_TARGET)/$(BUILD_TYPE)/test
 	@mkdir -p $(shell dirname $@)
 	cp $< $@
 
+$(TARGET_DIR)/etc/servers/test.manifest: test/test.manifest
+	@mkdir -p $(shell dirname $@)
+	cp $< $@
+
 test/target/$(BUILD_TARGET)/$(BUILD_TYPE)/test: test/target/$(BUILD_TARGET)/$(BUILD_TYPE)/libtest.a
 	$(LINKER) $(LINKER_FLAGS) $< -o $@
 
@@ -5,12 +5,38 @@ extern crate veos_std;
 #[allow(unused_extern_crates)]
 extern crate rlibc;
 
+use core::str;
 use core::time::Duration;
+use veos_std::ipc::message::{Endpoint, MAX_MESSAGE_SIZE};
 
 #[no_mangle]
 pub fn main() {
+    let endpoint = match Endpoint::create() {
+        Ok(endpoint) => endpoint,
+        Err(error) => {
+            println!("Could not create an endpoint: {}", error);
+            return;
+        }
+    };
+
+    if let Err(error) = veos_std::service::register("test", endpoint.id()) {
+        println!("Could not register the test service: {:?}", error);
+    }
+
+    let mut buffer = [0; MAX_MESSAGE_SIZE];
+
     loop {
-        veos_std::thread::sleep(Duration::from_millis(1000));
-        println!("Nest");
+        match endpoint.receive(&mut buffer, Some(Duration::from_millis(1000))) {
+            Ok(Some(message)) => {
+                let length = message.length.min(buffer.len());
+                let text = str::from_utf8(&buffer[..length]).unwrap_or("<invalid>");
+                println!("Message from {}: {}", message.sender, text);
+            }
+            Ok(None) => println!("Nest"),
+            Err(error) => {
+                println!("Could not receive a message: {}", error);
+                veos_std::thread::sleep(Duration::from_millis(1000));
+            }
+        }
     }
 }
@@ -0,0 +1,2 @@
+BUILD_DIRS += xtask/target
+FMT_DIRS += xtask
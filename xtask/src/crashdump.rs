@@ -0,0 +1,314 @@
+//! Finds a kernel crash dump in a memory image and prints it.
+//!
+//! The kernel writes the dump to a reserved memory area when it crashes. With
+//! `--memory-file` QEMU keeps the memory of the machine in a file, which still
+//! contains the dump after QEMU exited. A file that only contains the dump
+//! works as well. The format is described in `kernel/src/crashdump.rs`.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use util::{exit_with_error, ExitOnError};
+
+/// The magic number at the start of a dump.
+const MAGIC: &[u8] = b"VEOSDUMP";
+
+/// The version of the format.
+const VERSION: u64 = 1;
+
+/// The size of the header of a dump.
+const HEADER_SIZE: usize = 32;
+
+/// The size of the header of a section.
+const SECTION_HEADER_SIZE: usize = 8;
+
+/// The alignment of the sections.
+const SECTION_ALIGNMENT: usize = 8;
+
+/// The alignment of the dump in a memory image.
+const DUMP_ALIGNMENT: usize = 0x1000;
+
+/// The kind of the section that describes the reason of the crash.
+const REASON_SECTION: u32 = 1;
+
+/// The kind of the section that contains the backtrace of the crash.
+const BACKTRACE_SECTION: u32 = 2;
+
+/// The kind of the section that lists the threads running on each CPU.
+const THREADS_SECTION: u32 = 3;
+
+/// The kind of the section that contains the recent log records.
+const LOG_SECTION: u32 = 4;
+
+/// The kind of the section that contains the top level page table.
+const PAGE_TABLE_SECTION: u32 = 5;
+
+/// The bits of a page table entry that contain the physical address.
+const ENTRY_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// The size of the virtual memory that a top level entry maps.
+const ENTRY_AREA_SIZE: u64 = 1 << 39;
+
+/// A section of a dump.
+#[derive(Debug, PartialEq)]
+struct Section<'a> {
+    /// The kind of the section.
+    kind: u32,
+    /// The content of the section.
+    content: &'a [u8]
+}
+
+/// Prints the crash dump in the memory image at the given path.
+pub fn print(path: &Path) {
+    let image = fs::read(path).unwrap_or_exit(&format!("Could not read {}", path.display()));
+
+    match find(&image) {
+        Some((offset, sections)) => {
+            println!("Crash dump at offset {:#x}:", offset);
+            print!("{}", describe(&sections));
+        },
+        None => exit_with_error("No crash dump found", path.display())
+    }
+}
+
+/// Returns the offset and the sections of the first valid dump in the image.
+fn find<'a>(image: &'a [u8]) -> Option<(usize, Vec<Section<'a>>)> {
+    (0..image.len())
+        .step_by(DUMP_ALIGNMENT)
+        .filter_map(|offset| parse(&image[offset..]).map(|sections| (offset, sections)))
+        .next()
+}
+
+/// Returns the sections of the dump at the start of the bytes, if it is
+/// valid.
+fn parse<'a>(bytes: &'a [u8]) -> Option<Vec<Section<'a>>> {
+    if bytes.len() < HEADER_SIZE
+        || &bytes[..MAGIC.len()] != MAGIC
+        || read_le(bytes, 8, 4) != VERSION
+    {
+        return None;
+    }
+
+    let count = read_le(bytes, 12, 4) as usize;
+    let length = read_le(bytes, 16, 8);
+
+    if length > (bytes.len() - HEADER_SIZE) as u64 {
+        return None;
+    }
+
+    let content = &bytes[HEADER_SIZE..HEADER_SIZE + length as usize];
+
+    if checksum(content) != read_le(bytes, 24, 8) {
+        return None;
+    }
+
+    let mut sections = Vec::with_capacity(count);
+    let mut offset = 0;
+
+    while content.len() - offset >= SECTION_HEADER_SIZE {
+        let kind = read_le(content, offset, 4) as u32;
+        let length = read_le(content, offset + 4, 4) as usize;
+        let start = offset + SECTION_HEADER_SIZE;
+
+        if length > content.len() - start {
+            return None;
+        }
+
+        sections.push(Section {
+            kind,
+            content: &content[start..start + length]
+        });
+
+        offset = align_up(start + length, SECTION_ALIGNMENT);
+    }
+
+    if sections.len() == count {
+        Some(sections)
+    } else {
+        None
+    }
+}
+
+/// Returns the text that describes the sections.
+fn describe(sections: &[Section]) -> String {
+    let mut text = String::new();
+
+    for section in sections {
+        let title = match section.kind {
+            REASON_SECTION => "Reason".to_string(),
+            BACKTRACE_SECTION => "Backtrace".to_string(),
+            THREADS_SECTION => "Threads".to_string(),
+            LOG_SECTION => "Log".to_string(),
+            PAGE_TABLE_SECTION => "Top level page table".to_string(),
+            kind => format!("Unknown section {}", kind)
+        };
+
+        writeln!(text, "\n{}:", title).unwrap();
+
+        if section.kind == PAGE_TABLE_SECTION {
+            text.push_str(&describe_page_table(section.content));
+        } else {
+            for line in String::from_utf8_lossy(section.content).lines() {
+                writeln!(text, "    {}", line).unwrap();
+            }
+        }
+    }
+
+    text
+}
+
+/// Returns the text that describes the present entries of an x86_64 level 4
+/// page table.
+fn describe_page_table(content: &[u8]) -> String {
+    let mut text = String::new();
+
+    let entries = content
+        .chunks(8)
+        .map(|entry| read_le(entry, 0, entry.len()));
+
+    for (index, entry) in entries.enumerate() {
+        if entry & 1 == 0 {
+            continue;
+        }
+
+        let writable = if entry & 1 << 1 != 0 {
+            "writable"
+        } else {
+            "read-only"
+        };
+        let user = if entry & 1 << 2 != 0 {
+            "user"
+        } else {
+            "kernel"
+        };
+        let executable = if entry & 1 << 63 != 0 {
+            "no-execute"
+        } else {
+            "executable"
+        };
+
+        // The upper half of the entries maps the sign extended addresses.
+        let mut start = index as u64 * ENTRY_AREA_SIZE;
+        if index >= 256 {
+            start |= 0xffff_0000_0000_0000;
+        }
+
+        writeln!(
+            text,
+            "    {:3} {:#018x}-{:#018x} -> {:#x} {} {} {}",
+            index,
+            start,
+            start + (ENTRY_AREA_SIZE - 1),
+            entry & ENTRY_ADDRESS_MASK,
+            writable,
+            user,
+            executable
+        ).unwrap();
+    }
+
+    text
+}
+
+/// Returns the FNV-1a hash of the bytes, which is the checksum of a dump.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Reads a little endian number of `size` bytes at `offset`.
+fn read_le(bytes: &[u8], offset: usize, size: usize) -> u64 {
+    bytes[offset..offset + size]
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | u64::from(byte))
+}
+
+/// Rounds the value up to a multiple of the alignment.
+fn align_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a dump with the given sections.
+    fn dump(sections: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut content = Vec::new();
+
+        for &(kind, section) in sections {
+            content.extend_from_slice(&kind.to_le_bytes());
+            content.extend_from_slice(&(section.len() as u32).to_le_bytes());
+            content.extend_from_slice(section);
+            let length = align_up(content.len(), SECTION_ALIGNMENT);
+            content.resize(length, 0);
+        }
+
+        let mut dump = MAGIC.to_vec();
+        dump.extend_from_slice(&1u32.to_le_bytes());
+        dump.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+        dump.extend_from_slice(&(content.len() as u64).to_le_bytes());
+        dump.extend_from_slice(&checksum(&content).to_le_bytes());
+        dump.extend_from_slice(&content);
+        dump
+    }
+
+    /// Tests that a dump is found at a page boundary of the image.
+    #[test]
+    fn test_find() {
+        let mut image = vec![0; 3 * DUMP_ALIGNMENT];
+        let dump = dump(&[
+            (REASON_SECTION, b"panicked"),
+            (LOG_SECTION, b"first\nsecond"),
+        ]);
+        image[2 * DUMP_ALIGNMENT..2 * DUMP_ALIGNMENT + dump.len()].copy_from_slice(&dump);
+
+        let (offset, sections) = find(&image).unwrap();
+
+        assert_eq!(offset, 2 * DUMP_ALIGNMENT);
+        assert_eq!(
+            sections,
+            vec![
+                Section {
+                    kind: REASON_SECTION,
+                    content: b"panicked"
+                },
+                Section {
+                    kind: LOG_SECTION,
+                    content: b"first\nsecond"
+                },
+            ]
+        );
+        assert_eq!(
+            describe(&sections),
+            "\nReason:\n    panicked\n\nLog:\n    first\n    second\n"
+        );
+    }
+
+    /// Tests that dumps with a wrong checksum or length are rejected.
+    #[test]
+    fn test_invalid_dump() {
+        let mut dump = dump(&[(REASON_SECTION, b"panicked")]);
+        assert!(parse(&dump).is_some());
+        assert!(parse(&dump[..dump.len() - 1]).is_none());
+
+        dump[HEADER_SIZE + SECTION_HEADER_SIZE] = b'P';
+        assert!(parse(&dump).is_none());
+        assert!(find(&[0; 2 * DUMP_ALIGNMENT]).is_none());
+    }
+
+    /// Tests that the present page table entries are decoded.
+    #[test]
+    fn test_describe_page_table() {
+        let mut entries = vec![0; 512 * 8];
+        entries[..8].copy_from_slice(&0x0000_0000_0020_0007u64.to_le_bytes());
+        entries[256 * 8..257 * 8].copy_from_slice(&0x8000_0000_0030_0003u64.to_le_bytes());
+
+        assert_eq!(
+            describe_page_table(&entries),
+            "      0 0x0000000000000000-0x0000007fffffffff -> 0x200000 writable user executable\n    \
+             256 0xffff800000000000-0xffff807fffffffff -> 0x300000 writable kernel no-execute\n"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Describes the cargo features of the kernel and validates combinations of
+//! them.
+//!
+//! Every feature in the `[features]` section of `kernel/Cargo.toml` has an
+//! entry in `KERNEL_FEATURES`. Features that need another feature list it in
+//! `requires`, features that can't be combined list each other in `conflicts`.
+
+/// A cargo feature of the kernel.
+#[derive(Debug)]
+pub struct Feature {
+    /// The name of the feature.
+    pub name: &'static str,
+    /// What the feature does.
+    pub description: &'static str,
+    /// The features that must be enabled together with this one.
+    pub requires: &'static [&'static str],
+    /// The features that must not be enabled together with this one.
+    pub conflicts: &'static [&'static str]
+}
+
+/// All cargo features of the kernel.
+pub const KERNEL_FEATURES: &[Feature] = &[
+    Feature {
+        name: "alloc_tracking",
+        description: "Records the call sites of live heap and page frame allocations.",
+        requires: &[],
+        conflicts: &[]
+    },
+    Feature {
+        name: "benchmark",
+        description: "Runs the kernel microbenchmarks during boot.",
+        requires: &[],
+        conflicts: &[]
+    },
+    Feature {
+        name: "coverage",
+        description: "Counts the calls of the instrumented kernel functions.",
+        requires: &[],
+        conflicts: &[]
+    },
+    Feature {
+        name: "deferred_screen_flush",
+        description: "Flushes the screen periodically instead of after every print.",
+        requires: &[],
+        conflicts: &[]
+    },
+    Feature {
+        name: "lock_stats",
+        description: "Counts the acquisitions, contention and hold times of named kernel locks.",
+        requires: &[],
+        conflicts: &[]
+    },
+    Feature {
+        name: "page_table_checks",
+        description: "Verifies the invariants of the page tables at run time.",
+        requires: &[],
+        conflicts: &[]
+    },
+    Feature {
+        name: "preemption_audit",
+        description: "Reports regions in which preemption is disabled for too long.",
+        requires: &[],
+        conflicts: &[]
+    },
+];
+
+/// Checks that the kernel can be built with the given features.
+pub fn validate<S: AsRef<str>>(features: &[S]) -> Result<(), String> {
+    validate_with(KERNEL_FEATURES, features)
+}
+
+/// Prints all kernel features with their descriptions and dependencies.
+pub fn print() {
+    for feature in KERNEL_FEATURES {
+        eprintln!("{:<20} {}", feature.name, feature.description);
+
+        if !feature.requires.is_empty() {
+            eprintln!("{:<20} Requires {}.", "", feature.requires.join(", "));
+        }
+
+        if !feature.conflicts.is_empty() {
+            eprintln!("{:<20} Conflicts with {}.", "", feature.conflicts.join(", "));
+        }
+    }
+}
+
+/// Checks the features against the given feature descriptions.
+fn validate_with<S: AsRef<str>>(known: &[Feature], features: &[S]) -> Result<(), String> {
+    let enabled = |name: &str| features.iter().any(|feature| feature.as_ref() == name);
+
+    for name in features.iter().map(|feature| feature.as_ref()) {
+        let feature = known
+            .iter()
+            .find(|feature| feature.name == name)
+            .ok_or_else(|| format!("unknown feature {}", name))?;
+
+        if let Some(missing) = feature.requires.iter().find(|&&other| !enabled(other)) {
+            return Err(format!("the feature {} requires the feature {}", name, missing));
+        }
+
+        if let Some(conflict) = feature.conflicts.iter().find(|&&other| enabled(other)) {
+            return Err(format!("the feature {} conflicts with the feature {}", name, conflict));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The features used to test the validation.
+    const TEST_FEATURES: &[Feature] = &[
+        Feature {
+            name: "a",
+            description: "",
+            requires: &["b"],
+            conflicts: &[]
+        },
+        Feature {
+            name: "b",
+            description: "",
+            requires: &[],
+            conflicts: &["c"]
+        },
+        Feature {
+            name: "c",
+            description: "",
+            requires: &[],
+            conflicts: &[]
+        },
+    ];
+
+    /// Tests that unknown features, missing requirements and conflicts are
+    /// rejected.
+    #[test]
+    fn test_validate() {
+        assert!(validate_with(TEST_FEATURES, &[] as &[&str]).is_ok());
+        assert!(validate_with(TEST_FEATURES, &["a", "b"]).is_ok());
+        assert!(validate_with(TEST_FEATURES, &["c"]).is_ok());
+        assert!(validate_with(TEST_FEATURES, &["d"]).is_err());
+        assert!(validate_with(TEST_FEATURES, &["a"]).is_err());
+        assert!(validate_with(TEST_FEATURES, &["a", "b", "c"]).is_err());
+    }
+
+    /// Tests that the descriptions match the features in the kernel manifest.
+    #[test]
+    fn test_matches_manifest() {
+        let manifest = include_str!("../../kernel/Cargo.toml");
+        let mut names: Vec<&str> = manifest
+            .lines()
+            .skip_while(|line| line.trim() != "[features]")
+            .skip(1)
+            .take_while(|line| !line.starts_with('['))
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split('=').next())
+            .map(|name| name.trim())
+            .collect();
+        names.sort();
+
+        let mut known: Vec<&str> = KERNEL_FEATURES.iter().map(|feature| feature.name).collect();
+        known.sort();
+
+        assert_eq!(names, known);
+
+        for feature in KERNEL_FEATURES {
+            for other in feature.requires.iter().chain(feature.conflicts) {
+                assert!(known.contains(other), "{} names the unknown feature {}", feature.name, other);
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Creates the bootable image.
+
+use std::fs;
+use std::process::Command;
+use util::{self, ExitOnError};
+use {ARCH, ISO, TARGET_DIR};
+
+/// The line of the boot loader configuration that loads the kernel.
+const KERNEL_ENTRY: &str = "multiboot /boot/kernel.bin";
+
+/// Writes the boot loader configuration into the target directory, passing
+/// the given command line to the kernel.
+pub fn write_boot_config(command_line: &str) {
+    let source = util::path(format!("kernel/src/arch/{}/grub.cfg", ARCH));
+    let config = fs::read_to_string(&source)
+        .unwrap_or_exit(&format!("Could not read {}", source.display()));
+
+    let config = if command_line.is_empty() {
+        config
+    } else {
+        config.replace(KERNEL_ENTRY, &format!("{} {}", KERNEL_ENTRY, command_line))
+    };
+
+    util::write(
+        util::path(TARGET_DIR).join("boot/grub/grub.cfg"),
+        config.as_bytes()
+    );
+}
+
+/// Creates a bootable GRUB image from the target directory.
+pub fn create_iso() {
+    util::run(
+        Command::new("grub-mkrescue")
+            .arg("-o")
+            .arg(util::path(ISO))
+            .arg(util::path(TARGET_DIR))
+    );
+}
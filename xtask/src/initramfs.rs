@@ -0,0 +1,207 @@
+//! Builds the user programs and the initramfs from the manifest.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use util::{self, exit_with_error, ExitOnError};
+use {Options, TARGET_DIR, USER_TARGET};
+
+/// The path of the manifest, relative to the repository root.
+const MANIFEST: &str = "initramfs.manifest";
+
+/// The prefix of sources that are user programs.
+const PROGRAM_PREFIX: &str = "program:";
+
+/// The source of a file in the initramfs.
+#[derive(Debug, PartialEq)]
+enum Source {
+    /// The user program built from the crate with the given name.
+    Program(String),
+    /// The file at the given path relative to the repository root.
+    File(PathBuf)
+}
+
+/// A file in the initramfs.
+#[derive(Debug, PartialEq)]
+struct Entry {
+    /// The path of the file within the initramfs.
+    path: String,
+    /// Where the content of the file comes from.
+    source: Source
+}
+
+/// Builds the programs listed in the manifest and creates the initramfs in
+/// the target directory.
+pub fn build(options: &Options) {
+    let manifest_path = util::path(MANIFEST);
+    let manifest = fs::read_to_string(&manifest_path)
+        .unwrap_or_exit(&format!("Could not read {}", manifest_path.display()));
+    let entries = parse_manifest(&manifest).unwrap_or_exit("Invalid initramfs manifest");
+
+    let target_dir = util::path(TARGET_DIR);
+
+    for entry in &entries {
+        let source = match entry.source {
+            Source::Program(ref name) => build_program(name, options),
+            Source::File(ref path) => util::path(path)
+        };
+
+        // The paths in the manifest are absolute.
+        util::copy(source, target_dir.join(&entry.path[1..]));
+    }
+
+    let mut file_list = String::new();
+    for entry in &entries {
+        file_list.push_str(&entry.path);
+        file_list.push('\n');
+    }
+
+    let file_list_path = target_dir.join("conf/mkinitramfs");
+    util::write(&file_list_path, file_list.as_bytes());
+
+    let mut command = mkinitramfs();
+
+    if let Some(ref signing_key) = options.signing_key {
+        command.arg("--sign").arg(signing_key);
+    }
+
+    let initramfs_path = target_dir.join("boot/initramfs");
+    util::create_parent(&initramfs_path);
+    util::run(command.arg(&file_list_path).arg(&initramfs_path).arg(&target_dir));
+}
+
+/// Returns the public key that belongs to the secret key in the given file
+/// in hexadecimal.
+pub fn public_key(signing_key: &Path) -> String {
+    let output = util::output(mkinitramfs().arg("--public-key").arg(signing_key));
+
+    String::from_utf8_lossy(&output).trim().to_string()
+}
+
+/// Builds `mkinitramfs` and returns a command that runs it.
+fn mkinitramfs() -> Command {
+    util::run(
+        Command::new("cargo")
+            .current_dir(util::path("mkinitramfs"))
+            .args(["build", "--release"])
+    );
+
+    Command::new(util::path("mkinitramfs/target/release/mkinitramfs"))
+}
+
+/// Parses the lines of the manifest.
+///
+/// Empty lines and lines starting with `#` are ignored.
+fn parse_manifest(manifest: &str) -> Result<Vec<Entry>, String> {
+    let mut entries: Vec<Entry> = Vec::new();
+
+    for (index, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let (path, source) = match (words.next(), words.next(), words.next()) {
+            (Some(path), Some(source), None) => (path, source),
+            _ => return Err(format!("line {}: expected a path and a source", index + 1))
+        };
+
+        if !path.starts_with('/') {
+            return Err(format!("line {}: {} is not an absolute path", index + 1, path));
+        }
+
+        if entries.iter().any(|entry| entry.path == path) {
+            return Err(format!("line {}: {} is listed twice", index + 1, path));
+        }
+
+        let source = match source.strip_prefix(PROGRAM_PREFIX) {
+            Some(program) => Source::Program(program.to_string()),
+            None => Source::File(PathBuf::from(source))
+        };
+
+        entries.push(Entry {
+            path: path.to_string(),
+            source
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Builds the user program from the crate with the given name and returns the
+/// path of the binary.
+fn build_program(name: &str, options: &Options) -> PathBuf {
+    let crate_dir = util::path(name);
+
+    if !crate_dir.join("Cargo.toml").is_file() {
+        exit_with_error(
+            &format!("Could not build the program {}", name),
+            "there is no crate with that name"
+        );
+    }
+
+    let mut command = Command::new("xargo");
+    command
+        .current_dir(&crate_dir)
+        .env("RUST_TARGET_PATH", util::path("targets"))
+        .args(["build", "--target", USER_TARGET]);
+
+    if options.release {
+        command.arg("--release");
+    }
+
+    util::run(&mut command);
+
+    let output_dir = crate_dir
+        .join("target")
+        .join(USER_TARGET)
+        .join(options.profile());
+    let binary = output_dir.join(name);
+
+    // Cargo replaces the hyphens in library names with underscores.
+    util::run(
+        Command::new("ld")
+            .arg("--gc-sections")
+            .arg(output_dir.join(format!("lib{}.a", name.replace('-', "_"))))
+            .arg("-o")
+            .arg(&binary)
+    );
+
+    binary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests parsing programs, files and comments.
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = "# A comment.\n\n/bin/init program:init\n/etc/a.conf  conf/a.conf\n";
+
+        assert_eq!(
+            parse_manifest(manifest),
+            Ok(vec![
+                Entry {
+                    path: "/bin/init".to_string(),
+                    source: Source::Program("init".to_string())
+                },
+                Entry {
+                    path: "/etc/a.conf".to_string(),
+                    source: Source::File(PathBuf::from("conf/a.conf"))
+                },
+            ])
+        );
+    }
+
+    /// Tests that malformed lines are rejected.
+    #[test]
+    fn test_invalid_manifest() {
+        assert!(parse_manifest("/bin/init").is_err());
+        assert!(parse_manifest("/bin/init program:init extra").is_err());
+        assert!(parse_manifest("bin/init program:init").is_err());
+        assert!(parse_manifest("/bin/init program:init\n/bin/init program:test").is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! Builds the kernel binary.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use initramfs;
+use util::{self, ExitOnError};
+use {Options, ARCH, KERNEL_TARGET, TARGET_DIR};
+
+/// Builds the kernel and copies it into the target directory.
+pub fn build(options: &Options) {
+    let library = build_library(options);
+    let build_dir = util::path(format!("kernel/target/{}/build", KERNEL_TARGET));
+    let objects = assemble_sources(&build_dir);
+
+    // The kernel is linked twice. The first link uses an empty symbol table
+    // and determines the addresses of all functions. The second link includes
+    // the symbol table generated from the first one. The symbol table is
+    // placed after the code, so the addresses of the functions don't change.
+    let empty_symbols = generate_symbols(None, &build_dir.join("ksymbols-empty.asm"));
+    let binary_without_symbols = build_dir.join(format!("kernel-{}.nosym.bin", ARCH));
+    link(&objects, &empty_symbols, &library, &binary_without_symbols);
+
+    let symbols = generate_symbols(
+        Some(&binary_without_symbols),
+        &build_dir.join("ksymbols.asm")
+    );
+    let binary = build_dir.join(format!("kernel-{}.bin", ARCH));
+    link(&objects, &symbols, &library, &binary);
+
+    util::copy(&binary, util::path(TARGET_DIR).join("boot/kernel.bin"));
+}
+
+/// Compiles the kernel crate and returns the path of the static library.
+fn build_library(options: &Options) -> PathBuf {
+    let mut command = Command::new("xargo");
+    command
+        .current_dir(util::path("kernel"))
+        .env("RUST_TARGET_PATH", util::path("targets"))
+        .args(["build", "--target", KERNEL_TARGET]);
+
+    if options.release {
+        command.arg("--release");
+    }
+
+    if !options.features.is_empty() {
+        command.arg("--features").arg(options.features.join(" "));
+    }
+
+    // The kernel verifies the signatures of executables with the public key.
+    if let Some(ref signing_key) = options.signing_key {
+        command.env("VEOS_EXEC_PUBLIC_KEY", initramfs::public_key(signing_key));
+    }
+
+    util::run(&mut command);
+
+    util::path(format!(
+        "kernel/target/{}/{}/libveos.a",
+        KERNEL_TARGET,
+        options.profile()
+    ))
+}
+
+/// Assembles the assembly sources of the kernel and returns the object files.
+fn assemble_sources(build_dir: &Path) -> Vec<PathBuf> {
+    let source_dir = util::path(format!("kernel/src/arch/{}/init", ARCH));
+    let mut sources: Vec<PathBuf> = fs::read_dir(&source_dir)
+        .unwrap_or_exit(&format!("Could not read {}", source_dir.display()))
+        .map(|entry| {
+            entry
+                .unwrap_or_exit(&format!("Could not read {}", source_dir.display()))
+                .path()
+        })
+        .filter(|path| path.extension().is_some_and(|extension| extension == "asm"))
+        .collect();
+
+    // Keep the link order stable.
+    sources.sort();
+
+    sources
+        .iter()
+        .map(|source| {
+            let object = build_dir
+                .join(format!("arch/{}/init", ARCH))
+                .join(source.file_name().unwrap())
+                .with_extension("o");
+
+            assemble(source, &object);
+
+            object
+        })
+        .collect()
+}
+
+/// Assembles a single source file.
+fn assemble(source: &Path, object: &Path) {
+    util::create_parent(object);
+    util::run(Command::new("nasm").arg("-felf64").arg(source).arg("-o").arg(object));
+}
+
+/// Generates and assembles the symbol table for the given binary and returns
+/// the object file.
+///
+/// Without a binary, an empty symbol table is generated.
+fn generate_symbols(binary: Option<&Path>, source: &Path) -> PathBuf {
+    let mut command = Command::new(util::path("kernel/mksymbols.sh"));
+
+    if let Some(binary) = binary {
+        command.arg(binary);
+    }
+
+    util::write(source, &util::output(&mut command));
+
+    let object = source.with_extension("o");
+    assemble(source, &object);
+
+    object
+}
+
+/// Links the kernel binary.
+fn link(objects: &[PathBuf], symbols: &Path, library: &Path, binary: &Path) {
+    util::run(
+        Command::new("ld")
+            .args(["-n", "--gc-sections", "-T"])
+            .arg(util::path(format!("kernel/src/arch/{}/linker.ld", ARCH)))
+            .arg("-o")
+            .arg(binary)
+            .args(objects)
+            .arg(symbols)
+            .arg(library)
+    );
+}
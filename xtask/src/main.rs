@@ -0,0 +1,237 @@
+//! This crate builds and runs VeOS.
+//!
+//! It is the single entry point for building the kernel and the user programs,
+//! creating the initramfs and the bootable image, running it in QEMU and
+//! running the end-to-end test scenarios. Run it with `cargo xtask <command>`.
+
+mod crashdump;
+mod features;
+mod image;
+mod initramfs;
+mod kernel;
+mod qemu;
+mod scenario;
+mod util;
+
+use std::env::args;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+/// The architecture that VeOS is built for.
+pub const ARCH: &str = "x86_64";
+
+/// The target triple of the kernel.
+pub const KERNEL_TARGET: &str = "x86_64-unknown-none-gnu";
+
+/// The target triple of the user programs.
+pub const USER_TARGET: &str = "x86_64-unknown-veos-gnu";
+
+/// The directory that contains the file tree of the image, relative to the
+/// repository root.
+pub const TARGET_DIR: &str = "target";
+
+/// The path of the bootable image, relative to the repository root.
+pub const ISO: &str = "image.iso";
+
+/// The options that influence the build and the run of VeOS.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Whether to build with optimizations.
+    pub release: bool,
+    /// The cargo features the kernel is built with.
+    pub features: Vec<String>,
+    /// The options passed to the kernel on its command line.
+    pub command_line: Vec<String>,
+    /// The file that the serial output is written to instead of stdout.
+    pub serial_log: Option<PathBuf>,
+    /// Whether the serial input is read from the standard input of QEMU
+    /// while the serial output is written to the serial log.
+    pub serial_input: bool,
+    /// The file with the secret key that the executables are signed with.
+    pub signing_key: Option<PathBuf>,
+    /// The host directory that the kernel mounts at `/host/`.
+    pub share: Option<PathBuf>,
+    /// The file that keeps the memory of the machine, so that crash dumps
+    /// can be read after QEMU exited.
+    pub memory_file: Option<PathBuf>,
+    /// Whether the kernel runs in test mode and QEMU runs without a display
+    /// and can be exited by the kernel.
+    pub test: bool,
+    /// Whether QEMU waits for a debugger and logs interrupts.
+    pub debug: bool,
+    /// Whether QEMU runs without KVM.
+    pub no_kvm: bool
+}
+
+impl Options {
+    /// Returns the cargo profile directory name for the options.
+    pub fn profile(&self) -> &'static str {
+        if self.release {
+            "release"
+        } else {
+            "debug"
+        }
+    }
+
+    /// Returns the command line passed to the kernel.
+    pub fn kernel_command_line(&self) -> String {
+        let mut command_line = if self.test {
+            vec!["test".to_string()]
+        } else {
+            Vec::new()
+        };
+
+        command_line.extend(self.command_line.iter().cloned());
+
+        command_line.join(" ")
+    }
+}
+
+/// The main entry point for the application.
+fn main() {
+    let arguments: Vec<String> = args().skip(1).collect();
+
+    let command = match arguments.first() {
+        Some(command) => command.clone(),
+        None => print_usage("No command supplied.")
+    };
+
+    let (options, names) = parse_options(&arguments[1..]);
+
+    if command != "test" && command != "crashdump" && !names.is_empty() {
+        print_usage(&format!("Unexpected argument \"{}\".", names[0]));
+    }
+
+    match &command[..] {
+        "build" => build(&options),
+        "iso" => {
+            build(&options);
+            image::create_iso();
+        },
+        "run" => {
+            build(&options);
+            image::create_iso();
+            exit(qemu::run(&options));
+        },
+        "test" => {
+            build(&options);
+            if !scenario::run(&options, &names) {
+                exit(1);
+            }
+        },
+        "crashdump" => match names.first() {
+            Some(path) => crashdump::print(Path::new(path)),
+            None => print_usage("crashdump needs a memory image.")
+        },
+        "features" => features::print(),
+        "clean" => util::clean(),
+        "help" | "--help" | "-h" => print_usage(""),
+        _ => print_usage(&format!("Unknown command \"{}\".", command))
+    }
+}
+
+/// Builds the kernel, the user programs and the initramfs into the target
+/// directory.
+fn build(options: &Options) {
+    if let Err(error) = features::validate(&options.features) {
+        util::exit_with_error("Invalid kernel features", error);
+    }
+
+    kernel::build(options);
+    image::write_boot_config(&options.kernel_command_line());
+    initramfs::build(options);
+}
+
+/// Parses the options following the command.
+///
+/// Returns the options and the other arguments.
+fn parse_options(arguments: &[String]) -> (Options, Vec<String>) {
+    let mut options = Options::default();
+    let mut names = Vec::new();
+    let mut arguments = arguments.iter();
+
+    while let Some(argument) = arguments.next() {
+        match &argument[..] {
+            "--release" => options.release = true,
+            "--features" => match arguments.next() {
+                Some(features) => options.features.extend(
+                    features
+                        .split([',', ' '])
+                        .filter(|feature| !feature.is_empty())
+                        .map(|feature| feature.to_string())
+                ),
+                None => print_usage("--features needs a list of features.")
+            },
+            "--command-line" => match arguments.next() {
+                Some(command_line) => options
+                    .command_line
+                    .extend(command_line.split_whitespace().map(|word| word.to_string())),
+                None => print_usage("--command-line needs a list of kernel options.")
+            },
+            "--serial-log" => match arguments.next() {
+                Some(path) => options.serial_log = Some(PathBuf::from(path)),
+                None => print_usage("--serial-log needs a file.")
+            },
+            "--signing-key" => match arguments.next() {
+                Some(path) => options.signing_key = Some(PathBuf::from(path)),
+                None => print_usage("--signing-key needs a key file.")
+            },
+            "--share" => match arguments.next() {
+                Some(path) => options.share = Some(PathBuf::from(path)),
+                None => print_usage("--share needs a directory.")
+            },
+            "--memory-file" => match arguments.next() {
+                Some(path) => options.memory_file = Some(PathBuf::from(path)),
+                None => print_usage("--memory-file needs a file.")
+            },
+            "--test" => options.test = true,
+            "--debug" => options.debug = true,
+            "--no-kvm" => options.no_kvm = true,
+            _ if argument.starts_with('-') => {
+                print_usage(&format!("Unknown option \"{}\".", argument))
+            },
+            _ => names.push(argument.clone())
+        }
+    }
+
+    (options, names)
+}
+
+/// Prints usage information and exits.
+fn print_usage(error: &str) -> ! {
+    if !error.is_empty() {
+        eprintln!("{}", error);
+        eprintln!();
+    }
+    eprintln!("Usage:");
+    eprintln!("cargo xtask command [options]");
+    eprintln!("cargo xtask test [options] [scenarios]");
+    eprintln!("cargo xtask crashdump <memory image>");
+    eprintln!();
+    eprintln!("Commands:");
+    eprintln!("    build     Builds the kernel, the user programs and the initramfs.");
+    eprintln!("    iso       Builds everything and creates the bootable image.");
+    eprintln!("    run       Builds everything and runs the image in QEMU.");
+    eprintln!("    test      Builds everything and runs the given or all test scenarios.");
+    eprintln!("    crashdump Prints the kernel crash dump in a memory image.");
+    eprintln!("    features  Lists the kernel features and their dependencies.");
+    eprintln!("    clean     Removes all build output.");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("    --release              Builds with optimizations.");
+    eprintln!("    --features <features>  Builds the kernel with the given features.");
+    eprintln!("    --command-line <args>  Passes the arguments to the kernel.");
+    eprintln!("    --serial-log <file>    Writes the serial output to the file.");
+    eprintln!("    --signing-key <file>   Signs the executables with the key in the file.");
+    eprintln!("    --share <directory>    Shares the directory with the kernel at /host/.");
+    eprintln!("    --memory-file <file>   Keeps the memory of the machine in the file.");
+    eprintln!("    --test                 Runs the kernel in test mode, without a display.");
+    eprintln!("    --debug                Waits for gdb and logs interrupts.");
+    eprintln!("    --no-kvm               Runs QEMU without KVM.");
+
+    if error.is_empty() {
+        exit(0)
+    } else {
+        exit(1)
+    }
+}
@@ -0,0 +1,91 @@
+//! Runs the bootable image in QEMU.
+
+use std::process::Command;
+use util::{self, ExitOnError};
+use {Options, ARCH, ISO};
+
+/// The I/O port of the exit device in test mode.
+pub const EXIT_PORT: u16 = 0xf4;
+
+/// The size of the memory of the machine.
+const MEMORY_SIZE: &str = "128M";
+
+/// The tag of the shared host directory.
+const SHARE_TAG: &str = "host";
+
+/// Runs the image in QEMU and returns the exit code of QEMU.
+pub fn run(options: &Options) -> i32 {
+    let mut command = command(options);
+
+    eprintln!("Running {:?}", command);
+
+    let status = command
+        .status()
+        .unwrap_or_exit(&format!("Could not run {:?}", command));
+
+    status.code().unwrap_or(1)
+}
+
+/// Returns the QEMU command that runs the image with the given options.
+pub fn command(options: &Options) -> Command {
+    let mut command = Command::new(format!("qemu-system-{}", ARCH));
+    command
+        .arg("-cdrom")
+        .arg(util::path(ISO))
+        .args(["--no-reboot", "-smp", "cores=4", "-s"])
+        .args(["-device", "virtio-rng-pci", "-device", "AC97"]);
+
+    match options.serial_log {
+        Some(ref path) if options.serial_input => {
+            util::create_parent(path);
+            command
+                .arg("-chardev")
+                .arg(format!(
+                    "stdio,id=serial,signal=off,logfile={}",
+                    path.display()
+                ))
+                .args(["-serial", "chardev:serial"]);
+        },
+        Some(ref path) => {
+            util::create_parent(path);
+            command.arg("-serial").arg(format!("file:{}", path.display()));
+        },
+        None => {
+            command.args(["-serial", "stdio"]);
+        }
+    }
+
+    if let Some(ref path) = options.share {
+        command.arg("-virtfs").arg(format!(
+            "local,path={},mount_tag={},security_model=none,readonly=on",
+            path.display(),
+            SHARE_TAG
+        ));
+    }
+
+    if let Some(ref path) = options.memory_file {
+        util::create_parent(path);
+        command
+            .args(["-m", MEMORY_SIZE, "-object"])
+            .arg(format!(
+                "memory-backend-file,id=ram,size={},mem-path={},share=on",
+                MEMORY_SIZE,
+                path.display()
+            ))
+            .args(["-machine", "memory-backend=ram"]);
+    }
+
+    if options.test {
+        command
+            .args(["-display", "none", "-device"])
+            .arg(format!("isa-debug-exit,iobase={:#x},iosize=0x04", EXIT_PORT));
+    }
+
+    if options.debug {
+        command.args(["-d", "int", "-S"]);
+    } else if !options.no_kvm {
+        command.arg("-enable-kvm");
+    }
+
+    command
+}
@@ -0,0 +1,286 @@
+//! Runs the end-to-end test scenarios.
+//!
+//! Each scenario boots the image in QEMU with the kernel in test mode and
+//! checks the serial output and the exit code of QEMU, typing into the serial
+//! port where the scenario needs input. In test mode, the kernel exits QEMU
+//! when it panics, so a scenario that doesn't expect an exit code fails as
+//! soon as QEMU exits.
+
+use image;
+use qemu;
+use std::fs;
+use std::io::Write;
+use std::process::{Child, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use util::{self, ExitOnError};
+use Options;
+
+/// The directory that the serial output of the scenarios is written to,
+/// relative to the repository root.
+const LOG_DIR: &str = "xtask/target/scenarios";
+
+/// The time between two checks of a running scenario.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A test scenario.
+struct Scenario {
+    /// The name used to select the scenario.
+    name: &'static str,
+    /// The options added to the kernel command line.
+    command_line: &'static str,
+    /// The strings that must appear in the serial output, in this order.
+    expected_output: &'static [&'static str],
+    /// The text typed into the serial port, each once the string before it
+    /// appeared in the serial output, in this order.
+    serial_input: &'static [(&'static str, &'static str)],
+    /// The exit code that QEMU must exit with.
+    ///
+    /// Without one, the scenario passes as soon as the expected output was
+    /// seen.
+    exit_code: Option<i32>,
+    /// The time in seconds after which the scenario fails.
+    timeout: u64
+}
+
+/// All test scenarios.
+const SCENARIOS: &[Scenario] = &[
+    // Boots to init, which prints "Test" and starts the test server, which
+    // prints "Nest".
+    Scenario {
+        name: "boot",
+        command_line: "",
+        expected_output: &["Running in test mode.", "Test", "Nest"],
+        serial_input: &[],
+        exit_code: None,
+        timeout: 60
+    },
+    // Checks that the message init sends to the test server arrives.
+    Scenario {
+        name: "ipc",
+        command_line: "",
+        expected_output: &["Test", "Message from", "Hello from init"],
+        serial_input: &[],
+        exit_code: None,
+        timeout: 60
+    },
+    // Checks that kernel panics are reported with the failure exit code.
+    Scenario {
+        name: "panic",
+        command_line: "test_panic",
+        expected_output: &["A panic was requested on the command line."],
+        serial_input: &[],
+        exit_code: Some(3),
+        timeout: 60
+    },
+    // Makes random syscalls, which the kernel must survive without breaking
+    // the syscall invariants it checks in test mode.
+    Scenario {
+        name: "syscall-fuzz",
+        command_line: "syscall_fuzz_seed=1",
+        expected_output: &["Fuzzing", "The kernel survived"],
+        serial_input: &[],
+        exit_code: None,
+        timeout: 120
+    },
+    // Runs the tests of the standard library in the image.
+    Scenario {
+        name: "userspace-tests",
+        command_line: "run_tests=all",
+        expected_output: &["Running", "test result: ok."],
+        serial_input: &[],
+        exit_code: None,
+        timeout: 120
+    },
+    // Forks many processes at the same time and waits for all of them.
+    Scenario {
+        name: "spawn-many",
+        command_line: "run_tests=process::spawn_many",
+        expected_output: &["Running", "test result: ok. 1 passed"],
+        serial_input: &[],
+        exit_code: None,
+        timeout: 120
+    },
+    // Uses the kernel shell on the serial port once init is running.
+    Scenario {
+        name: "serial-shell",
+        command_line: "",
+        expected_output: &["Nest", "PID", "init", "KiB free"],
+        serial_input: &[("Nest", "ps\n"), ("PID", "mem\n")],
+        exit_code: None,
+        timeout: 60
+    },
+];
+
+/// Runs the scenarios with the given names, or all scenarios without names.
+///
+/// Returns true if all of them passed.
+pub fn run(options: &Options, names: &[String]) -> bool {
+    if let Some(name) = names
+        .iter()
+        .find(|name| !SCENARIOS.iter().any(|scenario| scenario.name == *name))
+    {
+        util::exit_with_error("Could not run the scenarios", format!("unknown scenario {}", name));
+    }
+
+    let mut failed = 0;
+    let mut count = 0;
+
+    for scenario in SCENARIOS {
+        if !names.is_empty() && !names.iter().any(|name| name == scenario.name) {
+            continue;
+        }
+
+        eprintln!("Scenario {}...", scenario.name);
+        count += 1;
+
+        match run_scenario(scenario, options) {
+            Ok(()) => eprintln!("Scenario {} passed.", scenario.name),
+            Err(error) => {
+                failed += 1;
+                eprintln!("Scenario {} failed: {}", scenario.name, error);
+            }
+        }
+    }
+
+    eprintln!("{} of {} scenarios passed.", count - failed, count);
+
+    failed == 0
+}
+
+/// Runs a single scenario.
+fn run_scenario(scenario: &Scenario, options: &Options) -> Result<(), String> {
+    let log_path = util::path(LOG_DIR).join(format!("{}.log", scenario.name));
+
+    let mut options = options.clone();
+    options.test = true;
+    options.serial_log = Some(log_path.clone());
+    options.serial_input = !scenario.serial_input.is_empty();
+    options
+        .command_line
+        .extend(scenario.command_line.split_whitespace().map(|word| word.to_string()));
+
+    image::write_boot_config(&options.kernel_command_line());
+    image::create_iso();
+
+    if log_path.exists() {
+        fs::remove_file(&log_path)
+            .unwrap_or_exit(&format!("Could not remove {}", log_path.display()));
+    }
+
+    let mut command = qemu::command(&options);
+    if options.serial_input {
+        // The serial output is read from the log, so stdout isn't needed.
+        command.stdin(Stdio::piped()).stdout(Stdio::null());
+    }
+    eprintln!("Running {:?}", command);
+    let mut qemu = command
+        .spawn()
+        .unwrap_or_exit(&format!("Could not run {:?}", command));
+
+    let start = Instant::now();
+    let timeout = Duration::from_secs(scenario.timeout);
+    let mut typed = 0;
+
+    loop {
+        let exit_code = qemu
+            .try_wait()
+            .map_err(|error| format!("could not wait for QEMU: {}", error))?
+            .map(|status| status.code().unwrap_or(1));
+        let output = fs::read(&log_path)
+            .map(|output| String::from_utf8_lossy(&output).into_owned())
+            .unwrap_or_default();
+        let missing = first_missing(&output, scenario.expected_output);
+
+        if let Some(&(after, input)) = scenario.serial_input.get(typed) {
+            if output.contains(after) {
+                type_input(&mut qemu, input)?;
+                typed += 1;
+            }
+        }
+
+        match (exit_code, scenario.exit_code) {
+            (Some(code), Some(expected_code)) if code != expected_code => {
+                return Err(format!("QEMU exited with {} instead of {}", code, expected_code))
+            },
+            (Some(code), None) => return Err(format!("QEMU exited with {}", code)),
+            (Some(_), Some(_)) | (None, None) if missing.is_none() => {
+                stop(&mut qemu);
+                return Ok(());
+            },
+            (Some(_), Some(_)) => return Err(missing_output_error(missing)),
+            _ => ()
+        }
+
+        if start.elapsed() > timeout {
+            stop(&mut qemu);
+            return Err(format!(
+                "timed out after {}s, {}",
+                scenario.timeout,
+                missing_output_error(missing)
+            ));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Returns the first expected string that doesn't appear in the output after
+/// the strings before it.
+fn first_missing<'a>(output: &str, expected_output: &[&'a str]) -> Option<&'a str> {
+    let mut rest = output;
+
+    for expected in expected_output {
+        match rest.find(expected) {
+            Some(index) => rest = &rest[index + expected.len()..],
+            None => return Some(expected)
+        }
+    }
+
+    None
+}
+
+/// Describes the missing output for an error message.
+fn missing_output_error(missing: Option<&str>) -> String {
+    match missing {
+        Some(missing) => format!("\"{}\" was not printed", missing),
+        None => "all output was printed".to_string()
+    }
+}
+
+/// Types the input into the serial port of QEMU.
+fn type_input(qemu: &mut Child, input: &str) -> Result<(), String> {
+    let stdin = qemu
+        .stdin
+        .as_mut()
+        .ok_or_else(|| "the serial input is not connected".to_string())?;
+
+    stdin
+        .write_all(input.as_bytes())
+        .and_then(|()| stdin.flush())
+        .map_err(|error| format!("could not type {:?}: {}", input, error))
+}
+
+/// Stops QEMU if it is still running.
+fn stop(qemu: &mut Child) {
+    // Killing fails if QEMU already exited, which is fine.
+    let _ = qemu.kill();
+    let _ = qemu.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the expected output must appear in order.
+    #[test]
+    fn test_first_missing() {
+        let output = "Booted\nTest\nNest\nTest\n";
+
+        assert_eq!(first_missing(output, &[]), None);
+        assert_eq!(first_missing(output, &["Booted", "Nest", "Test"]), None);
+        assert_eq!(first_missing(output, &["Nest", "Booted"]), Some("Booted"));
+        assert_eq!(first_missing(output, &["Test", "Test", "Test"]), Some("Test"));
+        assert_eq!(first_missing("", &["Booted"]), Some("Booted"));
+    }
+}
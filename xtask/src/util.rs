@@ -0,0 +1,131 @@
+//! Helpers for running tools and handling files.
+
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
+
+/// The build directories of all crates, relative to the repository root.
+const BUILD_DIRS: &[&str] = &[
+    "kernel/target",
+    "init/target",
+    "test/target",
+    "test-runner/target",
+    "syscall-fuzz/target",
+    "display/target",
+    "display-demo/target",
+    "std/target",
+    "harness/target",
+    "initramfs/target",
+    "crypto/target",
+    "mkinitramfs/target"
+];
+
+/// Returns the root directory of the repository.
+pub fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("The xtask crate is in the repository root.")
+        .to_path_buf()
+}
+
+/// Returns the given path relative to the repository root.
+pub fn path<P: AsRef<Path>>(path: P) -> PathBuf {
+    root().join(path)
+}
+
+/// Runs the command and exits if it fails.
+pub fn run(command: &mut Command) {
+    eprintln!("Running {:?}", command);
+
+    let status = command
+        .status()
+        .unwrap_or_exit(&format!("Could not run {:?}", command));
+
+    if !status.success() {
+        exit_with_error(&format!("Running {:?} failed", command), status);
+    }
+}
+
+/// Runs the command and returns its standard output, exiting if it fails.
+pub fn output(command: &mut Command) -> Vec<u8> {
+    let output = command
+        .output()
+        .unwrap_or_exit(&format!("Could not run {:?}", command));
+
+    if !output.status.success() {
+        exit_with_error(&format!("Running {:?} failed", command), output.status);
+    }
+
+    output.stdout
+}
+
+/// Copies the file, creating the parent directories of the destination.
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) {
+    let (from, to) = (from.as_ref(), to.as_ref());
+
+    create_parent(to);
+    fs::copy(from, to).unwrap_or_exit(&format!(
+        "Could not copy {} to {}",
+        from.display(),
+        to.display()
+    ));
+}
+
+/// Writes the file, creating the parent directories.
+pub fn write<P: AsRef<Path>>(path: P, content: &[u8]) {
+    let path = path.as_ref();
+
+    create_parent(path);
+    fs::write(path, content).unwrap_or_exit(&format!("Could not write {}", path.display()));
+}
+
+/// Creates the parent directories of the path.
+pub fn create_parent(path: &Path) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .unwrap_or_exit(&format!("Could not create {}", parent.display()));
+    }
+}
+
+/// Removes all build output.
+pub fn clean() {
+    let directories = BUILD_DIRS.iter().chain(&[::TARGET_DIR, "xtask/target"]);
+
+    for directory in directories.map(path).filter(|directory| directory.is_dir()) {
+        eprintln!("Removing {}", directory.display());
+        fs::remove_dir_all(&directory)
+            .unwrap_or_exit(&format!("Could not remove {}", directory.display()));
+    }
+
+    let iso = path(::ISO);
+    if iso.is_file() {
+        fs::remove_file(&iso).unwrap_or_exit(&format!("Could not remove {}", iso.display()));
+    }
+}
+
+/// Allows exiting with a message instead of handling an error.
+pub trait ExitOnError {
+    /// The type of the value on success.
+    type ResultType;
+
+    /// Either unwraps the result or exits with an error message.
+    fn unwrap_or_exit(self, message: &str) -> Self::ResultType;
+}
+
+impl<T, E: Display> ExitOnError for Result<T, E> {
+    type ResultType = T;
+
+    fn unwrap_or_exit(self, message: &str) -> T {
+        match self {
+            Ok(result) => result,
+            Err(error) => exit_with_error(message, error)
+        }
+    }
+}
+
+/// Exits printing the error and the given message.
+pub fn exit_with_error<E: Display>(message: &str, error: E) -> ! {
+    eprintln!("{}: {}", message, error);
+    exit(1)
+}